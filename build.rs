@@ -0,0 +1,13 @@
+fn main() {
+    #[cfg(feature = "grpc")]
+    compile_protos();
+}
+
+/// Generates the gRPC service from `proto/himalia.proto` with a vendored `protoc`,
+/// so building with the `grpc` feature doesn't need one installed.
+#[cfg(feature = "grpc")]
+fn compile_protos() {
+    let protoc = protoc_bin_vendored::protoc_bin_path().expect("protoc is vendored");
+    std::env::set_var("PROTOC", protoc);
+    tonic_build::compile_protos("proto/himalia.proto").expect("proto/himalia.proto compiles");
+}
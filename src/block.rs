@@ -1,33 +1,99 @@
+use bincode::Options;
+use num::BigUint;
 use serde::{Deserialize, Serialize};
 use sled::IVec;
 
-use crate::{current_timestamp, sha256_digest};
-use crate::{proof_of_work::ProofOfWork, transactions::Transaction};
+use crate::merkle::MerkleTree;
+use crate::proof_of_work::{CancelHandle, ProofOfWork, MAX_TARGET_BITS};
+use crate::transactions::Transaction;
+use crate::types::{BlockHash, Txid};
+use crate::{clock::Clock, config::GLOBAL_CONFIG};
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Block {
     timestamp: i64,
-    pre_block_hash: String,
-    hash: String,
+    pre_block_hash: BlockHash,
+    hash: BlockHash,
     transactions: Vec<Transaction>,
     nonce: i64,
     height: usize,
+    /// The number of leading zero bits the hash must have.
+    target_bits: i64,
+}
+
+/// The fields of a [Block] its [`ProofOfWork`] commits to, along with its hash and
+/// height, which is all a light client keeps of it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlockHeader {
+    pre_block_hash: BlockHash,
+    hash: BlockHash,
+    /// The root of the [`MerkleTree`] of the [Transaction] ids of the [Block].
+    merkle_root: [u8; 32],
+    timestamp: i64,
+    target_bits: i64,
+    nonce: i64,
+    height: usize,
 }
 
 impl Block {
-    /// Creates a new [Block] instance for incorporation into the [Blockchain].
-    pub fn new(pre_block_hash: String, transactions: &[Transaction], height: usize) -> Self {
+    /// Creates a new [Block] instance for incorporation into the [Blockchain], stamped
+    /// with the time of `clock` and mined to the difficulty of `target_bits`.
+    pub fn new(
+        pre_block_hash: BlockHash,
+        transactions: Vec<Transaction>,
+        height: usize,
+        target_bits: i64,
+        clock: &dyn Clock,
+    ) -> Self {
+        Self::mine(
+            pre_block_hash,
+            transactions,
+            height,
+            target_bits,
+            clock,
+            &CancelHandle::new(),
+        )
+        .expect("mining without cancelling runs until a nonce is found")
+    }
+
+    /// Like [`Block::new`] on the configured number of mining threads, giving up and
+    /// returning `None` once `cancel` is triggered.
+    pub fn mine(
+        pre_block_hash: BlockHash,
+        transactions: Vec<Transaction>,
+        height: usize,
+        target_bits: i64,
+        clock: &dyn Clock,
+        cancel: &CancelHandle,
+    ) -> Option<Self> {
+        let threads = GLOBAL_CONFIG.get_mining_threads();
         let mut block = Self {
-            timestamp: current_timestamp(),
+            timestamp: clock.now(),
             pre_block_hash,
-            hash: String::new(),
-            transactions: transactions.to_vec(),
+            hash: BlockHash::default(),
+            transactions,
             nonce: 0,
             height,
+            target_bits,
         };
-        let pow = ProofOfWork::new(block.clone());
-        (block.nonce, block.hash) = pow.run();
-        block
+        let transactions_hash = block.hash_transactions();
+        loop {
+            let pow = ProofOfWork::new(
+                block.pre_block_hash,
+                transactions_hash,
+                block.timestamp,
+                block.target_bits,
+            );
+            if let Some((nonce, hash)) = pow.run(threads, cancel) {
+                (block.nonce, block.hash) = (nonce, hash);
+                return Some(block);
+            }
+            if cancel.is_cancelled() {
+                return None;
+            }
+            // Every nonce was tried, a later timestamp gives new ones to try.
+            block.timestamp += 1;
+        }
     }
 
     /// Deserializes a [Block] object from a slice of bytes.
@@ -35,46 +101,92 @@ impl Block {
         bincode::deserialize(bytes).unwrap()
     }
 
+    /// Deserializes a [Block] from untrusted bytes, such as those sent by a peer.
+    ///
+    /// Lengths inside `bytes` may not claim more bytes than were given.
+    pub fn from_bytes(bytes: &[u8]) -> bincode::Result<Self> {
+        bincode::options()
+            .with_fixint_encoding()
+            .with_limit(bytes.len() as u64)
+            .deserialize(bytes)
+    }
+
     /// Serializes a slice of bytes from a reference to a [Block].
     pub fn serialize(&self) -> Vec<u8> {
         bincode::serialize(self).unwrap()
     }
 
-    /// Generate the first block in the [Blockchain].
-    pub fn generate_genesis(transaction: &Transaction) -> Self {
-        let transactions = vec![transaction.clone()];
-        Self::new(String::from("None"), &transactions, 0)
+    /// Generate the first block in the [Blockchain], at the lowest difficulty of the
+    /// network.
+    pub fn generate_genesis(transaction: Transaction, clock: &dyn Clock) -> Self {
+        let network = GLOBAL_CONFIG.get_network();
+        let pre_block_hash = network.genesis_pre_block_hash();
+        let target_bits = network.params().target_bits;
+        Self::new(pre_block_hash, vec![transaction], 0, target_bits, clock)
     }
 
-    /// Hash the [Transaction] IDs using SHA-256 and return the hash
-    /// a vector of bytes.
-    pub fn hash_transactions(&self) -> Vec<u8> {
-        let mut txhashs = vec![];
-        for transaction in &self.transactions {
-            txhashs.extend(transaction.get_id());
+    /// Returns the root of the [`MerkleTree`] of the [Transaction] IDs, which the
+    /// proof of work commits to.
+    pub fn hash_transactions(&self) -> [u8; 32] {
+        self.get_merkle_tree().root()
+    }
+
+    /// Builds the [`MerkleTree`] of the [Transaction] IDs, in the order of the
+    /// [Transaction]s, to prove that one of them is in the [Block].
+    pub fn get_merkle_tree(&self) -> MerkleTree {
+        let txids: Vec<Txid> = self.transactions.iter().map(Transaction::get_id).collect();
+        MerkleTree::new(&txids)
+    }
+
+    /// Returns the header of the [Block], which commits to its [Transaction]s through
+    /// the root of their [`MerkleTree`].
+    pub fn get_header(&self) -> BlockHeader {
+        BlockHeader {
+            pre_block_hash: self.pre_block_hash,
+            hash: self.hash,
+            merkle_root: self.hash_transactions(),
+            timestamp: self.timestamp,
+            target_bits: self.target_bits,
+            nonce: self.nonce,
+            height: self.height,
         }
-        sha256_digest(txhashs.as_slice())
     }
 
     /// Get the list of [Transaction]s.
-    pub fn get_transactions(&self) -> &[Transaction] {
+    pub const fn get_transactions(&self) -> &[Transaction] {
         self.transactions.as_slice()
     }
 
-    /// Returns a cloned copy of the `pre_block_hash` string.
-    pub fn get_pre_block_hash(&self) -> String {
-        self.pre_block_hash.clone()
+    /// Takes the [Transaction]s back, to mine them in another [Block].
+    pub fn into_transactions(self) -> Vec<Transaction> {
+        self.transactions
     }
 
-    /// Get the hash of the [Transaction].
-    pub fn get_hash(&self) -> &str {
-        self.hash.as_str()
+    /// Returns the hash of the [Block] this one builds on.
+    pub const fn get_pre_block_hash(&self) -> BlockHash {
+        self.pre_block_hash
     }
 
-    /// Returns a vector of bytes representing the hash string held
-    /// within the [Block] instance.
+    /// Get the hash of the [Block].
+    pub const fn get_hash(&self) -> BlockHash {
+        self.hash
+    }
+
+    /// Returns the bytes of the hash of the [Block].
     pub fn get_hash_bytes(&self) -> Vec<u8> {
-        self.hash.as_bytes().to_vec()
+        self.hash.to_vec()
+    }
+
+    /// Checks that the nonce of the [Block] produces its hash and that the hash meets
+    /// the target of the [`ProofOfWork`].
+    pub fn validate_pow(&self) -> bool {
+        (1..=MAX_TARGET_BITS).contains(&self.target_bits)
+            && ProofOfWork::for_block(self).validate(self.nonce, self.hash)
+    }
+
+    /// Returns the work the [`ProofOfWork`] of the [Block] adds to its chain.
+    pub fn get_work(&self) -> BigUint {
+        ProofOfWork::for_block(self).work()
     }
 
     /// Return the timestamp held within the [Block] instance.
@@ -82,10 +194,61 @@ impl Block {
         self.timestamp
     }
 
+    /// Return the nonce found by the [`ProofOfWork`].
+    pub const fn get_nonce(&self) -> i64 {
+        self.nonce
+    }
+
     /// Return the height of the [Block].
     pub const fn get_height(&self) -> usize {
         self.height
     }
+
+    /// Returns the number of leading zero bits the hash of the [Block] must have.
+    pub const fn get_target_bits(&self) -> i64 {
+        self.target_bits
+    }
+}
+
+impl BlockHeader {
+    /// Like [`Block::validate_pow`], given only the header.
+    pub fn validate_pow(&self) -> bool {
+        (1..=MAX_TARGET_BITS).contains(&self.target_bits)
+            && ProofOfWork::for_header(self).validate(self.nonce, self.hash)
+    }
+
+    /// Returns the work the [`ProofOfWork`] of the [Block] adds to its chain.
+    pub fn get_work(&self) -> BigUint {
+        ProofOfWork::for_header(self).work()
+    }
+
+    pub const fn get_pre_block_hash(&self) -> BlockHash {
+        self.pre_block_hash
+    }
+
+    pub const fn get_hash(&self) -> BlockHash {
+        self.hash
+    }
+
+    pub const fn get_merkle_root(&self) -> &[u8; 32] {
+        &self.merkle_root
+    }
+
+    pub const fn get_timestamp(&self) -> i64 {
+        self.timestamp
+    }
+
+    pub const fn get_target_bits(&self) -> i64 {
+        self.target_bits
+    }
+
+    pub const fn get_nonce(&self) -> i64 {
+        self.nonce
+    }
+
+    pub const fn get_height(&self) -> usize {
+        self.height
+    }
 }
 
 // TODO: implement `TryFrom`
@@ -1,147 +1,797 @@
-use std::collections::HashMap;
-use std::env::current_dir;
-use std::sync::{Arc, RwLock};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
+use std::iter;
+use std::ops::RangeInclusive;
+use std::sync::mpsc::{Receiver, SyncSender};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
 
-use data_encoding::HEXLOWER;
-use sled::transaction::TransactionResult;
-use sled::{Db, Tree};
+use log::info;
+use num::{BigUint, Zero};
+use serde::{Deserialize, Serialize};
+use sled::transaction::{TransactionError as SledTransactionError, TransactionResult};
+use sled::{Db, IVec, Tree};
 
-use crate::block::Block;
+use crate::block::{Block, BlockHeader};
+use crate::clock::{system_clock, Clock};
+use crate::config::{ChainParams, Network, GLOBAL_CONFIG};
+use crate::events::{Event, EventBus, TxEventStatus};
+use crate::memory_pool::MEMPOOL_TREE;
+use crate::proof_of_work::{retarget_step, CancelHandle, MAX_TARGET_BITS};
+use crate::storage::open_db;
+use crate::summary::BlockSummary;
 use crate::transactions::{TXOutput, Transaction};
+use crate::types::{BlockHash, Txid, HASH_LEN};
+use crate::utxo_set::{SpendError, UTXOSet, UNDO_TREE, UTXO_TREE};
+use crate::wallet::hash_pub_key;
 
 const TIP_BLOCK_HASH_KEY: &str = "tip_block_hash";
 const BLOCKS_TREE: &str = "blocks";
+/// Maps the hash of each [Block] to the total work of the chain ending at it, as the
+/// big-endian bytes of the number.
+const CHAIN_WORK_TREE: &str = "chainwork";
+/// Maps each height of the best chain, as eight big-endian bytes, to the hash of its
+/// [Block], up to the tip at [`HEIGHT_INDEX_TIP_KEY`].
+const HEIGHT_TREE: &str = "heights";
+/// Maps the id of each [Transaction] on the best chain to the hash of its [Block]
+/// followed by its position in the [Block] as four big-endian bytes.
+const TX_INDEX_TREE: &str = "txindex";
+/// Holds a key for each pub key hash a [Transaction] on the best chain pays or spends
+/// from: the hash, then the height of the [Block] as eight big-endian bytes and the
+/// position of the [Transaction] in it as four, mapped to the id of the [Transaction].
+const ADDRESS_INDEX_TREE: &str = "addrindex";
+/// Holds the tip the indexes were last brought up to date with, in the blocks tree.
+const HEIGHT_INDEX_TIP_KEY: &str = "height_index_tip";
+/// Holds the version of the indexes in the blocks tree. Indexes built by another
+/// version are dropped and built again on first use.
+const INDEX_VERSION_KEY: &str = "index_version";
+const INDEX_VERSION: u8 = 2;
+/// Holds the version of the encoding of the blocks tree. Trees without it store the
+/// hashes within each [Block] as hex strings.
+const BLOCKS_FORMAT_KEY: &str = "blocks_format";
+const BLOCKS_FORMAT: u8 = 3;
+/// Holds the name of the [Network] the chain belongs to in the blocks tree.
+const NETWORK_KEY: &str = "network";
+/// The largest payload read from a peer by default, room for a [Block] of the largest
+/// size any network allows.
+pub const DEFAULT_MAX_PACKAGE_BYTES: usize = 4_000_000;
+pub const DEFAULT_MAX_BLOCK_TXS: usize = 10_000;
+pub const DEFAULT_MAX_TX_BYTES: usize = 100_000;
+/// The bytes [`MemoryPool::get_block_template`](crate::memory_pool::MemoryPool::get_block_template)
+/// leaves for the header and the Coinbase of a [Block].
+pub const BLOCK_RESERVED_BYTES: usize = 1_000;
+
+/// The largest packages, [Block]s and [Transaction]s a node accepts, which also bound
+/// the [Block]s it mines.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SizeLimits {
+    /// Checked against the length of a package before it is decoded.
+    pub max_package_bytes: usize,
+    /// The serialized size of a [Block].
+    pub max_block_bytes: usize,
+    /// The number of [Transaction]s in a [Block], its Coinbase included.
+    pub max_block_txs: usize,
+    /// The serialized size of a [Transaction].
+    pub max_tx_bytes: usize,
+}
+
+impl SizeLimits {
+    /// The default limits, with [Block]s up to the `max_block_size` of `params`.
+    pub const fn new(params: &ChainParams) -> Self {
+        Self {
+            max_package_bytes: DEFAULT_MAX_PACKAGE_BYTES,
+            max_block_bytes: params.max_block_size,
+            max_block_txs: DEFAULT_MAX_BLOCK_TXS,
+            max_tx_bytes: DEFAULT_MAX_TX_BYTES,
+        }
+    }
+}
+
+/// The reasons an existing [Blockchain] can't be opened.
+#[derive(Debug)]
+pub enum BlockchainError {
+    /// No genesis block has been created in the data directory yet.
+    NotFound,
+    /// The database could not be opened, e.g. because a running node holds its lock.
+    Database(sled::Error),
+    /// The blocks tree is stored in an older format that can't be migrated.
+    Migration(String),
+    /// The stored tip hash isn't a valid hash.
+    CorruptTip,
+    /// The chain starts from the genesis [Block] of another [Network] than the
+    /// selected one, `None` when it matches none of them.
+    WrongNetwork {
+        expected: Network,
+        found: Option<Network>,
+    },
+    /// The address to pay the genesis [Block] to isn't an address of the selected
+    /// [Network].
+    InvalidAddress(String),
+}
+
+impl Display for BlockchainError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(
+                f,
+                "no existing blockchain found, create one with `createblockchain` first"
+            ),
+            Self::Database(e) => write!(f, "unable to open the blockchain database: {e}"),
+            Self::Migration(e) => write!(f, "unable to migrate the blockchain database: {e}"),
+            Self::CorruptTip => write!(f, "the stored tip of the blockchain is corrupt"),
+            Self::WrongNetwork {
+                expected,
+                found: Some(found),
+            } => write!(
+                f,
+                "the chain in the data directory belongs to the {found} network, not \
+                 {expected}; pass `--network {found}` to open it"
+            ),
+            Self::WrongNetwork {
+                expected,
+                found: None,
+            } => write!(
+                f,
+                "the chain in the data directory doesn't belong to the {expected} network"
+            ),
+            Self::InvalidAddress(address) => write!(f, "address `{address}` is not valid"),
+        }
+    }
+}
+
+impl std::error::Error for BlockchainError {}
+
+impl From<sled::Error> for BlockchainError {
+    fn from(e: sled::Error) -> Self {
+        Self::Database(e)
+    }
+}
+
+/// The reasons [`Blockchain::add_block`] refuses a [Block].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockError {
+    /// The nonce doesn't produce the hash, or the hash doesn't meet the target.
+    InvalidProofOfWork,
+    /// The [Block] builds on one that isn't stored.
+    UnknownParent(BlockHash),
+    /// The height isn't one above that of the parent.
+    WrongHeight { height: usize, expected: usize },
+    /// The difficulty isn't the one [`Blockchain::get_next_target_bits`] expects.
+    WrongDifficulty { target_bits: i64, expected: i64 },
+    /// The id of a [Transaction] isn't its hash.
+    MismatchedId(Txid),
+    /// The [Block] doesn't end with its Coinbase, or holds more than one.
+    MisplacedCoinbase,
+    /// Two [Transaction]s in the [Block] have the same id.
+    DuplicateTransaction(Txid),
+    /// A [Transaction] has the id of one whose outputs aren't all spent yet.
+    ExistingTransaction(Txid),
+    /// A [Transaction] spends an output that an earlier input of the [Block] spends.
+    DoubleSpend(Txid),
+    /// A [Transaction] spends an unknown or spent output, isn't signed by its owner, or
+    /// pays out more than it spends.
+    InvalidTransaction(Txid),
+    /// The Coinbase [Transaction]s pay out more than the subsidy and the fees of the
+    /// [Block].
+    ExcessiveReward { reward: u64, allowed: u64 },
+    /// The serialized [Block] is larger than [`SizeLimits::max_block_bytes`].
+    TooLarge { size: usize, max: usize },
+    /// The [Block] holds more than [`SizeLimits::max_block_txs`] [Transaction]s.
+    TooManyTransactions { count: usize, max: usize },
+    /// A [Transaction] is larger than [`SizeLimits::max_tx_bytes`].
+    TransactionTooLarge { txid: Txid, size: usize, max: usize },
+    /// The UTXO set can't apply the [Block], which spends an output it doesn't hold.
+    Unspendable(SpendError),
+    /// The database failed to read or write the [Block] or the tip.
+    Database(String),
+}
+
+impl Display for BlockError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidProofOfWork => write!(f, "the proof of work is not valid"),
+            Self::UnknownParent(hash) => write!(f, "the parent block {hash} is unknown"),
+            Self::WrongHeight { height, expected } => {
+                write!(f, "the block is at height {height}, expected {expected}")
+            }
+            Self::WrongDifficulty {
+                target_bits,
+                expected,
+            } => write!(
+                f,
+                "the block has {target_bits} target bits, expected {expected}"
+            ),
+            Self::MismatchedId(txid) => {
+                write!(f, "the id of transaction {txid} is not its hash")
+            }
+            Self::MisplacedCoinbase => {
+                write!(f, "the block must end with its only coinbase transaction")
+            }
+            Self::DuplicateTransaction(txid) => {
+                write!(f, "the transaction {txid} is in the block more than once")
+            }
+            Self::ExistingTransaction(txid) => {
+                write!(f, "the transaction {txid} already has unspent outputs")
+            }
+            Self::DoubleSpend(txid) => {
+                write!(
+                    f,
+                    "the transaction {txid} spends an output already spent in the block"
+                )
+            }
+            Self::InvalidTransaction(txid) => write!(f, "the transaction {txid} is not valid"),
+            Self::ExcessiveReward { reward, allowed } => write!(
+                f,
+                "the coinbase pays {reward}, more than the {allowed} of subsidy and fees"
+            ),
+            Self::TooLarge { size, max } => {
+                write!(f, "the block is {size} bytes, more than the {max} allowed")
+            }
+            Self::TooManyTransactions { count, max } => write!(
+                f,
+                "the block holds {count} transactions, more than the {max} allowed"
+            ),
+            Self::TransactionTooLarge { txid, size, max } => write!(
+                f,
+                "the transaction {txid} is {size} bytes, more than the {max} allowed"
+            ),
+            Self::Unspendable(e) => write!(f, "the block can't be applied: {e}"),
+            Self::Database(e) => write!(f, "the block database failed: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for BlockError {}
+
+impl From<sled::Error> for BlockError {
+    fn from(e: sled::Error) -> Self {
+        Self::Database(e.to_string())
+    }
+}
+
+/// How much of a [Block] [`Blockchain::check_block`] checks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockChecks {
+    /// Everything [`Blockchain::validate_block`] checks.
+    Full,
+    /// Everything but the signatures, which the caller verified already.
+    SignaturesVerified,
+    /// The size, the proof of work, and that the [Block] builds on a stored one at the
+    /// next height and difficulty.
+    Linkage,
+    /// Nothing, though the UTXO set still refuses inputs spending outputs it doesn't
+    /// hold when the [Block] is connected.
+    None,
+}
+
+/// Whether a [Transaction] took funds from an address or brought funds to it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+impl Display for Direction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Sent => write!(f, "sent"),
+            Self::Received => write!(f, "received"),
+        }
+    }
+}
+
+/// A [Transaction] of the best chain touching an address, as listed by
+/// [`Blockchain::get_address_history`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TxRecord {
+    pub txid: String,
+    pub height: usize,
+    pub direction: Direction,
+    /// What the address received less what it spent, or the other way around when
+    /// it is [`Direction::Sent`], which it is whenever the [Transaction] spends from
+    /// the address at least what it pays back, as a self-send does.
+    pub amount: u64,
+    pub coinbase: bool,
+}
+
+/// Headline figures describing the [Blockchain].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChainStats {
+    pub height: usize,
+    pub tip_hash: BlockHash,
+    pub tip_timestamp: i64,
+}
+
+/// The outputs created by the [Block]s of a branch that aren't on the best chain, and
+/// the height of the [Block] of the best chain the branch builds on.
+#[derive(Default)]
+struct Branch {
+    outputs: HashMap<Txid, Vec<TXOutput>>,
+    fork_height: usize,
+}
 
 #[derive(Clone)]
 pub struct Blockchain {
-    tip_hash: Arc<RwLock<String>>,
-    db: Db,
+    tip_hash: Arc<RwLock<BlockHash>>,
+    /// Held by whoever moves the tip from deciding where it moves until it has, so that
+    /// [Block]s are added one at a time while readers of the tip carry on.
+    tip_writer: Arc<Mutex<()>>,
+    /// Filled by [`Blockchain::get_genesis_hash`] the first time it's called.
+    genesis_hash: Arc<OnceLock<BlockHash>>,
+    db: Arc<Db>,
+    blocks_tree: Tree,
+    chain_work_tree: Tree,
+    height_tree: Tree,
+    tx_index_tree: Tree,
+    address_index_tree: Tree,
+    params: &'static ChainParams,
+    limits: SizeLimits,
+    /// Shared by every clone so that subscribers see [Block]s added through any of them.
+    events: Arc<EventBus>,
+    clock: Arc<dyn Clock>,
 }
 
 impl Blockchain {
     /// Create a new [Blockchain] instance by initializing a new database connection
-    /// and creating the genesis block.
-    pub fn create(genesis_address: &str) -> Self {
-        let db = sled::open(current_dir().unwrap().join("data")).unwrap();
-        let blocks_tree = db.open_tree(BLOCKS_TREE).unwrap();
-        let data = blocks_tree.get(TIP_BLOCK_HASH_KEY).unwrap();
-        let tip_hash = data.map_or_else(
-            || {
-                let coinbase_tx = Transaction::new_coinbase_tx(genesis_address);
-                let block = Block::generate_genesis(&coinbase_tx);
-                Self::update_blocks_tree(&blocks_tree, &block);
-                String::from(block.get_hash())
-            },
-            |data| String::from_utf8(data.to_vec()).unwrap(),
-        );
-        Self {
-            tip_hash: Arc::new(RwLock::new(tip_hash)),
-            db,
+    /// and creating the genesis block, or opens the one already in the data directory.
+    pub fn create(genesis_address: &str) -> Result<Self, BlockchainError> {
+        // Held until the chain is open, as sled releases the lock of a dropped handle
+        // in the background and reopening it right away could fail.
+        let _db = open_db(&GLOBAL_CONFIG.get_data_dir())?;
+        match Self::open() {
+            Err(BlockchainError::NotFound) => {
+                let params = GLOBAL_CONFIG.get_network().params();
+                let coinbase_tx = Transaction::new_coinbase_tx(genesis_address, params, 0)
+                    .map_err(|_| BlockchainError::InvalidAddress(String::from(genesis_address)))?;
+                let genesis = Block::generate_genesis(coinbase_tx, &*system_clock());
+                Self::open_or_init(&genesis)
+            }
+            result => result,
         }
     }
 
     /// Update the `blocks_tree` database tree with the new [Block] instance.
-    fn update_blocks_tree(blocks_tree: &Tree, block: &Block) {
+    fn update_blocks_tree(blocks_tree: &Tree, block: &Block) -> sled::Result<()> {
         let block_hash = block.get_hash();
-        let _: TransactionResult<(), ()> = blocks_tree.transaction(|tx_db| {
-            let _ = tx_db.insert(block_hash, block.clone());
-            let _ = tx_db.insert(TIP_BLOCK_HASH_KEY, block_hash);
+        let block_bytes = IVec::from(block.serialize());
+        let result: TransactionResult<(), sled::Error> = blocks_tree.transaction(|tx_db| {
+            tx_db.insert(block_hash.as_bytes(), block_bytes.clone())?;
+            tx_db.insert(TIP_BLOCK_HASH_KEY, block_hash.as_bytes())?;
             Ok(())
         });
+        result.map_err(|(SledTransactionError::Abort(e) | SledTransactionError::Storage(e))| e)
+    }
+
+    /// Marks an empty blocks tree with [`BLOCKS_FORMAT`], refusing one stored in an
+    /// earlier format.
+    ///
+    /// Format 3 widened output values from `i32` to `u64`. Transaction ids and
+    /// signatures cover the encoded values, so older chains can't be rewritten
+    /// without invalidating every [Transaction] in them; they have to be reset and
+    /// synced or mined again.
+    fn migrate_blocks_tree(blocks_tree: &Tree) -> Result<(), BlockchainError> {
+        let format = blocks_tree
+            .get(BLOCKS_FORMAT_KEY)?
+            .and_then(|v| v.first().copied());
+        if format == Some(BLOCKS_FORMAT) {
+            return Ok(());
+        }
+        for entry in blocks_tree {
+            let (key, _) = entry?;
+            if key != BLOCKS_FORMAT_KEY.as_bytes() {
+                return Err(BlockchainError::Migration(format!(
+                    "the chain is stored in format {}, which can't be converted to format \
+                     {BLOCKS_FORMAT}; run `resetchain` and sync it again",
+                    format.unwrap_or(0)
+                )));
+            }
+        }
+        blocks_tree.insert(BLOCKS_FORMAT_KEY, &[BLOCKS_FORMAT])?;
+        Ok(())
+    }
+
+    /// Refuses a chain that doesn't start from the genesis [Block] of the selected
+    /// [Network], which the parent hash of every genesis [Block] commits to.
+    ///
+    /// The [Network] is recorded in the blocks tree once checked, so that only chains
+    /// created before it was are walked back to their genesis [Block].
+    fn check_network(blocks_tree: &Tree, tip_hash: BlockHash) -> Result<(), BlockchainError> {
+        let expected = GLOBAL_CONFIG.get_network();
+        let found = if let Some(name) = blocks_tree.get(NETWORK_KEY)? {
+            std::str::from_utf8(name.as_ref())
+                .ok()
+                .and_then(|name| name.parse().ok())
+        } else {
+            let mut block_hash = tip_hash;
+            let genesis = loop {
+                let Some(bytes) = blocks_tree.get(block_hash.as_bytes())? else {
+                    break None;
+                };
+                let block = Block::deserialize(bytes.as_ref());
+                if block.get_height() == 0 {
+                    break Some(block);
+                }
+                block_hash = block.get_pre_block_hash();
+            };
+            let found = genesis.and_then(|genesis| {
+                Network::ALL.into_iter().find(|network| {
+                    network.genesis_pre_block_hash() == genesis.get_pre_block_hash()
+                })
+            });
+            if found == Some(expected) {
+                blocks_tree.insert(NETWORK_KEY, expected.as_str())?;
+            }
+            found
+        };
+        if found == Some(expected) {
+            Ok(())
+        } else {
+            Err(BlockchainError::WrongNetwork { expected, found })
+        }
     }
 
     /// Initialize the new [Blockchain] instance by initiating a new instance
     /// of the database and retrieving the latest block hash.
-    pub fn new() -> Self {
-        let db = sled::open(current_dir().unwrap().join("data")).unwrap();
-        let blocks_tree = db.open_tree(BLOCKS_TREE).unwrap();
+    ///
+    /// Fails with [`BlockchainError::NotFound`] when no chain has been created yet.
+    pub fn new() -> Result<Self, BlockchainError> {
+        Self::open()
+    }
+
+    /// Opens the existing [Blockchain] in the data directory, failing instead of
+    /// panicking when there is none or the database is unavailable.
+    pub fn open() -> Result<Self, BlockchainError> {
+        let db = open_db(&GLOBAL_CONFIG.get_data_dir())?;
+        let blocks_tree = db.open_tree(BLOCKS_TREE)?;
+        Self::migrate_blocks_tree(&blocks_tree)?;
         let tip_bytes = blocks_tree
-            .get(TIP_BLOCK_HASH_KEY)
-            .unwrap()
-            .expect("No existing blockchain found. Create one first.");
-        let tip_hash = String::from_utf8(tip_bytes.to_vec()).unwrap();
-        Self {
+            .get(TIP_BLOCK_HASH_KEY)?
+            .ok_or(BlockchainError::NotFound)?;
+        let tip_hash =
+            BlockHash::from_slice(tip_bytes.as_ref()).map_err(|_| BlockchainError::CorruptTip)?;
+        Self::check_network(&blocks_tree, tip_hash)?;
+        let blockchain = Self {
+            tip_hash: Arc::new(RwLock::new(tip_hash)),
+            tip_writer: Arc::new(Mutex::new(())),
+            genesis_hash: Arc::new(OnceLock::new()),
+            chain_work_tree: db.open_tree(CHAIN_WORK_TREE)?,
+            height_tree: db.open_tree(HEIGHT_TREE)?,
+            tx_index_tree: db.open_tree(TX_INDEX_TREE)?,
+            address_index_tree: db.open_tree(ADDRESS_INDEX_TREE)?,
+            db,
+            blocks_tree,
+            params: GLOBAL_CONFIG.get_network().params(),
+            limits: GLOBAL_CONFIG.get_size_limits(),
+            events: Arc::new(EventBus::new()),
+            clock: system_clock(),
+        };
+        blockchain.update_indexes()?;
+        Ok(blockchain)
+    }
+
+    /// Opens the [Blockchain] in the data directory, starting it from `genesis`
+    /// when none exists yet.
+    pub fn open_or_init(genesis: &Block) -> Result<Self, BlockchainError> {
+        Self::open_or_init_in(open_db(&GLOBAL_CONFIG.get_data_dir())?, genesis)
+    }
+
+    /// Like [`Blockchain::open_or_init`] on `db` instead of the database in the data
+    /// directory.
+    pub fn open_or_init_in(db: Arc<Db>, genesis: &Block) -> Result<Self, BlockchainError> {
+        let blocks_tree = db.open_tree(BLOCKS_TREE)?;
+        Self::migrate_blocks_tree(&blocks_tree)?;
+        let data = blocks_tree.get(TIP_BLOCK_HASH_KEY)?;
+        let tip_hash = if let Some(data) = data {
+            BlockHash::from_slice(data.as_ref()).map_err(|_| BlockchainError::CorruptTip)?
+        } else {
+            Self::update_blocks_tree(&blocks_tree, genesis)?;
+            genesis.get_hash()
+        };
+        Self::check_network(&blocks_tree, tip_hash)?;
+        let blockchain = Self {
             tip_hash: Arc::new(RwLock::new(tip_hash)),
+            tip_writer: Arc::new(Mutex::new(())),
+            genesis_hash: Arc::new(OnceLock::new()),
+            chain_work_tree: db.open_tree(CHAIN_WORK_TREE)?,
+            height_tree: db.open_tree(HEIGHT_TREE)?,
+            tx_index_tree: db.open_tree(TX_INDEX_TREE)?,
+            address_index_tree: db.open_tree(ADDRESS_INDEX_TREE)?,
             db,
+            blocks_tree,
+            params: GLOBAL_CONFIG.get_network().params(),
+            limits: GLOBAL_CONFIG.get_size_limits(),
+            events: Arc::new(EventBus::new()),
+            clock: system_clock(),
+        };
+        blockchain.update_indexes()?;
+        Ok(blockchain)
+    }
+
+    /// Drops the [Block]s, the UTXO set, the indexes and the saved mempool from the
+    /// data directory, leaving every other file, such as the wallet, in place.
+    ///
+    /// Fails with [`BlockchainError::Database`] while another process holds the database.
+    pub fn reset() -> Result<(), BlockchainError> {
+        let db = open_db(&GLOBAL_CONFIG.get_data_dir())?;
+        for tree in [
+            BLOCKS_TREE,
+            CHAIN_WORK_TREE,
+            HEIGHT_TREE,
+            TX_INDEX_TREE,
+            ADDRESS_INDEX_TREE,
+            UTXO_TREE,
+            UNDO_TREE,
+            MEMPOOL_TREE,
+        ] {
+            db.drop_tree(tree)?;
         }
+        db.flush()?;
+        Ok(())
     }
 
-    pub const fn get_db(&self) -> &Db {
+    pub fn get_db(&self) -> &Db {
         &self.db
     }
 
-    pub fn get_tip_hash(&self) -> String {
-        self.tip_hash.read().unwrap().clone()
+    const fn blocks_tree(&self) -> &Tree {
+        &self.blocks_tree
+    }
+
+    /// Returns the [`ChainParams`] of the [Network] this [Blockchain] belongs to.
+    pub const fn get_params(&self) -> &'static ChainParams {
+        self.params
+    }
+
+    pub const fn get_limits(&self) -> SizeLimits {
+        self.limits
+    }
+
+    /// Replaces the [`SizeLimits`] [Block]s are checked against, those of the
+    /// configuration by default.
+    #[must_use]
+    pub const fn with_limits(mut self, limits: SizeLimits) -> Self {
+        self.limits = limits;
+        self
     }
 
-    pub fn set_tip_hash(&self, new_tip_hash: &str) {
+    /// Replaces the [`ChainParams`] [Block]s are checked against, those of the selected
+    /// [Network] by default.
+    #[must_use]
+    pub const fn with_params(mut self, params: &'static ChainParams) -> Self {
+        self.params = params;
+        self
+    }
+
+    /// Replaces the [Clock] new [Block]s are stamped with.
+    #[must_use]
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    pub fn get_clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    pub fn get_tip_hash(&self) -> BlockHash {
+        *self.tip_hash.read().unwrap()
+    }
+
+    /// Returns the hash of the genesis [Block], walking back from the tip to find it
+    /// the first time.
+    pub fn get_genesis_hash(&self) -> BlockHash {
+        *self.genesis_hash.get_or_init(|| {
+            let mut iterator = self.iterator();
+            let mut genesis_hash = self.get_tip_hash();
+            while let Some(block) = iterator.next() {
+                genesis_hash = block.get_hash();
+            }
+            genesis_hash
+        })
+    }
+
+    pub fn set_tip_hash(&self, new_tip_hash: BlockHash) {
         let mut tip_hash = self.tip_hash.write().unwrap();
-        *tip_hash = String::from(new_tip_hash);
+        *tip_hash = new_tip_hash;
     }
 
     /// Mine a block. Create a new block and incorporate it into the [Blockchain].
-    pub fn mine_block(&self, transactions: &[Transaction]) -> Block {
-        for transaction in transactions {
-            assert!(transaction.verify(self), "ERROR: Invalid transaction");
+    ///
+    /// Fails unless `transactions` end with their only Coinbase, each [Transaction]
+    /// spends outputs of the UTXO set or of those before it that no other
+    /// [Transaction] of the [Block] spends, the Coinbase pays no more than the subsidy
+    /// and the fees, and the [Block] fits within the [`SizeLimits`].
+    pub fn mine_block(&self, transactions: Vec<Transaction>) -> Result<Block, BlockError> {
+        check_coinbase_position(&transactions)?;
+        if transactions.len() > self.limits.max_block_txs {
+            return Err(BlockError::TooManyTransactions {
+                count: transactions.len(),
+                max: self.limits.max_block_txs,
+            });
         }
-        let best_height = self.get_best_height();
+        // The header and the Coinbase take the reserved bytes, as in a block template.
+        let size: usize = transactions
+            .iter()
+            .filter(|tx| !tx.is_coinbase())
+            .map(|tx| tx.serialize().len())
+            .sum();
+        if size + BLOCK_RESERVED_BYTES > self.limits.max_block_bytes {
+            return Err(BlockError::TooLarge {
+                size: size + BLOCK_RESERVED_BYTES,
+                max: self.limits.max_block_bytes,
+            });
+        }
+        let utxo_set = UTXOSet::new(self.clone());
+        let mut created = HashMap::new();
+        let mut spent = HashSet::new();
+        let mut fees = 0_u64;
+        for transaction in &transactions {
+            fees = utxo_set
+                .validate_tx_spending(transaction, &created)
+                .ok()
+                .and_then(|fee| fees.checked_add(fee))
+                .ok_or_else(|| BlockError::InvalidTransaction(transaction.get_id()))?;
+            let spends_new_outputs = transaction.is_coinbase()
+                || transaction
+                    .get_vin()
+                    .iter()
+                    .all(|input| spent.insert((input.get_txid(), input.get_vout())));
+            if !spends_new_outputs {
+                return Err(BlockError::DoubleSpend(transaction.get_id()));
+            }
+            created.insert(transaction.get_id(), transaction.get_vout().to_vec());
+        }
+        self.check_reward(&transactions, fees)?;
+        self.mine_verified_block(transactions)
+    }
 
-        let block = Block::new(self.get_tip_hash(), transactions, best_height + 1);
-        let block_hash = block.get_hash();
+    /// Like [`Blockchain::mine_block`] without verifying the [Transaction]s again, for
+    /// those taken from [`MemoryPool::get_block_template`](crate::memory_pool::MemoryPool::get_block_template).
+    ///
+    /// The [Block] is applied to the UTXO set along with the tip, so it still fails
+    /// when a [Transaction] spends an output the set doesn't hold.
+    ///
+    /// The tip is only held to add the mined [Block], which is mined again on the new
+    /// tip when another [Block] took it in the meantime.
+    pub fn mine_verified_block(
+        &self,
+        mut transactions: Vec<Transaction>,
+    ) -> Result<Block, BlockError> {
+        loop {
+            let parent = self
+                .get_block(self.get_tip_hash())
+                .expect("the tip block is stored");
+            let block = Block::new(
+                parent.get_hash(),
+                transactions,
+                parent.get_height() + 1,
+                self.get_next_target_bits(&parent),
+                &*self.clock,
+            );
+            let _writer = self.tip_writer.lock().unwrap();
+            if self.get_tip_hash() == parent.get_hash() {
+                self.extend_tip(&block)?;
+                return Ok(block);
+            }
+            transactions = block.into_transactions();
+        }
+    }
 
-        let blocks_tree = self.db.open_tree(BLOCKS_TREE).unwrap();
-        Self::update_blocks_tree(&blocks_tree, &block);
-        self.set_tip_hash(block_hash);
-        block
+    /// Like [`Blockchain::mine_verified_block`], giving up once `cancel` is triggered.
+    ///
+    /// The [Block] is only added while the tip is still the [Block] it was mined on,
+    /// so `None` is also returned when another [Block] took the tip in the meantime.
+    pub fn mine_verified_block_cancellable(
+        &self,
+        transactions: Vec<Transaction>,
+        cancel: &CancelHandle,
+    ) -> Result<Option<Block>, BlockError> {
+        let parent = self
+            .get_block(self.get_tip_hash())
+            .expect("the tip block is stored");
+        let Some(block) = Block::mine(
+            parent.get_hash(),
+            transactions,
+            parent.get_height() + 1,
+            self.get_next_target_bits(&parent),
+            &*self.clock,
+            cancel,
+        ) else {
+            return Ok(None);
+        };
+        let _writer = self.tip_writer.lock().unwrap();
+        if self.get_tip_hash() != parent.get_hash() {
+            return Ok(None);
+        }
+        self.extend_tip(&block)?;
+        Ok(Some(block))
+    }
+
+    /// Returns a receiver for an [`Event::Block`] followed by a confirmed
+    /// [`Event::Tx`] per [Transaction] for every [Block] added from now on.
+    pub fn subscribe(&self) -> Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// Like [`Blockchain::subscribe`], delivering to an existing channel.
+    pub fn subscribe_with(&self, sender: SyncSender<Event>) {
+        self.events.subscribe_with(sender);
+    }
+
+    fn publish_block(&self, block: &Block) {
+        self.events.publish(&Event::block(block));
+        for tx in block.get_transactions() {
+            self.events
+                .publish(&Event::tx(tx, TxEventStatus::Confirmed));
+        }
     }
 
     pub fn iterator(&self) -> Iterator {
-        Iterator::new(self.get_tip_hash(), self.db.clone())
+        Iterator::new(self.get_tip_hash(), self.blocks_tree.clone())
+    }
+
+    /// Walks from the [Block] with `block_hash` back to genesis.
+    pub fn iterator_from(&self, block_hash: BlockHash) -> Iterator {
+        Iterator::new(block_hash, self.blocks_tree.clone())
+    }
+
+    /// Walks the best chain from the [Block] at `height` up to the tip.
+    pub fn forward_iterator(&self, height: usize) -> ForwardIterator {
+        ForwardIterator {
+            blockchain: self.clone(),
+            height,
+        }
+    }
+
+    /// Summarizes the [Block]s of the best chain at the heights in `range`, lowest
+    /// first, with every input and output along with the value each input spends.
+    pub fn export_range(&self, range: RangeInclusive<usize>) -> Vec<BlockSummary> {
+        let mut iterator = self.forward_iterator(*range.start());
+        iter::from_fn(move || iterator.next())
+            .take_while(|block| block.get_height() <= *range.end())
+            .map(|block| BlockSummary::resolve(&block, self))
+            .collect()
     }
 
     /// Navigates through the [Blockchain], identifying UTXOs by inspecting each
-    /// transaction within each [Block].
-    pub fn find_utxo(&self) -> HashMap<String, Vec<TXOutput>> {
-        let mut utxo: HashMap<String, Vec<TXOutput>> = HashMap::new();
-        let mut spent_txos: HashMap<String, Vec<usize>> = HashMap::new();
+    /// transaction within each [Block]. The outputs of each [Transaction] with any
+    /// unspent are kept by index, `None` where spent.
+    pub fn find_utxo(&self) -> HashMap<Txid, Vec<Option<TXOutput>>> {
+        self.find_utxo_with_progress(|_| {})
+    }
+
+    /// Like [`Blockchain::find_utxo`], calling `progress` with the number of [Block]s
+    /// inspected so far after each one.
+    pub fn find_utxo_with_progress(
+        &self,
+        mut progress: impl FnMut(usize),
+    ) -> HashMap<Txid, Vec<Option<TXOutput>>> {
+        let mut utxo = HashMap::new();
+        let mut spent_txos: HashSet<(Txid, usize)> = HashSet::new();
 
         let mut iterator = self.iterator();
-        loop {
-            let option = iterator.next();
-            if option.is_none() {
-                break;
+        let mut blocks = 0;
+        while let Some(block) = iterator.next() {
+            blocks += 1;
+            progress(blocks);
+            for txin in block
+                .get_transactions()
+                .iter()
+                .filter(|tx| !tx.is_coinbase())
+                .flat_map(Transaction::get_vin)
+            {
+                spent_txos.insert((txin.get_txid(), txin.get_vout()));
             }
-            let block = option.unwrap();
-            'outer: for tx in block.get_transactions() {
-                let txid_hex = HEXLOWER.encode(tx.get_id());
-                for (idx, out) in tx.get_vout().iter().enumerate() {
-                    if let Some(outs) = spent_txos.get(txid_hex.as_str()) {
-                        for spend_out_idx in outs {
-                            if idx.eq(spend_out_idx) {
-                                continue 'outer;
-                            }
-                        }
-                    }
-                    if utxo.contains_key(txid_hex.as_str()) {
-                        utxo.get_mut(txid_hex.as_str()).unwrap().push(out.clone());
-                    } else {
-                        utxo.insert(txid_hex.clone(), vec![out.clone()]);
-                    }
-                }
-                if tx.is_coinbase() {
-                    continue;
-                }
-
-                for txin in tx.get_vin() {
-                    let txid_hex = HEXLOWER.encode(txin.get_txid());
-                    if spent_txos.contains_key(txid_hex.as_str()) {
-                        spent_txos
-                            .get_mut(txid_hex.as_str())
-                            .unwrap()
-                            .push(txin.get_vout());
-                    } else {
-                        spent_txos.insert(txid_hex, vec![txin.get_vout()]);
-                    }
+            for tx in block.get_transactions() {
+                let txid = tx.get_id();
+                let outs: Vec<Option<TXOutput>> = tx
+                    .get_vout()
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, out)| (!spent_txos.contains(&(txid, idx))).then(|| out.clone()))
+                    .collect();
+                if outs.iter().any(Option::is_some) {
+                    utxo.insert(txid, outs);
                 }
             }
         }
@@ -149,49 +799,493 @@ impl Blockchain {
     }
 
     /// Searches the [Blockchain] for a specific transaction by its ID.
-    pub fn find_transaction(&self, txid: &[u8]) -> Option<Transaction> {
+    pub fn find_transaction(&self, txid: Txid) -> Option<Transaction> {
+        let (block_hash, idx) = self.locate_transaction(txid)?;
+        self.get_block(block_hash)?
+            .get_transactions()
+            .get(idx)
+            .cloned()
+    }
+
+    /// Checks whether a confirmed [Transaction] spends output `vout` of `txid`.
+    pub fn is_spent(&self, txid: Txid, vout: usize) -> bool {
         let mut iterator = self.iterator();
-        loop {
-            let option = iterator.next();
-            if option.is_none() {
-                break;
+        while let Some(block) = iterator.next() {
+            let spent = block
+                .get_transactions()
+                .iter()
+                .filter(|tx| !tx.is_coinbase())
+                .flat_map(Transaction::get_vin)
+                .any(|input| input.get_txid() == txid && input.get_vout() == vout);
+            if spent {
+                return true;
             }
-            let block = option.unwrap();
-            for transaction in block.get_transactions() {
-                if txid.eq(transaction.get_id()) {
-                    return Some(transaction.clone());
+        }
+        false
+    }
+
+    /// Looks up the [Block] of the best chain containing the transaction with the given ID.
+    pub fn get_transaction_block(&self, txid: Txid) -> Option<Block> {
+        let (block_hash, _) = self.locate_transaction(txid)?;
+        self.get_block(block_hash)
+    }
+
+    /// Checks a [Block] received from a peer: its proof of work, that it builds on a
+    /// stored [Block] one below it at the difficulty expected there, that it ends with
+    /// its only Coinbase, that each of its [Transaction]s has its hash for id, is
+    /// unique and spends known outputs with valid signatures, none of them twice, worth
+    /// at least its outputs, and that the Coinbase pays no more than the subsidy and
+    /// the fees. The [Block] and its [Transaction]s must also fit within the
+    /// [`SizeLimits`].
+    ///
+    /// The inputs of a [Block] on top of the one the UTXO set is up to date with must
+    /// spend outputs of the set. Those of other [Block]s are only checked to spend
+    /// outputs on their branch, created by its [Block]s back to where it joins the
+    /// best chain or by the best chain below that. Either may also spend the outputs
+    /// of a [Transaction] earlier in the [Block].
+    pub fn validate_block(&self, block: &Block) -> Result<(), BlockError> {
+        self.check_block(block, BlockChecks::Full)
+    }
+
+    /// Like [`Blockchain::validate_block`], checking only as much as `checks` asks.
+    pub fn check_block(&self, block: &Block, checks: BlockChecks) -> Result<(), BlockError> {
+        if checks == BlockChecks::None {
+            return Ok(());
+        }
+        self.check_size(block)?;
+        if !block.validate_pow() {
+            return Err(BlockError::InvalidProofOfWork);
+        }
+        let pre_block_hash = block.get_pre_block_hash();
+        let parent = self
+            .get_block(pre_block_hash)
+            .ok_or(BlockError::UnknownParent(pre_block_hash))?;
+        let expected = parent.get_height() + 1;
+        if block.get_height() != expected {
+            return Err(BlockError::WrongHeight {
+                height: block.get_height(),
+                expected,
+            });
+        }
+        let expected = self.get_next_target_bits(&parent);
+        if block.get_target_bits() != expected {
+            return Err(BlockError::WrongDifficulty {
+                target_bits: block.get_target_bits(),
+                expected,
+            });
+        }
+        if checks == BlockChecks::Linkage {
+            return Ok(());
+        }
+        check_coinbase_position(block.get_transactions())?;
+        let verify_signatures = checks == BlockChecks::Full;
+        let utxo_set = UTXOSet::new(self.clone());
+        let on_utxo_set = utxo_set.get_best_block() == Some(pre_block_hash);
+        let branch = if on_utxo_set {
+            Branch::default()
+        } else {
+            self.branch_ending_at(parent)
+        };
+        let mut txids = HashSet::new();
+        let mut created = HashMap::new();
+        let mut spent = HashSet::new();
+        let mut fees = 0_u64;
+        for tx in block.get_transactions() {
+            if !tx.has_valid_id() {
+                return Err(BlockError::MismatchedId(tx.get_id()));
+            }
+            if !txids.insert(tx.get_id()) {
+                return Err(BlockError::DuplicateTransaction(tx.get_id()));
+            }
+            if on_utxo_set && utxo_set.has_outputs(tx.get_id()) {
+                return Err(BlockError::ExistingTransaction(tx.get_id()));
+            }
+            if tx.is_coinbase() {
+                continue;
+            }
+            for input in tx.get_vin() {
+                if !spent.insert((input.get_txid(), input.get_vout())) {
+                    return Err(BlockError::DoubleSpend(tx.get_id()));
                 }
             }
+            let fee = if on_utxo_set {
+                utxo_set
+                    .check_tx_spending(tx, &created, verify_signatures)
+                    .ok()
+            } else {
+                self.validate_tx_on_branch(tx, &created, &branch, verify_signatures)
+            };
+            fees = fee
+                .and_then(|fee| fees.checked_add(fee))
+                .ok_or_else(|| BlockError::InvalidTransaction(tx.get_id()))?;
+            created.insert(tx.get_id(), tx.get_vout().to_vec());
         }
-        None
+        self.check_reward(block.get_transactions(), fees)
+    }
+
+    /// Returns the difficulty a [Block] on top of `parent` must be mined to, see
+    /// [`next_target_bits`].
+    pub fn get_next_target_bits(&self, parent: &Block) -> i64 {
+        next_target_bits(self.params, &parent.get_header(), |header| {
+            self.get_block(header.get_pre_block_hash())
+                .map(|block| block.get_header())
+        })
     }
 
-    /// Add a new [Block] to the [Blockchain] after it's been mined.
-    pub fn add_block(&self, block: &Block) {
-        let block_tree = self.db.open_tree(BLOCKS_TREE).unwrap();
-        if block_tree.get(block.get_hash()).unwrap().is_some() {
-            return;
+    /// Collects the outputs created on the branch ending at `tip`, by its [Block]s
+    /// back to where it joins the best chain.
+    fn branch_ending_at(&self, tip: Block) -> Branch {
+        let mut outputs = HashMap::new();
+        let mut block = tip;
+        while !self.is_on_best_chain(&block) {
+            for tx in block.get_transactions() {
+                outputs.insert(tx.get_id(), tx.get_vout().to_vec());
+            }
+            block = self
+                .get_block(block.get_pre_block_hash())
+                .expect("the parent of a stored block is stored");
         }
-        let _: TransactionResult<(), ()> = block_tree.transaction(|tx_db| {
-            let _ = tx_db.insert(block.get_hash(), block.serialize()).unwrap();
-            let tip_block_bytes = tx_db
-                .get(self.get_tip_hash())
-                .unwrap()
-                .expect("The tip hash is not valid");
-            let tip_block = Block::deserialize(tip_block_bytes.as_ref());
-            if block.get_height() > tip_block.get_height() {
-                let _ = tx_db.insert(TIP_BLOCK_HASH_KEY, block.get_hash()).unwrap();
-                self.set_tip_hash(block.get_hash());
+        Branch {
+            outputs,
+            fork_height: block.get_height(),
+        }
+    }
+
+    /// Like [`UTXOSet::check_tx_spending`] against every output on `branch`, spent or
+    /// not, returning the fee `tx` pays when it is valid.
+    fn validate_tx_on_branch(
+        &self,
+        tx: &Transaction,
+        unconfirmed: &HashMap<Txid, Vec<TXOutput>>,
+        branch: &Branch,
+        verify_signatures: bool,
+    ) -> Option<u64> {
+        let spent = tx
+            .get_vin()
+            .iter()
+            .map(|input| {
+                let (txid, vout) = (input.get_txid(), input.get_vout());
+                if let Some(outputs) = unconfirmed.get(&txid).or_else(|| branch.outputs.get(&txid))
+                {
+                    return outputs.get(vout).cloned();
+                }
+                let (block_hash, idx) = self.locate_transaction(txid)?;
+                let block = self
+                    .get_block(block_hash)
+                    .filter(|block| block.get_height() <= branch.fork_height)?;
+                block
+                    .get_transactions()
+                    .get(idx)?
+                    .get_vout()
+                    .get(vout)
+                    .cloned()
+            })
+            .collect::<Option<Vec<TXOutput>>>()?;
+        let fee = tx.get_fee(&spent).ok()?;
+        (!verify_signatures || tx.verify_spending(&spent)).then_some(fee)
+    }
+
+    /// Checks `block` and each of its [Transaction]s against the [`SizeLimits`].
+    fn check_size(&self, block: &Block) -> Result<(), BlockError> {
+        let count = block.get_transactions().len();
+        if count > self.limits.max_block_txs {
+            return Err(BlockError::TooManyTransactions {
+                count,
+                max: self.limits.max_block_txs,
+            });
+        }
+        let size = block.serialize().len();
+        if size > self.limits.max_block_bytes {
+            return Err(BlockError::TooLarge {
+                size,
+                max: self.limits.max_block_bytes,
+            });
+        }
+        for tx in block.get_transactions() {
+            let size = tx.serialize().len();
+            if size > self.limits.max_tx_bytes {
+                return Err(BlockError::TransactionTooLarge {
+                    txid: tx.get_id(),
+                    size,
+                    max: self.limits.max_tx_bytes,
+                });
             }
-            Ok(())
-        });
+        }
+        Ok(())
+    }
+
+    /// Checks that the Coinbase [Transaction]s among `transactions` pay out no more
+    /// than the subsidy and the `fees` of the others.
+    fn check_reward(&self, transactions: &[Transaction], fees: u64) -> Result<(), BlockError> {
+        let allowed = self.params.subsidy.saturating_add(fees);
+        let mut reward = 0_u64;
+        for tx in transactions.iter().filter(|tx| tx.is_coinbase()) {
+            reward = tx
+                .get_output_value()
+                .ok()
+                .and_then(|value| reward.checked_add(value))
+                .ok_or_else(|| BlockError::InvalidTransaction(tx.get_id()))?;
+        }
+        if reward > allowed {
+            return Err(BlockError::ExcessiveReward { reward, allowed });
+        }
+        Ok(())
+    }
+
+    /// Adds a [Block] received from a peer once [`Blockchain::validate_block`] accepts
+    /// it. A [Block] already stored is accepted without checking it again.
+    ///
+    /// The tip moves to the [Block] when the chain ending at it has more total work
+    /// than the current one, so the first of two equal chains to arrive is kept, and
+    /// the UTXO set is brought up to date with it. When the [Block] isn't on top of
+    /// the old tip the chain is reorganized onto it, which fails when a [Block] of its
+    /// branch turns out to be invalid once the UTXO set is rolled back to the fork.
+    ///
+    /// Subscribers only hear of the [Block]s the tip moves onto, every [Block] of the
+    /// new branch after a reorganization, and nothing of those stored on a side branch.
+    pub fn add_block(&self, block: &Block) -> Result<(), BlockError> {
+        self.add_block_with(block, BlockChecks::Full)
+    }
+
+    /// Like [`Blockchain::add_block`], checking the [Block] only as much as `checks`
+    /// asks. The [Block]s of a branch the chain is reorganized onto are still checked
+    /// in full.
+    pub fn add_block_with(&self, block: &Block, checks: BlockChecks) -> Result<(), BlockError> {
+        let _writer = self.tip_writer.lock().unwrap();
+        let block_hash = block.get_hash();
+        if self.blocks_tree().contains_key(block_hash.as_bytes())? {
+            return Ok(());
+        }
+        let old_tip_hash = self.get_tip_hash();
+        if block.get_pre_block_hash() == old_tip_hash {
+            self.sync_utxo_set();
+        }
+        self.check_block(block, checks)?;
+        self.blocks_tree()
+            .insert(block_hash.as_bytes(), block.serialize())?;
+        let work = self
+            .get_chain_work(block_hash)
+            .expect("the block was just stored");
+        let tip_work = self
+            .get_chain_work(old_tip_hash)
+            .expect("the tip hash is valid");
+        if work <= tip_work {
+            return Ok(());
+        }
+        if block.get_pre_block_hash() == old_tip_hash {
+            self.extend_tip(block)?;
+        } else {
+            let branch = self.reorganize(block)?;
+            info!("Reorganized the chain from tip {old_tip_hash} to {block_hash}");
+            for connected in &branch {
+                self.publish_block(connected);
+            }
+        }
+        Ok(())
+    }
+
+    /// Stores `block`, a child of the tip, applies it to the UTXO set and moves the tip
+    /// to it. The caller holds the tip writer.
+    fn extend_tip(&self, block: &Block) -> Result<(), BlockError> {
+        self.sync_utxo_set();
+        self.blocks_tree()
+            .insert(block.get_hash().as_bytes(), block.serialize())?;
+        UTXOSet::new(self.clone())
+            .update(block)
+            .map_err(BlockError::Unspendable)?;
+        self.move_tip(block.get_hash())?;
+        self.publish_block(block);
+        Ok(())
+    }
+
+    /// Rebuilds the UTXO set at the tip when it isn't up to date with it, as after a
+    /// crash between moving the tip and applying its [Block] to the set.
+    fn sync_utxo_set(&self) {
+        let utxo_set = UTXOSet::new(self.clone());
+        if utxo_set.get_best_block() != Some(self.get_tip_hash()) {
+            info!("The UTXO set is not up to date with the tip, rebuilding it");
+            utxo_set.reindex();
+        }
+    }
+
+    /// Moves the tip to `block` on another branch. The old branch is disconnected from
+    /// the UTXO set back to the fork point, then each [Block] of the new branch is
+    /// validated against the rolled-back set before it is connected.
+    ///
+    /// When a [Block] of the new branch is invalid, those connected so far are
+    /// disconnected again and the old branch is reconnected, leaving the tip where it
+    /// was. The UTXO set is rebuilt at the fork point instead of rolled back when it
+    /// isn't up to date with the old tip or is missing the undo data of a [Block] on
+    /// the old branch.
+    ///
+    /// Returns the [Block]s of the new branch, from the fork point up to `block`.
+    fn reorganize(&self, block: &Block) -> Result<Vec<Block>, BlockError> {
+        let parent = |block: &Block| {
+            self.get_block(block.get_pre_block_hash())
+                .expect("the parent of a stored block is stored")
+        };
+        let old_tip = self
+            .get_block(self.get_tip_hash())
+            .expect("the tip hash is valid");
+        let mut branch = vec![];
+        let mut old_branch = vec![];
+        let mut new_side = block.clone();
+        while new_side.get_height() > old_tip.get_height() {
+            let next = parent(&new_side);
+            branch.push(new_side);
+            new_side = next;
+        }
+        let mut old_side = old_tip.clone();
+        while old_side.get_height() > new_side.get_height() {
+            let next = parent(&old_side);
+            old_branch.push(old_side);
+            old_side = next;
+        }
+        while old_side.get_hash() != new_side.get_hash() {
+            let next = parent(&new_side);
+            branch.push(new_side);
+            new_side = next;
+            let next = parent(&old_side);
+            old_branch.push(old_side);
+            old_side = next;
+        }
+        let fork_hash = new_side.get_hash();
+
+        self.rewind_to(fork_hash)?;
+        for new_block in branch.iter().rev() {
+            if let Err(e) = self.connect_tip(new_block) {
+                self.rewind_to(fork_hash)?;
+                let restored = old_branch
+                    .iter()
+                    .rev()
+                    .try_for_each(|old_block| self.connect_tip(old_block));
+                if restored.is_err() {
+                    self.move_tip(old_tip.get_hash())?;
+                    UTXOSet::new(self.clone()).reindex();
+                }
+                return Err(e);
+            }
+        }
+        branch.reverse();
+        Ok(branch)
+    }
+
+    /// Moves the tip back to `fork_hash`, one of its ancestors, disconnecting the
+    /// [Block]s above it from the UTXO set, or rebuilding the set at `fork_hash` when
+    /// they can't be disconnected.
+    fn rewind_to(&self, fork_hash: BlockHash) -> Result<(), BlockError> {
+        let utxo_set = UTXOSet::new(self.clone());
+        let mut rolled_back = utxo_set.get_best_block() == Some(self.get_tip_hash());
+        while rolled_back && self.get_tip_hash() != fork_hash {
+            rolled_back = self.pop_tip()?.is_some();
+        }
+        if !rolled_back {
+            self.move_tip(fork_hash)?;
+            utxo_set.reindex();
+        }
+        Ok(())
+    }
+
+    /// Validates `block`, a stored child of the tip, against the UTXO set, which must be
+    /// up to date with the tip, then connects it to the set and moves the tip to it.
+    fn connect_tip(&self, block: &Block) -> Result<(), BlockError> {
+        self.validate_block(block)?;
+        UTXOSet::new(self.clone())
+            .update(block)
+            .map_err(BlockError::Unspendable)?;
+        self.move_tip(block.get_hash())
+    }
+
+    /// Records `block_hash` as the tip and brings the indexes up to date with it.
+    fn move_tip(&self, block_hash: BlockHash) -> Result<(), BlockError> {
+        self.blocks_tree()
+            .insert(TIP_BLOCK_HASH_KEY, block_hash.as_bytes())?;
+        self.set_tip_hash(block_hash);
+        self.update_indexes()?;
+        Ok(())
+    }
+
+    /// Moves the tip back to its parent, reverting the tip [Block] in the UTXO set, which
+    /// must be up to date with it. Returns the [Block] taken off the chain, or `None`
+    /// when the tip is the genesis [Block] or the UTXO set can't revert it.
+    pub fn disconnect_tip(&self) -> Result<Option<Block>, BlockError> {
+        let _writer = self.tip_writer.lock().unwrap();
+        self.pop_tip()
+    }
+
+    /// Like [`Blockchain::disconnect_tip`] for a caller holding the tip writer.
+    fn pop_tip(&self) -> Result<Option<Block>, BlockError> {
+        let utxo_set = UTXOSet::new(self.clone());
+        let block = self
+            .get_block(self.get_tip_hash())
+            .expect("the tip hash is valid");
+        if block.get_height() == 0 || !utxo_set.disconnect(&block) {
+            return Ok(None);
+        }
+        self.move_tip(block.get_pre_block_hash())?;
+        Ok(Some(block))
+    }
+
+    /// Returns the total work of the chain ending at the [Block] with `block_hash`,
+    /// or `None` when it isn't stored.
+    ///
+    /// The work of [Block]s stored before it was tracked is computed on first use, back
+    /// to the nearest [Block] whose work is known, and kept.
+    pub fn get_chain_work(&self, block_hash: BlockHash) -> Option<BigUint> {
+        let mut unknown = vec![];
+        let mut hash = block_hash;
+        let mut work = loop {
+            if let Some(bytes) = self.chain_work_tree.get(hash.as_bytes()).unwrap() {
+                break BigUint::from_bytes_be(bytes.as_ref());
+            }
+            let block = self.get_block(hash)?;
+            let is_genesis = block.get_height() == 0;
+            hash = block.get_pre_block_hash();
+            unknown.push(block);
+            if is_genesis {
+                break BigUint::zero();
+            }
+        };
+        for block in unknown.iter().rev() {
+            work += block.get_work();
+            self.chain_work_tree
+                .insert(block.get_hash().as_bytes(), work.to_bytes_be())
+                .unwrap();
+        }
+        Some(work)
+    }
+
+    /// Adds [Block]s read in bulk, each through [`Blockchain::add_block_with`] with
+    /// `checks`, flushing the database once for all of them. Stops at the first [Block]
+    /// rejected, returning it with the reason.
+    pub fn add_blocks<'a>(
+        &self,
+        blocks: &'a [Block],
+        checks: BlockChecks,
+    ) -> Result<(), (&'a Block, BlockError)> {
+        let added = blocks
+            .iter()
+            .try_for_each(|block| self.add_block_with(block, checks).map_err(|e| (block, e)));
+        self.db.flush().unwrap();
+        added
+    }
+
+    /// Returns the height, hash and timestamp of the tip [Block].
+    pub fn get_stats(&self) -> ChainStats {
+        let tip_hash = self.get_tip_hash();
+        let tip_block = self.get_block(tip_hash).expect("The tip hash is valid");
+        ChainStats {
+            height: tip_block.get_height(),
+            tip_hash,
+            tip_timestamp: tip_block.get_timestamp(),
+        }
     }
 
     /// Returns the height of the [Block] with the highest height in [Blockchain].
     pub fn get_best_height(&self) -> usize {
-        let block_tree = self.db.open_tree(BLOCKS_TREE).unwrap();
+        let block_tree = self.blocks_tree();
         let tip_block_bytes = block_tree
-            .get(self.get_tip_hash())
+            .get(self.get_tip_hash().as_bytes())
             .unwrap()
             .expect("The tip hash is valid");
         let tip_block = Block::deserialize(tip_block_bytes.as_ref());
@@ -200,16 +1294,219 @@ impl Blockchain {
 
     /// Retrieve the [Block] bytes for the database corresponding to the hash
     /// and deserialize them into a [Block].
-    pub fn get_block(&self, block_hash: &[u8]) -> Option<Block> {
-        let block_tree = self.db.open_tree(BLOCKS_TREE).unwrap();
-        if let Some(block_bytes) = block_tree.get(block_hash).unwrap() {
+    pub fn get_block(&self, block_hash: BlockHash) -> Option<Block> {
+        let block_tree = self.blocks_tree();
+        if let Some(block_bytes) = block_tree.get(block_hash.as_bytes()).unwrap() {
             return Some(Block::deserialize(&block_bytes));
         }
         None
     }
 
+    /// Looks up the [Block] at `height` on the best chain in the height index.
+    pub fn get_block_by_height(&self, height: usize) -> Option<Block> {
+        let hash = self.height_tree.get(height_key(height)).unwrap()?;
+        self.get_block(BlockHash::from_slice(hash.as_ref()).ok()?)
+    }
+
+    /// Returns up to `max` headers of the best chain following the first hash of
+    /// `locator` that is on it, or from genesis when none is. A light client lists its
+    /// headers in `locator` from its tip back, so that the headers pick up from where
+    /// its chain and this one part.
+    pub fn get_headers(&self, locator: &[BlockHash], max: usize) -> Vec<BlockHeader> {
+        let start = locator
+            .iter()
+            .filter_map(|hash| self.get_block(*hash))
+            .find(|block| self.is_on_best_chain(block))
+            .map_or(0, |block| block.get_height() + 1);
+        (start..)
+            .map_while(|height| self.get_block_by_height(height))
+            .take(max)
+            .map(|block| block.get_header())
+            .collect()
+    }
+
+    /// Checks whether `block` is the [Block] of the best chain at its height.
+    fn is_on_best_chain(&self, block: &Block) -> bool {
+        self.get_block_by_height(block.get_height())
+            .is_some_and(|best| best.get_hash() == block.get_hash())
+    }
+
+    /// Brings the height, transaction and address indexes up to date with the tip.
+    ///
+    /// Walks back from the tip until a height already maps to the [Block] on the best
+    /// chain, replacing the [Block]s the index held at the heights on the way and
+    /// dropping those above the tip, along with their [Transaction]s. Called whenever
+    /// the tip moves, and when the chain is opened to build the indexes of a chain
+    /// stored before they existed.
+    fn update_indexes(&self) -> sled::Result<()> {
+        let version = self
+            .blocks_tree()
+            .get(INDEX_VERSION_KEY)?
+            .and_then(|v| v.first().copied());
+        if version != Some(INDEX_VERSION) {
+            self.clear_indexes()?;
+        }
+        let tip_hash = self.get_tip_hash();
+        let indexed_tip = self.blocks_tree().get(HEIGHT_INDEX_TIP_KEY)?;
+        if indexed_tip.as_deref() == Some(tip_hash.as_bytes()) {
+            return Ok(());
+        }
+        let mut block = self.get_block(tip_hash).expect("the tip hash is valid");
+        let mut replaced = vec![];
+        for entry in self.height_tree.range(height_key(block.get_height() + 1)..) {
+            let (key, hash) = entry?;
+            replaced.push((key, Some(hash)));
+        }
+        let mut connected = vec![];
+        loop {
+            let key = height_key(block.get_height());
+            let indexed = self.height_tree.get(key)?;
+            if indexed.as_deref() == Some(block.get_hash().as_bytes()) {
+                break;
+            }
+            replaced.push((IVec::from(&key), indexed));
+            let height = block.get_height();
+            let pre_block_hash = block.get_pre_block_hash();
+            connected.push(block);
+            if height == 0 {
+                break;
+            }
+            block = self
+                .get_block(pre_block_hash)
+                .expect("the parent of a stored block is stored");
+        }
+        let mut heights = sled::Batch::default();
+        let mut txids = sled::Batch::default();
+        let mut addresses = sled::Batch::default();
+        // Removed before the new branch is added, as a Transaction can be on both.
+        for (key, hash) in replaced {
+            heights.remove(key);
+            let old_block = hash
+                .and_then(|hash| BlockHash::from_slice(hash.as_ref()).ok())
+                .and_then(|hash| self.get_block(hash));
+            let Some(old_block) = old_block else {
+                continue;
+            };
+            for tx in old_block.get_transactions() {
+                txids.remove(tx.get_id().as_bytes());
+            }
+            for (key, _) in address_entries(&old_block) {
+                addresses.remove(key);
+            }
+        }
+        for block in &connected {
+            heights.insert(&height_key(block.get_height()), block.get_hash().as_bytes());
+            for (idx, tx) in block.get_transactions().iter().enumerate() {
+                let mut location = block.get_hash().as_bytes().to_vec();
+                location.extend(tx_position(idx));
+                txids.insert(tx.get_id().as_bytes(), location);
+            }
+            for (key, txid) in address_entries(block) {
+                addresses.insert(key, txid.as_bytes());
+            }
+        }
+        self.height_tree.apply_batch(heights)?;
+        self.tx_index_tree.apply_batch(txids)?;
+        self.address_index_tree.apply_batch(addresses)?;
+        self.blocks_tree()
+            .insert(HEIGHT_INDEX_TIP_KEY, tip_hash.as_bytes())?;
+        self.blocks_tree()
+            .insert(INDEX_VERSION_KEY, &[INDEX_VERSION])?;
+        Ok(())
+    }
+
+    fn clear_indexes(&self) -> sled::Result<()> {
+        self.blocks_tree().remove(HEIGHT_INDEX_TIP_KEY)?;
+        self.height_tree.clear()?;
+        self.tx_index_tree.clear()?;
+        self.address_index_tree.clear()
+    }
+
+    /// Drops the height, transaction and address indexes and builds them again from the
+    /// best chain, returning the number of [Transaction]s indexed.
+    pub fn reindex_transactions(&self) -> sled::Result<usize> {
+        self.clear_indexes()?;
+        self.update_indexes()?;
+        Ok(self.tx_index_tree.len())
+    }
+
+    /// Looks up the hash of the [Block] of the best chain holding `txid` and the
+    /// position of the [Transaction] in it.
+    fn locate_transaction(&self, txid: Txid) -> Option<(BlockHash, usize)> {
+        let location = self.tx_index_tree.get(txid.as_bytes()).unwrap()?;
+        let (hash, idx) = location.split_at_checked(HASH_LEN)?;
+        let idx = u32::from_be_bytes(idx.try_into().ok()?);
+        Some((
+            BlockHash::from_slice(hash).ok()?,
+            usize::try_from(idx).ok()?,
+        ))
+    }
+
+    /// Lists the [Transaction]s of the best chain that pay `pub_key_hash` or spend
+    /// from it, newest first, with the net amount each one moved for it.
+    pub fn get_address_history(&self, pub_key_hash: &[u8]) -> Vec<TxRecord> {
+        let mut records = vec![];
+        for entry in self.address_index_tree.scan_prefix(pub_key_hash).rev() {
+            let (key, txid) = entry.unwrap();
+            let height = key
+                .get(pub_key_hash.len()..pub_key_hash.len() + 8)
+                .and_then(|height| height.try_into().ok())
+                .map(u64::from_be_bytes)
+                .and_then(|height| usize::try_from(height).ok());
+            let tx = Txid::from_slice(txid.as_ref())
+                .ok()
+                .and_then(|txid| self.find_transaction(txid));
+            let (Some(height), Some(tx)) = (height, tx) else {
+                continue;
+            };
+            let received = tx
+                .get_vout()
+                .iter()
+                .filter(|output| output.is_locked_with_key(pub_key_hash))
+                .fold(0_u64, |sum, output| sum.saturating_add(output.get_value()));
+            let sent = if tx.is_coinbase() {
+                0
+            } else {
+                tx.get_vin()
+                    .iter()
+                    .filter(|input| input.uses_key(pub_key_hash))
+                    .filter_map(|input| {
+                        self.find_transaction(input.get_txid())?
+                            .get_vout()
+                            .get(input.get_vout())
+                            .map(TXOutput::get_value)
+                    })
+                    .fold(0_u64, u64::saturating_add)
+            };
+            let (direction, amount) = if sent > 0 && sent >= received {
+                (Direction::Sent, sent - received)
+            } else {
+                (Direction::Received, received - sent)
+            };
+            records.push(TxRecord {
+                txid: tx.get_id().to_string(),
+                height,
+                direction,
+                amount,
+                coinbase: tx.is_coinbase(),
+            });
+        }
+        records
+    }
+
+    /// Looks a [Block] up by its hex hash, or by its height when `id` is a number.
+    pub fn find_block(&self, id: &str) -> Option<Block> {
+        if id.len() == 64 {
+            if let Ok(block_hash) = id.parse() {
+                return self.get_block(block_hash);
+            }
+        }
+        let height = id.parse().ok()?;
+        self.get_block_by_height(height)
+    }
+
     /// Returns a list of [Block] hashes in the [Blockchain].
-    pub fn get_block_hashes(&self) -> Vec<Vec<u8>> {
+    pub fn get_block_hashes(&self) -> Vec<BlockHash> {
         let mut iterator = self.iterator();
         let mut blocks = vec![];
         loop {
@@ -218,7 +1515,7 @@ impl Blockchain {
                 break;
             }
             let block = option.unwrap();
-            blocks.push(block.get_hash_bytes());
+            blocks.push(block.get_hash());
         }
         blocks
     }
@@ -226,25 +1523,1136 @@ impl Blockchain {
 
 // TODO: implement Iterator for Block.
 pub struct Iterator {
-    db: Db,
-    current_hash: String,
+    blocks_tree: Tree,
+    current_hash: BlockHash,
 }
 
 impl Iterator {
-    const fn new(tip_hash: String, db: Db) -> Self {
+    const fn new(tip_hash: BlockHash, blocks_tree: Tree) -> Self {
         Self {
             current_hash: tip_hash,
-            db,
+            blocks_tree,
         }
     }
 
     #[allow(clippy::should_implement_trait)]
     pub fn next(&mut self) -> Option<Block> {
-        let block_tree = self.db.open_tree(BLOCKS_TREE).unwrap();
-        let data = block_tree.get(self.current_hash.clone()).unwrap();
+        let data = self.blocks_tree.get(self.current_hash.as_bytes()).unwrap();
         data.as_ref()?;
         let block = Block::deserialize(data.unwrap().to_vec().as_slice());
         self.current_hash = block.get_pre_block_hash();
         Some(block)
     }
 }
+
+/// Walks the best chain from genesis towards the tip, looking each [Block] up in the
+/// height index when it is reached so that memory stays flat however long the chain.
+pub struct ForwardIterator {
+    blockchain: Blockchain,
+    /// The height of the next [Block].
+    height: usize,
+}
+
+impl ForwardIterator {
+    #[allow(clippy::should_implement_trait)]
+    pub fn next(&mut self) -> Option<Block> {
+        let block = self.blockchain.get_block_by_height(self.height)?;
+        self.height += 1;
+        Some(block)
+    }
+}
+
+/// Returns the difficulty a [Block] on top of `parent` must be mined to on a chain
+/// with `params`, looking up the ancestors of `parent` with `get_parent`.
+///
+/// Every `retarget_interval` [Block]s, the time the last interval took is compared
+/// with the time expected at one [Block] per `block_interval`, and the target moves
+/// by the power of two nearest to their ratio, at most 4x either way. Otherwise
+/// the difficulty of `parent` carries over.
+pub fn next_target_bits(
+    params: &ChainParams,
+    parent: &BlockHeader,
+    get_parent: impl Fn(&BlockHeader) -> Option<BlockHeader>,
+) -> i64 {
+    let interval = params.retarget_interval;
+    let height = parent.get_height() + 1;
+    if interval == 0 || !height.is_multiple_of(interval) {
+        return parent.get_target_bits();
+    }
+    let mut first = parent.clone();
+    let mut gaps = 0;
+    while gaps < interval && first.get_height() > 0 {
+        first = get_parent(&first).expect("the ancestors of a stored block are stored");
+        gaps += 1;
+    }
+    if gaps == 0 {
+        return parent.get_target_bits();
+    }
+    let expected =
+        u128::try_from(gaps).unwrap_or(u128::MAX) * u128::from(params.block_interval) * 1000;
+    let actual = u128::try_from(parent.get_timestamp() - first.get_timestamp())
+        .unwrap_or(0)
+        .max(1);
+    (parent.get_target_bits() + retarget_step(expected, actual))
+        .clamp(params.target_bits, MAX_TARGET_BITS)
+}
+
+/// Checks that the last of `transactions` is a Coinbase and that none of the others is.
+fn check_coinbase_position(transactions: &[Transaction]) -> Result<(), BlockError> {
+    match transactions.split_last() {
+        Some((last, others))
+            if last.is_coinbase() && !others.iter().any(Transaction::is_coinbase) =>
+        {
+            Ok(())
+        }
+        _ => Err(BlockError::MisplacedCoinbase),
+    }
+}
+
+/// The key of `height` in the height index, big-endian so that heights sort in order.
+fn height_key(height: usize) -> [u8; 8] {
+    u64::try_from(height).unwrap_or(u64::MAX).to_be_bytes()
+}
+
+/// Encodes the position of a [Transaction] in its [Block] for the indexes.
+fn tx_position(idx: usize) -> [u8; 4] {
+    u32::try_from(idx).unwrap_or(u32::MAX).to_be_bytes()
+}
+
+/// Lists the address index keys of `block`, one for each pub key hash that each
+/// [Transaction] pays or spends from, with the id of the [Transaction].
+fn address_entries(block: &Block) -> Vec<(Vec<u8>, Txid)> {
+    let mut entries = vec![];
+    for (idx, tx) in block.get_transactions().iter().enumerate() {
+        let mut hashes: Vec<Vec<u8>> = tx
+            .get_vout()
+            .iter()
+            .map(|output| output.get_pub_key_hash().to_vec())
+            .collect();
+        if !tx.is_coinbase() {
+            hashes.extend(
+                tx.get_vin()
+                    .iter()
+                    .map(|input| hash_pub_key(input.get_pub_key())),
+            );
+        }
+        hashes.sort();
+        hashes.dedup();
+        for mut key in hashes {
+            key.extend(height_key(block.get_height()));
+            key.extend(tx_position(idx));
+            entries.push((key, tx.get_id()));
+        }
+    }
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::test_utils::{coinbase, mine, pay, temp_chain};
+    use crate::transactions::{OutPoint, Recipient, TransactionError};
+    use crate::wallet::Wallet;
+
+    /// Mines `transactions` on top of the tip without validating them.
+    fn block_on_tip(blockchain: &Blockchain, transactions: Vec<Transaction>) -> Block {
+        let parent = blockchain.get_block(blockchain.get_tip_hash()).unwrap();
+        block_on(blockchain, &parent, transactions)
+    }
+
+    /// Mines `transactions` on top of `parent` without validating them.
+    fn block_on(blockchain: &Blockchain, parent: &Block, transactions: Vec<Transaction>) -> Block {
+        Block::new(
+            parent.get_hash(),
+            transactions,
+            parent.get_height() + 1,
+            blockchain.get_next_target_bits(parent),
+            &*system_clock(),
+        )
+    }
+
+    /// Rewrites the value of the first output of `tx`, keeping its id.
+    fn with_first_value(tx: &Transaction, value: u64) -> Transaction {
+        let mut json = serde_json::to_value(tx).unwrap();
+        json["vout"][0]["value"] = value.into();
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn rewritten_coinbase_is_rejected() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let tx = with_first_value(&coinbase(&miner, 0), 1);
+        let block = block_on_tip(&blockchain, vec![tx.clone()]);
+        assert_eq!(
+            blockchain.validate_block(&block),
+            Err(BlockError::MismatchedId(tx.get_id()))
+        );
+    }
+
+    #[test]
+    fn rewritten_transaction_is_rejected() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let tx = with_first_value(&pay(&blockchain, &miner, &Wallet::new(), 3), 2);
+        let block = block_on_tip(&blockchain, vec![tx.clone(), coinbase(&miner, 0)]);
+        assert_eq!(
+            blockchain.validate_block(&block),
+            Err(BlockError::MismatchedId(tx.get_id()))
+        );
+    }
+
+    #[test]
+    fn coinbase_must_be_last_and_alone() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let tx = pay(&blockchain, &miner, &Wallet::new(), 3);
+        for transactions in [
+            vec![],
+            vec![tx.clone()],
+            vec![coinbase(&miner, 1), tx],
+            vec![coinbase(&miner, 0), coinbase(&miner, 0)],
+        ] {
+            let block = block_on_tip(&blockchain, transactions);
+            assert_eq!(
+                blockchain.validate_block(&block),
+                Err(BlockError::MisplacedCoinbase)
+            );
+        }
+    }
+
+    #[test]
+    fn transaction_with_unspent_outputs_is_not_repeated() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let block = mine(&blockchain, vec![], &miner);
+        let repeated = block.get_transactions()[0].clone();
+        let block = block_on_tip(&blockchain, vec![repeated.clone()]);
+        assert_eq!(
+            blockchain.validate_block(&block),
+            Err(BlockError::ExistingTransaction(repeated.get_id()))
+        );
+    }
+
+    #[test]
+    fn valid_block_is_accepted() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let tx = pay(&blockchain, &miner, &Wallet::new(), 3);
+        let block = block_on_tip(&blockchain, vec![tx, coinbase(&miner, 1)]);
+        assert_eq!(blockchain.validate_block(&block), Ok(()));
+        blockchain.add_block(&block).unwrap();
+        assert_eq!(blockchain.get_tip_hash(), block.get_hash());
+    }
+
+    #[test]
+    fn branch_with_more_work_replaces_the_tip() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let genesis = blockchain.get_block(blockchain.get_tip_hash()).unwrap();
+        let (tx_a, tx_b) = (
+            pay(&blockchain, &miner, &Wallet::new(), 3),
+            pay(&blockchain, &miner, &Wallet::new(), 4),
+        );
+        let a1 = mine(&blockchain, vec![tx_a.clone()], &miner);
+
+        let b1 = block_on(
+            &blockchain,
+            &genesis,
+            vec![tx_b.clone(), coinbase(&miner, 1)],
+        );
+        blockchain.add_block(&b1).unwrap();
+        assert_eq!(blockchain.get_tip_hash(), a1.get_hash());
+
+        let b2 = block_on(&blockchain, &b1, vec![coinbase(&miner, 0)]);
+        blockchain.add_block(&b2).unwrap();
+        assert_eq!(blockchain.get_tip_hash(), b2.get_hash());
+        let utxo_set = UTXOSet::new(blockchain);
+        assert_eq!(utxo_set.get_best_block(), Some(b2.get_hash()));
+        assert!(!utxo_set.has_outputs(tx_a.get_id()));
+        assert!(utxo_set.has_outputs(tx_b.get_id()));
+        assert!(utxo_set.verify(|_| {}).is_consistent());
+    }
+
+    /// A chain whose difficulty is retargeted every `interval` [Block]s, expected a
+    /// minute apart.
+    fn retargeting_chain(miner: &Wallet, interval: usize) -> Blockchain {
+        let params = Box::leak(Box::new(ChainParams {
+            retarget_interval: interval,
+            block_interval: 60,
+            ..*Network::Regtest.params()
+        }));
+        temp_chain(miner).with_params(params)
+    }
+
+    /// Mines `transactions` on top of `parent` without validating them, stamped
+    /// `millis` after it.
+    fn block_after(
+        blockchain: &Blockchain,
+        parent: &Block,
+        millis: i64,
+        transactions: Vec<Transaction>,
+    ) -> Block {
+        Block::new(
+            parent.get_hash(),
+            transactions,
+            parent.get_height() + 1,
+            blockchain.get_next_target_bits(parent),
+            &MockClock::new(parent.get_timestamp() + millis),
+        )
+    }
+
+    #[test]
+    fn difficulty_tightens_and_loosens_with_the_pace_of_each_interval_at_most_4x() {
+        let miner = Wallet::new();
+        let blockchain = retargeting_chain(&miner, 4);
+        let genesis = blockchain.get_block(blockchain.get_tip_hash()).unwrap();
+        let lowest = genesis.get_target_bits();
+        // Mines `count` blocks a block every `seconds`, returning the bits of each one.
+        let mine_every = |seconds: i64, count: usize| {
+            (0..count)
+                .map(|_| {
+                    let tip = blockchain.get_block(blockchain.get_tip_hash()).unwrap();
+                    let block =
+                        block_after(&blockchain, &tip, seconds * 1000, vec![coinbase(&miner, 0)]);
+                    blockchain.add_block(&block).unwrap();
+                    block.get_target_bits()
+                })
+                .collect::<Vec<_>>()
+        };
+
+        // Each interval is mined at the difficulty the pace of the one before sets.
+        assert_eq!(mine_every(1, 3), vec![lowest; 3]);
+        // Sixty times too fast tightens by 4x, no more.
+        assert_eq!(mine_every(30, 4), vec![lowest + 2; 4]);
+        // Twice too fast tightens by 2x.
+        assert_eq!(mine_every(600, 4), vec![lowest + 3; 4]);
+        // Ten times too slow loosens by 4x, no more.
+        assert_eq!(mine_every(60, 4), vec![lowest + 1; 4]);
+        // On time keeps it.
+        assert_eq!(mine_every(6000, 4), vec![lowest + 1; 4]);
+        // A hundred times too slow loosens down to the lowest difficulty only.
+        assert_eq!(mine_every(60, 1), vec![lowest]);
+    }
+
+    /// A [Transaction] from `owner` paying `amount` to `to` out of the Coinbase of
+    /// `block`, with a fee of one.
+    fn spend_reward(
+        blockchain: &Blockchain,
+        block: &Block,
+        owner: &Wallet,
+        to: &Wallet,
+        amount: u64,
+    ) -> Transaction {
+        let reward = block.get_transactions().last().unwrap();
+        let unspent = [(
+            OutPoint {
+                txid: reward.get_id(),
+                vout: 0,
+            },
+            reward.get_vout()[0].clone(),
+        )];
+        let recipients = [Recipient {
+            address: to.get_address(),
+            amount,
+        }];
+        Transaction::new_spending_transaction(
+            owner.get_address().as_str(),
+            &recipients,
+            1,
+            &unspent,
+            blockchain.get_params().dust_threshold,
+            owner,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn shorter_branch_of_harder_blocks_spending_its_own_outputs_replaces_the_tip() {
+        let miner = Wallet::new();
+        let blockchain = retargeting_chain(&miner, 2);
+        let genesis = blockchain.get_block(blockchain.get_tip_hash()).unwrap();
+
+        // Slow blocks keep the lowest difficulty.
+        let mut easy = genesis.clone();
+        for _ in 0..4 {
+            easy = block_after(&blockchain, &easy, 600_000, vec![coinbase(&miner, 0)]);
+            blockchain.add_block(&easy).unwrap();
+        }
+        assert_eq!(easy.get_target_bits(), genesis.get_target_bits());
+
+        // Fast blocks raise it, and the second one spends the coinbase of the first.
+        let other = Wallet::new();
+        let b1 = block_after(&blockchain, &genesis, 1, vec![coinbase(&other, 0)]);
+        blockchain.add_block(&b1).unwrap();
+        let spend = spend_reward(&blockchain, &b1, &other, &miner, 9);
+        let b2 = block_after(
+            &blockchain,
+            &b1,
+            1,
+            vec![spend.clone(), coinbase(&other, 1)],
+        );
+        assert!(b2.get_target_bits() > easy.get_target_bits());
+        assert!(
+            blockchain.get_chain_work(b1.get_hash()) < blockchain.get_chain_work(easy.get_hash())
+        );
+        assert_eq!(blockchain.get_tip_hash(), easy.get_hash());
+
+        blockchain.add_block(&b2).unwrap();
+        assert_eq!(blockchain.get_tip_hash(), b2.get_hash());
+        assert_eq!(blockchain.get_best_height(), 2);
+        let utxo_set = UTXOSet::new(blockchain);
+        assert!(utxo_set.has_outputs(spend.get_id()));
+        assert!(!utxo_set.has_outputs(easy.get_transactions()[0].get_id()));
+        assert!(utxo_set.verify(|_| {}).is_consistent());
+    }
+
+    #[test]
+    fn side_branch_spending_an_output_confirmed_after_its_fork_is_rejected() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let genesis = blockchain.get_block(blockchain.get_tip_hash()).unwrap();
+        let confirmed = mine(&blockchain, vec![], &miner);
+        mine(&blockchain, vec![], &miner);
+        let spend = spend_reward(&blockchain, &confirmed, &miner, &Wallet::new(), 3);
+
+        let side = block_on(&blockchain, &genesis, vec![coinbase(&miner, 0)]);
+        blockchain.add_block(&side).unwrap();
+        let block = block_on(&blockchain, &side, vec![spend.clone(), coinbase(&miner, 1)]);
+        assert_eq!(
+            blockchain.validate_block(&block),
+            Err(BlockError::InvalidTransaction(spend.get_id()))
+        );
+    }
+
+    /// The hashes of the [Block]s `events` announce, leaving out the other events.
+    fn announced_blocks(events: &Receiver<Event>) -> Vec<String> {
+        events
+            .try_iter()
+            .filter_map(|event| match event {
+                Event::Block { hash, .. } => Some(hash),
+                Event::Tx { .. } => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn only_blocks_the_tip_moves_onto_are_published() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let genesis = blockchain.get_block(blockchain.get_tip_hash()).unwrap();
+        let a1 = mine(&blockchain, vec![], &miner);
+        let events = blockchain.subscribe();
+
+        let b1 = block_on(&blockchain, &genesis, vec![coinbase(&miner, 0)]);
+        blockchain.add_block(&b1).unwrap();
+        assert_eq!(blockchain.get_tip_hash(), a1.get_hash());
+        assert!(events.try_recv().is_err());
+
+        let b2 = block_on(&blockchain, &b1, vec![coinbase(&miner, 0)]);
+        blockchain.add_block(&b2).unwrap();
+        assert_eq!(
+            announced_blocks(&events),
+            vec![b1.get_hash().to_string(), b2.get_hash().to_string()]
+        );
+
+        let b3 = block_on(&blockchain, &b2, vec![coinbase(&miner, 0)]);
+        blockchain.add_block(&b3).unwrap();
+        assert_eq!(announced_blocks(&events), vec![b3.get_hash().to_string()]);
+    }
+
+    #[test]
+    fn utxo_set_behind_the_tip_is_caught_up() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let a1 = mine(&blockchain, vec![], &miner);
+        let utxo_set = UTXOSet::new(blockchain.clone());
+        assert!(utxo_set.disconnect(&a1));
+
+        let tx = pay(&blockchain, &miner, &Wallet::new(), 3);
+        let a2 = block_on_tip(&blockchain, vec![tx, coinbase(&miner, 1)]);
+        blockchain.add_block(&a2).unwrap();
+        assert_eq!(blockchain.get_tip_hash(), a2.get_hash());
+        assert_eq!(utxo_set.get_best_block(), Some(a2.get_hash()));
+        assert!(utxo_set.verify(|_| {}).is_consistent());
+    }
+
+    #[test]
+    fn blocks_added_concurrently_are_connected_once() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let mut blocks = vec![blockchain.get_block(blockchain.get_tip_hash()).unwrap()];
+        for _ in 0..5 {
+            let parent = blocks.last().unwrap();
+            blocks.push(block_on(&blockchain, parent, vec![coinbase(&miner, 0)]));
+        }
+        let blocks = &blocks[1..];
+        let events = blockchain.subscribe();
+        std::thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for block in blocks {
+                        blockchain.add_block(block).unwrap();
+                    }
+                });
+            }
+        });
+        let expected: Vec<String> = blocks
+            .iter()
+            .map(|block| block.get_hash().to_string())
+            .collect();
+        assert_eq!(announced_blocks(&events), expected);
+        let utxo_set = UTXOSet::new(blockchain.clone());
+        assert_eq!(utxo_set.get_best_block(), Some(blockchain.get_tip_hash()));
+        assert!(utxo_set.verify(|_| {}).is_consistent());
+    }
+
+    #[test]
+    fn branch_spending_a_spent_output_is_rolled_back() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let genesis = blockchain.get_block(blockchain.get_tip_hash()).unwrap();
+        let (tx_a, tx_b1, tx_b2) = (
+            pay(&blockchain, &miner, &Wallet::new(), 3),
+            pay(&blockchain, &miner, &Wallet::new(), 4),
+            pay(&blockchain, &miner, &Wallet::new(), 5),
+        );
+        let a1 = mine(&blockchain, vec![tx_a.clone()], &miner);
+        let b1 = block_on(&blockchain, &genesis, vec![tx_b1, coinbase(&miner, 1)]);
+        blockchain.add_block(&b1).unwrap();
+
+        // tx_b2 spends the genesis coinbase again, which only shows once b1 is connected.
+        let b2 = block_on(&blockchain, &b1, vec![tx_b2.clone(), coinbase(&miner, 1)]);
+        assert_eq!(
+            blockchain.add_block(&b2),
+            Err(BlockError::InvalidTransaction(tx_b2.get_id()))
+        );
+        assert_eq!(blockchain.get_tip_hash(), a1.get_hash());
+        let utxo_set = UTXOSet::new(blockchain);
+        assert_eq!(utxo_set.get_best_block(), Some(a1.get_hash()));
+        assert!(utxo_set.has_outputs(tx_a.get_id()));
+        assert!(utxo_set.verify(|_| {}).is_consistent());
+    }
+
+    #[test]
+    fn bulk_adding_stops_at_the_first_invalid_block() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let tx = with_first_value(&pay(&blockchain, &miner, &Wallet::new(), 3), 2);
+        let b1 = block_on_tip(&blockchain, vec![coinbase(&miner, 0)]);
+        let b2 = block_on(&blockchain, &b1, vec![tx.clone(), coinbase(&miner, 1)]);
+        let b3 = block_on(&blockchain, &b2, vec![coinbase(&miner, 0)]);
+        let blocks = [b1.clone(), b2.clone(), b3.clone()];
+        let (rejected, e) = blockchain
+            .add_blocks(&blocks, BlockChecks::Full)
+            .unwrap_err();
+        assert_eq!(rejected.get_hash(), b2.get_hash());
+        assert_eq!(e, BlockError::MismatchedId(tx.get_id()));
+        assert_eq!(blockchain.get_tip_hash(), b1.get_hash());
+        assert!(blockchain.get_block(b3.get_hash()).is_none());
+        let utxo_set = UTXOSet::new(blockchain);
+        assert_eq!(utxo_set.get_best_block(), Some(b1.get_hash()));
+    }
+
+    /// A [Clock] whose first reading waits until the test has passed its gate twice.
+    struct GatedClock {
+        read: std::sync::atomic::AtomicBool,
+        gate: std::sync::Barrier,
+    }
+
+    impl Clock for GatedClock {
+        fn now(&self) -> i64 {
+            if !self.read.swap(true, std::sync::atomic::Ordering::SeqCst) {
+                self.gate.wait();
+                self.gate.wait();
+            }
+            system_clock().now()
+        }
+    }
+
+    #[test]
+    fn tip_moves_on_while_a_block_is_mined_which_is_then_mined_again() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let clock = Arc::new(GatedClock {
+            read: std::sync::atomic::AtomicBool::new(false),
+            gate: std::sync::Barrier::new(2),
+        });
+        let slow = blockchain.clone().with_clock(clock.clone());
+        let transactions = vec![coinbase(&miner, 0)];
+        let mining = std::thread::spawn(move || slow.mine_verified_block(transactions));
+
+        clock.gate.wait();
+        let faster = mine(&blockchain, vec![], &miner);
+        clock.gate.wait();
+        let block = mining.join().unwrap().unwrap();
+        assert_eq!(block.get_pre_block_hash(), faster.get_hash());
+        assert_eq!(block.get_height(), 2);
+        assert_eq!(blockchain.get_tip_hash(), block.get_hash());
+    }
+
+    #[test]
+    fn mining_an_invalid_block_fails_and_keeps_the_tip() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let tip_hash = blockchain.get_tip_hash();
+        let first = pay(&blockchain, &miner, &Wallet::new(), 3);
+        let second = pay(&blockchain, &miner, &Wallet::new(), 4);
+        let double_spend = vec![first, second.clone(), coinbase(&miner, 2)];
+        assert_eq!(
+            blockchain.mine_block(double_spend).unwrap_err(),
+            BlockError::DoubleSpend(second.get_id())
+        );
+        assert_eq!(
+            blockchain
+                .mine_block(vec![coinbase(&miner, 5)])
+                .unwrap_err(),
+            BlockError::ExcessiveReward {
+                reward: 15,
+                allowed: 10
+            }
+        );
+        assert_eq!(blockchain.get_tip_hash(), tip_hash);
+    }
+
+    #[test]
+    fn mined_blocks_are_stamped_by_the_chain_clock() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let genesis_time = blockchain.get_stats().tip_timestamp;
+        let clock = Arc::new(crate::clock::MockClock::new(genesis_time + 1_000));
+        let blockchain = blockchain.with_clock(clock.clone());
+        let first = mine(&blockchain, vec![], &miner);
+        assert_eq!(first.get_timestamp(), genesis_time + 1_000);
+
+        clock.advance(5 * 3_600_000);
+        let later = mine(&blockchain, vec![], &miner);
+        assert_eq!(later.get_timestamp() - first.get_timestamp(), 5 * 3_600_000);
+        assert_eq!(blockchain.get_stats().tip_timestamp, later.get_timestamp());
+    }
+
+    #[test]
+    fn forward_iteration_walks_the_best_chain_up_from_a_height() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let genesis = blockchain.get_block(blockchain.get_tip_hash()).unwrap();
+        let chain: Vec<Block> = (0..4).map(|_| mine(&blockchain, vec![], &miner)).collect();
+        let side = block_on(&blockchain, &genesis, vec![coinbase(&miner, 0)]);
+        blockchain.add_block(&side).unwrap();
+
+        let mut iterator = blockchain.forward_iterator(2);
+        let walked: Vec<BlockHash> = iter::from_fn(|| iterator.next())
+            .map(|block| block.get_hash())
+            .collect();
+        let expected: Vec<BlockHash> = chain[1..].iter().map(Block::get_hash).collect();
+        assert_eq!(walked, expected);
+
+        let heights: Vec<usize> = blockchain
+            .export_range(1..=2)
+            .iter()
+            .map(|summary| summary.height)
+            .collect();
+        assert_eq!(heights, vec![1, 2]);
+        assert!(blockchain.forward_iterator(5).next().is_none());
+    }
+
+    #[test]
+    fn mining_with_a_misplaced_coinbase_fails() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let tip_hash = blockchain.get_tip_hash();
+        let tx = pay(&blockchain, &miner, &Wallet::new(), 3);
+        for transactions in [
+            vec![tx.clone()],
+            vec![coinbase(&miner, 1), tx],
+            vec![coinbase(&miner, 0), coinbase(&miner, 0)],
+        ] {
+            assert_eq!(
+                blockchain.mine_block(transactions).unwrap_err(),
+                BlockError::MisplacedCoinbase
+            );
+        }
+        assert_eq!(blockchain.get_tip_hash(), tip_hash);
+    }
+
+    #[test]
+    fn verifying_a_spend_of_an_unknown_output_fails() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let elsewhere = temp_chain(&miner);
+        let tx = pay(&elsewhere, &miner, &Wallet::new(), 3);
+        let input = &tx.get_vin()[0];
+        let outpoint = OutPoint {
+            txid: input.get_txid(),
+            vout: input.get_vout(),
+        };
+        assert_eq!(
+            tx.verify(&blockchain),
+            Err(TransactionError::UnknownOutput(outpoint))
+        );
+        assert_eq!(tx.verify(&elsewhere), Ok(true));
+    }
+
+    /// Rewrites the first byte of the signature of the first input of `tx`, keeping
+    /// its id.
+    fn with_forged_signature(tx: &Transaction) -> Transaction {
+        let mut json = serde_json::to_value(tx).unwrap();
+        let byte = json["vin"][0]["signature"][0].as_u64().unwrap();
+        json["vin"][0]["signature"][0] = (byte ^ 1).into();
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn block_with_a_changed_nonce_is_rejected() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let block = block_on_tip(&blockchain, vec![coinbase(&miner, 0)]);
+        let mut json = serde_json::to_value(&block).unwrap();
+        json["nonce"] = (block.get_nonce() + 1).into();
+        let tampered: Block = serde_json::from_value(json).unwrap();
+        assert_eq!(
+            blockchain.validate_block(&tampered),
+            Err(BlockError::InvalidProofOfWork)
+        );
+    }
+
+    #[test]
+    fn block_on_an_unknown_parent_is_rejected() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let genesis = blockchain.get_block(blockchain.get_tip_hash()).unwrap();
+        let unknown = BlockHash::from_slice(&[7; 32]).unwrap();
+        let block = Block::new(
+            unknown,
+            vec![coinbase(&miner, 0)],
+            1,
+            blockchain.get_next_target_bits(&genesis),
+            &*system_clock(),
+        );
+        assert_eq!(
+            blockchain.validate_block(&block),
+            Err(BlockError::UnknownParent(unknown))
+        );
+    }
+
+    #[test]
+    fn block_at_the_wrong_height_is_rejected() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let genesis = blockchain.get_block(blockchain.get_tip_hash()).unwrap();
+        let block = Block::new(
+            genesis.get_hash(),
+            vec![coinbase(&miner, 0)],
+            2,
+            blockchain.get_next_target_bits(&genesis),
+            &*system_clock(),
+        );
+        assert_eq!(
+            blockchain.validate_block(&block),
+            Err(BlockError::WrongHeight {
+                height: 2,
+                expected: 1
+            })
+        );
+    }
+
+    #[test]
+    fn block_at_the_wrong_difficulty_is_rejected() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let genesis = blockchain.get_block(blockchain.get_tip_hash()).unwrap();
+        let expected = blockchain.get_next_target_bits(&genesis);
+        let block = Block::new(
+            genesis.get_hash(),
+            vec![coinbase(&miner, 0)],
+            1,
+            expected + 1,
+            &*system_clock(),
+        );
+        assert_eq!(
+            blockchain.validate_block(&block),
+            Err(BlockError::WrongDifficulty {
+                target_bits: expected + 1,
+                expected
+            })
+        );
+    }
+
+    #[test]
+    fn transaction_twice_in_a_block_is_rejected() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let tx = pay(&blockchain, &miner, &Wallet::new(), 3);
+        let block = block_on_tip(
+            &blockchain,
+            vec![tx.clone(), tx.clone(), coinbase(&miner, 1)],
+        );
+        assert_eq!(
+            blockchain.validate_block(&block),
+            Err(BlockError::DuplicateTransaction(tx.get_id()))
+        );
+    }
+
+    #[test]
+    fn spending_an_output_twice_in_a_block_is_rejected() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let first = pay(&blockchain, &miner, &Wallet::new(), 3);
+        let second = pay(&blockchain, &miner, &Wallet::new(), 4);
+        let block = block_on_tip(
+            &blockchain,
+            vec![first, second.clone(), coinbase(&miner, 2)],
+        );
+        assert_eq!(
+            blockchain.validate_block(&block),
+            Err(BlockError::DoubleSpend(second.get_id()))
+        );
+    }
+
+    #[test]
+    fn forged_signature_is_rejected() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let forged = with_forged_signature(&pay(&blockchain, &miner, &Wallet::new(), 3));
+        assert!(forged.has_valid_id());
+        let block = block_on_tip(&blockchain, vec![forged.clone(), coinbase(&miner, 1)]);
+        assert_eq!(
+            blockchain.validate_block(&block),
+            Err(BlockError::InvalidTransaction(forged.get_id()))
+        );
+        assert_eq!(
+            blockchain.check_block(&block, BlockChecks::SignaturesVerified),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn coinbase_paying_more_than_allowed_is_rejected() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let tx = pay(&blockchain, &miner, &Wallet::new(), 3);
+        let block = block_on_tip(&blockchain, vec![tx, coinbase(&miner, 2)]);
+        assert_eq!(
+            blockchain.validate_block(&block),
+            Err(BlockError::ExcessiveReward {
+                reward: 12,
+                allowed: 11
+            })
+        );
+    }
+
+    #[test]
+    fn block_over_a_size_limit_is_rejected() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let tx = pay(&blockchain, &miner, &Wallet::new(), 3);
+        let block = block_on_tip(&blockchain, vec![tx.clone(), coinbase(&miner, 1)]);
+        let limits = blockchain.get_limits();
+        let (block_size, tx_size) = (block.serialize().len(), tx.serialize().len());
+
+        let few_txs = blockchain.clone().with_limits(SizeLimits {
+            max_block_txs: 1,
+            ..limits
+        });
+        assert_eq!(
+            few_txs.validate_block(&block),
+            Err(BlockError::TooManyTransactions { count: 2, max: 1 })
+        );
+        let small_blocks = blockchain.clone().with_limits(SizeLimits {
+            max_block_bytes: block_size - 1,
+            ..limits
+        });
+        assert_eq!(
+            small_blocks.validate_block(&block),
+            Err(BlockError::TooLarge {
+                size: block_size,
+                max: block_size - 1
+            })
+        );
+        let small_txs = blockchain.clone().with_limits(SizeLimits {
+            max_tx_bytes: tx_size - 1,
+            ..limits
+        });
+        assert_eq!(
+            small_txs.validate_block(&block),
+            Err(BlockError::TransactionTooLarge {
+                txid: tx.get_id(),
+                size: tx_size,
+                max: tx_size - 1
+            })
+        );
+        assert_eq!(blockchain.validate_block(&block), Ok(()));
+    }
+
+    #[test]
+    fn unchecked_block_spending_an_unknown_output_is_unspendable() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let tip_hash = blockchain.get_tip_hash();
+        let elsewhere = temp_chain(&miner);
+        let tx = pay(&elsewhere, &miner, &Wallet::new(), 3);
+        let input = &tx.get_vin()[0];
+        let block = block_on_tip(&blockchain, vec![tx.clone(), coinbase(&miner, 1)]);
+        assert_eq!(
+            blockchain.add_block_with(&block, BlockChecks::None),
+            Err(BlockError::Unspendable(SpendError::MissingOutput {
+                txid: input.get_txid(),
+                vout: input.get_vout()
+            }))
+        );
+        assert_eq!(blockchain.get_tip_hash(), tip_hash);
+    }
+
+    #[test]
+    fn reopened_chain_reads_back_its_blocks_by_their_raw_hashes() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let genesis = blockchain.get_block(blockchain.get_tip_hash()).unwrap();
+        let chain: Vec<Block> = (0..2).map(|_| mine(&blockchain, vec![], &miner)).collect();
+        let blocks_tree = blockchain.blocks_tree();
+        let tip_bytes = blocks_tree.get(TIP_BLOCK_HASH_KEY).unwrap().unwrap();
+        assert_eq!(tip_bytes.as_ref(), chain[1].get_hash().as_bytes());
+        assert_eq!(
+            blocks_tree
+                .get(BLOCKS_FORMAT_KEY)
+                .unwrap()
+                .unwrap()
+                .as_ref(),
+            [BLOCKS_FORMAT]
+        );
+
+        let db = Arc::new(blockchain.get_db().clone());
+        let reopened = Blockchain::open_or_init_in(db, &genesis).unwrap();
+        assert_eq!(reopened.get_tip_hash(), chain[1].get_hash());
+        for block in chain.iter().chain([&genesis]) {
+            let read = reopened.get_block(block.get_hash()).unwrap();
+            assert_eq!(read.serialize(), block.serialize());
+        }
+    }
+
+    #[test]
+    fn chain_stored_in_an_older_format_is_refused() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let genesis = blockchain.get_block(blockchain.get_tip_hash()).unwrap();
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let blocks_tree = db.open_tree(BLOCKS_TREE).unwrap();
+        let hex_hash = genesis.get_hash().to_string();
+        blocks_tree
+            .insert(hex_hash.as_bytes(), genesis.serialize())
+            .unwrap();
+        blocks_tree
+            .insert(TIP_BLOCK_HASH_KEY, hex_hash.as_bytes())
+            .unwrap();
+        assert!(matches!(
+            Blockchain::open_or_init_in(Arc::new(db), &genesis),
+            Err(BlockchainError::Migration(_))
+        ));
+    }
+
+    #[test]
+    fn disconnected_transactions_are_no_longer_found() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let tx = pay(&blockchain, &miner, &Wallet::new(), 3);
+        let block = mine(&blockchain, vec![tx.clone()], &miner);
+        let found = blockchain.find_transaction(tx.get_id()).unwrap();
+        assert_eq!(found.get_id(), tx.get_id());
+        let by_height = blockchain.get_block_by_height(1).unwrap();
+        assert_eq!(by_height.get_hash(), block.get_hash());
+
+        let disconnected = blockchain.disconnect_tip().unwrap().unwrap();
+        assert_eq!(disconnected.get_hash(), block.get_hash());
+        assert!(blockchain.find_transaction(tx.get_id()).is_none());
+        assert!(blockchain.get_block_by_height(1).is_none());
+    }
+
+    #[test]
+    fn lookups_follow_a_reorganization() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let genesis = blockchain.get_block(blockchain.get_tip_hash()).unwrap();
+        let (tx_a, tx_b) = (
+            pay(&blockchain, &miner, &Wallet::new(), 3),
+            pay(&blockchain, &miner, &Wallet::new(), 4),
+        );
+        let a1 = mine(&blockchain, vec![tx_a.clone()], &miner);
+        assert!(blockchain.find_transaction(tx_a.get_id()).is_some());
+
+        let b1 = block_on(
+            &blockchain,
+            &genesis,
+            vec![tx_b.clone(), coinbase(&miner, 1)],
+        );
+        blockchain.add_block(&b1).unwrap();
+        assert_eq!(
+            blockchain.get_block_by_height(1).unwrap().get_hash(),
+            a1.get_hash()
+        );
+        assert!(blockchain.find_transaction(tx_b.get_id()).is_none());
+
+        let b2 = block_on(&blockchain, &b1, vec![coinbase(&miner, 0)]);
+        blockchain.add_block(&b2).unwrap();
+        assert!(blockchain.find_transaction(tx_a.get_id()).is_none());
+        assert!(blockchain.find_transaction(tx_b.get_id()).is_some());
+        for (height, block) in [(1, &b1), (2, &b2)] {
+            let by_height = blockchain.get_block_by_height(height).unwrap();
+            assert_eq!(by_height.get_hash(), block.get_hash());
+        }
+    }
+
+    #[test]
+    fn finding_a_transaction_does_not_slow_down_as_the_chain_grows() {
+        let short = temp_chain(&Wallet::new());
+        let miner = Wallet::new();
+        let long = temp_chain(&miner);
+        for _ in 0..300 {
+            mine(&long, vec![], &miner);
+        }
+        // The Coinbase of the genesis block, which a walk from the tip would reach last.
+        let first_txid = |blockchain: &Blockchain| {
+            blockchain
+                .get_block_by_height(0)
+                .unwrap()
+                .get_transactions()[0]
+                .get_id()
+        };
+        let time = |blockchain: &Blockchain| {
+            let txid = first_txid(blockchain);
+            let started = Instant::now();
+            for _ in 0..50 {
+                assert!(blockchain.find_transaction(txid).is_some());
+            }
+            started.elapsed()
+        };
+        // The quickest of many interleaved rounds, so that a busy machine slows both alike.
+        let (mut on_short, mut on_long) = (Duration::MAX, Duration::MAX);
+        for _ in 0..20 {
+            on_short = on_short.min(time(&short));
+            on_long = on_long.min(time(&long));
+        }
+        assert!(
+            on_long < on_short * 3,
+            "{on_long:?} on 301 blocks against {on_short:?} on one"
+        );
+    }
+
+    /// The history of `wallet`'s address as (direction, amount, coinbase), newest first.
+    fn history_of(blockchain: &Blockchain, wallet: &Wallet) -> Vec<(Direction, u64, bool)> {
+        blockchain
+            .get_address_history(&hash_pub_key(wallet.get_public_key()))
+            .into_iter()
+            .map(|record| (record.direction, record.amount, record.coinbase))
+            .collect()
+    }
+
+    #[test]
+    fn address_history_nets_what_each_transaction_moved() {
+        let miner = Wallet::new();
+        let alice = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        assert_eq!(history_of(&blockchain, &alice), vec![]);
+        assert_eq!(
+            history_of(&blockchain, &miner),
+            vec![(Direction::Received, 10, true)]
+        );
+
+        mine(
+            &blockchain,
+            vec![pay(&blockchain, &miner, &alice, 3)],
+            &miner,
+        );
+        assert_eq!(
+            history_of(&blockchain, &alice),
+            vec![(Direction::Received, 3, false)]
+        );
+        let history = history_of(&blockchain, &miner);
+        assert_eq!(history.len(), 3);
+        assert!(history.contains(&(Direction::Sent, 4, false)));
+        assert_eq!(history[2], (Direction::Received, 10, true));
+
+        mine(
+            &blockchain,
+            vec![pay(&blockchain, &alice, &alice, 1)],
+            &miner,
+        );
+        assert_eq!(
+            history_of(&blockchain, &alice),
+            vec![(Direction::Sent, 1, false), (Direction::Received, 3, false)]
+        );
+    }
+
+    #[test]
+    fn address_history_follows_a_reorganization() {
+        let miner = Wallet::new();
+        let (alice, bob) = (Wallet::new(), Wallet::new());
+        let blockchain = temp_chain(&miner);
+        let genesis = blockchain.get_block(blockchain.get_tip_hash()).unwrap();
+        let (to_alice, to_bob) = (
+            pay(&blockchain, &miner, &alice, 3),
+            pay(&blockchain, &miner, &bob, 4),
+        );
+        mine(&blockchain, vec![to_alice], &miner);
+        assert_eq!(history_of(&blockchain, &alice).len(), 1);
+
+        let b1 = block_on(&blockchain, &genesis, vec![to_bob, coinbase(&miner, 1)]);
+        blockchain.add_block(&b1).unwrap();
+        let b2 = block_on(&blockchain, &b1, vec![coinbase(&miner, 0)]);
+        blockchain.add_block(&b2).unwrap();
+        assert_eq!(history_of(&blockchain, &alice), vec![]);
+        assert_eq!(
+            history_of(&blockchain, &bob),
+            vec![(Direction::Received, 4, false)]
+        );
+    }
+
+    #[test]
+    fn chain_with_a_corrupt_tip_is_refused() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let genesis = blockchain.get_block(blockchain.get_tip_hash()).unwrap();
+        blockchain
+            .blocks_tree()
+            .insert(TIP_BLOCK_HASH_KEY, &b"not a hash"[..])
+            .unwrap();
+        let db = Arc::new(blockchain.get_db().clone());
+        assert!(matches!(
+            Blockchain::open_or_init_in(db, &genesis),
+            Err(BlockchainError::CorruptTip)
+        ));
+    }
+
+    #[test]
+    fn reading_blocks_from_the_kept_tree_is_cheaper_than_reopening_it() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        for _ in 0..200 {
+            mine(&blockchain, vec![], &miner);
+        }
+        let hashes = blockchain.get_block_hashes();
+        let time = |read: &dyn Fn(&BlockHash) -> Option<sled::IVec>| {
+            let started = Instant::now();
+            assert!(hashes.iter().all(|hash| read(hash).is_some()));
+            started.elapsed()
+        };
+        let kept_read = |hash: &BlockHash| blockchain.blocks_tree().get(hash.as_bytes()).unwrap();
+        // How each step of the walk read its block before the handle was kept.
+        let reopened_read = |hash: &BlockHash| {
+            let blocks_tree = blockchain.get_db().open_tree(BLOCKS_TREE).unwrap();
+            blocks_tree.get(hash.as_bytes()).unwrap()
+        };
+        // The quickest of many interleaved walks, so that a busy machine slows both alike.
+        let (mut kept, mut reopened) = (Duration::MAX, Duration::MAX);
+        for _ in 0..50 {
+            kept = kept.min(time(&kept_read));
+            reopened = reopened.min(time(&reopened_read));
+        }
+        assert!(
+            kept < reopened,
+            "{kept:?} with the kept tree against {reopened:?} reopening it"
+        );
+    }
+}
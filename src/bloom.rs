@@ -0,0 +1,305 @@
+use std::fmt::{self, Display, Formatter};
+use std::{collections::HashMap, f64::consts::LN_2, net::SocketAddr, sync::RwLock};
+
+use crate::{block::Block, transactions::Transaction, types::Txid, wallet::hash_pub_key};
+
+/// The largest filter a peer may set, enough for 20,000 elements at 0.1% false positives.
+pub const MAX_FILTER_BYTES: usize = 36_000;
+pub const MAX_HASH_FUNCS: u32 = 50;
+/// A filter matching more [Transaction]s than this in a single [Block] matches far
+/// too much to be worth filtering with and is dropped.
+pub const MAX_MATCHES_PER_BLOCK: usize = 1000;
+/// Spreads the seeds of the hash functions apart, as in BIP 37.
+const SEED_MULTIPLIER: u32 = 0xFBA4_C795;
+
+/// The reasons a filter sent by a peer is refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BloomFilterError {
+    Empty,
+    TooLarge(usize),
+    TooManyHashFuncs(u32),
+}
+
+impl Display for BloomFilterError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Empty => write!(f, "the filter is empty"),
+            Self::TooLarge(size) => write!(
+                f,
+                "the filter is {size} bytes, more than the {MAX_FILTER_BYTES} allowed"
+            ),
+            Self::TooManyHashFuncs(count) => write!(
+                f,
+                "the filter uses {count} hash functions, more than the {MAX_HASH_FUNCS} allowed"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BloomFilterError {}
+
+/// A probabilistic set of public key hashes and outpoints a light client is
+/// interested in. May report elements it never saw, but never misses one it did.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    hash_funcs: u32,
+    tweak: u32,
+}
+
+impl BloomFilter {
+    /// Sizes a filter to hold `elements` with a false positive rate of about
+    /// `fp_rate`, within [`MAX_FILTER_BYTES`] and [`MAX_HASH_FUNCS`].
+    #[allow(
+        clippy::cast_possible_truncation,
+        clippy::cast_sign_loss,
+        clippy::cast_precision_loss
+    )]
+    pub fn new(elements: usize, fp_rate: f64, tweak: u32) -> Self {
+        let elements = elements.max(1) as f64;
+        let fp_rate = fp_rate.clamp(f64::MIN_POSITIVE, 1.0);
+        let bits = -elements * fp_rate.ln() / (LN_2 * LN_2);
+        let bytes = ((bits / 8.0).ceil() as usize).clamp(1, MAX_FILTER_BYTES);
+        let hash_funcs = ((bytes * 8) as f64 / elements * LN_2).round() as u32;
+        Self {
+            bits: vec![0; bytes],
+            hash_funcs: hash_funcs.clamp(1, MAX_HASH_FUNCS),
+            tweak,
+        }
+    }
+
+    /// Rebuilds a filter received from a peer, refusing one that exceeds the limits.
+    pub fn from_parts(
+        bits: Vec<u8>,
+        hash_funcs: u32,
+        tweak: u32,
+    ) -> Result<Self, BloomFilterError> {
+        if bits.is_empty() || hash_funcs == 0 {
+            return Err(BloomFilterError::Empty);
+        }
+        if bits.len() > MAX_FILTER_BYTES {
+            return Err(BloomFilterError::TooLarge(bits.len()));
+        }
+        if hash_funcs > MAX_HASH_FUNCS {
+            return Err(BloomFilterError::TooManyHashFuncs(hash_funcs));
+        }
+        Ok(Self {
+            bits,
+            hash_funcs,
+            tweak,
+        })
+    }
+
+    pub const fn get_bits(&self) -> &[u8] {
+        self.bits.as_slice()
+    }
+
+    pub const fn get_hash_funcs(&self) -> u32 {
+        self.hash_funcs
+    }
+
+    pub const fn get_tweak(&self) -> u32 {
+        self.tweak
+    }
+
+    pub fn insert(&mut self, data: &[u8]) {
+        for idx in 0..self.hash_funcs {
+            let bit = self.bit_index(idx, data);
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    pub fn contains(&self, data: &[u8]) -> bool {
+        (0..self.hash_funcs).all(|idx| {
+            let bit = self.bit_index(idx, data);
+            self.bits[bit / 8] & (1 << (bit % 8)) != 0
+        })
+    }
+
+    /// Checks whether `tx` pays to or spends from an element of the filter.
+    ///
+    /// The outpoints of matching outputs are added to the filter, so that the
+    /// [Transaction] spending them later matches too.
+    pub fn matches_tx(&mut self, tx: &Transaction) -> bool {
+        let mut matched = self.contains(tx.get_id().as_bytes());
+        for (vout, out) in tx.get_vout().iter().enumerate() {
+            if self.contains(out.get_pub_key_hash()) {
+                matched = true;
+                self.insert(outpoint(tx.get_id(), vout).as_slice());
+            }
+        }
+        if matched || tx.is_coinbase() {
+            return matched;
+        }
+        tx.get_vin().iter().any(|input| {
+            self.contains(outpoint(input.get_txid(), input.get_vout()).as_slice())
+                || self.contains(hash_pub_key(input.get_pub_key()).as_slice())
+        })
+    }
+
+    fn bit_index(&self, idx: u32, data: &[u8]) -> usize {
+        let seed = idx.wrapping_mul(SEED_MULTIPLIER).wrapping_add(self.tweak);
+        let hash = usize::try_from(murmur3_32(seed, data)).unwrap();
+        hash % (self.bits.len() * 8)
+    }
+}
+
+/// Serializes the output `vout` of the [Transaction] `txid` as a filter element.
+pub fn outpoint(txid: Txid, vout: usize) -> Vec<u8> {
+    let mut bytes = txid.to_vec();
+    bytes.extend(u64::try_from(vout).unwrap().to_le_bytes());
+    bytes
+}
+
+/// The 32-bit `MurmurHash3`, the hash BIP 37 filters use.
+fn murmur3_32(seed: u32, data: &[u8]) -> u32 {
+    const C1: u32 = 0xcc9e_2d51;
+    const C2: u32 = 0x1b87_3593;
+    let mut hash = seed;
+    let chunks = data.chunks_exact(4);
+    let tail = chunks.remainder();
+    for chunk in chunks {
+        let mut k = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+        k = k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+        hash ^= k;
+        hash = hash
+            .rotate_left(13)
+            .wrapping_mul(5)
+            .wrapping_add(0xe654_6b64);
+    }
+    if !tail.is_empty() {
+        let mut k = 0;
+        for (shift, byte) in tail.iter().enumerate() {
+            k |= u32::from(*byte) << (8 * shift);
+        }
+        hash ^= k.wrapping_mul(C1).rotate_left(15).wrapping_mul(C2);
+    }
+    hash ^= u32::try_from(data.len()).unwrap_or(u32::MAX);
+    hash ^= hash >> 16;
+    hash = hash.wrapping_mul(0x85eb_ca6b);
+    hash ^= hash >> 13;
+    hash = hash.wrapping_mul(0xc2b2_ae35);
+    hash ^ (hash >> 16)
+}
+
+/// The [`BloomFilter`]s set by peers, keyed by the remote address of the connection
+/// each one was set on, which only lasts as long as the connection.
+#[derive(Default)]
+pub struct PeerFilters(RwLock<HashMap<SocketAddr, BloomFilter>>);
+
+impl PeerFilters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, conn: SocketAddr, filter: BloomFilter) {
+        self.0.write().unwrap().insert(conn, filter);
+    }
+
+    pub fn clear(&self, conn: SocketAddr) {
+        self.0.write().unwrap().remove(&conn);
+    }
+
+    /// Returns the [Transaction]s of `block` matching the filter set on the connection
+    /// from `conn`, with their positions in the [Block], or `None` when it has no
+    /// filter or its filter matches more than [`MAX_MATCHES_PER_BLOCK`], in which
+    /// case the filter is dropped.
+    pub fn filter_block<'a>(
+        &self,
+        conn: SocketAddr,
+        block: &'a Block,
+    ) -> Option<Vec<(usize, &'a Transaction)>> {
+        let mut inner = self.0.write().unwrap();
+        let filter = inner.get_mut(&conn)?;
+        let matched: Vec<(usize, &Transaction)> = block
+            .get_transactions()
+            .iter()
+            .enumerate()
+            .filter(|(_, tx)| filter.matches_tx(tx))
+            .collect();
+        let too_many = matched.len() > MAX_MATCHES_PER_BLOCK;
+        if too_many {
+            inner.remove(&conn);
+        }
+        drop(inner);
+        (!too_many).then_some(matched)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::system_clock;
+    use crate::test_utils::{coinbase, regtest};
+    use crate::types::BlockHash;
+    use crate::wallet::Wallet;
+
+    /// A filter sized for a handful of elements watching `wallet`.
+    fn watching(wallet: &Wallet) -> BloomFilter {
+        let mut filter = BloomFilter::new(10, 0.01, 7);
+        filter.insert(hash_pub_key(wallet.get_public_key()).as_slice());
+        filter
+    }
+
+    #[test]
+    fn filter_matches_payments_to_its_address_and_tracks_their_outputs() {
+        regtest();
+        let watched = Wallet::new();
+        let mut filter = watching(&watched);
+        let tx = coinbase(&watched, 0);
+        assert!(filter.matches_tx(&tx));
+        assert!(filter.contains(outpoint(tx.get_id(), 0).as_slice()));
+    }
+
+    #[test]
+    fn filter_rarely_matches_unrelated_transactions() {
+        regtest();
+        let mut filter = watching(&Wallet::new());
+        let false_positives = (0..200)
+            .filter(|_| filter.matches_tx(&coinbase(&Wallet::new(), 0)))
+            .count();
+        // About 2 are expected at a 1% false positive rate.
+        assert!(false_positives < 20, "{false_positives} false positives");
+    }
+
+    #[test]
+    fn filter_from_a_peer_is_limited() {
+        assert_eq!(
+            BloomFilter::from_parts(vec![], 1, 0),
+            Err(BloomFilterError::Empty)
+        );
+        assert_eq!(
+            BloomFilter::from_parts(vec![0; MAX_FILTER_BYTES + 1], 1, 0),
+            Err(BloomFilterError::TooLarge(MAX_FILTER_BYTES + 1))
+        );
+        assert_eq!(
+            BloomFilter::from_parts(vec![0; 8], MAX_HASH_FUNCS + 1, 0),
+            Err(BloomFilterError::TooManyHashFuncs(MAX_HASH_FUNCS + 1))
+        );
+    }
+
+    #[test]
+    fn filter_only_applies_to_the_connection_it_was_set_on() {
+        regtest();
+        let watched = Wallet::new();
+        let tx = coinbase(&watched, 0);
+        let block = Block::new(
+            BlockHash::default(),
+            vec![tx.clone()],
+            1,
+            1,
+            &*system_clock(),
+        );
+        let filters = PeerFilters::new();
+        let conn: SocketAddr = "127.0.0.1:50000".parse().unwrap();
+        let other: SocketAddr = "127.0.0.1:50001".parse().unwrap();
+        filters.set(conn, watching(&watched));
+        assert!(filters.filter_block(other, &block).is_none());
+        let matched = filters.filter_block(conn, &block).unwrap();
+        assert_eq!(matched.len(), 1);
+        assert_eq!(matched[0].0, 0);
+        assert_eq!(matched[0].1.get_id(), tx.get_id());
+        filters.clear(conn);
+        assert!(filters.filter_block(conn, &block).is_none());
+    }
+}
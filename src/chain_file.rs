@@ -0,0 +1,777 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::{fs::File, path::Path, str::FromStr, thread};
+
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::{BlockChecks, Blockchain, BlockchainError};
+use crate::config::{Network, GLOBAL_CONFIG};
+use crate::transactions::{TXOutput, Transaction};
+use crate::types::Txid;
+use crate::{block::Block, utxo_set::UTXOSet};
+
+/// Identifies a chain file, followed by [`FORMAT_VERSION`] and the network name.
+const MAGIC: &[u8] = b"HIMALIA";
+const FORMAT_VERSION: u8 = 3;
+/// The number of [Block]s between two progress callbacks.
+pub const PROGRESS_INTERVAL: usize = 1000;
+/// The number of [Block]s [`import_chain`] adds between two database flushes.
+pub const IMPORT_BATCH: usize = 500;
+/// Holds how far an interrupted [`import_chain`] got.
+const IMPORT_TREE: &str = "import";
+const IMPORT_PROGRESS_KEY: &str = "progress";
+
+/// The reasons a chain file can't be written or read.
+#[derive(Debug)]
+pub enum ChainFileError {
+    Io(io::Error),
+    /// The file doesn't start with a chain file header.
+    InvalidHeader,
+    WrongNetwork {
+        expected: Network,
+        found: String,
+    },
+    /// The file ends part way through a [Block], after `blocks` complete ones.
+    Truncated {
+        blocks: usize,
+        bytes: u64,
+    },
+    /// A record can't be decoded into a [Block], after `blocks` complete ones.
+    Corrupt {
+        blocks: usize,
+        bytes: u64,
+    },
+    InvalidBlock {
+        height: usize,
+        hash: String,
+        reason: String,
+    },
+    Blockchain(BlockchainError),
+    Database(sled::Error),
+}
+
+impl Display for ChainFileError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::InvalidHeader => write!(f, "the file is not a himalia chain file"),
+            Self::WrongNetwork { expected, found } => write!(
+                f,
+                "the file holds a {found} network chain but this is the {expected} network"
+            ),
+            Self::Truncated { blocks, bytes } => write!(
+                f,
+                "the file is truncated after {blocks} blocks ({bytes} bytes)"
+            ),
+            Self::Corrupt { blocks, bytes } => write!(
+                f,
+                "the file is corrupt after {blocks} blocks ({bytes} bytes)"
+            ),
+            Self::InvalidBlock {
+                height,
+                hash,
+                reason,
+            } => write!(f, "block {hash} at height {height} is not valid: {reason}"),
+            Self::Blockchain(e) => write!(f, "{e}"),
+            Self::Database(e) => write!(f, "unable to record the import progress: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for ChainFileError {}
+
+impl From<io::Error> for ChainFileError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<sled::Error> for ChainFileError {
+    fn from(e: sled::Error) -> Self {
+        Self::Database(e)
+    }
+}
+
+impl From<BlockchainError> for ChainFileError {
+    fn from(e: BlockchainError) -> Self {
+        Self::Blockchain(e)
+    }
+}
+
+/// How far an export or import got.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ChainFileSummary {
+    /// The [Block]s written, or connected to the [Blockchain] on import.
+    pub blocks: usize,
+    /// The [Block]s already known to the [Blockchain] on import.
+    pub skipped: usize,
+    pub bytes: u64,
+    pub tip_hash: String,
+    /// Where an interrupted import of the same file was picked up.
+    pub resumed_from: Option<u64>,
+}
+
+/// Writes every [Block] from genesis to tip to the file at `path`, one at a time.
+///
+/// `progress` is called every [`PROGRESS_INTERVAL`] blocks.
+pub fn export_chain(
+    blockchain: &Blockchain,
+    path: &Path,
+    mut progress: impl FnMut(&ChainFileSummary),
+) -> Result<ChainFileSummary, ChainFileError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    let network = GLOBAL_CONFIG.get_network();
+    let mut summary = ChainFileSummary {
+        tip_hash: blockchain.get_tip_hash().to_string(),
+        ..ChainFileSummary::default()
+    };
+    summary.bytes = write_header(&mut writer, network)?;
+    // Only the hashes are held in memory, the blocks are read back one by one.
+    let mut hashes = blockchain.get_block_hashes();
+    hashes.reverse();
+    for hash in hashes {
+        let Some(block) = blockchain.get_block(hash) else {
+            continue;
+        };
+        summary.bytes += write_block(&mut writer, &block)?;
+        summary.blocks += 1;
+        if summary.blocks.is_multiple_of(PROGRESS_INTERVAL) {
+            progress(&summary);
+        }
+    }
+    writer.flush()?;
+    Ok(summary)
+}
+
+/// Reads the file at `path` one [Block] at a time, connecting each one not already
+/// in the [Blockchain] and updating the UTXO set as it goes.
+///
+/// [Block]s are checked as thoroughly as `level` asks and added
+/// [`IMPORT_BATCH`] at a time, with the signatures of a batch verified on up to
+/// `threads` threads first. How far the file has been read is recorded after every
+/// batch, so that an interrupted import of the same file picks up where it stopped.
+/// Starts a new [Blockchain] from the file's genesis block when the data directory
+/// has none.
+pub fn import_chain(
+    path: &Path,
+    level: VerifyLevel,
+    threads: usize,
+    progress: impl FnMut(&ChainFileSummary),
+) -> Result<ChainFileSummary, ChainFileError> {
+    let blockchain = match Blockchain::open() {
+        Ok(blockchain) => Some(blockchain),
+        Err(BlockchainError::NotFound) => None,
+        Err(e) => return Err(e.into()),
+    };
+    import_chain_into(blockchain, path, level, threads, progress)
+}
+
+/// Like [`import_chain`] into `blockchain`, or a new [Blockchain] in the data
+/// directory when there is none.
+fn import_chain_into(
+    blockchain: Option<Blockchain>,
+    path: &Path,
+    level: VerifyLevel,
+    threads: usize,
+    mut progress: impl FnMut(&ChainFileSummary),
+) -> Result<ChainFileSummary, ChainFileError> {
+    let file = File::open(path)?;
+    let id = ImportId::new(path, &file)?;
+    let mut reader = BufReader::new(file);
+    let network = GLOBAL_CONFIG.get_network();
+    let mut summary = ChainFileSummary {
+        bytes: read_header(&mut reader, network)?,
+        ..ChainFileSummary::default()
+    };
+    let mut importer = blockchain.map(|blockchain| Importer::new(blockchain, level, threads));
+    if let Some(importer) = importer.as_mut() {
+        if let Some(offset) = importer.resume(&id)? {
+            reader.seek(SeekFrom::Start(offset))?;
+            summary.bytes = offset;
+            summary.resumed_from = Some(offset);
+        }
+    }
+    while let Some(block) = read_block(&mut reader, &mut summary)? {
+        match importer.as_mut() {
+            None => {
+                if level != VerifyLevel::None {
+                    verify_genesis(&block, network)?;
+                }
+                let blockchain = Blockchain::open_or_init(&block)?;
+                UTXOSet::new(blockchain.clone())
+                    .update(&block)
+                    .map_err(|e| invalid_block(&block, e.to_string()))?;
+                importer = Some(Importer::new(blockchain, level, threads));
+                summary.blocks += 1;
+            }
+            Some(importer) if importer.is_known(&block) => summary.skipped += 1,
+            Some(importer) => {
+                importer.batch.push(block);
+                summary.blocks += 1;
+                if importer.batch.len() == IMPORT_BATCH {
+                    importer.commit(&id, &summary)?;
+                }
+            }
+        }
+        if (summary.blocks + summary.skipped).is_multiple_of(PROGRESS_INTERVAL) {
+            progress(&summary);
+        }
+    }
+    let Some(mut importer) = importer else {
+        return Err(BlockchainError::NotFound.into());
+    };
+    importer.commit(&id, &summary)?;
+    importer.finish()?;
+    summary.tip_hash = importer.blockchain.get_tip_hash().to_string();
+    Ok(summary)
+}
+
+/// How thoroughly [`import_chain`] checks the [Block]s it connects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyLevel {
+    /// Trusts the file entirely.
+    None,
+    /// Checks that every [Block] has a valid proof of work and extends a known one at
+    /// the next height.
+    Linkage,
+    /// Checks every [Block] as if a peer had sent it.
+    Full,
+}
+
+impl VerifyLevel {
+    /// The [`BlockChecks`] left to [`Blockchain::add_blocks`], the signatures having
+    /// been verified for the whole batch first at [`VerifyLevel::Full`].
+    const fn block_checks(self) -> BlockChecks {
+        match self {
+            Self::None => BlockChecks::None,
+            Self::Linkage => BlockChecks::Linkage,
+            Self::Full => BlockChecks::SignaturesVerified,
+        }
+    }
+}
+
+impl Display for VerifyLevel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::None => f.write_str("none"),
+            Self::Linkage => f.write_str("linkage"),
+            Self::Full => f.write_str("full"),
+        }
+    }
+}
+
+impl FromStr for VerifyLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "linkage" => Ok(Self::Linkage),
+            "full" => Ok(Self::Full),
+            _ => Err(format!(
+                "unknown verify level `{s}`, expected full, linkage or none"
+            )),
+        }
+    }
+}
+
+/// The file an interrupted import was reading and how far it got.
+#[derive(Serialize, Deserialize)]
+struct ImportProgress {
+    id: ImportId,
+    offset: u64,
+}
+
+/// Tells chain files apart well enough to resume reading the same one.
+#[derive(Clone, Serialize, Deserialize, PartialEq, Eq)]
+struct ImportId {
+    path: String,
+    len: u64,
+}
+
+impl ImportId {
+    fn new(path: &Path, file: &File) -> io::Result<Self> {
+        Ok(Self {
+            path: path.canonicalize()?.to_string_lossy().into_owned(),
+            len: file.metadata()?.len(),
+        })
+    }
+}
+
+/// Checks and connects the [Block]s of a chain file to an existing [Blockchain].
+struct Importer {
+    blockchain: Blockchain,
+    level: VerifyLevel,
+    threads: usize,
+    /// The [Block]s read but not yet added.
+    batch: Vec<Block>,
+}
+
+impl Importer {
+    /// Brings the UTXO set up to date with the tip first if an interrupted import
+    /// left it behind, since [`Blockchain::add_block_with`] checks against it.
+    fn new(blockchain: Blockchain, level: VerifyLevel, threads: usize) -> Self {
+        let utxo_set = UTXOSet::new(blockchain.clone());
+        if utxo_set.get_best_block() != Some(blockchain.get_tip_hash()) {
+            utxo_set.reindex();
+        }
+        Self {
+            blockchain,
+            level,
+            threads: threads.max(1),
+            batch: vec![],
+        }
+    }
+
+    /// Returns the offset to resume reading the file `id` from, if an earlier import
+    /// of it was interrupted.
+    fn resume(&self, id: &ImportId) -> Result<Option<u64>, ChainFileError> {
+        let tree = self.blockchain.get_db().open_tree(IMPORT_TREE)?;
+        let Some(bytes) = tree.get(IMPORT_PROGRESS_KEY)? else {
+            return Ok(None);
+        };
+        let Ok(recorded) = bincode::deserialize::<ImportProgress>(bytes.as_ref()) else {
+            return Ok(None);
+        };
+        Ok((recorded.id == *id).then_some(recorded.offset))
+    }
+
+    fn is_known(&self, block: &Block) -> bool {
+        self.batch
+            .iter()
+            .any(|pending| pending.get_hash() == block.get_hash())
+            || self.blockchain.get_block(block.get_hash()).is_some()
+    }
+
+    /// Adds the batch up to the first invalid [Block] and records how far the file
+    /// has been read. At [`VerifyLevel::Full`] the signatures of the whole batch are
+    /// verified before any of it is added.
+    fn commit(&mut self, id: &ImportId, summary: &ChainFileSummary) -> Result<(), ChainFileError> {
+        let mut blocks = std::mem::take(&mut self.batch);
+        let mut error = None;
+        if self.level == VerifyLevel::Full {
+            let spent = self.spent_outputs(blocks.as_slice());
+            if let Some((idx, txid)) = first_invalid_signature(spent.as_slice(), self.threads) {
+                error = Some(invalid_block(
+                    &blocks[idx],
+                    format!("transaction {txid} has an invalid signature"),
+                ));
+                blocks.truncate(idx);
+            }
+        }
+        self.blockchain
+            .add_blocks(blocks.as_slice(), self.level.block_checks())
+            .map_err(|(block, e)| invalid_block(block, e.to_string()))?;
+        // An invalid block ends the import, which has to start over from it.
+        if let Some(e) = error {
+            return Err(e);
+        }
+        self.record(id, summary.bytes)
+    }
+
+    /// Pairs each non-coinbase [Transaction] of `blocks` with the index of its [Block]
+    /// and the outputs its inputs spend, looked up among those of the earlier
+    /// [Transaction]s of `blocks` and on the [Blockchain]. Those spending an output
+    /// that can't be found are left out, for [`Blockchain::add_blocks`] to refuse.
+    fn spent_outputs(&self, blocks: &[Block]) -> Vec<(usize, Transaction, Vec<TXOutput>)> {
+        let mut created: HashMap<Txid, Vec<TXOutput>> = HashMap::new();
+        let mut spent = vec![];
+        for (idx, block) in blocks.iter().enumerate() {
+            for tx in block.get_transactions() {
+                if !tx.is_coinbase() {
+                    let outputs = tx
+                        .get_vin()
+                        .iter()
+                        .map(|input| match created.get(&input.get_txid()) {
+                            Some(outputs) => outputs.get(input.get_vout()).cloned(),
+                            None => self
+                                .blockchain
+                                .find_transaction(input.get_txid())?
+                                .get_vout()
+                                .get(input.get_vout())
+                                .cloned(),
+                        })
+                        .collect::<Option<Vec<TXOutput>>>();
+                    if let Some(outputs) = outputs {
+                        spent.push((idx, tx.clone(), outputs));
+                    }
+                }
+                created.insert(tx.get_id(), tx.get_vout().to_vec());
+            }
+        }
+        spent
+    }
+
+    /// Records that the file `id` has been read up to `offset`.
+    fn record(&self, id: &ImportId, offset: u64) -> Result<(), ChainFileError> {
+        let recorded = ImportProgress {
+            id: id.clone(),
+            offset,
+        };
+        let tree = self.blockchain.get_db().open_tree(IMPORT_TREE)?;
+        tree.insert(IMPORT_PROGRESS_KEY, bincode::serialize(&recorded).unwrap())?;
+        self.blockchain.get_db().flush()?;
+        Ok(())
+    }
+
+    /// Forgets the finished import.
+    fn finish(&self) -> Result<(), ChainFileError> {
+        let tree = self.blockchain.get_db().open_tree(IMPORT_TREE)?;
+        tree.remove(IMPORT_PROGRESS_KEY)?;
+        self.blockchain.get_db().flush()?;
+        Ok(())
+    }
+}
+
+/// Verifies the signatures of `spent` on up to `threads` threads, returning the batch
+/// index and txid of the earliest [Transaction] with an invalid one.
+fn first_invalid_signature(
+    spent: &[(usize, Transaction, Vec<TXOutput>)],
+    threads: usize,
+) -> Option<(usize, Txid)> {
+    if spent.is_empty() {
+        return None;
+    }
+    let chunk_size = spent.len().div_ceil(threads);
+    thread::scope(|scope| {
+        // Every thread has to be spawned before the first one is joined.
+        #[allow(clippy::needless_collect)]
+        let handles: Vec<_> = spent
+            .chunks(chunk_size)
+            .map(|chunk| {
+                scope.spawn(move || {
+                    chunk
+                        .iter()
+                        .find(|(_, tx, outputs)| !tx.verify_spending(outputs.as_slice()))
+                        .map(|(idx, tx, _)| (*idx, tx.get_id()))
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .filter_map(|handle| handle.join().unwrap())
+            .min()
+    })
+}
+
+fn write_header(writer: &mut impl Write, network: Network) -> io::Result<u64> {
+    let name = network.as_str().as_bytes();
+    writer.write_all(MAGIC)?;
+    writer.write_all(&[FORMAT_VERSION, u8::try_from(name.len()).unwrap()])?;
+    writer.write_all(name)?;
+    Ok((MAGIC.len() + 2 + name.len()) as u64)
+}
+
+/// Writes `block` with its length in front, returning the bytes written.
+fn write_block(writer: &mut impl Write, block: &Block) -> io::Result<u64> {
+    let bytes = block.serialize();
+    let len = u32::try_from(bytes.len()).map_err(|_| io::Error::from(ErrorKind::InvalidData))?;
+    writer.write_all(len.to_le_bytes().as_slice())?;
+    writer.write_all(bytes.as_slice())?;
+    Ok(4 + u64::from(len))
+}
+
+fn read_header(reader: &mut impl Read, network: Network) -> Result<u64, ChainFileError> {
+    let mut magic = vec![0; MAGIC.len()];
+    let mut version_and_len = [0; 2];
+    if reader.read_exact(magic.as_mut_slice()).is_err()
+        || magic != MAGIC
+        || reader.read_exact(&mut version_and_len).is_err()
+        || version_and_len[0] != FORMAT_VERSION
+    {
+        return Err(ChainFileError::InvalidHeader);
+    }
+    let mut name = vec![0; usize::from(version_and_len[1])];
+    reader
+        .read_exact(name.as_mut_slice())
+        .map_err(|_| ChainFileError::InvalidHeader)?;
+    let name = String::from_utf8_lossy(name.as_slice()).into_owned();
+    if name != network.as_str() {
+        return Err(ChainFileError::WrongNetwork {
+            expected: network,
+            found: name,
+        });
+    }
+    Ok((MAGIC.len() + 2 + name.len()) as u64)
+}
+
+/// Reads the next length-prefixed [Block], or `None` at the end of the file.
+fn read_block(
+    reader: &mut impl Read,
+    summary: &mut ChainFileSummary,
+) -> Result<Option<Block>, ChainFileError> {
+    let truncated = |summary: &ChainFileSummary| ChainFileError::Truncated {
+        blocks: summary.blocks + summary.skipped,
+        bytes: summary.bytes,
+    };
+    let mut len_bytes = [0; 4];
+    let mut filled = 0;
+    while filled < len_bytes.len() {
+        match reader.read(&mut len_bytes[filled..])? {
+            0 if filled == 0 => return Ok(None),
+            0 => return Err(truncated(summary)),
+            n => filled += n,
+        }
+    }
+    let len = u32::from_le_bytes(len_bytes);
+    let max_block_bytes = GLOBAL_CONFIG.get_size_limits().max_block_bytes;
+    if usize::try_from(len).map_or(true, |len| len > max_block_bytes) {
+        return Err(ChainFileError::Corrupt {
+            blocks: summary.blocks + summary.skipped,
+            bytes: summary.bytes,
+        });
+    }
+    let mut bytes = vec![0; len as usize];
+    if let Err(e) = reader.read_exact(bytes.as_mut_slice()) {
+        return Err(if e.kind() == ErrorKind::UnexpectedEof {
+            truncated(summary)
+        } else {
+            e.into()
+        });
+    }
+    let Ok(block) = bincode::deserialize(bytes.as_slice()) else {
+        return Err(ChainFileError::Corrupt {
+            blocks: summary.blocks + summary.skipped,
+            bytes: summary.bytes,
+        });
+    };
+    summary.bytes += 4 + u64::from(len);
+    Ok(Some(block))
+}
+
+fn invalid_block(block: &Block, reason: impl Into<String>) -> ChainFileError {
+    ChainFileError::InvalidBlock {
+        height: block.get_height(),
+        hash: block.get_hash().to_string(),
+        reason: reason.into(),
+    }
+}
+
+/// Checks that the first [Block] of a new [Blockchain] is a genesis block of `network`.
+fn verify_genesis(block: &Block, network: Network) -> Result<(), ChainFileError> {
+    if block.get_height() != 0 || block.get_pre_block_hash() != network.genesis_pre_block_hash() {
+        return Err(invalid_block(
+            block,
+            format!("it is not the genesis block of the {network} network"),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::clock::system_clock;
+    use crate::test_utils::{coinbase, mine, pay, regtest, temp_chain};
+    use crate::wallet::Wallet;
+
+    /// A chain file of its own in the temporary directory, removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new() -> Self {
+            let name = format!("himalia-chain-{}", crate::random_u64());
+            Self(std::env::temp_dir().join(name))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(self.0.as_path());
+        }
+    }
+
+    /// A [Blockchain] in a temporary database holding only the genesis [Block] of
+    /// `blockchain`.
+    fn empty_copy(blockchain: &Blockchain) -> Blockchain {
+        regtest();
+        let genesis = blockchain.get_block_by_height(0).unwrap();
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        Blockchain::open_or_init_in(db.into(), &genesis).unwrap()
+    }
+
+    /// Writes `blocks` to a new chain file.
+    fn write_chain_file(blocks: &[Block]) -> TempFile {
+        let file = TempFile::new();
+        let mut writer = BufWriter::new(File::create(file.0.as_path()).unwrap());
+        write_header(&mut writer, GLOBAL_CONFIG.get_network()).unwrap();
+        for block in blocks {
+            write_block(&mut writer, block).unwrap();
+        }
+        writer.flush().unwrap();
+        file
+    }
+
+    #[test]
+    fn interrupted_import_resumes_where_it_stopped() {
+        let miner = Wallet::new();
+        let source = temp_chain(&miner);
+        for amount in 1..=4 {
+            let payment = pay(&source, &miner, &Wallet::new(), amount);
+            mine(&source, vec![payment], &miner);
+        }
+        let file = TempFile::new();
+        export_chain(&source, file.0.as_path(), |_| {}).unwrap();
+
+        // Connects the genesis block and two more, as an import stopped after them would.
+        let target = empty_copy(&source);
+        let mut reader = BufReader::new(File::open(file.0.as_path()).unwrap());
+        let mut read = ChainFileSummary {
+            bytes: read_header(&mut reader, GLOBAL_CONFIG.get_network()).unwrap(),
+            ..ChainFileSummary::default()
+        };
+        for height in 0..3 {
+            let block = read_block(&mut reader, &mut read).unwrap().unwrap();
+            if height > 0 {
+                target.add_block(&block).unwrap();
+            }
+        }
+        let id = ImportId::new(file.0.as_path(), reader.get_ref()).unwrap();
+        let importer = Importer::new(target.clone(), VerifyLevel::Full, 2);
+        importer.record(&id, read.bytes).unwrap();
+
+        let summary = import_chain_into(
+            Some(target.clone()),
+            file.0.as_path(),
+            VerifyLevel::Full,
+            2,
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(summary.resumed_from, Some(read.bytes));
+        assert_eq!(summary.blocks, 2);
+        assert_eq!(summary.skipped, 0);
+        assert_eq!(target.get_tip_hash(), source.get_tip_hash());
+        assert_eq!(importer.resume(&id).unwrap(), None);
+        assert!(UTXOSet::new(target).verify(|_| {}).is_consistent());
+    }
+
+    #[test]
+    fn only_a_full_import_verifies_signatures() {
+        let miner = Wallet::new();
+        let source = temp_chain(&miner);
+        let genesis = source.get_block_by_height(0).unwrap();
+        let mut json = serde_json::to_value(pay(&source, &miner, &Wallet::new(), 3)).unwrap();
+        let byte = json["vin"][0]["signature"][0].as_u64().unwrap();
+        json["vin"][0]["signature"][0] = (byte ^ 1).into();
+        let forged: Transaction = serde_json::from_value(json).unwrap();
+        assert!(forged.has_valid_id());
+        let b1 = Block::new(
+            genesis.get_hash(),
+            vec![coinbase(&miner, 0)],
+            1,
+            source.get_next_target_bits(&genesis),
+            &*system_clock(),
+        );
+        let b2 = Block::new(
+            b1.get_hash(),
+            vec![forged.clone(), coinbase(&miner, 1)],
+            2,
+            b1.get_target_bits(),
+            &*system_clock(),
+        );
+        let file = write_chain_file(&[genesis, b1.clone(), b2.clone()]);
+
+        let target = empty_copy(&source);
+        let refused = import_chain_into(
+            Some(target.clone()),
+            file.0.as_path(),
+            VerifyLevel::Full,
+            2,
+            |_| {},
+        );
+        match refused {
+            Err(ChainFileError::InvalidBlock { height, reason, .. }) => {
+                assert_eq!(height, 2);
+                assert_eq!(
+                    reason,
+                    format!("transaction {} has an invalid signature", forged.get_id())
+                );
+            }
+            other => panic!("expected an invalid block, got {other:?}"),
+        }
+        assert_eq!(target.get_tip_hash(), b1.get_hash());
+
+        let target = empty_copy(&source);
+        import_chain_into(
+            Some(target.clone()),
+            file.0.as_path(),
+            VerifyLevel::Linkage,
+            1,
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(target.get_tip_hash(), b2.get_hash());
+    }
+
+    #[test]
+    fn blocks_are_imported_a_batch_at_a_time_and_skipped_once_known() {
+        let (miner, payee) = (Wallet::new(), Wallet::new());
+        let source = temp_chain(&miner);
+        let payment = pay(&source, &miner, &payee, 5);
+        mine(&source, vec![payment], &miner);
+        // Spends an output created earlier in the same batch.
+        let onward = pay(&source, &payee, &Wallet::new(), 2);
+        mine(&source, vec![onward], &miner);
+        while source.get_best_height() <= IMPORT_BATCH {
+            mine(&source, vec![], &miner);
+        }
+        let file = TempFile::new();
+        export_chain(&source, file.0.as_path(), |_| {}).unwrap();
+
+        let target = empty_copy(&source);
+        let summary = import_chain_into(
+            Some(target.clone()),
+            file.0.as_path(),
+            VerifyLevel::Full,
+            4,
+            |_| {},
+        )
+        .unwrap();
+        // The genesis block is known from the start.
+        assert_eq!((summary.blocks, summary.skipped), (IMPORT_BATCH + 1, 1));
+        assert_eq!(target.get_tip_hash(), source.get_tip_hash());
+        assert!(UTXOSet::new(target.clone()).verify(|_| {}).is_consistent());
+
+        let again = import_chain_into(Some(target), file.0.as_path(), VerifyLevel::Full, 4, |_| {})
+            .unwrap();
+        assert_eq!((again.blocks, again.skipped), (0, IMPORT_BATCH + 2));
+        assert_eq!(again.tip_hash, source.get_tip_hash().to_string());
+    }
+
+    #[test]
+    fn linkage_refuses_a_block_at_the_wrong_height() {
+        let miner = Wallet::new();
+        let source = temp_chain(&miner);
+        let genesis = source.get_block_by_height(0).unwrap();
+        let b1 = mine(&source, vec![], &miner);
+        let skipped_ahead = Block::new(
+            b1.get_hash(),
+            vec![coinbase(&miner, 0)],
+            5,
+            b1.get_target_bits(),
+            &*system_clock(),
+        );
+        let file = write_chain_file(&[genesis, b1.clone(), skipped_ahead.clone()]);
+
+        let target = empty_copy(&source);
+        let refused = import_chain_into(
+            Some(target.clone()),
+            file.0.as_path(),
+            VerifyLevel::Linkage,
+            1,
+            |_| {},
+        );
+        match refused {
+            Err(ChainFileError::InvalidBlock { height, hash, .. }) => {
+                assert_eq!(height, 5);
+                assert_eq!(hash, skipped_ahead.get_hash().to_string());
+            }
+            other => panic!("expected an invalid block, got {other:?}"),
+        }
+        assert_eq!(target.get_tip_hash(), b1.get_hash());
+    }
+}
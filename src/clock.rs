@@ -0,0 +1,110 @@
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, LazyLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The [`SystemClock`] every [Clock] not given explicitly defaults to, shared so that
+/// its timestamps never go backwards across the process.
+static SYSTEM_CLOCK: LazyLock<Arc<SystemClock>> = LazyLock::new(|| Arc::new(SystemClock::new()));
+
+/// A source of timestamps in milliseconds since the Unix epoch.
+///
+/// [Block](crate::block::Block)s, the [`MemoryPool`](crate::memory_pool::MemoryPool)
+/// and the background tasks read the time through one, so that a [`MockClock`] can
+/// stand in for the system time.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> i64;
+}
+
+/// Returns the process wide [`SystemClock`].
+pub fn system_clock() -> Arc<dyn Clock> {
+    SYSTEM_CLOCK.clone()
+}
+
+/// Reads the system time, never returning less than it returned before.
+///
+/// When the system clock steps backwards the last timestamp is returned until the
+/// system time catches up with it, instead of panicking.
+#[derive(Debug, Default)]
+pub struct SystemClock {
+    last: AtomicI64,
+}
+
+impl SystemClock {
+    pub const fn new() -> Self {
+        Self {
+            last: AtomicI64::new(0),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> i64 {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| {
+                i64::try_from(elapsed.as_millis()).unwrap_or(i64::MAX)
+            });
+        let last = self.last.fetch_max(now, Ordering::Relaxed);
+        now.max(last)
+    }
+}
+
+/// A [Clock] that only moves when told to.
+#[derive(Debug, Default)]
+pub struct MockClock {
+    now: AtomicI64,
+}
+
+impl MockClock {
+    pub const fn new(now: i64) -> Self {
+        Self {
+            now: AtomicI64::new(now),
+        }
+    }
+
+    pub fn set(&self, now: i64) {
+        self.now.store(now, Ordering::Relaxed);
+    }
+
+    /// Moves the time forward by `millis`, or backwards when it is negative.
+    pub fn advance(&self, millis: i64) {
+        self.now.fetch_add(millis, Ordering::Relaxed);
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> i64 {
+        self.now.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn system_clock_holds_its_last_timestamp_while_the_time_is_behind() {
+        let fresh = SystemClock::new().now();
+        assert!(fresh > 0);
+        // As if the system time had been stepped back an hour since the last reading.
+        let ahead = fresh + 3_600_000;
+        let clock = SystemClock {
+            last: AtomicI64::new(ahead),
+        };
+        assert_eq!(clock.now(), ahead);
+        assert_eq!(clock.now(), ahead);
+        assert!(system_clock().now() >= fresh);
+    }
+
+    #[test]
+    fn mock_clock_only_moves_when_told() {
+        let clock = MockClock::new(1_000);
+        assert_eq!(clock.now(), 1_000);
+        clock.advance(5 * 3_600_000);
+        assert_eq!(clock.now(), 18_001_000);
+        clock.advance(-1_000);
+        assert_eq!(clock.now(), 18_000_000);
+        clock.set(7);
+        assert_eq!(clock.now(), 7);
+    }
+}
@@ -1,44 +1,1695 @@
-use std::{collections::HashMap, env, sync::RwLock};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display, Formatter};
+use std::net::SocketAddr;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{LazyLock, RwLock};
+use std::time::Duration;
+use std::{env, env::current_dir, error::Error, fs, thread};
 
-use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
 
-pub static GLOBAL_CONFIG: Lazy<Config> = Lazy::new(Config::new);
-static DEFAULT_NODE_ADDR: &str = "127.0.0.1:2001";
+use crate::blockchain::SizeLimits;
+use crate::memory_pool::{MempoolLimits, DEFAULT_MEMPOOL_EXPIRY};
+use crate::node::{
+    is_valid_addr, DEFAULT_BAN_DURATION, DEFAULT_BAN_THRESHOLD, DEFAULT_PEER_EXPIRY,
+};
+use crate::{sha256_digest, types::BlockHash, wallet::address_pub_key_hash_for};
+
+pub static GLOBAL_CONFIG: LazyLock<Config> = LazyLock::new(Config::new);
+pub const DEFAULT_CONFIG_FILE: &str = "himalia.toml";
+const CONFIG_FILE_ENV: &str = "HIMALIA_CONFIG";
 const NODE_ADDRESS_KEY: &str = "NODE_ADDRESS";
 const MINING_ADDRESS_KEY: &str = "MINING_ADDRESS";
+/// The number of threads searching for a nonce, every available core when unset.
+const MINING_THREADS_KEY: &str = "MINING_THREADS";
+/// The number of pending transactions that triggers mining a block, the network's
+/// by default.
+const TRANSACTION_THRESHOLD_KEY: &str = "TRANSACTION_THRESHOLD";
+/// How many seconds a miner waits for a block before mining whatever is pending,
+/// the block interval of the network by default.
+const MINING_INTERVAL_KEY: &str = "MINING_INTERVAL";
+/// The most transactions the mempool holds before evicting those paying the least.
+const MEMPOOL_MAX_TXS_KEY: &str = "MEMPOOL_MAX_TXS";
+/// The most serialized bytes the transactions of the mempool take up.
+const MEMPOOL_MAX_BYTES_KEY: &str = "MEMPOOL_MAX_BYTES";
+/// The largest payload read from a peer before it is decoded.
+const MAX_PACKAGE_BYTES_KEY: &str = "MAX_PACKAGE_BYTES";
+/// The largest serialized block accepted or mined, the network's by default.
+const MAX_BLOCK_BYTES_KEY: &str = "MAX_BLOCK_BYTES";
+/// The most transactions a block may hold, its coinbase included.
+const MAX_BLOCK_TXS_KEY: &str = "MAX_BLOCK_TXS";
+/// The largest serialized transaction accepted.
+const MAX_TX_BYTES_KEY: &str = "MAX_TX_BYTES";
+/// How many seconds a transaction stays in the mempool before it is evicted.
+const MEMPOOL_EXPIRY_KEY: &str = "MEMPOOL_EXPIRY";
+/// How many connections from peers a node serves at a time, and how many more wait
+/// for their turn before further ones are closed.
+const MAX_CONNECTIONS_KEY: &str = "MAX_CONNECTIONS";
+const DEFAULT_MAX_CONNECTIONS: usize = 8;
+/// How many seconds a peer that hasn't been seen is remembered across restarts.
+const PEER_EXPIRY_KEY: &str = "PEER_EXPIRY";
+/// The ban score at which a misbehaving peer is banned.
+const BAN_THRESHOLD_KEY: &str = "BAN_THRESHOLD";
+/// How many seconds a misbehaving peer stays banned.
+const BAN_DURATION_KEY: &str = "BAN_DURATION";
+const NETWORK_KEY: &str = "NETWORK";
+const LOG_LEVEL_KEY: &str = "LOG_LEVEL";
+const LOG_FILE_KEY: &str = "LOG_FILE";
+const LOG_FORMAT_KEY: &str = "LOG_FORMAT";
+const DEFAULT_LOG_LEVEL: &str = "info";
+const WALLET_PATH_KEY: &str = "WALLET_PATH";
+const WALLET_BACKUP_DIR_KEY: &str = "WALLET_BACKUP_DIR";
+/// Comma separated peers to bootstrap from instead of the central node.
+const PEERS_KEY: &str = "PEERS";
+const LISTEN_KEY: &str = "LISTEN";
+const WAIT_FOR_SYNC_KEY: &str = "WAIT_FOR_SYNC";
+/// Where a running node serves JSON-RPC, [`DEFAULT_RPC_BIND`] when unset while
+/// [`RPC_AUTH_KEY`] is set. JSON-RPC is off without a token.
+const RPC_BIND_KEY: &str = "RPC_BIND";
+const DEFAULT_RPC_BIND: &str = "127.0.0.1:2011";
+/// The bearer token JSON-RPC requests must carry. Only read from the environment.
+const RPC_AUTH_KEY: &str = "RPC_AUTH";
+/// Where a running node serves the read-only REST API, which is off when unset.
+const REST_BIND_KEY: &str = "REST_BIND";
+/// Sent as `Access-Control-Allow-Origin` by the REST API, e.g. `*`.
+const REST_CORS_ORIGIN_KEY: &str = "REST_CORS_ORIGIN";
+/// Where a node built with the `grpc` feature serves gRPC, which is off when unset.
+const GRPC_BIND_KEY: &str = "GRPC_BIND";
+/// Where a running node publishes block and transaction notifications, off when unset.
+const NOTIFY_BIND_KEY: &str = "NOTIFY_BIND";
+/// The program `--signer external` asks for signatures, see
+/// [`CommandSigner`](crate::signer::CommandSigner).
+const SIGNER_COMMAND_KEY: &str = "SIGNER_COMMAND";
+/// The endpoint `--signer http` asks for signatures, see
+/// [`HttpSigner`](crate::signer::HttpSigner).
+const SIGNER_URL_KEY: &str = "SIGNER_URL";
+/// Signs with RFC 6979 nonces so that the same [Transaction](crate::transactions::Transaction)
+/// always gets the same bytes. Only allowed on test networks.
+const DETERMINISTIC_SIGNING_KEY: &str = "DETERMINISTIC_SIGNING";
+/// Set by `--wallet` for a single invocation and never persisted.
+const WALLET_OVERRIDE_KEY: &str = "WALLET_OVERRIDE";
+const DEFAULT_WALLET_FILE: &str = "wallet.dat";
+/// The directory holding the chain data of every [Network], resolved against the
+/// working directory.
+const DATA_DIR_KEY: &str = "DATA_DIR";
+/// Sets [`DATA_DIR_KEY`] from the environment, where a bare `DATA_DIR` is too generic.
+const DATA_DIR_ENV: &str = "HIMALIA_DATA_DIR";
+const DEFAULT_DATA_DIR: &str = "data";
+const DEFAULT_WALLET_BACKUP_DIR: &str = "backups";
+/// Every setting that can be given in the config file or the environment.
+const SETTING_KEYS: [&str; 34] = [
+    NETWORK_KEY,
+    NODE_ADDRESS_KEY,
+    MINING_ADDRESS_KEY,
+    MINING_THREADS_KEY,
+    TRANSACTION_THRESHOLD_KEY,
+    MINING_INTERVAL_KEY,
+    MEMPOOL_MAX_TXS_KEY,
+    MEMPOOL_MAX_BYTES_KEY,
+    MEMPOOL_EXPIRY_KEY,
+    MAX_PACKAGE_BYTES_KEY,
+    MAX_BLOCK_BYTES_KEY,
+    MAX_BLOCK_TXS_KEY,
+    MAX_TX_BYTES_KEY,
+    MAX_CONNECTIONS_KEY,
+    PEER_EXPIRY_KEY,
+    BAN_THRESHOLD_KEY,
+    BAN_DURATION_KEY,
+    LOG_LEVEL_KEY,
+    LOG_FILE_KEY,
+    LOG_FORMAT_KEY,
+    WALLET_PATH_KEY,
+    WALLET_BACKUP_DIR_KEY,
+    PEERS_KEY,
+    LISTEN_KEY,
+    WAIT_FOR_SYNC_KEY,
+    RPC_BIND_KEY,
+    RPC_AUTH_KEY,
+    REST_BIND_KEY,
+    REST_CORS_ORIGIN_KEY,
+    GRPC_BIND_KEY,
+    NOTIFY_BIND_KEY,
+    SIGNER_COMMAND_KEY,
+    SIGNER_URL_KEY,
+    DETERMINISTIC_SIGNING_KEY,
+];
+const REDACTED: &str = "<redacted>";
+const MEMPOOL_SNAPSHOT_FILE: &str = "mempool.json";
+const NODE_STATUS_FILE: &str = "node.json";
+const PEERS_FILE: &str = "peers.json";
+const BAN_LIST_FILE: &str = "banlist.json";
+const CONFIG_FILE_HEADER: &str = "\
+# himalia configuration, regenerated by `--save`.
+# Comments added by hand are not preserved.
+
+";
+
+/// Parameters that nodes on the same [Network] must agree on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct ChainParams {
+    /// The reward paid to the miner of a [Block].
+    pub subsidy: u64,
+    /// The number of pending transactions that triggers mining a [Block].
+    pub transaction_threshold: usize,
+    /// The expected number of seconds between [Block]s.
+    pub block_interval: u64,
+    /// The number of blocks a coinbase output has to wait before it can be spent.
+    pub coinbase_maturity: usize,
+    /// The maximum size of a serialized [Block] in bytes.
+    pub max_block_size: usize,
+    /// Outputs worth less than this are not created.
+    pub dust_threshold: u64,
+    /// The number of leading zero bits the hash of the genesis [Block] must have, and
+    /// the lowest difficulty retargeting goes down to.
+    pub target_bits: i64,
+    /// The number of [Block]s between difficulty adjustments, `0` to keep every
+    /// [Block] at `target_bits`.
+    pub retarget_interval: usize,
+}
+
+const MAIN_PARAMS: ChainParams = ChainParams {
+    subsidy: 10,
+    transaction_threshold: 2,
+    block_interval: 60,
+    coinbase_maturity: 100,
+    max_block_size: 1_000_000,
+    dust_threshold: 1,
+    target_bits: 18,
+    retarget_interval: 60,
+};
+
+const TEST_PARAMS: ChainParams = ChainParams {
+    block_interval: 30,
+    target_bits: 14,
+    ..MAIN_PARAMS
+};
+
+/// Permissive parameters so that blocks can be produced instantly in tests.
+const REGTEST_PARAMS: ChainParams = ChainParams {
+    transaction_threshold: 1,
+    block_interval: 1,
+    coinbase_maturity: 0,
+    dust_threshold: 0,
+    target_bits: 1,
+    retarget_interval: 0,
+    ..MAIN_PARAMS
+};
+
+/// The chain a node participates in.
+///
+/// Each profile keeps its own data directory, address version byte, ports and
+/// consensus parameters so that test runs never touch the main chain.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Network {
+    #[default]
+    Main,
+    Test,
+    Regtest,
+}
+
+impl Network {
+    pub const ALL: [Self; 3] = [Self::Main, Self::Test, Self::Regtest];
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Main => "main",
+            Self::Test => "test",
+            Self::Regtest => "regtest",
+        }
+    }
+
+    /// The subdirectory of `data/` holding the chain for this [Network].
+    pub const fn data_subdir(self) -> &'static str {
+        self.as_str()
+    }
+
+    /// The wallet file in the working directory used before `wallet_path` existed.
+    pub const fn legacy_wallet_file(self) -> &'static str {
+        match self {
+            Self::Main => "wallet.dat",
+            Self::Test => "wallet-test.dat",
+            Self::Regtest => "wallet-regtest.dat",
+        }
+    }
+
+    /// The version byte prepended to public key hashes when building addresses.
+    pub const fn address_version(self) -> u8 {
+        match self {
+            Self::Main => 0x00,
+            Self::Test => 0x6f,
+            Self::Regtest => 0x7a,
+        }
+    }
+
+    /// The version byte prepended to private keys exported from a wallet, so that
+    /// they can't be mistaken for addresses or imported on another [Network].
+    pub const fn private_key_version(self) -> u8 {
+        match self {
+            Self::Main => 0x80,
+            Self::Test => 0xef,
+            Self::Regtest => 0xfa,
+        }
+    }
+
+    /// The bytes every frame sent between peers starts with, so that nodes of
+    /// different [Network]s refuse each other's packages.
+    pub const fn magic(self) -> [u8; 4] {
+        match self {
+            Self::Main => [0x68, 0x69, 0x6d, 0xd1],
+            Self::Test => [0x68, 0x69, 0x6d, 0x7e],
+            Self::Regtest => [0x68, 0x69, 0x6d, 0x5a],
+        }
+    }
+
+    pub const fn default_port(self) -> u16 {
+        match self {
+            Self::Main => 2001,
+            Self::Test => 12001,
+            Self::Regtest => 22001,
+        }
+    }
+
+    /// The address of the central node every other [Node] bootstraps from.
+    pub fn central_node(self) -> String {
+        format!("127.0.0.1:{}", self.default_port())
+    }
+
+    /// The consensus-adjacent parameters every [Node] on this [Network] must agree on.
+    pub const fn params(self) -> &'static ChainParams {
+        match self {
+            Self::Main => &MAIN_PARAMS,
+            Self::Test => &TEST_PARAMS,
+            Self::Regtest => &REGTEST_PARAMS,
+        }
+    }
+
+    /// The value stored as the parent hash of the genesis [Block].
+    ///
+    /// Commits to the [Network] name and its [`ChainParams`] so that nodes whose
+    /// parameters differ end up with different genesis blocks instead of diverging later.
+    pub fn genesis_pre_block_hash(self) -> BlockHash {
+        let mut data = self.as_str().as_bytes().to_vec();
+        data.extend(bincode::serialize(self.params()).unwrap());
+        BlockHash::from_slice(sha256_digest(data.as_slice()).as_slice())
+            .expect("SHA-256 digests are 32 bytes")
+    }
+}
+
+impl Display for Network {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "main" | "mainnet" => Ok(Self::Main),
+            "test" | "testnet" => Ok(Self::Test),
+            "regtest" => Ok(Self::Regtest),
+            _ => Err(format!(
+                "unknown network `{s}`, expected main, test or regtest"
+            )),
+        }
+    }
+}
+
+/// Where the effective value of a setting came from.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Source {
+    #[default]
+    Default,
+    File,
+    Env,
+    Flag,
+}
+
+impl Display for Source {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Default => f.write_str("default"),
+            Self::File => f.write_str("file"),
+            Self::Env => f.write_str("env"),
+            Self::Flag => f.write_str("flag"),
+        }
+    }
+}
+
+/// The effective value of a single setting and where it came from.
+#[derive(Debug, Clone, Serialize)]
+pub struct SettingSnapshot {
+    pub value: Option<String>,
+    pub source: Source,
+}
+
+/// A copy of the effective [Config] keyed by setting name, with secrets redacted.
+#[derive(Debug, Clone, Serialize)]
+#[serde(transparent)]
+pub struct ConfigSnapshot(BTreeMap<String, SettingSnapshot>);
+
+impl ConfigSnapshot {
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &SettingSnapshot)> {
+        self.0.iter()
+    }
+}
+
+/// How log records are rendered.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Human readable lines.
+    #[default]
+    Text,
+    /// One JSON object per line, for ingestion by log collectors.
+    Json,
+}
+
+impl Display for LogFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Text => f.write_str("text"),
+            Self::Json => f.write_str("json"),
+        }
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("unknown log format `{s}`, expected text or json")),
+        }
+    }
+}
+
+/// A setting that would stop the node from working correctly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The config file exists but could not be read or parsed.
+    InvalidFile {
+        path: PathBuf,
+        reason: String,
+    },
+    UnknownNetwork(String),
+    InvalidNodeAddress(String),
+    InvalidPeerAddress(String),
+    InvalidRpcAddress(String),
+    InvalidRestAddress(String),
+    InvalidGrpcAddress(String),
+    InvalidNotifyAddress(String),
+    InvalidSignerUrl(String),
+    InvalidWebhook {
+        url: String,
+        reason: String,
+    },
+    /// JSON-RPC or gRPC is enabled without a token to authenticate requests with.
+    MissingRpcAuth,
+    InvalidMiningAddress {
+        address: String,
+        network: Network,
+    },
+    /// The number of mining threads isn't a positive integer.
+    InvalidMiningThreads(String),
+    /// The number of connections served at a time isn't a positive integer.
+    InvalidMaxConnections(String),
+    /// The peer expiry isn't a positive number of seconds.
+    InvalidPeerExpiry(String),
+    /// The ban threshold or duration isn't a positive integer.
+    InvalidBanSetting {
+        key: &'static str,
+        value: String,
+    },
+    /// The transaction threshold or mining interval isn't a positive integer.
+    InvalidMiningSetting {
+        key: &'static str,
+        value: String,
+    },
+    /// A mempool limit isn't a positive integer.
+    InvalidMempoolLimit {
+        key: &'static str,
+        value: String,
+    },
+    /// A package, block or transaction size limit isn't a positive integer.
+    InvalidSizeLimit {
+        key: &'static str,
+        value: String,
+    },
+    /// Blocks of the largest size allowed don't fit in the largest package.
+    BlockExceedsPackage {
+        max_block_bytes: usize,
+        max_package_bytes: usize,
+    },
+    DataDirNotWritable {
+        path: PathBuf,
+        reason: String,
+    },
+    InvalidLogLevel(String),
+    UnknownLogFormat(String),
+    InvalidBool {
+        key: &'static str,
+        value: String,
+    },
+    /// Deterministic signing is enabled on the main network.
+    DeterministicSigningOnMain,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidFile { path, reason } => {
+                write!(f, "unable to load config file {}: {reason}", path.display())
+            }
+            Self::UnknownNetwork(network) => write!(
+                f,
+                "unknown network `{network}`, set {NETWORK_KEY} to main, test or regtest"
+            ),
+            Self::InvalidNodeAddress(addr) => write!(
+                f,
+                "node address `{addr}` is not a valid host:port, expected e.g. 127.0.0.1:2001"
+            ),
+            Self::InvalidPeerAddress(addr) => write!(
+                f,
+                "peer address `{addr}` is not a valid host:port, expected e.g. 127.0.0.1:2001"
+            ),
+            Self::InvalidRpcAddress(addr) => write!(
+                f,
+                "rpc address `{addr}` is not a valid socket address, expected e.g. 127.0.0.1:8332"
+            ),
+            Self::InvalidRestAddress(addr) => write!(
+                f,
+                "rest address `{addr}` is not a valid socket address, expected e.g. 127.0.0.1:8080"
+            ),
+            Self::InvalidGrpcAddress(addr) => write!(
+                f,
+                "grpc address `{addr}` is not a valid socket address, expected e.g. 127.0.0.1:50051"
+            ),
+            Self::InvalidNotifyAddress(addr) => write!(
+                f,
+                "notify address `{addr}` is not a valid socket address, expected e.g. 127.0.0.1:28332"
+            ),
+            Self::InvalidSignerUrl(url) => write!(
+                f,
+                "signer URL `{url}` is not supported, expected e.g. http://127.0.0.1:9000"
+            ),
+            Self::InvalidWebhook { url, reason } => {
+                write!(f, "webhook `{url}` is not valid: {reason}")
+            }
+            Self::MissingRpcAuth => write!(
+                f,
+                "{RPC_BIND_KEY} or {GRPC_BIND_KEY} is set but {RPC_AUTH_KEY} is not, set {RPC_AUTH_KEY} in the environment"
+            ),
+            Self::InvalidMiningAddress { address, network } => write!(
+                f,
+                "mining address `{address}` is not a valid {network} network address"
+            ),
+            Self::InvalidMiningThreads(threads) => write!(
+                f,
+                "{MINING_THREADS_KEY} is `{threads}`, expected a positive number of threads"
+            ),
+            Self::InvalidMaxConnections(count) => write!(
+                f,
+                "{MAX_CONNECTIONS_KEY} is `{count}`, expected a positive number of connections"
+            ),
+            Self::InvalidPeerExpiry(secs) => write!(
+                f,
+                "{PEER_EXPIRY_KEY} is `{secs}`, expected a positive number of seconds"
+            ),
+            Self::InvalidBanSetting { key, value } | Self::InvalidMiningSetting { key, value } => {
+                write!(f, "{key} is `{value}`, expected a positive integer")
+            }
+            Self::InvalidMempoolLimit { key, value } | Self::InvalidSizeLimit { key, value } => {
+                write!(f, "{key} is `{value}`, expected a positive number")
+            }
+            Self::BlockExceedsPackage {
+                max_block_bytes,
+                max_package_bytes,
+            } => write!(
+                f,
+                "{MAX_BLOCK_BYTES_KEY} is {max_block_bytes}, more than the \
+                 {max_package_bytes} of {MAX_PACKAGE_BYTES_KEY}"
+            ),
+            Self::DataDirNotWritable { path, reason } => {
+                write!(f, "data directory {} is not writable: {reason}", path.display())
+            }
+            Self::InvalidLogLevel(level) => write!(
+                f,
+                "log level `{level}` is not valid, expected e.g. `info` or `info,himalia::server=debug`"
+            ),
+            Self::UnknownLogFormat(format) => {
+                write!(f, "unknown log format `{format}`, expected text or json")
+            }
+            Self::InvalidBool { key, value } => {
+                write!(f, "{key} is `{value}`, expected true or false")
+            }
+            Self::DeterministicSigningOnMain => write!(
+                f,
+                "{DETERMINISTIC_SIGNING_KEY} is only allowed on the test and regtest networks"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// The settings that can be given in the config file.
+///
+/// This is also what [`Config::persist`] writes, so secrets must never be added here.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct ConfigFile {
+    network: Option<String>,
+    node_address: Option<String>,
+    mining_address: Option<String>,
+    mining_threads: Option<usize>,
+    transaction_threshold: Option<usize>,
+    mining_interval: Option<u64>,
+    mempool_max_txs: Option<usize>,
+    mempool_max_bytes: Option<usize>,
+    mempool_expiry: Option<u64>,
+    max_package_bytes: Option<usize>,
+    max_block_bytes: Option<usize>,
+    max_block_txs: Option<usize>,
+    max_tx_bytes: Option<usize>,
+    max_connections: Option<usize>,
+    peer_expiry: Option<u64>,
+    ban_threshold: Option<u32>,
+    ban_duration: Option<u64>,
+    log_level: Option<String>,
+    log_file: Option<String>,
+    log_format: Option<String>,
+    wallet_path: Option<String>,
+    wallet_backup_dir: Option<String>,
+    data_dir: Option<String>,
+    peers: Option<Vec<String>>,
+    listen: Option<bool>,
+    wait_for_sync: Option<bool>,
+    rpc_bind: Option<String>,
+    rest_bind: Option<String>,
+    rest_cors_origin: Option<String>,
+    grpc_bind: Option<String>,
+    notify_bind: Option<String>,
+    signer_command: Option<String>,
+    signer_url: Option<String>,
+    deterministic_signing: Option<bool>,
+    webhooks: Option<Vec<Webhook>>,
+}
+
+/// Where a node built with the `webhooks` feature posts payments to `addresses`,
+/// given in the config file as e.g.
+///
+/// ```toml
+/// [[webhooks]]
+/// url = "http://127.0.0.1:8000/payments"
+/// addresses = ["..."]
+/// confirmations = 6
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Webhook {
+    pub url: String,
+    pub addresses: Vec<String>,
+    /// The depth at which a payment is posted a second time, after it was first
+    /// posted on reaching the mempool. Zero only posts it from the mempool.
+    #[serde(default = "default_webhook_confirmations")]
+    pub confirmations: usize,
+}
+
+const fn default_webhook_confirmations() -> usize {
+    1
+}
 
 /// Centralized repository for managing configurations within the [Blockchain].
-pub struct Config(RwLock<HashMap<String, String>>);
+///
+/// Settings are resolved from defaults, then the config file, then the environment,
+/// and finally from command line flags applied through the setters.
+pub struct Config {
+    settings: RwLock<HashMap<String, String>>,
+    sources: RwLock<HashMap<String, Source>>,
+    /// The settings read from the config file, even those the environment overrides,
+    /// which are written back by [`Config::persist`].
+    file_settings: RwLock<HashMap<String, String>>,
+    load_errors: RwLock<Vec<ConfigError>>,
+    /// Only read from the config file.
+    webhooks: RwLock<Vec<Webhook>>,
+    path: PathBuf,
+    /// Set when a setting changes at runtime and has not been persisted yet.
+    dirty: AtomicBool,
+}
 
 impl Config {
     pub fn new() -> Self {
-        let node_addr = env::var("NODE_ADDRESS").unwrap_or_else(|_| DEFAULT_NODE_ADDR.to_owned());
-        let map = HashMap::from([(String::from(NODE_ADDRESS_KEY), node_addr)]);
-        Self(RwLock::new(map))
+        Self::load(config_file_path(), |key| env::var(key).ok())
+    }
+
+    /// Loads the config file at `path`, then the settings `env` returns a value for.
+    fn load(path: PathBuf, env: impl Fn(&str) -> Option<String>) -> Self {
+        let config = Self {
+            settings: RwLock::new(HashMap::new()),
+            sources: RwLock::new(HashMap::new()),
+            file_settings: RwLock::new(HashMap::new()),
+            load_errors: RwLock::new(Vec::new()),
+            webhooks: RwLock::new(Vec::new()),
+            path,
+            dirty: AtomicBool::new(false),
+        };
+        config.load_file(config.path.as_path());
+        config.load_env(env);
+        config
+    }
+
+    /// Reads the settings present in the TOML file at `path`, ignoring a missing file.
+    /// Parse failures are kept and reported by [`Config::validate`].
+    #[allow(clippy::too_many_lines)]
+    fn load_file(&self, path: &Path) {
+        let Ok(contents) = fs::read_to_string(path) else {
+            return;
+        };
+        let file: ConfigFile = match toml::from_str(contents.as_str()) {
+            Ok(file) => file,
+            Err(e) => {
+                self.load_errors
+                    .write()
+                    .unwrap()
+                    .push(ConfigError::InvalidFile {
+                        path: path.to_path_buf(),
+                        reason: e.message().to_owned(),
+                    });
+                return;
+            }
+        };
+        for (key, value) in [
+            (NETWORK_KEY, file.network),
+            (NODE_ADDRESS_KEY, file.node_address),
+            (MINING_ADDRESS_KEY, file.mining_address),
+            (
+                MINING_THREADS_KEY,
+                file.mining_threads.map(|threads| threads.to_string()),
+            ),
+            (
+                TRANSACTION_THRESHOLD_KEY,
+                file.transaction_threshold.map(|count| count.to_string()),
+            ),
+            (
+                MINING_INTERVAL_KEY,
+                file.mining_interval.map(|secs| secs.to_string()),
+            ),
+            (
+                MEMPOOL_MAX_TXS_KEY,
+                file.mempool_max_txs.map(|count| count.to_string()),
+            ),
+            (
+                MEMPOOL_MAX_BYTES_KEY,
+                file.mempool_max_bytes.map(|bytes| bytes.to_string()),
+            ),
+            (
+                MEMPOOL_EXPIRY_KEY,
+                file.mempool_expiry.map(|secs| secs.to_string()),
+            ),
+            (
+                MAX_PACKAGE_BYTES_KEY,
+                file.max_package_bytes.map(|bytes| bytes.to_string()),
+            ),
+            (
+                MAX_BLOCK_BYTES_KEY,
+                file.max_block_bytes.map(|bytes| bytes.to_string()),
+            ),
+            (
+                MAX_BLOCK_TXS_KEY,
+                file.max_block_txs.map(|count| count.to_string()),
+            ),
+            (
+                MAX_TX_BYTES_KEY,
+                file.max_tx_bytes.map(|bytes| bytes.to_string()),
+            ),
+            (
+                MAX_CONNECTIONS_KEY,
+                file.max_connections.map(|count| count.to_string()),
+            ),
+            (
+                PEER_EXPIRY_KEY,
+                file.peer_expiry.map(|secs| secs.to_string()),
+            ),
+            (
+                BAN_THRESHOLD_KEY,
+                file.ban_threshold.map(|score| score.to_string()),
+            ),
+            (
+                BAN_DURATION_KEY,
+                file.ban_duration.map(|secs| secs.to_string()),
+            ),
+            (LOG_LEVEL_KEY, file.log_level),
+            (LOG_FILE_KEY, file.log_file),
+            (LOG_FORMAT_KEY, file.log_format),
+            (WALLET_PATH_KEY, file.wallet_path),
+            (WALLET_BACKUP_DIR_KEY, file.wallet_backup_dir),
+            (DATA_DIR_KEY, file.data_dir),
+            (PEERS_KEY, file.peers.map(|peers| peers.join(","))),
+            (LISTEN_KEY, file.listen.map(|listen| listen.to_string())),
+            (
+                WAIT_FOR_SYNC_KEY,
+                file.wait_for_sync.map(|wait| wait.to_string()),
+            ),
+            (RPC_BIND_KEY, file.rpc_bind),
+            (REST_BIND_KEY, file.rest_bind),
+            (REST_CORS_ORIGIN_KEY, file.rest_cors_origin),
+            (GRPC_BIND_KEY, file.grpc_bind),
+            (NOTIFY_BIND_KEY, file.notify_bind),
+            (SIGNER_COMMAND_KEY, file.signer_command),
+            (SIGNER_URL_KEY, file.signer_url),
+            (
+                DETERMINISTIC_SIGNING_KEY,
+                file.deterministic_signing
+                    .map(|enabled| enabled.to_string()),
+            ),
+        ] {
+            if let Some(value) = value {
+                self.file_settings
+                    .write()
+                    .unwrap()
+                    .insert(String::from(key), value.clone());
+                self.insert(key, value, Source::File);
+            }
+        }
+        if let Some(webhooks) = file.webhooks {
+            *self.webhooks.write().unwrap() = webhooks;
+        }
+    }
+
+    /// Reads the settings present in the environment, looked up through `env`.
+    fn load_env(&self, env: impl Fn(&str) -> Option<String>) {
+        for key in SETTING_KEYS {
+            if let Some(value) = env(key) {
+                self.insert(key, value, Source::Env);
+            }
+        }
+        if let Some(value) = env(DATA_DIR_ENV) {
+            self.insert(DATA_DIR_KEY, value, Source::Env);
+        }
+    }
+
+    /// Stores a setting together with where it came from.
+    fn insert(&self, key: &str, value: String, source: Source) {
+        self.settings
+            .write()
+            .unwrap()
+            .insert(String::from(key), value);
+        self.sources
+            .write()
+            .unwrap()
+            .insert(String::from(key), source);
+    }
+
+    /// Changes a setting at runtime, marking the [Config] as having unsaved changes.
+    fn set(&self, key: &str, value: String) {
+        self.insert(key, value, Source::Flag);
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    fn get_source(&self, key: &str) -> Source {
+        let sources = self.sources.read().unwrap();
+        sources.get(key).copied().unwrap_or_default()
+    }
+
+    /// Returns the selected [Network], defaulting to main when unset or unknown.
+    pub fn get_network(&self) -> Network {
+        let inner = self.settings.read().unwrap();
+        inner
+            .get(NETWORK_KEY)
+            .and_then(|network| network.parse().ok())
+            .unwrap_or_default()
+    }
+
+    pub fn set_network(&self, network: Network) {
+        self.set(NETWORK_KEY, network.to_string());
+    }
+
+    /// Returns the directory holding the chain data of the selected [Network], within
+    /// the configured data directory.
+    pub fn get_data_dir(&self) -> PathBuf {
+        let dir = self.settings.read().unwrap().get(DATA_DIR_KEY).cloned();
+        current_dir()
+            .unwrap()
+            .join(dir.as_deref().unwrap_or(DEFAULT_DATA_DIR))
+            .join(self.get_network().data_subdir())
+    }
+
+    pub fn set_data_dir(&self, dir: String) {
+        self.set(DATA_DIR_KEY, dir);
+    }
+
+    /// Returns the file a running node keeps a [`MempoolSnapshot`] in.
+    pub fn get_mempool_snapshot_path(&self) -> PathBuf {
+        self.get_data_dir().join(MEMPOOL_SNAPSHOT_FILE)
+    }
+
+    /// Returns the file a node keeps the peers it has seen in.
+    pub fn get_peers_path(&self) -> PathBuf {
+        self.get_data_dir().join(PEERS_FILE)
+    }
+
+    /// Returns the file a node keeps the peers it banned in.
+    pub fn get_ban_list_path(&self) -> PathBuf {
+        self.get_data_dir().join(BAN_LIST_FILE)
+    }
+
+    /// Returns where a running node reports its [`NodeStatus`](crate::server::NodeStatus).
+    pub fn get_node_status_path(&self) -> PathBuf {
+        self.get_data_dir().join(NODE_STATUS_FILE)
     }
 
+    /// Returns the location of the wallet file, relative paths being resolved against
+    /// the data directory. A `--wallet` override takes precedence over the setting.
+    pub fn get_wallet_path(&self) -> PathBuf {
+        let path = {
+            let inner = self.settings.read().unwrap();
+            inner
+                .get(WALLET_OVERRIDE_KEY)
+                .or_else(|| inner.get(WALLET_PATH_KEY))
+                .cloned()
+        };
+        path.map_or_else(
+            || self.get_data_dir().join(DEFAULT_WALLET_FILE),
+            |path| self.get_data_dir().join(path),
+        )
+    }
+
+    /// Uses the wallet file at `path` for this invocation only, without marking the
+    /// [Config] as changed.
+    pub fn override_wallet_path(&self, path: String) {
+        self.insert(WALLET_OVERRIDE_KEY, path, Source::Flag);
+    }
+
+    /// Checks whether `--wallet` replaced the configured wallet file.
+    pub fn has_wallet_override(&self) -> bool {
+        let inner = self.settings.read().unwrap();
+        inner.contains_key(WALLET_OVERRIDE_KEY)
+    }
+
+    /// Returns the location of the wallet file of the selected [Network] used
+    /// before the wallet path became configurable.
+    pub fn get_legacy_wallet_path(&self) -> PathBuf {
+        current_dir()
+            .unwrap()
+            .join(self.get_network().legacy_wallet_file())
+    }
+
+    /// Returns the directory wallet backups are written to, relative paths being
+    /// resolved against the data directory.
+    pub fn get_wallet_backup_dir(&self) -> PathBuf {
+        let dir = self
+            .settings
+            .read()
+            .unwrap()
+            .get(WALLET_BACKUP_DIR_KEY)
+            .cloned();
+        self.get_data_dir()
+            .join(dir.as_deref().unwrap_or(DEFAULT_WALLET_BACKUP_DIR))
+    }
+
+    /// Returns the address this [Node] listens on, defaulting to the central node
+    /// of the selected [Network].
     pub fn get_node_addr(&self) -> String {
-        let inner = self.0.read().unwrap();
-        inner.get(NODE_ADDRESS_KEY).unwrap().clone()
+        let network = self.get_network();
+        let inner = self.settings.read().unwrap();
+        inner
+            .get(NODE_ADDRESS_KEY)
+            .cloned()
+            .unwrap_or_else(|| network.central_node())
+    }
+
+    pub fn set_node_addr(&self, addr: String) {
+        self.set(NODE_ADDRESS_KEY, addr);
+    }
+
+    /// Returns the peers to bootstrap from, empty when the central node should be used.
+    pub fn get_peers(&self) -> Vec<String> {
+        let inner = self.settings.read().unwrap();
+        inner
+            .get(PEERS_KEY)
+            .map_or_else(Vec::new, |peers| split_peers(peers))
+    }
+
+    pub fn set_peers(&self, peers: &[String]) {
+        self.set(PEERS_KEY, peers.join(","));
+    }
+
+    /// Checks whether the [Node] accepts incoming connections, which is the default.
+    pub fn get_listen(&self) -> bool {
+        self.get_bool(LISTEN_KEY).unwrap_or(true)
+    }
+
+    pub fn set_listen(&self, listen: bool) {
+        self.set(LISTEN_KEY, listen.to_string());
+    }
+
+    /// Checks whether a miner holds off until it has caught up with its peers.
+    pub fn get_wait_for_sync(&self) -> bool {
+        self.get_bool(WAIT_FOR_SYNC_KEY).unwrap_or(false)
+    }
+
+    pub fn set_wait_for_sync(&self, wait: bool) {
+        self.set(WAIT_FOR_SYNC_KEY, wait.to_string());
+    }
+
+    /// Checks whether wallets sign with RFC 6979 nonces instead of random ones.
+    pub fn get_deterministic_signing(&self) -> bool {
+        self.get_bool(DETERMINISTIC_SIGNING_KEY).unwrap_or(false)
+    }
+
+    fn get_bool(&self, key: &str) -> Option<bool> {
+        let inner = self.settings.read().unwrap();
+        inner.get(key).and_then(|value| value.parse().ok())
+    }
+
+    /// Returns the address to serve JSON-RPC on, [`DEFAULT_RPC_BIND`] when only the
+    /// token is set, and `None` when it is turned off.
+    pub fn get_rpc_bind(&self) -> Option<String> {
+        let inner = self.settings.read().unwrap();
+        inner.get(RPC_BIND_KEY).cloned().or_else(|| {
+            inner
+                .contains_key(RPC_AUTH_KEY)
+                .then(|| String::from(DEFAULT_RPC_BIND))
+        })
+    }
+
+    pub fn get_rpc_auth(&self) -> Option<String> {
+        self.settings.read().unwrap().get(RPC_AUTH_KEY).cloned()
+    }
+
+    /// Returns the address to serve the REST API on, `None` when it is turned off.
+    pub fn get_rest_bind(&self) -> Option<String> {
+        self.settings.read().unwrap().get(REST_BIND_KEY).cloned()
+    }
+
+    pub fn get_rest_cors_origin(&self) -> Option<String> {
+        self.settings
+            .read()
+            .unwrap()
+            .get(REST_CORS_ORIGIN_KEY)
+            .cloned()
+    }
+
+    /// Returns the address to serve gRPC on, `None` when it is turned off.
+    pub fn get_grpc_bind(&self) -> Option<String> {
+        self.settings.read().unwrap().get(GRPC_BIND_KEY).cloned()
+    }
+
+    /// Returns the address to publish notifications on, `None` when it is turned off.
+    pub fn get_notify_bind(&self) -> Option<String> {
+        self.settings.read().unwrap().get(NOTIFY_BIND_KEY).cloned()
+    }
+
+    /// Returns the external program that signs for `--signer external`.
+    pub fn get_signer_command(&self) -> Option<String> {
+        self.settings
+            .read()
+            .unwrap()
+            .get(SIGNER_COMMAND_KEY)
+            .cloned()
+    }
+
+    /// Returns the endpoint that signs for `--signer http`.
+    pub fn get_signer_url(&self) -> Option<String> {
+        self.settings.read().unwrap().get(SIGNER_URL_KEY).cloned()
+    }
+
+    /// Returns the webhooks configured in the config file.
+    pub fn get_webhooks(&self) -> Vec<Webhook> {
+        self.webhooks.read().unwrap().clone()
     }
 
     pub fn set_mining_addr(&self, addr: String) {
-        let mut inner = self.0.write().unwrap();
-        inner.insert(String::from(MINING_ADDRESS_KEY), addr);
+        self.set(MINING_ADDRESS_KEY, addr);
     }
 
     pub fn get_mining_addr(&self) -> Option<String> {
-        if let Some(addr) = self.0.read().unwrap().get(MINING_ADDRESS_KEY) {
+        if let Some(addr) = self.settings.read().unwrap().get(MINING_ADDRESS_KEY) {
             return Some(addr.clone());
         }
         None
     }
 
+    /// Returns the number of threads mining a [Block](crate::block::Block), defaulting
+    /// to the available parallelism of the machine.
+    pub fn get_mining_threads(&self) -> usize {
+        let inner = self.settings.read().unwrap();
+        inner
+            .get(MINING_THREADS_KEY)
+            .and_then(|threads| threads.parse().ok())
+            .filter(|threads| *threads > 0)
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, NonZeroUsize::get))
+    }
+
+    pub fn set_mining_threads(&self, threads: usize) {
+        self.set(MINING_THREADS_KEY, threads.to_string());
+    }
+
+    /// Returns the number of pending transactions that triggers mining a
+    /// [Block](crate::block::Block), that of the [Network] by default.
+    pub fn get_transaction_threshold(&self) -> usize {
+        let params = self.get_network().params();
+        self.get_positive(TRANSACTION_THRESHOLD_KEY)
+            .unwrap_or(params.transaction_threshold)
+    }
+
+    pub fn set_transaction_threshold(&self, count: usize) {
+        self.set(TRANSACTION_THRESHOLD_KEY, count.to_string());
+    }
+
+    /// Returns how long a miner waits for a [Block](crate::block::Block) before mining
+    /// the pending transactions short of the threshold, the block interval of the
+    /// [Network] by default.
+    pub fn get_mining_interval(&self) -> Duration {
+        let params = self.get_network().params();
+        Duration::from_secs(
+            self.get_positive(MINING_INTERVAL_KEY)
+                .unwrap_or(params.block_interval),
+        )
+    }
+
+    pub fn set_mining_interval(&self, secs: u64) {
+        self.set(MINING_INTERVAL_KEY, secs.to_string());
+    }
+
+    /// Returns the caps on the size of the mempool.
+    pub fn get_mempool_limits(&self) -> MempoolLimits {
+        let defaults = MempoolLimits::default();
+        MempoolLimits {
+            max_txs: self
+                .get_positive(MEMPOOL_MAX_TXS_KEY)
+                .unwrap_or(defaults.max_txs),
+            max_bytes: self
+                .get_positive(MEMPOOL_MAX_BYTES_KEY)
+                .unwrap_or(defaults.max_bytes),
+        }
+    }
+
+    /// Returns the size limits on what peers send and on the blocks this node mines,
+    /// those of the [Network] for anything left unset.
+    pub fn get_size_limits(&self) -> SizeLimits {
+        let defaults = SizeLimits::new(self.get_network().params());
+        SizeLimits {
+            max_package_bytes: self
+                .get_positive(MAX_PACKAGE_BYTES_KEY)
+                .unwrap_or(defaults.max_package_bytes),
+            max_block_bytes: self
+                .get_positive(MAX_BLOCK_BYTES_KEY)
+                .unwrap_or(defaults.max_block_bytes),
+            max_block_txs: self
+                .get_positive(MAX_BLOCK_TXS_KEY)
+                .unwrap_or(defaults.max_block_txs),
+            max_tx_bytes: self
+                .get_positive(MAX_TX_BYTES_KEY)
+                .unwrap_or(defaults.max_tx_bytes),
+        }
+    }
+
+    /// Returns how long a transaction stays in the mempool before it is evicted.
+    pub fn get_mempool_expiry(&self) -> Duration {
+        self.get_positive(MEMPOOL_EXPIRY_KEY)
+            .map_or(DEFAULT_MEMPOOL_EXPIRY, Duration::from_secs)
+    }
+
+    /// Returns how many connections from peers a node serves at a time.
+    pub fn get_max_connections(&self) -> usize {
+        self.get_positive(MAX_CONNECTIONS_KEY)
+            .unwrap_or(DEFAULT_MAX_CONNECTIONS)
+    }
+
+    pub fn set_max_connections(&self, count: usize) {
+        self.set(MAX_CONNECTIONS_KEY, count.to_string());
+    }
+
+    /// Returns how long a peer that hasn't been seen is remembered.
+    pub fn get_peer_expiry(&self) -> Duration {
+        self.get_positive(PEER_EXPIRY_KEY)
+            .map_or(DEFAULT_PEER_EXPIRY, Duration::from_secs)
+    }
+
+    /// Returns the ban score at which a misbehaving peer is banned.
+    pub fn get_ban_threshold(&self) -> u32 {
+        self.get_positive(BAN_THRESHOLD_KEY)
+            .unwrap_or(DEFAULT_BAN_THRESHOLD)
+    }
+
+    /// Returns how long a misbehaving peer stays banned.
+    pub fn get_ban_duration(&self) -> Duration {
+        self.get_positive(BAN_DURATION_KEY)
+            .map_or(DEFAULT_BAN_DURATION, Duration::from_secs)
+    }
+
+    fn get_positive<T: FromStr + Default + PartialOrd>(&self, key: &str) -> Option<T> {
+        let inner = self.settings.read().unwrap();
+        inner
+            .get(key)
+            .and_then(|value| value.parse().ok())
+            .filter(|value| *value > T::default())
+    }
+
+    /// Returns the log filter, either a single level or `env_logger` style
+    /// per-module directives such as `info,himalia::server=debug`.
+    pub fn get_log_level(&self) -> String {
+        let inner = self.settings.read().unwrap();
+        inner
+            .get(LOG_LEVEL_KEY)
+            .cloned()
+            .unwrap_or_else(|| String::from(DEFAULT_LOG_LEVEL))
+    }
+
+    pub fn set_log_level(&self, level: String) {
+        self.set(LOG_LEVEL_KEY, level);
+    }
+
+    /// Returns the file logs are written to, relative paths being resolved against
+    /// the data directory. Logs go to stderr when unset.
+    pub fn get_log_file(&self) -> Option<PathBuf> {
+        let file = self.settings.read().unwrap().get(LOG_FILE_KEY).cloned()?;
+        Some(self.get_data_dir().join(file))
+    }
+
+    pub fn set_log_file(&self, file: String) {
+        self.set(LOG_FILE_KEY, file);
+    }
+
+    pub fn get_log_format(&self) -> LogFormat {
+        let inner = self.settings.read().unwrap();
+        inner
+            .get(LOG_FORMAT_KEY)
+            .and_then(|format| format.parse().ok())
+            .unwrap_or_default()
+    }
+
+    pub fn set_log_format(&self, format: LogFormat) {
+        self.set(LOG_FORMAT_KEY, format.to_string());
+    }
+
     /// Checks whether a mining address is present in the [Config].
     pub fn is_miner(&self) -> bool {
-        let inner = self.0.read().unwrap();
+        let inner = self.settings.read().unwrap();
         inner.contains_key(MINING_ADDRESS_KEY)
     }
+
+    /// Returns the path of the config file settings are loaded from and persisted to.
+    pub fn get_path(&self) -> &Path {
+        self.path.as_path()
+    }
+
+    /// Checks whether settings changed at runtime have not been persisted yet.
+    pub fn is_dirty(&self) -> bool {
+        self.dirty.load(Ordering::Relaxed)
+    }
+
+    /// Writes the settings read from the config file and those changed at runtime
+    /// back to the config file, leaving out those only given in the environment.
+    ///
+    /// The file is regenerated from the current settings rather than edited in
+    /// place, and replaced atomically so a crash never leaves it half written.
+    /// This is only ever called on an explicit request such as `--save`.
+    pub fn persist(&self) -> Result<(), Box<dyn Error>> {
+        let mut persisted = self.file_settings.read().unwrap().clone();
+        {
+            let settings = self.settings.read().unwrap();
+            let sources = self.sources.read().unwrap();
+            for (key, value) in settings.iter() {
+                if sources.get(key) == Some(&Source::Flag) {
+                    persisted.insert(key.clone(), value.clone());
+                }
+            }
+        }
+        let text = |key: &str| persisted.get(key).cloned();
+        let file = ConfigFile {
+            network: text(NETWORK_KEY),
+            node_address: text(NODE_ADDRESS_KEY),
+            mining_address: text(MINING_ADDRESS_KEY),
+            mining_threads: parse_setting(&persisted, MINING_THREADS_KEY),
+            transaction_threshold: parse_setting(&persisted, TRANSACTION_THRESHOLD_KEY),
+            mining_interval: parse_setting(&persisted, MINING_INTERVAL_KEY),
+            mempool_max_txs: parse_setting(&persisted, MEMPOOL_MAX_TXS_KEY),
+            mempool_max_bytes: parse_setting(&persisted, MEMPOOL_MAX_BYTES_KEY),
+            mempool_expiry: parse_setting(&persisted, MEMPOOL_EXPIRY_KEY),
+            max_package_bytes: parse_setting(&persisted, MAX_PACKAGE_BYTES_KEY),
+            max_block_bytes: parse_setting(&persisted, MAX_BLOCK_BYTES_KEY),
+            max_block_txs: parse_setting(&persisted, MAX_BLOCK_TXS_KEY),
+            max_tx_bytes: parse_setting(&persisted, MAX_TX_BYTES_KEY),
+            max_connections: parse_setting(&persisted, MAX_CONNECTIONS_KEY),
+            peer_expiry: parse_setting(&persisted, PEER_EXPIRY_KEY),
+            ban_threshold: parse_setting(&persisted, BAN_THRESHOLD_KEY),
+            ban_duration: parse_setting(&persisted, BAN_DURATION_KEY),
+            log_level: text(LOG_LEVEL_KEY),
+            log_file: text(LOG_FILE_KEY),
+            log_format: text(LOG_FORMAT_KEY),
+            wallet_path: text(WALLET_PATH_KEY),
+            wallet_backup_dir: text(WALLET_BACKUP_DIR_KEY),
+            data_dir: text(DATA_DIR_KEY),
+            peers: text(PEERS_KEY).map(|peers| split_peers(peers.as_str())),
+            listen: parse_setting(&persisted, LISTEN_KEY),
+            wait_for_sync: parse_setting(&persisted, WAIT_FOR_SYNC_KEY),
+            rpc_bind: text(RPC_BIND_KEY),
+            rest_bind: text(REST_BIND_KEY),
+            rest_cors_origin: text(REST_CORS_ORIGIN_KEY),
+            grpc_bind: text(GRPC_BIND_KEY),
+            notify_bind: text(NOTIFY_BIND_KEY),
+            signer_command: text(SIGNER_COMMAND_KEY),
+            signer_url: text(SIGNER_URL_KEY),
+            deterministic_signing: parse_setting(&persisted, DETERMINISTIC_SIGNING_KEY),
+            webhooks: Some(self.get_webhooks()).filter(|webhooks| !webhooks.is_empty()),
+        };
+        let contents = format!("{CONFIG_FILE_HEADER}{}", toml::to_string(&file)?);
+        let tmp_path = self.path.with_extension("toml.tmp");
+        fs::write(tmp_path.as_path(), contents)?;
+        fs::rename(tmp_path, self.path.as_path())?;
+        *self.file_settings.write().unwrap() = persisted;
+        self.dirty.store(false, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Returns a copy of every effective setting annotated with where it came from.
+    ///
+    /// Secrets are redacted so the snapshot can be printed or served over RPC.
+    #[allow(clippy::too_many_lines)]
+    pub fn snapshot(&self) -> ConfigSnapshot {
+        let webhook_urls: Vec<String> = self
+            .get_webhooks()
+            .into_iter()
+            .map(|webhook| webhook.url)
+            .collect();
+        let wallet_source = if self.has_wallet_override() {
+            Source::Flag
+        } else {
+            self.get_source(WALLET_PATH_KEY)
+        };
+        let path_to_string = |path: PathBuf| path.display().to_string();
+        let settings = [
+            (
+                NETWORK_KEY,
+                Some(self.get_network().to_string()),
+                self.get_source(NETWORK_KEY),
+            ),
+            (
+                NODE_ADDRESS_KEY,
+                Some(self.get_node_addr()),
+                self.get_source(NODE_ADDRESS_KEY),
+            ),
+            (
+                PEERS_KEY,
+                Some(self.get_peers().join(",")).filter(|peers| !peers.is_empty()),
+                self.get_source(PEERS_KEY),
+            ),
+            (
+                LISTEN_KEY,
+                Some(self.get_listen().to_string()),
+                self.get_source(LISTEN_KEY),
+            ),
+            (
+                MINING_ADDRESS_KEY,
+                self.get_mining_addr(),
+                self.get_source(MINING_ADDRESS_KEY),
+            ),
+            (
+                MINING_THREADS_KEY,
+                Some(self.get_mining_threads().to_string()),
+                self.get_source(MINING_THREADS_KEY),
+            ),
+            (
+                TRANSACTION_THRESHOLD_KEY,
+                Some(self.get_transaction_threshold().to_string()),
+                self.get_source(TRANSACTION_THRESHOLD_KEY),
+            ),
+            (
+                MINING_INTERVAL_KEY,
+                Some(self.get_mining_interval().as_secs().to_string()),
+                self.get_source(MINING_INTERVAL_KEY),
+            ),
+            (
+                MEMPOOL_MAX_TXS_KEY,
+                Some(self.get_mempool_limits().max_txs.to_string()),
+                self.get_source(MEMPOOL_MAX_TXS_KEY),
+            ),
+            (
+                MEMPOOL_MAX_BYTES_KEY,
+                Some(self.get_mempool_limits().max_bytes.to_string()),
+                self.get_source(MEMPOOL_MAX_BYTES_KEY),
+            ),
+            (
+                MEMPOOL_EXPIRY_KEY,
+                Some(self.get_mempool_expiry().as_secs().to_string()),
+                self.get_source(MEMPOOL_EXPIRY_KEY),
+            ),
+            (
+                MAX_PACKAGE_BYTES_KEY,
+                Some(self.get_size_limits().max_package_bytes.to_string()),
+                self.get_source(MAX_PACKAGE_BYTES_KEY),
+            ),
+            (
+                MAX_BLOCK_BYTES_KEY,
+                Some(self.get_size_limits().max_block_bytes.to_string()),
+                self.get_source(MAX_BLOCK_BYTES_KEY),
+            ),
+            (
+                MAX_BLOCK_TXS_KEY,
+                Some(self.get_size_limits().max_block_txs.to_string()),
+                self.get_source(MAX_BLOCK_TXS_KEY),
+            ),
+            (
+                MAX_TX_BYTES_KEY,
+                Some(self.get_size_limits().max_tx_bytes.to_string()),
+                self.get_source(MAX_TX_BYTES_KEY),
+            ),
+            (
+                MAX_CONNECTIONS_KEY,
+                Some(self.get_max_connections().to_string()),
+                self.get_source(MAX_CONNECTIONS_KEY),
+            ),
+            (
+                PEER_EXPIRY_KEY,
+                Some(self.get_peer_expiry().as_secs().to_string()),
+                self.get_source(PEER_EXPIRY_KEY),
+            ),
+            (
+                BAN_THRESHOLD_KEY,
+                Some(self.get_ban_threshold().to_string()),
+                self.get_source(BAN_THRESHOLD_KEY),
+            ),
+            (
+                BAN_DURATION_KEY,
+                Some(self.get_ban_duration().as_secs().to_string()),
+                self.get_source(BAN_DURATION_KEY),
+            ),
+            (
+                WAIT_FOR_SYNC_KEY,
+                Some(self.get_wait_for_sync().to_string()),
+                self.get_source(WAIT_FOR_SYNC_KEY),
+            ),
+            (
+                RPC_BIND_KEY,
+                self.get_rpc_bind(),
+                self.get_source(RPC_BIND_KEY),
+            ),
+            (
+                RPC_AUTH_KEY,
+                self.get_rpc_auth(),
+                self.get_source(RPC_AUTH_KEY),
+            ),
+            (
+                REST_BIND_KEY,
+                self.get_rest_bind(),
+                self.get_source(REST_BIND_KEY),
+            ),
+            (
+                REST_CORS_ORIGIN_KEY,
+                self.get_rest_cors_origin(),
+                self.get_source(REST_CORS_ORIGIN_KEY),
+            ),
+            (
+                GRPC_BIND_KEY,
+                self.get_grpc_bind(),
+                self.get_source(GRPC_BIND_KEY),
+            ),
+            (
+                NOTIFY_BIND_KEY,
+                self.get_notify_bind(),
+                self.get_source(NOTIFY_BIND_KEY),
+            ),
+            (
+                SIGNER_COMMAND_KEY,
+                self.get_signer_command(),
+                self.get_source(SIGNER_COMMAND_KEY),
+            ),
+            (
+                SIGNER_URL_KEY,
+                self.get_signer_url(),
+                self.get_source(SIGNER_URL_KEY),
+            ),
+            (
+                DETERMINISTIC_SIGNING_KEY,
+                Some(self.get_deterministic_signing().to_string()),
+                self.get_source(DETERMINISTIC_SIGNING_KEY),
+            ),
+            (
+                "WEBHOOKS",
+                Some(webhook_urls.join(",")).filter(|urls| !urls.is_empty()),
+                if webhook_urls.is_empty() {
+                    Source::Default
+                } else {
+                    Source::File
+                },
+            ),
+            (
+                DATA_DIR_KEY,
+                Some(path_to_string(self.get_data_dir())),
+                self.get_source(DATA_DIR_KEY),
+            ),
+            (
+                LOG_LEVEL_KEY,
+                Some(self.get_log_level()),
+                self.get_source(LOG_LEVEL_KEY),
+            ),
+            (
+                LOG_FILE_KEY,
+                self.get_log_file().map(path_to_string),
+                self.get_source(LOG_FILE_KEY),
+            ),
+            (
+                LOG_FORMAT_KEY,
+                Some(self.get_log_format().to_string()),
+                self.get_source(LOG_FORMAT_KEY),
+            ),
+            (
+                WALLET_PATH_KEY,
+                Some(path_to_string(self.get_wallet_path())),
+                wallet_source,
+            ),
+            (
+                WALLET_BACKUP_DIR_KEY,
+                Some(path_to_string(self.get_wallet_backup_dir())),
+                self.get_source(WALLET_BACKUP_DIR_KEY),
+            ),
+        ];
+        let snapshot = settings
+            .into_iter()
+            .map(|(key, value, source)| {
+                let value = value.map(|value| {
+                    if is_secret(key) {
+                        String::from(REDACTED)
+                    } else {
+                        value
+                    }
+                });
+                (key.to_ascii_lowercase(), SettingSnapshot { value, source })
+            })
+            .collect();
+        ConfigSnapshot(snapshot)
+    }
+
+    /// Checks every setting, returning all problems found rather than stopping at the first.
+    ///
+    /// Must be called before any subsystem starts so that bad settings are reported
+    /// up front instead of surfacing as panics deep inside the node.
+    #[allow(clippy::too_many_lines)]
+    pub fn validate(&self) -> Result<(), Vec<ConfigError>> {
+        let mut errors = self.load_errors.read().unwrap().clone();
+        let (
+            network_setting,
+            log_format_setting,
+            mining_threads_setting,
+            max_connections_setting,
+            peer_expiry_setting,
+            bool_settings,
+            mempool_settings,
+            size_settings,
+            ban_settings,
+            mining_settings,
+        ) = {
+            let inner = self.settings.read().unwrap();
+            let bool_settings: Vec<_> = [LISTEN_KEY, WAIT_FOR_SYNC_KEY, DETERMINISTIC_SIGNING_KEY]
+                .into_iter()
+                .filter_map(|key| Some((key, inner.get(key)?.clone())))
+                .collect();
+            let mempool_settings: Vec<_> = [
+                MEMPOOL_MAX_TXS_KEY,
+                MEMPOOL_MAX_BYTES_KEY,
+                MEMPOOL_EXPIRY_KEY,
+            ]
+            .into_iter()
+            .filter_map(|key| Some((key, inner.get(key)?.clone())))
+            .collect();
+            let size_settings: Vec<_> = [
+                MAX_PACKAGE_BYTES_KEY,
+                MAX_BLOCK_BYTES_KEY,
+                MAX_BLOCK_TXS_KEY,
+                MAX_TX_BYTES_KEY,
+            ]
+            .into_iter()
+            .filter_map(|key| Some((key, inner.get(key)?.clone())))
+            .collect();
+            let ban_settings: Vec<_> = [BAN_THRESHOLD_KEY, BAN_DURATION_KEY]
+                .into_iter()
+                .filter_map(|key| Some((key, inner.get(key)?.clone())))
+                .collect();
+            let mining_settings: Vec<_> = [TRANSACTION_THRESHOLD_KEY, MINING_INTERVAL_KEY]
+                .into_iter()
+                .filter_map(|key| Some((key, inner.get(key)?.clone())))
+                .collect();
+            (
+                inner.get(NETWORK_KEY).cloned(),
+                inner.get(LOG_FORMAT_KEY).cloned(),
+                inner.get(MINING_THREADS_KEY).cloned(),
+                inner.get(MAX_CONNECTIONS_KEY).cloned(),
+                inner.get(PEER_EXPIRY_KEY).cloned(),
+                bool_settings,
+                mempool_settings,
+                size_settings,
+                ban_settings,
+                mining_settings,
+            )
+        };
+        for (key, value) in bool_settings {
+            if value.parse::<bool>().is_err() {
+                errors.push(ConfigError::InvalidBool { key, value });
+            }
+        }
+        for (key, value) in mempool_settings {
+            if value.parse::<u64>().map_or(true, |limit| limit == 0) {
+                errors.push(ConfigError::InvalidMempoolLimit { key, value });
+            }
+        }
+        let size_valid = size_settings
+            .iter()
+            .all(|(_, value)| value.parse::<usize>().is_ok_and(|limit| limit > 0));
+        for (key, value) in size_settings {
+            if value.parse::<usize>().map_or(true, |limit| limit == 0) {
+                errors.push(ConfigError::InvalidSizeLimit { key, value });
+            }
+        }
+        let limits = self.get_size_limits();
+        if size_valid && limits.max_block_bytes > limits.max_package_bytes {
+            errors.push(ConfigError::BlockExceedsPackage {
+                max_block_bytes: limits.max_block_bytes,
+                max_package_bytes: limits.max_package_bytes,
+            });
+        }
+        if let Some(network) = network_setting {
+            if network.parse::<Network>().is_err() {
+                errors.push(ConfigError::UnknownNetwork(network));
+            }
+        }
+        if let Some(threads) = mining_threads_setting {
+            if threads
+                .parse::<usize>()
+                .map_or(true, |threads| threads == 0)
+            {
+                errors.push(ConfigError::InvalidMiningThreads(threads));
+            }
+        }
+        if let Some(count) = max_connections_setting {
+            if count.parse::<usize>().map_or(true, |count| count == 0) {
+                errors.push(ConfigError::InvalidMaxConnections(count));
+            }
+        }
+        for (key, value) in ban_settings {
+            let valid = if key == BAN_THRESHOLD_KEY {
+                value.parse::<u32>().is_ok_and(|score| score > 0)
+            } else {
+                value.parse::<u64>().is_ok_and(|secs| secs > 0)
+            };
+            if !valid {
+                errors.push(ConfigError::InvalidBanSetting { key, value });
+            }
+        }
+        for (key, value) in mining_settings {
+            if value.parse::<u64>().map_or(true, |value| value == 0) {
+                errors.push(ConfigError::InvalidMiningSetting { key, value });
+            }
+        }
+        if let Some(secs) = peer_expiry_setting {
+            if secs.parse::<u64>().map_or(true, |secs| secs == 0) {
+                errors.push(ConfigError::InvalidPeerExpiry(secs));
+            }
+        }
+        if let Some(format) = log_format_setting {
+            if format.parse::<LogFormat>().is_err() {
+                errors.push(ConfigError::UnknownLogFormat(format));
+            }
+        }
+        let log_level = self.get_log_level();
+        if !is_valid_log_filter(log_level.as_str()) {
+            errors.push(ConfigError::InvalidLogLevel(log_level));
+        }
+        let node_addr = self.get_node_addr();
+        if !is_valid_addr(node_addr.as_str()) {
+            errors.push(ConfigError::InvalidNodeAddress(node_addr));
+        }
+        if let Some(addr) = self.get_rpc_bind() {
+            if addr.parse::<SocketAddr>().is_err() {
+                errors.push(ConfigError::InvalidRpcAddress(addr));
+            }
+        }
+        if let Some(addr) = self.get_rest_bind() {
+            if addr.parse::<SocketAddr>().is_err() {
+                errors.push(ConfigError::InvalidRestAddress(addr));
+            }
+        }
+        if let Some(addr) = self.get_grpc_bind() {
+            if addr.parse::<SocketAddr>().is_err() {
+                errors.push(ConfigError::InvalidGrpcAddress(addr));
+            }
+        }
+        if let Some(addr) = self.get_notify_bind() {
+            if addr.parse::<SocketAddr>().is_err() {
+                errors.push(ConfigError::InvalidNotifyAddress(addr));
+            }
+        }
+        if let Some(url) = self.get_signer_url() {
+            if !url.starts_with("http://") {
+                errors.push(ConfigError::InvalidSignerUrl(url));
+            }
+        }
+        let network = self.get_network();
+        for webhook in self.get_webhooks() {
+            if let Some(reason) = webhook_problem(&webhook, network) {
+                errors.push(ConfigError::InvalidWebhook {
+                    url: webhook.url,
+                    reason,
+                });
+            }
+        }
+        if self.get_deterministic_signing() && network == Network::Main {
+            errors.push(ConfigError::DeterministicSigningOnMain);
+        }
+        let serves_rpc = self.get_rpc_bind().is_some() || self.get_grpc_bind().is_some();
+        if serves_rpc && self.get_rpc_auth().is_none() {
+            errors.push(ConfigError::MissingRpcAuth);
+        }
+        for peer in self.get_peers() {
+            if !is_valid_addr(peer.as_str()) {
+                errors.push(ConfigError::InvalidPeerAddress(peer));
+            }
+        }
+        if let Some(address) = self.get_mining_addr() {
+            if address_pub_key_hash_for(address.as_str(), network).is_none() {
+                errors.push(ConfigError::InvalidMiningAddress { address, network });
+            }
+        }
+        let data_dir = self.get_data_dir();
+        if let Err(e) = check_writable(data_dir.as_path()) {
+            errors.push(ConfigError::DataDirNotWritable {
+                path: data_dir,
+                reason: e.to_string(),
+            });
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
 }
 
 impl Default for Config {
@@ -46,3 +1697,428 @@ impl Default for Config {
         Self::new()
     }
 }
+
+/// Explains what is wrong with `webhook` on `network`, if anything.
+fn webhook_problem(webhook: &Webhook, network: Network) -> Option<String> {
+    if !webhook.url.starts_with("http://") {
+        return Some(String::from("only http:// URLs are supported"));
+    }
+    if webhook.addresses.is_empty() {
+        return Some(String::from("it watches no addresses"));
+    }
+    webhook
+        .addresses
+        .iter()
+        .find(|address| address_pub_key_hash_for(address, network).is_none())
+        .map(|address| format!("`{address}` is not a valid address"))
+}
+
+/// Parses the setting `key` of `settings`, `None` when it is unset or malformed.
+fn parse_setting<T: FromStr>(settings: &HashMap<String, String>, key: &str) -> Option<T> {
+    settings.get(key)?.parse().ok()
+}
+
+/// Splits a comma separated list of peers, dropping empty entries.
+fn split_peers(peers: &str) -> Vec<String> {
+    peers
+        .split(',')
+        .map(str::trim)
+        .filter(|peer| !peer.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// Checks whether the value of the setting `key` must never be shown.
+fn is_secret(key: &str) -> bool {
+    ["PASSPHRASE", "PASSWORD", "TOKEN", "AUTH"]
+        .iter()
+        .any(|secret| key.contains(secret))
+}
+
+/// Checks that every directive of a `target=level` style filter names a known level.
+fn is_valid_log_filter(filter: &str) -> bool {
+    filter.split(',').all(|directive| {
+        let level = directive.rsplit('=').next().unwrap_or_default();
+        level.parse::<log::LevelFilter>().is_ok()
+            || (!directive.contains('=') && !directive.is_empty())
+    })
+}
+
+/// Creates `dir` if needed and probes that files can be written inside it.
+fn check_writable(dir: &Path) -> std::io::Result<()> {
+    fs::create_dir_all(dir)?;
+    let probe = dir.join(".write-test");
+    fs::write(probe.as_path(), [])?;
+    fs::remove_file(probe)
+}
+
+/// Returns the path of the config file, taken from `HIMALIA_CONFIG` when set.
+fn config_file_path() -> PathBuf {
+    env::var(CONFIG_FILE_ENV).map_or_else(
+        |_| current_dir().unwrap().join(DEFAULT_CONFIG_FILE),
+        PathBuf::from,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+    use crate::transactions::Transaction;
+    use crate::wallet::convert_address_for;
+
+    /// A directory under the system temporary directory, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let name = format!("himalia-config-{}", crate::random_u64());
+            let dir = env::temp_dir().join(name);
+            fs::create_dir_all(dir.as_path()).unwrap();
+            Self(dir)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(self.0.as_path());
+        }
+    }
+
+    /// Loads a [Config] from `file`, written to the config file in `dir`, and from
+    /// `env`, keeping the data directory in `dir` unless `env` moves it.
+    fn load(dir: &TempDir, file: &str, env: &[(&str, &str)]) -> Config {
+        fs::write(dir.0.join(DEFAULT_CONFIG_FILE), file).unwrap();
+        open(dir, env)
+    }
+
+    /// Like [`load`] with the config file already in `dir`.
+    fn open(dir: &TempDir, env: &[(&str, &str)]) -> Config {
+        let path = dir.0.join(DEFAULT_CONFIG_FILE);
+        let mut env: HashMap<&str, String> = env
+            .iter()
+            .map(|(key, value)| (*key, String::from(*value)))
+            .collect();
+        let data_dir = dir.0.join(DEFAULT_DATA_DIR);
+        env.entry(DATA_DIR_ENV)
+            .or_insert_with(|| data_dir.display().to_string());
+        Config::load(path, |key| env.get(key).cloned())
+    }
+
+    /// Builds the [`ConfigError`] reporting a setting of the given value.
+    type ErrorFor = fn(String) -> ConfigError;
+
+    fn problems(config: &Config) -> Vec<ConfigError> {
+        config.validate().err().unwrap_or_default()
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        let dir = TempDir::new();
+        assert_eq!(problems(&load(&dir, "", &[])), vec![]);
+    }
+
+    #[test]
+    fn node_and_peer_addresses_must_be_a_host_and_a_port() {
+        let dir = TempDir::new();
+        let config = load(
+            &dir,
+            "",
+            &[
+                (NODE_ADDRESS_KEY, "seed.example.org:2001"),
+                (PEERS_KEY, "127.0.0.1:2002, [::1]:2003"),
+            ],
+        );
+        assert_eq!(problems(&config), vec![]);
+        assert_eq!(config.get_node_addr(), "seed.example.org:2001");
+
+        let config = load(
+            &dir,
+            "",
+            &[
+                (NODE_ADDRESS_KEY, "127.0.0.1"),
+                (PEERS_KEY, "127.0.0.1:2002,bad host:1,:2001"),
+            ],
+        );
+        assert_eq!(
+            problems(&config),
+            vec![
+                ConfigError::InvalidNodeAddress(String::from("127.0.0.1")),
+                ConfigError::InvalidPeerAddress(String::from("bad host:1")),
+                ConfigError::InvalidPeerAddress(String::from(":2001")),
+            ]
+        );
+    }
+
+    #[test]
+    fn mining_address_must_belong_to_the_selected_network() {
+        let dir = TempDir::new();
+        let address = convert_address_for(&[7; 20], Network::Regtest);
+        let file = format!("mining_address = \"{address}\"");
+        let config = load(&dir, file.as_str(), &[(NETWORK_KEY, "regtest")]);
+        assert_eq!(problems(&config), vec![]);
+
+        let config = load(&dir, file.as_str(), &[(NETWORK_KEY, "main")]);
+        assert_eq!(
+            problems(&config),
+            vec![ConfigError::InvalidMiningAddress {
+                address,
+                network: Network::Main
+            }]
+        );
+        let config = load(
+            &dir,
+            "mining_address = \"nope\"",
+            &[(NETWORK_KEY, "regtest")],
+        );
+        assert_eq!(
+            problems(&config),
+            vec![ConfigError::InvalidMiningAddress {
+                address: String::from("nope"),
+                network: Network::Regtest
+            }]
+        );
+    }
+
+    #[test]
+    fn numeric_settings_must_be_positive() {
+        let dir = TempDir::new();
+        let rules: [(&str, ErrorFor); 7] = [
+            (MINING_THREADS_KEY, ConfigError::InvalidMiningThreads),
+            (MAX_CONNECTIONS_KEY, ConfigError::InvalidMaxConnections),
+            (PEER_EXPIRY_KEY, ConfigError::InvalidPeerExpiry),
+            (BAN_THRESHOLD_KEY, |value| ConfigError::InvalidBanSetting {
+                key: BAN_THRESHOLD_KEY,
+                value,
+            }),
+            (TRANSACTION_THRESHOLD_KEY, |value| {
+                ConfigError::InvalidMiningSetting {
+                    key: TRANSACTION_THRESHOLD_KEY,
+                    value,
+                }
+            }),
+            (MEMPOOL_MAX_BYTES_KEY, |value| {
+                ConfigError::InvalidMempoolLimit {
+                    key: MEMPOOL_MAX_BYTES_KEY,
+                    value,
+                }
+            }),
+            (MAX_TX_BYTES_KEY, |value| ConfigError::InvalidSizeLimit {
+                key: MAX_TX_BYTES_KEY,
+                value,
+            }),
+        ];
+        for (key, error) in rules {
+            for refused in ["0", "-1", "ten"] {
+                let config = load(&dir, "", &[(key, refused)]);
+                let expected = error(String::from(refused));
+                assert_eq!(problems(&config), vec![expected], "{key} = {refused}");
+            }
+            let config = load(&dir, "", &[(key, "1")]);
+            assert_eq!(problems(&config), vec![], "{key} = 1");
+        }
+    }
+
+    #[test]
+    fn largest_block_must_fit_in_the_largest_package() {
+        let dir = TempDir::new();
+        let file = "max_block_bytes = 2000\nmax_package_bytes = 1000";
+        assert_eq!(
+            problems(&load(&dir, file, &[])),
+            vec![ConfigError::BlockExceedsPackage {
+                max_block_bytes: 2000,
+                max_package_bytes: 1000
+            }]
+        );
+        let file = "max_block_bytes = 1000\nmax_package_bytes = 1000";
+        assert_eq!(problems(&load(&dir, file, &[])), vec![]);
+    }
+
+    #[test]
+    fn data_dir_must_be_writable() {
+        let dir = TempDir::new();
+        let file = dir.0.join("not-a-dir");
+        fs::write(file.as_path(), "").unwrap();
+        let data_dir = file.display().to_string();
+        let config = load(&dir, "", &[(DATA_DIR_ENV, data_dir.as_str())]);
+        let problems = problems(&config);
+        assert!(
+            matches!(
+                problems.as_slice(),
+                [ConfigError::DataDirNotWritable { path, .. }] if path.starts_with(file.as_path())
+            ),
+            "{problems:?}"
+        );
+    }
+
+    #[test]
+    fn every_problem_is_reported_at_once() {
+        let dir = TempDir::new();
+        let config = load(
+            &dir,
+            "log_format = \"xml\"",
+            &[(NETWORK_KEY, "moon"), (MINING_THREADS_KEY, "0")],
+        );
+        assert_eq!(
+            problems(&config),
+            vec![
+                ConfigError::UnknownNetwork(String::from("moon")),
+                ConfigError::InvalidMiningThreads(String::from("0")),
+                ConfigError::UnknownLogFormat(String::from("xml")),
+            ]
+        );
+
+        let config = load(&dir, "network = [", &[]);
+        assert!(matches!(
+            problems(&config).as_slice(),
+            [ConfigError::InvalidFile { .. }]
+        ));
+    }
+
+    #[test]
+    fn mining_address_set_at_runtime_survives_a_reload() {
+        let dir = TempDir::new();
+        let address = convert_address_for(&[7; 20], Network::Regtest);
+        let config = load(&dir, "network = \"regtest\"", &[]);
+        config.set_mining_addr(address.clone());
+        config.persist().unwrap();
+
+        let reloaded = open(&dir, &[]);
+        assert_eq!(reloaded.get_mining_addr(), Some(address));
+        assert_eq!(reloaded.get_source(MINING_ADDRESS_KEY), Source::File);
+        assert_eq!(reloaded.get_network(), Network::Regtest);
+    }
+
+    #[test]
+    fn only_runtime_changes_mark_the_config_dirty() {
+        let dir = TempDir::new();
+        let config = load(&dir, "mining_threads = 2", &[(MAX_CONNECTIONS_KEY, "3")]);
+        assert!(!config.is_dirty());
+        config.override_wallet_path(String::from("other.dat"));
+        assert!(!config.is_dirty());
+        config.set_mining_threads(4);
+        assert!(config.is_dirty());
+        config.persist().unwrap();
+        assert!(!config.is_dirty());
+    }
+
+    #[test]
+    fn environment_overrides_and_secrets_are_not_persisted() {
+        let dir = TempDir::new();
+        let config = load(
+            &dir,
+            "node_address = \"127.0.0.1:2001\"",
+            &[
+                (NODE_ADDRESS_KEY, "127.0.0.1:3001"),
+                (MINING_THREADS_KEY, "3"),
+                (RPC_AUTH_KEY, "hunter2"),
+            ],
+        );
+        config.set_max_connections(5);
+        config.persist().unwrap();
+
+        let contents = fs::read_to_string(config.get_path()).unwrap();
+        assert!(!contents.contains("3001"), "{contents}");
+        assert!(!contents.contains("hunter2"), "{contents}");
+        let reloaded = open(&dir, &[]);
+        assert_eq!(reloaded.get_node_addr(), "127.0.0.1:2001");
+        assert_eq!(reloaded.get_source(MINING_THREADS_KEY), Source::Default);
+        assert_eq!(reloaded.get_max_connections(), 5);
+        assert_eq!(reloaded.get_rpc_auth(), None);
+    }
+
+    #[test]
+    fn coinbase_pays_the_subsidy_of_its_params() {
+        crate::test_utils::regtest();
+        let address = crate::wallet::Wallet::new().get_address();
+        let richer = ChainParams {
+            subsidy: 25,
+            ..REGTEST_PARAMS
+        };
+        for (params, value) in [(&REGTEST_PARAMS, 12), (&richer, 27)] {
+            let coinbase = Transaction::new_coinbase_tx(address.as_str(), params, 2).unwrap();
+            assert_eq!(coinbase.get_output_value(), Ok(value));
+        }
+    }
+
+    #[test]
+    fn each_network_starts_from_its_own_genesis() {
+        let hashes: HashSet<BlockHash> = Network::ALL
+            .into_iter()
+            .map(Network::genesis_pre_block_hash)
+            .collect();
+        assert_eq!(hashes.len(), Network::ALL.len());
+        assert_ne!(MAIN_PARAMS, TEST_PARAMS);
+        assert_eq!(REGTEST_PARAMS.coinbase_maturity, 0);
+        assert_eq!(REGTEST_PARAMS.retarget_interval, 0);
+    }
+
+    #[test]
+    fn wallet_flag_beats_the_configured_path_which_beats_the_default() {
+        let dir = TempDir::new();
+        let data_dir = dir.0.join(DEFAULT_DATA_DIR).join("regtest");
+        let config = load(&dir, "network = \"regtest\"\n", &[]);
+        assert_eq!(config.get_wallet_path(), data_dir.join(DEFAULT_WALLET_FILE));
+
+        let config = load(
+            &dir,
+            "network = \"regtest\"\nwallet_path = \"mine.dat\"\n",
+            &[],
+        );
+        assert_eq!(config.get_wallet_path(), data_dir.join("mine.dat"));
+        assert!(!config.has_wallet_override());
+
+        config.override_wallet_path(String::from("other.dat"));
+        assert_eq!(config.get_wallet_path(), data_dir.join("other.dat"));
+        assert!(config.has_wallet_override());
+        assert!(!config.is_dirty());
+    }
+
+    #[test]
+    fn snapshot_records_where_each_setting_came_from_and_hides_secrets() {
+        let dir = TempDir::new();
+        let config = load(
+            &dir,
+            "network = \"regtest\"\nmining_threads = 3\n",
+            &[(MAX_CONNECTIONS_KEY, "5"), (RPC_AUTH_KEY, "hunter2")],
+        );
+        let miner = convert_address_for(&[7; 20], Network::Regtest);
+        config.set_mining_addr(miner.clone());
+        config.override_wallet_path(String::from("other.dat"));
+
+        let snapshot = config.snapshot();
+        let setting = |key: &str| {
+            let setting = &snapshot.0[key];
+            (setting.value.as_deref(), setting.source)
+        };
+        assert_eq!(setting("max_block_txs").1, Source::Default);
+        assert_eq!(setting("mining_threads"), (Some("3"), Source::File));
+        assert_eq!(setting("max_connections"), (Some("5"), Source::Env));
+        assert_eq!(
+            setting("mining_address"),
+            (Some(miner.as_str()), Source::Flag)
+        );
+        assert_eq!(setting("wallet_path").1, Source::Flag);
+        assert_eq!(setting("rpc_auth"), (Some(REDACTED), Source::Env));
+        assert_eq!(setting("signer_url"), (None, Source::Default));
+
+        let json = serde_json::to_string(&snapshot).unwrap();
+        assert!(!json.contains("hunter2"));
+    }
+
+    #[test]
+    fn secrets_are_recognized_by_name() {
+        for key in [
+            RPC_AUTH_KEY,
+            "WALLET_PASSPHRASE",
+            "SIGNER_TOKEN",
+            "DB_PASSWORD",
+        ] {
+            assert!(is_secret(key), "{key}");
+        }
+        for key in [RPC_BIND_KEY, SIGNER_URL_KEY, MINING_ADDRESS_KEY] {
+            assert!(!is_secret(key), "{key}");
+        }
+    }
+}
@@ -0,0 +1,104 @@
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Connections to peers kept open between the packages sent to them, so that a sync
+/// doesn't connect once per package.
+///
+/// A connection unused for longer than the idle timeout is closed, which must stay
+/// below the read timeout of the peer for it not to close the connection first.
+pub struct ConnectionPool {
+    /// The connection to each peer address, and when it was last written to.
+    streams: Mutex<HashMap<String, (TcpStream, Instant)>>,
+    idle_timeout: Duration,
+    write_timeout: Duration,
+    /// The number of connections opened so far.
+    opened: AtomicUsize,
+}
+
+impl ConnectionPool {
+    pub fn new(idle_timeout: Duration, write_timeout: Duration) -> Self {
+        Self {
+            streams: Mutex::new(HashMap::new()),
+            idle_timeout,
+            write_timeout,
+            opened: AtomicUsize::new(0),
+        }
+    }
+
+    /// Runs `write` on the open connection to `addr`, or on a new one to the first of
+    /// `socket_addrs` accepting it when there's none, or writing to it fails.
+    pub fn send<F>(&self, addr: &str, socket_addrs: &[SocketAddr], mut write: F) -> io::Result<()>
+    where
+        F: FnMut(&mut TcpStream) -> io::Result<()>,
+    {
+        if let Some(mut stream) = self.take(addr) {
+            if write(&mut stream).is_ok() {
+                self.put(addr, stream);
+                return Ok(());
+            }
+        }
+        let mut stream = TcpStream::connect(socket_addrs)?;
+        stream.set_write_timeout(Some(self.write_timeout))?;
+        self.opened.fetch_add(1, Ordering::Relaxed);
+        write(&mut stream)?;
+        self.put(addr, stream);
+        Ok(())
+    }
+
+    /// Closes the connections unused for longer than the idle timeout, returning how
+    /// many were closed.
+    pub fn expire_idle(&self) -> usize {
+        let mut streams = self.streams.lock().unwrap();
+        let before = streams.len();
+        streams.retain(|_, (_, last_used)| last_used.elapsed() <= self.idle_timeout);
+        let expired = before - streams.len();
+        drop(streams);
+        expired
+    }
+
+    /// Closes the connection to `addr`, if there is one.
+    pub fn close(&self, addr: &str) {
+        self.streams.lock().unwrap().remove(addr);
+    }
+
+    pub fn len(&self) -> usize {
+        self.streams.lock().unwrap().len()
+    }
+
+    /// Returns the number of connections opened since the [`ConnectionPool`] was created.
+    pub fn get_opened(&self) -> usize {
+        self.opened.load(Ordering::Relaxed)
+    }
+
+    /// Removes the connection to `addr` for a write, unless it has been idle too long
+    /// or the peer closed it.
+    fn take(&self, addr: &str) -> Option<TcpStream> {
+        let (stream, last_used) = self.streams.lock().unwrap().remove(addr)?;
+        (last_used.elapsed() <= self.idle_timeout && !is_closed(&stream)).then_some(stream)
+    }
+
+    /// Keeps `stream` as the connection to `addr`, closing the one it replaces.
+    fn put(&self, addr: &str, stream: TcpStream) {
+        self.streams
+            .lock()
+            .unwrap()
+            .insert(String::from(addr), (stream, Instant::now()));
+    }
+}
+
+/// Checks whether the peer closed `stream`. Peers never write to the connections
+/// packages are sent on, so anything to read is the end of the stream or an error.
+fn is_closed(stream: &TcpStream) -> bool {
+    if stream.set_nonblocking(true).is_err() {
+        return true;
+    }
+    let closed = match stream.peek(&mut [0]) {
+        Err(e) => e.kind() != ErrorKind::WouldBlock,
+        Ok(_) => true,
+    };
+    closed || stream.set_nonblocking(false).is_err()
+}
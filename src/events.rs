@@ -0,0 +1,134 @@
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Mutex;
+
+use serde::Serialize;
+
+use crate::wallet::{convert_address, hash_pub_key};
+use crate::{block::Block, transactions::Transaction};
+
+/// How many [Event]s may wait for a subscriber before it is dropped as too slow.
+pub const SUBSCRIBER_CAPACITY: usize = 256;
+
+/// What happened to a [Transaction] an [`Event::Tx`] describes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TxEventStatus {
+    /// Added to the mempool.
+    Pending,
+    /// Included in a [Block] added to the chain.
+    Confirmed,
+    /// Dropped from the mempool for a confirmed [Transaction] spending the same output.
+    Replaced,
+    /// Dropped from the mempool to make room for others or for being pending too long.
+    Evicted,
+}
+
+/// A change to the chain or the mempool, serialized as e.g.
+/// `{"type":"block","hash":...,"height":...}`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Event {
+    Block {
+        hash: String,
+        height: usize,
+        /// Every address paid or spent from by the [Block]'s [Transaction]s.
+        #[serde(skip)]
+        addresses: Vec<String>,
+    },
+    Tx {
+        txid: String,
+        status: TxEventStatus,
+        #[serde(skip)]
+        addresses: Vec<String>,
+    },
+}
+
+impl Event {
+    pub fn block(block: &Block) -> Self {
+        let mut addresses: Vec<String> = block
+            .get_transactions()
+            .iter()
+            .flat_map(tx_addresses)
+            .collect();
+        addresses.sort();
+        addresses.dedup();
+        Self::Block {
+            hash: block.get_hash().to_string(),
+            height: block.get_height(),
+            addresses,
+        }
+    }
+
+    pub fn tx(tx: &Transaction, status: TxEventStatus) -> Self {
+        Self::Tx {
+            txid: tx.get_id().to_string(),
+            status,
+            addresses: tx_addresses(tx),
+        }
+    }
+
+    /// Checks whether the event involves any of `addresses`, or any address at all
+    /// when `addresses` is empty.
+    pub fn touches(&self, addresses: &[String]) -> bool {
+        let (Self::Block {
+            addresses: touched, ..
+        }
+        | Self::Tx {
+            addresses: touched, ..
+        }) = self;
+        addresses.is_empty() || addresses.iter().any(|address| touched.contains(address))
+    }
+}
+
+/// Every address paying into or receiving from `tx`.
+fn tx_addresses(tx: &Transaction) -> Vec<String> {
+    let mut addresses: Vec<String> = tx
+        .get_vout()
+        .iter()
+        .map(|out| convert_address(out.get_pub_key_hash()))
+        .collect();
+    if !tx.is_coinbase() {
+        for input in tx.get_vin() {
+            addresses.push(convert_address(
+                hash_pub_key(input.get_pub_key()).as_slice(),
+            ));
+        }
+    }
+    addresses.sort();
+    addresses.dedup();
+    addresses
+}
+
+/// Fans [Event]s out to subscribers without ever blocking the publisher: a
+/// subscriber whose queue is full or whose receiver is gone is dropped.
+#[derive(Default)]
+pub struct EventBus(Mutex<Vec<SyncSender<Event>>>);
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns a receiver for every [Event] published from now on.
+    pub fn subscribe(&self) -> Receiver<Event> {
+        let (sender, receiver) = mpsc::sync_channel(SUBSCRIBER_CAPACITY);
+        self.subscribe_with(sender);
+        receiver
+    }
+
+    /// Like [`EventBus::subscribe`], delivering to an existing channel so that one
+    /// receiver can follow several buses.
+    pub fn subscribe_with(&self, sender: SyncSender<Event>) {
+        self.0.lock().unwrap().push(sender);
+    }
+
+    pub fn publish(&self, event: &Event) {
+        self.0
+            .lock()
+            .unwrap()
+            .retain(|sender| match sender.try_send(event.clone()) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_) | TrySendError::Disconnected(_)) => false,
+            });
+    }
+}
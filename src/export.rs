@@ -0,0 +1,537 @@
+use std::collections::HashMap;
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, BufWriter, Write};
+use std::{fs::File, path::Path, str::FromStr};
+
+use serde::Serialize;
+
+use crate::transactions::{TXOutput, Transaction};
+use crate::types::Txid;
+use crate::{block::Block, blockchain::Blockchain, wallet::convert_address};
+
+/// The file formats [`export`] writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Comma separated values with a header row.
+    Csv,
+    /// One JSON object per line.
+    Jsonl,
+}
+
+impl Display for ExportFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Csv => f.write_str("csv"),
+            Self::Jsonl => f.write_str("jsonl"),
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "csv" => Ok(Self::Csv),
+            "jsonl" => Ok(Self::Jsonl),
+            _ => Err(format!(
+                "unknown export format `{s}`, expected csv or jsonl"
+            )),
+        }
+    }
+}
+
+/// What [`export`] writes one row for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportKind {
+    Blocks,
+    Transactions,
+    /// The outputs still unspent at the last exported height.
+    Utxos,
+}
+
+impl Display for ExportKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Blocks => f.write_str("blocks"),
+            Self::Transactions => f.write_str("transactions"),
+            Self::Utxos => f.write_str("utxos"),
+        }
+    }
+}
+
+impl FromStr for ExportKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "blocks" => Ok(Self::Blocks),
+            "transactions" => Ok(Self::Transactions),
+            "utxos" => Ok(Self::Utxos),
+            _ => Err(format!(
+                "unknown export kind `{s}`, expected blocks, transactions or utxos"
+            )),
+        }
+    }
+}
+
+/// How much an [`export`] wrote.
+#[derive(Debug, Clone, Serialize)]
+pub struct ExportSummary {
+    pub rows: usize,
+    /// The [Block]s read, including those below `from` read to resolve input values.
+    pub blocks: usize,
+}
+
+/// A record [`export`] can write as a CSV row or a JSON line.
+trait Row: Serialize {
+    const COLUMNS: &'static [&'static str];
+
+    /// The values in the order of [`Row::COLUMNS`].
+    fn values(&self) -> Vec<String>;
+}
+
+#[derive(Serialize)]
+struct BlockRow {
+    height: usize,
+    hash: String,
+    pre_block_hash: String,
+    timestamp: i64,
+    transaction_count: usize,
+    size: usize,
+    fees: u64,
+}
+
+impl Row for BlockRow {
+    const COLUMNS: &'static [&'static str] = &[
+        "height",
+        "hash",
+        "pre_block_hash",
+        "timestamp",
+        "transaction_count",
+        "size",
+        "fees",
+    ];
+
+    fn values(&self) -> Vec<String> {
+        vec![
+            self.height.to_string(),
+            self.hash.clone(),
+            self.pre_block_hash.clone(),
+            self.timestamp.to_string(),
+            self.transaction_count.to_string(),
+            self.size.to_string(),
+            self.fees.to_string(),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+struct TransactionRow {
+    txid: String,
+    block_height: usize,
+    is_coinbase: bool,
+    total_input: u64,
+    total_output: u64,
+    fee: u64,
+    input_count: usize,
+    output_count: usize,
+}
+
+impl Row for TransactionRow {
+    const COLUMNS: &'static [&'static str] = &[
+        "txid",
+        "block_height",
+        "is_coinbase",
+        "total_input",
+        "total_output",
+        "fee",
+        "input_count",
+        "output_count",
+    ];
+
+    fn values(&self) -> Vec<String> {
+        vec![
+            self.txid.clone(),
+            self.block_height.to_string(),
+            self.is_coinbase.to_string(),
+            self.total_input.to_string(),
+            self.total_output.to_string(),
+            self.fee.to_string(),
+            self.input_count.to_string(),
+            self.output_count.to_string(),
+        ]
+    }
+}
+
+#[derive(Serialize)]
+struct UtxoRow {
+    txid: String,
+    vout: usize,
+    block_height: usize,
+    address: String,
+    value: u64,
+}
+
+impl Row for UtxoRow {
+    const COLUMNS: &'static [&'static str] = &["txid", "vout", "block_height", "address", "value"];
+
+    fn values(&self) -> Vec<String> {
+        vec![
+            self.txid.clone(),
+            self.vout.to_string(),
+            self.block_height.to_string(),
+            self.address.clone(),
+            self.value.to_string(),
+        ]
+    }
+}
+
+/// Writes [Row]s to a file one at a time.
+struct RowWriter {
+    writer: BufWriter<File>,
+    format: ExportFormat,
+    rows: usize,
+}
+
+impl RowWriter {
+    /// Writes the CSV header, even when no row follows.
+    fn header(&mut self, columns: &[&str]) -> io::Result<()> {
+        if self.format == ExportFormat::Csv {
+            writeln!(self.writer, "{}", columns.join(","))?;
+        }
+        Ok(())
+    }
+
+    fn write<R: Row>(&mut self, row: &R) -> io::Result<()> {
+        match self.format {
+            ExportFormat::Csv => {
+                let values: Vec<String> = row.values().iter().map(|value| escape(value)).collect();
+                writeln!(self.writer, "{}", values.join(","))?;
+            }
+            ExportFormat::Jsonl => {
+                serde_json::to_writer(&mut self.writer, row)?;
+                writeln!(self.writer)?;
+            }
+        }
+        self.rows += 1;
+        Ok(())
+    }
+}
+
+/// Quotes a CSV value holding a separator, a quote or a line break.
+fn escape(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        String::from(value)
+    }
+}
+
+/// An output not yet spent at the height the walk has reached.
+struct Unspent {
+    height: usize,
+    output: TXOutput,
+}
+
+/// Writes one row per [Block], [Transaction] or unspent output between the heights
+/// `from` and `to` inclusive to the file at `path`.
+///
+/// The [Blockchain] is read one [Block] at a time from genesis, so that input values
+/// and fees can be resolved from the outputs still unspent, which are the only thing
+/// held in memory.
+pub fn export(
+    blockchain: &Blockchain,
+    path: &Path,
+    format: ExportFormat,
+    kind: ExportKind,
+    from: usize,
+    to: Option<usize>,
+) -> io::Result<ExportSummary> {
+    let mut writer = RowWriter {
+        writer: BufWriter::new(File::create(path)?),
+        format,
+        rows: 0,
+    };
+    writer.header(match kind {
+        ExportKind::Blocks => BlockRow::COLUMNS,
+        ExportKind::Transactions => TransactionRow::COLUMNS,
+        ExportKind::Utxos => UtxoRow::COLUMNS,
+    })?;
+    let mut unspent: HashMap<(Txid, usize), Unspent> = HashMap::new();
+    let mut blocks = 0;
+    let mut iterator = blockchain.forward_iterator(0);
+    while let Some(block) = iterator.next() {
+        let height = block.get_height();
+        if to.is_some_and(|to| height > to) {
+            break;
+        }
+        blocks += 1;
+        let mut fees = 0;
+        for tx in block.get_transactions() {
+            let row = connect(tx, height, &mut unspent);
+            fees += row.fee;
+            if kind == ExportKind::Transactions && height >= from {
+                writer.write(&row)?;
+            }
+        }
+        if kind == ExportKind::Blocks && height >= from {
+            writer.write(&block_row(&block, fees))?;
+        }
+    }
+    if kind == ExportKind::Utxos {
+        let mut utxos: Vec<_> = unspent
+            .into_iter()
+            .filter(|(_, unspent)| unspent.height >= from)
+            .collect();
+        utxos.sort_by(|(a, a_unspent), (b, b_unspent)| {
+            a_unspent.height.cmp(&b_unspent.height).then(a.cmp(b))
+        });
+        for ((txid, vout), unspent) in utxos {
+            writer.write(&UtxoRow {
+                txid: txid.to_string(),
+                vout,
+                block_height: unspent.height,
+                address: convert_address(unspent.output.get_pub_key_hash()),
+                value: unspent.output.get_value(),
+            })?;
+        }
+    }
+    writer.writer.flush()?;
+    Ok(ExportSummary {
+        rows: writer.rows,
+        blocks,
+    })
+}
+
+/// Spends the outputs `tx` consumes from `unspent` and adds the ones it creates,
+/// returning its row.
+fn connect(
+    tx: &Transaction,
+    height: usize,
+    unspent: &mut HashMap<(Txid, usize), Unspent>,
+) -> TransactionRow {
+    let mut total_input = 0;
+    if !tx.is_coinbase() {
+        for input in tx.get_vin() {
+            if let Some(spent) = unspent.remove(&(input.get_txid(), input.get_vout())) {
+                total_input += spent.output.get_value();
+            }
+        }
+    }
+    for (vout, output) in tx.get_vout().iter().enumerate() {
+        let unspent_output = Unspent {
+            height,
+            output: output.clone(),
+        };
+        unspent.insert((tx.get_id(), vout), unspent_output);
+    }
+    let total_output = tx.get_vout().iter().map(TXOutput::get_value).sum();
+    TransactionRow {
+        txid: tx.get_id().to_string(),
+        block_height: height,
+        is_coinbase: tx.is_coinbase(),
+        total_input,
+        total_output,
+        fee: if tx.is_coinbase() {
+            0
+        } else {
+            total_input.saturating_sub(total_output)
+        },
+        input_count: tx.get_vin().len(),
+        output_count: tx.get_vout().len(),
+    }
+}
+
+fn block_row(block: &Block, fees: u64) -> BlockRow {
+    BlockRow {
+        height: block.get_height(),
+        hash: block.get_hash().to_string(),
+        pre_block_hash: block.get_pre_block_hash().to_string(),
+        timestamp: block.get_timestamp(),
+        transaction_count: block.get_transactions().len(),
+        size: block.serialize().len(),
+        fees,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{mine, pay, temp_chain};
+    use crate::wallet::Wallet;
+    use serde_json::Value;
+    use std::path::PathBuf;
+
+    /// An export file of its own in the temporary directory, removed when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new() -> Self {
+            let name = format!("himalia-export-{}", crate::random_u64());
+            Self(std::env::temp_dir().join(name))
+        }
+
+        fn lines(&self) -> Vec<String> {
+            std::fs::read_to_string(&self.0)
+                .unwrap()
+                .lines()
+                .map(String::from)
+                .collect()
+        }
+
+        fn json_lines(&self) -> Vec<Value> {
+            self.lines()
+                .iter()
+                .map(|line| serde_json::from_str(line).unwrap())
+                .collect()
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(self.0.as_path());
+        }
+    }
+
+    /// A chain of 50 [Block]s, the one at height 10 paying 3 from `miner` to `payee`.
+    fn fifty_blocks(miner: &Wallet, payee: &Wallet) -> (Blockchain, Transaction) {
+        let blockchain = temp_chain(miner);
+        let mut payment = None;
+        for height in 1..50 {
+            let transactions = if height == 10 {
+                let tx = pay(&blockchain, miner, payee, 3);
+                payment = Some(tx.clone());
+                vec![tx]
+            } else {
+                vec![]
+            };
+            mine(&blockchain, transactions, miner);
+        }
+        (blockchain, payment.unwrap())
+    }
+
+    #[test]
+    fn every_kind_has_a_row_per_block_transaction_or_unspent_output() {
+        let (miner, payee) = (Wallet::new(), Wallet::new());
+        let (blockchain, payment) = fifty_blocks(&miner, &payee);
+        let file = TempFile::new();
+
+        let blocks = export(
+            &blockchain,
+            &file.0,
+            ExportFormat::Csv,
+            ExportKind::Blocks,
+            0,
+            None,
+        );
+        assert_eq!(blocks.unwrap().rows, 50);
+        let lines = file.lines();
+        assert_eq!(lines.len(), 51);
+        assert_eq!(lines[0], BlockRow::COLUMNS.join(","));
+        let block = blockchain.get_block_by_height(10).unwrap();
+        let columns: Vec<&str> = lines[11].split(',').collect();
+        assert_eq!(columns[0], "10");
+        assert_eq!(columns[1], block.get_hash().to_string());
+        assert_eq!(
+            columns[4..],
+            ["2", &block.serialize().len().to_string(), "1"]
+        );
+
+        let kind = ExportKind::Transactions;
+        let transactions = export(&blockchain, &file.0, ExportFormat::Jsonl, kind, 0, None);
+        assert_eq!(transactions.unwrap().rows, 51);
+        let rows = file.json_lines();
+        let row = rows
+            .iter()
+            .find(|row| row["txid"] == payment.get_id().to_string())
+            .unwrap();
+        assert_eq!(row["block_height"], 10);
+        assert_eq!(row["is_coinbase"], false);
+        assert_eq!(row["total_input"], 10);
+        assert_eq!(row["total_output"], 9);
+        assert_eq!(row["fee"], 1);
+        assert_eq!(
+            (row["input_count"].clone(), row["output_count"].clone()),
+            (1.into(), 2.into())
+        );
+        assert_eq!(
+            rows.iter().filter(|row| row["is_coinbase"] == true).count(),
+            50
+        );
+
+        let utxos = export(
+            &blockchain,
+            &file.0,
+            ExportFormat::Jsonl,
+            ExportKind::Utxos,
+            0,
+            None,
+        );
+        assert_eq!(utxos.unwrap().rows, 51);
+        let rows = file.json_lines();
+        let heights: Vec<u64> = rows
+            .iter()
+            .map(|row| row["block_height"].as_u64().unwrap())
+            .collect();
+        assert!(heights.is_sorted());
+        let paid = rows
+            .iter()
+            .find(|row| row["address"] == payee.get_address())
+            .unwrap();
+        assert_eq!(
+            (paid["block_height"].clone(), paid["value"].clone()),
+            (10.into(), 3.into())
+        );
+    }
+
+    #[test]
+    fn heights_outside_from_and_to_are_left_out() {
+        let (miner, payee) = (Wallet::new(), Wallet::new());
+        let (blockchain, _) = fifty_blocks(&miner, &payee);
+        let file = TempFile::new();
+
+        let blocks = export(
+            &blockchain,
+            &file.0,
+            ExportFormat::Jsonl,
+            ExportKind::Blocks,
+            10,
+            Some(19),
+        );
+        let summary = blocks.unwrap();
+        assert_eq!((summary.rows, summary.blocks), (10, 20));
+        let heights: Vec<Value> = file
+            .json_lines()
+            .iter()
+            .map(|row| row["height"].clone())
+            .collect();
+        assert_eq!(heights, (10..20).map(Value::from).collect::<Vec<_>>());
+        assert_eq!(file.json_lines()[0]["fees"], 1);
+
+        let kind = ExportKind::Transactions;
+        let transactions = export(&blockchain, &file.0, ExportFormat::Csv, kind, 45, None);
+        assert_eq!(transactions.unwrap().rows, 5);
+        assert_eq!(file.lines().len(), 6);
+
+        let utxos = export(
+            &blockchain,
+            &file.0,
+            ExportFormat::Csv,
+            ExportKind::Utxos,
+            0,
+            Some(9),
+        );
+        assert_eq!(utxos.unwrap().rows, 10);
+        let empty = export(
+            &blockchain,
+            &file.0,
+            ExportFormat::Csv,
+            ExportKind::Blocks,
+            60,
+            None,
+        );
+        assert_eq!(empty.unwrap().rows, 0);
+        assert_eq!(file.lines(), [BlockRow::COLUMNS.join(",")]);
+    }
+}
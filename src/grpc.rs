@@ -0,0 +1,479 @@
+// Every call fails with a `tonic::Status`, which is larger than clippy would like.
+#![allow(clippy::result_large_err)]
+
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::{error::Error, net::TcpListener, time::Duration};
+
+use log::info;
+use tokio_stream::wrappers::{ReceiverStream, TcpListenerStream};
+use tonic::{transport::Server, Code, Request, Response, Status};
+
+use crate::blockchain::{Blockchain, ChainStats};
+use crate::events::{Event, TxEventStatus, SUBSCRIBER_CAPACITY};
+use crate::rpc::{self, RpcError};
+use crate::server::{sync_status, SyncStatus, GLOBAL_MEMORY_POOL, GLOBAL_NODES};
+use crate::transactions::{TXOutput, Transaction};
+use crate::{block::Block, config::GLOBAL_CONFIG, constant_time_eq, utxo_set::UTXOSet};
+use crate::{wallet::address_pub_key_hash, wallet::convert_address};
+
+/// The types and service generated from `proto/himalia.proto`.
+#[allow(clippy::all, clippy::pedantic, clippy::nursery)]
+pub mod pb {
+    tonic::include_proto!("himalia");
+}
+
+/// How often a quiet subscription checks whether its client has gone away.
+const SUBSCRIPTION_POLL: u64 = 1000;
+
+/// Serves the gRPC `Node` service on `listener` until the process exits.
+pub fn serve(listener: TcpListener, blockchain: Blockchain) -> Result<(), Box<dyn Error>> {
+    listener.set_nonblocking(true)?;
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(async {
+        let incoming = TcpListenerStream::new(tokio::net::TcpListener::from_std(listener)?);
+        let service =
+            pb::node_server::NodeServer::with_interceptor(NodeService { blockchain }, authorize);
+        Server::builder()
+            .add_service(service)
+            .serve_with_incoming(incoming)
+            .await?;
+        Ok(())
+    })
+}
+
+/// Checks the bearer token every call must carry, the same one JSON-RPC takes.
+fn authorize(request: Request<()>) -> Result<Request<()>, Status> {
+    let token = GLOBAL_CONFIG.get_rpc_auth();
+    let authorization = request
+        .metadata()
+        .get("authorization")
+        .and_then(|value| value.to_str().ok());
+    let authorized = token.is_some_and(|token| {
+        authorization.is_some_and(|authorization| {
+            constant_time_eq(
+                authorization.as_bytes(),
+                format!("Bearer {token}").as_bytes(),
+            )
+        })
+    });
+    if !authorized {
+        return Err(Status::unauthenticated("missing or wrong bearer token"));
+    }
+    Ok(request)
+}
+
+impl From<RpcError> for Status {
+    fn from(error: RpcError) -> Self {
+        let code = match error.code {
+            rpc::INVALID_PARAMS => Code::InvalidArgument,
+            rpc::NOT_FOUND => Code::NotFound,
+            _ => Code::FailedPrecondition,
+        };
+        Self::new(code, error.message)
+    }
+}
+
+impl From<ChainStats> for pb::ChainStats {
+    fn from(stats: ChainStats) -> Self {
+        Self {
+            height: stats.height as u64,
+            tip_hash: stats.tip_hash.to_string(),
+            tip_timestamp: stats.tip_timestamp,
+        }
+    }
+}
+
+impl From<&Block> for pb::Block {
+    fn from(block: &Block) -> Self {
+        Self {
+            hash: block.get_hash().to_string(),
+            pre_block_hash: block.get_pre_block_hash().to_string(),
+            height: block.get_height() as u64,
+            timestamp: block.get_timestamp(),
+            nonce: block.get_nonce(),
+            transactions: block
+                .get_transactions()
+                .iter()
+                .map(pb::Transaction::from)
+                .collect(),
+        }
+    }
+}
+
+impl From<TxEventStatus> for pb::transaction_event::Status {
+    fn from(status: TxEventStatus) -> Self {
+        match status {
+            TxEventStatus::Pending => Self::Pending,
+            TxEventStatus::Confirmed => Self::Confirmed,
+            TxEventStatus::Replaced => Self::Replaced,
+            TxEventStatus::Evicted => Self::Evicted,
+        }
+    }
+}
+
+struct NodeService {
+    blockchain: Blockchain,
+}
+
+#[tonic::async_trait]
+impl pb::node_server::Node for NodeService {
+    type SubscribeBlocksStream = ReceiverStream<Result<pb::BlockEvent, Status>>;
+    type SubscribeTransactionsStream = ReceiverStream<Result<pb::TransactionEvent, Status>>;
+
+    async fn get_blockchain_info(
+        &self,
+        _request: Request<pb::GetBlockchainInfoRequest>,
+    ) -> Result<Response<pb::BlockchainInfo>, Status> {
+        let blockchain = self.blockchain.clone();
+        blocking(move || {
+            Ok(pb::BlockchainInfo {
+                network: GLOBAL_CONFIG.get_network().to_string(),
+                chain: Some(blockchain.get_stats().into()),
+                synced: sync_status() == SyncStatus::Synced,
+            })
+        })
+        .await
+    }
+
+    async fn get_block(
+        &self,
+        request: Request<pb::GetBlockRequest>,
+    ) -> Result<Response<pb::Block>, Status> {
+        let id = request
+            .into_inner()
+            .id
+            .ok_or_else(|| Status::invalid_argument("`hash` or `height` must be given"))?;
+        let blockchain = self.blockchain.clone();
+        blocking(move || {
+            let block = match id {
+                pb::get_block_request::Id::Hash(hash) => hash
+                    .parse()
+                    .ok()
+                    .and_then(|hash| blockchain.get_block(hash)),
+                pb::get_block_request::Id::Height(height) => usize::try_from(height)
+                    .ok()
+                    .and_then(|height| blockchain.get_block_by_height(height)),
+            };
+            block
+                .map(|block| pb::Block::from(&block))
+                .ok_or_else(|| Status::not_found("block not found"))
+        })
+        .await
+    }
+
+    async fn get_transaction(
+        &self,
+        request: Request<pb::GetTransactionRequest>,
+    ) -> Result<Response<pb::TransactionInfo>, Status> {
+        let txid = request.into_inner().txid;
+        let blockchain = self.blockchain.clone();
+        blocking(move || {
+            let (tx, block) = rpc::find_transaction(&blockchain, txid.as_str())?;
+            let confirmations = block.as_ref().map_or(0, |block| {
+                blockchain.get_best_height() + 1 - block.get_height()
+            });
+            Ok(pb::TransactionInfo {
+                transaction: Some(pb::Transaction::from(&tx)),
+                block_hash: block.map(|block| block.get_hash().to_string()),
+                confirmations: confirmations as u64,
+            })
+        })
+        .await
+    }
+
+    async fn get_balance(
+        &self,
+        request: Request<pb::GetBalanceRequest>,
+    ) -> Result<Response<pb::Balance>, Status> {
+        let address = request.into_inner().address;
+        let pub_key_hash = address_pub_key_hash(address.as_str())
+            .ok_or_else(|| Status::invalid_argument(format!("address `{address}` is not valid")))?;
+        let blockchain = self.blockchain.clone();
+        blocking(move || {
+            let utxo_set = UTXOSet::new(blockchain);
+            let confirmed = utxo_set
+                .find_utxo(pub_key_hash.as_slice())
+                .iter()
+                .map(TXOutput::get_value)
+                .sum();
+            Ok(pb::Balance { address, confirmed })
+        })
+        .await
+    }
+
+    async fn send_to_address(
+        &self,
+        request: Request<pb::SendToAddressRequest>,
+    ) -> Result<Response<pb::SendResponse>, Status> {
+        let request = request.into_inner();
+        let blockchain = self.blockchain.clone();
+        blocking(move || {
+            let txid = rpc::send_to(
+                &blockchain,
+                request.from.as_str(),
+                request.to.as_str(),
+                request.amount,
+                request.fee,
+            )?;
+            Ok(pb::SendResponse { txid })
+        })
+        .await
+    }
+
+    async fn send_raw_transaction(
+        &self,
+        request: Request<pb::SendRawTransactionRequest>,
+    ) -> Result<Response<pb::SendResponse>, Status> {
+        let tx = request
+            .into_inner()
+            .transaction
+            .map(Transaction::from)
+            .ok_or_else(|| Status::invalid_argument("`transaction` must be given"))?;
+        let blockchain = self.blockchain.clone();
+        blocking(move || {
+            let txid = rpc::send_raw(&blockchain, &tx)?;
+            Ok(pb::SendResponse { txid })
+        })
+        .await
+    }
+
+    async fn get_mempool_info(
+        &self,
+        _request: Request<pb::GetMempoolInfoRequest>,
+    ) -> Result<Response<pb::MempoolInfo>, Status> {
+        let blockchain = self.blockchain.clone();
+        blocking(move || {
+            let stats = GLOBAL_MEMORY_POOL.get_stats(&blockchain);
+            Ok(pb::MempoolInfo {
+                count: stats.count as u64,
+                bytes: stats.bytes as u64,
+                total_fees: stats.total_fees,
+            })
+        })
+        .await
+    }
+
+    async fn get_peer_info(
+        &self,
+        _request: Request<pb::GetPeerInfoRequest>,
+    ) -> Result<Response<pb::PeerInfo>, Status> {
+        let addresses = GLOBAL_NODES
+            .get_nodes()
+            .iter()
+            .map(crate::node::Node::get_addr)
+            .collect();
+        Ok(Response::new(pb::PeerInfo { addresses }))
+    }
+
+    async fn subscribe_blocks(
+        &self,
+        request: Request<pb::SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeBlocksStream>, Status> {
+        let addresses = subscription_addresses(request.into_inner())?;
+        let receiver = self.blockchain.subscribe();
+        Ok(Response::new(forward(
+            receiver,
+            addresses,
+            |event| match event {
+                Event::Block { hash, height, .. } => Some(pb::BlockEvent {
+                    hash,
+                    height: height as u64,
+                }),
+                Event::Tx { .. } => None,
+            },
+        )))
+    }
+
+    async fn subscribe_transactions(
+        &self,
+        request: Request<pb::SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeTransactionsStream>, Status> {
+        let addresses = subscription_addresses(request.into_inner())?;
+        let (sender, receiver) = mpsc::sync_channel(SUBSCRIBER_CAPACITY);
+        self.blockchain.subscribe_with(sender.clone());
+        GLOBAL_MEMORY_POOL.subscribe_with(sender);
+        Ok(Response::new(forward(
+            receiver,
+            addresses,
+            |event| match event {
+                Event::Tx { txid, status, .. } => Some(pb::TransactionEvent {
+                    txid,
+                    status: pb::transaction_event::Status::from(status).into(),
+                }),
+                Event::Block { .. } => None,
+            },
+        )))
+    }
+}
+
+/// Runs a call that reads the database or talks to peers off the async runtime.
+async fn blocking<T: Send + 'static>(
+    call: impl FnOnce() -> Result<T, Status> + Send + 'static,
+) -> Result<Response<T>, Status> {
+    tokio::task::spawn_blocking(call)
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?
+        .map(Response::new)
+}
+
+/// Validates the addresses a subscription is limited to, normalized the way
+/// [`Event::touches`] compares them.
+fn subscription_addresses(request: pb::SubscribeRequest) -> Result<Vec<String>, Status> {
+    request
+        .addresses
+        .into_iter()
+        .map(|address| {
+            address_pub_key_hash(address.as_str())
+                .map(|pub_key_hash| convert_address(pub_key_hash.as_slice()))
+                .ok_or_else(|| {
+                    Status::invalid_argument(format!("address `{address}` is not valid"))
+                })
+        })
+        .collect()
+}
+
+/// Passes the [Event]s `convert` keeps on to a gRPC stream, until the client goes
+/// away or the [`EventBus`](crate::events::EventBus) drops the subscription as too
+/// slow, which ends the stream with `RESOURCE_EXHAUSTED`.
+fn forward<T: Send + 'static>(
+    receiver: Receiver<Event>,
+    addresses: Vec<String>,
+    convert: fn(Event) -> Option<T>,
+) -> ReceiverStream<Result<T, Status>> {
+    let (sender, stream) = tokio::sync::mpsc::channel(SUBSCRIBER_CAPACITY);
+    tokio::task::spawn_blocking(move || loop {
+        match receiver.recv_timeout(Duration::from_millis(SUBSCRIPTION_POLL)) {
+            Ok(event) => {
+                let item = Some(event)
+                    .filter(|event| event.touches(addresses.as_slice()))
+                    .and_then(convert);
+                if let Some(item) = item {
+                    if sender.blocking_send(Ok(item)).is_err() {
+                        return;
+                    }
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {
+                if sender.is_closed() {
+                    info!("gRPC subscriber went away");
+                    return;
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                let _ = sender.blocking_send(Err(Status::resource_exhausted(
+                    "the subscriber fell behind",
+                )));
+                return;
+            }
+        }
+    });
+    ReceiverStream::new(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::pb::node_server::Node;
+    use super::*;
+    use crate::test_utils::{mine, pay, temp_chain};
+    use crate::wallet::Wallet;
+
+    fn block_on<T>(call: impl std::future::Future<Output = T>) -> T {
+        tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(call)
+    }
+
+    fn get_block(
+        service: &NodeService,
+        id: pb::get_block_request::Id,
+    ) -> Result<pb::Block, Status> {
+        let request = Request::new(pb::GetBlockRequest { id: Some(id) });
+        block_on(service.get_block(request)).map(Response::into_inner)
+    }
+
+    #[test]
+    fn chain_is_read_by_block_transaction_and_address() {
+        let (miner, payee) = (Wallet::new(), Wallet::new());
+        let blockchain = temp_chain(&miner);
+        let tx = pay(&blockchain, &miner, &payee, 3);
+        let block = mine(&blockchain, vec![tx.clone()], &miner);
+        let service = NodeService { blockchain };
+
+        let by_height = get_block(&service, pb::get_block_request::Id::Height(1)).unwrap();
+        let hash = block.get_hash().to_string();
+        let by_hash = get_block(&service, pb::get_block_request::Id::Hash(hash.clone())).unwrap();
+        assert_eq!(by_height, by_hash);
+        assert_eq!(
+            (by_hash.hash, by_hash.transactions.len()),
+            (hash.clone(), 2)
+        );
+
+        let request = Request::new(pb::GetTransactionRequest {
+            txid: tx.get_id().to_string(),
+        });
+        let info = block_on(service.get_transaction(request))
+            .unwrap()
+            .into_inner();
+        assert_eq!(info.block_hash, Some(hash));
+        assert_eq!(info.confirmations, 1);
+
+        let request = Request::new(pb::GetBalanceRequest {
+            address: payee.get_address(),
+        });
+        let balance = block_on(service.get_balance(request)).unwrap().into_inner();
+        assert_eq!(balance.confirmed, 3);
+    }
+
+    #[test]
+    fn failures_map_to_grpc_codes() {
+        let service = NodeService {
+            blockchain: temp_chain(&Wallet::new()),
+        };
+        let missing = get_block(&service, pb::get_block_request::Id::Height(5));
+        assert_eq!(missing.unwrap_err().code(), Code::NotFound);
+        let no_id = block_on(service.get_block(Request::new(pb::GetBlockRequest { id: None })));
+        assert_eq!(no_id.unwrap_err().code(), Code::InvalidArgument);
+        let request = Request::new(pb::GetBalanceRequest {
+            address: String::from("nope"),
+        });
+        let invalid = block_on(service.get_balance(request));
+        assert_eq!(invalid.unwrap_err().code(), Code::InvalidArgument);
+        let request = Request::new(pb::GetTransactionRequest {
+            txid: crate::types::Txid::from([7; 32]).to_string(),
+        });
+        let unknown = block_on(service.get_transaction(request));
+        assert_eq!(unknown.unwrap_err().code(), Code::NotFound);
+    }
+
+    #[test]
+    fn subscriber_is_streamed_new_blocks() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let service = NodeService {
+            blockchain: blockchain.clone(),
+        };
+        block_on(async {
+            let request = Request::new(pb::SubscribeRequest { addresses: vec![] });
+            let mut stream = service
+                .subscribe_blocks(request)
+                .await
+                .unwrap()
+                .into_inner()
+                .into_inner();
+            let block = mine(&blockchain, vec![], &miner);
+            let event = stream.recv().await.unwrap().unwrap();
+            assert_eq!(event.hash, block.get_hash().to_string());
+            assert_eq!(event.height, 1);
+        });
+    }
+
+    #[test]
+    fn calls_without_the_bearer_token_are_refused() {
+        let refused = authorize(Request::new(())).unwrap_err();
+        assert_eq!(refused.code(), Code::Unauthenticated);
+    }
+}
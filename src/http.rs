@@ -0,0 +1,134 @@
+use std::io::{BufRead, BufReader, Read, Write};
+use std::{error::Error, net::TcpStream, time::Duration};
+
+use serde_json::Value;
+
+/// The largest request body accepted, larger requests are refused with 413.
+const MAX_BODY_BYTES: usize = 1024 * 1024;
+/// The most header bytes read before a request is refused with 431.
+const MAX_HEADER_BYTES: usize = 8 * 1024;
+pub const READ_TIMEOUT: u64 = 5000;
+
+/// An HTTP status code with its reason phrase.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Status(pub u16, pub &'static str);
+
+pub const OK: Status = Status(200, "OK");
+pub const NO_CONTENT: Status = Status(204, "No Content");
+pub const BAD_REQUEST: Status = Status(400, "Bad Request");
+pub const UNAUTHORIZED: Status = Status(401, "Unauthorized");
+pub const NOT_FOUND: Status = Status(404, "Not Found");
+pub const METHOD_NOT_ALLOWED: Status = Status(405, "Method Not Allowed");
+pub const PAYLOAD_TOO_LARGE: Status = Status(413, "Payload Too Large");
+pub const HEADERS_TOO_LARGE: Status = Status(431, "Request Header Fields Too Large");
+
+/// An HTTP/1.1 request that made it past the size limits.
+pub struct Request {
+    pub method: String,
+    /// The path and query string, e.g. `/blocks?limit=10`.
+    pub target: String,
+    pub authorization: Option<String>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn path(&self) -> &str {
+        self.target
+            .split_once('?')
+            .map_or(self.target.as_str(), |(path, _)| path)
+    }
+
+    /// Returns the value of the query parameter `name`, if given.
+    pub fn query(&self, name: &str) -> Option<&str> {
+        let (_, query) = self.target.split_once('?')?;
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+}
+
+/// Reads the request line, headers and body, refusing oversized requests with an
+/// HTTP [Status].
+pub fn read_request(stream: &TcpStream) -> Result<Request, Status> {
+    stream
+        .set_read_timeout(Some(Duration::from_millis(READ_TIMEOUT)))
+        .map_err(|_| BAD_REQUEST)?;
+    let mut reader = BufReader::new(stream);
+    let mut header_bytes = 0;
+    let mut read_line = |reader: &mut dyn BufRead| -> Result<String, Status> {
+        let mut line = String::new();
+        let limit = (MAX_HEADER_BYTES - header_bytes + 1) as u64;
+        let read = reader
+            .take(limit)
+            .read_line(&mut line)
+            .map_err(|_| BAD_REQUEST)?;
+        header_bytes += read;
+        if header_bytes > MAX_HEADER_BYTES {
+            return Err(HEADERS_TOO_LARGE);
+        }
+        Ok(line.trim_end().to_owned())
+    };
+    let request_line = read_line(&mut reader)?;
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().ok_or(BAD_REQUEST)?.to_owned();
+    let target = parts.next().ok_or(BAD_REQUEST)?.to_owned();
+    let mut content_length = 0;
+    let mut authorization = None;
+    loop {
+        let line = read_line(&mut reader)?;
+        if line.is_empty() {
+            break;
+        }
+        let (name, value) = line.split_once(':').ok_or(BAD_REQUEST)?;
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("content-length") {
+            content_length = value.parse().map_err(|_| BAD_REQUEST)?;
+        } else if name.eq_ignore_ascii_case("authorization") {
+            authorization = Some(value.to_owned());
+        }
+    }
+    if content_length > MAX_BODY_BYTES {
+        return Err(PAYLOAD_TOO_LARGE);
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).map_err(|_| BAD_REQUEST)?;
+    Ok(Request {
+        method,
+        target,
+        authorization,
+        body,
+    })
+}
+
+/// Writes a response with a JSON `body`, or no body at all for [`NO_CONTENT`], and
+/// closes the connection.
+pub fn write_response(
+    stream: &mut TcpStream,
+    status: Status,
+    headers: &[(&str, &str)],
+    body: &Value,
+) -> Result<(), Box<dyn Error>> {
+    let body = if status == NO_CONTENT {
+        vec![]
+    } else {
+        serde_json::to_vec(body)?
+    };
+    let Status(code, reason) = status;
+    let mut head = format!("HTTP/1.1 {code} {reason}\r\n");
+    for (name, value) in headers {
+        head.push_str(format!("{name}: {value}\r\n").as_str());
+    }
+    head.push_str(
+        format!(
+            "Content-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        )
+        .as_str(),
+    );
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body.as_slice())?;
+    stream.flush()?;
+    Ok(())
+}
@@ -6,16 +6,50 @@
 )]
 pub mod block;
 pub mod blockchain;
+pub mod bloom;
+pub mod chain_file;
+pub mod clock;
 pub mod config;
+mod connection_pool;
+pub mod events;
+pub mod export;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+mod http;
+pub mod light_client;
+pub mod logging;
 pub mod memory_pool;
+pub mod merkle;
 pub mod node;
+pub mod notify;
 pub mod proof_of_work;
+pub mod rest;
+pub mod rpc;
 pub mod server;
+pub mod signer;
+pub mod storage;
+pub mod summary;
+#[cfg(test)]
+mod test_utils;
+mod thread_pool;
 pub mod transactions;
+pub mod types;
 pub mod utils;
 pub mod utxo_set;
 pub mod wallet;
 pub mod wallets;
+#[cfg(feature = "webhooks")]
+pub mod webhooks;
+mod wire;
 
-pub use utils::{base58_decode, base58_encode, current_timestamp, ripemd160_digest, sha256_digest};
+pub use utils::pkcs8_from_private_key;
+pub use utils::random_u64;
+pub use utils::{
+    base58_decode, base58_encode, current_timestamp, format_timestamp, ripemd160_digest,
+    sha256_digest, Base58Error,
+};
+pub use utils::{bech32_decode, bech32_encode, convert_bits, Bech32Error};
+pub use utils::{constant_time_eq, SecretBytes};
+pub use utils::{ecdsa_p256_sha256_sign_deterministic, public_key_from_pkcs8, KeyError};
 pub use utils::{ecdsa_p256_sha256_sign_digest, ecdsa_p256_sha256_sign_verify, new_key_pair};
+pub use utils::{hash160, sha256d, tagged_hash, Hash160, Sha256};
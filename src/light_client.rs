@@ -0,0 +1,742 @@
+//! A light client, keeping only headers and the [Transaction]s of its wallets.
+//!
+//! Each [Transaction] is shown to be in a [Block] by a [`MerkleProof`] against the
+//! header of the [Block] instead of by downloading the [Block].
+
+use std::collections::HashSet;
+use std::fmt::{self, Display, Formatter};
+use std::io::{BufReader, ErrorKind};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::{error::Error, thread, time::Duration};
+
+use log::{info, trace, warn};
+use num::BigUint;
+use sled::{Db, Tree};
+
+use crate::block::{Block, BlockHeader};
+use crate::blockchain::next_target_bits;
+use crate::bloom::BloomFilter;
+use crate::config::{ChainParams, GLOBAL_CONFIG};
+use crate::merkle::{MerkleProof, MerkleTree};
+use crate::random_u64;
+use crate::server::{send_filter, send_get_data, send_get_headers, send_version_of};
+use crate::server::{OpType, Package, MAX_HEADERS};
+use crate::transactions::{OutPoint, TXOutput, Transaction};
+use crate::types::{BlockHash, Txid};
+use crate::wallet::{convert_address, hash_pub_key};
+use crate::wire::{read_package, FrameError};
+
+const HEADERS_TREE: &str = "light_headers";
+const WORK_TREE: &str = "light_work";
+/// The hash of the header at each height of the best chain.
+const HEIGHTS_TREE: &str = "light_heights";
+/// The [Transaction]s of the watched keys, with the hash of the [Block] holding them.
+const TXS_TREE: &str = "light_txs";
+/// The hashes of the [Block]s whose [Transaction]s were looked through.
+const SCANNED_TREE: &str = "light_scanned";
+const TIP_KEY: &str = "tip";
+/// How many filtered [Block]s are asked for at a time.
+const MAX_BLOCKS_REQUESTED: usize = 500;
+/// The false positive rate of the filter sent to the full node, which trades the
+/// unrelated [Transaction]s it sends for how much it learns about the wallets.
+const FILTER_FP_RATE: f64 = 0.0001;
+/// How long the full node may stay silent before its connection is closed.
+const TCP_READ_TIMEOUT: u64 = 10_000;
+
+/// The reasons headers or a filtered [Block] from the full node are refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LightClientError {
+    /// The first header isn't the genesis [Block] of the network, or another one than
+    /// the genesis [Block] synced before.
+    NotGenesis(BlockHash),
+    UnknownParent(BlockHash),
+    WrongHeight {
+        hash: BlockHash,
+        height: usize,
+        expected: usize,
+    },
+    InvalidPow(BlockHash),
+    WrongDifficulty {
+        hash: BlockHash,
+        target_bits: i64,
+        expected: i64,
+    },
+    /// A [Block] whose header isn't one of the headers synced.
+    UnknownBlock(BlockHash),
+    ProofCount {
+        transactions: usize,
+        proofs: usize,
+    },
+    MismatchedId(Txid),
+    InvalidProof(Txid),
+}
+
+impl Display for LightClientError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotGenesis(hash) => {
+                write!(
+                    f,
+                    "the header {hash} is not the genesis block of the network"
+                )
+            }
+            Self::UnknownParent(hash) => write!(f, "the parent {hash} of a header is unknown"),
+            Self::WrongHeight {
+                hash,
+                height,
+                expected,
+            } => write!(
+                f,
+                "the header {hash} claims height {height} instead of {expected}"
+            ),
+            Self::InvalidPow(hash) => write!(f, "the header {hash} has an invalid proof of work"),
+            Self::WrongDifficulty {
+                hash,
+                target_bits,
+                expected,
+            } => write!(
+                f,
+                "the header {hash} is mined to {target_bits} target bits instead of {expected}"
+            ),
+            Self::UnknownBlock(hash) => {
+                write!(f, "the block {hash} doesn't match a synced header")
+            }
+            Self::ProofCount {
+                transactions,
+                proofs,
+            } => write!(
+                f,
+                "{transactions} transactions came with {proofs} merkle proofs"
+            ),
+            Self::MismatchedId(txid) => write!(f, "the id {txid} is not the transaction hash"),
+            Self::InvalidProof(txid) => {
+                write!(f, "the merkle proof of transaction {txid} is invalid")
+            }
+        }
+    }
+}
+
+impl std::error::Error for LightClientError {}
+
+/// Syncs the headers of the best chain and the watched [Transaction]s from a full node.
+///
+/// The [Transaction]s kept are those paying to or spending from the watched public
+/// key hashes, which give their balances without ever storing a [Block].
+#[derive(Clone)]
+pub struct LightClient {
+    headers: Tree,
+    work: Tree,
+    heights: Tree,
+    txs: Tree,
+    scanned: Tree,
+    params: &'static ChainParams,
+    watched: Arc<Vec<Vec<u8>>>,
+    /// Held while headers or [Transaction]s are added, so that the tip and the best
+    /// chain move together.
+    write_lock: Arc<Mutex<()>>,
+    /// The filtered [Block]s asked for and not received yet.
+    in_flight: Arc<AtomicUsize>,
+    /// Set once the full node was sent a [`Package::Version`] to be relayed new
+    /// [Block]s.
+    announced: Arc<AtomicBool>,
+}
+
+impl LightClient {
+    /// Opens the headers and [Transaction]s kept in `db`, watching for those of the
+    /// public key hashes in `watched`.
+    pub fn open(db: &Db, watched: Vec<Vec<u8>>) -> sled::Result<Self> {
+        Ok(Self {
+            headers: db.open_tree(HEADERS_TREE)?,
+            work: db.open_tree(WORK_TREE)?,
+            heights: db.open_tree(HEIGHTS_TREE)?,
+            txs: db.open_tree(TXS_TREE)?,
+            scanned: db.open_tree(SCANNED_TREE)?,
+            params: GLOBAL_CONFIG.get_network().params(),
+            watched: Arc::new(watched),
+            write_lock: Arc::new(Mutex::new(())),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            announced: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    pub fn get_header(&self, hash: BlockHash) -> Option<BlockHeader> {
+        let bytes = self.headers.get(hash.as_bytes()).unwrap()?;
+        Some(bincode::deserialize(bytes.as_ref()).expect("stored headers deserialize"))
+    }
+
+    /// Returns the header ending the chain with the most work, `None` before the
+    /// genesis header is synced.
+    pub fn get_tip(&self) -> Option<BlockHeader> {
+        let bytes = self.headers.get(TIP_KEY).unwrap()?;
+        self.get_header(BlockHash::from_slice(bytes.as_ref()).ok()?)
+    }
+
+    /// Returns the hash of the header at `height` on the best chain.
+    pub fn get_best_hash(&self, height: usize) -> Option<BlockHash> {
+        let bytes = self.heights.get(height_key(height)).unwrap()?;
+        BlockHash::from_slice(bytes.as_ref()).ok()
+    }
+
+    /// Lists the hashes of the best chain from the tip back to genesis, ten in a row
+    /// and then twice as far apart each time, for [`Package::GetHeaders`].
+    pub fn locator(&self) -> Vec<BlockHash> {
+        let Some(tip) = self.get_tip() else {
+            return vec![];
+        };
+        let mut locator = vec![];
+        let mut height = tip.get_height();
+        let mut step = 1;
+        loop {
+            locator.extend(self.get_best_hash(height));
+            if height == 0 {
+                break;
+            }
+            if locator.len() >= 10 {
+                step *= 2;
+            }
+            height = height.saturating_sub(step);
+        }
+        locator
+    }
+
+    /// Adds the headers of the full node, each of which has to build on the genesis
+    /// header of the network or on one added before, at the next height, and be
+    /// mined to the difficulty the chain requires. Returns how many were new.
+    ///
+    /// The tip moves to the header with the most work, so that the best chain follows
+    /// the full node through a reorganization.
+    pub fn add_headers(&self, headers: &[BlockHeader]) -> Result<usize, LightClientError> {
+        let _lock = self.write_lock.lock().unwrap();
+        let mut added = 0;
+        for header in headers {
+            if self.get_header(header.get_hash()).is_some() {
+                continue;
+            }
+            self.check_header(header)?;
+            let parent_work = self
+                .get_work(header.get_pre_block_hash())
+                .unwrap_or_default();
+            let work = parent_work + header.get_work();
+            let tip_work = self
+                .get_tip()
+                .and_then(|tip| self.get_work(tip.get_hash()))
+                .unwrap_or_default();
+            self.headers
+                .insert(
+                    header.get_hash().as_bytes(),
+                    bincode::serialize(header).unwrap(),
+                )
+                .unwrap();
+            self.work
+                .insert(header.get_hash().as_bytes(), work.to_bytes_be())
+                .unwrap();
+            if work > tip_work {
+                self.move_tip(header);
+            }
+            added += 1;
+        }
+        Ok(added)
+    }
+
+    /// Checks that `header` extends the genesis header of the network or a header
+    /// added before, without adding it.
+    fn check_header(&self, header: &BlockHeader) -> Result<(), LightClientError> {
+        let hash = header.get_hash();
+        let network = GLOBAL_CONFIG.get_network();
+        let expected_bits = if header.get_pre_block_hash() == network.genesis_pre_block_hash() {
+            if header.get_height() != 0 || self.get_best_hash(0).is_some() {
+                return Err(LightClientError::NotGenesis(hash));
+            }
+            self.params.target_bits
+        } else {
+            let parent = self
+                .get_header(header.get_pre_block_hash())
+                .ok_or_else(|| LightClientError::UnknownParent(header.get_pre_block_hash()))?;
+            if header.get_height() != parent.get_height() + 1 {
+                return Err(LightClientError::WrongHeight {
+                    hash,
+                    height: header.get_height(),
+                    expected: parent.get_height() + 1,
+                });
+            }
+            next_target_bits(self.params, &parent, |header| {
+                self.get_header(header.get_pre_block_hash())
+            })
+        };
+        if header.get_target_bits() != expected_bits {
+            return Err(LightClientError::WrongDifficulty {
+                hash,
+                target_bits: header.get_target_bits(),
+                expected: expected_bits,
+            });
+        }
+        if !header.validate_pow() {
+            return Err(LightClientError::InvalidPow(hash));
+        }
+        Ok(())
+    }
+
+    fn get_work(&self, hash: BlockHash) -> Option<BigUint> {
+        let bytes = self.work.get(hash.as_bytes()).unwrap()?;
+        Some(BigUint::from_bytes_be(bytes.as_ref()))
+    }
+
+    /// Makes `header` the tip, pointing the heights on the way back to where the old
+    /// best chain and the new one meet at the headers of the new one.
+    fn move_tip(&self, header: &BlockHeader) {
+        let stale: Vec<_> = self
+            .heights
+            .range(height_key(header.get_height() + 1)..)
+            .keys()
+            .map(Result::unwrap)
+            .collect();
+        for key in stale {
+            self.heights.remove(key).unwrap();
+        }
+        let mut next = Some(header.clone());
+        while let Some(header) = next.take() {
+            if self.get_best_hash(header.get_height()) == Some(header.get_hash()) {
+                break;
+            }
+            self.heights
+                .insert(
+                    height_key(header.get_height()),
+                    header.get_hash().as_bytes(),
+                )
+                .unwrap();
+            if header.get_height() > 0 {
+                next = self.get_header(header.get_pre_block_hash());
+            }
+        }
+        self.headers
+            .insert(TIP_KEY, header.get_hash().as_bytes())
+            .unwrap();
+    }
+
+    /// Builds the filter the full node matches [Transaction]s against, holding the
+    /// watched public key hashes.
+    pub fn get_filter(&self) -> BloomFilter {
+        let tweak = u32::try_from(random_u64() & u64::from(u32::MAX)).unwrap();
+        let mut filter = BloomFilter::new(self.watched.len(), FILTER_FP_RATE, tweak);
+        for pub_key_hash in self.watched.iter() {
+            filter.insert(pub_key_hash.as_slice());
+        }
+        filter
+    }
+
+    /// Returns the hashes of up to `max` [Block]s of the best chain, lowest first,
+    /// whose [Transaction]s weren't looked through yet.
+    pub fn get_unscanned(&self, max: usize) -> Vec<BlockHash> {
+        self.heights
+            .iter()
+            .values()
+            .map(|hash| BlockHash::from_slice(hash.unwrap().as_ref()).unwrap())
+            .filter(|hash| !self.scanned.contains_key(hash.as_bytes()).unwrap())
+            .take(max)
+            .collect()
+    }
+
+    /// Records the [Transaction]s of a filtered [Block] sent by the full node that
+    /// pay to or spend from a watched key, returning their ids.
+    ///
+    /// The header has to be one of those synced, and each [Transaction] has to come
+    /// with a [`MerkleProof`] leading from its id to the Merkle root of the header.
+    pub fn add_filtered_block(
+        &self,
+        header: &BlockHeader,
+        transactions: &[Transaction],
+        proofs: &[MerkleProof],
+    ) -> Result<Vec<Txid>, LightClientError> {
+        if self.get_header(header.get_hash()).as_ref() != Some(header) {
+            return Err(LightClientError::UnknownBlock(header.get_hash()));
+        }
+        if transactions.len() != proofs.len() {
+            return Err(LightClientError::ProofCount {
+                transactions: transactions.len(),
+                proofs: proofs.len(),
+            });
+        }
+        for (tx, proof) in transactions.iter().zip(proofs) {
+            if !tx.has_valid_id() {
+                return Err(LightClientError::MismatchedId(tx.get_id()));
+            }
+            if !MerkleTree::verify_proof(header.get_merkle_root(), tx.get_id(), proof) {
+                return Err(LightClientError::InvalidProof(tx.get_id()));
+            }
+        }
+        Ok(self.record(header.get_hash(), transactions))
+    }
+
+    /// Like [`LightClient::add_filtered_block`] for a whole [Block], which the full
+    /// node sends when the filter was set on another connection than the request.
+    pub fn add_block(&self, block: &Block) -> Result<Vec<Txid>, LightClientError> {
+        if self.get_header(block.get_hash()) != Some(block.get_header()) {
+            return Err(LightClientError::UnknownBlock(block.get_hash()));
+        }
+        if let Some(tx) = block
+            .get_transactions()
+            .iter()
+            .find(|tx| !tx.has_valid_id())
+        {
+            return Err(LightClientError::MismatchedId(tx.get_id()));
+        }
+        Ok(self.record(block.get_hash(), block.get_transactions()))
+    }
+
+    /// Keeps the [Transaction]s among `transactions` of a watched key and marks the
+    /// [Block] hashed to `block_hash` scanned.
+    fn record(&self, block_hash: BlockHash, transactions: &[Transaction]) -> Vec<Txid> {
+        let _lock = self.write_lock.lock().unwrap();
+        let mut recorded = vec![];
+        for tx in transactions.iter().filter(|tx| self.is_watched(tx)) {
+            let entry = bincode::serialize(&(block_hash, tx)).unwrap();
+            self.txs.insert(tx.get_id().as_bytes(), entry).unwrap();
+            recorded.push(tx.get_id());
+        }
+        self.scanned.insert(block_hash.as_bytes(), &[]).unwrap();
+        recorded
+    }
+
+    /// Checks whether `tx` pays to or spends from a watched key.
+    fn is_watched(&self, tx: &Transaction) -> bool {
+        self.watched.iter().any(|pub_key_hash| {
+            tx.get_vout()
+                .iter()
+                .any(|out| out.is_locked_with_key(pub_key_hash.as_slice()))
+                || tx
+                    .get_vin()
+                    .iter()
+                    .any(|input| hash_pub_key(input.get_pub_key()) == *pub_key_hash)
+        })
+    }
+
+    /// Returns the recorded [Transaction]s in [Block]s of the best chain.
+    fn get_confirmed(&self) -> Vec<Transaction> {
+        self.txs
+            .iter()
+            .values()
+            .map(|entry| {
+                bincode::deserialize::<(BlockHash, Transaction)>(entry.unwrap().as_ref())
+                    .expect("stored transactions deserialize")
+            })
+            .filter(|(block_hash, _)| {
+                self.get_header(*block_hash).is_some_and(|header| {
+                    self.get_best_hash(header.get_height()) == Some(*block_hash)
+                })
+            })
+            .map(|(_, tx)| tx)
+            .collect()
+    }
+
+    /// Returns the outputs locked with `pub_key_hash` that no confirmed [Transaction]
+    /// spends, in the order of their outpoints.
+    pub fn get_unspent(&self, pub_key_hash: &[u8]) -> Vec<(OutPoint, TXOutput)> {
+        let confirmed = self.get_confirmed();
+        let spent: HashSet<(Txid, usize)> = confirmed
+            .iter()
+            .flat_map(|tx| tx.get_vin().iter())
+            .map(|input| (input.get_txid(), input.get_vout()))
+            .collect();
+        let mut unspent: Vec<(OutPoint, TXOutput)> = confirmed
+            .iter()
+            .flat_map(|tx| {
+                tx.get_vout().iter().enumerate().map(move |(vout, out)| {
+                    (
+                        OutPoint {
+                            txid: tx.get_id(),
+                            vout,
+                        },
+                        out,
+                    )
+                })
+            })
+            .filter(|(outpoint, out)| {
+                out.is_locked_with_key(pub_key_hash)
+                    && !spent.contains(&(outpoint.txid, outpoint.vout))
+            })
+            .map(|(outpoint, out)| (outpoint, out.clone()))
+            .collect();
+        unspent.sort_unstable_by_key(|(outpoint, _)| (outpoint.txid, outpoint.vout));
+        unspent
+    }
+
+    /// Returns the value of the outputs of `pub_key_hash` left unspent on the best
+    /// chain.
+    pub fn get_balance(&self, pub_key_hash: &[u8]) -> u64 {
+        self.get_unspent(pub_key_hash)
+            .iter()
+            .map(|(_, out)| out.get_value())
+            .fold(0, u64::saturating_add)
+    }
+
+    /// Listens on `addr` for the packages of the full node at `peer`, after asking
+    /// it for the headers following the synced ones, until the process ends.
+    ///
+    /// Once the headers are synced, the full node is sent a filter of the watched
+    /// keys and asked for the filtered [Block]s not yet looked through, and told
+    /// about this client so that it announces new [Block]s.
+    pub fn run(&self, addr: &str, peer: &str) -> Result<(), Box<dyn Error>> {
+        let listener = TcpListener::bind(addr)?;
+        info!("Syncing headers from {peer}");
+        send_get_headers(peer, self.locator())?;
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!("Unable to accept a connection: {e}");
+                    continue;
+                }
+            };
+            let client = self.clone();
+            let peer = String::from(peer);
+            thread::spawn(move || {
+                if let Err(e) = client.serve(&stream, peer.as_str()) {
+                    warn!("Unable to serve the connection from {peer}: {e}");
+                }
+            });
+        }
+        Ok(())
+    }
+
+    fn serve(&self, stream: &TcpStream, peer: &str) -> Result<(), Box<dyn Error>> {
+        stream.set_read_timeout(Some(Duration::from_millis(TCP_READ_TIMEOUT)))?;
+        let mut reader = BufReader::new(stream);
+        loop {
+            let pkg = match read_package(&mut reader) {
+                Ok(Some(pkg)) => pkg,
+                Ok(None) => break,
+                Err(FrameError::Io(e))
+                    if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+                {
+                    break;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            trace!("Received package: {pkg:?}");
+            match pkg {
+                Package::Headers { headers, .. } => {
+                    let added = self.add_headers(headers.as_slice())?;
+                    let height = self.get_tip().map_or(0, |tip| tip.get_height());
+                    info!("Synced {added} headers, the tip is at height {height}");
+                    if headers.len() == MAX_HEADERS {
+                        send_get_headers(peer, self.locator())?;
+                    } else {
+                        self.announce(peer)?;
+                        self.request_blocks(peer)?;
+                    }
+                }
+                Package::FilteredBlock {
+                    header,
+                    transactions,
+                    proofs,
+                    ..
+                } => {
+                    let transactions = transactions
+                        .iter()
+                        .map(|bytes| Transaction::from_bytes(bytes.as_slice()))
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let found = self.add_filtered_block(&header, &transactions, &proofs)?;
+                    self.received(peer, found.as_slice())?;
+                }
+                Package::Block { block, .. } => {
+                    let block = Block::from_bytes(block.as_slice())?;
+                    let found = self.add_block(&block)?;
+                    self.received(peer, found.as_slice())?;
+                }
+                Package::Inv {
+                    op_type: OpType::Block,
+                    ..
+                } => send_get_headers(peer, self.locator())?,
+                Package::Version { best_height, .. } => {
+                    if self
+                        .get_tip()
+                        .is_none_or(|tip| tip.get_height() < best_height)
+                    {
+                        send_get_headers(peer, self.locator())?;
+                    }
+                }
+                pkg => trace!("Ignoring {} from {peer}", pkg.name()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Tells the full node at `peer` about this client once, so that it announces the
+    /// [Block]s it gets.
+    fn announce(&self, peer: &str) -> Result<(), Box<dyn Error>> {
+        let (Some(tip), Some(genesis_hash)) = (self.get_tip(), self.get_best_hash(0)) else {
+            return Ok(());
+        };
+        if !self.announced.swap(true, Ordering::Relaxed) {
+            send_version_of(peer, tip.get_height(), genesis_hash)?;
+        }
+        Ok(())
+    }
+
+    /// Sends the filter to the full node at `peer` and asks it for the filtered
+    /// [Block]s not looked through yet, on the same connection so that the filter
+    /// applies to them.
+    fn request_blocks(&self, peer: &str) -> Result<(), Box<dyn Error>> {
+        let unscanned = self.get_unscanned(MAX_BLOCKS_REQUESTED);
+        if unscanned.is_empty() || self.in_flight.load(Ordering::Relaxed) > 0 {
+            return Ok(());
+        }
+        send_filter(peer, &self.get_filter())?;
+        self.in_flight.store(unscanned.len(), Ordering::Relaxed);
+        for hash in unscanned {
+            send_get_data(peer, OpType::Block, hash.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Reports the [Transaction]s `found` in a [Block] and the new balances, asking
+    /// for more [Block]s once all those asked for arrived.
+    fn received(&self, peer: &str, found: &[Txid]) -> Result<(), Box<dyn Error>> {
+        for txid in found {
+            info!("Found transaction {txid}");
+        }
+        if !found.is_empty() {
+            for pub_key_hash in self.watched.iter() {
+                let address = convert_address(pub_key_hash.as_slice());
+                let balance = self.get_balance(pub_key_hash.as_slice());
+                info!("Balance of {address}: {balance}");
+            }
+        }
+        let in_flight = self.in_flight.load(Ordering::Relaxed).saturating_sub(1);
+        self.in_flight.store(in_flight, Ordering::Relaxed);
+        if in_flight == 0 {
+            self.request_blocks(peer)?;
+        }
+        Ok(())
+    }
+}
+
+/// Keys the heights so that they sort in order.
+fn height_key(height: usize) -> [u8; 8] {
+    u64::try_from(height).unwrap().to_be_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::Blockchain;
+    use crate::test_utils::{mine, pay, temp_chain};
+    use crate::wallet::Wallet;
+
+    fn client_watching(wallet: &Wallet) -> LightClient {
+        let db = sled::Config::new()
+            .temporary(true)
+            .open()
+            .expect("a temporary database opens");
+        LightClient::open(&db, vec![hash_pub_key(wallet.get_public_key())]).expect("the trees open")
+    }
+
+    /// Filters the [Block] hashed to `hash` the way a full node does, returning its
+    /// header and the matching [Transaction]s with their [`MerkleProof`]s.
+    fn filtered(
+        blockchain: &Blockchain,
+        client: &LightClient,
+        hash: BlockHash,
+    ) -> (BlockHeader, Vec<Transaction>, Vec<MerkleProof>) {
+        let block = blockchain.get_block(hash).expect("the block is stored");
+        let mut filter = client.get_filter();
+        let merkle_tree = block.get_merkle_tree();
+        let (transactions, proofs) = block
+            .get_transactions()
+            .iter()
+            .enumerate()
+            .filter(|(_, tx)| filter.matches_tx(tx))
+            .map(|(idx, tx)| (tx.clone(), merkle_tree.proof(idx).unwrap()))
+            .unzip();
+        (block.get_header(), transactions, proofs)
+    }
+
+    /// A chain whose second [Block] pays 3 to `watched`, and a client synced to its
+    /// headers.
+    fn synced(watched: &Wallet) -> (Blockchain, LightClient) {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let payment = pay(&blockchain, &miner, watched, 3);
+        mine(&blockchain, vec![payment], &miner);
+        let client = client_watching(watched);
+        let headers = blockchain.get_headers(&[], MAX_HEADERS);
+        assert_eq!(client.add_headers(headers.as_slice()), Ok(2));
+        (blockchain, client)
+    }
+
+    #[test]
+    fn payments_are_found_in_filtered_blocks() {
+        let watched = Wallet::new();
+        let (blockchain, client) = synced(&watched);
+        assert_eq!(
+            client.get_tip().unwrap().get_hash(),
+            blockchain.get_tip_hash()
+        );
+        let unscanned = client.get_unscanned(MAX_BLOCKS_REQUESTED);
+        assert_eq!(unscanned.len(), 2);
+        for hash in unscanned {
+            let (header, transactions, proofs) = filtered(&blockchain, &client, hash);
+            client
+                .add_filtered_block(&header, &transactions, &proofs)
+                .unwrap();
+        }
+        assert!(client.get_unscanned(MAX_BLOCKS_REQUESTED).is_empty());
+        let pub_key_hash = hash_pub_key(watched.get_public_key());
+        assert_eq!(client.get_balance(pub_key_hash.as_slice()), 3);
+    }
+
+    #[test]
+    fn transactions_must_be_proven_in_the_block() {
+        let watched = Wallet::new();
+        let (blockchain, client) = synced(&watched);
+        let (header, transactions, proofs) =
+            filtered(&blockchain, &client, blockchain.get_tip_hash());
+        let payment = transactions[0].get_id();
+
+        let mut value = serde_json::to_value(&transactions[0]).unwrap();
+        value["vout"][0]["value"] = 1_000.into();
+        let tampered: Transaction = serde_json::from_value(value).unwrap();
+        assert_eq!(
+            client.add_filtered_block(&header, &[tampered], &proofs[..1]),
+            Err(LightClientError::MismatchedId(payment))
+        );
+
+        let block = blockchain.get_block(blockchain.get_tip_hash()).unwrap();
+        let coinbase_proof = block.get_merkle_tree().proof(1).unwrap();
+        assert_eq!(
+            client.add_filtered_block(&header, &transactions[..1], &[coinbase_proof]),
+            Err(LightClientError::InvalidProof(payment))
+        );
+        assert_eq!(
+            client.add_filtered_block(&header, &transactions, &[]),
+            Err(LightClientError::ProofCount {
+                transactions: transactions.len(),
+                proofs: 0
+            })
+        );
+        assert_eq!(
+            client.get_balance(hash_pub_key(watched.get_public_key()).as_slice()),
+            0
+        );
+    }
+
+    #[test]
+    fn headers_must_carry_their_proof_of_work() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        mine(&blockchain, vec![], &miner);
+        let client = client_watching(&miner);
+        let mut headers = blockchain.get_headers(&[], MAX_HEADERS);
+        let mut value = serde_json::to_value(&headers[1]).unwrap();
+        value["nonce"] = (headers[1].get_nonce() + 1).into();
+        headers[1] = serde_json::from_value(value).unwrap();
+        let hash = headers[1].get_hash();
+        assert_eq!(
+            client.add_headers(headers.as_slice()),
+            Err(LightClientError::InvalidPow(hash))
+        );
+        assert_eq!(client.get_tip().unwrap().get_height(), 0);
+    }
+}
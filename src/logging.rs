@@ -0,0 +1,118 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::{error::Error, path::PathBuf};
+
+use env_logger::{Builder, Target};
+use serde_json::json;
+
+use crate::config::{Config, LogFormat};
+
+/// The size at which the log file is rotated.
+const LOG_FILE_MAX_BYTES: u64 = 10 * 1024 * 1024;
+/// The number of rotated log files kept next to the active one.
+const LOG_FILE_BACKUPS: usize = 3;
+
+/// Initializes the global logger from the log level, file and format in the [Config].
+///
+/// Library modules only ever use the `log` macros, so this is the single place
+/// deciding where records end up.
+pub fn init(config: &Config) -> Result<(), Box<dyn Error>> {
+    let mut builder = Builder::new();
+    builder.parse_filters(config.get_log_level().as_str());
+    if config.get_log_format() == LogFormat::Json {
+        builder.format(|buf, record| {
+            let line = json!({
+                "timestamp": buf.timestamp_millis().to_string(),
+                "level": record.level().as_str(),
+                "target": record.target(),
+                "message": record.args().to_string(),
+            });
+            writeln!(buf, "{line}")
+        });
+    }
+    if let Some(path) = config.get_log_file() {
+        builder.target(Target::Pipe(Box::new(RotatingFile::open(path)?)));
+    }
+    builder.try_init()?;
+    Ok(())
+}
+
+/// A log file that is renamed to `<name>.1` once it grows past [`LOG_FILE_MAX_BYTES`],
+/// shifting older files up to [`LOG_FILE_BACKUPS`].
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self { path, file, size })
+    }
+
+    fn backup_path(&self, idx: usize) -> PathBuf {
+        let mut name = self.path.clone().into_os_string();
+        name.push(format!(".{idx}"));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        for idx in (1..LOG_FILE_BACKUPS).rev() {
+            let from = self.backup_path(idx);
+            if from.exists() {
+                fs::rename(from, self.backup_path(idx + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.backup_path(1))?;
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > LOG_FILE_MAX_BYTES {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn log_file_rotates_once_full() {
+        let dir = std::env::temp_dir().join(format!("himalia-log-{}", crate::random_u64()));
+        let path = dir.join("node.log");
+        let mut log = RotatingFile::open(path.clone()).unwrap();
+        let full = vec![b'a'; usize::try_from(LOG_FILE_MAX_BYTES).unwrap()];
+        log.write_all(&full).unwrap();
+        log.write_all(b"next\n").unwrap();
+        log.flush().unwrap();
+
+        assert_eq!(
+            fs::metadata(log.backup_path(1)).unwrap().len(),
+            LOG_FILE_MAX_BYTES
+        );
+        assert_eq!(fs::read_to_string(&path).unwrap(), "next\n");
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,20 +1,105 @@
 #![allow(clippy::unwrap_used)]
-use std::error::Error;
+use std::io::{self, Read, Write};
+use std::{collections::HashSet, error::Error, fmt, path::PathBuf, process, time::Instant};
 
-use data_encoding::HEXLOWER;
-use log::LevelFilter;
+use data_encoding::{BASE64, HEXLOWER, HEXLOWER_PERMISSIVE};
+use serde::Serialize;
+use serde_json::json;
 use structopt::StructOpt;
 
-use himalia::server::{send_tx, Server, CENTRAL_NODE};
-use himalia::wallet::{self, validate_address, ADDRESS_CHECK_SUM_LEN};
-use himalia::{blockchain::Blockchain, config::GLOBAL_CONFIG};
-use himalia::{transactions::Transaction, utxo_set::UTXOSet, wallets::Wallets};
+use himalia::block::Block;
+use himalia::blockchain::{BlockError, Blockchain, BlockchainError, TxRecord};
+use himalia::chain_file::{export_chain, import_chain, ChainFileError, VerifyLevel};
+use himalia::config::{LogFormat, Network, GLOBAL_CONFIG};
+use himalia::export::{export, ExportFormat, ExportKind};
+use himalia::light_client::LightClient;
+use himalia::logging;
+use himalia::memory_pool::{
+    fee_for_rate, pending_balance, MemoryPool, MempoolSnapshot, MEMPOOL_TREE,
+};
+use himalia::node::{is_valid_addr, Ban, NodeHandle};
+use himalia::rpc::{self, RpcError};
+use himalia::server::{bootstrap_peers, central_node, send_tx, submit_tx, NodeStatus};
+use himalia::signer::{configured_signer, Signer, SignerError, SignerKind, SIGNATURE_LEN};
+use himalia::storage::open_db;
+use himalia::summary::{AddressBalance, BlockSummary, TransactionDetail, WalletBalance};
+use himalia::transactions::{
+    create_raw_transaction, decode_raw_transaction, DecodeError, OutPoint, Recipient, TXOutput,
+    Transaction, TransactionError,
+};
+use himalia::types::Txid;
+use himalia::utxo_set::{
+    SpendError, UTXOSet, UtxoDiff, PROGRESS_INTERVAL as UTXO_PROGRESS_INTERVAL,
+};
+use himalia::wallet::{
+    address_pub_key_hash, encode_message_envelope, new_mnemonic, validate_address, verify_message,
+    verify_message_envelope, Wallet,
+};
+use himalia::wallets::{WalletError, WalletStore, Wallets};
 
 const MINE_TRUE: usize = 1;
+/// Fees above this percentage of the amount sent need `--allow-high-fee`.
+const HIGH_FEE_PERCENT: u64 = 10;
+/// Exit code for invalid input such as a malformed address, and any failure
+/// without a more specific code.
+const EXIT_USAGE: i32 = 1;
+/// Exit code for a command that doesn't fit the local state, e.g. no blockchain.
+const EXIT_STATE: i32 = 2;
+/// Exit code for a failure to reach or run a node.
+const EXIT_NETWORK: i32 = 3;
+/// Exit code for an invalid configuration, following `EX_CONFIG` from sysexits.
+const EXIT_CONFIG: i32 = 78;
 
 #[derive(Debug, StructOpt)]
-#[structopt(name = "himalia")]
+#[structopt(
+    name = "himalia",
+    after_help = "EXIT CODES:\n    1     Invalid input or usage\n    2     The command doesn't fit the local state, e.g. no blockchain or not enough funds\n    3     A node could not be reached or started\n    78    Invalid configuration"
+)]
 struct Opt {
+    #[structopt(
+        long,
+        global = true,
+        help = "The network to use: main, test or regtest"
+    )]
+    network: Option<Network>,
+    #[structopt(
+        long,
+        global = true,
+        help = "Write settings given on the command line back to the config file"
+    )]
+    save: bool,
+    #[structopt(
+        long,
+        global = true,
+        help = "The log filter, e.g. `info` or `info,himalia::server=debug`"
+    )]
+    log_level: Option<String>,
+    #[structopt(
+        long,
+        global = true,
+        help = "Write logs to this file, relative to the data directory"
+    )]
+    log_file: Option<String>,
+    #[structopt(long, global = true, help = "The log format: text or json")]
+    log_format: Option<LogFormat>,
+    #[structopt(
+        long,
+        global = true,
+        help = "Use this wallet file, relative to the data directory"
+    )]
+    wallet: Option<String>,
+    #[structopt(
+        long,
+        global = true,
+        help = "Keep the chain data of each network in this directory instead of ./data"
+    )]
+    data_dir: Option<String>,
+    #[structopt(
+        long,
+        global = true,
+        help = "Print a single JSON document instead of text, errors included"
+    )]
+    json: bool,
     #[structopt(subcommand)]
     command: Command,
 }
@@ -29,157 +114,2659 @@ enum Command {
         )]
         address: String,
     },
+    #[structopt(
+        name = "generate",
+        about = "Mine blocks holding only a coinbase, on regtest only"
+    )]
+    Generate {
+        #[structopt(name = "count", help = "The number of blocks to mine")]
+        count: usize,
+        #[structopt(name = "address", help = "The address to send the block rewards to")]
+        address: String,
+    },
+    #[structopt(
+        name = "mine",
+        about = "Mine blocks of the pending transactions while no node is running"
+    )]
+    Mine {
+        #[structopt(name = "address", help = "The address to send the block rewards to")]
+        address: String,
+        #[structopt(long, default_value = "1", help = "The number of blocks to mine")]
+        count: usize,
+        #[structopt(long, help = "Leave the pending transactions out of the blocks")]
+        empty: bool,
+    },
     #[structopt(name = "createwallet", help = "Create a new wallet")]
-    CreateWallet,
+    CreateWallet {
+        #[structopt(long, default_value = "1", help = "The number of wallets to create")]
+        count: usize,
+        #[structopt(
+            long,
+            help = "Label the new wallet, suffixed with an index when creating several"
+        )]
+        label: Option<String>,
+        #[structopt(long, help = "Print only the new addresses, one per line")]
+        quiet: bool,
+        #[structopt(
+            long,
+            help = "Derive the wallet from the wallet's seed, creating one first if there is none"
+        )]
+        hd: bool,
+    },
+    #[structopt(
+        name = "recover",
+        about = "Restore the used addresses derived from a seed phrase"
+    )]
+    Recover {
+        #[structopt(
+            name = "mnemonic",
+            help = "The seed phrase printed by createwallet --hd, quoted"
+        )]
+        mnemonic: String,
+        #[structopt(
+            long,
+            default_value = "20",
+            help = "Stop after this many consecutive unused addresses"
+        )]
+        gap_limit: u32,
+    },
+    #[structopt(
+        name = "backupwallet",
+        about = "Copy the wallet file to the backup directory"
+    )]
+    BackupWallet {
+        #[structopt(
+            long,
+            help = "Write the backup here instead of the configured directory"
+        )]
+        dir: Option<PathBuf>,
+    },
+    #[structopt(
+        name = "signmessage",
+        about = "Sign a message with the key of a wallet address"
+    )]
+    SignMessage {
+        #[structopt(name = "address", help = "The wallet address to sign with")]
+        address: String,
+        #[structopt(
+            name = "message",
+            help = "The message to sign, read from --file or stdin when omitted"
+        )]
+        message: Option<String>,
+        #[structopt(
+            long,
+            parse(from_os_str),
+            conflicts_with = "message",
+            help = "Read the message from this file"
+        )]
+        file: Option<PathBuf>,
+    },
+    #[structopt(
+        name = "verifymessage",
+        about = "Check a message signature made by signmessage"
+    )]
+    VerifyMessage {
+        #[structopt(name = "address", help = "The address that signed the message")]
+        address: String,
+        #[structopt(name = "message", help = "The signed message")]
+        message: String,
+        #[structopt(
+            name = "signature",
+            help = "The base64 signature, or the Base58 envelope when no public key is given"
+        )]
+        signature: String,
+        #[structopt(name = "pubkey", help = "The hex public key of the signer")]
+        public_key: Option<String>,
+    },
     #[structopt(
         name = "getbalance",
         about = "Get the wallet balance of the target address"
     )]
     GetBalance {
-        #[structopt(name = "address", help = "The wallet address")]
-        address: String,
+        #[structopt(
+            name = "address",
+            help = "The wallet address, every local address when omitted"
+        )]
+        address: Option<String>,
+        #[structopt(long, conflicts_with = "address", help = "Show every local address")]
+        all: bool,
+        #[structopt(
+            long,
+            help = "Count the transactions pending in the node's mempool in the balance"
+        )]
+        include_mempool: bool,
     },
     #[structopt(name = "listaddresses", about = "Pring local wallet address")]
     ListAddresses,
+    #[structopt(name = "setlabel", about = "Label a wallet address")]
+    SetLabel {
+        #[structopt(name = "address", help = "The wallet or watched address to label")]
+        address: String,
+        #[structopt(
+            name = "label",
+            help = "The new label, removing the label when omitted"
+        )]
+        label: Option<String>,
+    },
+    #[structopt(
+        name = "importaddress",
+        about = "Watch an address whose key is kept elsewhere"
+    )]
+    ImportAddress {
+        #[structopt(name = "address", help = "The address to watch")]
+        address: String,
+        #[structopt(long, help = "A label to show next to the address")]
+        label: Option<String>,
+    },
+    #[structopt(
+        name = "dumpprivkey",
+        about = "Print the private key of a wallet address, to import elsewhere"
+    )]
+    DumpPrivKey {
+        #[structopt(name = "address", help = "The wallet address to export the key of")]
+        address: String,
+    },
+    #[structopt(
+        name = "importprivkey",
+        about = "Add a private key printed by dumpprivkey to the wallet"
+    )]
+    ImportPrivKey {
+        #[structopt(name = "key", help = "The private key, as printed by dumpprivkey")]
+        key: String,
+        #[structopt(long, help = "A label to show next to the address")]
+        label: Option<String>,
+    },
+    #[structopt(
+        name = "getwalletbalance",
+        about = "Add up the balances of every wallet address"
+    )]
+    GetWalletBalance,
     #[structopt(name = "send", about = "Add new block to chain")]
     Send {
-        #[structopt(name = "from", help = "Source wallet address")]
+        #[structopt(name = "from", help = "Source wallet address or its label")]
         from: String,
         #[structopt(name = "to", help = "Destination wallet address")]
         to: String,
         #[structopt(name = "amount", help = "Amount to send")]
-        amount: i32,
+        amount: u64,
         #[structopt(name = "mine", help = "Mine immediately on the same node")]
         mine: usize,
+        #[structopt(
+            long = "to",
+            name = "also-to",
+            help = "Another destination as <address>:<amount>, paid in the same transaction"
+        )]
+        also_to: Vec<Recipient>,
+        #[structopt(long, help = "Allow paying the same address more than once")]
+        allow_duplicates: bool,
+        #[structopt(long, help = "Absolute fee to leave for the miner")]
+        fee: Option<u64>,
+        #[structopt(
+            long,
+            conflicts_with = "fee",
+            help = "Fee per byte, defaults to the estimate from the running node's mempool"
+        )]
+        fee_rate: Option<f64>,
+        #[structopt(long, help = "Send without asking to confirm the fee")]
+        yes: bool,
+        #[structopt(long, help = "Allow a fee above 10% of the amount")]
+        allow_high_fee: bool,
+        #[structopt(long, help = "Build and print the transaction without sending it")]
+        dry_run: bool,
+        #[structopt(
+            long,
+            requires = "dry-run",
+            conflicts_with = "signer",
+            help = "Leave the dry run transaction unsigned"
+        )]
+        no_sign: bool,
+        #[structopt(
+            long,
+            requires = "dry-run",
+            help = "Also print the dry run transaction serialized in hex"
+        )]
+        raw: bool,
+        #[structopt(
+            long,
+            default_value = "wallet",
+            help = "Where signatures come from: wallet, external or http"
+        )]
+        signer: SignerKind,
+        #[structopt(
+            long,
+            help = "Node to submit the transaction to, defaults to the configured node address"
+        )]
+        node: Option<String>,
+    },
+    #[structopt(name = "sendmany", about = "Pay several addresses in one transaction")]
+    SendMany {
+        #[structopt(name = "from", help = "Source wallet address or its label")]
+        from: String,
+        #[structopt(
+            name = "recipients",
+            required = true,
+            help = "Destinations as <address>:<amount> pairs"
+        )]
+        recipients: Vec<Recipient>,
+        #[structopt(long, default_value = "0", help = "Fee to leave for the miner")]
+        fee: u64,
+        #[structopt(long, help = "Mine immediately on the same node")]
+        mine: bool,
+        #[structopt(long, help = "Allow paying the same address more than once")]
+        allow_duplicates: bool,
+        #[structopt(long, help = "Build and print the transaction without sending it")]
+        dry_run: bool,
+        #[structopt(
+            long,
+            requires = "dry-run",
+            help = "Leave the dry run transaction unsigned"
+        )]
+        no_sign: bool,
+        #[structopt(
+            long,
+            requires = "dry-run",
+            help = "Also print the dry run transaction serialized in hex"
+        )]
+        raw: bool,
+    },
+    #[structopt(
+        name = "sweep",
+        about = "Move the funds of every wallet address to one address in one transaction"
+    )]
+    Sweep {
+        #[structopt(name = "to", help = "Destination wallet address")]
+        to: String,
+        #[structopt(long, default_value = "0", help = "Fee to leave for the miner")]
+        fee: u64,
+        #[structopt(long, help = "Mine immediately on the same node")]
+        mine: bool,
+    },
+    #[structopt(
+        name = "sendrawtransaction",
+        about = "Relay a transaction signed elsewhere, given in hex"
+    )]
+    SendRawTransaction {
+        #[structopt(
+            name = "hex",
+            help = "The serialized transaction, as printed by send --dry-run --raw"
+        )]
+        hex: String,
+    },
+    #[structopt(
+        name = "createrawtx",
+        about = "Build an unsigned transaction from given outputs to spend, in hex"
+    )]
+    CreateRawTx {
+        #[structopt(
+            long = "input",
+            required = true,
+            help = "An output to spend as <txid>:<vout>"
+        )]
+        inputs: Vec<OutPoint>,
+        #[structopt(
+            long = "output",
+            required = true,
+            help = "A destination as <address>:<amount>"
+        )]
+        outputs: Vec<Recipient>,
+    },
+    #[structopt(
+        name = "signrawtx",
+        about = "Sign a raw transaction with the key of a local address"
+    )]
+    SignRawTx {
+        #[structopt(
+            name = "hex",
+            help = "The transaction in hex, as printed by createrawtx"
+        )]
+        hex: String,
+        #[structopt(name = "address", help = "The address whose key signs every input")]
+        address: String,
+        #[structopt(
+            long = "prevout",
+            help = "The output each input spends as <address>:<amount>, in the order of \
+                    the inputs, looked up in the blockchain when omitted"
+        )]
+        prev_outputs: Vec<Recipient>,
+    },
+    #[structopt(name = "sendrawtx", about = "Push a signed raw transaction to a node")]
+    SendRawTx {
+        #[structopt(name = "hex", help = "The signed transaction in hex")]
+        hex: String,
+        #[structopt(
+            long,
+            help = "Node to push the transaction to, defaults to the configured node address"
+        )]
+        node: Option<String>,
+    },
+    #[structopt(name = "getblock", about = "Print a single block")]
+    GetBlock {
+        #[structopt(name = "block", help = "The block hash in hex or the block height")]
+        block: String,
+        #[structopt(long, help = "Include every transaction input and output")]
+        verbose: bool,
+        #[structopt(long, help = "Print the serialized block in hex")]
+        raw: bool,
+    },
+    #[structopt(
+        name = "getblockbyheight",
+        about = "Print the block at a height of the best chain"
+    )]
+    GetBlockByHeight {
+        #[structopt(name = "height", help = "The height of the block")]
+        height: usize,
+        #[structopt(long, help = "Include every transaction input and output")]
+        verbose: bool,
+        #[structopt(long, help = "Print the serialized block in hex")]
+        raw: bool,
     },
-    #[structopt(name = "printchain", about = "Print blockchain all blocks")]
-    PrintChain,
-    #[structopt(name = "reindexutxo", about = "Rebuild UTXO index set")]
-    ReindexUtxo,
+    #[structopt(name = "gettransaction", about = "Print a single transaction")]
+    GetTransaction {
+        #[structopt(name = "txid", help = "The transaction id in hex")]
+        txid: String,
+        #[structopt(long, help = "Print the serialized transaction in hex")]
+        raw: bool,
+    },
+    #[structopt(
+        name = "listtransactions",
+        about = "List the confirmed transactions touching an address, newest first"
+    )]
+    ListTransactions {
+        #[structopt(
+            name = "address",
+            help = "The address to list the transactions of, every wallet address when omitted"
+        )]
+        address: Option<String>,
+        #[structopt(long, help = "The number of transactions to list")]
+        limit: Option<usize>,
+    },
+    #[structopt(
+        name = "mempool",
+        about = "Inspect the pending transactions of a running node"
+    )]
+    Mempool {
+        #[structopt(long, help = "Only show transactions touching this address")]
+        address: Option<String>,
+    },
+    #[structopt(name = "getpeerinfo", about = "List the peers of a running node")]
+    GetPeerInfo,
+    #[structopt(
+        name = "setban",
+        about = "Ban a peer of a running node, or lift its ban with a duration of 0"
+    )]
+    SetBan {
+        #[structopt(name = "ADDRESS", help = "The IP address or ip:port of the peer")]
+        address: String,
+        #[structopt(
+            name = "DURATION",
+            help = "How many seconds to ban the peer for, the configured ban duration by default"
+        )]
+        duration: Option<u64>,
+    },
+    #[structopt(
+        name = "listbanned",
+        about = "List the peers a running node has banned"
+    )]
+    ListBanned,
+    #[structopt(
+        name = "printchain",
+        about = "Print the blocks of the blockchain, newest first"
+    )]
+    PrintChain {
+        #[structopt(long, default_value = "20", help = "The number of blocks to print")]
+        limit: usize,
+        #[structopt(long, help = "Print every block, ignoring --limit")]
+        all: bool,
+        #[structopt(long, help = "Start at this block height or hash")]
+        from: Option<String>,
+        #[structopt(long, help = "The lowest block height to print")]
+        from_height: Option<usize>,
+        #[structopt(long, help = "The highest block height to print")]
+        to_height: Option<usize>,
+        #[structopt(long, help = "Walk from genesis towards the tip")]
+        reverse: bool,
+        #[structopt(long, help = "Print one line per block")]
+        summary: bool,
+        #[structopt(
+            long,
+            help = "Only print blocks with transactions touching this address"
+        )]
+        address: Option<String>,
+    },
+    #[structopt(name = "exportchain", about = "Write every block to a file")]
+    ExportChain {
+        #[structopt(name = "file", help = "The file to write the chain to")]
+        file: PathBuf,
+    },
+    #[structopt(
+        name = "export",
+        about = "Write blocks, transactions or unspent outputs as CSV or JSON lines"
+    )]
+    Export {
+        #[structopt(long, default_value = "csv", help = "The file format: csv or jsonl")]
+        format: ExportFormat,
+        #[structopt(
+            long,
+            default_value = "blocks",
+            help = "What to write a row for: blocks, transactions or utxos"
+        )]
+        what: ExportKind,
+        #[structopt(long, default_value = "0", help = "The first block height to export")]
+        from: usize,
+        #[structopt(long, help = "The last block height to export, the tip when omitted")]
+        to: Option<usize>,
+        #[structopt(name = "file", help = "The file to write the rows to")]
+        file: PathBuf,
+    },
+    #[structopt(
+        name = "importchain",
+        about = "Connect the blocks of a file written by exportchain"
+    )]
+    ImportChain {
+        #[structopt(name = "file", help = "The file to read the chain from")]
+        file: PathBuf,
+        #[structopt(
+            long,
+            default_value = "full",
+            help = "How to check blocks: full, linkage or none"
+        )]
+        verify: VerifyLevel,
+        #[structopt(long, conflicts_with = "verify", help = "Same as --verify none")]
+        no_verify: bool,
+        #[structopt(
+            long,
+            default_value = "1",
+            help = "The number of threads verifying signatures"
+        )]
+        threads: usize,
+    },
+    #[structopt(
+        name = "reindexutxo",
+        about = "Rebuild UTXO index set",
+        after_help = "Exits with 2 when --verify found the set inconsistent and rebuilt it."
+    )]
+    ReindexUtxo {
+        #[structopt(
+            long,
+            help = "Check the set first and only rebuild it when it is inconsistent"
+        )]
+        verify: bool,
+        #[structopt(
+            long,
+            conflicts_with = "verify",
+            help = "Only replay the blocks from this height onto the existing set"
+        )]
+        from: Option<usize>,
+    },
+    #[structopt(
+        name = "reindextx",
+        about = "Rebuild the index of transactions and block heights"
+    )]
+    ReindexTx,
+    #[structopt(
+        name = "resetchain",
+        about = "Delete the blockchain of the selected network, keeping the wallet"
+    )]
+    ResetChain {
+        #[structopt(long, help = "Delete without asking to confirm")]
+        yes: bool,
+    },
+    #[structopt(
+        name = "status",
+        about = "Summarize the chain, the running node and the wallet"
+    )]
+    Status,
+    #[structopt(name = "config", about = "Inspect the effective configuration")]
+    Config(ConfigCommand),
     #[structopt(name = "startnode", about = "Start a node")]
     StartNode {
-        #[structopt(name = "miner", help = "Enable mining mode and send rewerd to ADDRESS")]
+        #[structopt(long, help = "Enable mining mode and send rewards to this address")]
         miner: Option<String>,
+        #[structopt(
+            name = "ADDRESS",
+            conflicts_with = "miner",
+            help = "Deprecated, use --miner instead"
+        )]
+        legacy_miner: Option<String>,
+        #[structopt(long, help = "Listen on this address instead of the configured one")]
+        bind: Option<String>,
+        #[structopt(
+            long,
+            number_of_values = 1,
+            help = "Bootstrap from this peer instead of the central node, may be repeated"
+        )]
+        connect: Vec<String>,
+        #[structopt(long, help = "Only make outbound connections")]
+        no_listen: bool,
+        #[structopt(long, help = "Don't mine until caught up with the peers")]
+        wait_for_sync: bool,
+        #[structopt(long, help = "Mine on this many threads instead of one per core")]
+        threads: Option<usize>,
+        #[structopt(
+            long,
+            help = "Mine once this many transactions are pending instead of the network's threshold"
+        )]
+        threshold: Option<usize>,
+        #[structopt(
+            long,
+            help = "Mine whatever is pending after this many seconds without a block"
+        )]
+        mining_interval: Option<u64>,
+        #[structopt(long, help = "Serve this many peer connections at a time instead of 8")]
+        max_connections: Option<usize>,
+        #[structopt(
+            long,
+            conflicts_with_all = &["miner", "ADDRESS"],
+            help = "Sync only headers and the wallets' transactions from the first peer"
+        )]
+        light: bool,
     },
 }
 
-#[allow(clippy::too_many_lines)]
-fn main() -> Result<(), Box<dyn Error>> {
-    env_logger::builder().filter_level(LevelFilter::Info).init();
+#[derive(Debug, StructOpt)]
+enum ConfigCommand {
+    #[structopt(
+        name = "show",
+        about = "Print every setting and where its value came from"
+    )]
+    Show,
+}
+
+/// A failure reported to the user, with the exit code the process ends with.
+#[derive(Debug)]
+struct CliError {
+    code: i32,
+    message: String,
+}
+
+impl CliError {
+    fn new(code: i32, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn usage(message: impl Into<String>) -> Self {
+        Self::new(EXIT_USAGE, message)
+    }
+
+    fn state(message: impl Into<String>) -> Self {
+        Self::new(EXIT_STATE, message)
+    }
+
+    /// Wraps a failure to talk to a node, keeping its description.
+    fn network(context: &str, error: &dyn Error) -> Self {
+        Self::new(EXIT_NETWORK, format!("{context}: {error}"))
+    }
+}
+
+impl fmt::Display for CliError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl Error for CliError {}
+
+fn main() {
     let opt = Opt::from_args();
+    let json = opt.json;
+    if let Err(error) = run(opt) {
+        report_error(error.as_ref(), json);
+    }
+}
+
+#[allow(clippy::too_many_lines)]
+fn run(opt: Opt) -> Result<(), Box<dyn Error>> {
+    let json = opt.json;
+    if let Some(network) = opt.network {
+        GLOBAL_CONFIG.set_network(network);
+    }
+    if let Some(level) = opt.log_level {
+        GLOBAL_CONFIG.set_log_level(level);
+    }
+    if let Some(file) = opt.log_file {
+        GLOBAL_CONFIG.set_log_file(file);
+    }
+    if let Some(format) = opt.log_format {
+        GLOBAL_CONFIG.set_log_format(format);
+    }
+    if let Some(dir) = opt.data_dir {
+        GLOBAL_CONFIG.set_data_dir(dir);
+    }
+    if let Some(wallet) = opt.wallet {
+        GLOBAL_CONFIG.override_wallet_path(wallet);
+    }
+    if let Command::StartNode {
+        miner,
+        legacy_miner,
+        bind,
+        connect,
+        no_listen,
+        wait_for_sync,
+        threads,
+        threshold,
+        mining_interval,
+        max_connections,
+        ..
+    } = &opt.command
+    {
+        if legacy_miner.is_some() {
+            eprintln!("warning: the positional mining address is deprecated, use --miner");
+        }
+        if let Some(addr) = miner.as_ref().or(legacy_miner.as_ref()) {
+            GLOBAL_CONFIG.set_mining_addr(addr.clone());
+        }
+        if let Some(addr) = bind {
+            GLOBAL_CONFIG.set_node_addr(addr.clone());
+        }
+        if !connect.is_empty() {
+            GLOBAL_CONFIG.set_peers(connect.as_slice());
+        }
+        if *no_listen {
+            GLOBAL_CONFIG.set_listen(false);
+        }
+        if *wait_for_sync {
+            GLOBAL_CONFIG.set_wait_for_sync(true);
+        }
+        if let Some(threads) = threads {
+            GLOBAL_CONFIG.set_mining_threads(*threads);
+        }
+        if let Some(count) = threshold {
+            GLOBAL_CONFIG.set_transaction_threshold(*count);
+        }
+        if let Some(secs) = mining_interval {
+            GLOBAL_CONFIG.set_mining_interval(*secs);
+        }
+        if let Some(count) = max_connections {
+            GLOBAL_CONFIG.set_max_connections(*count);
+        }
+    }
+    if let Err(errors) = GLOBAL_CONFIG.validate() {
+        let mut message = String::from("invalid configuration");
+        for error in errors {
+            message.push_str(format!("\n  - {error}").as_str());
+        }
+        return Err(CliError::new(EXIT_CONFIG, message).into());
+    }
+    logging::init(&GLOBAL_CONFIG)?;
+    if opt.save {
+        GLOBAL_CONFIG.persist()?;
+        if !json {
+            println!("Saved settings to {}", GLOBAL_CONFIG.get_path().display());
+        }
+    }
     match opt.command {
         Command::CreateBlockchain { address } => {
-            let blockchain = Blockchain::create(address.as_str());
+            if !validate_address(address.as_str()) {
+                return Err(CliError::usage(format!("address `{address}` is not valid")).into());
+            }
+            let blockchain = Blockchain::create(address.as_str())?;
+            let genesis_hash = blockchain.get_tip_hash();
             let utxo_set = UTXOSet::new(blockchain);
             utxo_set.reindex();
-            println!("Done!");
-        }
-        Command::CreateWallet => {
-            let mut wallet = Wallets::new();
-            let address = wallet.create_wallet();
-            println!("Your new address: {address}");
-        }
-        Command::GetBalance { address } => {
-            let address_valid = validate_address(address.as_str());
-            assert!(address_valid, "Error: Address in not valid");
-            let payload = himalia::base58_decode(address.as_str());
-            let pub_key_hash = &payload[1..payload.len() - ADDRESS_CHECK_SUM_LEN];
-
-            let blockchain = Blockchain::new();
-            let utxo_set = UTXOSet::new(blockchain);
-            let utxos = utxo_set.find_utxo(pub_key_hash);
-            let mut balance = 0;
-            for utxo in utxos {
-                balance += utxo.get_value();
+            if json {
+                print_json(&json!({ "genesis_block": genesis_hash }))?;
+            } else {
+                println!("Done!");
             }
-            println!("Balance of {address}, {balance}");
         }
-        Command::ListAddresses => {
-            let wallets = Wallets::new();
-            for address in wallets.get_addresses() {
-                println!("{address}");
+        Command::Generate { count, address } => {
+            if GLOBAL_CONFIG.get_network() != Network::Regtest {
+                let message = "generate only mines on regtest, pass `--network regtest`";
+                return Err(CliError::usage(message).into());
+            }
+            if !validate_address(address.as_str()) {
+                return Err(CliError::usage(format!("address `{address}` is not valid")).into());
+            }
+            let blockchain = Blockchain::open()?;
+            let mut block_hashes = vec![];
+            for _ in 0..count {
+                let block = mine_next_block(&blockchain, address.as_str(), None)?;
+                if !json {
+                    println!("{}", block.get_hash());
+                }
+                block_hashes.push(block.get_hash().to_string());
+            }
+            if json {
+                print_json(&json!({ "blocks": block_hashes }))?;
             }
         }
-        Command::Send {
-            from,
-            to,
-            amount,
-            mine,
+        Command::Mine {
+            address,
+            count,
+            empty,
         } => {
-            assert!(
-                validate_address(from.as_str()),
-                "Error: Sender address is not valid"
-            );
-            assert!(
-                validate_address(to.as_str()),
-                "Error: Recipient address is not valid"
-            );
-            let blockchain = Blockchain::new();
-            let utxo_set = UTXOSet::new(blockchain.clone());
+            if !validate_address(address.as_str()) {
+                return Err(CliError::usage(format!("address `{address}` is not valid")).into());
+            }
+            let blockchain = Blockchain::open()?;
+            let mempool = MemoryPool::new(blockchain.get_params());
+            let mempool_tree = blockchain.get_db().open_tree(MEMPOOL_TREE)?;
+            if !empty {
+                mempool.load_from(&mempool_tree, &blockchain)?;
+            }
+            let mut blocks = vec![];
+            for _ in 0..count {
+                let pending = (!empty).then_some(&mempool);
+                let block = mine_next_block(&blockchain, address.as_str(), pending)?;
+                mempool.remove_confirmed(&block);
+                if !json {
+                    println!("{} {}", block.get_height(), block.get_hash());
+                }
+                blocks.push(json!({
+                    "hash": block.get_hash(),
+                    "height": block.get_height(),
+                    "transactions": block.get_transactions().len(),
+                }));
+            }
+            if !empty {
+                mempool.save_to(&mempool_tree)?;
+                let path = GLOBAL_CONFIG.get_mempool_snapshot_path();
+                mempool.snapshot(&blockchain).save(path.as_path())?;
+            }
+            if json {
+                print_json(&json!({ "blocks": blocks }))?;
+            }
+        }
+        Command::CreateWallet {
+            count,
+            label,
+            quiet,
+            hd,
+        } => {
+            if count == 0 {
+                return Err(CliError::usage("--count must be at least 1").into());
+            }
+            let wallets = WalletStore::new();
+            let mnemonic = (hd && !wallets.has_seed()).then(new_mnemonic);
+            let addresses = if hd {
+                wallets.derive_wallets(mnemonic.as_deref(), count, label.as_deref())?
+            } else {
+                wallets.create_wallets(count, label.as_deref())?
+            };
+            if json {
+                let created: Vec<_> = addresses
+                    .iter()
+                    .map(|address| json!({ "address": address, "label": wallets.get_label(address) }))
+                    .collect();
+                if hd {
+                    print_json(&json!({ "mnemonic": mnemonic, "wallets": created }))?;
+                } else {
+                    print_json(&created)?;
+                }
+                return Ok(());
+            }
+            if let Some(mnemonic) = &mnemonic {
+                eprintln!("Your new seed phrase: {mnemonic}");
+                eprintln!("Write it down, `recover` restores every address derived from it");
+            }
+            if quiet {
+                for address in &addresses {
+                    println!("{address}");
+                }
+            } else {
+                for address in &addresses {
+                    match wallets.get_label(address) {
+                        Some(label) => println!("Your new address: {address} ({label})"),
+                        None => println!("Your new address: {address}"),
+                    }
+                }
+            }
+        }
+        Command::Recover {
+            mnemonic,
+            gap_limit,
+        } => {
+            if gap_limit == 0 {
+                return Err(CliError::usage("--gap-limit must be at least 1").into());
+            }
+            let blockchain = Blockchain::open()?;
+            let recovered =
+                WalletStore::new().recover(mnemonic.as_str(), &blockchain, gap_limit)?;
+            if json {
+                print_json(&json!({ "recovered": recovered }))?;
+            } else if recovered.is_empty() {
+                println!("No used addresses found for the seed");
+            } else {
+                for address in &recovered {
+                    println!("Recovered address: {address}");
+                }
+            }
+        }
+        Command::BackupWallet { dir } => {
+            let wallets = Wallets::new();
+            let path = wallets.backup(dir.as_deref())?;
+            if json {
+                print_json(&json!({ "path": path }))?;
+            } else {
+                println!("Wallet backed up to {}", path.display());
+            }
+        }
+        Command::SignMessage {
+            address,
+            message,
+            file,
+        } => {
+            if !validate_address(address.as_str()) {
+                return Err(CliError::usage(format!("address `{address}` is not valid")).into());
+            }
+            let message = match (message, file) {
+                (Some(message), _) => message.into_bytes(),
+                (None, Some(file)) => std::fs::read(file)?,
+                (None, None) => {
+                    let mut message = vec![];
+                    io::stdin().read_to_end(&mut message)?;
+                    message
+                }
+            };
+            let wallets = Wallets::new();
+            let wallet = signing_wallet(&wallets, address.as_str())?;
+            let signature = wallet.sign_message(message.as_slice())?;
+            let envelope = encode_message_envelope(wallet.get_public_key(), signature.as_slice());
+            let signature = BASE64.encode(signature.as_slice());
+            let public_key = HEXLOWER.encode(wallet.get_public_key());
+            if json {
+                print_json(&json!({
+                    "address": address,
+                    "signature": signature,
+                    "public_key": public_key,
+                    "envelope": envelope,
+                }))?;
+            } else {
+                println!("Signature: {signature}");
+                println!("Public key: {public_key}");
+                println!("Envelope: {envelope}");
+            }
+        }
+        Command::VerifyMessage {
+            address,
+            message,
+            signature,
+            public_key,
+        } => {
+            let valid = if let Some(public_key) = public_key {
+                let signature = BASE64
+                    .decode(signature.as_bytes())
+                    .map_err(|_| CliError::usage("the signature is not valid base64"))?;
+                let public_key = HEXLOWER_PERMISSIVE
+                    .decode(public_key.as_bytes())
+                    .map_err(|_| CliError::usage("the public key is not valid hex"))?;
+                verify_message(
+                    address.as_str(),
+                    message.as_bytes(),
+                    signature.as_slice(),
+                    public_key.as_slice(),
+                )
+            } else {
+                verify_message_envelope(address.as_str(), message.as_bytes(), signature.as_str())
+            };
+            if !valid {
+                return Err(
+                    CliError::usage(format!("the signature is not valid for {address}")).into(),
+                );
+            }
+            if json {
+                print_json(&json!({ "address": address, "valid": true }))?;
+            } else {
+                println!("The signature is valid for {address}");
+            }
+        }
+        Command::GetBalance {
+            address: None,
+            include_mempool,
+            ..
+        }
+        | Command::GetBalance {
+            all: true,
+            include_mempool,
+            ..
+        } => {
+            let utxo_set = UTXOSet::new(Blockchain::open()?);
+            let mempool = load_mempool()?;
+            let balances = Wallets::new().get_balances(&utxo_set, mempool.as_ref());
+            if json && include_mempool {
+                let balances: Vec<_> = balances
+                    .iter()
+                    .map(|balance| {
+                        let mut value = json!(balance);
+                        value["pending_balance"] = json!(balance.pending_balance());
+                        value
+                    })
+                    .collect();
+                print_json(&balances)?;
+            } else if json {
+                print_json(&balances)?;
+            } else {
+                print_balances(balances.as_slice(), include_mempool);
+            }
+        }
+        Command::GetBalance {
+            address: Some(address),
+            include_mempool,
+            ..
+        } => {
+            let Some(pub_key_hash) = address_pub_key_hash(address.as_str()) else {
+                return Err(CliError::usage(format!("address `{address}` is not valid")).into());
+            };
 
+            // A running node answers with its live mempool, otherwise the snapshot it
+            // left behind is used.
+            let (balance, pending_in, pending_out) = if let Some(blockchain) = open_or_rpc()? {
+                let utxo_set = UTXOSet::new(blockchain);
+                let utxos = utxo_set.find_utxo(pub_key_hash.as_slice());
+                let mut balance = 0;
+                for utxo in utxos {
+                    balance += utxo.get_value();
+                }
+                let (pending_in, pending_out) =
+                    load_mempool()?.map_or((0, 0), |mempool| mempool.get_pending(address.as_str()));
+                (balance, pending_in, pending_out)
+            } else {
+                let result = rpc_call("getbalance", &json!([address]))?;
+                (
+                    result["confirmed"].as_u64().unwrap_or_default(),
+                    result["pending_in"].as_u64().unwrap_or_default(),
+                    result["pending_out"].as_u64().unwrap_or_default(),
+                )
+            };
+            let pending_balance = pending_balance(balance, pending_in, pending_out);
+            if json {
+                let mut value = json!({
+                    "address": address,
+                    "confirmed": balance,
+                    "pending": i128::from(pending_in) - i128::from(pending_out),
+                    "pending_in": pending_in,
+                    "pending_out": pending_out,
+                });
+                if include_mempool {
+                    value["pending_balance"] = json!(pending_balance);
+                }
+                print_json(&value)?;
+            } else if include_mempool {
+                println!(
+                    "Balance of {address}, {pending_balance} (confirmed {balance}, pending +{pending_in} -{pending_out})"
+                );
+            } else {
+                println!("Balance of {address}, {balance} (pending +{pending_in} -{pending_out})");
+            }
+        }
+        Command::ListAddresses => {
+            let wallets = Wallets::new();
+            let mut addresses = wallets.get_addresses();
+            addresses.sort();
+            let mut watch_only = wallets.get_watch_only_addresses();
+            watch_only.sort();
+            addresses.extend(watch_only);
+            if json {
+                let listed: Vec<_> = addresses
+                    .iter()
+                    .map(|address| {
+                        json!({
+                            "address": address,
+                            "label": wallets.get_label(address),
+                            "watch_only": wallets.is_watch_only(address),
+                        })
+                    })
+                    .collect();
+                print_json(&listed)?;
+            } else {
+                println!("{:<36}  label", "address");
+                for address in addresses {
+                    let label = wallets.get_label(address.as_str()).unwrap_or("-");
+                    if wallets.is_watch_only(address.as_str()) {
+                        println!("{address:<36}  {label:<16}  (watch-only)");
+                    } else {
+                        println!("{address:<36}  {label}");
+                    }
+                }
+            }
+        }
+        Command::SetLabel { address, label } => {
+            WalletStore::new().set_label(address.as_str(), label.as_deref())?;
+            if json {
+                print_json(&json!({ "address": address, "label": label }))?;
+            } else if let Some(label) = label {
+                println!("Labelled {address} {label}");
+            } else {
+                println!("Removed the label of {address}");
+            }
+        }
+        Command::ImportAddress { address, label } => {
+            WalletStore::new().import_address(address.as_str(), label.as_deref())?;
+            if json {
+                print_json(&json!({ "address": address, "watch_only": true }))?;
+            } else {
+                println!("Watching {address}");
+            }
+        }
+        Command::DumpPrivKey { address } => {
+            let wallets = Wallets::new();
+            let key = signing_wallet(&wallets, address.as_str())?.export_key();
+            if json {
+                print_json(&json!({ "address": address, "key": key }))?;
+            } else {
+                println!("{key}");
+            }
+        }
+        Command::ImportPrivKey { key, label } => {
+            let wallet = Wallet::from_exported_key(key.as_str())?;
+            let address = wallet.get_address();
+            let imported = WalletStore::new().import_key(wallet, label.as_deref())?;
+            if !imported {
+                eprintln!(
+                    "Warning: the key of {address} is already in the wallet, nothing was imported"
+                );
+            }
+            if json {
+                print_json(&json!({ "address": address, "imported": imported }))?;
+            } else {
+                println!("{address}");
+            }
+        }
+        Command::GetWalletBalance => {
+            let utxo_set = UTXOSet::new(Blockchain::open()?);
+            let mempool = load_mempool()?;
+            let balances = Wallets::new().get_balances(&utxo_set, mempool.as_ref());
+            let balance = WalletBalance::new(balances);
+            if json {
+                print_json(&balance)?;
+            } else {
+                print_balances(balance.addresses.as_slice(), false);
+                println!();
+                println!(
+                    "Balance:    {} (pending +{} -{})",
+                    balance.confirmed, balance.pending_in, balance.pending_out
+                );
+                println!(
+                    "Watch-only: {} (pending +{} -{})",
+                    balance.watch_only_confirmed,
+                    balance.watch_only_pending_in,
+                    balance.watch_only_pending_out
+                );
+            }
+        }
+        Command::Send {
+            from,
+            to,
+            amount,
+            mine,
+            also_to,
+            allow_duplicates,
+            fee,
+            fee_rate,
+            yes,
+            allow_high_fee,
+            dry_run,
+            no_sign,
+            raw,
+            signer,
+            node,
+        } => {
+            let from = resolve_sender(from)?;
+            if amount == 0 {
+                return Err(CliError::usage("amount must be positive").into());
+            }
+            let mut recipients = vec![Recipient {
+                address: to.clone(),
+                amount,
+            }];
+            recipients.extend(also_to);
+            check_recipients(recipients.as_slice(), allow_duplicates)?;
+            let total = recipients
+                .iter()
+                .try_fold(0_u64, |total, recipient| {
+                    total.checked_add(recipient.amount)
+                })
+                .ok_or(TransactionError::AmountOverflow)?;
+            let to = if recipients.len() > 1 {
+                format!("{} recipients", recipients.len())
+            } else {
+                to
+            };
+            let node = node.unwrap_or_else(|| GLOBAL_CONFIG.get_node_addr());
+            if !is_valid_addr(node.as_str()) {
+                let message = format!("node address `{node}` is not a valid host:port");
+                return Err(CliError::usage(message).into());
+            }
+            let Some(blockchain) = open_or_rpc()? else {
+                if mine == MINE_TRUE || dry_run {
+                    let message = "a node is running, stop it to mine or dry run locally";
+                    return Err(CliError::state(message).into());
+                }
+                if signer != SignerKind::Wallet {
+                    let message =
+                        format!("a node is running, stop it to sign with --signer {signer}");
+                    return Err(CliError::state(message).into());
+                }
+                let Some(fee) = fee else {
+                    let message = "--fee is required while a node is running";
+                    return Err(CliError::usage(message).into());
+                };
+                if recipients.len() > 1 {
+                    let message = "a node is running, stop it to pay several recipients";
+                    return Err(CliError::state(message).into());
+                }
+                confirm_send(amount, to.as_str(), fee, yes, allow_high_fee, json)?;
+                let txid = rpc_call("sendtoaddress", &json!([from, to, amount, fee]))?;
+                if json {
+                    print_json(&json!({ "txid": txid, "fee": fee, "mined_block": null }))?;
+                } else {
+                    println!("Success!");
+                }
+                return Ok(());
+            };
+            let utxo_set = UTXOSet::new(blockchain.clone());
+            let wallets = WalletStore::new();
+            let external = configured_signer(signer)?;
+            let signing = match (&external, no_sign) {
+                (Some(external), _) => Signing::Signer(external.as_ref()),
+                (None, true) => Signing::Unsigned(&wallets),
+                (None, false) => Signing::Wallet(&wallets),
+            };
+            let fee = if let Some(fee) = fee {
+                fee
+            } else {
+                let fee_rate = match fee_rate {
+                    Some(fee_rate) => fee_rate,
+                    None => load_mempool()?.map_or(0.0, |snapshot| snapshot.estimate_fee_rate()),
+                };
+                if !fee_rate.is_finite() || fee_rate < 0.0 {
+                    return Err(CliError::usage("fee rate must not be negative").into());
+                }
+                // Sized without a fee, which may select fewer inputs than the final one.
+                let draft = match &external {
+                    Some(external) => {
+                        let draft_signer = DraftSigner(external.public_key());
+                        let draft_signing = Signing::Signer(&draft_signer);
+                        build_transaction(&utxo_set, from.as_str(), &recipients, 0, &draft_signing)?
+                    }
+                    None => build_transaction(
+                        &utxo_set,
+                        from.as_str(),
+                        &recipients,
+                        0,
+                        &Signing::Wallet(&wallets),
+                    )?,
+                };
+                fee_for_rate(fee_rate, draft.serialize().len())
+                    .ok_or_else(|| CliError::usage("fee rate is too high"))?
+            };
+            if dry_run {
+                return print_dry_run(
+                    &utxo_set,
+                    from.as_str(),
+                    &recipients,
+                    fee,
+                    &signing,
+                    raw,
+                    json,
+                );
+            }
+            confirm_send(total, to.as_str(), fee, yes, allow_high_fee, json)?;
+            let transaction =
+                build_transaction(&utxo_set, from.as_str(), &recipients, fee, &signing)?;
+            let txid = transaction.get_id().to_string();
+            let mined_block = mine_or_submit(
+                &blockchain,
+                &utxo_set,
+                from.as_str(),
+                transaction,
+                mine == MINE_TRUE,
+                node.as_str(),
+            )?;
+            if json {
+                print_json(&json!({ "txid": txid, "fee": fee, "mined_block": mined_block }))?;
+            } else {
+                println!("Success!");
+            }
+        }
+        Command::SendMany {
+            from,
+            recipients,
+            fee,
+            mine,
+            allow_duplicates,
+            dry_run,
+            no_sign,
+            raw,
+        } => {
+            let from = resolve_sender(from)?;
+            check_recipients(recipients.as_slice(), allow_duplicates)?;
+            let blockchain = Blockchain::open()?;
+            let utxo_set = UTXOSet::new(blockchain.clone());
+            let wallets = WalletStore::new();
+            if dry_run {
+                let signing = if no_sign {
+                    Signing::Unsigned(&wallets)
+                } else {
+                    Signing::Wallet(&wallets)
+                };
+                return print_dry_run(
+                    &utxo_set,
+                    from.as_str(),
+                    &recipients,
+                    fee,
+                    &signing,
+                    raw,
+                    json,
+                );
+            }
+
+            let transaction = Transaction::new_multi_recipient_transaction(
+                from.as_str(),
+                recipients.as_slice(),
+                fee,
+                &utxo_set,
+                &wallets,
+            )?;
+            let txid = transaction.get_id().to_string();
+            let node_addr = GLOBAL_CONFIG.get_node_addr();
+            let mined_block = mine_or_submit(
+                &blockchain,
+                &utxo_set,
+                from.as_str(),
+                transaction,
+                mine,
+                node_addr.as_str(),
+            )?;
+            if json {
+                print_json(&json!({ "txid": txid, "mined_block": mined_block }))?;
+            } else {
+                println!("Txid: {txid}");
+                if let Some(hash) = mined_block {
+                    println!("Mined block: {hash}");
+                }
+            }
+        }
+        Command::Sweep { to, fee, mine } => {
+            if !validate_address(to.as_str()) {
+                return Err(
+                    CliError::usage(format!("recipient address `{to}` is not valid")).into(),
+                );
+            }
+            let blockchain = Blockchain::open()?;
+            let utxo_set = UTXOSet::new(blockchain.clone());
+            let wallets = Wallets::new();
+            let mut addresses = wallets.get_addresses();
+            addresses.sort();
+            let keys: Vec<Wallet> = addresses
+                .iter()
+                .filter_map(|address| wallets.get_wallet(address).cloned())
+                .collect();
             let transaction =
-                Transaction::new_utxo_transaction(from.as_str(), to.as_str(), amount, &utxo_set);
-
-            if mine == MINE_TRUE {
-                let coinbase_tx = Transaction::new_coinbase_tx(from.as_str());
-                let block = blockchain.mine_block(&[transaction, coinbase_tx]);
-                utxo_set.update(&block);
-            } else {
-                send_tx(CENTRAL_NODE, &transaction)?;
-            }
-            println!("Success!");
-        }
-        Command::PrintChain => {
-            let mut block_iterator = Blockchain::new().iterator();
-            loop {
-                let option = block_iterator.next();
-                if option.is_none() {
-                    break;
-                }
-                let block = option.unwrap();
-                println!("Pre block hash: {}", block.get_pre_block_hash());
-                println!("Cur block hash: {}", block.get_hash());
-                println!("Pre block timestamp: {}", block.get_timestamp());
-                for tx in block.get_transactions() {
-                    let cur_txid_hex = HEXLOWER.encode(tx.get_id());
-                    println!("– Transaction txid_hex: {cur_txid_hex}");
-                    if !tx.is_coinbase() {
-                        for input in tx.get_vin() {
-                            let txid_hex = HEXLOWER.encode(input.get_txid());
-                            let pub_key_hash = wallet::hash_pub_key(input.get_pub_key());
-                            let address = wallet::convert_address(pub_key_hash.as_slice());
-                            println!(
-                                "-- Input txid = {txid_hex}, vout = {}, from = {address}",
-                                input.get_vout()
-                            );
+                match Transaction::new_sweep_transaction(to.as_str(), fee, &utxo_set, &keys) {
+                    Ok(transaction) => transaction,
+                    Err(
+                        e @ (TransactionError::NothingToSweep
+                        | TransactionError::AlreadyConsolidated(_)),
+                    ) => {
+                        if json {
+                            print_json(&json!({ "txid": null, "reason": e.to_string() }))?;
+                        } else {
+                            println!("Nothing to do, {e}");
                         }
+                        return Ok(());
                     }
-                    for output in tx.get_vout() {
-                        let pub_key_hash = output.get_pub_key_hash();
-                        let address = wallet::convert_address(pub_key_hash);
-                        println!("-- Output value = {}, to = {address}", output.get_value());
-                    }
+                    Err(e) => return Err(e.into()),
+                };
+            let txid = transaction.get_id().to_string();
+            let inputs = transaction.get_vin().len();
+            let amount = transaction.get_output_value()?;
+            let node_addr = GLOBAL_CONFIG.get_node_addr();
+            let mined_block = mine_or_submit(
+                &blockchain,
+                &utxo_set,
+                to.as_str(),
+                transaction,
+                mine,
+                node_addr.as_str(),
+            )?;
+            if json {
+                print_json(&json!({
+                    "txid": txid,
+                    "inputs": inputs,
+                    "amount": amount,
+                    "mined_block": mined_block,
+                }))?;
+            } else {
+                println!("Swept {amount} from {inputs} outputs to {to}");
+                println!("Txid: {txid}");
+                if let Some(hash) = mined_block {
+                    println!("Mined block: {hash}");
                 }
-                println!();
             }
         }
-        Command::ReindexUtxo => {
-            let blockchain = Blockchain::new();
+        Command::SendRawTransaction { hex } => {
+            let transaction =
+                Transaction::from_hex(hex.as_str()).map_err(|e| CliError::usage(e.to_string()))?;
+            let txid = if let Some(blockchain) = open_or_rpc()? {
+                // Checked against a pool of its own, the central node checks it again.
+                MemoryPool::new(blockchain.get_params())
+                    .try_add(transaction.clone(), &blockchain)
+                    .map_err(|e| CliError::state(e.to_string()))?;
+                let central_node = central_node();
+                submit_tx(central_node.as_str(), &transaction).map_err(|e| {
+                    CliError::network(format!("unable to send to {central_node}").as_str(), &*e)
+                })?;
+                transaction.get_id().to_string()
+            } else {
+                let txid = rpc_call("sendrawtransaction", &json!([hex]))?;
+                String::from(txid.as_str().unwrap_or_default())
+            };
+            if json {
+                print_json(&json!({ "txid": txid }))?;
+            } else {
+                println!("{txid}");
+            }
+        }
+        Command::CreateRawTx { inputs, outputs } => {
+            let tx = create_raw_transaction(inputs.as_slice(), outputs.as_slice())?;
+            print_raw_tx(&tx, json)?;
+        }
+        Command::SignRawTx {
+            hex,
+            address,
+            prev_outputs,
+        } => {
+            let mut tx = raw_tx_from_hex(hex.as_str())?;
+            let wallets = Wallets::new();
+            let wallet = signing_wallet(&wallets, address.as_str())?;
+            let prev_outputs = if prev_outputs.is_empty() {
+                let blockchain = Blockchain::open()?;
+                let utxo_set = UTXOSet::new(blockchain);
+                tx.get_vin()
+                    .iter()
+                    .map(|input| {
+                        utxo_set
+                            .get_output(input.get_txid(), input.get_vout())
+                            .ok_or_else(|| {
+                                CliError::state(format!(
+                                    "output {}:{} is not unspent",
+                                    input.get_txid(),
+                                    input.get_vout()
+                                ))
+                            })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?
+            } else {
+                prev_outputs
+                    .iter()
+                    .map(|output| TXOutput::new(output.amount, output.address.as_str()))
+                    .collect::<Result<Vec<_>, _>>()?
+            };
+            tx.sign_with(wallet.get_pksc8(), prev_outputs.as_slice())?;
+            print_raw_tx(&tx, json)?;
+        }
+        Command::SendRawTx { hex, node } => {
+            let node = node.unwrap_or_else(|| GLOBAL_CONFIG.get_node_addr());
+            if !is_valid_addr(node.as_str()) {
+                return Err(CliError::usage(format!("node address `{node}` is not valid")).into());
+            }
+            let tx = raw_tx_from_hex(hex.as_str())?;
+            send_tx(node.as_str(), &tx).map_err(|e| {
+                CliError::network(format!("unable to send to {node}").as_str(), &*e)
+            })?;
+            if json {
+                print_json(&json!({ "txid": tx.get_id().to_string() }))?;
+            } else {
+                println!("{}", tx.get_id());
+            }
+        }
+        Command::GetBlock {
+            block,
+            verbose,
+            raw,
+        } => show_block(block.as_str(), verbose, raw, json)?,
+        Command::GetBlockByHeight {
+            height,
+            verbose,
+            raw,
+        } => show_block(height.to_string().as_str(), verbose, raw, json)?,
+        Command::GetTransaction { txid, raw } => {
+            let Ok(txid) = txid.parse::<Txid>() else {
+                return Err(CliError::usage("transaction id is not a valid hash").into());
+            };
+            let Some(blockchain) = open_or_rpc()? else {
+                let txid = txid.to_string();
+                let result = rpc_call("gettransaction", &json!([txid, raw]))?;
+                if raw && json {
+                    print_json(&json!({ "hex": result }))?;
+                } else if raw {
+                    println!("{}", result.as_str().unwrap_or_default());
+                } else if json {
+                    print_json(&result)?;
+                } else {
+                    print_transaction(&serde_json::from_value(result)?);
+                }
+                return Ok(());
+            };
+            let Some(block) = blockchain.get_transaction_block(txid) else {
+                return Err(CliError::usage("transaction not found").into());
+            };
+            let Some(tx) = block
+                .get_transactions()
+                .iter()
+                .find(|tx| tx.get_id() == txid)
+            else {
+                return Err(CliError::state(
+                    "the transaction index is out of date, rebuild it with `reindextx`",
+                )
+                .into());
+            };
+            if raw && json {
+                print_json(&json!({ "hex": HEXLOWER.encode(tx.serialize().as_slice()) }))?;
+            } else if raw {
+                println!("{}", HEXLOWER.encode(tx.serialize().as_slice()));
+            } else if json {
+                print_json(&TransactionDetail::new(tx, Some(&block), &blockchain))?;
+            } else {
+                print_transaction(&TransactionDetail::new(tx, Some(&block), &blockchain));
+            }
+        }
+        Command::Mempool { address } => {
+            let Some(mut snapshot) = load_mempool()? else {
+                let message = format!(
+                    "no mempool found at {}, start a node with `startnode` to create one",
+                    GLOBAL_CONFIG.get_mempool_snapshot_path().display()
+                );
+                return Err(CliError::state(message).into());
+            };
+            if let Some(address) = address {
+                snapshot
+                    .entries
+                    .retain(|entry| entry.addresses.contains(&address));
+            }
+            if json {
+                print_json(&snapshot)?;
+            } else {
+                print_mempool(&snapshot);
+            }
+        }
+        Command::GetPeerInfo => {
+            let path = GLOBAL_CONFIG.get_node_status_path();
+            let Some(node) = NodeStatus::load(path.as_path())? else {
+                let message = format!(
+                    "no node status found at {}, start a node with `startnode` to create one",
+                    path.display()
+                );
+                return Err(CliError::state(message).into());
+            };
+            if json {
+                let peers: Vec<serde_json::Value> = node
+                    .peers
+                    .iter()
+                    .map(|peer| json!({ "address": peer }))
+                    .collect();
+                print_json(&peers)?;
+            } else if node.peers.is_empty() {
+                println!("No peers");
+            } else {
+                for peer in &node.peers {
+                    println!("{peer}");
+                }
+            }
+        }
+        Command::SetBan { address, duration } => {
+            let result = rpc_call("setban", &json!([address, duration]))?;
+            if json {
+                print_json(&result)?;
+            } else if let Some(unbanned) = result["unbanned"].as_bool() {
+                let ip = result["address"].as_str().unwrap_or_default();
+                if unbanned {
+                    println!("Lifted the ban on {ip}");
+                } else {
+                    println!("{ip} is not banned");
+                }
+            } else {
+                let ban: Ban = serde_json::from_value(result)?;
+                println!(
+                    "Banned {} for {}s",
+                    ban.address,
+                    (ban.banned_until - himalia::current_timestamp()) / 1000
+                );
+            }
+        }
+        Command::ListBanned => {
+            let bans: Vec<Ban> = serde_json::from_value(rpc_call("listbanned", &json!([]))?)?;
+            if json {
+                print_json(&bans)?;
+            } else if bans.is_empty() {
+                println!("No banned peers");
+            } else {
+                let now = himalia::current_timestamp();
+                for ban in &bans {
+                    println!(
+                        "{:<39}  {:>8}s left  {}",
+                        ban.address,
+                        (ban.banned_until - now) / 1000,
+                        ban.reason
+                    );
+                }
+            }
+        }
+        Command::ListTransactions {
+            address: Some(address),
+            limit,
+        } => {
+            let Some(pub_key_hash) = address_pub_key_hash(address.as_str()) else {
+                return Err(CliError::usage(format!("address `{address}` is not valid")).into());
+            };
+            let mut records = Blockchain::open()?.get_address_history(pub_key_hash.as_slice());
+            records.truncate(limit.unwrap_or(usize::MAX));
+            if json {
+                print_json(&records)?;
+            } else if records.is_empty() {
+                println!("No transactions for {address}");
+            } else {
+                print_tx_records(&records);
+            }
+        }
+        Command::ListTransactions {
+            address: None,
+            limit,
+        } => {
+            let blockchain = Blockchain::open()?;
+            let wallets = Wallets::new();
+            let mut addresses = wallets.get_addresses();
+            addresses.extend(wallets.get_watch_only_addresses());
+            addresses.sort();
+            let mut listed = vec![];
+            for address in addresses {
+                let Some(pub_key_hash) = address_pub_key_hash(address.as_str()) else {
+                    continue;
+                };
+                let mut records = blockchain.get_address_history(pub_key_hash.as_slice());
+                records.truncate(limit.unwrap_or(usize::MAX));
+                if !records.is_empty() {
+                    let watch_only = wallets.is_watch_only(address.as_str());
+                    listed.push((address, watch_only, records));
+                }
+            }
+            if json {
+                let listed: Vec<_> = listed
+                    .iter()
+                    .map(|(address, watch_only, records)| {
+                        json!({
+                            "address": address,
+                            "watch_only": watch_only,
+                            "transactions": records,
+                        })
+                    })
+                    .collect();
+                print_json(&listed)?;
+            } else if listed.is_empty() {
+                println!("No transactions for the wallet");
+            } else {
+                for (address, watch_only, records) in &listed {
+                    let watch_only = if *watch_only { " (watch-only)" } else { "" };
+                    println!("{address}{watch_only}");
+                    print_tx_records(records);
+                    println!();
+                }
+            }
+        }
+        Command::PrintChain {
+            limit,
+            all,
+            from,
+            from_height,
+            to_height,
+            reverse,
+            summary,
+            address,
+        } => {
+            let limit = if all { usize::MAX } else { limit };
+            if from_height.is_some() || to_height.is_some() {
+                if from.is_some() {
+                    return Err(CliError::usage(
+                        "--from can't be combined with --from-height or --to-height",
+                    )
+                    .into());
+                }
+                let blockchain = Blockchain::open()?;
+                let from_height = from_height.unwrap_or(0);
+                let best_height = blockchain.get_best_height();
+                let to_height =
+                    to_height.map_or(best_height, |to_height| to_height.min(best_height));
+                if to_height < from_height {
+                    return Err(
+                        CliError::usage("--to-height must not be below --from-height").into(),
+                    );
+                }
+                let mut blocks = blockchain.export_range(from_height..=to_height);
+                if !reverse {
+                    blocks.reverse();
+                }
+                let blocks = blocks
+                    .iter()
+                    .filter(|block| {
+                        address
+                            .as_deref()
+                            .is_none_or(|address| summary_touches(block, address))
+                    })
+                    .take(limit);
+                for block in blocks {
+                    print_chain_summary(block, json, summary)?;
+                }
+                return Ok(());
+            }
+            let pub_key_hash = match address {
+                Some(address) => {
+                    let Some(pub_key_hash) = address_pub_key_hash(address.as_str()) else {
+                        return Err(
+                            CliError::usage(format!("address `{address}` is not valid")).into()
+                        );
+                    };
+                    Some(pub_key_hash)
+                }
+                None => None,
+            };
+            let blockchain = Blockchain::open()?;
+            let start = match from {
+                Some(id) => Some(
+                    blockchain
+                        .find_block(id.as_str())
+                        .ok_or_else(|| CliError::state(format!("block `{id}` not found")))?,
+                ),
+                None => None,
+            };
+            let blocks: Box<dyn Iterator<Item = Block>> = if reverse {
+                let mut iterator = blockchain.forward_iterator(start.map_or(0, |b| b.get_height()));
+                Box::new(std::iter::from_fn(move || iterator.next()))
+            } else {
+                let mut iterator = start.map_or_else(
+                    || blockchain.iterator(),
+                    |b| blockchain.iterator_from(b.get_hash()),
+                );
+                Box::new(std::iter::from_fn(move || iterator.next()))
+            };
+            let blocks = blocks
+                .filter(|block| {
+                    pub_key_hash
+                        .as_deref()
+                        .is_none_or(|pub_key_hash| block_touches(block, pub_key_hash))
+                })
+                .take(limit);
+            for block in blocks {
+                let block = if summary && !json {
+                    BlockSummary::new(&block, false)
+                } else {
+                    BlockSummary::resolve(&block, &blockchain)
+                };
+                print_chain_summary(&block, json, summary)?;
+            }
+        }
+        Command::ExportChain { file } => {
+            let blockchain = Blockchain::open()?;
+            let summary = export_chain(&blockchain, file.as_path(), |summary| {
+                if !json {
+                    eprintln!(
+                        "Exported {} blocks, {} bytes",
+                        summary.blocks, summary.bytes
+                    );
+                }
+            })?;
+            if json {
+                print_json(&summary)?;
+            } else {
+                println!(
+                    "Exported {} blocks, {} bytes, tip {}",
+                    summary.blocks, summary.bytes, summary.tip_hash
+                );
+            }
+        }
+        Command::Export {
+            format,
+            what,
+            from,
+            to,
+            file,
+        } => {
+            if to.is_some_and(|to| to < from) {
+                return Err(CliError::usage("--to must not be below --from").into());
+            }
+            let blockchain = Blockchain::open()?;
+            let summary = export(&blockchain, file.as_path(), format, what, from, to)?;
+            if json {
+                print_json(&summary)?;
+            } else {
+                println!(
+                    "Exported {} rows of {what} from {} blocks to {}",
+                    summary.rows,
+                    summary.blocks,
+                    file.display()
+                );
+            }
+        }
+        Command::ImportChain {
+            file,
+            verify,
+            no_verify,
+            threads,
+        } => {
+            let level = if no_verify { VerifyLevel::None } else { verify };
+            let summary = import_chain(file.as_path(), level, threads, |summary| {
+                if !json {
+                    eprintln!(
+                        "Imported {} blocks, skipped {} known blocks, read {} bytes",
+                        summary.blocks, summary.skipped, summary.bytes
+                    );
+                }
+            })?;
+            if json {
+                print_json(&summary)?;
+            } else {
+                if let Some(offset) = summary.resumed_from {
+                    println!("Resumed an interrupted import at byte {offset}");
+                }
+                println!(
+                    "Imported {} blocks, skipped {} known blocks, read {} bytes, tip {}",
+                    summary.blocks, summary.skipped, summary.bytes, summary.tip_hash
+                );
+            }
+        }
+        Command::ReindexUtxo { verify, from } => {
+            let blockchain = Blockchain::open()?;
+            let total = blockchain.get_best_height() + 1;
             let utxo_set = UTXOSet::new(blockchain);
-            utxo_set.reindex();
-            let count = utxo_set.count_transactions();
-            println!("Done! There are {count} transactions in the UTXO set.");
+            let before = utxo_set.get_stats();
+            let started = Instant::now();
+            let progress = |action: &str, blocks: usize, total: usize| {
+                if json || !blocks.is_multiple_of(UTXO_PROGRESS_INTERVAL) {
+                    return;
+                }
+                let as_f64 = |n: usize| f64::from(u32::try_from(n).unwrap_or(u32::MAX));
+                let rate = as_f64(blocks) / started.elapsed().as_secs_f64().max(0.001);
+                let eta = as_f64(total.saturating_sub(blocks)) / rate;
+                eprintln!("{action} {blocks}/{total} blocks, {rate:.0} blocks/s, ETA {eta:.0}s");
+            };
+            let diff = if verify {
+                let diff = utxo_set.verify(|blocks| progress("Verified", blocks, total));
+                if !json {
+                    print_utxo_diff(&diff);
+                }
+                Some(diff)
+            } else {
+                None
+            };
+            let rebuilt = diff.as_ref().is_none_or(|diff| !diff.is_consistent());
+            if let Some(height) = from {
+                if height >= total {
+                    let message = format!("height {height} is above the tip at {}", total - 1);
+                    return Err(CliError::state(message).into());
+                }
+                let blocks = total - height;
+                utxo_set.catch_up(height, |done| progress("Replayed", done, blocks))?;
+            } else if rebuilt {
+                utxo_set.reindex_with_progress(|blocks| progress("Reindexed", blocks, total));
+            }
+            let after = utxo_set.get_stats();
+            if json {
+                print_json(&json!({
+                    "transactions": after.transactions,
+                    "rebuilt": rebuilt,
+                    "diff": diff,
+                    "before": before,
+                    "after": after,
+                }))?;
+            } else {
+                println!(
+                    "Before: {} transactions, {} outputs, value {}",
+                    before.transactions, before.outputs, before.total_value
+                );
+                println!(
+                    "After:  {} transactions, {} outputs, value {}",
+                    after.transactions, after.outputs, after.total_value
+                );
+            }
+            if diff.is_some_and(|diff| !diff.is_consistent()) {
+                let message = "the UTXO set was inconsistent and has been rebuilt";
+                return Err(CliError::state(message).into());
+            }
         }
-        Command::StartNode { miner } => {
-            if let Some(addr) = miner {
-                assert!(validate_address(addr.as_str()), "Wrong miner address");
-                println!("Mining is on. Address to receive rewards: {addr}");
-                GLOBAL_CONFIG.set_mining_addr(addr);
+        Command::ReindexTx => {
+            let blockchain = Blockchain::open()?;
+            let started = Instant::now();
+            let transactions = blockchain.reindex_transactions()?;
+            let blocks = blockchain.get_best_height() + 1;
+            if json {
+                print_json(&json!({ "blocks": blocks, "transactions": transactions }))?;
+            } else {
+                println!(
+                    "Indexed {transactions} transactions in {blocks} blocks in {:.1}s",
+                    started.elapsed().as_secs_f64()
+                );
+            }
+        }
+        Command::ResetChain { yes } => {
+            let data_dir = GLOBAL_CONFIG.get_data_dir();
+            if let Err(BlockchainError::Database(_)) = Blockchain::open() {
+                let message = format!(
+                    "the blockchain in {} is in use, stop the running node first",
+                    data_dir.display()
+                );
+                return Err(CliError::state(message).into());
+            }
+            let question = format!(
+                "Delete the blockchain in {}? The wallet is kept.",
+                data_dir.display()
+            );
+            if !yes && !confirm(question.as_str())? {
+                return Err(CliError::usage("aborted").into());
+            }
+            Blockchain::reset()?;
+            let mut removed = vec![];
+            for path in [
+                GLOBAL_CONFIG.get_mempool_snapshot_path(),
+                GLOBAL_CONFIG.get_node_status_path(),
+            ] {
+                if path.exists() {
+                    std::fs::remove_file(path.as_path())?;
+                    removed.push(path);
+                }
             }
-            let blockchain = Blockchain::new();
+            if json {
+                print_json(&json!({ "data_dir": data_dir, "removed_files": removed }))?;
+            } else {
+                println!("Deleted the blockchain in {}", data_dir.display());
+            }
+        }
+        Command::Status => {
+            let wallet_count = Wallets::new().get_addresses().len();
+            let (node_running, chain) = match Blockchain::open() {
+                Ok(blockchain) => (false, Some(blockchain.get_stats())),
+                Err(BlockchainError::NotFound) => (false, None),
+                // The database is locked while a node is running.
+                Err(BlockchainError::Database(_)) => (true, None),
+                Err(e) => return Err(e.into()),
+            };
+            let node = if node_running {
+                NodeStatus::load(GLOBAL_CONFIG.get_node_status_path().as_path())?
+            } else {
+                None
+            };
+            let chain = chain.or_else(|| node.as_ref().map(|node| node.chain.clone()));
+            let uptime = node
+                .as_ref()
+                .map(|node| (himalia::current_timestamp() - node.started_at) / 1000);
+            let mining_address = node.as_ref().map_or_else(
+                || GLOBAL_CONFIG.get_mining_addr(),
+                |node| node.mining_address.clone(),
+            );
+            if json {
+                print_json(&json!({
+                    "network": GLOBAL_CONFIG.get_network(),
+                    "data_dir": GLOBAL_CONFIG.get_data_dir(),
+                    "node_running": node_running,
+                    "uptime_secs": uptime,
+                    "chain": chain,
+                    "node": node,
+                    "mining": {
+                        "enabled": node_running && mining_address.is_some(),
+                        "address": mining_address,
+                    },
+                    "wallets": wallet_count,
+                }))?;
+            } else {
+                println!("Network:  {}", GLOBAL_CONFIG.get_network());
+                println!("Data dir: {}", GLOBAL_CONFIG.get_data_dir().display());
+                match (&node, node_running) {
+                    (Some(node), _) => print_node_status(node),
+                    (None, true) => {
+                        println!("Node:     running, but it hasn't reported its status yet");
+                    }
+                    (None, false) => println!("Node:     not running, showing what is on disk"),
+                }
+                match &chain {
+                    Some(chain) => {
+                        println!("Chain:    height {}, tip {}", chain.height, chain.tip_hash);
+                    }
+                    None => println!("Chain:    none, create one with `createblockchain`"),
+                }
+                match (&mining_address, node_running) {
+                    (Some(address), true) => println!("Mining:   on, rewards to {address}"),
+                    (Some(address), false) => {
+                        println!("Mining:   off, configured to reward {address}");
+                    }
+                    (None, _) => println!("Mining:   off"),
+                }
+                println!("Wallets:  {wallet_count}");
+            }
+        }
+        Command::Config(ConfigCommand::Show) => {
+            let snapshot = GLOBAL_CONFIG.snapshot();
+            if json {
+                print_json(&snapshot)?;
+            } else {
+                for (key, setting) in snapshot.iter() {
+                    let value = setting.value.as_deref().unwrap_or("-");
+                    println!("{key:<20} {value:<50} ({})", setting.source);
+                }
+            }
+        }
+        Command::StartNode { light: true, .. } => {
             let socket_addr = GLOBAL_CONFIG.get_node_addr();
-            Server::new(blockchain).run(socket_addr.as_str())?;
+            let peer = bootstrap_peers().swap_remove(0);
+            let wallets = WalletStore::new();
+            let watched = wallets
+                .get_addresses()
+                .iter()
+                .filter_map(|address| address_pub_key_hash(address.as_str()))
+                .collect();
+            let db = open_db(GLOBAL_CONFIG.get_data_dir().as_path())?;
+            let client = LightClient::open(&db, watched)?;
+            if json {
+                print_json(&json!({ "node_address": socket_addr, "peer": peer }))?;
+            } else {
+                println!("Light client syncing from {peer}");
+            }
+            client
+                .run(socket_addr.as_str(), peer.as_str())
+                .map_err(|e| {
+                    CliError::network(
+                        format!("light client on {socket_addr} failed").as_str(),
+                        &*e,
+                    )
+                })?;
+        }
+        Command::StartNode { .. } => {
+            let socket_addr = GLOBAL_CONFIG.get_node_addr();
+            if json {
+                print_json(&json!({
+                    "node_address": socket_addr,
+                    "mining_address": GLOBAL_CONFIG.get_mining_addr(),
+                }))?;
+            } else if let Some(addr) = GLOBAL_CONFIG.get_mining_addr() {
+                println!("Mining is on. Address to receive rewards: {addr}");
+            }
+            let node = match NodeHandle::builder().start() {
+                Ok(node) => node,
+                Err(e) if e.is::<BlockchainError>() => return Err(e),
+                Err(e) => {
+                    return Err(CliError::network(
+                        format!("node on {socket_addr} failed").as_str(),
+                        &*e,
+                    )
+                    .into())
+                }
+            };
+            node.wait()?;
+        }
+    }
+    Ok(())
+}
+
+/// Prints `error` to stderr, as `{"error": {"code", "message"}}` when `json`, and
+/// exits with its [`exit_code`].
+fn report_error(error: &(dyn Error + 'static), json: bool) -> ! {
+    let code = exit_code(error);
+    if json {
+        let document = json!({ "error": { "code": code, "message": error.to_string() } });
+        eprintln!("{document}");
+    } else {
+        eprintln!("Error: {error}");
+    }
+    process::exit(code);
+}
+
+/// Mines `transaction` into a new [Block] rewarding `from` with the subsidy and its
+/// fee when `mine`, returning its hash, or otherwise submits it to the node at `node`.
+fn mine_or_submit(
+    blockchain: &Blockchain,
+    utxo_set: &UTXOSet,
+    from: &str,
+    transaction: Transaction,
+    mine: bool,
+    node: &str,
+) -> Result<Option<String>, Box<dyn Error>> {
+    if mine {
+        let fee = transaction.fee(utxo_set).unwrap_or(0);
+        let coinbase_tx = Transaction::new_coinbase_tx(from, blockchain.get_params(), fee)?;
+        let block = blockchain.mine_block(vec![transaction, coinbase_tx])?;
+        return Ok(Some(block.get_hash().to_string()));
+    }
+    submit_tx(node, &transaction)
+        .map_err(|e| CliError::network(format!("unable to send to {node}").as_str(), &*e))?;
+    Ok(None)
+}
+
+/// Mines a [Block] on the tip of `blockchain` paying `address` the subsidy, along with
+/// the fees of the pending [Transaction]s of `mempool` that fit in it. The [Block]
+/// holds only the coinbase without a `mempool`.
+fn mine_next_block(
+    blockchain: &Blockchain,
+    address: &str,
+    mempool: Option<&MemoryPool>,
+) -> Result<Block, Box<dyn Error>> {
+    let template = mempool.map(|mempool| mempool.get_block_template(blockchain));
+    let (mut transactions, fees) = template.map_or((vec![], 0), |template| {
+        (template.transactions, template.fees)
+    });
+    transactions.push(Transaction::new_coinbase_tx(
+        address,
+        blockchain.get_params(),
+        fees,
+    )?);
+    let block = blockchain.mine_block(transactions)?;
+    Ok(block)
+}
+
+/// How a send signs its [Transaction].
+enum Signing<'a> {
+    /// With the key of the sending address in the local wallet file.
+    Wallet(&'a WalletStore),
+    Signer(&'a dyn Signer),
+    /// Leaving the inputs unsigned, taking the public key from the local wallet file.
+    Unsigned(&'a WalletStore),
+}
+
+/// Stands in for a [Signer] while sizing a draft [Transaction] for its fee, so that
+/// an external signer isn't asked to sign something that is never sent.
+struct DraftSigner(Vec<u8>);
+
+impl Signer for DraftSigner {
+    fn public_key(&self) -> Vec<u8> {
+        self.0.clone()
+    }
+
+    fn sign(&self, _digest: &[u8]) -> Result<Vec<u8>, SignerError> {
+        Ok(vec![0; SIGNATURE_LEN])
+    }
+}
+
+fn build_transaction(
+    utxo_set: &UTXOSet,
+    from: &str,
+    recipients: &[Recipient],
+    fee: u64,
+    signing: &Signing,
+) -> Result<Transaction, TransactionError> {
+    match signing {
+        Signing::Wallet(wallets) => {
+            Transaction::new_multi_recipient_transaction(from, recipients, fee, utxo_set, wallets)
+        }
+        Signing::Signer(signer) => {
+            Transaction::new_signed_transaction(from, recipients, fee, utxo_set, *signer)
+        }
+        Signing::Unsigned(wallets) => {
+            Transaction::new_unsigned_transaction(from, recipients, fee, utxo_set, wallets)
+        }
+    }
+}
+
+/// Builds the [Transaction] a send would make and prints it, leaving the wallet,
+/// the mempool and the network untouched.
+fn print_dry_run(
+    utxo_set: &UTXOSet,
+    from: &str,
+    recipients: &[Recipient],
+    fee: u64,
+    signing: &Signing,
+    raw: bool,
+    json: bool,
+) -> Result<(), Box<dyn Error>> {
+    let transaction = build_transaction(utxo_set, from, recipients, fee, signing)?;
+    let sign = !matches!(signing, Signing::Unsigned(_));
+    let detail = TransactionDetail::new(&transaction, None, utxo_set.get_blockchain());
+    let bytes = transaction.serialize();
+    let hex = raw.then(|| HEXLOWER.encode(bytes.as_slice()));
+    if json {
+        return print_json(&json!({
+            "dry_run": true,
+            "signed": sign,
+            "size": bytes.len(),
+            "transaction": detail,
+            "hex": hex,
+        }));
+    }
+    println!("Dry run, nothing was sent");
+    print_transaction(&detail);
+    println!("Size:          {} bytes", bytes.len());
+    if !sign {
+        println!("Inputs are not signed");
+    }
+    if let Some(hex) = hex {
+        println!("{hex}");
+    }
+    Ok(())
+}
+
+/// Refuses fees above [`HIGH_FEE_PERCENT`] of `amount` unless `allow_high_fee`, then
+/// asks for confirmation unless `yes`.
+fn confirm_send(
+    amount: u64,
+    to: &str,
+    fee: u64,
+    yes: bool,
+    allow_high_fee: bool,
+    json: bool,
+) -> Result<(), Box<dyn Error>> {
+    if u128::from(fee) * 100 > u128::from(amount) * u128::from(HIGH_FEE_PERCENT) && !allow_high_fee
+    {
+        let message = format!(
+            "fee {fee} is more than {HIGH_FEE_PERCENT}% of the amount, pass --allow-high-fee to send anyway"
+        );
+        return Err(CliError::usage(message).into());
+    }
+    if yes {
+        if !json {
+            println!("Fee: {fee}");
+        }
+    } else if !confirm(format!("Send {amount} to {to} with a fee of {fee}?").as_str())? {
+        return Err(CliError::usage("aborted").into());
+    }
+    Ok(())
+}
+
+/// Prints the [Block] with the hex hash or the height `id`, from the running node when
+/// it holds the [Blockchain].
+fn show_block(id: &str, verbose: bool, raw: bool, json: bool) -> Result<(), Box<dyn Error>> {
+    let Some(blockchain) = open_or_rpc()? else {
+        let id = id
+            .parse::<usize>()
+            .map_or_else(|_| json!(id), |height| json!(height));
+        let verbosity = if raw { 0 } else { 1 + usize::from(verbose) };
+        let result = rpc_call("getblock", &json!([id, verbosity]))?;
+        if raw && json {
+            print_json(&json!({ "hex": result }))?;
+        } else if raw {
+            println!("{}", result.as_str().unwrap_or_default());
+        } else if json {
+            print_json(&result)?;
+        } else {
+            print_block(&serde_json::from_value(result)?);
+        }
+        return Ok(());
+    };
+    let Some(block) = blockchain.find_block(id) else {
+        return Err(CliError::usage("block not found").into());
+    };
+    if raw && json {
+        print_json(&json!({ "hex": HEXLOWER.encode(block.serialize().as_slice()) }))?;
+    } else if raw {
+        println!("{}", HEXLOWER.encode(block.serialize().as_slice()));
+    } else if json {
+        print_json(&BlockSummary::new(&block, verbose))?;
+    } else {
+        print_block(&BlockSummary::new(&block, verbose));
+    }
+    Ok(())
+}
+
+/// Opens the [Blockchain], or returns `None` when a running node holds it and can be
+/// reached over JSON-RPC instead.
+fn open_or_rpc() -> Result<Option<Blockchain>, Box<dyn Error>> {
+    match Blockchain::open() {
+        Ok(blockchain) => Ok(Some(blockchain)),
+        Err(BlockchainError::Database(_))
+            if GLOBAL_CONFIG.get_rpc_bind().is_some() && GLOBAL_CONFIG.get_rpc_auth().is_some() =>
+        {
+            Ok(None)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Calls `method` on the running node's JSON-RPC server.
+fn rpc_call(method: &str, params: &serde_json::Value) -> Result<serde_json::Value, Box<dyn Error>> {
+    let addr = GLOBAL_CONFIG.get_rpc_bind().unwrap_or_default();
+    let token = GLOBAL_CONFIG.get_rpc_auth().unwrap_or_default();
+    rpc::call(addr.as_str(), token.as_str(), method, params).map_err(|e| {
+        if e.is::<RpcError>() {
+            e
+        } else {
+            CliError::network(format!("unable to reach the node at {addr}").as_str(), &*e).into()
+        }
+    })
+}
+
+/// Reads the mempool snapshot of the running node, if there is one.
+fn load_mempool() -> Result<Option<MempoolSnapshot>, Box<dyn Error>> {
+    MempoolSnapshot::load(GLOBAL_CONFIG.get_mempool_snapshot_path().as_path())
+}
+
+/// Asks a yes or no question on the terminal, defaulting to no.
+fn confirm(question: &str) -> Result<bool, Box<dyn Error>> {
+    eprint!("{question} [y/N] ");
+    io::stderr().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+/// Maps an error to the documented exit code of its kind.
+fn exit_code(error: &(dyn Error + 'static)) -> i32 {
+    if let Some(error) = error.downcast_ref::<CliError>() {
+        return error.code;
+    }
+    if error.is::<BlockchainError>()
+        || error.is::<BlockError>()
+        || error.is::<SpendError>()
+        || matches!(
+            error.downcast_ref::<ChainFileError>(),
+            Some(ChainFileError::Blockchain(_) | ChainFileError::Database(_))
+        )
+    {
+        return EXIT_STATE;
+    }
+    if let Some(error) = error.downcast_ref::<RpcError>() {
+        return match error.code {
+            rpc::INVALID_PARAMS | rpc::NOT_FOUND => EXIT_USAGE,
+            _ => EXIT_STATE,
+        };
+    }
+    let signer_error = match error.downcast_ref::<TransactionError>() {
+        Some(TransactionError::Signer(error)) => Some(error),
+        _ => error.downcast_ref::<SignerError>(),
+    };
+    if let Some(error) = signer_error {
+        return match error {
+            SignerError::Unavailable(_) => EXIT_NETWORK,
+            SignerError::NotConfigured(_) => EXIT_CONFIG,
+            SignerError::Unsupported(_) => EXIT_USAGE,
+            _ => EXIT_STATE,
+        };
+    }
+    if let Some(error) = error.downcast_ref::<WalletError>() {
+        return match error {
+            WalletError::InvalidMnemonic(_)
+            | WalletError::InvalidAddress(_)
+            | WalletError::UnknownAddress(_)
+            | WalletError::AmbiguousLabel { .. } => EXIT_USAGE,
+            WalletError::SeedExists
+            | WalletError::NoSeed
+            | WalletError::HasKey(_)
+            | WalletError::LabelTaken(_) => EXIT_STATE,
+        };
+    }
+    match error.downcast_ref::<TransactionError>() {
+        Some(
+            TransactionError::BelowDustThreshold { .. }
+            | TransactionError::ZeroAmount
+            | TransactionError::AmountOverflow,
+        )
+        | None => EXIT_USAGE,
+        Some(_) => EXIT_STATE,
+    }
+}
+
+/// Checks that every recipient address is valid, and listed only once unless
+/// `allow_duplicates`, in which case each gets its own output.
+fn check_recipients(recipients: &[Recipient], allow_duplicates: bool) -> Result<(), CliError> {
+    let mut seen = HashSet::new();
+    for recipient in recipients {
+        if !validate_address(recipient.address.as_str()) {
+            let message = format!("recipient address `{}` is not valid", recipient.address);
+            return Err(CliError::usage(message));
+        }
+        if !seen.insert(recipient.address.as_str()) && !allow_duplicates {
+            let message = format!(
+                "recipient `{}` is listed more than once, pass --allow-duplicates to allow it",
+                recipient.address
+            );
+            return Err(CliError::usage(message));
         }
     }
     Ok(())
 }
+
+/// Resolves the `from` of a send, either an address or the label of one.
+fn resolve_sender(from: String) -> Result<String, Box<dyn Error>> {
+    if validate_address(from.as_str()) {
+        return Ok(from);
+    }
+    let address = WalletStore::new().get_by_label(from.as_str())?;
+    Ok(address.ok_or_else(|| {
+        CliError::usage(format!(
+            "sender `{from}` is neither a valid address nor a wallet label"
+        ))
+    })?)
+}
+
+/// Looks up the [Wallet] holding the key of `address`, refusing watch-only addresses.
+fn signing_wallet<'a>(wallets: &'a Wallets, address: &str) -> Result<&'a Wallet, TransactionError> {
+    if wallets.is_watch_only(address) {
+        return Err(TransactionError::WatchOnly(String::from(address)));
+    }
+    wallets
+        .get_wallet(address)
+        .ok_or_else(|| TransactionError::WalletNotFound(String::from(address)))
+}
+
+/// Decodes the hex of a raw [Transaction] given on the command line.
+fn raw_tx_from_hex(hex: &str) -> Result<Transaction, CliError> {
+    let bytes = HEXLOWER_PERMISSIVE
+        .decode(hex.trim().as_bytes())
+        .map_err(|_| CliError::usage(DecodeError::NotHex.to_string()))?;
+    decode_raw_transaction(bytes.as_slice()).map_err(|e| CliError::usage(e.to_string()))
+}
+
+/// Prints a raw [Transaction] serialized in hex, along with its id in JSON.
+fn print_raw_tx(tx: &Transaction, json: bool) -> Result<(), Box<dyn Error>> {
+    let hex = HEXLOWER.encode(tx.serialize().as_slice());
+    if json {
+        print_json(&json!({ "txid": tx.get_id().to_string(), "hex": hex }))?;
+    } else {
+        println!("{hex}");
+    }
+    Ok(())
+}
+
+/// Prints `value` to stdout as a single pretty-printed JSON document.
+fn print_json(value: &impl Serialize) -> Result<(), Box<dyn Error>> {
+    println!("{}", serde_json::to_string_pretty(value)?);
+    Ok(())
+}
+
+/// Checks whether any [Transaction] of `block` spends from or pays to `pub_key_hash`.
+fn block_touches(block: &Block, pub_key_hash: &[u8]) -> bool {
+    block.get_transactions().iter().any(|tx| {
+        (!tx.is_coinbase()
+            && tx
+                .get_vin()
+                .iter()
+                .any(|input| input.uses_key(pub_key_hash)))
+            || tx
+                .get_vout()
+                .iter()
+                .any(|output| output.is_locked_with_key(pub_key_hash))
+    })
+}
+
+/// Checks whether any transaction of `block` spends from or pays to `address`.
+fn summary_touches(block: &BlockSummary, address: &str) -> bool {
+    block.transactions.iter().any(|tx| {
+        tx.inputs
+            .iter()
+            .flatten()
+            .any(|input| input.address == address)
+            || tx
+                .outputs
+                .iter()
+                .flatten()
+                .any(|output| output.address == address)
+    })
+}
+
+/// Prints a block of `printchain`, as a single line of JSON when `json` is set.
+fn print_chain_summary(
+    block: &BlockSummary,
+    json: bool,
+    summary: bool,
+) -> Result<(), Box<dyn Error>> {
+    if json {
+        println!("{}", serde_json::to_string(block)?);
+    } else if summary {
+        print_block_line(block);
+    } else {
+        print_chain_block(block);
+    }
+    Ok(())
+}
+
+fn print_block_line(block: &BlockSummary) {
+    println!(
+        "{:>6}  {}  {}  {} txs  {} bytes",
+        block.height,
+        block.hash,
+        himalia::format_timestamp(block.timestamp),
+        block.transaction_count,
+        block.size
+    );
+}
+
+fn print_block(block: &BlockSummary) {
+    println!("Hash:         {}", block.hash);
+    println!("Pre hash:     {}", block.pre_block_hash);
+    println!("Height:       {}", block.height);
+    println!("Timestamp:    {}", block.timestamp);
+    println!("Nonce:        {}", block.nonce);
+    println!("Target bits:  {}", block.target_bits);
+    println!("Size:         {} bytes", block.size);
+    println!("Transactions: {}", block.transaction_count);
+    for tx in &block.transactions {
+        let kind = if tx.is_coinbase {
+            "coinbase"
+        } else {
+            "transfer"
+        };
+        println!(
+            "– {} ({kind}, {} in, {} out, value {})",
+            tx.txid, tx.input_count, tx.output_count, tx.total_output
+        );
+        for input in tx.inputs.iter().flatten() {
+            println!(
+                "-- Input txid = {}, vout = {}, from = {}",
+                input.txid, input.vout, input.address
+            );
+        }
+        for output in tx.outputs.iter().flatten() {
+            println!(
+                "-- Output value = {}, to = {}",
+                output.value, output.address
+            );
+        }
+    }
+}
+
+/// Prints a block in the `printchain` layout.
+fn print_chain_block(block: &BlockSummary) {
+    println!("Pre block hash: {}", block.pre_block_hash);
+    println!("Cur block hash: {}", block.hash);
+    println!("Height: {}", block.height);
+    println!("Timestamp: {}", himalia::format_timestamp(block.timestamp));
+    for tx in &block.transactions {
+        println!("– Transaction txid_hex: {}", tx.txid);
+        for input in tx.inputs.iter().flatten() {
+            match input.value {
+                Some(value) => println!(
+                    "-- Input txid = {}, vout = {}, from = {}, value = {value}",
+                    input.txid, input.vout, input.address
+                ),
+                None => println!(
+                    "-- Input txid = {}, vout = {}, from = {}",
+                    input.txid, input.vout, input.address
+                ),
+            }
+        }
+        for output in tx.outputs.iter().flatten() {
+            println!(
+                "-- Output value = {}, to = {}",
+                output.value, output.address
+            );
+        }
+    }
+    println!();
+}
+
+/// The number of peers listed by `status`, the rest are only counted.
+const STATUS_PEERS_SHOWN: usize = 5;
+
+fn print_node_status(node: &NodeStatus) {
+    let now = himalia::current_timestamp();
+    println!(
+        "Node:     running on {} (pid {}) for {}s, reported {}s ago",
+        node.node_address,
+        node.pid,
+        (now - node.started_at) / 1000,
+        (now - node.updated_at) / 1000
+    );
+    println!(
+        "Sync:     {} ({} blocks in transit)",
+        node.sync, node.blocks_in_transit
+    );
+    let mut peers = node
+        .peers
+        .iter()
+        .take(STATUS_PEERS_SHOWN)
+        .cloned()
+        .collect::<Vec<String>>()
+        .join(", ");
+    if node.peers.len() > STATUS_PEERS_SHOWN {
+        peers.push_str(", ...");
+    }
+    println!("Peers:    {} {peers}", node.peers.len());
+    println!(
+        "Sockets:  {} open, {} opened since start",
+        node.connections, node.connections_opened
+    );
+    println!(
+        "Mempool:  {} transactions, {} bytes",
+        node.mempool.count, node.mempool.bytes
+    );
+}
+
+fn print_tx_records(records: &[TxRecord]) {
+    println!(
+        "{:>7}  {:<8}  {:>10}  {:<64}",
+        "height", "type", "amount", "txid"
+    );
+    for record in records {
+        let coinbase = if record.coinbase { "  (coinbase)" } else { "" };
+        println!(
+            "{:>7}  {:<8}  {:>10}  {:<64}{coinbase}",
+            record.height,
+            record.direction.to_string(),
+            record.amount,
+            record.txid
+        );
+    }
+}
+
+/// Prints a table of `balances`, with a column for the balances once the pending
+/// transactions are mined when `include_mempool` is set.
+fn print_balances(balances: &[AddressBalance], include_mempool: bool) {
+    let column = |value: &dyn fmt::Display| {
+        if include_mempool {
+            format!("  {value:>10}")
+        } else {
+            String::new()
+        }
+    };
+    println!(
+        "{:<36}  {:>10}  {:>10}  {:>11}{}",
+        "address",
+        "confirmed",
+        "pending in",
+        "pending out",
+        column(&"balance")
+    );
+    for balance in balances {
+        let watch_only = if balance.watch_only {
+            "  (watch-only)"
+        } else {
+            ""
+        };
+        println!(
+            "{:<36}  {:>10}  {:>10}  {:>11}{}{watch_only}",
+            balance.address,
+            balance.confirmed,
+            balance.pending_in,
+            balance.pending_out,
+            column(&balance.pending_balance())
+        );
+    }
+    let confirmed = balances
+        .iter()
+        .map(|balance| balance.confirmed)
+        .sum::<u64>();
+    let pending_in = balances
+        .iter()
+        .map(|balance| balance.pending_in)
+        .sum::<u64>();
+    let pending_out = balances
+        .iter()
+        .map(|balance| balance.pending_out)
+        .sum::<u64>();
+    println!(
+        "{:<36}  {confirmed:>10}  {pending_in:>10}  {pending_out:>11}{}",
+        "total",
+        column(&pending_balance(confirmed, pending_in, pending_out))
+    );
+}
+
+fn print_transaction(tx: &TransactionDetail) {
+    println!("Txid:          {}", tx.txid);
+    if let (Some(hash), Some(height)) = (&tx.block_hash, tx.block_height) {
+        println!("Block:         {hash} (height {height})");
+        println!("Confirmations: {}", tx.confirmations);
+    } else {
+        println!("Status:        unconfirmed");
+    }
+    if let Some(fee) = tx.fee {
+        println!("Fee:           {fee}");
+    }
+    if tx.is_coinbase {
+        println!("-- Coinbase input");
+    }
+    for input in &tx.inputs {
+        let value = input
+            .value
+            .map_or_else(|| String::from("unknown"), |value| value.to_string());
+        println!(
+            "-- Input txid = {}, vout = {}, from = {}, value = {value}",
+            input.txid, input.vout, input.address
+        );
+    }
+    for output in &tx.outputs {
+        println!(
+            "-- Output value = {}, to = {}",
+            output.value, output.address
+        );
+    }
+}
+
+fn print_utxo_diff(diff: &UtxoDiff) {
+    if diff.is_consistent() {
+        println!("The UTXO set is consistent");
+        return;
+    }
+    println!(
+        "The UTXO set is inconsistent: {} missing, {} unexpected, {} mismatched transactions",
+        diff.missing.len(),
+        diff.unexpected.len(),
+        diff.mismatched.len()
+    );
+    for (kind, txids) in [
+        ("missing", &diff.missing),
+        ("unexpected", &diff.unexpected),
+        ("mismatched", &diff.mismatched),
+    ] {
+        for txid in txids {
+            println!("-- {kind:<10} {txid}");
+        }
+    }
+}
+
+fn print_mempool(snapshot: &MempoolSnapshot) {
+    let now = himalia::current_timestamp();
+    println!(
+        "Snapshot taken {}s ago by the running node",
+        (now - snapshot.taken_at) / 1000
+    );
+    println!(
+        "{} transactions, {} bytes, {} in fees",
+        snapshot.stats.count, snapshot.stats.bytes, snapshot.stats.total_fees
+    );
+    if snapshot.entries.is_empty() {
+        return;
+    }
+    println!(
+        "{:<64}  {:>8}  {:>8}  {:>8}  depends on",
+        "txid", "size", "fee/byte", "age (s)"
+    );
+    for entry in &snapshot.entries {
+        let fee_rate = entry
+            .fee_rate
+            .map_or_else(|| String::from("?"), |rate| format!("{rate:.4}"));
+        println!(
+            "{:<64}  {:>8}  {fee_rate:>8}  {:>8}  {}",
+            entry.txid,
+            entry.size,
+            (now - entry.added_at) / 1000,
+            entry.depends_on.join(", ")
+        );
+    }
+}
@@ -1,107 +1,1919 @@
-use std::{collections::HashMap, sync::RwLock};
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::{self, Display, Formatter};
+use std::sync::mpsc::{Receiver, SyncSender};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+use std::{error::Error, fs, path::Path};
 
-use data_encoding::HEXLOWER;
+use serde::{Deserialize, Serialize};
+use sled::{Batch, Tree};
 
-use crate::transactions::Transaction;
+use crate::blockchain::{Blockchain, BLOCK_RESERVED_BYTES};
+use crate::clock::{system_clock, Clock};
+use crate::events::{Event, EventBus, TxEventStatus};
+use crate::summary::{InputSummary, OutputSummary};
+use crate::transactions::{TXOutput, Transaction, ValueError};
+use crate::types::{BlockHash, Txid};
+use crate::utxo_set::{SpendError, UTXOSet};
+use crate::{block::Block, config::ChainParams};
+
+/// The reasons a [Transaction] is refused by [`MemoryPool::try_add`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AdmissionError {
+    /// Coinbase [Transaction]s only exist inside the [Block] that mints them.
+    Coinbase,
+    AlreadyPending,
+    /// The [Transaction] is larger than the
+    /// [`SizeLimits::max_tx_bytes`](crate::blockchain::SizeLimits::max_tx_bytes).
+    TooLarge {
+        size: usize,
+        max: usize,
+    },
+    /// An input spends an output that is neither on the [Blockchain] nor pending.
+    UnknownInput {
+        txid: Txid,
+        vout: usize,
+    },
+    /// An input spends an output already spent on the [Blockchain] or by a pending
+    /// [Transaction].
+    AlreadySpent {
+        txid: Txid,
+        vout: usize,
+    },
+    /// The id isn't the hash of the [Transaction].
+    MismatchedId,
+    /// The [Transaction] is confirmed already, some of its outputs still unspent.
+    AlreadyConfirmed,
+    InvalidSignature,
+    /// The outputs are worth more than the inputs, leaving a negative fee.
+    FeeTooLow {
+        inputs: u64,
+        outputs: u64,
+    },
+    /// The values add up to more than an amount can hold.
+    InvalidValue,
+    /// The [`MemoryPool`] is at its [`MempoolLimits`] and nothing pending pays a lower
+    /// fee rate.
+    MempoolFull,
+}
+
+impl Display for AdmissionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Coinbase => write!(f, "coinbase transactions can't be relayed"),
+            Self::AlreadyPending => write!(f, "the transaction is already in the mempool"),
+            Self::TooLarge { size, max } => write!(
+                f,
+                "the transaction is {size} bytes, more than the {max} allowed"
+            ),
+            Self::UnknownInput { txid, vout } => {
+                write!(f, "the transaction spends an unknown output {txid}:{vout}")
+            }
+            Self::AlreadySpent { txid, vout } => {
+                write!(f, "the output {txid}:{vout} is already spent")
+            }
+            Self::MismatchedId => write!(f, "the transaction id is not its hash"),
+            Self::AlreadyConfirmed => write!(f, "the transaction is already confirmed"),
+            Self::InvalidSignature => write!(f, "the transaction signature is not valid"),
+            Self::FeeTooLow { inputs, outputs } => write!(
+                f,
+                "the fee is too low, the outputs are worth {outputs} but the inputs only {inputs}"
+            ),
+            Self::InvalidValue => write!(f, "the output values are too large"),
+            Self::MempoolFull => write!(
+                f,
+                "the mempool is full of transactions paying at least the same fee rate"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for AdmissionError {}
+
+/// A [Transaction] waiting in the [`MemoryPool`].
+struct PoolEntry {
+    tx: Transaction,
+    /// Milliseconds since the Unix epoch when the [Transaction] was added.
+    added_at: i64,
+    /// The serialized size of the [Transaction].
+    size: usize,
+    /// The tip of the [Blockchain] the signatures were last verified against.
+    verified_against_tip: BlockHash,
+    /// The fee, as of the UTXO set at `verified_against_tip`.
+    fee: u64,
+}
+
+impl PoolEntry {
+    /// The fee per serialized byte.
+    #[allow(clippy::cast_precision_loss)]
+    fn fee_rate(&self) -> f64 {
+        self.fee as f64 / self.size as f64
+    }
+}
+
+/// The pending [Transaction]s to mine into the next [Block], with the fees they pay.
+#[derive(Debug, Clone, Default)]
+pub struct BlockTemplate {
+    pub transactions: Vec<Transaction>,
+    pub fees: u64,
+}
+
+/// The most [Transaction]s a [`MemoryPool`] holds by default.
+pub const DEFAULT_MEMPOOL_MAX_TXS: usize = 5000;
+/// The most serialized bytes the [Transaction]s of a [`MemoryPool`] take up by default.
+pub const DEFAULT_MEMPOOL_MAX_BYTES: usize = 5_000_000;
+/// How long a [Transaction] stays pending by default before it is evicted.
+pub const DEFAULT_MEMPOOL_EXPIRY: Duration = Duration::from_hours(14 * 24);
+/// Holds the pending [Transaction]s saved by [`MemoryPool::save_to`].
+///
+/// Maps the id of each one to the milliseconds since the Unix epoch when it was
+/// added, as eight big-endian bytes, followed by the serialized [Transaction].
+pub const MEMPOOL_TREE: &str = "mempool";
+
+/// Caps on the size of a [`MemoryPool`], past which the [Transaction]s paying the lowest
+/// fee rate are evicted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MempoolLimits {
+    pub max_txs: usize,
+    /// The total serialized size of the pending [Transaction]s.
+    pub max_bytes: usize,
+}
+
+impl Default for MempoolLimits {
+    fn default() -> Self {
+        Self {
+            max_txs: DEFAULT_MEMPOOL_MAX_TXS,
+            max_bytes: DEFAULT_MEMPOOL_MAX_BYTES,
+        }
+    }
+}
+
+/// Aggregate figures describing the [`MemoryPool`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MempoolStats {
+    pub count: usize,
+    /// The total serialized size of the pending [Transaction]s.
+    pub bytes: usize,
+    /// The sum of the fees of the pending [Transaction]s whose inputs could be resolved.
+    pub total_fees: u64,
+}
+
+/// A description of a single pending [Transaction].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MempoolEntrySummary {
+    pub txid: String,
+    pub size: usize,
+    pub fee: Option<u64>,
+    /// The fee per serialized byte.
+    pub fee_rate: Option<f64>,
+    pub added_at: i64,
+    /// Other pending [Transaction]s whose outputs this one spends.
+    pub depends_on: Vec<String>,
+    /// Every address paying into or receiving from the [Transaction].
+    pub addresses: Vec<String>,
+    /// The inputs, valued when the output they spend could be found.
+    pub inputs: Vec<InputSummary>,
+    pub outputs: Vec<OutputSummary>,
+}
+
+/// The contents of the [`MemoryPool`] at a point in time, written to disk by a
+/// running node so that other processes can inspect it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MempoolSnapshot {
+    pub taken_at: i64,
+    pub stats: MempoolStats,
+    pub entries: Vec<MempoolEntrySummary>,
+}
+
+impl MempoolSnapshot {
+    /// Writes the snapshot as JSON, replacing the file at `path` atomically.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(tmp_path.as_path(), serde_json::to_vec(self)?)?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Reads the snapshot at `path`, returning `None` when no node has written one.
+    pub fn load(path: &Path) -> Result<Option<Self>, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let bytes = fs::read(path)?;
+        Ok(Some(serde_json::from_slice(bytes.as_slice())?))
+    }
+
+    /// Suggests a fee rate per byte for a new [Transaction]: the median rate of the
+    /// pending transactions, or zero when none have a known fee.
+    pub fn estimate_fee_rate(&self) -> f64 {
+        let mut rates: Vec<f64> = self
+            .entries
+            .iter()
+            .filter_map(|entry| entry.fee_rate)
+            .collect();
+        if rates.is_empty() {
+            return 0.0;
+        }
+        rates.sort_by(f64::total_cmp);
+        rates[rates.len() / 2]
+    }
+
+    /// Sums the pending outputs paying to `address` and the pending inputs spending
+    /// from it, returned as `(incoming, outgoing)`.
+    pub fn get_pending(&self, address: &str) -> (u64, u64) {
+        pending_of(self.entries.as_slice(), address)
+    }
+
+    /// Returns what the `confirmed` balance of `address` becomes once the pending
+    /// [Transaction]s are mined.
+    pub fn get_pending_balance(&self, address: &str, confirmed: u64) -> u64 {
+        let (incoming, outgoing) = self.get_pending(address);
+        pending_balance(confirmed, incoming, outgoing)
+    }
+}
+
+/// Sums the outputs of `entries` paying to `address` and their inputs spending from it,
+/// returned as `(incoming, outgoing)`.
+fn pending_of(entries: &[MempoolEntrySummary], address: &str) -> (u64, u64) {
+    let mut incoming: u64 = 0;
+    let mut outgoing: u64 = 0;
+    for entry in entries {
+        for output in entry
+            .outputs
+            .iter()
+            .filter(|output| output.address == address)
+        {
+            incoming = incoming.saturating_add(output.value);
+        }
+        for input in entry.inputs.iter().filter(|input| input.address == address) {
+            outgoing = outgoing.saturating_add(input.value.unwrap_or(0));
+        }
+    }
+    (incoming, outgoing)
+}
+
+/// Adds the `incoming` pending funds of an address to its `confirmed` balance and
+/// takes away the `outgoing` ones, stopping at zero.
+pub const fn pending_balance(confirmed: u64, incoming: u64, outgoing: u64) -> u64 {
+    confirmed.saturating_add(incoming).saturating_sub(outgoing)
+}
+
+/// The fee paying `fee_rate` per byte for a [Transaction] of `size` bytes, rounded up.
+///
+/// Returns `None` when the fee doesn't fit in an amount.
+#[allow(
+    clippy::cast_possible_truncation,
+    clippy::cast_precision_loss,
+    clippy::cast_sign_loss
+)]
+pub fn fee_for_rate(fee_rate: f64, size: usize) -> Option<u64> {
+    let fee = (fee_rate * f64::from(u32::try_from(size).ok()?)).ceil();
+    (0.0..u64::MAX as f64).contains(&fee).then_some(fee as u64)
+}
 
 /// A mempool. Serves as a holding area for pending transactions awaiting
 /// validation and inclusion in a block on the [Blockchain] network.
+///
 /// Stores unconfirmed transactions, acting as a temporary repository before
 /// miners select and verify them for block inclusion.
-#[derive(Default)]
-pub struct MemoryPool(RwLock<HashMap<String, Transaction>>);
+pub struct MemoryPool {
+    txs: RwLock<HashMap<Txid, PoolEntry>>,
+    limits: MempoolLimits,
+    /// The number of pending [Transaction]s that triggers mining a [Block].
+    threshold: usize,
+    events: EventBus,
+    clock: Arc<dyn Clock>,
+}
 
 impl MemoryPool {
-    pub fn new() -> Self {
-        Self(RwLock::new(HashMap::new()))
+    pub fn new(params: &'static ChainParams) -> Self {
+        Self::with_clock(params, system_clock())
+    }
+
+    /// Creates a [`MemoryPool`] that stamps [Transaction]s with the time of `clock`.
+    pub fn with_clock(params: &'static ChainParams, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            txs: RwLock::new(HashMap::new()),
+            limits: MempoolLimits::default(),
+            threshold: params.transaction_threshold,
+            events: EventBus::new(),
+            clock,
+        }
+    }
+
+    /// Caps the [`MemoryPool`] at `limits` instead of the [`MempoolLimits::default`].
+    #[must_use]
+    pub const fn with_limits(mut self, limits: MempoolLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Mines a [Block] once `threshold` [Transaction]s are pending instead of the
+    /// [`ChainParams::transaction_threshold`].
+    #[must_use]
+    pub const fn with_threshold(mut self, threshold: usize) -> Self {
+        self.threshold = threshold;
+        self
+    }
+
+    pub const fn get_threshold(&self) -> usize {
+        self.threshold
     }
 
     /// Checks whether a [Transaction] with a specific id exists within the [`MemoryPool`].
-    pub fn contains(&self, txid_hex: &str) -> bool {
-        self.0.read().unwrap().contains_key(txid_hex)
+    pub fn contains(&self, txid: Txid) -> bool {
+        self.txs.read().unwrap().contains_key(&txid)
+    }
+
+    /// Validates a [Transaction] before inserting it into the [`MemoryPool`], publishing
+    /// a pending [`Event::Tx`], or returns why it wasn't accepted. Its id must be its
+    /// hash, and every input must spend an output of the UTXO set or of another
+    /// pending [Transaction] that no other pending [Transaction] spends.
+    pub fn try_add(&self, tx: Transaction, blockchain: &Blockchain) -> Result<(), AdmissionError> {
+        if tx.is_coinbase() {
+            return Err(AdmissionError::Coinbase);
+        }
+        let size = tx.serialize().len();
+        let max = blockchain.get_limits().max_tx_bytes;
+        if size > max {
+            return Err(AdmissionError::TooLarge { size, max });
+        }
+        let tip_hash = blockchain.get_tip_hash();
+        let unconfirmed = self.get_spent_pending_outputs(&tx);
+        let fee = UTXOSet::new(blockchain.clone())
+            .validate_tx_spending(&tx, &unconfirmed)
+            .map_err(|e| match e {
+                SpendError::MissingOutput { txid, vout } => {
+                    let exists = blockchain
+                        .find_transaction(txid)
+                        .is_some_and(|prev_tx| vout < prev_tx.get_vout().len());
+                    if exists {
+                        AdmissionError::AlreadySpent { txid, vout }
+                    } else {
+                        AdmissionError::UnknownInput { txid, vout }
+                    }
+                }
+                SpendError::DuplicateInput { txid, vout } => {
+                    AdmissionError::AlreadySpent { txid, vout }
+                }
+                SpendError::MismatchedId(_) => AdmissionError::MismatchedId,
+                SpendError::DuplicateTransaction(_) => AdmissionError::AlreadyConfirmed,
+                SpendError::InvalidSignature => AdmissionError::InvalidSignature,
+                SpendError::Value(ValueError::OutputsExceedInputs { inputs, outputs }) => {
+                    AdmissionError::FeeTooLow { inputs, outputs }
+                }
+                SpendError::Value(ValueError::OutOfRange) => AdmissionError::InvalidValue,
+            })?;
+        let txid = tx.get_id();
+        let event = Event::tx(&tx, TxEventStatus::Pending);
+        let mut inner = self.txs.write().unwrap();
+        if inner.contains_key(&txid) {
+            return Err(AdmissionError::AlreadyPending);
+        }
+        // The pending Transactions it spends may have been dropped since.
+        let orphaned = tx.get_vin().iter().find(|input| {
+            unconfirmed.contains_key(&input.get_txid()) && !inner.contains_key(&input.get_txid())
+        });
+        if let Some(input) = orphaned {
+            return Err(AdmissionError::UnknownInput {
+                txid: input.get_txid(),
+                vout: input.get_vout(),
+            });
+        }
+        if let Some((txid, vout)) = find_conflict(&tx, &inner) {
+            return Err(AdmissionError::AlreadySpent { txid, vout });
+        }
+        let entry = PoolEntry {
+            tx,
+            added_at: self.clock.now(),
+            size,
+            verified_against_tip: tip_hash,
+            fee,
+        };
+        let evicted = self.insert_within_limits(&mut inner, txid, entry)?;
+        drop(inner);
+        self.publish_evicted(evicted);
+        self.events.publish(&event);
+        Ok(())
+    }
+
+    /// Returns the outputs of the pending [Transaction]s that the inputs of `tx` spend,
+    /// by transaction id.
+    fn get_spent_pending_outputs(&self, tx: &Transaction) -> HashMap<Txid, Vec<TXOutput>> {
+        let inner = self.txs.read().unwrap();
+        let outputs = tx
+            .get_vin()
+            .iter()
+            .filter_map(|input| {
+                let entry = inner.get(&input.get_txid())?;
+                Some((input.get_txid(), entry.tx.get_vout().to_vec()))
+            })
+            .collect();
+        drop(inner);
+        outputs
     }
 
-    /// Inserts a new [Transaction] into the [`MemoryPool`].
-    pub fn add(&self, tx: Transaction) {
-        let txid_hex = HEXLOWER.encode(tx.get_id());
-        self.0.write().unwrap().insert(txid_hex, tx);
+    /// Inserts `entry` unless that takes the [`MemoryPool`] past its [`MempoolLimits`]
+    /// and evicting the [Transaction]s paying a lower fee rate, along with those
+    /// spending their outputs, doesn't make room for it. The oldest are evicted first
+    /// among those paying the same fee rate.
+    ///
+    /// Returns the evicted [Transaction]s.
+    fn insert_within_limits(
+        &self,
+        inner: &mut HashMap<Txid, PoolEntry>,
+        txid: Txid,
+        entry: PoolEntry,
+    ) -> Result<Vec<Transaction>, AdmissionError> {
+        let mut count = inner.len() + 1;
+        let mut bytes = inner.values().map(|entry| entry.size).sum::<usize>() + entry.size;
+        let mut evicted = HashSet::new();
+        let children = children_of(inner);
+        for victim in eviction_order(inner) {
+            if count <= self.limits.max_txs && bytes <= self.limits.max_bytes {
+                break;
+            }
+            if evicted.contains(&victim) {
+                continue;
+            }
+            if eviction_key(&inner[&victim]) >= eviction_key(&entry) {
+                return Err(AdmissionError::MempoolFull);
+            }
+            for txid in descendants(victim, &children) {
+                if evicted.insert(txid) {
+                    count -= 1;
+                    bytes -= inner[&txid].size;
+                }
+            }
+        }
+        let orphaned = entry
+            .tx
+            .get_vin()
+            .iter()
+            .any(|input| evicted.contains(&input.get_txid()));
+        if count > self.limits.max_txs || bytes > self.limits.max_bytes || orphaned {
+            return Err(AdmissionError::MempoolFull);
+        }
+        let evicted = evicted
+            .iter()
+            .filter_map(|txid| inner.remove(txid))
+            .map(|entry| entry.tx)
+            .collect();
+        inner.insert(txid, entry);
+        Ok(evicted)
+    }
+
+    /// Writes the pending [Transaction]s to `tree`, replacing those saved before, and
+    /// returns how many were written.
+    pub fn save_to(&self, tree: &Tree) -> sled::Result<usize> {
+        let mut batch = Batch::default();
+        for key in tree.iter().keys() {
+            batch.remove(key?);
+        }
+        let inner = self.txs.read().unwrap();
+        for (txid, entry) in inner.iter() {
+            let mut value = entry.added_at.to_be_bytes().to_vec();
+            value.extend(entry.tx.serialize());
+            batch.insert(txid.as_bytes(), value);
+        }
+        let saved = inner.len();
+        drop(inner);
+        tree.apply_batch(batch)?;
+        tree.flush()?;
+        Ok(saved)
+    }
+
+    /// Adds back the [Transaction]s saved to `tree` by [`MemoryPool::save_to`], oldest
+    /// first and keeping the time they were first added, validating each one like
+    /// [`MemoryPool::try_add`]. Those mined or spent while they weren't pending are
+    /// dropped.
+    ///
+    /// Returns how many were added back and how many were dropped.
+    pub fn load_from(&self, tree: &Tree, blockchain: &Blockchain) -> sled::Result<(usize, usize)> {
+        let mut saved = vec![];
+        let mut dropped = 0;
+        for entry in tree {
+            let (_, value) = entry?;
+            let (added_at, tx) = value.split_at_checked(8).map_or((None, None), |(at, tx)| {
+                (
+                    at.try_into().ok().map(i64::from_be_bytes),
+                    bincode::deserialize::<Transaction>(tx).ok(),
+                )
+            });
+            match (added_at, tx) {
+                (Some(added_at), Some(tx)) => saved.push((added_at, tx)),
+                _ => dropped += 1,
+            }
+        }
+        saved.sort_by_key(|(added_at, _)| *added_at);
+        let mut restored = 0;
+        // A child added in the same millisecond as its parent may sort before it, so
+        // those spending an unknown output are tried again once the others are in.
+        while !saved.is_empty() {
+            let restored_before = restored;
+            let mut deferred = vec![];
+            for (added_at, tx) in saved {
+                let txid = tx.get_id();
+                if self.contains(txid) {
+                    continue;
+                }
+                match self.try_add(tx.clone(), blockchain) {
+                    Ok(()) => {}
+                    Err(AdmissionError::UnknownInput { .. }) => {
+                        deferred.push((added_at, tx));
+                        continue;
+                    }
+                    Err(_) => {
+                        dropped += 1;
+                        continue;
+                    }
+                }
+                if let Some(entry) = self.txs.write().unwrap().get_mut(&txid) {
+                    entry.added_at = added_at;
+                }
+                restored += 1;
+            }
+            if restored == restored_before {
+                dropped += deferred.len();
+                break;
+            }
+            saved = deferred;
+        }
+        Ok((restored, dropped))
+    }
+
+    /// Drops the [Transaction]s pending for longer than `max_age`, along with those
+    /// spending their outputs, returning how many were dropped.
+    pub fn evict_expired(&self, max_age: Duration) -> usize {
+        let max_age = i64::try_from(max_age.as_millis()).unwrap_or(i64::MAX);
+        let now = self.clock.now();
+        let mut inner = self.txs.write().unwrap();
+        let children = children_of(&inner);
+        let expired: HashSet<Txid> = inner
+            .iter()
+            .filter(|(_, entry)| now.saturating_sub(entry.added_at) > max_age)
+            .flat_map(|(txid, _)| descendants(*txid, &children))
+            .collect();
+        let evicted: Vec<Transaction> = expired
+            .iter()
+            .filter_map(|txid| inner.remove(txid))
+            .map(|entry| entry.tx)
+            .collect();
+        drop(inner);
+        let count = evicted.len();
+        self.publish_evicted(evicted);
+        count
+    }
+
+    fn publish_evicted(&self, evicted: Vec<Transaction>) {
+        for tx in evicted {
+            self.events.publish(&Event::tx(&tx, TxEventStatus::Evicted));
+        }
     }
 
     /// Attempts to retrieve a [Transaction] from the [`MemoryPool`] matching
     /// the given transaction id.
-    pub fn get(&self, txid_hex: &str) -> Option<Transaction> {
-        if let Some(tx) = self.0.read().unwrap().get(txid_hex) {
-            return Some(tx.clone());
+    pub fn get(&self, txid: Txid) -> Option<Transaction> {
+        if let Some(entry) = self.txs.read().unwrap().get(&txid) {
+            return Some(entry.tx.clone());
         }
         None
     }
 
     /// Removes a [Transaction] from the [`MemoryPool`] matching the given
     /// transaction ID.
-    pub fn remove(&self, txid_hex: &str) {
-        let mut inner = self.0.write().unwrap();
-        inner.remove(txid_hex);
+    pub fn remove(&self, txid: Txid) {
+        let mut inner = self.txs.write().unwrap();
+        inner.remove(&txid);
+    }
+
+    /// Drops the pending [Transaction]s included in `block`, and those spending an
+    /// output that a [Transaction] in `block` also spends along with those spending
+    /// their outputs, publishing a replaced [`Event::Tx`] for each of the latter.
+    pub fn remove_confirmed(&self, block: &Block) {
+        let spent: HashSet<(Txid, usize)> = block
+            .get_transactions()
+            .iter()
+            .filter(|tx| !tx.is_coinbase())
+            .flat_map(Transaction::get_vin)
+            .map(|input| (input.get_txid(), input.get_vout()))
+            .collect();
+        let confirmed: HashSet<Txid> = block
+            .get_transactions()
+            .iter()
+            .map(Transaction::get_id)
+            .collect();
+        let mut inner = self.txs.write().unwrap();
+        inner.retain(|txid, _| !confirmed.contains(txid));
+        let children = children_of(&inner);
+        let replaced: Vec<Txid> = inner
+            .iter()
+            .filter(|(_, entry)| {
+                !entry.tx.is_coinbase()
+                    && entry
+                        .tx
+                        .get_vin()
+                        .iter()
+                        .any(|input| spent.contains(&(input.get_txid(), input.get_vout())))
+            })
+            .flat_map(|(txid, _)| descendants(*txid, &children))
+            .collect();
+        for txid in replaced {
+            if let Some(entry) = inner.remove(&txid) {
+                self.events
+                    .publish(&Event::tx(&entry.tx, TxEventStatus::Replaced));
+            }
+        }
+    }
+
+    /// Returns a receiver for a pending [`Event::Tx`] for every [Transaction] added
+    /// from now on, and a replaced one for every conflict removed.
+    pub fn subscribe(&self) -> Receiver<Event> {
+        self.events.subscribe()
+    }
+
+    /// Like [`MemoryPool::subscribe`], delivering to an existing channel.
+    pub fn subscribe_with(&self, sender: SyncSender<Event>) {
+        self.events.subscribe_with(sender);
     }
 
     /// Retrieves all [Transaction]s stored in the [`MemoryPool`].
     pub fn get_all(&self) -> Vec<Transaction> {
         let mut txs = vec![];
-        for (_, v) in self.0.read().unwrap().iter() {
-            txs.push(v.clone());
+        for (_, entry) in self.txs.read().unwrap().iter() {
+            txs.push(entry.tx.clone());
         }
         txs
     }
 
+    /// Returns up to `limit` pending [Transaction]s, those paying the highest fee per
+    /// byte first.
+    pub fn get_sorted_by_fee(&self, limit: usize) -> Vec<Transaction> {
+        let inner = self.txs.read().unwrap();
+        let txs = fee_order(&inner)
+            .into_iter()
+            .take(limit)
+            .map(|txid| inner[&txid].tx.clone())
+            .collect();
+        drop(inner);
+        txs
+    }
+
+    /// Fills a [`BlockTemplate`] with the pending [Transaction]s that are valid on top
+    /// of the current tip, ready for [`Blockchain::mine_verified_block`] once a
+    /// Coinbase claiming the fees is added. Those paying the highest fee per byte are
+    /// taken first, as long as they fit in a [Block] within the
+    /// [`SizeLimits`](crate::blockchain::SizeLimits) of `blockchain`,
+    /// [`BLOCK_RESERVED_BYTES`] of which are left for the header and the Coinbase. A
+    /// [Transaction] spending the outputs of other pending ones is only taken after
+    /// them.
+    ///
+    /// Signatures verified against a tip that is still on the best chain are trusted,
+    /// so only [Transaction]s verified on a chain since replaced are verified again.
+    /// Every input must still spend an output of the UTXO set or of another pending
+    /// [Transaction], and those that don't or no longer verify are dropped from the
+    /// pool along with those spending their outputs.
+    pub fn get_block_template(&self, blockchain: &Blockchain) -> BlockTemplate {
+        let tip_hash = blockchain.get_tip_hash();
+        let utxo_set = UTXOSet::new(blockchain.clone());
+        let mut inner = self.txs.write().unwrap();
+        let recorded: HashSet<BlockHash> = inner
+            .values()
+            .map(|entry| entry.verified_against_tip)
+            .collect();
+        let on_best_chain = best_chain_members(blockchain, recorded);
+        let unconfirmed: HashMap<Txid, Vec<TXOutput>> = inner
+            .iter()
+            .map(|(txid, entry)| (*txid, entry.tx.get_vout().to_vec()))
+            .collect();
+        let mut invalid = vec![];
+        for (txid, entry) in inner.iter_mut() {
+            let fee = if on_best_chain.contains(&entry.verified_against_tip) {
+                pending_fee(&entry.tx, &utxo_set, &unconfirmed)
+            } else {
+                utxo_set.validate_tx_spending(&entry.tx, &unconfirmed).ok()
+            };
+            let Some(fee) = fee else {
+                invalid.push(*txid);
+                continue;
+            };
+            entry.verified_against_tip = tip_hash;
+            entry.fee = fee;
+        }
+        let children = children_of(&inner);
+        for txid in invalid {
+            for txid in descendants(txid, &children) {
+                inner.remove(&txid);
+            }
+        }
+        let limits = blockchain.get_limits();
+        let max_size = limits.max_block_bytes.saturating_sub(BLOCK_RESERVED_BYTES);
+        let mut template = BlockTemplate::default();
+        let mut taken = HashSet::new();
+        let mut spent = HashSet::new();
+        let mut size = 0;
+        let mut queue = fee_order(&inner);
+        // Goes over those waiting for their parents again while that takes any more.
+        while !queue.is_empty() {
+            let taken_before = taken.len();
+            let mut waiting = vec![];
+            for txid in queue {
+                // Leaves room for the Coinbase.
+                if template.transactions.len() + 1 >= limits.max_block_txs {
+                    break;
+                }
+                let entry = &inner[&txid];
+                let parents_taken = entry.tx.get_vin().iter().all(|input| {
+                    !inner.contains_key(&input.get_txid()) || taken.contains(&input.get_txid())
+                });
+                if !parents_taken {
+                    waiting.push(txid);
+                    continue;
+                }
+                let conflicts = entry
+                    .tx
+                    .get_vin()
+                    .iter()
+                    .any(|input| spent.contains(&(input.get_txid(), input.get_vout())));
+                if conflicts {
+                    continue;
+                }
+                let tx_size = entry.tx.serialize().len();
+                if size + tx_size > max_size {
+                    continue;
+                }
+                let Some(fees) = template.fees.checked_add(entry.fee) else {
+                    continue;
+                };
+                spent.extend(
+                    entry
+                        .tx
+                        .get_vin()
+                        .iter()
+                        .map(|input| (input.get_txid(), input.get_vout())),
+                );
+                size += tx_size;
+                template.fees = fees;
+                template.transactions.push(entry.tx.clone());
+                taken.insert(txid);
+            }
+            if taken.len() == taken_before {
+                break;
+            }
+            queue = waiting;
+        }
+        drop(inner);
+        template
+    }
+
     pub fn len(&self) -> usize {
-        self.0.read().unwrap().len()
+        self.txs.read().unwrap().len()
     }
 
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
+
+    /// Checks whether enough [Transaction]s are pending to mine a [Block].
+    pub fn reached_threshold(&self) -> bool {
+        self.len() >= self.threshold
+    }
+
+    /// Returns aggregate figures for the pending [Transaction]s, resolving fees
+    /// against the [Blockchain].
+    pub fn get_stats(&self, blockchain: &Blockchain) -> MempoolStats {
+        stats_of(self.get_summaries(blockchain).as_slice())
+    }
+
+    /// Describes every pending [Transaction], resolving fees against the [Blockchain].
+    pub fn get_summaries(&self, blockchain: &Blockchain) -> Vec<MempoolEntrySummary> {
+        let mut summaries: Vec<MempoolEntrySummary> = {
+            let inner = self.txs.read().unwrap();
+            inner
+                .iter()
+                .map(|(txid, entry)| summarize(*txid, entry, &inner, blockchain))
+                .collect()
+        };
+        summaries.sort_by_key(|summary| summary.added_at);
+        summaries
+    }
+
+    /// Sums the pending outputs paying to `address` and the pending inputs spending
+    /// from it, returned as `(incoming, outgoing)`, resolving the spent outputs
+    /// against the [Blockchain].
+    pub fn get_pending(&self, address: &str, blockchain: &Blockchain) -> (u64, u64) {
+        pending_of(self.get_summaries(blockchain).as_slice(), address)
+    }
+
+    /// Returns what the `confirmed` balance of `address` becomes once the pending
+    /// [Transaction]s are mined.
+    pub fn get_pending_balance(
+        &self,
+        address: &str,
+        confirmed: u64,
+        blockchain: &Blockchain,
+    ) -> u64 {
+        let (incoming, outgoing) = self.get_pending(address, blockchain);
+        pending_balance(confirmed, incoming, outgoing)
+    }
+
+    /// Captures the current contents for [`MempoolSnapshot::save`].
+    pub fn snapshot(&self, blockchain: &Blockchain) -> MempoolSnapshot {
+        let entries = self.get_summaries(blockchain);
+        MempoolSnapshot {
+            taken_at: self.clock.now(),
+            stats: stats_of(entries.as_slice()),
+            entries,
+        }
+    }
+}
+
+/// Returns those of `hashes` that are on the best chain of `blockchain`, walking back
+/// from the tip no further than the lowest of them.
+fn best_chain_members(
+    blockchain: &Blockchain,
+    mut hashes: HashSet<BlockHash>,
+) -> HashSet<BlockHash> {
+    let lowest = hashes
+        .iter()
+        .filter_map(|hash| blockchain.get_block(*hash))
+        .map(|block| block.get_height())
+        .min();
+    let mut members = HashSet::new();
+    let Some(lowest) = lowest else {
+        return members;
+    };
+    let mut iterator = blockchain.iterator();
+    while let Some(block) = iterator.next() {
+        if hashes.remove(&block.get_hash()) {
+            members.insert(block.get_hash());
+        }
+        if hashes.is_empty() || block.get_height() <= lowest {
+            break;
+        }
+    }
+    members
+}
+
+/// Orders the ids of the `pending` [Transaction]s by descending fee rate, the oldest
+/// first among equals.
+fn fee_order(pending: &HashMap<Txid, PoolEntry>) -> Vec<Txid> {
+    let mut keyed: Vec<(f64, i64, Txid)> = pending
+        .iter()
+        .map(|(txid, entry)| (entry.fee_rate(), entry.added_at, *txid))
+        .collect();
+    keyed.sort_by(|a, b| {
+        b.0.partial_cmp(&a.0)
+            .unwrap_or(Ordering::Equal)
+            .then(a.1.cmp(&b.1))
+    });
+    keyed.into_iter().map(|(_, _, txid)| txid).collect()
+}
+
+/// Orders the ids of the `pending` [Transaction]s to evict from first to last: by
+/// ascending fee rate, the oldest first among equals.
+fn eviction_order(pending: &HashMap<Txid, PoolEntry>) -> Vec<Txid> {
+    let mut keyed: Vec<((f64, i64), Txid)> = pending
+        .iter()
+        .map(|(txid, entry)| (eviction_key(entry), *txid))
+        .collect();
+    keyed.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(Ordering::Equal));
+    keyed.into_iter().map(|(_, txid)| txid).collect()
+}
+
+/// Ranks a [`PoolEntry`] for eviction, the lowest key going first.
+fn eviction_key(entry: &PoolEntry) -> (f64, i64) {
+    (entry.fee_rate(), entry.added_at)
+}
+
+/// Maps the id of each `pending` [Transaction] to the ids of the pending
+/// [Transaction]s spending its outputs.
+fn children_of(pending: &HashMap<Txid, PoolEntry>) -> HashMap<Txid, HashSet<Txid>> {
+    let mut children: HashMap<Txid, HashSet<Txid>> = HashMap::new();
+    for (txid, entry) in pending {
+        for input in entry.tx.get_vin() {
+            if pending.contains_key(&input.get_txid()) {
+                children.entry(input.get_txid()).or_default().insert(*txid);
+            }
+        }
+    }
+    children
 }
 
-/// For tracking [Block]s that are in transit during a P2P networking protocol.
-#[derive(Default)]
-pub struct BlockInTransit(RwLock<Vec<Vec<u8>>>);
+/// Returns `txid` and the ids of the pending [Transaction]s spending its outputs,
+/// directly or through other pending [Transaction]s, following their `children`.
+fn descendants(txid: Txid, children: &HashMap<Txid, HashSet<Txid>>) -> Vec<Txid> {
+    let mut found = vec![txid];
+    let mut seen = HashSet::from([txid]);
+    let mut next = 0;
+    while let Some(parent) = found.get(next).copied() {
+        next += 1;
+        for child in children.get(&parent).into_iter().flatten() {
+            if seen.insert(*child) {
+                found.push(*child);
+            }
+        }
+    }
+    found
+}
+
+/// Returns the fee `tx` pays, looking up the outputs its inputs spend in `utxo_set`
+/// or among the `unconfirmed` outputs of the pending [Transaction]s.
+fn pending_fee(
+    tx: &Transaction,
+    utxo_set: &UTXOSet,
+    unconfirmed: &HashMap<Txid, Vec<TXOutput>>,
+) -> Option<u64> {
+    let spent = tx
+        .get_vin()
+        .iter()
+        .map(|input| {
+            utxo_set.get_output_or_unconfirmed(input.get_txid(), input.get_vout(), unconfirmed)
+        })
+        .collect::<Option<Vec<TXOutput>>>()?;
+    tx.get_fee(&spent).ok()
+}
+
+/// Returns the first output `tx` spends that a pending [Transaction] other than `tx`
+/// spends too.
+fn find_conflict(tx: &Transaction, pending: &HashMap<Txid, PoolEntry>) -> Option<(Txid, usize)> {
+    tx.get_vin()
+        .iter()
+        .map(|input| (input.get_txid(), input.get_vout()))
+        .find(|outpoint| {
+            pending
+                .values()
+                .filter(|entry| entry.tx.get_id() != tx.get_id())
+                .flat_map(|entry| entry.tx.get_vin())
+                .any(|pending| (pending.get_txid(), pending.get_vout()) == *outpoint)
+        })
+}
+
+fn stats_of(entries: &[MempoolEntrySummary]) -> MempoolStats {
+    MempoolStats {
+        count: entries.len(),
+        bytes: entries.iter().map(|entry| entry.size).sum(),
+        total_fees: entries.iter().filter_map(|entry| entry.fee).sum(),
+    }
+}
+
+/// Describes a single pending [Transaction]. Inputs spending other pending
+/// [Transaction]s are resolved against the pool, the rest against the [Blockchain].
+fn summarize(
+    txid: Txid,
+    entry: &PoolEntry,
+    pool: &HashMap<Txid, PoolEntry>,
+    blockchain: &Blockchain,
+) -> MempoolEntrySummary {
+    let tx = &entry.tx;
+    let size = tx.serialize().len();
+    let mut depends_on = vec![];
+    let mut addresses = vec![];
+    let mut inputs = vec![];
+    let mut total_input = Some(0_u64);
+    for input in tx.get_vin() {
+        if tx.is_coinbase() {
+            break;
+        }
+        let summary = InputSummary::from(input);
+        addresses.push(summary.address.clone());
+        let prev_txid = input.get_txid();
+        let pending_tx = pool.get(&prev_txid).map(|prev| prev.tx.clone());
+        if pending_tx.is_some() {
+            depends_on.push(prev_txid.to_string());
+        }
+        let prev_tx = pending_tx.or_else(|| blockchain.find_transaction(input.get_txid()));
+        let value = prev_tx.and_then(|prev_tx| {
+            prev_tx
+                .get_vout()
+                .get(input.get_vout())
+                .map(TXOutput::get_value)
+        });
+        total_input = total_input
+            .zip(value)
+            .and_then(|(total, value)| total.checked_add(value));
+        inputs.push(InputSummary { value, ..summary });
+    }
+    let outputs: Vec<OutputSummary> = tx.get_vout().iter().map(OutputSummary::from).collect();
+    for output in &outputs {
+        addresses.push(output.address.clone());
+    }
+    addresses.sort();
+    addresses.dedup();
+    let fee = total_input
+        .zip(tx.get_output_value().ok())
+        .and_then(|(total_input, total_output)| total_input.checked_sub(total_output));
+    #[allow(clippy::cast_precision_loss)]
+    let fee_rate = fee
+        .zip(u32::try_from(size).ok())
+        .map(|(fee, size)| fee as f64 / f64::from(size));
+    MempoolEntrySummary {
+        txid: txid.to_string(),
+        size,
+        fee,
+        fee_rate,
+        added_at: entry.added_at,
+        depends_on,
+        addresses,
+        inputs,
+        outputs,
+    }
+}
+
+/// How long a requested [Block] may take to arrive before it is requested again.
+pub const BLOCK_REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where the download of a [Block] in transit stands.
+#[derive(Debug)]
+enum TransitState {
+    Pending,
+    Requested(Instant),
+    /// Arrived before its parent, and waits for it to be stored.
+    Arrived(Box<Block>),
+}
+
+#[derive(Debug)]
+struct TransitEntry {
+    block_hash: BlockHash,
+    /// The peer that announced the [Block], and that it is requested from.
+    peer: String,
+    state: TransitState,
+}
+
+/// For tracking [Block]s that are in transit during a P2P networking protocol, in
+/// the order they were announced, parents first.
+#[derive(Debug)]
+pub struct BlockInTransit {
+    entries: RwLock<Vec<TransitEntry>>,
+    timeout: Duration,
+}
+
+impl Default for BlockInTransit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl BlockInTransit {
     pub const fn new() -> Self {
-        Self(RwLock::new(Vec::new()))
+        Self::with_timeout(BLOCK_REQUEST_TIMEOUT)
     }
 
-    pub fn add_blocks(&self, blocks: &[Vec<u8>]) {
-        let mut inner = self.0.write().unwrap();
-        for hash in blocks {
-            inner.push(hash.clone());
+    /// Creates a [`BlockInTransit`] requesting [Block]s again once they haven't
+    /// arrived within `timeout`.
+    pub const fn with_timeout(timeout: Duration) -> Self {
+        Self {
+            entries: RwLock::new(Vec::new()),
+            timeout,
         }
     }
 
-    pub fn first(&self) -> Option<Vec<u8>> {
-        if let Some(block_hash) = self.0.read().unwrap().first() {
-            return Some(block_hash.clone());
+    /// Tracks the [Block]s `peer` announced, skipping those already in transit.
+    pub fn add_blocks(&self, peer: &str, blocks: &[BlockHash]) {
+        let mut inner = self.entries.write().unwrap();
+        for block_hash in blocks {
+            if inner.iter().all(|entry| entry.block_hash != *block_hash) {
+                inner.push(TransitEntry {
+                    block_hash: *block_hash,
+                    peer: peer.to_owned(),
+                    state: TransitState::Pending,
+                });
+            }
         }
-        None
     }
 
-    /// Deletes a specific [Block] identified by its hash from [`BlockInTransit`].
-    pub fn remove(&self, block_hash: &[u8]) {
-        let mut inner = self.0.write().unwrap();
-        if let Some(idx) = inner.iter().position(|x| x.eq(block_hash)) {
-            inner.remove(idx);
+    /// Returns the [Block]s to request next along with the peer to request each from,
+    /// marking them as requested.
+    ///
+    /// The pending [Block]s and those that timed out are handed out in order, so that
+    /// at most `n` requests are in flight.
+    pub fn take_next(&self, n: usize) -> Vec<(String, BlockHash)> {
+        let now = Instant::now();
+        let mut inner = self.entries.write().unwrap();
+        let in_flight = inner
+            .iter()
+            .filter(|entry| {
+                matches!(entry.state, TransitState::Requested(at) if now - at < self.timeout)
+            })
+            .count();
+        let mut next = vec![];
+        for entry in inner.iter_mut() {
+            if next.len() + in_flight >= n {
+                break;
+            }
+            let due = match entry.state {
+                TransitState::Pending => true,
+                TransitState::Requested(at) => now - at >= self.timeout,
+                TransitState::Arrived(_) => false,
+            };
+            if due {
+                entry.state = TransitState::Requested(now);
+                next.push((entry.peer.clone(), entry.block_hash));
+            }
+        }
+        drop(inner);
+        next
+    }
+
+    /// Stops tracking a [Block] once it is stored, or rejected.
+    pub fn mark_received(&self, block_hash: BlockHash) {
+        let mut inner = self.entries.write().unwrap();
+        inner.retain(|entry| entry.block_hash != block_hash);
+    }
+
+    /// Holds on to a [Block] that arrived before its parent, returning `false` when
+    /// either of them isn't in transit, so that the parent won't arrive.
+    pub fn park(&self, block: &Block) -> bool {
+        let mut inner = self.entries.write().unwrap();
+        let parent = block.get_pre_block_hash();
+        if inner.iter().all(|entry| entry.block_hash != parent) {
+            return false;
+        }
+        let block_hash = block.get_hash();
+        let Some(entry) = inner
+            .iter_mut()
+            .find(|entry| entry.block_hash == block_hash)
+        else {
+            return false;
+        };
+        entry.state = TransitState::Arrived(Box::new(block.clone()));
+        drop(inner);
+        true
+    }
+
+    /// Stops tracking and returns a [Block] that arrived before its parent `parent`.
+    pub fn take_child(&self, parent: BlockHash) -> Option<Block> {
+        let mut inner = self.entries.write().unwrap();
+        let idx = inner.iter().position(|entry| {
+            matches!(&entry.state, TransitState::Arrived(block) if block.get_pre_block_hash() == parent)
+        })?;
+        match inner.remove(idx).state {
+            TransitState::Arrived(block) => Some(*block),
+            _ => unreachable!("the entry was matched as arrived"),
         }
     }
 
     pub fn clear(&self) {
-        let mut inner = self.0.write().unwrap();
+        let mut inner = self.entries.write().unwrap();
         inner.clear();
     }
 
     pub fn len(&self) -> usize {
-        self.0.read().unwrap().len()
+        self.entries.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// How many txids a [`SeenTxids`] remembers before forgetting the oldest.
+pub const SEEN_TXIDS_CAPACITY: usize = 50_000;
+
+/// The ids of the [Transaction]s announced to peers lately, so that one coming back
+/// around a loop of peers isn't relayed again, even once it left the mempool.
+#[derive(Debug)]
+pub struct SeenTxids {
+    /// The txids seen, along with the order they were seen in.
+    inner: RwLock<(HashSet<Txid>, VecDeque<Txid>)>,
+    capacity: usize,
+}
+
+impl Default for SeenTxids {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SeenTxids {
+    pub fn new() -> Self {
+        Self::with_capacity(SEEN_TXIDS_CAPACITY)
+    }
+
+    /// Creates a [`SeenTxids`] forgetting the oldest txid once it holds `capacity`.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            inner: RwLock::new((HashSet::new(), VecDeque::new())),
+            capacity: capacity.max(1),
+        }
+    }
+
+    /// Records `txid`, returning `false` when it was seen already.
+    pub fn insert(&self, txid: Txid) -> bool {
+        let mut inner = self.inner.write().unwrap();
+        let (seen, order) = &mut *inner;
+        if !seen.insert(txid) {
+            return false;
+        }
+        order.push_back(txid);
+        if order.len() > self.capacity {
+            if let Some(oldest) = order.pop_front() {
+                seen.remove(&oldest);
+            }
+        }
+        drop(inner);
+        true
+    }
+
+    pub fn contains(&self, txid: Txid) -> bool {
+        self.inner.read().unwrap().0.contains(&txid)
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.read().unwrap().1.len()
     }
 
     pub fn is_empty(&self) -> bool {
         self.len() == 0
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::blockchain::SizeLimits;
+    use crate::clock::MockClock;
+    use crate::test_utils::{coinbase, mine, pay, temp_chain};
+    use crate::transactions::{OutPoint, Recipient};
+    use crate::wallet::Wallet;
+
+    #[test]
+    fn transaction_whose_id_is_not_its_hash_is_refused() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let tx = pay(&blockchain, &miner, &Wallet::new(), 3);
+        let mut json = serde_json::to_value(&tx).unwrap();
+        json["vout"][0]["value"] = 4.into();
+        let tampered: Transaction = serde_json::from_value(json).unwrap();
+        let mempool = MemoryPool::new(blockchain.get_params());
+        assert_eq!(
+            mempool.try_add(tampered, &blockchain),
+            Err(AdmissionError::MismatchedId)
+        );
+        assert!(mempool.is_empty());
+        assert_eq!(mempool.try_add(tx, &blockchain), Ok(()));
+    }
+
+    /// A [Transaction] from `from` paying `amount` to `to` out of output 0 of the pending
+    /// `parent`, with a fee of `fee`.
+    fn spend_pending(
+        blockchain: &Blockchain,
+        parent: &Transaction,
+        from: &Wallet,
+        to: &Wallet,
+        amount: u64,
+        fee: u64,
+    ) -> Transaction {
+        let unconfirmed = [(
+            OutPoint {
+                txid: parent.get_id(),
+                vout: 0,
+            },
+            parent.get_vout()[0].clone(),
+        )];
+        let recipients = [Recipient {
+            address: to.get_address(),
+            amount,
+        }];
+        Transaction::new_spending_transaction(
+            from.get_address().as_str(),
+            &recipients,
+            fee,
+            &unconfirmed,
+            blockchain.get_params().dust_threshold,
+            from,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn transactions_spend_outputs_only_once() {
+        let miner = Wallet::new();
+        let recipient = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let mempool = MemoryPool::new(blockchain.get_params());
+        let payment = pay(&blockchain, &miner, &recipient, 3);
+        let conflicting = pay(&blockchain, &miner, &Wallet::new(), 4);
+        assert_eq!(mempool.try_add(payment.clone(), &blockchain), Ok(()));
+        assert_eq!(
+            mempool.try_add(payment.clone(), &blockchain),
+            Err(AdmissionError::AlreadyPending)
+        );
+        let input = &conflicting.get_vin()[0];
+        assert_eq!(
+            mempool.try_add(conflicting.clone(), &blockchain),
+            Err(AdmissionError::AlreadySpent {
+                txid: input.get_txid(),
+                vout: input.get_vout()
+            })
+        );
+
+        let chained = spend_pending(&blockchain, &payment, &recipient, &miner, 1, 1);
+        let double = spend_pending(&blockchain, &payment, &recipient, &Wallet::new(), 2, 1);
+        assert_eq!(mempool.try_add(chained, &blockchain), Ok(()));
+        assert_eq!(
+            mempool.try_add(double, &blockchain),
+            Err(AdmissionError::AlreadySpent {
+                txid: payment.get_id(),
+                vout: 0
+            })
+        );
+        assert_eq!(mempool.len(), 2);
+    }
+
+    #[test]
+    fn chained_transactions_are_mined_after_their_parents() {
+        let miner = Wallet::new();
+        let recipient = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let mempool = MemoryPool::new(blockchain.get_params());
+        let parent = pay(&blockchain, &miner, &recipient, 3);
+        // Pays a higher fee rate than its parent.
+        let child = spend_pending(&blockchain, &parent, &recipient, &Wallet::new(), 1, 2);
+        assert_eq!(
+            mempool.try_add(child.clone(), &blockchain),
+            Err(AdmissionError::UnknownInput {
+                txid: parent.get_id(),
+                vout: 0
+            })
+        );
+        assert_eq!(mempool.try_add(parent.clone(), &blockchain), Ok(()));
+        assert_eq!(mempool.try_add(child.clone(), &blockchain), Ok(()));
+
+        let summaries = mempool.get_summaries(&blockchain);
+        let child_summary = summaries
+            .iter()
+            .find(|summary| summary.txid == child.get_id().to_string())
+            .unwrap();
+        assert_eq!(child_summary.depends_on, vec![parent.get_id().to_string()]);
+        assert_eq!(child_summary.fee, Some(2));
+
+        let template = mempool.get_block_template(&blockchain);
+        let taken: Vec<Txid> = template
+            .transactions
+            .iter()
+            .map(Transaction::get_id)
+            .collect();
+        assert_eq!(taken, vec![parent.get_id(), child.get_id()]);
+        assert_eq!(template.fees, 3);
+
+        let mut transactions = template.transactions;
+        transactions.push(coinbase(&miner, template.fees));
+        let block = blockchain.mine_block(transactions).unwrap();
+        mempool.remove_confirmed(&block);
+        assert!(mempool.is_empty());
+        let utxo_set = UTXOSet::new(blockchain);
+        assert!(utxo_set.get_output(child.get_id(), 0).is_some());
+        assert!(utxo_set.verify(|_| {}).is_consistent());
+    }
+
+    #[test]
+    fn replacing_a_transaction_drops_those_spending_its_outputs() {
+        let miner = Wallet::new();
+        let recipient = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let mempool = MemoryPool::new(blockchain.get_params());
+        let parent = pay(&blockchain, &miner, &recipient, 3);
+        let child = spend_pending(&blockchain, &parent, &recipient, &Wallet::new(), 1, 1);
+        let conflicting = pay(&blockchain, &miner, &Wallet::new(), 4);
+        assert_eq!(mempool.try_add(parent, &blockchain), Ok(()));
+        assert_eq!(mempool.try_add(child, &blockchain), Ok(()));
+
+        let events = mempool.subscribe();
+        let block = mine(&blockchain, vec![conflicting], &miner);
+        mempool.remove_confirmed(&block);
+        assert!(mempool.is_empty());
+        assert_eq!(events.try_iter().count(), 2);
+    }
+
+    /// A new [Wallet] holding the reward of a [Block] mined onto the tip.
+    fn funded(blockchain: &Blockchain) -> Wallet {
+        let wallet = Wallet::new();
+        mine(blockchain, vec![], &wallet);
+        wallet
+    }
+
+    /// A [Transaction] from `from` paying 3 to a new [Wallet] with a fee of `fee`.
+    fn pay_with_fee(blockchain: &Blockchain, from: &Wallet, fee: u64) -> Transaction {
+        let recipients = [Recipient {
+            address: Wallet::new().get_address(),
+            amount: 3,
+        }];
+        let utxo_set = UTXOSet::new(blockchain.clone());
+        Transaction::new_signed_transaction(
+            from.get_address().as_str(),
+            &recipients,
+            fee,
+            &utxo_set,
+            from,
+        )
+        .unwrap()
+    }
+
+    /// The ids of the [Transaction]s `events` announce as evicted.
+    fn evicted(events: &Receiver<Event>) -> HashSet<String> {
+        events
+            .try_iter()
+            .filter_map(|event| match event {
+                Event::Tx {
+                    txid,
+                    status: TxEventStatus::Evicted,
+                    ..
+                } => Some(txid),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn full_mempool_evicts_the_lowest_fee_rate() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let [low, mid, high, highest] =
+            [1, 2, 3, 4].map(|fee| pay_with_fee(&blockchain, &funded(&blockchain), fee));
+
+        for limits in [
+            MempoolLimits {
+                max_txs: 2,
+                ..MempoolLimits::default()
+            },
+            MempoolLimits {
+                max_txs: usize::MAX,
+                max_bytes: [&low, &mid, &high, &highest]
+                    .into_iter()
+                    .map(|tx| tx.serialize().len() * 2)
+                    .max()
+                    .unwrap(),
+            },
+        ] {
+            let mempool = MemoryPool::new(blockchain.get_params()).with_limits(limits);
+            assert_eq!(mempool.try_add(mid.clone(), &blockchain), Ok(()));
+            assert_eq!(mempool.try_add(high.clone(), &blockchain), Ok(()));
+            assert_eq!(
+                mempool.try_add(low.clone(), &blockchain),
+                Err(AdmissionError::MempoolFull)
+            );
+
+            let events = mempool.subscribe();
+            assert_eq!(mempool.try_add(highest.clone(), &blockchain), Ok(()));
+            assert_eq!(evicted(&events), HashSet::from([mid.get_id().to_string()]));
+            assert_eq!(mempool.len(), 2);
+            assert!(mempool.contains(high.get_id()));
+            assert!(mempool.contains(highest.get_id()));
+        }
+    }
+
+    #[test]
+    fn expired_transactions_are_evicted_with_their_children() {
+        let miner = Wallet::new();
+        let recipient = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let sender = funded(&blockchain);
+        let clock = Arc::new(MockClock::new(0));
+        let mempool = MemoryPool::with_clock(blockchain.get_params(), clock.clone());
+        let parent = pay(&blockchain, &miner, &recipient, 3);
+        let child = spend_pending(&blockchain, &parent, &recipient, &Wallet::new(), 1, 1);
+        let unrelated = pay(&blockchain, &sender, &Wallet::new(), 3);
+        assert_eq!(mempool.try_add(parent.clone(), &blockchain), Ok(()));
+        clock.set(50_000);
+        assert_eq!(mempool.try_add(unrelated.clone(), &blockchain), Ok(()));
+        clock.set(80_000);
+        assert_eq!(mempool.try_add(child.clone(), &blockchain), Ok(()));
+
+        clock.set(90_000);
+        assert_eq!(mempool.evict_expired(Duration::from_secs(90)), 0);
+        let events = mempool.subscribe();
+        clock.set(100_000);
+        assert_eq!(mempool.evict_expired(Duration::from_secs(90)), 2);
+        assert_eq!(
+            evicted(&events),
+            HashSet::from([parent.get_id().to_string(), child.get_id().to_string()])
+        );
+        assert_eq!(mempool.len(), 1);
+        assert!(mempool.contains(unrelated.get_id()));
+    }
+
+    /// Rewrites the values of the outputs of `tx`, spent by `from`, and signs it again
+    /// so that only the values are wrong.
+    fn with_values(
+        blockchain: &Blockchain,
+        tx: &Transaction,
+        values: &[u64],
+        from: &Wallet,
+    ) -> Transaction {
+        let mut json = serde_json::to_value(tx).unwrap();
+        let outputs = json["vout"].as_array_mut().unwrap();
+        for (output, value) in outputs.iter_mut().zip(values) {
+            output["value"] = (*value).into();
+        }
+        let mut rewritten: Transaction = serde_json::from_value(json).unwrap();
+        let utxo_set = UTXOSet::new(blockchain.clone());
+        let spent: Vec<TXOutput> = tx
+            .get_vin()
+            .iter()
+            .map(|input| {
+                utxo_set
+                    .get_output(input.get_txid(), input.get_vout())
+                    .unwrap()
+            })
+            .collect();
+        rewritten.sign_with(from.get_pksc8(), &spent).unwrap();
+        rewritten
+    }
+
+    #[test]
+    fn coinbase_is_refused() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let mempool = MemoryPool::new(blockchain.get_params());
+        assert_eq!(
+            mempool.try_add(coinbase(&miner, 0), &blockchain),
+            Err(AdmissionError::Coinbase)
+        );
+    }
+
+    #[test]
+    fn transaction_over_the_size_limit_is_refused() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let tx = pay(&blockchain, &miner, &Wallet::new(), 3);
+        let size = tx.serialize().len();
+        let limited = blockchain.clone().with_limits(SizeLimits {
+            max_tx_bytes: size - 1,
+            ..blockchain.get_limits()
+        });
+        let mempool = MemoryPool::new(blockchain.get_params());
+        assert_eq!(
+            mempool.try_add(tx.clone(), &limited),
+            Err(AdmissionError::TooLarge {
+                size,
+                max: size - 1
+            })
+        );
+        assert_eq!(mempool.try_add(tx, &blockchain), Ok(()));
+    }
+
+    #[test]
+    fn template_stops_exactly_at_the_block_size_and_transaction_count_limits() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let senders: Vec<Wallet> = (0..4).map(|_| funded(&blockchain)).collect();
+        let mempool = MemoryPool::new(blockchain.get_params());
+        let mut sizes = HashSet::new();
+        for sender in &senders {
+            let tx = pay(&blockchain, sender, &Wallet::new(), 3);
+            sizes.insert(tx.serialize().len());
+            assert_eq!(mempool.try_add(tx, &blockchain), Ok(()));
+        }
+        let [tx_size] = sizes.into_iter().collect::<Vec<_>>()[..] else {
+            panic!("the payments differ in size");
+        };
+        let limits = blockchain.get_limits();
+        let two_txs_of_bytes = SizeLimits {
+            max_block_bytes: BLOCK_RESERVED_BYTES + 2 * tx_size,
+            ..limits
+        };
+        let two_txs_and_the_coinbase = SizeLimits {
+            max_block_txs: 3,
+            ..limits
+        };
+        let template_len = |limits: SizeLimits| {
+            let limited = blockchain.clone().with_limits(limits);
+            mempool.get_block_template(&limited).transactions.len()
+        };
+        assert_eq!(template_len(two_txs_of_bytes), 2);
+        assert_eq!(
+            template_len(SizeLimits {
+                max_block_bytes: two_txs_of_bytes.max_block_bytes - 1,
+                ..limits
+            }),
+            1
+        );
+        assert_eq!(template_len(two_txs_and_the_coinbase), 2);
+        assert_eq!(
+            template_len(SizeLimits {
+                max_block_txs: 2,
+                ..limits
+            }),
+            1
+        );
+
+        // A block of the template at both limits is mined, and small enough for peers.
+        let limited = blockchain.clone().with_limits(SizeLimits {
+            max_block_txs: 3,
+            ..two_txs_of_bytes
+        });
+        let template = mempool.get_block_template(&limited);
+        let mut transactions = template.transactions;
+        transactions.push(coinbase(&miner, template.fees));
+        let block = limited.mine_block(transactions).unwrap();
+        assert_eq!(block.get_transactions().len(), 3);
+        assert!(block.serialize().len() <= two_txs_of_bytes.max_block_bytes);
+    }
+
+    #[test]
+    fn confirmed_transaction_is_refused() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let tx = pay(&blockchain, &miner, &Wallet::new(), 3);
+        mine(&blockchain, vec![tx.clone()], &miner);
+        let mempool = MemoryPool::new(blockchain.get_params());
+        assert_eq!(
+            mempool.try_add(tx, &blockchain),
+            Err(AdmissionError::AlreadyConfirmed)
+        );
+    }
+
+    #[test]
+    fn spend_of_a_spent_or_unknown_output_is_refused() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let mempool = MemoryPool::new(blockchain.get_params());
+        let confirmed = pay(&blockchain, &miner, &Wallet::new(), 3);
+        let conflicting = pay(&blockchain, &miner, &Wallet::new(), 4);
+        mine(&blockchain, vec![confirmed], &miner);
+        let input = &conflicting.get_vin()[0];
+        assert_eq!(
+            mempool.try_add(conflicting.clone(), &blockchain),
+            Err(AdmissionError::AlreadySpent {
+                txid: input.get_txid(),
+                vout: input.get_vout()
+            })
+        );
+
+        let elsewhere = temp_chain(&miner);
+        let unknown = pay(&elsewhere, &miner, &Wallet::new(), 3);
+        let input = &unknown.get_vin()[0];
+        assert_eq!(
+            mempool.try_add(unknown.clone(), &blockchain),
+            Err(AdmissionError::UnknownInput {
+                txid: input.get_txid(),
+                vout: input.get_vout()
+            })
+        );
+        assert!(mempool.is_empty());
+    }
+
+    #[test]
+    fn forged_signature_is_refused() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let mut json = serde_json::to_value(pay(&blockchain, &miner, &Wallet::new(), 3)).unwrap();
+        let byte = json["vin"][0]["signature"][0].as_u64().unwrap();
+        json["vin"][0]["signature"][0] = (byte ^ 1).into();
+        let forged: Transaction = serde_json::from_value(json).unwrap();
+        let mempool = MemoryPool::new(blockchain.get_params());
+        assert_eq!(
+            mempool.try_add(forged, &blockchain),
+            Err(AdmissionError::InvalidSignature)
+        );
+    }
+
+    #[test]
+    fn transaction_paying_out_more_than_it_spends_is_refused() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let tx = pay(&blockchain, &miner, &Wallet::new(), 3);
+        let inflating = with_values(&blockchain, &tx, &[5], &miner);
+        let mempool = MemoryPool::new(blockchain.get_params());
+        assert_eq!(
+            mempool.try_add(inflating, &blockchain),
+            Err(AdmissionError::FeeTooLow {
+                inputs: 10,
+                outputs: 11
+            })
+        );
+    }
+
+    #[test]
+    fn transaction_whose_values_overflow_is_refused() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let tx = pay(&blockchain, &miner, &Wallet::new(), 3);
+        let overflowing = with_values(&blockchain, &tx, &[u64::MAX], &miner);
+        let mempool = MemoryPool::new(blockchain.get_params());
+        assert_eq!(
+            mempool.try_add(overflowing, &blockchain),
+            Err(AdmissionError::InvalidValue)
+        );
+    }
+
+    /// The time each [Transaction] pending in `mempool` was added, by id.
+    fn added_times(mempool: &MemoryPool, blockchain: &Blockchain) -> HashMap<String, i64> {
+        mempool
+            .get_summaries(blockchain)
+            .into_iter()
+            .map(|summary| (summary.txid, summary.added_at))
+            .collect()
+    }
+
+    #[test]
+    fn saved_transactions_are_reloaded_with_the_time_they_were_added() {
+        let miner = Wallet::new();
+        let recipient = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let sender = funded(&blockchain);
+        let clock = Arc::new(MockClock::new(1_000));
+        let mempool = MemoryPool::with_clock(blockchain.get_params(), clock.clone());
+        let parent = pay(&blockchain, &miner, &recipient, 3);
+        let child = spend_pending(&blockchain, &parent, &recipient, &Wallet::new(), 1, 1);
+        let unrelated = pay(&blockchain, &sender, &Wallet::new(), 3);
+        for tx in [parent, child, unrelated] {
+            assert_eq!(mempool.try_add(tx, &blockchain), Ok(()));
+            clock.advance(1_000);
+        }
+
+        let tree = blockchain.get_db().open_tree(MEMPOOL_TREE).unwrap();
+        assert_eq!(mempool.save_to(&tree).unwrap(), 3);
+        let reloaded = MemoryPool::new(blockchain.get_params());
+        assert_eq!(reloaded.load_from(&tree, &blockchain).unwrap(), (3, 0));
+        assert_eq!(
+            added_times(&reloaded, &blockchain),
+            added_times(&mempool, &blockchain)
+        );
+        assert_eq!(reloaded.load_from(&tree, &blockchain).unwrap(), (0, 0));
+
+        assert_eq!(
+            MemoryPool::new(blockchain.get_params())
+                .save_to(&tree)
+                .unwrap(),
+            0
+        );
+        assert_eq!(reloaded.load_from(&tree, &blockchain).unwrap(), (0, 0));
+    }
+
+    #[test]
+    fn transactions_mined_while_the_node_was_down_are_dropped_on_reload() {
+        let miner = Wallet::new();
+        let recipient = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let (sender, other_sender) = (funded(&blockchain), funded(&blockchain));
+        // Every one is added in the same millisecond, so the child may sort before its
+        // parent when they are reloaded.
+        let clock = Arc::new(MockClock::new(1_000));
+        let mempool = MemoryPool::with_clock(blockchain.get_params(), clock);
+        let parent = pay(&blockchain, &miner, &recipient, 3);
+        let child = spend_pending(&blockchain, &parent, &recipient, &Wallet::new(), 1, 1);
+        let confirmed = pay(&blockchain, &sender, &Wallet::new(), 3);
+        let replaced = pay(&blockchain, &other_sender, &Wallet::new(), 3);
+        let conflicting = pay(&blockchain, &other_sender, &Wallet::new(), 4);
+        for tx in [parent.clone(), child.clone(), confirmed.clone(), replaced] {
+            assert_eq!(mempool.try_add(tx, &blockchain), Ok(()));
+        }
+        let tree = blockchain.get_db().open_tree(MEMPOOL_TREE).unwrap();
+        assert_eq!(mempool.save_to(&tree).unwrap(), 4);
+
+        mine(&blockchain, vec![confirmed, conflicting], &miner);
+        let reloaded = MemoryPool::new(blockchain.get_params());
+        assert_eq!(reloaded.load_from(&tree, &blockchain).unwrap(), (2, 2));
+        assert_eq!(reloaded.len(), 2);
+        assert!(reloaded.contains(parent.get_id()));
+        assert!(reloaded.contains(child.get_id()));
+    }
+
+    fn ids(transactions: &[Transaction]) -> Vec<Txid> {
+        transactions.iter().map(Transaction::get_id).collect()
+    }
+
+    #[test]
+    fn only_transactions_verified_on_a_replaced_branch_are_verified_again() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let genesis = blockchain.get_block_by_height(0).unwrap();
+        let tx = pay(&blockchain, &miner, &Wallet::new(), 3);
+        mine(&blockchain, vec![], &miner);
+        let mempool = MemoryPool::new(blockchain.get_params());
+        assert_eq!(mempool.try_add(tx.clone(), &blockchain), Ok(()));
+        // Swaps in a forged signature behind the pool's back, as it was verified already.
+        let mut json = serde_json::to_value(&tx).unwrap();
+        let byte = json["vin"][0]["signature"][0].as_u64().unwrap();
+        json["vin"][0]["signature"][0] = (byte ^ 1).into();
+        let forged: Transaction = serde_json::from_value(json).unwrap();
+        mempool
+            .txs
+            .write()
+            .unwrap()
+            .get_mut(&tx.get_id())
+            .unwrap()
+            .tx = forged.clone();
+
+        let trusted = mempool.get_block_template(&blockchain);
+        assert_eq!(ids(&trusted.transactions), vec![forged.get_id()]);
+
+        let other = Wallet::new();
+        let b1 = Block::new(
+            genesis.get_hash(),
+            vec![coinbase(&other, 0)],
+            1,
+            blockchain.get_next_target_bits(&genesis),
+            &*crate::clock::system_clock(),
+        );
+        blockchain.add_block(&b1).unwrap();
+        let b2 = Block::new(
+            b1.get_hash(),
+            vec![coinbase(&other, 0)],
+            2,
+            blockchain.get_next_target_bits(&b1),
+            &*crate::clock::system_clock(),
+        );
+        blockchain.add_block(&b2).unwrap();
+        assert_eq!(blockchain.get_tip_hash(), b2.get_hash());
+
+        let verified = mempool.get_block_template(&blockchain);
+        assert!(verified.transactions.is_empty());
+        assert!(mempool.is_empty());
+    }
+
+    #[test]
+    fn template_of_verified_transactions_skips_their_signatures() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let senders: Vec<Wallet> = (0..500).map(|_| funded(&blockchain)).collect();
+        let mempool = MemoryPool::new(blockchain.get_params());
+        for sender in &senders {
+            let tx = pay(&blockchain, sender, &Wallet::new(), 3);
+            assert_eq!(mempool.try_add(tx, &blockchain), Ok(()));
+        }
+
+        // The quickest of a few runs, so that a busy machine doesn't decide the test.
+        let timed = |forget: bool| {
+            (0..3)
+                .map(|_| {
+                    if forget {
+                        // As if every one had been verified on a branch since replaced.
+                        for entry in mempool.txs.write().unwrap().values_mut() {
+                            entry.verified_against_tip = BlockHash::default();
+                        }
+                    }
+                    let started = Instant::now();
+                    let template = mempool.get_block_template(&blockchain);
+                    (started.elapsed(), ids(&template.transactions))
+                })
+                .min()
+                .unwrap()
+        };
+        let (cached_time, cached) = timed(false);
+        let (verified_time, verified) = timed(true);
+
+        assert_eq!(cached, verified);
+        assert_eq!(mempool.len(), 500);
+        assert!(
+            cached_time * 2 < verified_time,
+            "{cached_time:?} with cached verification against {verified_time:?}"
+        );
+    }
+
+    #[test]
+    fn dropped_block_is_requested_again_until_the_peer_best_height_is_reached() {
+        let miner = Wallet::new();
+        let peer = temp_chain(&miner);
+        let mut announced = vec![];
+        for _ in 0..5 {
+            announced.push(mine(&peer, vec![], &miner).get_hash());
+        }
+        let genesis = peer.get_block_by_height(0).unwrap();
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let blockchain = Blockchain::open_or_init_in(db.into(), &genesis).unwrap();
+        crate::utxo_set::UTXOSet::new(blockchain.clone()).reindex();
+        let in_transit = BlockInTransit::with_timeout(Duration::from_millis(50));
+        in_transit.add_blocks("peer", &announced);
+
+        let dropped = announced[1];
+        let mut requests = HashMap::<BlockHash, usize>::new();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while !in_transit.is_empty() {
+            assert!(
+                Instant::now() < deadline,
+                "{} blocks still in transit",
+                in_transit.len()
+            );
+            for (_, block_hash) in in_transit.take_next(2) {
+                let count = requests.entry(block_hash).or_default();
+                *count += 1;
+                if block_hash == dropped && *count == 1 {
+                    continue;
+                }
+                // Stores the block like a node receiving it, along with those waiting on it.
+                let mut next = peer.get_block(block_hash);
+                while let Some(block) = next.take() {
+                    match blockchain.add_block(&block) {
+                        Ok(()) => {
+                            in_transit.mark_received(block.get_hash());
+                            next = in_transit.take_child(block.get_hash());
+                        }
+                        Err(_) => assert!(in_transit.park(&block)),
+                    }
+                }
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        assert_eq!(blockchain.get_best_height(), peer.get_best_height());
+        assert_eq!(blockchain.get_tip_hash(), peer.get_tip_hash());
+        assert_eq!(requests[&dropped], 2);
+        assert!(announced[2..]
+            .iter()
+            .all(|block_hash| requests[block_hash] == 1));
+    }
+}
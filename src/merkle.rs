@@ -0,0 +1,162 @@
+//! Merkle trees committing to the [Transaction]s of a [Block], so that a
+//! [Transaction] can be shown to be in a [Block] given only the root its header
+//! commits to.
+//!
+//! [Transaction]: crate::transactions::Transaction
+//! [Block]: crate::block::Block
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::{Txid, HASH_LEN};
+use crate::Sha256;
+
+/// The hashes of a Merkle tree, level by level from the [Txid] leaves up to the root.
+///
+/// A level with an odd number of hashes pairs its last hash with itself.
+#[derive(Debug, Clone)]
+pub struct MerkleTree {
+    levels: Vec<Vec<[u8; HASH_LEN]>>,
+}
+
+/// Shows that a leaf is in a [`MerkleTree`]: the position of the leaf and the hashes
+/// paired with it, and with each of the nodes above it, on the way up to the root.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    index: usize,
+    siblings: Vec<[u8; HASH_LEN]>,
+}
+
+impl MerkleTree {
+    pub fn new(leaves: &[Txid]) -> Self {
+        let mut level: Vec<[u8; HASH_LEN]> = leaves.iter().map(|txid| *txid.as_bytes()).collect();
+        let mut levels = vec![];
+        while level.len() > 1 {
+            let parents = level
+                .chunks(2)
+                .map(|pair| hash_pair(&pair[0], pair.get(1).unwrap_or(&pair[0])))
+                .collect();
+            levels.push(std::mem::replace(&mut level, parents));
+        }
+        levels.push(level);
+        Self { levels }
+    }
+
+    /// Returns the root hash, the SHA-256 of nothing for a tree without leaves.
+    pub fn root(&self) -> [u8; HASH_LEN] {
+        self.levels
+            .last()
+            .and_then(|level| level.first())
+            .copied()
+            .unwrap_or_else(|| Sha256::new().finish())
+    }
+
+    /// Builds the [`MerkleProof`] of the leaf at `index`, `None` when there is no such
+    /// leaf.
+    pub fn proof(&self, index: usize) -> Option<MerkleProof> {
+        self.levels.first()?.get(index)?;
+        let mut position = index;
+        let mut siblings = vec![];
+        for level in &self.levels[..self.levels.len() - 1] {
+            let sibling = level.get(position ^ 1).unwrap_or(&level[position]);
+            siblings.push(*sibling);
+            position /= 2;
+        }
+        Some(MerkleProof { index, siblings })
+    }
+
+    /// Checks that `proof` leads from `leaf` up to `root`.
+    pub fn verify_proof(root: &[u8; HASH_LEN], leaf: Txid, proof: &MerkleProof) -> bool {
+        let mut position = proof.index;
+        let mut hash = *leaf.as_bytes();
+        for sibling in &proof.siblings {
+            hash = if position.is_multiple_of(2) {
+                hash_pair(&hash, sibling)
+            } else {
+                hash_pair(sibling, &hash)
+            };
+            position /= 2;
+        }
+        position == 0 && hash == *root
+    }
+}
+
+impl MerkleProof {
+    /// Returns the position of the leaf among the leaves of the [`MerkleTree`].
+    pub const fn get_index(&self) -> usize {
+        self.index
+    }
+}
+
+fn hash_pair(left: &[u8; HASH_LEN], right: &[u8; HASH_LEN]) -> [u8; HASH_LEN] {
+    let mut context = Sha256::new();
+    context.update(left);
+    context.update(right);
+    context.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(count: u8) -> Vec<Txid> {
+        (0..count)
+            .map(|i| Txid::from_slice(&[i; HASH_LEN]).unwrap())
+            .collect()
+    }
+
+    fn check_every_proof(leaves: &[Txid]) {
+        let tree = MerkleTree::new(leaves);
+        for (index, leaf) in leaves.iter().enumerate() {
+            let proof = tree.proof(index).unwrap();
+            assert_eq!(proof.get_index(), index);
+            assert!(MerkleTree::verify_proof(&tree.root(), *leaf, &proof));
+        }
+        assert!(tree.proof(leaves.len()).is_none());
+    }
+
+    #[test]
+    fn one_leaf_is_the_root() {
+        let leaves = leaves(1);
+        assert_eq!(MerkleTree::new(&leaves).root(), *leaves[0].as_bytes());
+        check_every_proof(&leaves);
+    }
+
+    #[test]
+    fn two_leaves_hash_together() {
+        let leaves = leaves(2);
+        let root = hash_pair(leaves[0].as_bytes(), leaves[1].as_bytes());
+        assert_eq!(MerkleTree::new(&leaves).root(), root);
+        check_every_proof(&leaves);
+    }
+
+    #[test]
+    fn three_leaves_pair_the_last_with_itself() {
+        let leaves = leaves(3);
+        let [a, b, c] = [0, 1, 2].map(|i| *leaves[i].as_bytes());
+        let root = hash_pair(&hash_pair(&a, &b), &hash_pair(&c, &c));
+        assert_eq!(MerkleTree::new(&leaves).root(), root);
+        check_every_proof(&leaves);
+    }
+
+    #[test]
+    fn seven_leaves() {
+        let leaves = leaves(7);
+        let h: Vec<[u8; HASH_LEN]> = leaves.iter().map(|txid| *txid.as_bytes()).collect();
+        let left = hash_pair(&hash_pair(&h[0], &h[1]), &hash_pair(&h[2], &h[3]));
+        let right_pair = hash_pair(&h[6], &h[6]);
+        let right = hash_pair(&hash_pair(&h[4], &h[5]), &right_pair);
+        assert_eq!(MerkleTree::new(&leaves).root(), hash_pair(&left, &right));
+        check_every_proof(&leaves);
+    }
+
+    #[test]
+    fn tampered_leaf_is_rejected() {
+        let mut leaves = leaves(7);
+        let tree = MerkleTree::new(&leaves);
+        let proof = tree.proof(4).unwrap();
+        let tampered = Txid::from_slice(&[0xff; HASH_LEN]).unwrap();
+        assert!(!MerkleTree::verify_proof(&tree.root(), tampered, &proof));
+        leaves[4] = tampered;
+        assert_ne!(MerkleTree::new(&leaves).root(), tree.root());
+    }
+}
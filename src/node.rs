@@ -1,22 +1,144 @@
-use std::{net::SocketAddr, sync::RwLock};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::{self, Display, Formatter};
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use std::{error::Error, fs, path::Path, sync::RwLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::blockchain::Blockchain;
+use crate::config::{Network, GLOBAL_CONFIG};
+use crate::events::{Event, SUBSCRIBER_CAPACITY};
+use crate::memory_pool::MemoryPool;
+use crate::rpc::{self, RpcError};
+use crate::server::{self, Server, SyncStatus, GLOBAL_MEMORY_POOL, GLOBAL_WALLETS};
+use crate::{current_timestamp, random_u64, transactions::Transaction, wallets::WalletStore};
+
+/// How many block and transaction hashes are remembered per peer as known to it.
+pub const KNOWN_INVENTORY_CAPACITY: usize = 5000;
+/// How long a peer that hasn't been seen is remembered by default.
+pub const DEFAULT_PEER_EXPIRY: Duration = Duration::from_hours(7 * 24);
+
+/// The reasons a peer address can't be connected to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddressError {
+    /// The address isn't a `host:port` pair, such as `127.0.0.1:2001`, `[::1]:2001`
+    /// or `localhost:2001`.
+    Invalid(String),
+    /// Looking the host name up failed or found no address.
+    Unresolved { addr: String, reason: String },
+}
+
+impl Display for AddressError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid(addr) => write!(
+                f,
+                "`{addr}` is not a valid peer address, expected host:port, e.g. 127.0.0.1:2001"
+            ),
+            Self::Unresolved { addr, reason } => {
+                write!(f, "unable to resolve the peer address `{addr}`: {reason}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for AddressError {}
+
+/// Checks that `addr` has the form of a peer address: an IP address with a port, or
+/// a host name with a port, without looking the name up.
+///
+/// IPv6 addresses need brackets, as in `[::1]:2001`.
+pub fn is_valid_addr(addr: &str) -> bool {
+    if addr.parse::<SocketAddr>().is_ok() {
+        return true;
+    }
+    let Some((host, port)) = addr.rsplit_once(':') else {
+        return false;
+    };
+    port.parse::<u16>().is_ok()
+        && !host.is_empty()
+        && host.len() <= 253
+        && host.split('.').all(|label| {
+            !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+/// Resolves `addr` to the socket addresses it stands for, in the order they should
+/// be tried. IP addresses are returned as they are, host names are looked up.
+pub fn resolve_addr(addr: &str) -> Result<Vec<SocketAddr>, AddressError> {
+    if !is_valid_addr(addr) {
+        return Err(AddressError::Invalid(String::from(addr)));
+    }
+    let addrs: Vec<SocketAddr> = addr
+        .to_socket_addrs()
+        .map_err(|e| AddressError::Unresolved {
+            addr: String::from(addr),
+            reason: e.to_string(),
+        })?
+        .collect();
+    if addrs.is_empty() {
+        return Err(AddressError::Unresolved {
+            addr: String::from(addr),
+            reason: String::from("no addresses found"),
+        });
+    }
+    Ok(addrs)
+}
 
 /// Represents network nodes in the blockchain.
-#[derive(Clone)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Node {
     addr: String,
+    /// Milliseconds since the Unix epoch when the [Node] last answered, `None` until
+    /// it has.
+    #[serde(default)]
+    last_seen: Option<i64>,
+    /// Milliseconds since the Unix epoch when a connection to the [Node] was last
+    /// attempted.
+    #[serde(default)]
+    last_attempt: Option<i64>,
+    /// The inventory the [Node] announced, or was announced, so it's only announced
+    /// to it once.
+    #[serde(skip)]
+    known_inventory: KnownInventory,
 }
 
 impl Node {
-    const fn new(addr: String) -> Self {
-        Self { addr }
+    fn new(addr: String) -> Self {
+        Self {
+            addr,
+            last_seen: None,
+            last_attempt: None,
+            known_inventory: KnownInventory::default(),
+        }
     }
 
     pub fn get_addr(&self) -> String {
         self.addr.clone()
     }
 
-    pub fn parse_socket_addr(&self) -> SocketAddr {
-        self.addr.parse().unwrap()
+    pub const fn get_last_seen(&self) -> Option<i64> {
+        self.last_seen
+    }
+
+    pub const fn get_last_attempt(&self) -> Option<i64> {
+        self.last_attempt
+    }
+
+    /// Resolves the address of the [Node], returning the first socket address found.
+    /// See [`resolve_addr`] for all of them.
+    pub fn parse_socket_addr(&self) -> Result<SocketAddr, AddressError> {
+        resolve_addr(self.addr.as_str()).map(|addrs| addrs[0])
+    }
+
+    /// Checks whether the [Node] was seen within `max_age` of `now`.
+    fn seen_within(&self, max_age: Duration, now: i64) -> bool {
+        let max_age = i64::try_from(max_age.as_millis()).unwrap_or(i64::MAX);
+        self.last_seen
+            .is_some_and(|seen| now.saturating_sub(seen) <= max_age)
     }
 }
 
@@ -44,6 +166,93 @@ impl Nodes {
         }
     }
 
+    /// Records that the [Node] at `addr` answered just now.
+    pub fn mark_seen(&self, addr: &str) {
+        let now = current_timestamp();
+        let mut inner = self.0.write().unwrap();
+        if let Some(node) = inner.iter_mut().find(|x| x.get_addr().eq(addr)) {
+            node.last_seen = Some(now);
+        }
+    }
+
+    /// Records that a connection to the [Node] at `addr` is being attempted.
+    pub fn mark_attempt(&self, addr: &str) {
+        let now = current_timestamp();
+        let mut inner = self.0.write().unwrap();
+        if let Some(node) = inner.iter_mut().find(|x| x.get_addr().eq(addr)) {
+            node.last_attempt = Some(now);
+        }
+    }
+
+    /// Drops the [Node] at `addr` unless it was seen within `max_age`, returning
+    /// whether it was dropped.
+    pub fn evict_if_stale(&self, addr: &str, max_age: Duration) -> bool {
+        let now = current_timestamp();
+        let mut inner = self.0.write().unwrap();
+        let Some(idx) = inner.iter().position(|x| x.get_addr().eq(addr)) else {
+            return false;
+        };
+        if inner[idx].seen_within(max_age, now) {
+            return false;
+        }
+        inner.remove(idx);
+        true
+    }
+
+    /// Adds the [Node]s saved by [`Nodes::save`] to `path` that were seen within
+    /// `max_age`, returning how many were added. A missing file adds none.
+    pub fn load(&self, path: &Path, max_age: Duration) -> Result<usize, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(0);
+        }
+        let saved: Vec<Node> = serde_json::from_slice(fs::read(path)?.as_slice())?;
+        let now = current_timestamp();
+        let mut inner = self.0.write().unwrap();
+        let mut added = 0;
+        for node in saved {
+            if !node.seen_within(max_age, now) {
+                continue;
+            }
+            if let Some(known) = inner.iter_mut().find(|x| x.addr == node.addr) {
+                known.last_seen = known.last_seen.max(node.last_seen);
+                known.last_attempt = known.last_attempt.max(node.last_attempt);
+            } else {
+                inner.push(node);
+                added += 1;
+            }
+        }
+        drop(inner);
+        Ok(added)
+    }
+
+    /// Writes the [Node]s seen within `max_age` as JSON, replacing the file at `path`
+    /// atomically.
+    pub fn save(&self, path: &Path, max_age: Duration) -> Result<(), Box<dyn Error>> {
+        let now = current_timestamp();
+        let nodes: Vec<Node> = self
+            .get_nodes()
+            .into_iter()
+            .filter(|node| node.seen_within(max_age, now))
+            .collect();
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(tmp_path.as_path(), serde_json::to_vec(&nodes)?)?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Returns up to `n` of the [Node]s seen within `max_age`, most recently seen first.
+    pub fn get_recently_seen(&self, n: usize, max_age: Duration) -> Vec<Node> {
+        let now = current_timestamp();
+        let mut nodes: Vec<Node> = self
+            .get_nodes()
+            .into_iter()
+            .filter(|node| node.seen_within(max_age, now))
+            .collect();
+        nodes.sort_by_key(|node| std::cmp::Reverse(node.last_seen));
+        nodes.truncate(n);
+        nodes
+    }
+
     pub fn first(&self) -> Option<Node> {
         if let Some(node) = self.0.read().unwrap().first() {
             return Some(node.clone());
@@ -63,8 +272,402 @@ impl Nodes {
         self.len() == 0
     }
 
+    /// Returns up to `n` [Node]s picked at random.
+    pub fn get_random_sample(&self, n: usize) -> Vec<Node> {
+        let mut nodes = self.get_nodes();
+        let n = n.min(nodes.len());
+        for i in 0..n {
+            let remaining = (nodes.len() - i) as u64;
+            let j = i + usize::try_from(random_u64() % remaining).unwrap();
+            nodes.swap(i, j);
+        }
+        nodes.truncate(n);
+        nodes
+    }
+
     /// Returns true if a [Node] with the given address in in the collection.
     pub fn node_is_known(&self, addr: &str) -> bool {
         self.0.read().unwrap().iter().any(|x| x.get_addr().eq(addr))
     }
+
+    /// Records that the [Node] at `addr` knows about the block or transaction
+    /// hashed to `item`, returning `false` when it was known already. A [Node] not in
+    /// the collection knows about nothing.
+    pub fn add_known_inventory(&self, addr: &str, item: &[u8]) -> bool {
+        let mut inner = self.0.write().unwrap();
+        let added = inner
+            .iter_mut()
+            .find(|x| x.get_addr().eq(addr))
+            .is_none_or(|node| node.known_inventory.insert(item));
+        drop(inner);
+        added
+    }
+}
+
+/// The most recently used block and transaction hashes known to a peer, up to
+/// [`KNOWN_INVENTORY_CAPACITY`].
+#[derive(Clone, Debug, Default)]
+struct KnownInventory {
+    items: HashSet<Vec<u8>>,
+    /// The items from least to most recently used.
+    order: VecDeque<Vec<u8>>,
+}
+
+impl KnownInventory {
+    /// Marks `item` as the most recently used, returning `false` when it was known
+    /// already.
+    fn insert(&mut self, item: &[u8]) -> bool {
+        if self.items.contains(item) {
+            if let Some(idx) = self.order.iter().position(|x| x.as_slice() == item) {
+                let item = self.order.remove(idx).unwrap();
+                self.order.push_back(item);
+            }
+            return false;
+        }
+        self.items.insert(item.to_vec());
+        self.order.push_back(item.to_vec());
+        if self.order.len() > KNOWN_INVENTORY_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.items.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+/// The ban score past which a peer is banned by default.
+pub const DEFAULT_BAN_THRESHOLD: u32 = 100;
+/// How long a misbehaving peer is banned by default.
+pub const DEFAULT_BAN_DURATION: Duration = Duration::from_hours(24);
+
+/// A peer refused until `banned_until`, milliseconds since the Unix epoch.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Ban {
+    pub address: IpAddr,
+    pub banned_until: i64,
+    pub reason: String,
+}
+
+/// The ban scores of misbehaving peers and the peers banned for it.
+///
+/// Peers are told apart by IP address, as every package arrives on a new connection
+/// from another port.
+#[derive(Default)]
+pub struct BanList {
+    scores: RwLock<HashMap<IpAddr, u32>>,
+    bans: RwLock<HashMap<IpAddr, Ban>>,
+}
+
+impl BanList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `penalty` to the ban score of `ip`, banning it for `duration` once the
+    /// score reaches `threshold`. Returns whether `ip` got banned.
+    pub fn misbehaved(
+        &self,
+        ip: IpAddr,
+        penalty: u32,
+        threshold: u32,
+        duration: Duration,
+        reason: &str,
+    ) -> bool {
+        let mut scores = self.scores.write().unwrap();
+        let score = scores.entry(ip).or_default();
+        *score = score.saturating_add(penalty);
+        if *score < threshold {
+            return false;
+        }
+        scores.remove(&ip);
+        drop(scores);
+        self.ban(ip, duration, reason);
+        true
+    }
+
+    pub fn get_score(&self, ip: IpAddr) -> u32 {
+        self.scores
+            .read()
+            .unwrap()
+            .get(&ip)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Refuses `ip` for `duration` from now.
+    pub fn ban(&self, ip: IpAddr, duration: Duration, reason: &str) {
+        let duration = i64::try_from(duration.as_millis()).unwrap_or(i64::MAX);
+        let ban = Ban {
+            address: ip,
+            banned_until: current_timestamp().saturating_add(duration),
+            reason: String::from(reason),
+        };
+        self.bans.write().unwrap().insert(ip, ban);
+    }
+
+    /// Lifts the ban on `ip`, returning whether it was banned.
+    pub fn unban(&self, ip: IpAddr) -> bool {
+        self.scores.write().unwrap().remove(&ip);
+        self.bans.write().unwrap().remove(&ip).is_some()
+    }
+
+    pub fn is_banned(&self, ip: IpAddr) -> bool {
+        let now = current_timestamp();
+        self.bans
+            .read()
+            .unwrap()
+            .get(&ip)
+            .is_some_and(|ban| ban.banned_until > now)
+    }
+
+    /// Returns the bans that haven't expired yet, those ending first first.
+    pub fn get_bans(&self) -> Vec<Ban> {
+        let now = current_timestamp();
+        let mut bans: Vec<Ban> = self
+            .bans
+            .read()
+            .unwrap()
+            .values()
+            .filter(|ban| ban.banned_until > now)
+            .cloned()
+            .collect();
+        bans.sort_by_key(|ban| ban.banned_until);
+        bans
+    }
+
+    /// Adds the bans saved by [`BanList::save`] to `path` that haven't expired yet.
+    /// A missing file adds none.
+    pub fn load(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(());
+        }
+        let saved: Vec<Ban> = serde_json::from_slice(fs::read(path)?.as_slice())?;
+        let now = current_timestamp();
+        let mut bans = self.bans.write().unwrap();
+        for ban in saved.into_iter().filter(|ban| ban.banned_until > now) {
+            bans.insert(ban.address, ban);
+        }
+        drop(bans);
+        Ok(())
+    }
+
+    /// Writes the bans that haven't expired yet as JSON, replacing the file at `path`
+    /// atomically.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(tmp_path.as_path(), serde_json::to_vec(&self.get_bans())?)?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+}
+
+/// Configures a node to run inside the current process, see [`NodeHandle::builder`].
+///
+/// Settings left unset keep the value they have in the global
+/// [`Config`](crate::config::Config), which is shared by the whole process, so only
+/// one node can run at a time.
+#[derive(Debug, Clone, Default)]
+pub struct NodeBuilder {
+    network: Option<Network>,
+    bind: Option<String>,
+    miner: Option<String>,
+    peers: Vec<String>,
+    listen: Option<bool>,
+}
+
+impl NodeBuilder {
+    #[must_use]
+    pub const fn network(mut self, network: Network) -> Self {
+        self.network = Some(network);
+        self
+    }
+
+    /// Sets the address the node accepts peer connections on.
+    #[must_use]
+    pub fn bind(mut self, addr: impl Into<String>) -> Self {
+        self.bind = Some(addr.into());
+        self
+    }
+
+    /// Turns mining on, paying block rewards to `address`.
+    #[must_use]
+    pub fn miner(mut self, address: impl Into<String>) -> Self {
+        self.miner = Some(address.into());
+        self
+    }
+
+    /// Sets the peers the node announces itself to instead of the central node.
+    #[must_use]
+    pub fn peers(mut self, peers: &[String]) -> Self {
+        self.peers = peers.to_vec();
+        self
+    }
+
+    /// Turns accepting peer connections on or off, only making outbound ones when off.
+    #[must_use]
+    pub const fn listen(mut self, listen: bool) -> Self {
+        self.listen = Some(listen);
+        self
+    }
+
+    /// Opens the existing [Blockchain], binds the node's listeners, announces it to
+    /// its peers and serves connections on a background thread.
+    pub fn start(self) -> Result<NodeHandle, Box<dyn Error>> {
+        if let Some(network) = self.network {
+            GLOBAL_CONFIG.set_network(network);
+        }
+        if let Some(addr) = self.bind {
+            GLOBAL_CONFIG.set_node_addr(addr);
+        }
+        if let Some(address) = self.miner {
+            GLOBAL_CONFIG.set_mining_addr(address);
+        }
+        if !self.peers.is_empty() {
+            GLOBAL_CONFIG.set_peers(self.peers.as_slice());
+        }
+        if let Some(listen) = self.listen {
+            GLOBAL_CONFIG.set_listen(listen);
+        }
+        if let Err(errors) = GLOBAL_CONFIG.validate() {
+            let messages: Vec<String> = errors.iter().map(ToString::to_string).collect();
+            return Err(format!("invalid configuration: {}", messages.join(", ")).into());
+        }
+        let blockchain = Blockchain::open()?;
+        let addr = GLOBAL_CONFIG.get_node_addr();
+        let server = Server::new(blockchain.clone());
+        let listener = server.bind(addr.as_str())?;
+        let thread_addr = addr.clone();
+        let thread = thread::Builder::new()
+            .name(String::from("node"))
+            .spawn(move || server.accept(thread_addr.as_str(), listener))?;
+        Ok(NodeHandle {
+            addr,
+            blockchain,
+            thread,
+        })
+    }
+}
+
+/// A node running inside the current process, started with [`NodeBuilder::start`].
+///
+/// The JSON-RPC, REST, gRPC and notification listeners keep running until the
+/// process exits, even after [`NodeHandle::shutdown`].
+pub struct NodeHandle {
+    addr: String,
+    blockchain: Blockchain,
+    thread: JoinHandle<()>,
+}
+
+impl NodeHandle {
+    pub fn builder() -> NodeBuilder {
+        NodeBuilder::default()
+    }
+
+    /// Returns the address the node accepts peer connections on.
+    pub const fn get_addr(&self) -> &str {
+        self.addr.as_str()
+    }
+
+    pub const fn get_blockchain(&self) -> &Blockchain {
+        &self.blockchain
+    }
+
+    pub fn get_mempool(&self) -> &'static MemoryPool {
+        &GLOBAL_MEMORY_POOL
+    }
+
+    /// Returns the [`WalletStore`] the node spends from.
+    pub fn get_wallets(&self) -> WalletStore {
+        GLOBAL_WALLETS.clone()
+    }
+
+    pub fn get_sync_status(&self) -> SyncStatus {
+        server::sync_status()
+    }
+
+    /// Checks `tx` against the chain and the mempool, adds it to the mempool and
+    /// relays it, returning its txid.
+    pub fn submit_transaction(&self, tx: &Transaction) -> Result<String, RpcError> {
+        rpc::send_raw(&self.blockchain, tx)
+    }
+
+    /// Returns a receiver for every [Event] of both the [Blockchain] and the mempool.
+    pub fn subscribe_events(&self) -> Receiver<Event> {
+        let (sender, receiver) = mpsc::sync_channel(SUBSCRIBER_CAPACITY);
+        self.blockchain.subscribe_with(sender.clone());
+        GLOBAL_MEMORY_POOL.subscribe_with(sender);
+        receiver
+    }
+
+    /// Blocks until the node stops serving connections.
+    pub fn wait(self) -> Result<(), Box<dyn Error>> {
+        self.thread
+            .join()
+            .map_err(|_| "the node thread panicked".into())
+    }
+
+    /// Stops serving connections and waits for the node thread to finish.
+    pub fn shutdown(self) -> Result<(), Box<dyn Error>> {
+        server::request_shutdown(self.addr.as_str());
+        self.wait()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use super::*;
+
+    const PEER: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+
+    #[test]
+    fn host_names_and_ipv6_literals_resolve_to_their_socket_addresses() {
+        let localhost = resolve_addr("localhost:2001").unwrap();
+        assert!(!localhost.is_empty());
+        assert!(localhost
+            .iter()
+            .all(|addr| addr.ip().is_loopback() && addr.port() == 2001));
+        assert_eq!(
+            Node::new(String::from("[::1]:2001")).parse_socket_addr(),
+            Ok(SocketAddr::from((Ipv6Addr::LOCALHOST, 2001)))
+        );
+        assert!(matches!(
+            Node::new(String::from("nowhere.invalid:2001")).parse_socket_addr(),
+            Err(AddressError::Unresolved { addr, .. }) if addr == "nowhere.invalid:2001"
+        ));
+        assert_eq!(
+            resolve_addr("::1"),
+            Err(AddressError::Invalid(String::from("::1")))
+        );
+    }
+
+    #[test]
+    fn peer_is_banned_once_its_score_reaches_the_threshold() {
+        let bans = BanList::new();
+        assert!(!bans.misbehaved(PEER, 60, 100, DEFAULT_BAN_DURATION, "sent garbage"));
+        assert_eq!(bans.get_score(PEER), 60);
+        assert!(!bans.is_banned(PEER));
+
+        assert!(bans.misbehaved(PEER, 40, 100, DEFAULT_BAN_DURATION, "sent garbage"));
+        assert!(bans.is_banned(PEER));
+        assert_eq!(bans.get_score(PEER), 0);
+        assert!(!bans.is_banned(IpAddr::V4(Ipv4Addr::new(192, 0, 2, 2))));
+        let listed = bans.get_bans();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].address, PEER);
+        assert_eq!(listed[0].reason, "sent garbage");
+
+        assert!(bans.unban(PEER));
+        assert!(!bans.is_banned(PEER));
+        assert!(!bans.unban(PEER));
+    }
+
+    #[test]
+    fn expired_ban_no_longer_refuses_the_peer() {
+        let bans = BanList::new();
+        bans.ban(PEER, Duration::ZERO, "testing");
+        assert!(!bans.is_banned(PEER));
+        assert!(bans.get_bans().is_empty());
+    }
 }
@@ -0,0 +1,177 @@
+use std::io::{BufWriter, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver};
+use std::{error::Error, thread, time::Duration};
+
+use log::info;
+use serde_json::{json, Value};
+
+use crate::events::{Event, SUBSCRIBER_CAPACITY};
+use crate::{blockchain::Blockchain, server::GLOBAL_MEMORY_POOL};
+
+/// How long writing a notification may block before the client is dropped as too slow.
+const NOTIFY_WRITE_TIMEOUT: u64 = 1000;
+
+/// Publishes every [Event] as a line of JSON to each client connecting to `listener`.
+///
+/// There is no subscription protocol and delivery is best effort: a client that
+/// falls behind is disconnected, and the chain and the mempool never wait for one.
+pub fn serve(listener: &TcpListener, blockchain: &Blockchain) {
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let (sender, receiver) = mpsc::sync_channel(SUBSCRIBER_CAPACITY);
+        blockchain.subscribe_with(sender.clone());
+        GLOBAL_MEMORY_POOL.subscribe_with(sender);
+        thread::spawn(move || {
+            let peer = stream
+                .peer_addr()
+                .map_or_else(|_| String::from("unknown"), |addr| addr.to_string());
+            match publish(stream, &receiver) {
+                Ok(()) => info!("Notification client {peer} fell behind"),
+                Err(e) => info!("Notification client {peer} disconnected: {e}"),
+            }
+        });
+    }
+}
+
+/// Writes [Event]s to `stream` until it fails or the
+/// [`EventBus`](crate::events::EventBus) drops the subscription.
+fn publish(stream: TcpStream, receiver: &Receiver<Event>) -> Result<(), Box<dyn Error>> {
+    stream.set_write_timeout(Some(Duration::from_millis(NOTIFY_WRITE_TIMEOUT)))?;
+    let mut writer = BufWriter::new(stream);
+    for event in receiver {
+        writeln!(writer, "{}", notification(&event))?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Renders an [Event] as e.g. `{"event":"block","hash":...,"height":...}`.
+fn notification(event: &Event) -> Value {
+    match event {
+        Event::Block { hash, height, .. } => {
+            json!({ "event": "block", "hash": hash, "height": height })
+        }
+        Event::Tx { txid, status, .. } => {
+            json!({ "event": "tx", "txid": txid, "status": status })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::EventBus;
+    use crate::test_utils::{mine, temp_chain};
+    use crate::wallet::Wallet;
+    use std::io::{BufRead, BufReader, ErrorKind};
+    use std::time::Instant;
+
+    /// Reads notifications from `client` until a block one arrives, or returns `None`
+    /// when the stream stays quiet for a while.
+    fn next_block(client: &mut BufReader<TcpStream>) -> Option<Value> {
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match client.read_line(&mut line) {
+                Ok(0) => return None,
+                Ok(_) => {
+                    let notification: Value = serde_json::from_str(line.as_str()).unwrap();
+                    if notification["event"] == "block" {
+                        return Some(notification);
+                    }
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {
+                    return None;
+                }
+                Err(e) => panic!("{e}"),
+            }
+        }
+    }
+
+    #[test]
+    fn every_client_hears_of_a_new_block() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let served = blockchain.clone();
+        thread::spawn(move || serve(&listener, &served));
+        let mut clients: Vec<BufReader<TcpStream>> = (0..2)
+            .map(|_| {
+                let stream = TcpStream::connect(addr).unwrap();
+                stream
+                    .set_read_timeout(Some(Duration::from_millis(100)))
+                    .unwrap();
+                BufReader::new(stream)
+            })
+            .collect();
+
+        // The clients are subscribed once they have heard of any block.
+        let deadline = Instant::now() + Duration::from_secs(10);
+        let mut subscribed = [false, false];
+        while !subscribed.iter().all(|&heard| heard) {
+            assert!(
+                Instant::now() < deadline,
+                "the clients never got subscribed"
+            );
+            mine(&blockchain, vec![], &miner);
+            for (client, heard) in clients.iter_mut().zip(subscribed.iter_mut()) {
+                while next_block(client).is_some() {
+                    *heard = true;
+                }
+            }
+        }
+
+        let block = mine(&blockchain, vec![], &miner);
+        for client in &mut clients {
+            let notification = next_block(client).unwrap();
+            assert_eq!(notification["hash"], block.get_hash().to_string());
+            assert_eq!(notification["height"], block.get_height());
+        }
+    }
+
+    #[test]
+    fn stalled_client_is_dropped_without_holding_up_the_others() {
+        let bus = EventBus::new();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut publishers = vec![];
+        let mut clients = vec![];
+        for _ in 0..2 {
+            let receiver = bus.subscribe();
+            clients.push(TcpStream::connect(addr).unwrap());
+            let (stream, _) = listener.accept().unwrap();
+            publishers.push(thread::spawn(move || publish(stream, &receiver).is_ok()));
+        }
+        let stalled = publishers.remove(0);
+        let mut active = BufReader::new(clients.pop().unwrap());
+
+        // Long hashes fill the stalled client's socket buffers sooner.
+        let hash = "ab".repeat(512);
+        let deadline = Instant::now() + Duration::from_secs(30);
+        let mut height = 0;
+        while !stalled.is_finished() {
+            assert!(Instant::now() < deadline, "the stalled client was kept");
+            for _ in 0..100 {
+                height += 1;
+                let event = Event::Block {
+                    hash: hash.clone(),
+                    height,
+                    addresses: vec![],
+                };
+                bus.publish(&event);
+            }
+            let mut line = String::new();
+            for _ in 0..100 {
+                line.clear();
+                active.read_line(&mut line).unwrap();
+            }
+            let notification: Value = serde_json::from_str(line.as_str()).unwrap();
+            assert_eq!(notification["height"], height);
+        }
+        assert!(!publishers[0].is_finished());
+    }
+}
@@ -1,60 +1,285 @@
-use std::{borrow::Borrow, ops::ShlAssign};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::{borrow::Borrow, ops::ShlAssign, thread};
 
-use data_encoding::HEXLOWER;
-use num::{bigint::Sign, BigInt};
+use log::{debug, info};
+use num::{bigint::Sign, BigInt, BigUint};
 
-use crate::{block::Block, sha256_digest};
+use crate::block::{Block, BlockHeader};
+use crate::{sha256d, types::BlockHash};
 
-const TARGET_BITS: i64 = i64::MAX;
-const MAX_NONCE: i64 = 0;
+const MAX_NONCE: i64 = i64::MAX;
+/// The highest difficulty, leaving a target of a single hash.
+pub const MAX_TARGET_BITS: i64 = 255;
+
+/// Stops a [`ProofOfWork::run`] in progress from another thread. Clones share the
+/// same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle(Arc<AtomicBool>);
+
+impl CancelHandle {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
 
-#[allow(dead_code)]
 pub struct ProofOfWork {
-    block: Block,
+    pre_block_hash: BlockHash,
+    /// The hash of the [Transaction](crate::transactions::Transaction) ids of the
+    /// [Block](crate::block::Block), computed once by the caller.
+    transactions_hash: [u8; 32],
+    timestamp: i64,
+    target_bits: i64,
     target: BigInt,
 }
 
 impl ProofOfWork {
-    pub fn new(block: Block) -> Self {
+    /// Prepares to mine the [Block](crate::block::Block) with these header fields,
+    /// without holding on to its [Transaction](crate::transactions::Transaction)s.
+    pub fn new(
+        pre_block_hash: BlockHash,
+        transactions_hash: [u8; 32],
+        timestamp: i64,
+        target_bits: i64,
+    ) -> Self {
         let mut target = BigInt::from(1);
-        target.shl_assign(256 - TARGET_BITS);
-        Self { block, target }
+        target.shl_assign(256 - target_bits);
+        Self {
+            pre_block_hash,
+            transactions_hash,
+            timestamp,
+            target_bits,
+            target,
+        }
     }
 
     pub fn prepare_data(&self, nonce: i64) -> Vec<u8> {
-        let pre_block_hash = self.block.get_pre_block_hash();
-        let transactions_hash = self.block.hash_transactions();
-        let timestamp = self.block.get_timestamp();
         let mut data_bytes = Vec::new();
-        data_bytes.extend(pre_block_hash.as_bytes());
-        data_bytes.extend(transactions_hash);
-        data_bytes.extend(timestamp.to_be_bytes());
-        data_bytes.extend(TARGET_BITS.to_be_bytes());
+        data_bytes.extend(self.pre_block_hash.as_bytes());
+        data_bytes.extend(self.transactions_hash);
+        data_bytes.extend(self.timestamp.to_be_bytes());
+        data_bytes.extend(self.target_bits.to_be_bytes());
         data_bytes.extend(nonce.to_be_bytes());
         data_bytes
     }
 
-    /// TODO: remove `println!`.
+    /// Prepares to check the proof of work of an existing [Block].
+    pub fn for_block(block: &Block) -> Self {
+        Self::new(
+            block.get_pre_block_hash(),
+            block.hash_transactions(),
+            block.get_timestamp(),
+            block.get_target_bits(),
+        )
+    }
+
+    /// Prepares to check the proof of work of a [`BlockHeader`].
+    pub fn for_header(header: &BlockHeader) -> Self {
+        Self::new(
+            header.get_pre_block_hash(),
+            *header.get_merkle_root(),
+            header.get_timestamp(),
+            header.get_target_bits(),
+        )
+    }
+
+    /// The number of hashes expected to find a nonce meeting the target, which a
+    /// [Block] adds to the work of its chain.
+    pub fn work(&self) -> BigUint {
+        let mut space = BigInt::from(1);
+        space.shl_assign(256);
+        (space / &self.target)
+            .to_biguint()
+            .expect("the target is positive")
+    }
+
+    fn hash(&self, nonce: i64) -> BlockHash {
+        BlockHash::from(sha256d(self.prepare_data(nonce).as_slice()))
+    }
+
+    fn meets_target(&self, hash: BlockHash) -> bool {
+        BigInt::from_bytes_be(Sign::Plus, hash.as_bytes()).lt(self.target.borrow())
+    }
+
     /// Part of the [`ProofOfWork`] algorithm, used to find a nonce value that produces
     /// a hash of the [Block] data that is lower than the specific target value.
     ///
+    /// The nonces up to [`MAX_NONCE`] are split into one range per thread, and every
+    /// thread stops as soon as one of them finds a nonce or `cancel` is triggered.
+    ///
     /// Returns a tuple containing the found nonce value and the hash that was
-    /// produced using it.
-    pub fn run(&self) -> (i64, String) {
-        let mut nonce = 0;
-        let mut hash = Vec::new();
-        println!("mining the block");
-        while nonce < MAX_NONCE {
-            let data = self.prepare_data(nonce);
-            hash = sha256_digest(data.as_slice());
-            let hash_int = BigInt::from_bytes_be(Sign::Plus, hash.as_slice());
-            if hash_int.lt(self.target.borrow()) {
-                println!("{}", HEXLOWER.encode(hash.as_slice()));
-                break;
-            }
-            nonce += 1;
+    /// produced using it, or `None` when cancelled or no nonce does.
+    pub fn run(&self, threads: usize, cancel: &CancelHandle) -> Option<(i64, BlockHash)> {
+        info!("Mining the block on {threads} threads");
+        let threads = i64::try_from(threads.max(1)).unwrap_or(1);
+        let chunk = MAX_NONCE / threads;
+        let found = AtomicBool::new(false);
+        let result = thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads)
+                .map(|idx| {
+                    let start = idx * chunk;
+                    let end = if idx == threads - 1 {
+                        MAX_NONCE
+                    } else {
+                        start + chunk - 1
+                    };
+                    let found = &found;
+                    scope.spawn(move || {
+                        for nonce in start..=end {
+                            if found.load(Ordering::Relaxed) || cancel.is_cancelled() {
+                                return None;
+                            }
+                            let hash = self.hash(nonce);
+                            if self.meets_target(hash) {
+                                found.store(true, Ordering::Relaxed);
+                                return Some((nonce, hash));
+                            }
+                        }
+                        None
+                    })
+                })
+                .collect();
+            workers
+                .into_iter()
+                .filter_map(|worker| worker.join().expect("a mining thread panicked"))
+                .min_by_key(|(nonce, _)| *nonce)
+        });
+        if let Some((_, hash)) = result {
+            debug!("Found hash {hash}");
         }
-        println!();
-        (nonce, HEXLOWER.encode(hash.as_slice()))
+        result
+    }
+
+    /// Checks that `nonce` produces `hash` and that it is below the target, as a
+    /// [Block] received from a peer must.
+    pub fn validate(&self, nonce: i64, hash: BlockHash) -> bool {
+        nonce >= 0 && self.hash(nonce) == hash && self.meets_target(hash)
+    }
+}
+
+/// The change in target bits that brings [Block]s found in `actual` milliseconds back
+/// to `expected`: the power of two nearest to the ratio of the two, clamped to 4x.
+pub const fn retarget_step(expected: u128, actual: u128) -> i64 {
+    // Rounding in log2 space moves one bit once the ratio reaches √2 and two at 2√2.
+    let expected_sq = expected.saturating_mul(expected);
+    let actual_sq = actual.saturating_mul(actual);
+    if expected_sq >= actual_sq.saturating_mul(8) {
+        2
+    } else if expected_sq >= actual_sq.saturating_mul(2) {
+        1
+    } else if actual_sq >= expected_sq.saturating_mul(8) {
+        -2
+    } else if actual_sq >= expected_sq.saturating_mul(2) {
+        -1
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::time::{Duration, Instant};
+
+    use serde::{de::DeserializeOwned, Serialize};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::clock::system_clock;
+    use crate::test_utils::{coinbase, regtest};
+    use crate::wallet::Wallet;
+
+    /// High enough that mining takes a search, low enough to keep the tests quick.
+    const TARGET_BITS: i64 = 12;
+
+    fn mine(miner: &Wallet) -> Block {
+        regtest();
+        Block::new(
+            BlockHash::default(),
+            vec![coinbase(miner, 0)],
+            1,
+            TARGET_BITS,
+            &*system_clock(),
+        )
+    }
+
+    /// Returns `value` with `field` rewritten by `rewrite`.
+    fn tampered<T: Serialize + DeserializeOwned>(
+        value: &T,
+        field: &str,
+        rewrite: impl FnOnce(&mut Value),
+    ) -> T {
+        let mut json = serde_json::to_value(value).unwrap();
+        rewrite(&mut json[field]);
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn mined_block_validates() {
+        let block = mine(&Wallet::new());
+        assert!(block.validate_pow());
+        assert!(block.get_header().validate_pow());
+        let pow = ProofOfWork::for_block(&block);
+        assert!(pow.validate(block.get_nonce(), block.get_hash()));
+        assert!(!pow.validate(-1, block.get_hash()));
+    }
+
+    #[test]
+    fn tampered_block_does_not_validate() {
+        let miner = Wallet::new();
+        let block = mine(&miner);
+        let other = serde_json::to_value(coinbase(&miner, 1)).unwrap();
+        let changed_tx = tampered(&block, "transactions", |txs| txs[0] = other);
+        assert!(!changed_tx.validate_pow());
+        let changed_nonce = tampered(&block, "nonce", |nonce| {
+            *nonce = (nonce.as_i64().unwrap() + 1).into();
+        });
+        assert!(!changed_nonce.validate_pow());
+        let changed_timestamp = tampered(&block, "timestamp", |timestamp| {
+            *timestamp = (timestamp.as_i64().unwrap() + 1).into();
+        });
+        assert!(!changed_timestamp.validate_pow());
+
+        let header = block.get_header();
+        let changed_root = tampered(&header, "merkle_root", |root| {
+            root[0] = (root[0].as_u64().unwrap() ^ 1).into();
+        });
+        assert!(header.validate_pow());
+        assert!(!changed_root.validate_pow());
+    }
+
+    #[test]
+    fn four_threads_find_a_nonce_meeting_the_target() {
+        let pow = ProofOfWork::new(BlockHash::default(), [7; 32], 1, TARGET_BITS);
+        let (nonce, hash) = pow.run(4, &CancelHandle::new()).unwrap();
+        assert!(pow.validate(nonce, hash));
+    }
+
+    #[test]
+    fn cancelling_stops_every_thread_promptly() {
+        // No hash meets the highest difficulty, so the threads only stop when told.
+        let pow = ProofOfWork::new(BlockHash::default(), [7; 32], 1, MAX_TARGET_BITS);
+        let cancel = CancelHandle::new();
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn({
+            let cancel = cancel.clone();
+            move || sender.send(pow.run(4, &cancel)).unwrap()
+        });
+        thread::sleep(Duration::from_millis(100));
+        assert!(receiver.try_recv().is_err());
+
+        let cancelled_at = Instant::now();
+        cancel.cancel();
+        let result = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(result, None);
+        assert!(cancelled_at.elapsed() < Duration::from_secs(1));
     }
 }
@@ -0,0 +1,465 @@
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::{error::Error, thread, time::Duration};
+
+use log::{error, info};
+use serde_json::{json, Value};
+
+use crate::blockchain::Blockchain;
+use crate::events::{Event, SUBSCRIBER_CAPACITY};
+use crate::http::{self, Request, Status};
+use crate::server::{node_status, GLOBAL_MEMORY_POOL};
+use crate::summary::{AddressTransaction, BlockSummary, TransactionDetail};
+use crate::{config::GLOBAL_CONFIG, utxo_set::UTXOSet, wallet::address_pub_key_hash};
+use crate::{transactions::TXOutput, types::Txid};
+
+/// The page size when a request doesn't give `limit`.
+const DEFAULT_LIMIT: usize = 20;
+/// The largest page size a request may ask for, larger limits are capped.
+const MAX_LIMIT: usize = 100;
+const ALLOWED_METHODS: &str = "GET, OPTIONS";
+/// How long an event stream may stay quiet before a keep-alive comment is sent.
+const KEEP_ALIVE: u64 = 15_000;
+/// How long writing an event may block before the subscriber is dropped as too slow.
+const EVENT_WRITE_TIMEOUT: u64 = 5000;
+
+/// A failed request, answered with `status` and a JSON error object.
+type RestError = (Status, String);
+
+/// Serves the read-only REST API on `listener`, one thread per connection.
+pub fn serve(listener: &TcpListener, blockchain: &Blockchain) {
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let blockchain = blockchain.clone();
+        thread::spawn(move || {
+            if let Err(e) = handle_connection(&blockchain, stream) {
+                error!("REST connection failed: {e}");
+            }
+        });
+    }
+}
+
+fn handle_connection(blockchain: &Blockchain, mut stream: TcpStream) -> Result<(), Box<dyn Error>> {
+    let cors_origin = GLOBAL_CONFIG.get_rest_cors_origin();
+    let mut headers = vec![];
+    if let Some(origin) = cors_origin.as_deref() {
+        headers.push(("Access-Control-Allow-Origin", origin));
+    }
+    let request = match http::read_request(&stream) {
+        Ok(request) => request,
+        Err(status) => {
+            let body = error_body(status, status.1);
+            return http::write_response(&mut stream, status, &headers, &body);
+        }
+    };
+    let (status, body) = match request.method.as_str() {
+        "GET" if request.path() == "/events" => {
+            return stream_events(blockchain, &request, stream, &headers);
+        }
+        "GET" => match route(blockchain, &request) {
+            Ok(body) => (http::OK, body),
+            Err((status, message)) => (status, error_body(status, message.as_str())),
+        },
+        "OPTIONS" => {
+            headers.push(("Access-Control-Allow-Methods", ALLOWED_METHODS));
+            headers.push(("Access-Control-Allow-Headers", "Content-Type"));
+            (http::NO_CONTENT, Value::Null)
+        }
+        _ => {
+            headers.push(("Allow", ALLOWED_METHODS));
+            let status = http::METHOD_NOT_ALLOWED;
+            (status, error_body(status, "the REST API is read-only"))
+        }
+    };
+    http::write_response(&mut stream, status, &headers, &body)
+}
+
+/// `/events?addresses=<a>,<b>`: streams [Event]s from the chain and the mempool as
+/// server-sent events, only those touching `addresses` when given.
+fn stream_events(
+    blockchain: &Blockchain,
+    request: &Request,
+    mut stream: TcpStream,
+    headers: &[(&str, &str)],
+) -> Result<(), Box<dyn Error>> {
+    let addresses: Vec<String> = request
+        .query("addresses")
+        .map(|addresses| {
+            addresses
+                .split(',')
+                .filter(|address| !address.is_empty())
+                .map(String::from)
+                .collect()
+        })
+        .unwrap_or_default();
+    for address in &addresses {
+        if let Err((status, message)) = get_pub_key_hash(address) {
+            let body = error_body(status, message.as_str());
+            return http::write_response(&mut stream, status, headers, &body);
+        }
+    }
+    let (sender, receiver) = mpsc::sync_channel(SUBSCRIBER_CAPACITY);
+    blockchain.subscribe_with(sender.clone());
+    GLOBAL_MEMORY_POOL.subscribe_with(sender);
+    stream.set_write_timeout(Some(Duration::from_millis(EVENT_WRITE_TIMEOUT)))?;
+    let mut head = String::from("HTTP/1.1 200 OK\r\n");
+    for (name, value) in headers {
+        head.push_str(format!("{name}: {value}\r\n").as_str());
+    }
+    head.push_str(
+        "Content-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n",
+    );
+    stream.write_all(head.as_bytes())?;
+    stream.flush()?;
+    if let Err(e) = send_events(&mut stream, &receiver, addresses.as_slice()) {
+        info!("Event stream closed: {e}");
+    }
+    Ok(())
+}
+
+/// Writes [Event]s until the client goes away or falls too far behind, in which
+/// case the [`EventBus`](crate::events::EventBus) drops it and the channel closes.
+fn send_events(
+    stream: &mut TcpStream,
+    receiver: &Receiver<Event>,
+    addresses: &[String],
+) -> Result<(), Box<dyn Error>> {
+    loop {
+        match receiver.recv_timeout(Duration::from_millis(KEEP_ALIVE)) {
+            Ok(event) if event.touches(addresses) => {
+                write!(stream, "data: {}\n\n", serde_json::to_string(&event)?)?;
+            }
+            Ok(_) => continue,
+            Err(RecvTimeoutError::Timeout) => stream.write_all(b": keep-alive\n\n")?,
+            Err(RecvTimeoutError::Disconnected) => {
+                return Err("the subscriber fell behind".into());
+            }
+        }
+        stream.flush()?;
+    }
+}
+
+/// Mirrors the `{"error": {...}}` document the CLI prints in `--json` mode.
+fn error_body(status: Status, message: &str) -> Value {
+    json!({ "error": { "code": status.0, "message": message } })
+}
+
+fn route(blockchain: &Blockchain, request: &Request) -> Result<Value, RestError> {
+    let segments: Vec<&str> = request.path().trim_matches('/').split('/').collect();
+    match segments.as_slice() {
+        ["blocks"] => get_blocks(blockchain, request),
+        ["block", id] => get_block(blockchain, request, id),
+        ["tx", txid] => get_transaction(blockchain, txid),
+        ["address", address, "balance"] => get_balance(blockchain, address),
+        ["address", address, "utxos"] => get_utxos(blockchain, request, address),
+        ["address", address, "history"] => get_history(blockchain, request, address),
+        ["mempool"] => Ok(json!(GLOBAL_MEMORY_POOL.snapshot(blockchain))),
+        ["status"] => Ok(json!(node_status(blockchain))),
+        _ => Err(not_found(format!("no such endpoint `{}`", request.path()))),
+    }
+}
+
+fn bad_request(message: impl Into<String>) -> RestError {
+    (http::BAD_REQUEST, message.into())
+}
+
+fn not_found(message: impl Into<String>) -> RestError {
+    (http::NOT_FOUND, message.into())
+}
+
+/// Reads `offset` and `limit` from the query string, returned in that order.
+fn get_page(request: &Request) -> Result<(usize, usize), RestError> {
+    let offset = match request.query("offset") {
+        Some(offset) => offset
+            .parse()
+            .map_err(|_| bad_request("`offset` must be a number"))?,
+        None => 0,
+    };
+    let limit = match request.query("limit") {
+        Some(limit) => limit
+            .parse::<usize>()
+            .map_err(|_| bad_request("`limit` must be a number"))?
+            .min(MAX_LIMIT),
+        None => DEFAULT_LIMIT,
+    };
+    Ok((offset, limit))
+}
+
+fn get_pub_key_hash(address: &str) -> Result<Vec<u8>, RestError> {
+    address_pub_key_hash(address)
+        .ok_or_else(|| bad_request(format!("address `{address}` is not valid")))
+}
+
+/// `/blocks`: the [Block](crate::block::Block)s from the tip down, summarized the way
+/// `printchain --summary --json` does.
+fn get_blocks(blockchain: &Blockchain, request: &Request) -> Result<Value, RestError> {
+    let (offset, limit) = get_page(request)?;
+    let mut iterator = blockchain.iterator();
+    let blocks: Vec<BlockSummary> = std::iter::from_fn(|| iterator.next())
+        .skip(offset)
+        .take(limit)
+        .map(|block| BlockSummary::new(&block, false))
+        .collect();
+    Ok(json!(blocks))
+}
+
+/// `/block/<hash|height>`, with every input and output when `verbose=true`.
+fn get_block(blockchain: &Blockchain, request: &Request, id: &str) -> Result<Value, RestError> {
+    let verbose = request
+        .query("verbose")
+        .is_some_and(|verbose| verbose == "true");
+    let block = blockchain
+        .find_block(id)
+        .ok_or_else(|| not_found("block not found"))?;
+    Ok(json!(BlockSummary::new(&block, verbose)))
+}
+
+/// `/tx/<txid>`: looks in the mempool first, then the [Blockchain].
+fn get_transaction(blockchain: &Blockchain, txid_hex: &str) -> Result<Value, RestError> {
+    let txid: Txid = txid_hex
+        .parse()
+        .map_err(|_| bad_request("transaction id is not a valid hash"))?;
+    if let Some(tx) = GLOBAL_MEMORY_POOL.get(txid) {
+        return Ok(json!(TransactionDetail::new(&tx, None, blockchain)));
+    }
+    let block = blockchain
+        .get_transaction_block(txid)
+        .ok_or_else(|| not_found("transaction not found"))?;
+    let tx = block
+        .get_transactions()
+        .iter()
+        .find(|tx| tx.get_id() == txid)
+        .ok_or_else(|| not_found("transaction not found"))?;
+    Ok(json!(TransactionDetail::new(tx, Some(&block), blockchain)))
+}
+
+/// `/address/<address>/balance`, in the shape of `getbalance <address> --json`.
+fn get_balance(blockchain: &Blockchain, address: &str) -> Result<Value, RestError> {
+    let pub_key_hash = get_pub_key_hash(address)?;
+    let utxo_set = UTXOSet::new(blockchain.clone());
+    let confirmed: u64 = utxo_set
+        .find_utxo(pub_key_hash.as_slice())
+        .iter()
+        .map(TXOutput::get_value)
+        .sum();
+    let (pending_in, pending_out) = GLOBAL_MEMORY_POOL.snapshot(blockchain).get_pending(address);
+    Ok(json!({
+        "address": address,
+        "confirmed": confirmed,
+        "pending": pending_in - pending_out,
+        "pending_in": pending_in,
+        "pending_out": pending_out,
+    }))
+}
+
+fn get_utxos(
+    blockchain: &Blockchain,
+    request: &Request,
+    address: &str,
+) -> Result<Value, RestError> {
+    let pub_key_hash = get_pub_key_hash(address)?;
+    let (offset, limit) = get_page(request)?;
+    let utxo_set = UTXOSet::new(blockchain.clone());
+    let utxos: Vec<Value> = utxo_set
+        .find_unspent(pub_key_hash.as_slice())
+        .into_iter()
+        .skip(offset)
+        .take(limit)
+        .map(|(txid, vout, out)| {
+            json!({ "txid": txid, "vout": vout, "address": address, "value": out.get_value() })
+        })
+        .collect();
+    Ok(json!(utxos))
+}
+
+/// `/address/<address>/history`: confirmed [`AddressTransaction`]s, newest first.
+fn get_history(
+    blockchain: &Blockchain,
+    request: &Request,
+    address: &str,
+) -> Result<Value, RestError> {
+    let pub_key_hash = get_pub_key_hash(address)?;
+    let (offset, limit) = get_page(request)?;
+    let mut iterator = blockchain.iterator();
+    let history: Vec<AddressTransaction> = std::iter::from_fn(|| iterator.next())
+        .flat_map(|block| {
+            block
+                .get_transactions()
+                .iter()
+                .filter_map(|tx| {
+                    AddressTransaction::new(tx, &block, pub_key_hash.as_slice(), blockchain)
+                })
+                .collect::<Vec<_>>()
+        })
+        .skip(offset)
+        .take(limit)
+        .collect();
+    Ok(json!(history))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{mine, pay, temp_chain};
+    use crate::wallet::Wallet;
+    use std::io::Read;
+
+    fn get(blockchain: &Blockchain, target: &str) -> Result<Value, RestError> {
+        let request = Request {
+            method: String::from("GET"),
+            target: String::from(target),
+            authorization: None,
+            body: vec![],
+        };
+        route(blockchain, &request)
+    }
+
+    /// Sends `request` to [`handle_connection`] over loopback, returning the response.
+    fn respond(blockchain: &Blockchain, request: &[u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        client.write_all(request).unwrap();
+        handle_connection(blockchain, stream).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    /// A chain of three blocks, the last one paying 3 from `miner` to `payee`.
+    fn small_chain(miner: &Wallet, payee: &Wallet) -> (Blockchain, Txid) {
+        let blockchain = temp_chain(miner);
+        mine(&blockchain, vec![], miner);
+        let tx = pay(&blockchain, miner, payee, 3);
+        mine(&blockchain, vec![tx.clone()], miner);
+        (blockchain, tx.get_id())
+    }
+
+    #[test]
+    fn blocks_are_listed_from_the_tip_a_page_at_a_time() {
+        let (miner, payee) = (Wallet::new(), Wallet::new());
+        let (blockchain, _) = small_chain(&miner, &payee);
+        let blocks = get(&blockchain, "/blocks").unwrap();
+        let heights: Vec<&Value> = blocks
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|b| &b["height"])
+            .collect();
+        assert_eq!(heights, [&json!(2), &json!(1), &json!(0)]);
+        let page = get(&blockchain, "/blocks?offset=1&limit=1").unwrap();
+        assert_eq!(page[0]["height"], 1);
+        assert_eq!(page.as_array().unwrap().len(), 1);
+        assert_eq!(
+            get(&blockchain, "/blocks?limit=x").unwrap_err().0,
+            http::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn block_is_found_by_hash_or_height() {
+        let (miner, payee) = (Wallet::new(), Wallet::new());
+        let (blockchain, _) = small_chain(&miner, &payee);
+        let tip = blockchain.get_tip_hash().to_string();
+        let by_height = get(&blockchain, "/block/2").unwrap();
+        let by_hash = get(&blockchain, format!("/block/{tip}").as_str()).unwrap();
+        assert_eq!(by_height, by_hash);
+        assert_eq!(by_hash["transaction_count"], 2);
+        let verbose = get(&blockchain, "/block/2?verbose=true").unwrap();
+        assert_ne!(verbose, by_hash);
+        assert_eq!(get(&blockchain, "/block/9").unwrap_err().0, http::NOT_FOUND);
+    }
+
+    #[test]
+    fn confirmed_transaction_is_found_by_txid() {
+        let (miner, payee) = (Wallet::new(), Wallet::new());
+        let (blockchain, txid) = small_chain(&miner, &payee);
+        let tx = get(&blockchain, format!("/tx/{txid}").as_str()).unwrap();
+        assert_eq!(tx["txid"], txid.to_string());
+        assert_eq!(tx["block_height"], 2);
+        let unknown = Txid::from([7; 32]);
+        let missing = get(&blockchain, format!("/tx/{unknown}").as_str());
+        assert_eq!(missing.unwrap_err().0, http::NOT_FOUND);
+        assert_eq!(
+            get(&blockchain, "/tx/nope").unwrap_err().0,
+            http::BAD_REQUEST
+        );
+    }
+
+    #[test]
+    fn address_endpoints_report_balance_utxos_and_history() {
+        let (miner, payee) = (Wallet::new(), Wallet::new());
+        let (blockchain, txid) = small_chain(&miner, &payee);
+        let address = payee.get_address();
+
+        let balance = get(&blockchain, format!("/address/{address}/balance").as_str()).unwrap();
+        assert_eq!(balance["confirmed"], 3);
+        assert_eq!(balance["pending"], 0);
+        let utxos = get(&blockchain, format!("/address/{address}/utxos").as_str()).unwrap();
+        assert_eq!(
+            utxos,
+            json!([{ "txid": txid, "vout": 0, "address": address, "value": 3 }])
+        );
+        let history = get(&blockchain, format!("/address/{address}/history").as_str()).unwrap();
+        assert_eq!(history[0]["txid"], txid.to_string());
+        assert_eq!(history[0]["received"], 3);
+        let miner_history = format!("/address/{}/history?limit=2", miner.get_address());
+        assert_eq!(
+            get(&blockchain, miner_history.as_str())
+                .unwrap()
+                .as_array()
+                .unwrap()
+                .len(),
+            2
+        );
+
+        let invalid = get(&blockchain, "/address/nope/balance").unwrap_err();
+        assert_eq!(
+            invalid,
+            (
+                http::BAD_REQUEST,
+                String::from("address `nope` is not valid")
+            )
+        );
+    }
+
+    #[test]
+    fn mempool_and_status_describe_the_node() {
+        let (miner, payee) = (Wallet::new(), Wallet::new());
+        let (blockchain, _) = small_chain(&miner, &payee);
+        assert!(get(&blockchain, "/mempool").unwrap().is_object());
+        let status = get(&blockchain, "/status").unwrap();
+        assert_eq!(status["chain"]["height"], 2);
+        assert_eq!(
+            status["chain"]["tip_hash"],
+            blockchain.get_tip_hash().to_string()
+        );
+        assert_eq!(get(&blockchain, "/nope").unwrap_err().0, http::NOT_FOUND);
+    }
+
+    #[test]
+    fn only_reads_are_served_over_http() {
+        let blockchain = temp_chain(&Wallet::new());
+        let ok = respond(&blockchain, b"GET /status HTTP/1.1\r\n\r\n");
+        assert!(ok.starts_with("HTTP/1.1 200 OK\r\n"), "{ok}");
+        let (_, body) = ok.split_once("\r\n\r\n").unwrap();
+        let status: Value = serde_json::from_str(body).unwrap();
+        assert_eq!(status["chain"]["height"], 0);
+
+        let preflight = respond(&blockchain, b"OPTIONS /status HTTP/1.1\r\n\r\n");
+        assert!(preflight.starts_with("HTTP/1.1 204 No Content\r\n"));
+        assert!(preflight.contains("Access-Control-Allow-Methods: GET, OPTIONS\r\n"));
+        assert!(preflight.ends_with("Content-Length: 0\r\nConnection: close\r\n\r\n"));
+
+        let post = respond(
+            &blockchain,
+            b"POST /status HTTP/1.1\r\nContent-Length: 0\r\n\r\n",
+        );
+        assert!(post.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"));
+        assert!(post.contains("Allow: GET, OPTIONS\r\n"));
+        assert!(post.contains("the REST API is read-only"));
+    }
+}
@@ -0,0 +1,634 @@
+use std::fmt::{self, Display, Formatter};
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+use std::{error::Error, process, thread, time::Duration};
+
+use data_encoding::HEXLOWER;
+use log::{error, info};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::server::{is_shutting_down, relay_tx, save_ban_list, save_mempool, sync_status};
+use crate::server::{GLOBAL_BANS, GLOBAL_MEMORY_POOL, GLOBAL_NODES, GLOBAL_WALLETS};
+use crate::summary::{BlockSummary, TransactionDetail};
+use crate::{block::Block, blockchain::Blockchain};
+use crate::{config::GLOBAL_CONFIG, constant_time_eq, http};
+use crate::{
+    memory_pool::pending_balance,
+    transactions::{TXOutput, Transaction},
+    types::Txid,
+};
+use crate::{utxo_set::UTXOSet, wallet::address_pub_key_hash};
+
+/// How long `stop` waits for its response to be written before exiting.
+const STOP_DELAY: u64 = 100;
+
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+/// Requests that are well formed but can't be carried out, e.g. not enough funds.
+pub const SERVER_ERROR: i64 = -32000;
+/// The requested [Block] or [Transaction] doesn't exist.
+pub const NOT_FOUND: i64 = -32001;
+/// The mempool refused the [Transaction], the message says why.
+pub const REJECTED: i64 = -32002;
+
+/// A JSON-RPC error object, returned by the server and surfaced by [`call`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    fn invalid_params(message: impl Into<String>) -> Self {
+        Self::new(INVALID_PARAMS, message)
+    }
+
+    fn server(message: impl Into<String>) -> Self {
+        Self::new(SERVER_ERROR, message)
+    }
+
+    fn not_found(message: impl Into<String>) -> Self {
+        Self::new(NOT_FOUND, message)
+    }
+}
+
+impl Display for RpcError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (rpc error {})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: Option<String>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+    #[serde(default)]
+    id: Value,
+}
+
+/// Serves JSON-RPC 2.0 over HTTP on `listener`, one thread per connection, until
+/// the node shuts down.
+pub fn serve(listener: &TcpListener, blockchain: &Blockchain) {
+    for stream in listener.incoming() {
+        if is_shutting_down() {
+            break;
+        }
+        let Ok(stream) = stream else {
+            continue;
+        };
+        let blockchain = blockchain.clone();
+        thread::spawn(move || {
+            if let Err(e) = handle_connection(&blockchain, stream) {
+                error!("JSON-RPC connection failed: {e}");
+            }
+        });
+    }
+    info!("Stopped serving JSON-RPC");
+}
+
+fn handle_connection(blockchain: &Blockchain, mut stream: TcpStream) -> Result<(), Box<dyn Error>> {
+    let request = match http::read_request(&stream) {
+        Ok(request) => request,
+        Err(status) => return http::write_response(&mut stream, status, &[], &json!({})),
+    };
+    if request.method != "POST" {
+        let headers = [("Allow", "POST")];
+        return http::write_response(&mut stream, http::METHOD_NOT_ALLOWED, &headers, &json!({}));
+    }
+    let token = GLOBAL_CONFIG.get_rpc_auth();
+    let authorized = token.is_some_and(|token| {
+        request
+            .authorization
+            .as_deref()
+            .is_some_and(|authorization| {
+                constant_time_eq(
+                    authorization.as_bytes(),
+                    format!("Bearer {token}").as_bytes(),
+                )
+            })
+    });
+    if !authorized {
+        return http::write_response(&mut stream, http::UNAUTHORIZED, &[], &json!({}));
+    }
+    let (response, stop) = match serde_json::from_slice::<Value>(request.body.as_slice()) {
+        Ok(value) => respond(blockchain, value),
+        Err(e) => (
+            error_response(&Value::Null, &RpcError::new(PARSE_ERROR, e.to_string())),
+            false,
+        ),
+    };
+    http::write_response(&mut stream, http::OK, &[], &response)?;
+    if stop {
+        stop_node(blockchain);
+    }
+    Ok(())
+}
+
+/// Dispatches a parsed request, returning the response and whether the node should stop.
+fn respond(blockchain: &Blockchain, value: Value) -> (Value, bool) {
+    let request: Request = match serde_json::from_value(value) {
+        Ok(request) => request,
+        Err(e) => {
+            let error = RpcError::new(INVALID_REQUEST, e.to_string());
+            return (error_response(&Value::Null, &error), false);
+        }
+    };
+    if request.jsonrpc.as_deref() != Some("2.0") {
+        let error = RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\"");
+        return (error_response(&request.id, &error), false);
+    }
+    info!("JSON-RPC request {}", request.method);
+    let params = Params(request.params);
+    let result = match request.method.as_str() {
+        "getblockchaininfo" => Ok(get_blockchain_info(blockchain)),
+        "getbestblockhash" => Ok(json!(blockchain.get_tip_hash().to_string())),
+        "getblock" => get_block(blockchain, &params),
+        "gettransaction" => get_transaction(blockchain, &params),
+        "getbalance" => get_balance(blockchain, &params),
+        "listunspent" => list_unspent(blockchain, &params),
+        "sendtoaddress" => send_to_address(blockchain, &params),
+        "sendrawtransaction" => send_raw_transaction(blockchain, &params),
+        "getmempoolinfo" => Ok(json!(GLOBAL_MEMORY_POOL.get_stats(blockchain))),
+        "getpeerinfo" => Ok(get_peer_info()),
+        "setban" => set_ban(&params),
+        "listbanned" => Ok(json!(GLOBAL_BANS.get_bans())),
+        "stop" => {
+            return (
+                json!({ "jsonrpc": "2.0", "result": "stopping", "id": request.id }),
+                true,
+            )
+        }
+        method => Err(RpcError::new(
+            METHOD_NOT_FOUND,
+            format!("method `{method}` not found"),
+        )),
+    };
+    let response = match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "result": result, "id": request.id }),
+        Err(error) => error_response(&request.id, &error),
+    };
+    (response, false)
+}
+
+fn error_response(id: &Value, error: &RpcError) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "error": { "code": error.code, "message": error.message },
+        "id": id,
+    })
+}
+
+/// Request parameters given either by position or by name.
+struct Params(Value);
+
+impl Params {
+    fn get(&self, idx: usize, name: &str) -> Option<&Value> {
+        match &self.0 {
+            Value::Array(values) => values.get(idx),
+            Value::Object(values) => values.get(name),
+            _ => None,
+        }
+        .filter(|value| !value.is_null())
+    }
+
+    fn str(&self, idx: usize, name: &str) -> Result<&str, RpcError> {
+        self.get(idx, name)
+            .and_then(Value::as_str)
+            .ok_or_else(|| RpcError::invalid_params(format!("`{name}` must be a string")))
+    }
+
+    fn u64(&self, idx: usize, name: &str) -> Result<Option<u64>, RpcError> {
+        self.get(idx, name)
+            .map(|value| {
+                value
+                    .as_u64()
+                    .ok_or_else(|| RpcError::invalid_params(format!("`{name}` must be an amount")))
+            })
+            .transpose()
+    }
+
+    fn bool(&self, idx: usize, name: &str) -> Result<bool, RpcError> {
+        self.get(idx, name).map_or(Ok(false), |value| {
+            value
+                .as_bool()
+                .ok_or_else(|| RpcError::invalid_params(format!("`{name}` must be a boolean")))
+        })
+    }
+
+    fn address(&self, idx: usize, name: &str) -> Result<(String, Vec<u8>), RpcError> {
+        let address = self.str(idx, name)?;
+        let pub_key_hash = address_pub_key_hash(address)
+            .ok_or_else(|| RpcError::invalid_params(format!("address `{address}` is not valid")))?;
+        Ok((String::from(address), pub_key_hash))
+    }
+}
+
+fn get_blockchain_info(blockchain: &Blockchain) -> Value {
+    json!({
+        "network": GLOBAL_CONFIG.get_network().to_string(),
+        "chain": blockchain.get_stats(),
+        "sync": sync_status(),
+    })
+}
+
+/// `getblock <hash|height> [verbosity]`: the serialized [Block] in hex at verbosity
+/// 0, a summary at 1 and a summary with every input and output at 2.
+fn get_block(blockchain: &Blockchain, params: &Params) -> Result<Value, RpcError> {
+    let id = params
+        .get(0, "block")
+        .ok_or_else(|| RpcError::invalid_params("`block` must be a block hash or height"))?;
+    let block = match id {
+        Value::String(hash) => hash
+            .parse()
+            .ok()
+            .and_then(|hash| blockchain.get_block(hash)),
+        Value::Number(height) => height
+            .as_u64()
+            .and_then(|height| usize::try_from(height).ok())
+            .and_then(|height| blockchain.get_block_by_height(height)),
+        _ => {
+            return Err(RpcError::invalid_params(
+                "`block` must be a block hash or height",
+            ))
+        }
+    }
+    .ok_or_else(|| RpcError::not_found("block not found"))?;
+    let verbosity = params
+        .get(1, "verbosity")
+        .map_or(Some(1), Value::as_u64)
+        .ok_or_else(|| RpcError::invalid_params("`verbosity` must be 0, 1 or 2"))?;
+    Ok(match verbosity {
+        0 => json!(HEXLOWER.encode(block.serialize().as_slice())),
+        1 => json!(BlockSummary::new(&block, false)),
+        2 => json!(BlockSummary::new(&block, true)),
+        _ => return Err(RpcError::invalid_params("`verbosity` must be 0, 1 or 2")),
+    })
+}
+
+/// `gettransaction <txid> [raw]`: looks in the mempool first, then the [Blockchain].
+fn get_transaction(blockchain: &Blockchain, params: &Params) -> Result<Value, RpcError> {
+    let txid_hex = params.str(0, "txid")?;
+    let raw = params.bool(1, "raw")?;
+    let (tx, block) = find_transaction(blockchain, txid_hex)?;
+    if raw {
+        return Ok(json!(HEXLOWER.encode(tx.serialize().as_slice())));
+    }
+    Ok(json!(TransactionDetail::new(
+        &tx,
+        block.as_ref(),
+        blockchain
+    )))
+}
+
+/// Looks `txid_hex` up in the mempool, then the [Blockchain], returning the [Block]
+/// holding it once it is confirmed.
+pub(crate) fn find_transaction(
+    blockchain: &Blockchain,
+    txid_hex: &str,
+) -> Result<(Transaction, Option<Block>), RpcError> {
+    let txid: Txid = txid_hex
+        .parse()
+        .map_err(|_| RpcError::invalid_params("`txid` must be a 32 byte hex hash"))?;
+    if let Some(tx) = GLOBAL_MEMORY_POOL.get(txid) {
+        return Ok((tx, None));
+    }
+    let block = blockchain
+        .get_transaction_block(txid)
+        .ok_or_else(|| RpcError::not_found("transaction not found"))?;
+    let tx = block
+        .get_transactions()
+        .iter()
+        .find(|tx| tx.get_id() == txid)
+        .cloned()
+        .ok_or_else(|| RpcError::not_found("transaction not found"))?;
+    Ok((tx, Some(block)))
+}
+
+fn get_balance(blockchain: &Blockchain, params: &Params) -> Result<Value, RpcError> {
+    let (address, pub_key_hash) = params.address(0, "address")?;
+    let utxo_set = UTXOSet::new(blockchain.clone());
+    let confirmed: u64 = utxo_set
+        .find_utxo(pub_key_hash.as_slice())
+        .iter()
+        .map(TXOutput::get_value)
+        .sum();
+    let (pending_in, pending_out) = GLOBAL_MEMORY_POOL.get_pending(address.as_str(), blockchain);
+    Ok(json!({
+        "address": address,
+        "confirmed": confirmed,
+        "pending_in": pending_in,
+        "pending_out": pending_out,
+        "pending_balance": pending_balance(confirmed, pending_in, pending_out),
+    }))
+}
+
+fn list_unspent(blockchain: &Blockchain, params: &Params) -> Result<Value, RpcError> {
+    let (address, pub_key_hash) = params.address(0, "address")?;
+    let utxo_set = UTXOSet::new(blockchain.clone());
+    let unspent: Vec<Value> = utxo_set
+        .find_unspent(pub_key_hash.as_slice())
+        .into_iter()
+        .map(|(txid, vout, out)| {
+            json!({ "txid": txid, "vout": vout, "address": address, "value": out.get_value() })
+        })
+        .collect();
+    Ok(json!(unspent))
+}
+
+/// `sendtoaddress <from> <to> <amount> [fee]`: pays from a wallet held by the node.
+fn send_to_address(blockchain: &Blockchain, params: &Params) -> Result<Value, RpcError> {
+    let from = params.str(0, "from")?;
+    let to = params.str(1, "to")?;
+    let amount = params
+        .u64(2, "amount")?
+        .ok_or_else(|| RpcError::invalid_params("`amount` must be positive"))?;
+    let fee = params.u64(3, "fee")?.unwrap_or(0);
+    send_to(blockchain, from, to, amount, fee).map(|txid| json!(txid))
+}
+
+/// Pays `amount` from the wallet of `from` to `to` and returns the id of the
+/// [Transaction].
+pub(crate) fn send_to(
+    blockchain: &Blockchain,
+    from: &str,
+    to: &str,
+    amount: u64,
+    fee: u64,
+) -> Result<String, RpcError> {
+    for address in [from, to] {
+        if address_pub_key_hash(address).is_none() {
+            return Err(RpcError::invalid_params(format!(
+                "address `{address}` is not valid"
+            )));
+        }
+    }
+    if amount == 0 {
+        return Err(RpcError::invalid_params("`amount` must be positive"));
+    }
+    let utxo_set = UTXOSet::new(blockchain.clone());
+    let tx = Transaction::new_utxo_transaction(from, to, amount, fee, &utxo_set, &GLOBAL_WALLETS)
+        .map_err(|e| RpcError::server(e.to_string()))?;
+    submit(blockchain, &tx)
+}
+
+/// `sendrawtransaction <hex>`: relays a [Transaction] signed elsewhere.
+fn send_raw_transaction(blockchain: &Blockchain, params: &Params) -> Result<Value, RpcError> {
+    let tx = Transaction::from_hex(params.str(0, "hex")?)
+        .map_err(|e| RpcError::invalid_params(e.to_string()))?;
+    send_raw(blockchain, &tx).map(|txid| json!(txid))
+}
+
+/// Admits a [Transaction] signed elsewhere to the mempool and relays it, or returns
+/// why it was refused with [`REJECTED`].
+pub(crate) fn send_raw(blockchain: &Blockchain, tx: &Transaction) -> Result<String, RpcError> {
+    submit(blockchain, tx)
+}
+
+/// Admits `tx` to the mempool as if a peer had sent it, announcing it to every known
+/// peer, and returns its id, or why it was refused with [`REJECTED`].
+pub(crate) fn submit(blockchain: &Blockchain, tx: &Transaction) -> Result<String, RpcError> {
+    let txid = tx.get_id().to_string();
+    GLOBAL_MEMORY_POOL
+        .try_add(tx.clone(), blockchain)
+        .map_err(|e| RpcError::new(REJECTED, e.to_string()))?;
+    let node_addr = GLOBAL_CONFIG.get_node_addr();
+    relay_tx(blockchain, tx, node_addr.as_str()).map_err(|e| RpcError::server(e.to_string()))?;
+    Ok(txid)
+}
+
+fn get_peer_info() -> Value {
+    let peers: Vec<Value> = GLOBAL_NODES
+        .get_nodes()
+        .iter()
+        .map(|node| {
+            json!({
+                "address": node.get_addr(),
+                "last_seen": node.get_last_seen(),
+                "last_attempt": node.get_last_attempt(),
+                "ban_score": node
+                    .get_addr()
+                    .parse::<SocketAddr>()
+                    .map_or(0, |addr| GLOBAL_BANS.get_score(addr.ip())),
+            })
+        })
+        .collect();
+    json!(peers)
+}
+
+/// `setban <address> [duration]`: bans an IP address, or that of an `ip:port`
+/// address, for `duration` seconds, the configured ban duration by default. A
+/// duration of 0 lifts the ban.
+fn set_ban(params: &Params) -> Result<Value, RpcError> {
+    let address = params.str(0, "address")?;
+    let ip = address
+        .parse::<IpAddr>()
+        .or_else(|_| address.parse::<SocketAddr>().map(|addr| addr.ip()))
+        .map_err(|_| RpcError::invalid_params(format!("address `{address}` is not valid")))?;
+    let duration = params
+        .u64(1, "duration")?
+        .map_or_else(|| GLOBAL_CONFIG.get_ban_duration(), Duration::from_secs);
+    if duration.is_zero() {
+        let unbanned = GLOBAL_BANS.unban(ip);
+        save_ban_list();
+        return Ok(json!({ "address": ip, "unbanned": unbanned }));
+    }
+    GLOBAL_BANS.ban(ip, duration, "banned over JSON-RPC");
+    save_ban_list();
+    Ok(json!(GLOBAL_BANS
+        .get_bans()
+        .into_iter()
+        .find(|ban| ban.address == ip)))
+}
+
+/// Saves the mempool, flushes the database and exits once the `stop` response has
+/// been sent.
+fn stop_node(blockchain: &Blockchain) {
+    info!("Stopping on JSON-RPC request");
+    thread::sleep(Duration::from_millis(STOP_DELAY));
+    save_mempool(blockchain);
+    if let Err(e) = blockchain.get_db().flush() {
+        error!("Unable to flush the database: {e}");
+    }
+    let _ = std::fs::remove_file(GLOBAL_CONFIG.get_node_status_path());
+    process::exit(0);
+}
+
+/// Calls `method` on the JSON-RPC server at `addr`, authenticating with `token`.
+pub fn call(
+    addr: &str,
+    token: &str,
+    method: &str,
+    params: &Value,
+) -> Result<Value, Box<dyn Error>> {
+    let body = serde_json::to_vec(&json!({
+        "jsonrpc": "2.0",
+        "method": method,
+        "params": params,
+        "id": 1,
+    }))?;
+    let mut stream = TcpStream::connect(addr)?;
+    stream.set_read_timeout(Some(Duration::from_millis(http::READ_TIMEOUT)))?;
+    write!(
+        stream,
+        "POST / HTTP/1.1\r\nHost: {addr}\r\nAuthorization: Bearer {token}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    )?;
+    stream.write_all(body.as_slice())?;
+    stream.flush()?;
+    let mut response = vec![];
+    stream.read_to_end(&mut response)?;
+    let response = String::from_utf8_lossy(response.as_slice());
+    let (head, body) = response
+        .split_once("\r\n\r\n")
+        .ok_or("malformed JSON-RPC response")?;
+    let status = head.split_whitespace().nth(1).unwrap_or_default();
+    if status != "200" {
+        let reason = head.lines().next().unwrap_or_default();
+        return Err(format!("JSON-RPC request refused: {reason}").into());
+    }
+    let mut response: Value = serde_json::from_str(body)?;
+    if let Some(error) = response.get("error") {
+        let error: RpcError = serde_json::from_value(error.clone())?;
+        return Err(error.into());
+    }
+    Ok(response["result"].take())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::{mine, temp_chain};
+    use crate::wallet::Wallet;
+
+    fn call(blockchain: &Blockchain, method: &str, params: &Value) -> Value {
+        let request = json!({ "jsonrpc": "2.0", "method": method, "params": params, "id": 7 });
+        let (response, stop) = respond(blockchain, request);
+        assert!(!stop);
+        assert_eq!(response["id"], 7);
+        response
+    }
+
+    fn error_code(response: &Value) -> Option<i64> {
+        response["error"]["code"].as_i64()
+    }
+
+    /// Sends `request` to [`handle_connection`] over loopback, returning the status line.
+    fn status_line(blockchain: &Blockchain, request: &[u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        client.write_all(request).unwrap();
+        handle_connection(blockchain, stream).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response.lines().next().unwrap_or_default().to_string()
+    }
+
+    #[test]
+    fn block_is_found_by_position_or_name() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let block = mine(&blockchain, vec![], &miner);
+        let hash = block.get_hash().to_string();
+
+        let by_height = call(&blockchain, "getblock", &json!([1]));
+        let by_name = call(&blockchain, "getblock", &json!({ "block": hash }));
+        assert_eq!(by_height["result"], by_name["result"]);
+        assert_eq!(by_height["result"]["hash"], hash);
+        let raw = call(&blockchain, "getblock", &json!([hash, 0]));
+        assert_eq!(raw["result"], HEXLOWER.encode(block.serialize().as_slice()));
+
+        assert_eq!(
+            error_code(&call(&blockchain, "getblock", &json!([9]))),
+            Some(NOT_FOUND)
+        );
+        let bad_verbosity = call(&blockchain, "getblock", &json!([1, 3]));
+        assert_eq!(error_code(&bad_verbosity), Some(INVALID_PARAMS));
+    }
+
+    #[test]
+    fn balance_and_unspent_outputs_of_an_address() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let address = miner.get_address();
+        let balance = call(&blockchain, "getbalance", &json!([address]));
+        assert_eq!(balance["result"]["confirmed"], 10);
+        let unspent = call(&blockchain, "listunspent", &json!({ "address": address }));
+        assert_eq!(unspent["result"][0]["value"], 10);
+        let invalid = call(&blockchain, "getbalance", &json!(["nope"]));
+        assert_eq!(error_code(&invalid), Some(INVALID_PARAMS));
+    }
+
+    #[test]
+    fn errors_map_to_json_rpc_error_objects() {
+        let blockchain = temp_chain(&Wallet::new());
+        let unknown = call(&blockchain, "nosuchmethod", &json!([]));
+        assert_eq!(error_code(&unknown), Some(METHOD_NOT_FOUND));
+        let missing = call(
+            &blockchain,
+            "gettransaction",
+            &json!([Txid::from([7; 32]).to_string()]),
+        );
+        assert_eq!(error_code(&missing), Some(NOT_FOUND));
+        let malformed = call(&blockchain, "gettransaction", &json!(["nope"]));
+        assert_eq!(error_code(&malformed), Some(INVALID_PARAMS));
+
+        let (response, _) = respond(
+            &blockchain,
+            json!({ "method": "getblockchaininfo", "id": 1 }),
+        );
+        assert_eq!(error_code(&response), Some(INVALID_REQUEST));
+        let (response, _) = respond(&blockchain, json!([1, 2]));
+        assert_eq!(error_code(&response), Some(INVALID_REQUEST));
+    }
+
+    #[test]
+    fn stop_is_answered_before_stopping() {
+        let blockchain = temp_chain(&Wallet::new());
+        let request = json!({ "jsonrpc": "2.0", "method": "stop", "id": 1 });
+        let (response, stop) = respond(&blockchain, request);
+        assert!(stop);
+        assert_eq!(response["result"], "stopping");
+    }
+
+    #[test]
+    fn only_small_authenticated_posts_are_served() {
+        let blockchain = temp_chain(&Wallet::new());
+        let get = b"GET / HTTP/1.1\r\n\r\n";
+        assert_eq!(
+            status_line(&blockchain, get),
+            "HTTP/1.1 405 Method Not Allowed"
+        );
+        let huge = format!(
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            2 * 1024 * 1024
+        );
+        assert_eq!(
+            status_line(&blockchain, huge.as_bytes()),
+            "HTTP/1.1 413 Payload Too Large"
+        );
+        let body = r#"{"jsonrpc":"2.0","method":"getblockchaininfo","id":1}"#;
+        let anonymous = format!(
+            "POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        assert_eq!(
+            status_line(&blockchain, anonymous.as_bytes()),
+            "HTTP/1.1 401 Unauthorized"
+        );
+    }
+}
@@ -1,29 +1,166 @@
-use std::io::{BufReader, Write};
-use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
-use std::{error::Error, thread, time::Duration};
+use std::fmt::{self, Display, Formatter};
+use std::io::{BufReader, ErrorKind};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::{error::Error, fs, path::Path, process, sync::LazyLock, thread, time::Duration};
 
-use data_encoding::HEXLOWER;
-use log::{error, info};
-use once_cell::sync::Lazy;
+use log::{error, info, trace, warn};
 use serde::{Deserialize, Serialize};
-use serde_json::Deserializer;
 
-use crate::memory_pool::{BlockInTransit, MemoryPool};
+use crate::block::{Block, BlockHeader};
+use crate::blockchain::{BlockError, Blockchain, ChainStats};
+use crate::bloom::{BloomFilter, PeerFilters};
+use crate::connection_pool::ConnectionPool;
+use crate::memory_pool::MEMPOOL_TREE;
+use crate::memory_pool::{AdmissionError, BlockInTransit, MemoryPool, MempoolStats, SeenTxids};
+use crate::merkle::MerkleProof;
+use crate::node::{is_valid_addr, resolve_addr, BanList, Nodes};
+use crate::proof_of_work::CancelHandle;
+use crate::thread_pool::ThreadPool;
 use crate::transactions::Transaction;
-use crate::utxo_set::UTXOSet;
-use crate::{block::Block, blockchain::Blockchain, config::GLOBAL_CONFIG, node::Nodes};
+use crate::types::{BlockHash, Txid};
+use crate::wire::{read_package, write_package, FrameError};
+use crate::{config::GLOBAL_CONFIG, current_timestamp};
+use crate::{notify, random_u64, rest, rpc, wallets::WalletStore};
 
-const NODE_VERSION: usize = 1;
-pub const CENTRAL_NODE: &str = "127.0.0.1:2001";
-pub const TRANSACTION_THRESHOLD: usize = 2;
-static GLOBAL_NODES: Lazy<Nodes> = Lazy::new(|| {
+const NODE_VERSION: usize = 5;
+/// Sent in every [`Package::Version`] to recognize connections to this very process.
+static NODE_NONCE: LazyLock<u64> = LazyLock::new(random_u64);
+pub(crate) static GLOBAL_NODES: LazyLock<Nodes> = LazyLock::new(|| {
     let nodes = Nodes::new();
-    nodes.add_node(String::from(CENTRAL_NODE));
+    for peer in bootstrap_peers() {
+        nodes.add_node(peer);
+    }
     nodes
 });
-static GLOBAL_MEMORY_POOL: Lazy<MemoryPool> = Lazy::new(MemoryPool::new);
-static GLOBAL_BLOCKS_IN_TRANSIT: Lazy<BlockInTransit> = Lazy::new(BlockInTransit::new);
+pub(crate) static GLOBAL_MEMORY_POOL: LazyLock<MemoryPool> = LazyLock::new(|| {
+    MemoryPool::new(GLOBAL_CONFIG.get_network().params())
+        .with_limits(GLOBAL_CONFIG.get_mempool_limits())
+        .with_threshold(GLOBAL_CONFIG.get_transaction_threshold())
+});
+/// The wallets of the node, shared by every RPC call that spends from them.
+pub(crate) static GLOBAL_WALLETS: LazyLock<WalletStore> = LazyLock::new(WalletStore::new);
+static GLOBAL_BLOCKS_IN_TRANSIT: BlockInTransit = BlockInTransit::new();
+/// The [Transaction]s already relayed, which aren't fetched or relayed again.
+static GLOBAL_SEEN_TXIDS: LazyLock<SeenTxids> = LazyLock::new(SeenTxids::new);
+pub(crate) static GLOBAL_BANS: LazyLock<BanList> = LazyLock::new(BanList::new);
+/// Added to the ban score of a peer sending a package that doesn't deserialize.
+const MALFORMED_PACKAGE_PENALTY: u32 = 50;
+/// Added to the ban score of a peer sending a [Block] that fails validation.
+const INVALID_BLOCK_PENALTY: u32 = 100;
+/// Added to the ban score of a peer sending a [Transaction] no node would accept.
+const INVALID_TX_PENALTY: u32 = 10;
+/// Added to the ban score of a peer sending a package it shouldn't, such as an
+/// invalid filter or more addresses than asked for.
+const PROTOCOL_VIOLATION_PENALTY: u32 = 20;
+/// How many headers are sent in reply to a [`Package::GetHeaders`].
+pub const MAX_HEADERS: usize = 2000;
+/// How many hashes a [`Package::GetHeaders`] may list.
+const MAX_LOCATOR: usize = 64;
+/// How many [Block]s may be requested from peers at a time.
+const MAX_BLOCKS_IN_FLIGHT: usize = 16;
+/// How often the [Block]s in transit are checked for requests that timed out.
+const BLOCK_REQUEST_POLL: u64 = 1000;
+/// How many peer addresses are sent in reply to a [`Package::GetAddr`], and taken from
+/// a [`Package::Addr`].
+const ADDR_SAMPLE_SIZE: usize = 16;
+/// How many of the known peers seen most recently are contacted on startup, on top of
+/// the bootstrap peers, so that a node can sync while the central node is down.
+const BOOTSTRAP_KNOWN_PEERS: usize = 8;
+/// How often a miner checks whether the mining interval passed without a [Block].
+const MINING_TIMER_POLL: u64 = 1000;
+/// How often the mempool is swept for [Transaction]s pending for too long.
+const MEMPOOL_EXPIRY_POLL: u64 = 60_000;
+/// How many [Transaction]s are relayed between saves of the mempool to the database,
+/// which also happen when the node shuts down.
+const MEMPOOL_SAVE_INTERVAL: usize = 10;
+static RELAYED_SINCE_SAVE: AtomicUsize = AtomicUsize::new(0);
+static GLOBAL_PEER_FILTERS: LazyLock<PeerFilters> = LazyLock::new(PeerFilters::new);
+static GLOBAL_MINER: Miner = Miner::new();
+/// Milliseconds since the Unix epoch when this process started serving.
+static STARTED_AT: LazyLock<i64> = LazyLock::new(current_timestamp);
 const TCP_WRITE_TIMEOUT: u64 = 1000;
+/// How long a peer may stay silent before its connection is closed, so that it can't
+/// hold on to a worker serving connections.
+const TCP_READ_TIMEOUT: u64 = 10_000;
+/// How long a connection to a peer is kept open without a package to send, shorter
+/// than [`TCP_READ_TIMEOUT`] so that peers don't close it first.
+const CONNECTION_IDLE_TIMEOUT: u64 = 5000;
+/// How often connections to peers are checked for having been idle too long.
+const CONNECTION_IDLE_POLL: u64 = 1000;
+static GLOBAL_CONNECTIONS: LazyLock<ConnectionPool> = LazyLock::new(|| {
+    ConnectionPool::new(
+        Duration::from_millis(CONNECTION_IDLE_TIMEOUT),
+        Duration::from_millis(TCP_WRITE_TIMEOUT),
+    )
+});
+/// Set once the node has caught up with a peer, see [`Config::get_wait_for_sync`].
+///
+/// [`Config::get_wait_for_sync`]: crate::config::Config::get_wait_for_sync
+static CAUGHT_UP: AtomicBool = AtomicBool::new(false);
+/// Set by [`request_shutdown`] to stop [`Server::accept`].
+static SHUTTING_DOWN: AtomicBool = AtomicBool::new(false);
+/// How often a node that isn't listening checks whether it has been shut down.
+const SHUTDOWN_POLL: u64 = 1000;
+
+/// Whether the node is still downloading [Block]s announced by its peers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SyncStatus {
+    Synced,
+    Syncing,
+}
+
+impl Display for SyncStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Synced => write!(f, "synced"),
+            Self::Syncing => write!(f, "syncing"),
+        }
+    }
+}
+
+/// What a running node reports about itself, written to disk so that the CLI can
+/// read it while the node holds the database lock.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeStatus {
+    pub pid: u32,
+    pub started_at: i64,
+    pub updated_at: i64,
+    pub node_address: String,
+    pub mining_address: Option<String>,
+    pub chain: ChainStats,
+    pub sync: SyncStatus,
+    pub blocks_in_transit: usize,
+    pub peers: Vec<String>,
+    pub mempool: MempoolStats,
+    /// The connections to peers kept open for the packages sent to them.
+    #[serde(default)]
+    pub connections: usize,
+    /// The connections to peers opened since the node started.
+    #[serde(default)]
+    pub connections_opened: usize,
+}
+
+impl NodeStatus {
+    /// Writes the status as JSON, replacing the file at `path` atomically.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn Error>> {
+        let tmp_path = path.with_extension("json.tmp");
+        fs::write(tmp_path.as_path(), serde_json::to_vec(self)?)?;
+        fs::rename(tmp_path, path)?;
+        Ok(())
+    }
+
+    /// Reads the status at `path`, returning `None` when no node has written one.
+    pub fn load(path: &Path) -> Result<Option<Self>, Box<dyn Error>> {
+        if !path.exists() {
+            return Ok(None);
+        }
+        let bytes = fs::read(path)?;
+        Ok(Some(serde_json::from_slice(bytes.as_slice())?))
+    }
+}
 
 /// Defines essential functionalities to handle incoming client connections,
 /// communicate with a central [Node], and concurrently manage requests from
@@ -38,24 +175,288 @@ impl Server {
         Self { blockchain }
     }
 
+    #[cfg(feature = "grpc")]
+    fn serve_grpc(&self, grpc_bind: &str) -> Result<(), Box<dyn Error>> {
+        let grpc_listener = TcpListener::bind(grpc_bind)?;
+        let blockchain = self.blockchain.clone();
+        info!("Serving gRPC on {grpc_bind}");
+        thread::spawn(move || {
+            if let Err(e) = crate::grpc::serve(grpc_listener, blockchain) {
+                error!("gRPC server failed: {e}");
+            }
+        });
+        Ok(())
+    }
+
+    #[cfg(not(feature = "grpc"))]
+    #[allow(clippy::unnecessary_wraps, clippy::unused_self)]
+    fn serve_grpc(&self, grpc_bind: &str) -> Result<(), Box<dyn Error>> {
+        warn!("Not serving gRPC on {grpc_bind}, himalia was built without the `grpc` feature");
+        Ok(())
+    }
+
+    #[cfg(feature = "webhooks")]
+    fn start_webhooks(&self) -> Result<(), Box<dyn Error>> {
+        crate::webhooks::start(&self.blockchain)?;
+        Ok(())
+    }
+
+    #[cfg(not(feature = "webhooks"))]
+    #[allow(clippy::unnecessary_wraps, clippy::unused_self)]
+    fn start_webhooks(&self) -> Result<(), Box<dyn Error>> {
+        if !GLOBAL_CONFIG.get_webhooks().is_empty() {
+            warn!("Not posting to webhooks, himalia was built without the `webhooks` feature");
+        }
+        Ok(())
+    }
+
+    /// Announces the node to its bootstrap peers and serves incoming connections on
+    /// `addr`, or only makes outbound connections when listening is turned off.
     pub fn run(&self, addr: &str) -> Result<(), Box<dyn Error>> {
-        let listener = TcpListener::bind(addr).unwrap();
-        if !addr.eq(CENTRAL_NODE) {
-            let best_height = self.blockchain.get_best_height();
-            send_version(CENTRAL_NODE, best_height)?;
+        let listener = self.bind(addr)?;
+        self.accept(addr, listener);
+        Ok(())
+    }
+
+    /// Binds the node and API listeners and announces the node to its bootstrap peers,
+    /// returning the listener for `addr`, or `None` when listening is turned off.
+    pub fn bind(&self, addr: &str) -> Result<Option<TcpListener>, Box<dyn Error>> {
+        SHUTTING_DOWN.store(false, Ordering::Relaxed);
+        let mempool_tree = self.blockchain.get_db().open_tree(MEMPOOL_TREE)?;
+        let (restored, dropped) = GLOBAL_MEMORY_POOL.load_from(&mempool_tree, &self.blockchain)?;
+        if restored + dropped > 0 {
+            info!("Restored {restored} pending transactions, dropped {dropped} no longer valid");
+        }
+        let peer_expiry = GLOBAL_CONFIG.get_peer_expiry();
+        let peers_path = GLOBAL_CONFIG.get_peers_path();
+        match GLOBAL_NODES.load(peers_path.as_path(), peer_expiry) {
+            Ok(0) => {}
+            Ok(loaded) => info!("Loaded {loaded} known peers"),
+            Err(e) => warn!(
+                "Unable to read the known peers from {}: {e}",
+                peers_path.display()
+            ),
+        }
+        let ban_list_path = GLOBAL_CONFIG.get_ban_list_path();
+        if let Err(e) = GLOBAL_BANS.load(ban_list_path.as_path()) {
+            warn!(
+                "Unable to read the banned peers from {}: {e}",
+                ban_list_path.display()
+            );
+        }
+        let listener = if GLOBAL_CONFIG.get_listen() {
+            Some(TcpListener::bind(addr)?)
+        } else {
+            None
+        };
+        if let Some(rpc_bind) = GLOBAL_CONFIG.get_rpc_bind() {
+            let rpc_listener = TcpListener::bind(rpc_bind.as_str())?;
+            let blockchain = self.blockchain.clone();
+            info!("Serving JSON-RPC on {rpc_bind}");
+            thread::spawn(move || rpc::serve(&rpc_listener, &blockchain));
+        }
+        if let Some(rest_bind) = GLOBAL_CONFIG.get_rest_bind() {
+            let rest_listener = TcpListener::bind(rest_bind.as_str())?;
+            let blockchain = self.blockchain.clone();
+            info!("Serving the REST API on {rest_bind}");
+            thread::spawn(move || rest::serve(&rest_listener, &blockchain));
+        }
+        if let Some(notify_bind) = GLOBAL_CONFIG.get_notify_bind() {
+            let notify_listener = TcpListener::bind(notify_bind.as_str())?;
+            let blockchain = self.blockchain.clone();
+            info!("Publishing notifications on {notify_bind}");
+            thread::spawn(move || notify::serve(&notify_listener, &blockchain));
+        }
+        if let Some(grpc_bind) = GLOBAL_CONFIG.get_grpc_bind() {
+            self.serve_grpc(grpc_bind.as_str())?;
+        }
+        self.start_webhooks()?;
+        self.spawn_background_tasks();
+        LazyLock::force(&STARTED_AT);
+        save_snapshots(&self.blockchain);
+        let mut peers = bootstrap_peers();
+        for node in GLOBAL_NODES.get_recently_seen(BOOTSTRAP_KNOWN_PEERS, peer_expiry) {
+            if !peers.contains(&node.get_addr()) {
+                peers.push(node.get_addr());
+            }
         }
+        peers.retain(|peer| !addr.eq(peer.as_str()));
+        if peers.is_empty() {
+            CAUGHT_UP.store(true, Ordering::Relaxed);
+        }
+        for peer in &peers {
+            send_version(peer.as_str(), &self.blockchain)?;
+            send_get_addr(peer.as_str())?;
+        }
+        Ok(listener)
+    }
+
+    /// Starts the threads requesting the [Block]s in transit, closing idle connections,
+    /// evicting expired [Transaction]s and, on a miner, mining on the timer, all of
+    /// which stop on [`request_shutdown`].
+    fn spawn_background_tasks(&self) {
+        thread::spawn(|| {
+            while !SHUTTING_DOWN.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(BLOCK_REQUEST_POLL));
+                if let Err(e) = request_blocks() {
+                    warn!("Unable to request blocks: {e}");
+                }
+            }
+        });
+        thread::spawn(|| {
+            while !SHUTTING_DOWN.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(CONNECTION_IDLE_POLL));
+                let closed = GLOBAL_CONNECTIONS.expire_idle();
+                if closed > 0 {
+                    trace!("Closed {closed} idle connections to peers");
+                }
+            }
+        });
+        let blockchain = self.blockchain.clone();
+        thread::spawn(move || {
+            while !SHUTTING_DOWN.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(MEMPOOL_EXPIRY_POLL));
+                let expired = GLOBAL_MEMORY_POOL.evict_expired(GLOBAL_CONFIG.get_mempool_expiry());
+                if expired > 0 {
+                    info!("Evicted {expired} expired transactions from the mempool");
+                    save_snapshots(&blockchain);
+                }
+            }
+        });
+        if GLOBAL_CONFIG.is_miner() {
+            let blockchain = self.blockchain.clone();
+            thread::spawn(move || {
+                while !SHUTTING_DOWN.load(Ordering::Relaxed) {
+                    thread::sleep(Duration::from_millis(MINING_TIMER_POLL));
+                    if let Err(e) = mine_overdue(&blockchain) {
+                        warn!("Unable to mine the pending transactions: {e}");
+                    }
+                }
+            });
+        }
+    }
+
+    /// Serves the connections `listener` accepts until [`request_shutdown`] is called.
+    pub fn accept(&self, addr: &str, listener: Option<TcpListener>) {
+        let Some(listener) = listener else {
+            info!("Not listening for connections on {addr}");
+            while !SHUTTING_DOWN.load(Ordering::Relaxed) {
+                thread::park_timeout(Duration::from_millis(SHUTDOWN_POLL));
+            }
+            save_mempool(&self.blockchain);
+            save_snapshots(&self.blockchain);
+            return;
+        };
+        let max_connections = GLOBAL_CONFIG.get_max_connections();
+        let pool = ThreadPool::new(max_connections, max_connections);
         for stream in listener.incoming() {
-            let _blockchain = self.blockchain.clone();
-            thread::spawn(|| match stream {
-                Ok(_stream) => {
-                    //
+            if SHUTTING_DOWN.load(Ordering::Relaxed) {
+                break;
+            }
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    error!("Unable to accept a connection: {e}");
+                    continue;
                 }
-                Err(_e) => {
-                    //
+            };
+            if let Ok(peer) = stream.peer_addr() {
+                if GLOBAL_BANS.is_banned(peer.ip()) {
+                    info!("Refused the connection from {peer}, which is banned");
+                    continue;
+                }
+            }
+            let peer = stream
+                .peer_addr()
+                .map_or_else(|_| String::from("a peer"), |peer| peer.to_string());
+            let blockchain = self.blockchain.clone();
+            let served = pool.try_execute({
+                let peer = peer.clone();
+                move || {
+                    if let Err(e) = serve(&blockchain, stream) {
+                        error!("Unable to serve the connection from {peer}: {e}");
+                    }
                 }
             });
+            if served.is_err() {
+                warn!("Closing the connection from {peer}, every worker and queue slot is taken");
+            }
         }
-        Ok(())
+        info!("Stopped accepting connections on {addr}");
+        save_mempool(&self.blockchain);
+        save_snapshots(&self.blockchain);
+    }
+}
+
+/// Makes [`Server::accept`] return, waking it with a connection to `addr`, the
+/// address it listens on, and stops the JSON-RPC server the same way.
+pub fn request_shutdown(addr: &str) {
+    SHUTTING_DOWN.store(true, Ordering::Relaxed);
+    if GLOBAL_CONFIG.get_listen() {
+        let _ = TcpStream::connect(addr);
+    }
+    if let Some(rpc_bind) = GLOBAL_CONFIG.get_rpc_bind() {
+        let _ = TcpStream::connect(rpc_bind);
+    }
+}
+
+/// Whether [`request_shutdown`] was called since the node was last bound.
+pub(crate) fn is_shutting_down() -> bool {
+    SHUTTING_DOWN.load(Ordering::Relaxed)
+}
+
+/// Returns the address of the central node of the selected [Network].
+pub fn central_node() -> String {
+    GLOBAL_CONFIG.get_network().central_node()
+}
+
+/// Returns the peers given with `--connect`, or the central node when there are none.
+pub fn bootstrap_peers() -> Vec<String> {
+    let peers = GLOBAL_CONFIG.get_peers();
+    if peers.is_empty() {
+        vec![central_node()]
+    } else {
+        peers
+    }
+}
+
+/// Checks whether a miner may mine, holding off until the node has caught up with
+/// its peers when [`Config::get_wait_for_sync`] is set.
+///
+/// [`Config::get_wait_for_sync`]: crate::config::Config::get_wait_for_sync
+fn may_mine() -> bool {
+    GLOBAL_CONFIG.is_miner()
+        && (!GLOBAL_CONFIG.get_wait_for_sync() || CAUGHT_UP.load(Ordering::Relaxed))
+}
+
+/// Reports whether [Block]s announced by peers are still being downloaded.
+pub fn sync_status() -> SyncStatus {
+    if GLOBAL_BLOCKS_IN_TRANSIT.is_empty() {
+        SyncStatus::Synced
+    } else {
+        SyncStatus::Syncing
+    }
+}
+
+/// Describes this node, its peers and its mempool.
+pub fn node_status(blockchain: &Blockchain) -> NodeStatus {
+    NodeStatus {
+        pid: process::id(),
+        started_at: *STARTED_AT,
+        updated_at: current_timestamp(),
+        node_address: GLOBAL_CONFIG.get_node_addr(),
+        mining_address: GLOBAL_CONFIG.get_mining_addr(),
+        chain: blockchain.get_stats(),
+        sync: sync_status(),
+        blocks_in_transit: GLOBAL_BLOCKS_IN_TRANSIT.len(),
+        peers: GLOBAL_NODES
+            .get_nodes()
+            .iter()
+            .map(crate::node::Node::get_addr)
+            .collect(),
+        mempool: GLOBAL_MEMORY_POOL.get_stats(blockchain),
+        connections: GLOBAL_CONNECTIONS.len(),
+        connections_opened: GLOBAL_CONNECTIONS.get_opened(),
     }
 }
 
@@ -94,7 +495,124 @@ pub enum Package {
         addr_from: String,
         version: usize,
         best_height: usize,
+        /// Random for every process, so that a node can tell it dialed itself.
+        nonce: u64,
+        /// Peers only sync when they share the genesis [Block].
+        genesis_hash: BlockHash,
     },
+    /// Asks to only be sent the [Transaction]s matching a [`BloomFilter`].
+    SetFilter {
+        addr_from: String,
+        filter_bytes: Vec<u8>,
+        hash_funcs: u32,
+        tweak: u32,
+    },
+    ClearFilter {
+        addr_from: String,
+    },
+    /// Asks for the addresses of the peers the receiver knows.
+    GetAddr {
+        addr_from: String,
+    },
+    Addr {
+        addr_from: String,
+        addrs: Vec<String>,
+    },
+    /// The header of a [Block] and only those of its serialized [Transaction]s
+    /// matching the receiver's [`BloomFilter`], each with the [`MerkleProof`] that it
+    /// is in the [Block].
+    FilteredBlock {
+        addr_from: String,
+        header: BlockHeader,
+        transactions: Vec<Vec<u8>>,
+        proofs: Vec<MerkleProof>,
+    },
+    /// Asks for the headers of the best chain following the first hash of `locator`
+    /// the receiver has on it, see [`Blockchain::get_headers`].
+    GetHeaders {
+        addr_from: String,
+        locator: Vec<BlockHash>,
+    },
+    Headers {
+        addr_from: String,
+        headers: Vec<BlockHeader>,
+    },
+}
+
+impl Package {
+    /// Names the kind of [Package] for logs, which only show its contents, signatures
+    /// included, at trace level.
+    pub(crate) const fn name(&self) -> &'static str {
+        match self {
+            Self::Block { .. } => "block",
+            Self::GetBlocks { .. } => "getblocks",
+            Self::GetData { .. } => "getdata",
+            Self::Inv { .. } => "inv",
+            Self::Tx { .. } => "tx",
+            Self::Version { .. } => "version",
+            Self::SetFilter { .. } => "setfilter",
+            Self::ClearFilter { .. } => "clearfilter",
+            Self::GetAddr { .. } => "getaddr",
+            Self::Addr { .. } => "addr",
+            Self::FilteredBlock { .. } => "filteredblock",
+            Self::GetHeaders { .. } => "getheaders",
+            Self::Headers { .. } => "headers",
+        }
+    }
+}
+
+/// Requests the next [Block]s in transit from the peers that announced them, and
+/// again those that haven't arrived in time.
+fn request_blocks() -> Result<(), Box<dyn Error>> {
+    for (peer, block_hash) in GLOBAL_BLOCKS_IN_TRANSIT.take_next(MAX_BLOCKS_IN_FLIGHT) {
+        send_get_data(peer.as_str(), OpType::Block, block_hash.as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Stores a [Block] received from `addr_from`, along with the [Block]s in transit that
+/// arrived before it and build on it.
+///
+/// A [Block] arriving before its parent waits in [`BlockInTransit`] for the parent.
+/// Returns why `block` itself was rejected, if it was.
+fn receive_block(blockchain: &Blockchain, block: Block, addr_from: &str) -> Result<(), BlockError> {
+    let received = block.get_hash();
+    let mut result = Ok(());
+    let mut next = Some(block);
+    while let Some(block) = next.take() {
+        let block_hash = block.get_hash();
+        match blockchain.add_block(&block) {
+            Ok(()) => {
+                GLOBAL_BLOCKS_IN_TRANSIT.mark_received(block_hash);
+                info!("Added block {block_hash}");
+                if blockchain.get_tip_hash() == block_hash {
+                    GLOBAL_MEMORY_POOL.remove_confirmed(&block);
+                    GLOBAL_MINER.interrupt();
+                }
+                next = GLOBAL_BLOCKS_IN_TRANSIT.take_child(block_hash);
+            }
+            Err(BlockError::UnknownParent(_)) if GLOBAL_BLOCKS_IN_TRANSIT.park(&block) => {
+                trace!("Block {block_hash} waits for its parent");
+            }
+            Err(BlockError::UnknownParent(parent)) if blockchain.get_block(parent).is_some() => {
+                // The parent was stored after it looked for the blocks waiting on it.
+                next = Some(block);
+            }
+            Err(e) => {
+                warn!("Rejected block {block_hash} from {addr_from}: {e}");
+                GLOBAL_BLOCKS_IN_TRANSIT.mark_received(block_hash);
+                let mut rejected = block_hash;
+                while let Some(child) = GLOBAL_BLOCKS_IN_TRANSIT.take_child(rejected) {
+                    rejected = child.get_hash();
+                    warn!("Rejected block {rejected} from {addr_from}: its parent was rejected");
+                }
+                if block_hash == received {
+                    result = Err(e);
+                }
+            }
+        }
+    }
+    result
 }
 
 /// Transmits a request for specific data to a designated network address.
@@ -102,11 +620,10 @@ pub enum Package {
 /// Abstracts the process of sending a specific type of data to a specified
 /// address using a standardized package format. Will initiate a data retrieval
 /// request to the specified address in the [Blockchain] network.
-fn send_get_data(addr: &str, op_type: OpType, id: &[u8]) -> Result<(), Box<dyn Error>> {
-    let socket_addr = addr.parse().unwrap();
-    let node_addr = GLOBAL_CONFIG.get_node_addr().parse().unwrap();
+pub(crate) fn send_get_data(addr: &str, op_type: OpType, id: &[u8]) -> Result<(), Box<dyn Error>> {
+    let node_addr = GLOBAL_CONFIG.get_node_addr();
     send_data(
-        socket_addr,
+        addr,
         &Package::GetData {
             addr_from: node_addr,
             op_type,
@@ -122,30 +639,37 @@ fn send_get_data(addr: &str, op_type: OpType, id: &[u8]) -> Result<(), Box<dyn E
 /// using a standardized package format, which in this case represents [Block]s.
 /// Will help broadcast inventory notifications for specific data items to the
 /// indicated network address.
-fn send_inv(addr: &str, op_type: OpType, blocks: &[Vec<u8>]) -> Result<(), Box<dyn Error>> {
-    let socket_addr = addr.parse().unwrap();
-    let node_addr = GLOBAL_CONFIG.get_node_addr().parse().unwrap();
+fn send_inv(addr: &str, op_type: OpType, items: &[&[u8]]) -> Result<(), Box<dyn Error>> {
+    let node_addr = GLOBAL_CONFIG.get_node_addr();
     send_data(
-        socket_addr,
+        addr,
         &Package::Inv {
             addr_from: node_addr,
             op_type,
-            items: blocks.to_vec(),
+            items: items.iter().map(|item| item.to_vec()).collect(),
         },
     )?;
     Ok(())
 }
 
+/// Announces the [Block] or [Transaction] hashed to `item` to `addr` like
+/// [`send_inv`], unless the peer announced it or was announced it already.
+fn announce(addr: &str, op_type: OpType, item: &[u8]) -> Result<(), Box<dyn Error>> {
+    if GLOBAL_NODES.add_known_inventory(addr, item) {
+        send_inv(addr, op_type, &[item])?;
+    }
+    Ok(())
+}
+
 /// Transmits a [Block] to a specified network address.
 ///
 /// Abstracts the process of sending a block to a specified address using
 /// a standardized package format. The block is serialized before sending, likely
 /// to transmit it efficiently in byte form over the network.
 fn send_block(addr: &str, block: &Block) -> Result<(), Box<dyn Error>> {
-    let socket_addr = addr.parse().unwrap();
-    let node_addr = GLOBAL_CONFIG.get_node_addr().parse().unwrap();
+    let node_addr = GLOBAL_CONFIG.get_node_addr();
     send_data(
-        socket_addr,
+        addr,
         &Package::Block {
             addr_from: node_addr,
             block: block.serialize(),
@@ -160,32 +684,168 @@ fn send_block(addr: &str, block: &Block) -> Result<(), Box<dyn Error>> {
 /// a standardized package format. The [Transaction] is serialized before sending
 /// for efficient transmission over the network.
 pub fn send_tx(addr: &str, tx: &Transaction) -> Result<(), Box<dyn Error>> {
-    let socket_addr = addr.parse().unwrap();
-    let node_addr = GLOBAL_CONFIG.get_node_addr().parse().unwrap();
+    let node_addr = GLOBAL_CONFIG.get_node_addr();
     send_data(
-        socket_addr,
+        addr,
+        &Package::Tx {
+            addr_from: node_addr,
+            transaction: tx.serialize(),
+        },
+    )?;
+    Ok(())
+}
+
+/// Submits a [Transaction] to the node at `addr`, returning an error when it can't
+/// be reached rather than dropping it the way [`send_tx`] does between peers.
+pub fn submit_tx(addr: &str, tx: &Transaction) -> Result<(), Box<dyn Error>> {
+    let node_addr = GLOBAL_CONFIG.get_node_addr();
+    try_send_data(
+        addr,
         &Package::Tx {
             addr_from: node_addr,
             transaction: tx.serialize(),
         },
+    )
+}
+
+/// Sends the header of `block` with only `txs`, the [Transaction]s matching the
+/// [`BloomFilter`] set by `addr` and their positions in `block`, and their
+/// [`MerkleProof`]s.
+fn send_filtered_block(
+    addr: &str,
+    block: &Block,
+    txs: &[(usize, &Transaction)],
+) -> Result<(), Box<dyn Error>> {
+    let node_addr = GLOBAL_CONFIG.get_node_addr();
+    let merkle_tree = block.get_merkle_tree();
+    let (transactions, proofs): (Vec<Vec<u8>>, Vec<MerkleProof>) = txs
+        .iter()
+        .map(|(idx, tx)| {
+            let proof = merkle_tree
+                .proof(*idx)
+                .expect("the transaction is in the block");
+            (Transaction::serialize(tx), proof)
+        })
+        .unzip();
+    send_data(
+        addr,
+        &Package::FilteredBlock {
+            addr_from: node_addr,
+            header: block.get_header(),
+            transactions,
+            proofs,
+        },
+    )?;
+    Ok(())
+}
+
+/// Asks the node at `addr` for the headers following the first hash of `locator` on
+/// its best chain.
+pub(crate) fn send_get_headers(addr: &str, locator: Vec<BlockHash>) -> Result<(), Box<dyn Error>> {
+    let node_addr = GLOBAL_CONFIG.get_node_addr();
+    send_data(
+        addr,
+        &Package::GetHeaders {
+            addr_from: node_addr,
+            locator,
+        },
+    )?;
+    Ok(())
+}
+
+fn send_headers(addr: &str, headers: Vec<BlockHeader>) -> Result<(), Box<dyn Error>> {
+    let node_addr = GLOBAL_CONFIG.get_node_addr();
+    send_data(
+        addr,
+        &Package::Headers {
+            addr_from: node_addr,
+            headers,
+        },
     )?;
     Ok(())
 }
 
+/// Asks the node at `addr` to only send the [Transaction]s matching `filter` in the
+/// [Block]s asked for on the connection kept open to it, for as long as it stays open.
+pub fn send_filter(addr: &str, filter: &BloomFilter) -> Result<(), Box<dyn Error>> {
+    let node_addr = GLOBAL_CONFIG.get_node_addr();
+    try_send_pooled(
+        addr,
+        &Package::SetFilter {
+            addr_from: node_addr,
+            filter_bytes: filter.get_bits().to_vec(),
+            hash_funcs: filter.get_hash_funcs(),
+            tweak: filter.get_tweak(),
+        },
+    )
+}
+
+/// Asks the node at `addr` to send whole [Block]s again on the connection kept open
+/// to it.
+pub fn send_clear_filter(addr: &str) -> Result<(), Box<dyn Error>> {
+    let node_addr = GLOBAL_CONFIG.get_node_addr();
+    try_send_pooled(
+        addr,
+        &Package::ClearFilter {
+            addr_from: node_addr,
+        },
+    )
+}
+
 /// Broadcasts version information to a specified network address.
 ///
 /// Abstracts the process of sending a version message to a specified address using
 /// a standardized package format. The version message includes information about
-/// the [Node]'s version and the best-known height.
-fn send_version(addr: &str, height: usize) -> Result<(), Box<dyn Error>> {
-    let socket_addr = addr.parse().unwrap();
-    let node_addr = GLOBAL_CONFIG.get_node_addr().parse().unwrap();
+/// the [Node]'s version, the best-known height and the genesis [Block] of `blockchain`.
+fn send_version(addr: &str, blockchain: &Blockchain) -> Result<(), Box<dyn Error>> {
+    send_version_of(
+        addr,
+        blockchain.get_best_height(),
+        blockchain.get_genesis_hash(),
+    )
+}
+
+/// Like [`send_version`] for a chain ending at `best_height` that starts from the
+/// genesis [Block] hashed to `genesis_hash`, such as the headers of a light client.
+pub(crate) fn send_version_of(
+    addr: &str,
+    best_height: usize,
+    genesis_hash: BlockHash,
+) -> Result<(), Box<dyn Error>> {
+    let node_addr = GLOBAL_CONFIG.get_node_addr();
     send_data(
-        socket_addr,
+        addr,
         &Package::Version {
             addr_from: node_addr,
             version: NODE_VERSION,
-            best_height: height,
+            best_height,
+            nonce: *NODE_NONCE,
+            genesis_hash,
+        },
+    )?;
+    Ok(())
+}
+
+/// Asks the node at `addr` for the addresses of its peers.
+fn send_get_addr(addr: &str) -> Result<(), Box<dyn Error>> {
+    let node_addr = GLOBAL_CONFIG.get_node_addr();
+    send_data(
+        addr,
+        &Package::GetAddr {
+            addr_from: node_addr,
+        },
+    )?;
+    Ok(())
+}
+
+/// Tells the node at `addr` about the peers at `addrs`.
+fn send_addr(addr: &str, addrs: Vec<String>) -> Result<(), Box<dyn Error>> {
+    let node_addr = GLOBAL_CONFIG.get_node_addr();
+    send_data(
+        addr,
+        &Package::Addr {
+            addr_from: node_addr,
+            addrs,
         },
     )?;
     Ok(())
@@ -197,10 +857,9 @@ fn send_version(addr: &str, height: usize) -> Result<(), Box<dyn Error>> {
 /// using a standardized package format. The request does not include any specific
 /// block IDs or other parameters, it simply requests blocks from the receiving node.
 fn send_get_blocks(addr: &str) -> Result<(), Box<dyn Error>> {
-    let socket_addr = addr.parse().unwrap();
-    let node_addr = GLOBAL_CONFIG.get_node_addr().parse().unwrap();
+    let node_addr = GLOBAL_CONFIG.get_node_addr();
     send_data(
-        socket_addr,
+        addr,
         &Package::GetBlocks {
             addr_from: node_addr,
         },
@@ -214,25 +873,64 @@ fn send_get_blocks(addr: &str) -> Result<(), Box<dyn Error>> {
 #[allow(clippy::too_many_lines, clippy::needless_pass_by_value)]
 pub fn serve(blockchain: &Blockchain, stream: TcpStream) -> Result<(), Box<dyn Error>> {
     let peer_addr = stream.peer_addr()?;
-    let reader = BufReader::new(&stream);
-    let pkg_reader = Deserializer::from_reader(reader).into_iter::<Package>();
-    for pkg in pkg_reader {
-        let pkg = pkg?;
-        info!("Receive request from {peer_addr}: {pkg:?}");
+    let peer_ip = peer_addr.ip();
+    if GLOBAL_BANS.is_banned(peer_ip) {
+        stream.shutdown(Shutdown::Both)?;
+        return Ok(());
+    }
+    stream.set_read_timeout(Some(Duration::from_millis(TCP_READ_TIMEOUT)))?;
+    let _filter = ConnectionFilter(peer_addr);
+    let mut reader = BufReader::new(&stream);
+    loop {
+        let pkg = match read_package(&mut reader) {
+            Ok(Some(pkg)) => pkg,
+            Ok(None) => break,
+            // A peer keeps its connection open while it has packages to send.
+            Err(FrameError::Io(e))
+                if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) =>
+            {
+                break;
+            }
+            Err(e) => {
+                if e.is_invalid() {
+                    misbehaving(
+                        peer_ip,
+                        MALFORMED_PACKAGE_PENALTY,
+                        "sent a malformed package",
+                    );
+                }
+                return Err(e.into());
+            }
+        };
+        info!("Receive {} from {peer_addr}", pkg.name());
+        trace!("Received package: {pkg:?}");
         match pkg {
             Package::Block { addr_from, block } => {
-                let block = Block::deserialize(block.as_slice());
-                blockchain.add_block(&block);
-                info!("Added block {}", block.get_hash());
-                if !GLOBAL_BLOCKS_IN_TRANSIT.is_empty() {
-                    let block_hash = GLOBAL_BLOCKS_IN_TRANSIT.first().unwrap();
-                    send_get_data(addr_from.as_str(), OpType::Block, &block_hash)?;
-                    GLOBAL_BLOCKS_IN_TRANSIT.remove(block_hash.as_slice());
+                if block.len() > blockchain.get_limits().max_block_bytes {
+                    misbehaving(peer_ip, INVALID_BLOCK_PENALTY, "sent an oversized block");
+                    break;
+                }
+                let Ok(block) = Block::from_bytes(block.as_slice()) else {
+                    misbehaving(peer_ip, MALFORMED_PACKAGE_PENALTY, "sent a malformed block");
+                    break;
+                };
+                GLOBAL_NODES.add_known_inventory(addr_from.as_str(), block.get_hash().as_bytes());
+                if let Err(e) = receive_block(blockchain, block, addr_from.as_str()) {
+                    if !matches!(e, BlockError::UnknownParent(_)) {
+                        misbehaving(peer_ip, INVALID_BLOCK_PENALTY, "sent an invalid block");
+                    }
+                }
+                if GLOBAL_BLOCKS_IN_TRANSIT.is_empty() {
+                    CAUGHT_UP.store(true, Ordering::Relaxed);
+                } else {
+                    request_blocks()?;
                 }
+                save_snapshots(blockchain);
             }
             Package::GetBlocks { addr_from } => {
                 let blocks = blockchain.get_block_hashes();
-                send_inv(addr_from.as_str(), OpType::Block, &blocks)?;
+                let items: Vec<&[u8]> = blocks.iter().map(AsRef::as_ref).collect();
+                send_inv(addr_from.as_str(), OpType::Block, &items)?;
             }
             Package::GetData {
                 addr_from,
@@ -240,13 +938,21 @@ pub fn serve(blockchain: &Blockchain, stream: TcpStream) -> Result<(), Box<dyn E
                 id,
             } => match op_type {
                 OpType::Block => {
-                    if let Some(block) = blockchain.get_block(id.as_slice()) {
-                        send_block(addr_from.as_str(), &block)?;
+                    let block = BlockHash::from_slice(id.as_slice())
+                        .ok()
+                        .and_then(|block_hash| blockchain.get_block(block_hash));
+                    if let Some(block) = block {
+                        match GLOBAL_PEER_FILTERS.filter_block(peer_addr, &block) {
+                            Some(txs) => send_filtered_block(addr_from.as_str(), &block, &txs)?,
+                            None => send_block(addr_from.as_str(), &block)?,
+                        }
                     }
                 }
                 OpType::Tx => {
-                    let txid_hex = HEXLOWER.encode(id.as_slice());
-                    if let Some(tx) = GLOBAL_MEMORY_POOL.get(txid_hex.as_str()) {
+                    let tx = Txid::from_slice(id.as_slice())
+                        .ok()
+                        .and_then(|txid| GLOBAL_MEMORY_POOL.get(txid));
+                    if let Some(tx) = tx {
                         send_tx(addr_from.as_str(), &tx)?;
                     }
                 }
@@ -255,102 +961,851 @@ pub fn serve(blockchain: &Blockchain, stream: TcpStream) -> Result<(), Box<dyn E
                 addr_from,
                 op_type,
                 items,
-            } => match op_type {
-                OpType::Block => {
-                    GLOBAL_BLOCKS_IN_TRANSIT.add_blocks(items.as_slice());
-                    let block_hash = items.first().unwrap();
-                    send_get_data(addr_from.as_str(), OpType::Block, block_hash)?;
-                    GLOBAL_BLOCKS_IN_TRANSIT.remove(block_hash);
+            } => {
+                for item in &items {
+                    GLOBAL_NODES.add_known_inventory(addr_from.as_str(), item);
                 }
-                OpType::Tx => {
-                    let txid = items.first().unwrap();
-                    let txid_hex = HEXLOWER.encode(txid);
-                    if !GLOBAL_MEMORY_POOL.contains(txid_hex.as_str()) {
-                        send_get_data(addr_from.as_str(), OpType::Tx, txid)?;
+                match op_type {
+                    OpType::Block => {
+                        // Peers list their blocks tip first, but each one can only be
+                        // validated once its parent is stored.
+                        let block_hashes: Vec<BlockHash> = items
+                            .iter()
+                            .rev()
+                            .filter_map(|item| BlockHash::from_slice(item).ok())
+                            .filter(|block_hash| blockchain.get_block(*block_hash).is_none())
+                            .collect();
+                        GLOBAL_BLOCKS_IN_TRANSIT
+                            .add_blocks(addr_from.as_str(), block_hashes.as_slice());
+                        request_blocks()?;
+                    }
+                    OpType::Tx => {
+                        let txid = items.first().and_then(|item| Txid::from_slice(item).ok());
+                        if let Some(txid) = txid {
+                            if !GLOBAL_MEMORY_POOL.contains(txid)
+                                && !GLOBAL_SEEN_TXIDS.contains(txid)
+                            {
+                                send_get_data(addr_from.as_str(), OpType::Tx, txid.as_bytes())?;
+                            }
+                        }
                     }
                 }
-            },
+            }
             Package::Tx {
                 addr_from,
                 transaction,
             } => {
-                let tx = Transaction::deserialize(transaction.as_slice());
-                let txid = tx.get_id_bytes();
-                GLOBAL_MEMORY_POOL.add(tx);
-                let node_addr = GLOBAL_CONFIG.get_node_addr();
-                if node_addr.eq(CENTRAL_NODE) {
-                    let nodes = GLOBAL_NODES.get_nodes();
-                    for node in &nodes {
-                        if node_addr.eq(node.get_addr().as_str()) {
-                            continue;
-                        }
-                        if addr_from.eq(node.get_addr().as_str()) {
-                            continue;
-                        }
-                        send_inv(node.get_addr().as_str(), OpType::Tx, &[txid.clone()])?;
-                    }
+                if transaction.len() > blockchain.get_limits().max_tx_bytes {
+                    misbehaving(peer_ip, INVALID_TX_PENALTY, "sent an oversized transaction");
+                    break;
                 }
-                if GLOBAL_MEMORY_POOL.len() >= TRANSACTION_THRESHOLD && GLOBAL_CONFIG.is_miner() {
-                    let mining_address = GLOBAL_CONFIG.get_mining_addr().unwrap();
-                    let coinbase_tx = Transaction::new_coinbase_tx(mining_address.as_str());
-                    let mut txs = GLOBAL_MEMORY_POOL.get_all();
-                    txs.push(coinbase_tx);
-                    let new_block = blockchain.mine_block(&txs);
-                    let utxo_set = UTXOSet::new(blockchain.clone());
-                    utxo_set.reindex();
-                    info!("New block {} is mined!", new_block.get_hash());
-                    for tx in &txs {
-                        let txid_hex = HEXLOWER.encode(tx.get_id());
-                        GLOBAL_MEMORY_POOL.remove(txid_hex.as_str());
-                    }
-                    let nodes = GLOBAL_NODES.get_nodes();
-                    for node in &nodes {
-                        if node_addr.eq(node.get_addr().as_str()) {
-                            continue;
-                        }
-                        send_inv(
-                            node.get_addr().as_str(),
-                            OpType::Block,
-                            &[new_block.get_hash_bytes()],
-                        )?;
-                    }
+                let Ok(tx) = Transaction::from_bytes(transaction.as_slice()) else {
+                    misbehaving(
+                        peer_ip,
+                        MALFORMED_PACKAGE_PENALTY,
+                        "sent a malformed transaction",
+                    );
+                    break;
+                };
+                GLOBAL_NODES.add_known_inventory(addr_from.as_str(), tx.get_id().as_bytes());
+                if GLOBAL_SEEN_TXIDS.contains(tx.get_id()) {
+                    trace!("Ignored transaction {}, relayed already", tx.get_id());
+                } else {
+                    accept_tx(blockchain, &tx, addr_from.as_str(), peer_ip)?;
                 }
             }
             Package::Version {
                 addr_from,
                 version,
                 best_height,
+                nonce,
+                genesis_hash,
             } => {
                 info!("version = {version}, best_height = {best_height}");
+                if nonce == *NODE_NONCE {
+                    warn!("Dropped the connection from {addr_from}, which is this node");
+                    GLOBAL_NODES.evict_node(addr_from.as_str());
+                    GLOBAL_CONNECTIONS.close(addr_from.as_str());
+                    break;
+                }
+                if genesis_hash != blockchain.get_genesis_hash() {
+                    warn!(
+                        "Evicted {addr_from}, its chain starts from genesis block {genesis_hash}"
+                    );
+                    GLOBAL_NODES.evict_node(addr_from.as_str());
+                    GLOBAL_CONNECTIONS.close(addr_from.as_str());
+                    save_snapshots(blockchain);
+                    break;
+                }
                 let local_best_height = blockchain.get_best_height();
                 if local_best_height < best_height {
                     send_get_blocks(addr_from.as_str())?;
+                } else {
+                    CAUGHT_UP.store(true, Ordering::Relaxed);
                 }
                 if local_best_height > best_height {
-                    send_version(addr_from.as_str(), blockchain.get_best_height())?;
+                    send_version(addr_from.as_str(), blockchain)?;
+                }
+                if !GLOBAL_NODES.node_is_known(addr_from.as_str()) {
+                    GLOBAL_NODES.add_node(addr_from.clone());
+                    send_get_addr(addr_from.as_str())?;
+                }
+                GLOBAL_NODES.mark_seen(addr_from.as_str());
+                save_snapshots(blockchain);
+            }
+            Package::GetAddr { addr_from } => {
+                let addrs: Vec<String> = GLOBAL_NODES
+                    .get_random_sample(ADDR_SAMPLE_SIZE)
+                    .iter()
+                    .map(crate::node::Node::get_addr)
+                    .filter(|addr| !addr_from.eq(addr))
+                    .collect();
+                send_addr(addr_from.as_str(), addrs)?;
+            }
+            Package::Addr { addr_from, addrs } => {
+                if addrs.len() > ADDR_SAMPLE_SIZE {
+                    misbehaving(
+                        peer_ip,
+                        PROTOCOL_VIOLATION_PENALTY,
+                        "sent too many addresses",
+                    );
                 }
-                if !GLOBAL_NODES.node_is_known(peer_addr.to_string().as_str()) {
-                    GLOBAL_NODES.add_node(addr_from);
+                let node_addr = GLOBAL_CONFIG.get_node_addr();
+                for addr in addrs.into_iter().take(ADDR_SAMPLE_SIZE) {
+                    if node_addr.eq(&addr)
+                        || GLOBAL_NODES.node_is_known(addr.as_str())
+                        || !is_valid_addr(addr.as_str())
+                    {
+                        continue;
+                    }
+                    info!("Learned about {addr} from {addr_from}");
+                    GLOBAL_NODES.add_node(addr.clone());
+                    send_version(addr.as_str(), blockchain)?;
+                }
+                save_snapshots(blockchain);
+            }
+            Package::SetFilter {
+                addr_from,
+                filter_bytes,
+                hash_funcs,
+                tweak,
+            } => match BloomFilter::from_parts(filter_bytes, hash_funcs, tweak) {
+                Ok(filter) => GLOBAL_PEER_FILTERS.set(peer_addr, filter),
+                Err(e) => {
+                    error!("Refused the filter of {addr_from}: {e}");
+                    misbehaving(
+                        peer_ip,
+                        PROTOCOL_VIOLATION_PENALTY,
+                        "sent an invalid filter",
+                    );
                 }
+            },
+            Package::ClearFilter { .. } => GLOBAL_PEER_FILTERS.clear(peer_addr),
+            Package::FilteredBlock { header, .. } => {
+                let hash = header.get_hash();
+                info!("Ignoring filtered block {hash}, this node keeps full blocks");
+                misbehaving(
+                    peer_ip,
+                    PROTOCOL_VIOLATION_PENALTY,
+                    "sent a filtered block nobody asked for",
+                );
+            }
+            Package::GetHeaders { addr_from, locator } => {
+                if locator.len() > MAX_LOCATOR {
+                    misbehaving(
+                        peer_ip,
+                        PROTOCOL_VIOLATION_PENALTY,
+                        "sent too long a locator",
+                    );
+                }
+                let locator = &locator[..locator.len().min(MAX_LOCATOR)];
+                let headers = blockchain.get_headers(locator, MAX_HEADERS);
+                send_headers(addr_from.as_str(), headers)?;
+            }
+            Package::Headers { .. } => {
+                info!("Ignoring headers, this node keeps full blocks");
+                misbehaving(
+                    peer_ip,
+                    PROTOCOL_VIOLATION_PENALTY,
+                    "sent headers nobody asked for",
+                );
             }
         }
+        if GLOBAL_BANS.is_banned(peer_ip) {
+            break;
+        }
     }
     stream.shutdown(Shutdown::Both)?;
     Ok(())
 }
 
-/// Sends data packages to a specified socket address.
-fn send_data(addr: SocketAddr, pkg: &Package) -> Result<(), Box<dyn Error>> {
-    info!("send package: {:?}", pkg);
-    let stream = TcpStream::connect(addr);
-    if stream.is_err() {
-        error!("The {addr} is not valid");
-        GLOBAL_NODES.evict_node(addr.to_string().as_str());
+/// Drops the [`BloomFilter`] set on a connection once [`serve`] is done with it, so
+/// that it never applies to another connection.
+struct ConnectionFilter(SocketAddr);
+
+impl Drop for ConnectionFilter {
+    fn drop(&mut self) {
+        GLOBAL_PEER_FILTERS.clear(self.0);
+    }
+}
+
+/// Checks a [Transaction] received from `addr_from` against the chain and the
+/// mempool, and relays it like [`relay_tx`] once it's added to the mempool.
+///
+/// Refused [Transaction]s are never relayed, and those no node would accept count
+/// against `peer_ip`.
+fn accept_tx(
+    blockchain: &Blockchain,
+    tx: &Transaction,
+    addr_from: &str,
+    peer_ip: IpAddr,
+) -> Result<(), Box<dyn Error>> {
+    if let Err(e) = GLOBAL_MEMORY_POOL.try_add(tx.clone(), blockchain) {
+        warn!("Rejected transaction {} from {addr_from}: {e}", tx.get_id());
+        if matches!(
+            e,
+            AdmissionError::Coinbase
+                | AdmissionError::TooLarge { .. }
+                | AdmissionError::MismatchedId
+                | AdmissionError::InvalidSignature
+                | AdmissionError::FeeTooLow { .. }
+                | AdmissionError::InvalidValue
+        ) {
+            misbehaving(peer_ip, INVALID_TX_PENALTY, "sent an invalid transaction");
+        }
+        return Ok(());
+    }
+    relay_tx(blockchain, tx, addr_from)
+}
+
+/// Announces a [Transaction] just added to the mempool to every known peer other than
+/// `addr_from`, unless it was relayed before, and mines a [Block] once enough are
+/// pending.
+pub(crate) fn relay_tx(
+    blockchain: &Blockchain,
+    tx: &Transaction,
+    addr_from: &str,
+) -> Result<(), Box<dyn Error>> {
+    let txid = tx.get_id();
+    if RELAYED_SINCE_SAVE.fetch_add(1, Ordering::Relaxed) + 1 >= MEMPOOL_SAVE_INTERVAL {
+        save_mempool(blockchain);
+    }
+    save_snapshots(blockchain);
+    let node_addr = GLOBAL_CONFIG.get_node_addr();
+    if GLOBAL_SEEN_TXIDS.insert(txid) {
+        let nodes = GLOBAL_NODES.get_nodes();
+        for node in &nodes {
+            if node_addr.eq(node.get_addr().as_str()) {
+                continue;
+            }
+            if addr_from.eq(node.get_addr().as_str()) {
+                continue;
+            }
+            announce(node.get_addr().as_str(), OpType::Tx, txid.as_bytes())?;
+        }
+    }
+    if GLOBAL_MEMORY_POOL.reached_threshold() && may_mine() {
+        let threshold = GLOBAL_MEMORY_POOL.get_threshold();
+        if let Some(new_block) = GLOBAL_MINER.mine(blockchain, threshold)? {
+            announce_mined(blockchain, &new_block)?;
+        }
+    }
+    Ok(())
+}
+
+/// Mines a [Block] of whatever is pending once none was added for the configured
+/// mining interval, so that [Transaction]s short of the threshold get confirmed too.
+fn mine_overdue(blockchain: &Blockchain) -> Result<(), Box<dyn Error>> {
+    if GLOBAL_MEMORY_POOL.is_empty() || !may_mine() {
         return Ok(());
     }
-    let mut stream = stream.unwrap();
+    let tip_timestamp = blockchain
+        .get_block(blockchain.get_tip_hash())
+        .map_or(0, |block| block.get_timestamp());
+    let interval =
+        i64::try_from(GLOBAL_CONFIG.get_mining_interval().as_millis()).unwrap_or(i64::MAX);
+    if current_timestamp().saturating_sub(tip_timestamp) < interval {
+        return Ok(());
+    }
+    if let Some(new_block) = GLOBAL_MINER.mine(blockchain, 1)? {
+        announce_mined(blockchain, &new_block)?;
+    }
+    Ok(())
+}
+
+/// Drops the [Transaction]s of a [Block] this node just mined from the mempool and
+/// announces it to every known peer.
+fn announce_mined(blockchain: &Blockchain, new_block: &Block) -> Result<(), Box<dyn Error>> {
+    info!("New block {} is mined!", new_block.get_hash());
+    GLOBAL_MEMORY_POOL.remove_confirmed(new_block);
+    save_snapshots(blockchain);
+    let node_addr = GLOBAL_CONFIG.get_node_addr();
+    let nodes = GLOBAL_NODES.get_nodes();
+    for node in &nodes {
+        if node_addr.eq(node.get_addr().as_str()) {
+            continue;
+        }
+        announce(
+            node.get_addr().as_str(),
+            OpType::Block,
+            new_block.get_hash().as_bytes(),
+        )?;
+    }
+    Ok(())
+}
+
+/// Mines [Block]s of the pending [Transaction]s one at a time, starting over on the
+/// new tip when a [Block] from a peer replaces the one being mined on.
+struct Miner {
+    /// Held for as long as a [Block] is being mined.
+    running: Mutex<()>,
+    /// Cancels the [Block] being mined, `None` while idle.
+    cancel: Mutex<Option<CancelHandle>>,
+}
+
+impl Miner {
+    const fn new() -> Self {
+        Self {
+            running: Mutex::new(()),
+            cancel: Mutex::new(None),
+        }
+    }
+
+    /// Abandons the [Block] being mined, if any, so that [`Miner::mine`] rebuilds its
+    /// template on the new tip.
+    fn interrupt(&self) {
+        if let Some(cancel) = self.cancel.lock().unwrap().as_ref() {
+            cancel.cancel();
+        }
+    }
+
+    /// Mines a [Block] of the pending [Transaction]s paying the mining address, after
+    /// any [Block] already being mined, so that the threshold and the mining timer
+    /// never mine from the same template.
+    ///
+    /// The template is rebuilt from the mempool on the new tip after every
+    /// [`Miner::interrupt`], and `None` is returned once fewer than `threshold`
+    /// [Transaction]s are left to mine.
+    fn mine(
+        &self,
+        blockchain: &Blockchain,
+        threshold: usize,
+    ) -> Result<Option<Block>, Box<dyn Error>> {
+        let running = self.running.lock().unwrap();
+        let mining_address = GLOBAL_CONFIG.get_mining_addr().unwrap();
+        let block = loop {
+            let cancel = CancelHandle::new();
+            *self.cancel.lock().unwrap() = Some(cancel.clone());
+            let template = GLOBAL_MEMORY_POOL.get_block_template(blockchain);
+            if template.transactions.is_empty() || template.transactions.len() < threshold {
+                break None;
+            }
+            let coinbase_tx = Transaction::new_coinbase_tx(
+                mining_address.as_str(),
+                blockchain.get_params(),
+                template.fees,
+            )?;
+            let mut txs = template.transactions;
+            txs.push(coinbase_tx);
+            if let Some(block) = blockchain.mine_verified_block_cancellable(txs, &cancel)? {
+                break Some(block);
+            }
+            info!("The tip moved while mining, starting over on the new one");
+        };
+        *self.cancel.lock().unwrap() = None;
+        drop(running);
+        Ok(block)
+    }
+}
+
+/// Saves the pending [Transaction]s to the database so that they survive a restart.
+pub(crate) fn save_mempool(blockchain: &Blockchain) {
+    RELAYED_SINCE_SAVE.store(0, Ordering::Relaxed);
+    let saved = blockchain
+        .get_db()
+        .open_tree(MEMPOOL_TREE)
+        .and_then(|tree| GLOBAL_MEMORY_POOL.save_to(&tree));
+    if let Err(e) = saved {
+        error!("Unable to save the mempool: {e}");
+    }
+}
+
+/// Writes the contents of the mempool and the [`NodeStatus`] to disk so that the
+/// CLI can inspect them while this process holds the database lock, along with the
+/// peers seen recently for the next start.
+fn save_snapshots(blockchain: &Blockchain) {
+    let path = GLOBAL_CONFIG.get_peers_path();
+    if let Err(e) = GLOBAL_NODES.save(path.as_path(), GLOBAL_CONFIG.get_peer_expiry()) {
+        error!("Unable to write the known peers to {}: {e}", path.display());
+    }
+    let path = GLOBAL_CONFIG.get_mempool_snapshot_path();
+    if let Err(e) = GLOBAL_MEMORY_POOL.snapshot(blockchain).save(path.as_path()) {
+        error!(
+            "Unable to write the mempool snapshot to {}: {e}",
+            path.display()
+        );
+    }
+    let path = GLOBAL_CONFIG.get_node_status_path();
+    if let Err(e) = node_status(blockchain).save(path.as_path()) {
+        error!("Unable to write the node status to {}: {e}", path.display());
+    }
+}
+
+/// Adds `penalty` to the ban score of `peer_ip`, banning it once the score reaches
+/// the configured threshold. Returns whether `peer_ip` got banned.
+fn misbehaving(peer_ip: IpAddr, penalty: u32, reason: &str) -> bool {
+    let banned = GLOBAL_BANS.misbehaved(
+        peer_ip,
+        penalty,
+        GLOBAL_CONFIG.get_ban_threshold(),
+        GLOBAL_CONFIG.get_ban_duration(),
+        reason,
+    );
+    if banned {
+        warn!("Banned {peer_ip}, it {reason}");
+        save_ban_list();
+    } else {
+        info!(
+            "Ban score of {peer_ip} raised to {}, it {reason}",
+            GLOBAL_BANS.get_score(peer_ip)
+        );
+    }
+    banned
+}
+
+/// Writes the banned peers to disk so that they stay banned after a restart.
+pub(crate) fn save_ban_list() {
+    let path = GLOBAL_CONFIG.get_ban_list_path();
+    if let Err(e) = GLOBAL_BANS.save(path.as_path()) {
+        error!(
+            "Unable to write the banned peers to {}: {e}",
+            path.display()
+        );
+    }
+}
+
+/// Sends data packages to the peer at `addr` on the connection kept open to it,
+/// otherwise trying each address it resolves to in turn, except those that are
+/// banned. A peer that can't be reached is only logged.
+// Fallible like `try_send_data`, which the `send_*` helpers share their shape with.
+#[allow(clippy::unnecessary_wraps)]
+fn send_data(addr: &str, pkg: &Package) -> Result<(), Box<dyn Error>> {
+    let peer_expiry = GLOBAL_CONFIG.get_peer_expiry();
+    let socket_addrs = match resolve_addr(addr) {
+        Ok(socket_addrs) => socket_addrs,
+        Err(e) => {
+            error!("Unable to send {} to {addr}, {e}", pkg.name());
+            GLOBAL_NODES.evict_if_stale(addr, peer_expiry);
+            return Ok(());
+        }
+    };
+    let socket_addrs: Vec<SocketAddr> = socket_addrs
+        .into_iter()
+        .filter(|socket_addr| !GLOBAL_BANS.is_banned(socket_addr.ip()))
+        .collect();
+    if socket_addrs.is_empty() {
+        info!("Not sending {} to {addr}, which is banned", pkg.name());
+        return Ok(());
+    }
+    info!("Send {} to {addr}", pkg.name());
+    trace!("Sent package: {pkg:?}");
+    GLOBAL_NODES.mark_attempt(addr);
+    let sent = GLOBAL_CONNECTIONS.send(addr, socket_addrs.as_slice(), |stream| {
+        write_package(stream, pkg)
+    });
+    if let Err(e) = sent {
+        error!("Unable to send {} to {addr}: {e}", pkg.name());
+        GLOBAL_NODES.evict_if_stale(addr, peer_expiry);
+        return Ok(());
+    }
+    GLOBAL_NODES.mark_seen(addr);
+    Ok(())
+}
+
+/// Like [`send_data`], failing when `addr` can't be resolved or written to instead of
+/// only logging it.
+fn try_send_pooled(addr: &str, pkg: &Package) -> Result<(), Box<dyn Error>> {
+    info!("Send {} to {addr}", pkg.name());
+    trace!("Sent package: {pkg:?}");
+    let socket_addrs = resolve_addr(addr)?;
+    GLOBAL_CONNECTIONS.send(addr, socket_addrs.as_slice(), |stream| {
+        write_package(stream, pkg)
+    })?;
+    Ok(())
+}
+
+/// Sends a data package to the peer at `addr`, failing when it can't be resolved or
+/// connected to.
+fn try_send_data(addr: &str, pkg: &Package) -> Result<(), Box<dyn Error>> {
+    info!("Send {} to {addr}", pkg.name());
+    trace!("Sent package: {pkg:?}");
+    let mut stream = TcpStream::connect(resolve_addr(addr)?.as_slice())?;
     stream.set_write_timeout(Option::from(Duration::from_millis(TCP_WRITE_TIMEOUT)))?;
-    serde_json::to_writer(&stream, &pkg)?;
-    stream.flush()?;
+    write_package(&mut stream, pkg)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use std::net::Ipv4Addr;
+    use std::sync::{Arc, Barrier, MutexGuard, PoisonError};
+
+    use super::*;
+    use crate::clock::Clock;
+    use crate::events::Event;
+    use crate::test_utils::{coinbase, mine, pay, temp_chain};
+    use crate::wallet::Wallet;
+    use crate::wire::HEADER_LEN;
+
+    /// Held by a test driving the process-wide state of the node, such as the mempool
+    /// or the ban score in [`GLOBAL_BANS`] of the loopback address every peer connects
+    /// from, so that such tests run one at a time. Lifts any ban on the loopback
+    /// address once dropped, even when the test fails.
+    struct SharedNode {
+        _lock: MutexGuard<'static, ()>,
+    }
+
+    impl SharedNode {
+        fn lock() -> Self {
+            static NODE: Mutex<()> = Mutex::new(());
+            Self {
+                _lock: NODE.lock().unwrap_or_else(PoisonError::into_inner),
+            }
+        }
+    }
+
+    impl Drop for SharedNode {
+        fn drop(&mut self) {
+            GLOBAL_BANS.unban(IpAddr::V4(Ipv4Addr::LOCALHOST));
+        }
+    }
+
+    #[test]
+    fn peer_sending_garbage_is_banned_and_refused() {
+        let _node = SharedNode::lock();
+        let blockchain = temp_chain(&Wallet::new());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let strikes = GLOBAL_CONFIG
+            .get_ban_threshold()
+            .div_ceil(MALFORMED_PACKAGE_PENALTY);
+        for _ in 0..strikes {
+            assert!(!GLOBAL_BANS.is_banned(addr.ip()));
+            let mut peer = TcpStream::connect(addr).unwrap();
+            peer.write_all(&[0xab; HEADER_LEN]).unwrap();
+            let (stream, _) = listener.accept().unwrap();
+            assert!(serve(&blockchain, stream).is_err());
+        }
+        assert!(GLOBAL_BANS.is_banned(addr.ip()));
+
+        let mut peer = TcpStream::connect(addr).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        assert!(serve(&blockchain, stream).is_ok());
+        assert_eq!(peer.read(&mut [0; 1]).unwrap(), 0);
+    }
+
+    /// Has `serve` read a [`Package::Version`] from a known peer at `addr_from`, and
+    /// returns whether the connection was closed on it.
+    fn serve_version(
+        blockchain: &Blockchain,
+        addr_from: &str,
+        nonce: u64,
+        genesis_hash: BlockHash,
+    ) -> bool {
+        GLOBAL_NODES.add_node(addr_from.to_string());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let version = Package::Version {
+            addr_from: addr_from.to_string(),
+            version: NODE_VERSION,
+            best_height: 0,
+            nonce,
+            genesis_hash,
+        };
+        write_package(&mut peer, &version).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        serve(blockchain, stream).unwrap();
+        peer.read(&mut [0; 1]).unwrap() == 0
+    }
+
+    #[test]
+    fn connection_to_this_very_node_is_dropped() {
+        let blockchain = temp_chain(&Wallet::new());
+        let addr = "127.0.0.1:1";
+        let genesis_hash = blockchain.get_genesis_hash();
+        assert!(serve_version(&blockchain, addr, *NODE_NONCE, genesis_hash));
+        assert!(!GLOBAL_NODES.node_is_known(addr));
+    }
+
+    #[test]
+    fn peer_on_a_chain_with_another_genesis_block_is_evicted() {
+        let blockchain = temp_chain(&Wallet::new());
+        let addr = "127.0.0.1:2";
+        let nonce = NODE_NONCE.wrapping_add(1);
+        assert!(serve_version(
+            &blockchain,
+            addr,
+            nonce,
+            BlockHash::default()
+        ));
+        assert!(!GLOBAL_NODES.node_is_known(addr));
+    }
+
+    #[test]
+    fn only_transactions_the_mempool_accepts_are_relayed() {
+        let _node = SharedNode::lock();
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let relay_to = TcpListener::bind("127.0.0.1:0").unwrap();
+        let relay_addr = relay_to.local_addr().unwrap().to_string();
+        GLOBAL_NODES.add_node(relay_addr.clone());
+        let mut json = serde_json::to_value(pay(&blockchain, &miner, &Wallet::new(), 5)).unwrap();
+        let byte = json["vin"][0]["signature"][0].as_u64().unwrap();
+        json["vin"][0]["signature"][0] = (byte ^ 1).into();
+        let forged: Transaction = serde_json::from_value(json).unwrap();
+        let tx = pay(&blockchain, &miner, &Wallet::new(), 3);
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        for tx in [&forged, &tx] {
+            let package = Package::Tx {
+                addr_from: "127.0.0.1:3".to_string(),
+                transaction: tx.serialize(),
+            };
+            write_package(&mut peer, &package).unwrap();
+        }
+        peer.shutdown(Shutdown::Write).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        serve(&blockchain, stream).unwrap();
+
+        let (mut relayed, _) = relay_to.accept().unwrap();
+        let Some(Package::Inv {
+            op_type: OpType::Tx,
+            items,
+            ..
+        }) = read_package(&mut relayed).unwrap()
+        else {
+            panic!("the relayed transaction is announced first");
+        };
+        assert_eq!(items, [tx.get_id().as_bytes().to_vec()]);
+        assert!(!GLOBAL_MEMORY_POOL.contains(forged.get_id()));
+        GLOBAL_MEMORY_POOL.remove(tx.get_id());
+        GLOBAL_NODES.evict_node(relay_addr.as_str());
+    }
+
+    #[test]
+    fn blocks_sent_to_a_syncing_peer_share_one_connection() {
+        let _node = SharedNode::lock();
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        for _ in 0..30 {
+            mine(&blockchain, vec![], &miner);
+        }
+        let syncing = TcpListener::bind("127.0.0.1:0").unwrap();
+        let syncing_addr = syncing.local_addr().unwrap().to_string();
+        let mut hashes = blockchain.get_block_hashes();
+        hashes.reverse();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        for hash in &hashes {
+            let package = Package::GetData {
+                addr_from: syncing_addr.clone(),
+                op_type: OpType::Block,
+                id: hash.as_bytes().to_vec(),
+            };
+            write_package(&mut peer, &package).unwrap();
+        }
+        peer.shutdown(Shutdown::Write).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        serve(&blockchain, stream).unwrap();
+
+        let (mut blocks, _) = syncing.accept().unwrap();
+        for hash in &hashes {
+            let Some(Package::Block { block, .. }) = read_package(&mut blocks).unwrap() else {
+                panic!("the blocks are sent in the order they were asked for");
+            };
+            assert_eq!(
+                Block::from_bytes(block.as_slice()).unwrap().get_hash(),
+                *hash
+            );
+        }
+        syncing.set_nonblocking(true).unwrap();
+        assert!(syncing.accept().is_err());
+        GLOBAL_CONNECTIONS.close(syncing_addr.as_str());
+    }
+
+    /// Returns the packages sent to `listener` so far, over every connection to it.
+    fn received_by(listener: &TcpListener) -> Vec<Package> {
+        listener.set_nonblocking(true).unwrap();
+        let mut packages = vec![];
+        while let Ok((mut stream, _)) = listener.accept() {
+            stream.set_nonblocking(false).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_millis(100)))
+                .unwrap();
+            while let Ok(Some(package)) = read_package(&mut stream) {
+                packages.push(package);
+            }
+        }
+        packages
+    }
+
+    #[test]
+    fn transaction_is_announced_once_to_each_peer_not_knowing_it() {
+        let _node = SharedNode::lock();
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let tx = pay(&blockchain, &miner, &Wallet::new(), 3);
+        let txid = tx.get_id().as_bytes().to_vec();
+        let peers = [(); 3].map(|()| TcpListener::bind("127.0.0.1:0").unwrap());
+        let [announcing, sending, unaware] = peers
+            .each_ref()
+            .map(|peer| peer.local_addr().unwrap().to_string());
+        for addr in [&announcing, &sending, &unaware] {
+            GLOBAL_NODES.add_node(addr.clone());
+        }
+
+        // One peer announces the transaction and another sends it, then each relays it
+        // again as the other nodes of a mesh would.
+        let inv = |addr_from: &String| Package::Inv {
+            addr_from: addr_from.clone(),
+            op_type: OpType::Tx,
+            items: vec![txid.clone()],
+        };
+        let tx_from = |addr_from: &String| Package::Tx {
+            addr_from: addr_from.clone(),
+            transaction: tx.serialize(),
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut peer = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let packages = [
+            inv(&announcing),
+            tx_from(&sending),
+            tx_from(&announcing),
+            inv(&sending),
+            inv(&unaware),
+        ];
+        for package in &packages {
+            write_package(&mut peer, package).unwrap();
+        }
+        peer.shutdown(Shutdown::Write).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        serve(&blockchain, stream).unwrap();
+
+        // Leaves out the blocks a miner node mines of the transaction.
+        let [to_announcing, to_sending, to_unaware] = peers.each_ref().map(|peer| {
+            received_by(peer)
+                .into_iter()
+                .filter(|package| {
+                    !matches!(
+                        package,
+                        Package::Inv {
+                            op_type: OpType::Block,
+                            ..
+                        }
+                    )
+                })
+                .collect::<Vec<_>>()
+        });
+        assert!(
+            matches!(
+                to_announcing.as_slice(),
+                [Package::GetData { op_type: OpType::Tx, id, .. }] if *id == txid
+            ),
+            "{to_announcing:?}"
+        );
+        assert!(to_sending.is_empty(), "{to_sending:?}");
+        assert!(
+            matches!(
+                to_unaware.as_slice(),
+                [Package::Inv { op_type: OpType::Tx, items, .. }] if *items == [txid.clone()]
+            ),
+            "{to_unaware:?}"
+        );
+        GLOBAL_MEMORY_POOL.remove(tx.get_id());
+        for addr in [&announcing, &sending, &unaware] {
+            GLOBAL_NODES.evict_node(addr.as_str());
+            GLOBAL_CONNECTIONS.close(addr.as_str());
+        }
+    }
+
+    /// A [Clock] holding up the first [Block] stamped with it between two meetings at
+    /// `gate`, so that a test can act while the [Block] is being mined.
+    struct GatedClock {
+        read: AtomicBool,
+        gate: Barrier,
+    }
+
+    impl Clock for GatedClock {
+        fn now(&self) -> i64 {
+            if !self.read.swap(true, Ordering::SeqCst) {
+                self.gate.wait();
+                self.gate.wait();
+            }
+            current_timestamp()
+        }
+    }
+
+    #[test]
+    fn block_from_a_peer_taking_the_tip_restarts_the_miner_on_it() {
+        let _node = SharedNode::lock();
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let senders = [Wallet::new(), Wallet::new()];
+        for sender in &senders {
+            mine(&blockchain, vec![], sender);
+        }
+        let confirmed = pay(&blockchain, &senders[0], &Wallet::new(), 3);
+        let pending = pay(&blockchain, &senders[1], &Wallet::new(), 4);
+        for tx in [&confirmed, &pending] {
+            GLOBAL_MEMORY_POOL.try_add(tx.clone(), &blockchain).unwrap();
+        }
+        GLOBAL_CONFIG.set_mining_addr(miner.get_address());
+        let events = blockchain.subscribe();
+        let clock = Arc::new(GatedClock {
+            read: AtomicBool::new(false),
+            gate: Barrier::new(2),
+        });
+        let mining = thread::spawn({
+            let slow = blockchain.clone().with_clock(clock.clone());
+            move || GLOBAL_MINER.mine(&slow, 1).unwrap()
+        });
+
+        clock.gate.wait();
+        let tip = blockchain.get_block(blockchain.get_tip_hash()).unwrap();
+        let peer_block = Block::new(
+            tip.get_hash(),
+            vec![confirmed, coinbase(&Wallet::new(), 1)],
+            tip.get_height() + 1,
+            blockchain.get_next_target_bits(&tip),
+            &*blockchain.get_clock(),
+        );
+        receive_block(&blockchain, peer_block.clone(), "127.0.0.1:1").unwrap();
+        let cancel = GLOBAL_MINER.cancel.lock().unwrap().clone();
+        assert!(cancel.is_some_and(|cancel| cancel.is_cancelled()));
+        clock.gate.wait();
+        let block = mining.join().unwrap().unwrap();
+        GLOBAL_MEMORY_POOL.remove_confirmed(&block);
+
+        assert_eq!(block.get_pre_block_hash(), peer_block.get_hash());
+        let txids: Vec<Txid> = block
+            .get_transactions()
+            .iter()
+            .map(Transaction::get_id)
+            .collect();
+        assert_eq!(txids.len(), 2);
+        assert_eq!(txids[0], pending.get_id());
+        assert_eq!(blockchain.get_tip_hash(), block.get_hash());
+        let announced: Vec<BlockHash> = events
+            .try_iter()
+            .filter_map(|event| match event {
+                Event::Block { hash, .. } => Some(hash.parse().unwrap()),
+                Event::Tx { .. } => None,
+            })
+            .collect();
+        assert_eq!(announced, [peer_block.get_hash(), block.get_hash()]);
+    }
+}
@@ -0,0 +1,410 @@
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+use data_encoding::{HEXLOWER, HEXLOWER_PERMISSIVE};
+
+use crate::config::GLOBAL_CONFIG;
+use crate::wallet::Wallet;
+
+/// The length of the fixed-size P-256 signatures every [Signer] makes.
+pub const SIGNATURE_LEN: usize = 64;
+
+/// The reasons a [Signer] can't be set up or can't sign.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignerError {
+    /// The setting the [`SignerKind`] needs is missing.
+    NotConfigured(&'static str),
+    /// The external program couldn't be run or the endpoint couldn't be reached.
+    Unavailable(String),
+    /// The external program or the endpoint refused to sign.
+    Refused(String),
+    /// The answer is not a hex encoded key or signature.
+    Malformed,
+    /// The signature doesn't verify against the signer's public key.
+    InvalidSignature,
+    /// himalia was built without the feature the [`SignerKind`] needs.
+    Unsupported(&'static str),
+}
+
+impl Display for SignerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotConfigured(key) => write!(f, "no signer configured, set {key}"),
+            Self::Unavailable(reason) => write!(f, "the signer is unavailable: {reason}"),
+            Self::Refused(reason) => write!(f, "the signer refused to sign: {reason}"),
+            Self::Malformed => write!(f, "the signer answered with something other than hex"),
+            Self::InvalidSignature => write!(
+                f,
+                "the signer made a signature that doesn't match its public key"
+            ),
+            Self::Unsupported(feature) => write!(
+                f,
+                "this signer needs himalia built with the `{feature}` feature"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SignerError {}
+
+/// Holds a private key, in this process or elsewhere, and signs
+/// [Transaction](crate::transactions::Transaction) digests with it.
+pub trait Signer {
+    fn public_key(&self) -> Vec<u8>;
+
+    /// Signs `digest` with ECDSA P-256, returning a [`SIGNATURE_LEN`] byte signature.
+    fn sign(&self, digest: &[u8]) -> Result<Vec<u8>, SignerError>;
+}
+
+impl Signer for Wallet {
+    fn public_key(&self) -> Vec<u8> {
+        self.get_public_key().to_vec()
+    }
+
+    fn sign(&self, digest: &[u8]) -> Result<Vec<u8>, SignerError> {
+        self.sign_bytes(digest)
+            .map_err(|e| SignerError::Refused(e.to_string()))
+    }
+}
+
+/// Where `send` gets its signatures from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SignerKind {
+    /// The key in the local wallet file.
+    #[default]
+    Wallet,
+    /// A [`CommandSigner`] running the program set in `SIGNER_COMMAND`.
+    External,
+    /// An [`HttpSigner`] posting to the endpoint set in `SIGNER_URL`.
+    Http,
+}
+
+impl Display for SignerKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Wallet => f.write_str("wallet"),
+            Self::External => f.write_str("external"),
+            Self::Http => f.write_str("http"),
+        }
+    }
+}
+
+impl FromStr for SignerKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "wallet" => Ok(Self::Wallet),
+            "external" => Ok(Self::External),
+            "http" => Ok(Self::Http),
+            _ => Err(format!(
+                "unknown signer `{s}`, expected wallet, external or http"
+            )),
+        }
+    }
+}
+
+/// Sets up the configured [Signer] of `kind`, or `None` for [`SignerKind::Wallet`]
+/// whose key is looked up by address when a
+/// [Transaction](crate::transactions::Transaction) is built.
+pub fn configured_signer(kind: SignerKind) -> Result<Option<Box<dyn Signer>>, SignerError> {
+    match kind {
+        SignerKind::Wallet => Ok(None),
+        SignerKind::External => {
+            let program = GLOBAL_CONFIG
+                .get_signer_command()
+                .ok_or(SignerError::NotConfigured("SIGNER_COMMAND"))?;
+            Ok(Some(Box::new(CommandSigner::new(program.as_str())?)))
+        }
+        SignerKind::Http => http_signer(),
+    }
+}
+
+#[cfg(feature = "http-signer")]
+fn http_signer() -> Result<Option<Box<dyn Signer>>, SignerError> {
+    let url = GLOBAL_CONFIG
+        .get_signer_url()
+        .ok_or(SignerError::NotConfigured("SIGNER_URL"))?;
+    Ok(Some(Box::new(HttpSigner::new(url.as_str())?)))
+}
+
+#[cfg(not(feature = "http-signer"))]
+const fn http_signer() -> Result<Option<Box<dyn Signer>>, SignerError> {
+    Err(SignerError::Unsupported("http-signer"))
+}
+
+/// Signs with an external program.
+///
+/// The program is run as `<program> public-key` once to print its hex encoded public
+/// key, and as `<program> sign` for every signature, reading the hex encoded digest
+/// from stdin and printing the hex encoded signature.
+pub struct CommandSigner {
+    program: String,
+    public_key: Vec<u8>,
+}
+
+impl CommandSigner {
+    /// Asks `program` for its public key.
+    pub fn new(program: &str) -> Result<Self, SignerError> {
+        let public_key = run(program, "public-key", None)?;
+        Ok(Self {
+            program: String::from(program),
+            public_key,
+        })
+    }
+}
+
+impl Signer for CommandSigner {
+    fn public_key(&self) -> Vec<u8> {
+        self.public_key.clone()
+    }
+
+    fn sign(&self, digest: &[u8]) -> Result<Vec<u8>, SignerError> {
+        let signature = run(self.program.as_str(), "sign", Some(digest))?;
+        check_signature(self.public_key.as_slice(), signature, digest)
+    }
+}
+
+/// Runs `program` with `command`, passing `input` hex encoded on stdin, and decodes
+/// the hex it prints.
+fn run(program: &str, command: &str, input: Option<&[u8]>) -> Result<Vec<u8>, SignerError> {
+    let mut child = Command::new(program)
+        .arg(command)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| SignerError::Unavailable(format!("unable to run `{program}`: {e}")))?;
+    if let (Some(mut stdin), Some(input)) = (child.stdin.take(), input) {
+        match writeln!(stdin, "{}", HEXLOWER.encode(input)) {
+            // A program exiting without reading its input answers with its exit status.
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {}
+            Err(e) => return Err(SignerError::Unavailable(e.to_string())),
+        }
+    }
+    let output = child
+        .wait_with_output()
+        .map_err(|e| SignerError::Unavailable(e.to_string()))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(output.stderr.as_slice());
+        return Err(SignerError::Refused(format!(
+            "`{program} {command}` exited with {}: {}",
+            output.status,
+            stderr.trim()
+        )));
+    }
+    decode_hex(String::from_utf8_lossy(output.stdout.as_slice()).as_ref())
+}
+
+/// Signs by posting to an HTTP endpoint.
+///
+/// The endpoint answers `GET <url>/public-key` with `{"public_key": hex}` and
+/// `POST <url>/sign` with a body of `{"digest": hex}` with `{"signature": hex}`.
+#[cfg(feature = "http-signer")]
+pub struct HttpSigner {
+    url: String,
+    public_key: Vec<u8>,
+}
+
+#[cfg(feature = "http-signer")]
+impl HttpSigner {
+    /// Asks the endpoint at `url` for its public key.
+    pub fn new(url: &str) -> Result<Self, SignerError> {
+        let url = url.trim_end_matches('/');
+        let response = http_result(attohttpc::get(format!("{url}/public-key")).send())?;
+        let public_key = decode_field(&response, "public_key")?;
+        Ok(Self {
+            url: String::from(url),
+            public_key,
+        })
+    }
+}
+
+#[cfg(feature = "http-signer")]
+impl Signer for HttpSigner {
+    fn public_key(&self) -> Vec<u8> {
+        self.public_key.clone()
+    }
+
+    fn sign(&self, digest: &[u8]) -> Result<Vec<u8>, SignerError> {
+        let body = serde_json::json!({ "digest": HEXLOWER.encode(digest) });
+        let request = attohttpc::post(format!("{}/sign", self.url))
+            .json(&body)
+            .map_err(|e| SignerError::Unavailable(e.to_string()))?;
+        let response = http_result(request.send())?;
+        let signature = decode_field(&response, "signature")?;
+        check_signature(self.public_key.as_slice(), signature, digest)
+    }
+}
+
+/// Reads the JSON body of a successful response.
+#[cfg(feature = "http-signer")]
+fn http_result(
+    response: attohttpc::Result<attohttpc::Response>,
+) -> Result<serde_json::Value, SignerError> {
+    let response = response.map_err(|e| SignerError::Unavailable(e.to_string()))?;
+    let status = response.status();
+    if !status.is_success() {
+        let text = response.text().unwrap_or_default();
+        return Err(SignerError::Refused(format!(
+            "the endpoint answered {status}: {}",
+            text.trim()
+        )));
+    }
+    response.json().map_err(|_| SignerError::Malformed)
+}
+
+#[cfg(feature = "http-signer")]
+fn decode_field(response: &serde_json::Value, field: &str) -> Result<Vec<u8>, SignerError> {
+    response
+        .get(field)
+        .and_then(serde_json::Value::as_str)
+        .ok_or(SignerError::Malformed)
+        .and_then(decode_hex)
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>, SignerError> {
+    HEXLOWER_PERMISSIVE
+        .decode(hex.trim().as_bytes())
+        .map_err(|_| SignerError::Malformed)
+}
+
+/// Refuses a `signature` that doesn't verify, so that a misbehaving signer can't
+/// produce a [Transaction](crate::transactions::Transaction) the network rejects.
+fn check_signature(
+    public_key: &[u8],
+    signature: Vec<u8>,
+    digest: &[u8],
+) -> Result<Vec<u8>, SignerError> {
+    if crate::ecdsa_p256_sha256_sign_verify(public_key, signature.as_slice(), digest) {
+        Ok(signature)
+    } else {
+        Err(SignerError::InvalidSignature)
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_chain;
+    use crate::transactions::{Recipient, Transaction, TransactionError};
+    use crate::utxo_set::UTXOSet;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::PathBuf;
+
+    /// The private key the fake signer holds.
+    const KEY: [u8; 32] = [0x11; 32];
+
+    /// A directory of signer programs of its own, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let dir = std::env::temp_dir().join(format!("himalia-signer-{}", crate::random_u64()));
+            std::fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        /// Writes an executable shell script running `body`, returning its path.
+        fn script(&self, name: &str, body: &str) -> String {
+            let path = self.0.join(name);
+            std::fs::write(&path, format!("#!/bin/sh\n{body}\n")).unwrap();
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o755)).unwrap();
+            path.to_string_lossy().into_owned()
+        }
+
+        /// A script that answers `public-key` as the fake signer does and `sign` with
+        /// `sign`.
+        fn signer(&self, name: &str, sign: &str) -> String {
+            let fake_signer = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/fake_signer.py");
+            let key = HEXLOWER.encode(&KEY);
+            let sign = if sign.is_empty() {
+                format!("exec python3 {fake_signer} {key} sign")
+            } else {
+                String::from(sign)
+            };
+            self.script(
+                name,
+                format!(
+                    "if [ \"$1\" = public-key ]; then exec python3 {fake_signer} {key} public-key; fi\n{sign}"
+                )
+                .as_str(),
+            )
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn key_owner() -> Wallet {
+        Wallet::from_pkcs8(crate::pkcs8_from_private_key(&KEY).unwrap()).unwrap()
+    }
+
+    fn send_with(signer: &dyn Signer) -> Result<(Transaction, UTXOSet), TransactionError> {
+        let owner = key_owner();
+        let utxo_set = UTXOSet::new(temp_chain(&owner));
+        let recipient = Recipient {
+            address: Wallet::new().get_address(),
+            amount: 4,
+        };
+        let tx = Transaction::new_signed_transaction(
+            owner.get_address().as_str(),
+            &[recipient],
+            1,
+            &utxo_set,
+            signer,
+        )?;
+        Ok((tx, utxo_set))
+    }
+
+    #[test]
+    fn external_program_signs_a_transaction_that_verifies() {
+        let dir = TempDir::new();
+        let signer = CommandSigner::new(dir.signer("signer", "").as_str()).unwrap();
+        assert_eq!(signer.public_key(), key_owner().get_public_key());
+
+        let (tx, utxo_set) = send_with(&signer).unwrap();
+        assert!(tx.verify(utxo_set.get_blockchain()).unwrap());
+        assert_eq!(tx.get_vout()[0].get_value(), 4);
+    }
+
+    #[test]
+    fn misbehaving_program_is_refused() {
+        let dir = TempDir::new();
+        let missing = dir.0.join("missing");
+        assert!(matches!(
+            CommandSigner::new(missing.to_string_lossy().as_ref()),
+            Err(SignerError::Unavailable(_))
+        ));
+
+        let failing = CommandSigner::new(
+            dir.signer("failing", "echo 'the key is locked' >&2; exit 3")
+                .as_str(),
+        )
+        .unwrap();
+        match failing.sign(&[1, 2, 3]) {
+            Err(SignerError::Refused(reason)) => assert!(reason.ends_with(": the key is locked")),
+            other => panic!("expected a refusal, got {other:?}"),
+        }
+
+        let garbled = CommandSigner::new(dir.signer("garbled", "echo signed").as_str()).unwrap();
+        assert_eq!(garbled.sign(&[1, 2, 3]), Err(SignerError::Malformed));
+
+        let zeros = "00".repeat(SIGNATURE_LEN);
+        let forger = CommandSigner::new(
+            dir.signer("forger", format!("echo {zeros}").as_str())
+                .as_str(),
+        )
+        .unwrap();
+        assert!(matches!(
+            send_with(&forger),
+            Err(TransactionError::Signer(SignerError::InvalidSignature))
+        ));
+    }
+}
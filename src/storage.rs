@@ -0,0 +1,112 @@
+//! The sled databases of the process, opened once per data directory and shared by
+//! every [Blockchain](crate::blockchain::Blockchain) and
+//! [`UTXOSet`](crate::utxo_set::UTXOSet) reading it.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, LazyLock, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sled::Db;
+
+/// sled locks a directory for as long as one of its handles is alive, so that even
+/// within this process a second `sled::open` of it fails. The handles are held weakly
+/// so that a [Db] is still dropped, and flushed, once nothing uses it.
+static DATABASES: LazyLock<Mutex<HashMap<PathBuf, Weak<Db>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+/// How long reopening a dropped database waits for sled to release its lock.
+const REOPEN_TIMEOUT: Duration = Duration::from_secs(2);
+/// How often reopening a dropped database retries while its lock is still held.
+const REOPEN_POLL: Duration = Duration::from_millis(10);
+
+/// Returns the database in `dir`, opening it unless it is open already.
+pub fn open_db(dir: &Path) -> sled::Result<Arc<Db>> {
+    let mut databases = DATABASES.lock().unwrap();
+    let db = if let Some(db) = databases.get(dir).and_then(Weak::upgrade) {
+        db
+    } else {
+        let db = Arc::new(open(dir, databases.contains_key(dir))?);
+        databases.insert(dir.to_path_buf(), Arc::downgrade(&db));
+        db
+    };
+    drop(databases);
+    Ok(db)
+}
+
+/// Opens the database in `dir`. sled releases the lock of a dropped [Db] from its
+/// background threads, so when this process had `dir` open before, a lock still held
+/// is waited out for up to [`REOPEN_TIMEOUT`].
+fn open(dir: &Path, reopening: bool) -> sled::Result<Db> {
+    let deadline = Instant::now() + REOPEN_TIMEOUT;
+    loop {
+        match sled::open(dir) {
+            Err(sled::Error::Io(_)) if reopening && Instant::now() < deadline => {
+                thread::sleep(REOPEN_POLL);
+            }
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::block::Block;
+    use crate::blockchain::Blockchain;
+    use crate::clock::system_clock;
+    use crate::server::Server;
+    use crate::test_utils::{coinbase, regtest};
+    use crate::utxo_set::UTXOSet;
+    use crate::wallet::Wallet;
+
+    /// A directory under the system temporary directory, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let name = format!("himalia-storage-{}", crate::random_u64());
+            Self(std::env::temp_dir().join(name))
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(self.0.as_path());
+        }
+    }
+
+    #[test]
+    fn blockchain_utxo_set_and_server_share_one_database() {
+        regtest();
+        let dir = TempDir::new();
+        let miner = Wallet::new();
+        let genesis = Block::generate_genesis(coinbase(&miner, 0), &*system_clock());
+        let blockchain = Blockchain::open_or_init_in(open_db(&dir.0).unwrap(), &genesis).unwrap();
+        let utxo_set = UTXOSet::new(blockchain.clone());
+        utxo_set.reindex();
+        let _server = Server::new(blockchain.clone());
+
+        let db = open_db(&dir.0).unwrap();
+        assert!(std::ptr::eq(db.as_ref(), blockchain.get_db()));
+        let reopened = Blockchain::open_or_init_in(db, &genesis).unwrap();
+        assert_eq!(reopened.get_tip_hash(), blockchain.get_tip_hash());
+        assert_eq!(
+            UTXOSet::new(reopened).get_best_block(),
+            Some(blockchain.get_tip_hash())
+        );
+    }
+
+    #[test]
+    fn dropped_database_reopens_at_once() {
+        let dir = TempDir::new();
+        for round in 0_u8..20 {
+            let db = open_db(&dir.0).unwrap();
+            db.insert([round], &[round]).unwrap();
+        }
+        let db = open_db(&dir.0).unwrap();
+        assert_eq!(db.len(), 20);
+    }
+}
@@ -0,0 +1,346 @@
+use serde::{Deserialize, Serialize};
+
+use crate::transactions::{TXInput, TXOutput, Transaction};
+use crate::{block::Block, blockchain::Blockchain, memory_pool, wallet};
+
+/// A display-friendly view of a [Block] shared by the CLI `--json` output and
+/// any other interface rendering blocks.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockSummary {
+    pub hash: String,
+    pub pre_block_hash: String,
+    pub height: usize,
+    pub timestamp: i64,
+    pub nonce: i64,
+    pub target_bits: i64,
+    pub size: usize,
+    pub transaction_count: usize,
+    pub transactions: Vec<TransactionSummary>,
+}
+
+impl BlockSummary {
+    /// Summarizes the [Block], including every input and output when `verbose`.
+    pub fn new(block: &Block, verbose: bool) -> Self {
+        Self {
+            hash: block.get_hash().to_string(),
+            pre_block_hash: block.get_pre_block_hash().to_string(),
+            height: block.get_height(),
+            timestamp: block.get_timestamp(),
+            nonce: block.get_nonce(),
+            target_bits: block.get_target_bits(),
+            size: block.serialize().len(),
+            transaction_count: block.get_transactions().len(),
+            transactions: block
+                .get_transactions()
+                .iter()
+                .map(|tx| TransactionSummary::new(tx, verbose))
+                .collect(),
+        }
+    }
+
+    /// Summarizes the [Block] with every input and output, looking up the value of
+    /// the output each input spends.
+    pub fn resolve(block: &Block, blockchain: &Blockchain) -> Self {
+        Self {
+            transactions: block
+                .get_transactions()
+                .iter()
+                .map(|tx| TransactionSummary::resolve(tx, blockchain))
+                .collect(),
+            ..Self::new(block, false)
+        }
+    }
+}
+
+/// A display-friendly view of a [Transaction].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionSummary {
+    pub txid: String,
+    pub is_coinbase: bool,
+    pub input_count: usize,
+    pub output_count: usize,
+    pub total_output: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub inputs: Option<Vec<InputSummary>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub outputs: Option<Vec<OutputSummary>>,
+}
+
+impl TransactionSummary {
+    /// Summarizes the [Transaction], including every input and output when `verbose`.
+    pub fn new(tx: &Transaction, verbose: bool) -> Self {
+        let (inputs, outputs) = if verbose {
+            let inputs = if tx.is_coinbase() {
+                vec![]
+            } else {
+                tx.get_vin().iter().map(InputSummary::from).collect()
+            };
+            let outputs = tx.get_vout().iter().map(OutputSummary::from).collect();
+            (Some(inputs), Some(outputs))
+        } else {
+            (None, None)
+        };
+        Self {
+            txid: tx.get_id().to_string(),
+            is_coinbase: tx.is_coinbase(),
+            input_count: tx.get_vin().len(),
+            output_count: tx.get_vout().len(),
+            total_output: tx.get_vout().iter().map(TXOutput::get_value).sum(),
+            inputs,
+            outputs,
+        }
+    }
+
+    /// Summarizes the [Transaction] with every input and output, looking up the value
+    /// of the output each input spends.
+    pub fn resolve(tx: &Transaction, blockchain: &Blockchain) -> Self {
+        let inputs = if tx.is_coinbase() {
+            vec![]
+        } else {
+            tx.get_vin()
+                .iter()
+                .map(|input| InputSummary::resolve(input, blockchain))
+                .collect()
+        };
+        Self {
+            inputs: Some(inputs),
+            ..Self::new(tx, true)
+        }
+    }
+}
+
+/// Whether a [Transaction] has been included in a [Block] yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionStatus {
+    Confirmed,
+    Unconfirmed,
+}
+
+/// A [Transaction] with its inputs resolved against the outputs they spend.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransactionDetail {
+    pub txid: String,
+    pub status: TransactionStatus,
+    pub block_hash: Option<String>,
+    pub block_height: Option<usize>,
+    pub confirmations: usize,
+    pub is_coinbase: bool,
+    pub inputs: Vec<InputSummary>,
+    pub outputs: Vec<OutputSummary>,
+    /// Missing when an input spends an output that can't be found.
+    pub fee: Option<u64>,
+}
+
+impl TransactionDetail {
+    /// Describes `tx`, confirmed in `block` or unconfirmed when `block` is `None`.
+    pub fn new(tx: &Transaction, block: Option<&Block>, blockchain: &Blockchain) -> Self {
+        let inputs: Vec<InputSummary> = if tx.is_coinbase() {
+            vec![]
+        } else {
+            tx.get_vin()
+                .iter()
+                .map(|input| InputSummary::resolve(input, blockchain))
+                .collect()
+        };
+        let outputs: Vec<OutputSummary> = tx.get_vout().iter().map(OutputSummary::from).collect();
+        let total_output: u64 = outputs.iter().map(|output| output.value).sum();
+        let fee = if tx.is_coinbase() {
+            Some(0)
+        } else {
+            inputs
+                .iter()
+                .map(|input| input.value)
+                .sum::<Option<u64>>()
+                .and_then(|total_input| total_input.checked_sub(total_output))
+        };
+        let confirmations = block.map_or(0, |block| {
+            blockchain.get_best_height() + 1 - block.get_height()
+        });
+        Self {
+            txid: tx.get_id().to_string(),
+            status: if block.is_some() {
+                TransactionStatus::Confirmed
+            } else {
+                TransactionStatus::Unconfirmed
+            },
+            block_hash: block.map(|block| block.get_hash().to_string()),
+            block_height: block.map(Block::get_height),
+            confirmations,
+            is_coinbase: tx.is_coinbase(),
+            inputs,
+            outputs,
+            fee,
+        }
+    }
+}
+
+/// A [Transaction] input with the address of the key spending it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputSummary {
+    pub txid: String,
+    pub vout: usize,
+    pub address: String,
+    /// The value of the spent output, when it has been looked up.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub value: Option<u64>,
+}
+
+impl InputSummary {
+    /// Summarizes the input, looking up the value of the output it spends.
+    pub fn resolve(input: &TXInput, blockchain: &Blockchain) -> Self {
+        let value = blockchain
+            .find_transaction(input.get_txid())
+            .and_then(|prev_tx| {
+                prev_tx
+                    .get_vout()
+                    .get(input.get_vout())
+                    .map(TXOutput::get_value)
+            });
+        Self {
+            value,
+            ..Self::from(input)
+        }
+    }
+}
+
+impl From<&TXInput> for InputSummary {
+    fn from(input: &TXInput) -> Self {
+        let pub_key_hash = wallet::hash_pub_key(input.get_pub_key());
+        Self {
+            txid: input.get_txid().to_string(),
+            vout: input.get_vout(),
+            address: wallet::convert_address(pub_key_hash.as_slice()),
+            value: None,
+        }
+    }
+}
+
+/// The confirmed funds of an address and the pending [Transaction]s moving them.
+#[derive(Debug, Clone, Serialize)]
+pub struct AddressBalance {
+    pub address: String,
+    pub label: Option<String>,
+    /// Whether the address is watched without its key.
+    pub watch_only: bool,
+    pub confirmed: u64,
+    /// The value of pending outputs paying to the address.
+    pub pending_in: u64,
+    /// The value of the outputs pending [Transaction]s spend from the address.
+    pub pending_out: u64,
+}
+
+impl AddressBalance {
+    /// Returns what the confirmed balance becomes once the pending [Transaction]s are
+    /// mined.
+    pub const fn pending_balance(&self) -> u64 {
+        memory_pool::pending_balance(self.confirmed, self.pending_in, self.pending_out)
+    }
+}
+
+/// The funds of every wallet address added up, apart from the watch-only ones, along
+/// with the [`AddressBalance`] of each address.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct WalletBalance {
+    pub confirmed: u64,
+    pub pending_in: u64,
+    pub pending_out: u64,
+    pub watch_only_confirmed: u64,
+    pub watch_only_pending_in: u64,
+    pub watch_only_pending_out: u64,
+    pub addresses: Vec<AddressBalance>,
+}
+
+impl WalletBalance {
+    pub fn new(balances: Vec<AddressBalance>) -> Self {
+        let mut wallet_balance = balances.iter().fold(Self::default(), |mut total, balance| {
+            let (confirmed, pending_in, pending_out) = if balance.watch_only {
+                (
+                    &mut total.watch_only_confirmed,
+                    &mut total.watch_only_pending_in,
+                    &mut total.watch_only_pending_out,
+                )
+            } else {
+                (
+                    &mut total.confirmed,
+                    &mut total.pending_in,
+                    &mut total.pending_out,
+                )
+            };
+            *confirmed = confirmed.saturating_add(balance.confirmed);
+            *pending_in = pending_in.saturating_add(balance.pending_in);
+            *pending_out = pending_out.saturating_add(balance.pending_out);
+            total
+        });
+        wallet_balance.addresses = balances;
+        wallet_balance
+    }
+}
+
+/// A confirmed [Transaction] touching an address, with what it moved in and out of it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AddressTransaction {
+    pub txid: String,
+    pub block_hash: String,
+    pub block_height: usize,
+    pub timestamp: i64,
+    /// The value of the outputs paying to the address.
+    pub received: u64,
+    /// The value of the outputs spent from the address.
+    pub sent: u64,
+}
+
+impl AddressTransaction {
+    /// Describes `tx` from the point of view of `pub_key_hash`, or returns `None` when
+    /// none of its inputs or outputs belong to it.
+    pub fn new(
+        tx: &Transaction,
+        block: &Block,
+        pub_key_hash: &[u8],
+        blockchain: &Blockchain,
+    ) -> Option<Self> {
+        let outputs: Vec<&TXOutput> = tx
+            .get_vout()
+            .iter()
+            .filter(|out| out.is_locked_with_key(pub_key_hash))
+            .collect();
+        let inputs: Vec<&TXInput> = if tx.is_coinbase() {
+            vec![]
+        } else {
+            tx.get_vin()
+                .iter()
+                .filter(|input| input.uses_key(pub_key_hash))
+                .collect()
+        };
+        if outputs.is_empty() && inputs.is_empty() {
+            return None;
+        }
+        Some(Self {
+            txid: tx.get_id().to_string(),
+            block_hash: block.get_hash().to_string(),
+            block_height: block.get_height(),
+            timestamp: block.get_timestamp(),
+            received: outputs.iter().map(|out| out.get_value()).sum(),
+            sent: inputs
+                .iter()
+                .filter_map(|input| InputSummary::resolve(input, blockchain).value)
+                .sum(),
+        })
+    }
+}
+
+/// A [Transaction] output with the address it pays to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OutputSummary {
+    pub value: u64,
+    pub address: String,
+}
+
+impl From<&TXOutput> for OutputSummary {
+    fn from(output: &TXOutput) -> Self {
+        Self {
+            value: output.get_value(),
+            address: wallet::convert_address(output.get_pub_key_hash()),
+        }
+    }
+}
@@ -0,0 +1,71 @@
+//! Chains in temporary databases for the unit tests, on the regtest [Network] so that
+//! [Block]s are mined instantly.
+
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::clock::system_clock;
+use crate::config::{Network, GLOBAL_CONFIG};
+use crate::transactions::{Recipient, Transaction};
+use crate::utxo_set::UTXOSet;
+use crate::wallet::Wallet;
+
+/// Selects the regtest [Network], which every test shares, and mines on one thread.
+/// The files a node writes, such as its peers, go to a temporary directory.
+pub fn regtest() {
+    GLOBAL_CONFIG.set_network(Network::Regtest);
+    let data_dir = std::env::temp_dir().join(format!("himalia-tests-{}", std::process::id()));
+    GLOBAL_CONFIG.set_data_dir(data_dir.display().to_string());
+    GLOBAL_CONFIG.set_mining_threads(1);
+}
+
+/// Starts a [Blockchain] in a temporary database whose genesis [Block] pays `miner`,
+/// with its UTXO set up to date.
+pub fn temp_chain(miner: &Wallet) -> Blockchain {
+    regtest();
+    let db = sled::Config::new()
+        .temporary(true)
+        .open()
+        .expect("a temporary database opens");
+    let coinbase = coinbase(miner, 0);
+    let genesis = Block::generate_genesis(coinbase, &*system_clock());
+    let blockchain = Blockchain::open_or_init_in(db.into(), &genesis).expect("the new chain opens");
+    UTXOSet::new(blockchain.clone()).reindex();
+    blockchain
+}
+
+/// A Coinbase [Transaction] paying the subsidy and `fees` to `miner`.
+pub fn coinbase(miner: &Wallet, fees: u64) -> Transaction {
+    let params = GLOBAL_CONFIG.get_network().params();
+    Transaction::new_coinbase_tx(miner.get_address().as_str(), params, fees)
+        .expect("the miner address is valid")
+}
+
+/// Mines `transactions` and a Coinbase paying their fees to `miner` onto the tip.
+pub fn mine(blockchain: &Blockchain, mut transactions: Vec<Transaction>, miner: &Wallet) -> Block {
+    let utxo_set = UTXOSet::new(blockchain.clone());
+    let fees = transactions
+        .iter()
+        .map(|tx| tx.fee(&utxo_set).expect("the transactions are valid"))
+        .sum();
+    transactions.push(coinbase(miner, fees));
+    blockchain
+        .mine_block(transactions)
+        .expect("the transactions fit in a valid block")
+}
+
+/// A [Transaction] paying `amount` from `from` to `to` with a fee of one.
+pub fn pay(blockchain: &Blockchain, from: &Wallet, to: &Wallet, amount: u64) -> Transaction {
+    let recipient = Recipient {
+        address: to.get_address(),
+        amount,
+    };
+    let utxo_set = UTXOSet::new(blockchain.clone());
+    Transaction::new_signed_transaction(
+        from.get_address().as_str(),
+        &[recipient],
+        1,
+        &utxo_set,
+        from,
+    )
+    .expect("the sender has the funds")
+}
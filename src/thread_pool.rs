@@ -0,0 +1,48 @@
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A fixed number of worker threads running jobs taken from a bounded queue.
+///
+/// The workers finish the queued jobs and exit once the [`ThreadPool`] is dropped.
+pub struct ThreadPool {
+    sender: SyncSender<Job>,
+}
+
+impl ThreadPool {
+    /// Starts `size` workers sharing a queue that holds up to `queue_len` jobs
+    /// waiting for one of them.
+    pub fn new(size: usize, queue_len: usize) -> Self {
+        let (sender, receiver) = mpsc::sync_channel::<Job>(queue_len);
+        let receiver = Arc::new(Mutex::new(receiver));
+        for _ in 0..size.max(1) {
+            let receiver = Arc::clone(&receiver);
+            thread::spawn(move || work(&receiver));
+        }
+        Self { sender }
+    }
+
+    /// Queues `job` for the next free worker, handing it back when the queue is full.
+    pub fn try_execute<F>(&self, job: F) -> Result<(), Job>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        match self.sender.try_send(Box::new(job)) {
+            Ok(()) => Ok(()),
+            Err(TrySendError::Full(job) | TrySendError::Disconnected(job)) => Err(job),
+        }
+    }
+}
+
+/// Runs the jobs of `receiver` until every sender is gone.
+fn work(receiver: &Mutex<Receiver<Job>>) {
+    loop {
+        let job = receiver.lock().unwrap().recv();
+        match job {
+            Ok(job) => job(),
+            Err(_) => break,
+        }
+    }
+}
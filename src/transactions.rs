@@ -1,18 +1,233 @@
-use data_encoding::HEXLOWER;
+use std::fmt::{self, Display, Formatter};
+use std::str::FromStr;
+
+use bincode::Options;
+use data_encoding::HEXLOWER_PERMISSIVE;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::wallet::{self, hash_pub_key};
-use crate::{base58_decode, blockchain::Blockchain};
-use crate::{utxo_set::UTXOSet, wallets::Wallets};
+use crate::blockchain::Blockchain;
+use crate::config::ChainParams;
+use crate::signer::{Signer, SignerError};
+use crate::types::Txid;
+use crate::wallet::{address_pub_key_hash, convert_address, hash_pub_key, Wallet};
+use crate::{utxo_set::UTXOSet, wallets::WalletStore, KeyError, SecretBytes};
+
+/// The reasons a new [Transaction] can't be built from the local wallet.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransactionError {
+    /// The sending address has no key in the local wallet file.
+    WalletNotFound(String),
+    /// The sending address is only watched, its key is kept elsewhere.
+    WatchOnly(String),
+    /// An address to pay is not a valid address of the selected network.
+    InvalidAddress(String),
+    InsufficientFunds {
+        available: u64,
+        required: u64,
+    },
+    BelowDustThreshold {
+        amount: u64,
+        threshold: u64,
+    },
+    /// An amount to pay, or an output value, is zero.
+    ZeroAmount,
+    /// The amounts to pay, with the fee, don't fit in a `u64`.
+    AmountOverflow,
+    /// The [Signer]'s public key doesn't belong to the sending address.
+    SignerMismatch(String),
+    Signer(SignerError),
+    /// The key to sign a raw [Transaction] with is not a valid key pair.
+    InvalidKey(KeyError),
+    /// None of the wallets to sweep has an unspent output.
+    NothingToSweep,
+    /// The wallets to sweep hold a single unspent output, already paying the
+    /// destination.
+    AlreadyConsolidated(String),
+    /// A raw [Transaction] is signed with more or fewer spent outputs than it has
+    /// inputs.
+    PrevOutputsMismatch {
+        inputs: usize,
+        prev_outputs: usize,
+    },
+    /// An input spends an output that no [Transaction] of the [Blockchain] has.
+    UnknownOutput(OutPoint),
+}
 
-const SUBSIDY: i32 = 10;
+impl Display for TransactionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidAddress(address) => write!(f, "address `{address}` is not valid"),
+            Self::WalletNotFound(address) => {
+                write!(f, "no wallet found for address `{address}`")
+            }
+            Self::WatchOnly(address) => write!(
+                f,
+                "address `{address}` is watch-only, sign elsewhere with signrawtx"
+            ),
+            Self::InsufficientFunds {
+                available,
+                required,
+            } => write!(
+                f,
+                "not enough funds, {available} available but {required} required"
+            ),
+            Self::BelowDustThreshold { amount, threshold } => write!(
+                f,
+                "amount {amount} is below the dust threshold of {threshold}"
+            ),
+            Self::ZeroAmount => write!(f, "amounts must be positive"),
+            Self::AmountOverflow => write!(f, "the total amount to send is too large"),
+            Self::SignerMismatch(address) => {
+                write!(f, "the signer's key does not belong to `{address}`")
+            }
+            Self::Signer(e) => write!(f, "{e}"),
+            Self::InvalidKey(e) => write!(f, "{e}"),
+            Self::NothingToSweep => write!(f, "the wallet has no unspent outputs to sweep"),
+            Self::AlreadyConsolidated(address) => write!(
+                f,
+                "the wallet funds are already in a single output paying `{address}`"
+            ),
+            Self::PrevOutputsMismatch {
+                inputs,
+                prev_outputs,
+            } => write!(
+                f,
+                "the transaction has {inputs} inputs but {prev_outputs} spent outputs were given"
+            ),
+            Self::UnknownOutput(outpoint) => {
+                write!(f, "the spent output {outpoint} doesn't exist")
+            }
+        }
+    }
+}
+
+impl std::error::Error for TransactionError {}
+
+impl From<SignerError> for TransactionError {
+    fn from(e: SignerError) -> Self {
+        Self::Signer(e)
+    }
+}
+
+impl From<KeyError> for TransactionError {
+    fn from(e: KeyError) -> Self {
+        Self::InvalidKey(e)
+    }
+}
+
+/// The reasons a hex string can't be decoded into a [Transaction].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+    NotHex,
+    /// The bytes don't deserialize into a [Transaction].
+    Malformed,
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotHex => write!(f, "the raw transaction is not valid hex"),
+            Self::Malformed => write!(f, "the raw transaction is not a serialized transaction"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// The reasons the values of a [Transaction] don't add up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueError {
+    /// The values add up to more than a `u64` holds.
+    OutOfRange,
+    /// The outputs are worth more than the outputs the inputs spend.
+    OutputsExceedInputs { inputs: u64, outputs: u64 },
+}
+
+impl Display for ValueError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OutOfRange => write!(f, "the output values are too large"),
+            Self::OutputsExceedInputs { inputs, outputs } => write!(
+                f,
+                "the outputs are worth {outputs}, more than the {inputs} of the inputs"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ValueError {}
+
+/// An address and the amount a new [Transaction] pays to it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Recipient {
+    pub address: String,
+    pub amount: u64,
+}
+
+impl FromStr for Recipient {
+    type Err = String;
+
+    /// Parses `<address>:<amount>`, where the amount is a positive whole number.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((address, amount)) = s.rsplit_once(':') else {
+            return Err(format!("`{s}` is not of the form <address>:<amount>"));
+        };
+        if address.is_empty() {
+            return Err(format!("`{s}` is missing an address"));
+        }
+        if amount.is_empty() || !amount.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(format!("amount `{amount}` is not a whole number"));
+        }
+        let amount: u64 = amount
+            .parse()
+            .map_err(|_| format!("amount `{amount}` is too large"))?;
+        if amount == 0 {
+            return Err(format!("amount for `{address}` must be positive"));
+        }
+        Ok(Self {
+            address: String::from(address),
+            amount,
+        })
+    }
+}
+
+/// The output of a [Transaction] a raw [Transaction] spends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutPoint {
+    pub txid: Txid,
+    pub vout: usize,
+}
+
+impl Display for OutPoint {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.txid, self.vout)
+    }
+}
+
+impl FromStr for OutPoint {
+    type Err = String;
+
+    /// Parses `<txid>:<vout>`, where the txid is in hex.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let Some((txid, vout)) = s.rsplit_once(':') else {
+            return Err(format!("`{s}` is not of the form <txid>:<vout>"));
+        };
+        let txid = txid
+            .parse()
+            .map_err(|_| format!("`{txid}` is not a transaction id"))?;
+        let vout = vout
+            .parse()
+            .map_err(|_| format!("output index `{vout}` is not a whole number"))?;
+        Ok(Self { txid, vout })
+    }
+}
 
 #[derive(Clone, Debug, Default, Serialize, Deserialize)]
 pub struct TXInput {
-    /// Bytes representing the id of the [Transaction] that created
-    /// the output that this input is sending.
-    txid: Vec<u8>,
+    /// The id of the [Transaction] that created the output that this input
+    /// is sending, all zeros in a coinbase.
+    txid: Txid,
     /// An index that represents which output of the [Transaction]
     /// with `txid` this input is sending.
     vout: usize,
@@ -25,65 +240,72 @@ pub struct TXInput {
 }
 
 impl TXInput {
-    pub fn new(txid: &[u8], vout: usize) -> Self {
+    pub const fn new(txid: Txid, vout: usize) -> Self {
         Self {
-            txid: txid.to_vec(),
+            txid,
             vout,
             signature: Vec::new(),
             pub_key: Vec::new(),
         }
     }
 
-    pub fn get_txid(&self) -> &[u8] {
-        self.txid.as_slice()
+    pub const fn get_txid(&self) -> Txid {
+        self.txid
     }
 
     pub const fn get_vout(&self) -> usize {
         self.vout
     }
 
-    pub fn get_pub_key(&self) -> &[u8] {
+    pub const fn get_pub_key(&self) -> &[u8] {
         self.pub_key.as_slice()
     }
 
     /// Indicates whether the `pub_key` field of the input corresponds to
     /// the specified `pub_key_hash` byte vector.
     pub fn uses_key(&self, pub_key_hash: &[u8]) -> bool {
-        let locking_hash = wallet::hash_pub_key(self.pub_key.as_slice());
+        let locking_hash = hash_pub_key(self.pub_key.as_slice());
         locking_hash.eq(pub_key_hash)
     }
 }
 
 /// Manages [Transaction] outputs within the [Blockchain], storing values
-/// and public key hashes. Facilitates creation of new outputs, value
+/// and public key hashes.
+///
+/// Facilitates creation of new outputs, value
 /// retrieval, and verification of locked outputs using cryptographic hashes.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TXOutput {
-    value: i32,
+    value: u64,
     pub_key_hash: Vec<u8>,
 }
 
 impl TXOutput {
-    pub fn new(value: i32, address: &str) -> Self {
+    /// Creates an output paying `value` to `address`, failing when the value is zero.
+    pub fn new(value: u64, address: &str) -> Result<Self, TransactionError> {
+        if value == 0 {
+            return Err(TransactionError::ZeroAmount);
+        }
         let mut output = Self {
             value,
             pub_key_hash: Vec::new(),
         };
-        output.lock(address);
-        output
+        output.lock(address)?;
+        Ok(output)
     }
 
-    pub const fn get_value(&self) -> i32 {
+    pub const fn get_value(&self) -> u64 {
         self.value
     }
 
-    pub fn get_pub_key_hash(&self) -> &[u8] {
+    pub const fn get_pub_key_hash(&self) -> &[u8] {
         self.pub_key_hash.as_slice()
     }
 
-    fn lock(&mut self, address: &str) {
-        let payload = base58_decode(address);
-        self.pub_key_hash = payload[1..payload.len() - wallet::ADDRESS_CHECK_SUM_LEN].to_vec();
+    fn lock(&mut self, address: &str) -> Result<(), TransactionError> {
+        self.pub_key_hash = address_pub_key_hash(address)
+            .ok_or_else(|| TransactionError::InvalidAddress(String::from(address)))?;
+        Ok(())
     }
 
     /// Checks whether the given `pub_key_hash` matches the stored value.
@@ -93,69 +315,328 @@ impl TXOutput {
 }
 
 /// Manages [Transaction] creation, validation and signature verification
-/// in the [Blockchain]. Constructs Coinbase and UTXO transactions, handles
+/// in the [Blockchain].
+///
+/// Constructs Coinbase and UTXO transactions, handles
 /// transaction signing and verification, and provides methods for serialization
 /// and deserialization of transaction data.
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
-    id: Vec<u8>,
+    id: Txid,
     vin: Vec<TXInput>,
     vout: Vec<TXOutput>,
 }
 
+/// Borrows the fields of a [Transaction] to serialize it with another id, encoding
+/// exactly as the [Transaction] itself would.
+#[derive(Serialize)]
+struct TransactionView<'a> {
+    id: Txid,
+    vin: InputsView<'a>,
+    vout: &'a [TXOutput],
+}
+
+/// Borrows [`TXInput`]s to serialize them as a `Vec<TXInput>` would, with empty
+/// signatures unless `signed`.
+struct InputsView<'a> {
+    inputs: &'a [TXInput],
+    signed: bool,
+}
+
+impl Serialize for InputsView<'_> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.inputs.iter().map(|input| InputView {
+            txid: input.txid,
+            vout: input.vout,
+            signature: if self.signed {
+                input.signature.as_slice()
+            } else {
+                &[]
+            },
+            pub_key: input.pub_key.as_slice(),
+        }))
+    }
+}
+
+/// Borrows the fields of a [`TXInput`], encoding exactly as the [`TXInput`] would.
+#[derive(Serialize)]
+struct InputView<'a> {
+    txid: Txid,
+    vout: usize,
+    signature: &'a [u8],
+    pub_key: &'a [u8],
+}
+
 impl Transaction {
-    /// Creates a new Coinbase transaction, generating a [Transaction] output with
-    /// a specified value and recipient address.
-    pub fn new_coinbase_tx(to: &str) -> Self {
-        let tx_output = TXOutput::new(SUBSIDY, to);
+    /// Creates a new Coinbase transaction, generating a [Transaction] output paying
+    /// the subsidy of the given [`ChainParams`] and the `fees` collected from the other
+    /// [Transaction]s of the [Block](crate::block::Block) to the recipient address.
+    pub fn new_coinbase_tx(
+        to: &str,
+        params: &ChainParams,
+        fees: u64,
+    ) -> Result<Self, TransactionError> {
+        let reward = params
+            .subsidy
+            .checked_add(fees)
+            .ok_or(TransactionError::AmountOverflow)?;
+        let tx_output = TXOutput::new(reward, to)?;
         let tx_input = TXInput {
             signature: Uuid::new_v4().as_bytes().to_vec(),
             ..Default::default()
         };
         let mut tx = Self {
-            id: vec![],
+            id: Txid::default(),
             vin: vec![tx_input],
             vout: vec![tx_output],
         };
         tx.id = tx.hash();
-        tx
+        Ok(tx)
     }
 
     /// Constructs a new UTXO-based [Transaction] by selecting spendable outputs and creating
     /// inputs for the [Transaction]. Calculates inputs required based on available outputs,
     /// manages outputs for the recipient and change, signs the transaction, and computes its id.
-    pub fn new_utxo_transaction(from: &str, to: &str, amount: i32, utxo_set: &UTXOSet) -> Self {
-        let wallets = Wallets::new();
-        let wallet = wallets.get_wallet(from).expect("unable to find wallet");
-        let public_key_hash = hash_pub_key(wallet.get_public_key());
-        let (accumulated, valid_outputs) =
-            utxo_set.find_spendable_outputs(public_key_hash.as_slice(), amount);
-        assert!(accumulated >= amount, "Error: not enough funds");
+    pub fn new_utxo_transaction(
+        from: &str,
+        to: &str,
+        amount: u64,
+        fee: u64,
+        utxo_set: &UTXOSet,
+        wallets: &WalletStore,
+    ) -> Result<Self, TransactionError> {
+        let recipient = Recipient {
+            address: String::from(to),
+            amount,
+        };
+        Self::new_multi_recipient_transaction(from, &[recipient], fee, utxo_set, wallets)
+    }
+
+    /// Constructs a new UTXO-based [Transaction] paying every [Recipient] from the
+    /// wallet of `from` in a single set of inputs.
+    ///
+    /// The `fee` is left unspent on top of the recipient amounts, and any remaining
+    /// change at or above the dust threshold is returned to `from`.
+    pub fn new_multi_recipient_transaction(
+        from: &str,
+        recipients: &[Recipient],
+        fee: u64,
+        utxo_set: &UTXOSet,
+        wallets: &WalletStore,
+    ) -> Result<Self, TransactionError> {
+        let wallet = local_wallet(wallets, from)?;
+        Self::new_signed_transaction(from, recipients, fee, utxo_set, &wallet)
+    }
+
+    /// Selects inputs and builds outputs exactly like
+    /// [`Transaction::new_multi_recipient_transaction`] but leaves the inputs unsigned.
+    pub fn new_unsigned_transaction(
+        from: &str,
+        recipients: &[Recipient],
+        fee: u64,
+        utxo_set: &UTXOSet,
+        wallets: &WalletStore,
+    ) -> Result<Self, TransactionError> {
+        let wallet = local_wallet(wallets, from)?;
+        Self::build(from, recipients, fee, utxo_set, wallet.get_public_key())
+    }
+
+    /// Like [`Transaction::new_multi_recipient_transaction`], signing with `signer`,
+    /// whose key must belong to `from`, instead of the local wallet.
+    ///
+    /// Nothing is returned unless every input could be signed.
+    pub fn new_signed_transaction(
+        from: &str,
+        recipients: &[Recipient],
+        fee: u64,
+        utxo_set: &UTXOSet,
+        signer: &dyn Signer,
+    ) -> Result<Self, TransactionError> {
+        let public_key = signer.public_key();
+        if convert_address(hash_pub_key(public_key.as_slice()).as_slice()) != from {
+            return Err(TransactionError::SignerMismatch(String::from(from)));
+        }
+        let mut tx = Self::build(from, recipients, fee, utxo_set, public_key.as_slice())?;
+        tx.sign(utxo_set.get_blockchain(), signer)?;
+        Ok(tx)
+    }
+
+    /// Spends every unspent output of `wallets` in a single [Transaction] paying their
+    /// value less the `fee` to `to`, each input signed by the [Wallet] owning it.
+    pub fn new_sweep_transaction(
+        to: &str,
+        fee: u64,
+        utxo_set: &UTXOSet,
+        wallets: &[Wallet],
+    ) -> Result<Self, TransactionError> {
+        let to_pub_key_hash = address_pub_key_hash(to)
+            .ok_or_else(|| TransactionError::InvalidAddress(String::from(to)))?;
         let mut inputs = vec![];
-        for (txid_hex, outs) in valid_outputs {
-            let txid = HEXLOWER.decode(txid_hex.as_bytes()).unwrap();
-            for out in outs {
-                let input = TXInput {
-                    txid: txid.clone(),
-                    vout: out,
+        let mut spent = vec![];
+        let mut signers: Vec<&dyn Signer> = vec![];
+        for wallet in wallets {
+            let pub_key_hash = hash_pub_key(wallet.get_public_key());
+            let (_, outputs) = utxo_set.find_spendable_outputs(pub_key_hash.as_slice(), u64::MAX);
+            let mut outpoints: Vec<(Txid, usize)> = outputs
+                .into_iter()
+                .flat_map(|(txid, outs)| outs.into_iter().map(move |vout| (txid, vout)))
+                .collect();
+            outpoints.sort_unstable();
+            for (txid, vout) in outpoints {
+                let Some(output) = utxo_set.get_output(txid, vout) else {
+                    continue;
+                };
+                inputs.push(TXInput {
+                    txid,
+                    vout,
                     signature: vec![],
                     pub_key: wallet.get_public_key().to_vec(),
-                };
-                inputs.push(input);
+                });
+                spent.push(output);
+                signers.push(wallet);
+            }
+        }
+        if inputs.is_empty() {
+            return Err(TransactionError::NothingToSweep);
+        }
+        if let [output] = spent.as_slice() {
+            if output.is_locked_with_key(to_pub_key_hash.as_slice()) {
+                return Err(TransactionError::AlreadyConsolidated(String::from(to)));
+            }
+        }
+        let available =
+            sum_values(spent.as_slice()).map_err(|_| TransactionError::AmountOverflow)?;
+        let dust_threshold = utxo_set.get_blockchain().get_params().dust_threshold;
+        let amount = available
+            .checked_sub(fee)
+            .filter(|amount| *amount > 0)
+            .ok_or_else(|| TransactionError::InsufficientFunds {
+                available,
+                required: fee.saturating_add(dust_threshold.max(1)),
+            })?;
+        if amount < dust_threshold {
+            return Err(TransactionError::BelowDustThreshold {
+                amount,
+                threshold: dust_threshold,
+            });
+        }
+        let mut tx = Self {
+            id: Txid::default(),
+            vin: inputs,
+            vout: vec![TXOutput::new(amount, to)?],
+        };
+        tx.id = tx.hash();
+        tx.sign_inputs(spent.as_slice(), signers.as_slice())?;
+        Ok(tx)
+    }
+
+    /// Like [`Transaction::new_signed_transaction`], choosing the inputs among
+    /// `unspent`, the outputs a light client knows `from` to hold, instead of looking
+    /// them up in a UTXO set.
+    pub fn new_spending_transaction(
+        from: &str,
+        recipients: &[Recipient],
+        fee: u64,
+        unspent: &[(OutPoint, TXOutput)],
+        dust_threshold: u64,
+        signer: &dyn Signer,
+    ) -> Result<Self, TransactionError> {
+        let public_key = signer.public_key();
+        let public_key_hash = hash_pub_key(public_key.as_slice());
+        if convert_address(public_key_hash.as_slice()) != from {
+            return Err(TransactionError::SignerMismatch(String::from(from)));
+        }
+        let required = required_amount(recipients, fee, dust_threshold)?;
+        let mut accumulated = 0_u64;
+        let mut outpoints = vec![];
+        let mut spent = vec![];
+        for (outpoint, output) in unspent {
+            if accumulated >= required {
+                break;
+            }
+            if output.is_locked_with_key(public_key_hash.as_slice()) {
+                accumulated = accumulated.saturating_add(output.get_value());
+                outpoints.push(*outpoint);
+                spent.push(output.clone());
             }
         }
-        let mut outputs = vec![TXOutput::new(amount, to)];
-        if accumulated > amount {
-            outputs.push(TXOutput::new(accumulated - amount, from));
+        let mut tx = Self::assemble(
+            from,
+            recipients,
+            (accumulated, required),
+            outpoints.as_slice(),
+            dust_threshold,
+            public_key.as_slice(),
+        )?;
+        tx.sign_spending(spent.as_slice(), signer)?;
+        Ok(tx)
+    }
+
+    fn build(
+        from: &str,
+        recipients: &[Recipient],
+        fee: u64,
+        utxo_set: &UTXOSet,
+        public_key: &[u8],
+    ) -> Result<Self, TransactionError> {
+        let dust_threshold = utxo_set.get_blockchain().get_params().dust_threshold;
+        let required = required_amount(recipients, fee, dust_threshold)?;
+        let public_key_hash = hash_pub_key(public_key);
+        let (accumulated, valid_outputs) =
+            utxo_set.find_spendable_outputs(public_key_hash.as_slice(), required);
+        let outpoints: Vec<OutPoint> = valid_outputs
+            .into_iter()
+            .flat_map(|(txid, outs)| outs.into_iter().map(move |vout| OutPoint { txid, vout }))
+            .collect();
+        Self::assemble(
+            from,
+            recipients,
+            (accumulated, required),
+            outpoints.as_slice(),
+            dust_threshold,
+            public_key,
+        )
+    }
+
+    /// Builds the unsigned [Transaction] spending `outpoints`, worth `accumulated`,
+    /// to pay the `required` amount to `recipients`, returning the change to `from`.
+    fn assemble(
+        from: &str,
+        recipients: &[Recipient],
+        (accumulated, required): (u64, u64),
+        outpoints: &[OutPoint],
+        dust_threshold: u64,
+        public_key: &[u8],
+    ) -> Result<Self, TransactionError> {
+        if accumulated < required {
+            return Err(TransactionError::InsufficientFunds {
+                available: accumulated,
+                required,
+            });
+        }
+        let inputs = outpoints
+            .iter()
+            .map(|outpoint| TXInput {
+                txid: outpoint.txid,
+                vout: outpoint.vout,
+                signature: vec![],
+                pub_key: public_key.to_vec(),
+            })
+            .collect();
+        let mut outputs = recipients
+            .iter()
+            .map(|recipient| TXOutput::new(recipient.amount, recipient.address.as_str()))
+            .collect::<Result<Vec<_>, _>>()?;
+        if accumulated - required >= dust_threshold.max(1) {
+            outputs.push(TXOutput::new(accumulated - required, from)?);
         }
         let mut tx = Self {
-            id: vec![],
+            id: Txid::default(),
             vin: inputs,
             vout: outputs,
         };
         tx.id = tx.hash();
-        tx.sign(utxo_set.get_blockchain(), wallet.get_pksc8());
-        tx
+        Ok(tx)
     }
 
     /// Creates a trimmed copy of the [Transaction], excluding signatures, enabling
@@ -171,58 +652,142 @@ impl Transaction {
             outputs.push(output.clone());
         }
         Self {
-            id: self.id.clone(),
+            id: self.id,
             vin: inputs,
             vout: outputs,
         }
     }
 
-    /// Signs the [Transaction] inputs using the Elliptic Curve Digital Signature Algorithm (ECDSA)
-    fn sign(&mut self, blockchain: &Blockchain, pkcs8: &[u8]) {
+    /// Signs every input with the key pair in `pkcs8`, given the [`TXOutput`] each
+    /// input spends in the order of the inputs, so that the key never has to be on a
+    /// machine holding the [Blockchain].
+    ///
+    /// Every spent output must be locked with the key, whose public key the inputs
+    /// get. The id of the [Transaction] changes accordingly.
+    pub fn sign_with(
+        &mut self,
+        pkcs8: &[u8],
+        prev_outputs: &[TXOutput],
+    ) -> Result<(), TransactionError> {
+        if prev_outputs.len() != self.vin.len() {
+            return Err(TransactionError::PrevOutputsMismatch {
+                inputs: self.vin.len(),
+                prev_outputs: prev_outputs.len(),
+            });
+        }
+        let wallet = Wallet::from_pkcs8(SecretBytes::new(pkcs8.to_vec()))?;
+        let pub_key_hash = hash_pub_key(wallet.get_public_key());
+        if let Some(output) = prev_outputs
+            .iter()
+            .find(|output| !output.is_locked_with_key(pub_key_hash.as_slice()))
+        {
+            return Err(TransactionError::SignerMismatch(convert_address(
+                output.get_pub_key_hash(),
+            )));
+        }
+        for vin in &mut self.vin {
+            vin.signature = Vec::new();
+            vin.pub_key = wallet.get_public_key().to_vec();
+        }
+        self.id = self.hash();
+        self.sign_spending(prev_outputs, &wallet)?;
+        Ok(())
+    }
+
+    /// Signs the [Transaction] inputs using the Elliptic Curve Digital Signature Algorithm (ECDSA),
+    /// leaving them untouched unless `signer` signs every one of them.
+    fn sign(
+        &mut self,
+        blockchain: &Blockchain,
+        signer: &dyn Signer,
+    ) -> Result<(), TransactionError> {
+        let spent = self.find_spent(blockchain)?;
+        self.sign_spending(spent.as_slice(), signer)?;
+        Ok(())
+    }
+
+    /// Looks up the [`TXOutput`] each input spends in the [Blockchain], in the order
+    /// of the inputs.
+    fn find_spent(&self, blockchain: &Blockchain) -> Result<Vec<TXOutput>, TransactionError> {
+        self.vin
+            .iter()
+            .map(|vin| {
+                blockchain
+                    .find_transaction(vin.get_txid())
+                    .and_then(|prev_tx| prev_tx.vout.get(vin.vout).cloned())
+                    .ok_or_else(|| {
+                        TransactionError::UnknownOutput(OutPoint {
+                            txid: vin.get_txid(),
+                            vout: vin.get_vout(),
+                        })
+                    })
+            })
+            .collect()
+    }
+
+    /// Like [`Transaction::sign`], given the [`TXOutput`] each input spends, in the
+    /// order of the inputs, instead of looking them up in the [Blockchain].
+    fn sign_spending(
+        &mut self,
+        spent: &[TXOutput],
+        signer: &dyn Signer,
+    ) -> Result<(), SignerError> {
+        self.sign_inputs(spent, vec![signer; spent.len()].as_slice())
+    }
+
+    /// Like [`Transaction::sign_spending`], signing each input with the [Signer] at
+    /// its index in `signers`, so that inputs may spend from different keys.
+    fn sign_inputs(
+        &mut self,
+        spent: &[TXOutput],
+        signers: &[&dyn Signer],
+    ) -> Result<(), SignerError> {
         let mut tx_copy = self.trimmed_copy();
-        for (idx, vin) in self.vin.iter_mut().enumerate() {
-            let prev_tx_option = blockchain.find_transaction(vin.get_txid());
-            assert!(
-                prev_tx_option.is_some(),
-                "Error: previous transaction is not correct"
-            );
-            let prev_tx = prev_tx_option.unwrap();
+        let mut signatures = Vec::with_capacity(self.vin.len());
+        for (idx, (prev_out, signer)) in spent.iter().zip(signers).enumerate() {
             tx_copy.vin[idx].signature = Vec::new();
-            tx_copy.vin[idx]
-                .pub_key
-                .clone_from(&prev_tx.vout[vin.vout].pub_key_hash);
+            tx_copy.vin[idx].pub_key.clone_from(&prev_out.pub_key_hash);
             tx_copy.id = tx_copy.hash();
             tx_copy.vin[idx].pub_key = Vec::new();
-            vin.signature = crate::ecdsa_p256_sha256_sign_digest(pkcs8, tx_copy.get_id());
+            signatures.push(signer.sign(tx_copy.get_id().as_bytes())?);
         }
+        for (vin, signature) in self.vin.iter_mut().zip(signatures) {
+            vin.signature = signature;
+        }
+        Ok(())
     }
 
     /// Verifies the [Transaction] signatures against corresponding public keys. Checks for
     /// Coinbase transactions, prepares a trimmed copy, validates signatures against public
     /// keys, and ensures the correctness of previous transactions before confirming the
-    /// authority of signatures.
-    pub fn verify(&self, blockchain: &Blockchain) -> bool {
+    /// authority of signatures. Fails when an input spends an output that doesn't exist.
+    pub fn verify(&self, blockchain: &Blockchain) -> Result<bool, TransactionError> {
+        if self.is_coinbase() {
+            return Ok(true);
+        }
+        let spent = self.find_spent(blockchain)?;
+        Ok(self.verify_spending(spent.as_slice()))
+    }
+
+    /// Like [`Transaction::verify`], given the [`TXOutput`] each input spends, in the
+    /// order of the inputs, instead of looking them up in the [Blockchain].
+    pub fn verify_spending(&self, spent: &[TXOutput]) -> bool {
         if self.is_coinbase() {
             return true;
         }
+        if spent.len() != self.vin.len() {
+            return false;
+        }
         let mut tx_copy = self.trimmed_copy();
-        for (idx, vin) in self.vin.iter().enumerate() {
-            let prev_tx_option = blockchain.find_transaction(vin.get_txid());
-            assert!(
-                prev_tx_option.is_some(),
-                "Error: previous transaction is not correct"
-            );
-            let prev_tx = prev_tx_option.unwrap();
+        for (idx, (vin, prev_out)) in self.vin.iter().zip(spent).enumerate() {
             tx_copy.vin[idx].signature = Vec::new();
-            tx_copy.vin[idx]
-                .pub_key
-                .clone_from(&prev_tx.vout[vin.vout].pub_key_hash);
+            tx_copy.vin[idx].pub_key.clone_from(&prev_out.pub_key_hash);
             tx_copy.id = tx_copy.hash();
             tx_copy.vin[idx].pub_key = Vec::new();
             let verify = crate::ecdsa_p256_sha256_sign_verify(
                 vin.pub_key.as_slice(),
                 vin.signature.as_slice(),
-                tx_copy.get_id(),
+                tx_copy.get_id().as_bytes(),
             );
             if !verify {
                 return false;
@@ -231,34 +796,85 @@ impl Transaction {
         true
     }
 
-    /// Checks whether the [Transaction] is a Coinbase transaction.
+    /// Adds up the values of the outputs, failing when the total doesn't fit in a `u64`.
+    pub fn get_output_value(&self) -> Result<u64, ValueError> {
+        sum_values(&self.vout)
+    }
+
+    /// Returns the fee of the [Transaction], what the [`TXOutput`] its inputs spend are
+    /// worth beyond its own outputs, given in the order of the inputs. A Coinbase
+    /// transaction spends nothing and pays no fee.
+    pub fn get_fee(&self, spent: &[TXOutput]) -> Result<u64, ValueError> {
+        let outputs = self.get_output_value()?;
+        if self.is_coinbase() {
+            return Ok(0);
+        }
+        let inputs = sum_values(spent)?;
+        if outputs > inputs {
+            return Err(ValueError::OutputsExceedInputs { inputs, outputs });
+        }
+        Ok(inputs - outputs)
+    }
+
+    /// Returns the fee the [Transaction] pays, looking up the outputs its inputs spend
+    /// in `utxo_set`. `None` when one of them isn't there or the values don't add up.
+    pub fn fee(&self, utxo_set: &UTXOSet) -> Option<u64> {
+        if self.is_coinbase() {
+            return Some(0);
+        }
+        let spent = self
+            .vin
+            .iter()
+            .map(|input| utxo_set.get_output(input.get_txid(), input.get_vout()))
+            .collect::<Option<Vec<TXOutput>>>()?;
+        self.get_fee(&spent).ok()
+    }
+
+    /// Checks whether the [Transaction] is a Coinbase transaction: a single input
+    /// without a key, spending no output.
     pub fn is_coinbase(&self) -> bool {
-        self.vin.len() == 1 && self.vin[0].pub_key.is_empty()
+        self.vin.len() == 1 && self.vin[0].pub_key.is_empty() && self.vin[0].txid == Txid::default()
+    }
+
+    /// Checks that the id is the hash of the [Transaction], so that the id, which the
+    /// Merkle root of a [Block](crate::block::Block) commits to, commits to every
+    /// input and output in turn.
+    pub fn has_valid_id(&self) -> bool {
+        self.id == self.hash()
     }
 
     /// Generates the [Transaction]'s SHA256 hash.
-    fn hash(&self) -> Vec<u8> {
-        let tx_copy = Self {
-            id: vec![],
-            vin: self.vin.clone(),
-            vout: self.vout.clone(),
+    ///
+    /// The signatures are left out, as the id they sign is computed before them, except
+    /// for the random bytes in place of the signature of a Coinbase, which tell apart
+    /// two Coinbases paying the same reward to the same address.
+    fn hash(&self) -> Txid {
+        let view = TransactionView {
+            id: Txid::default(),
+            vin: InputsView {
+                inputs: self.vin.as_slice(),
+                signed: self.is_coinbase(),
+            },
+            vout: self.vout.as_slice(),
         };
-        crate::sha256_digest(tx_copy.serialize().as_slice())
+        let mut context = crate::Sha256::new();
+        bincode::serialize_into(&mut context, &view).unwrap();
+        Txid::from(context.finish())
     }
 
-    pub fn get_id(&self) -> &[u8] {
-        self.id.as_slice()
+    pub const fn get_id(&self) -> Txid {
+        self.id
     }
 
     pub fn get_id_bytes(&self) -> Vec<u8> {
-        self.id.clone()
+        self.id.to_vec()
     }
 
-    pub fn get_vin(&self) -> &[TXInput] {
+    pub const fn get_vin(&self) -> &[TXInput] {
         self.vin.as_slice()
     }
 
-    pub fn get_vout(&self) -> &[TXOutput] {
+    pub const fn get_vout(&self) -> &[TXOutput] {
         self.vout.as_slice()
     }
 
@@ -269,4 +885,402 @@ impl Transaction {
     pub fn deserialize(bytes: &[u8]) -> Self {
         bincode::deserialize(bytes).unwrap()
     }
+
+    /// Decodes a [Transaction] serialized in hex, as printed by `send --dry-run --raw`.
+    ///
+    /// Unlike [`Transaction::deserialize`] the input is untrusted, so lengths inside
+    /// it may not claim more bytes than were given.
+    pub fn from_hex(hex: &str) -> Result<Self, DecodeError> {
+        let bytes = HEXLOWER_PERMISSIVE
+            .decode(hex.trim().as_bytes())
+            .map_err(|_| DecodeError::NotHex)?;
+        Self::from_bytes(bytes.as_slice())
+    }
+
+    /// Deserializes a [Transaction] from untrusted bytes, such as those sent by a peer.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        bincode::options()
+            .with_fixint_encoding()
+            .with_limit(bytes.len() as u64)
+            .deserialize(bytes)
+            .map_err(|_| DecodeError::Malformed)
+    }
+}
+
+/// Builds an unsigned [Transaction] spending `inputs` and paying every [Recipient],
+/// to be signed with [`Transaction::sign_with`].
+///
+/// Nothing is looked up, so whether the inputs exist and cover the outputs is only
+/// checked once the [Transaction] is sent.
+pub fn create_raw_transaction(
+    inputs: &[OutPoint],
+    outputs: &[Recipient],
+) -> Result<Transaction, TransactionError> {
+    let vout = outputs
+        .iter()
+        .map(|recipient| TXOutput::new(recipient.amount, recipient.address.as_str()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut tx = Transaction {
+        id: Txid::default(),
+        vin: inputs
+            .iter()
+            .map(|input| TXInput::new(input.txid, input.vout))
+            .collect(),
+        vout,
+    };
+    tx.id = tx.hash();
+    Ok(tx)
+}
+
+/// Checks that every [Recipient] is paid at least `dust_threshold`, returning the
+/// amount the inputs must cover to pay them all and the `fee`.
+fn required_amount(
+    recipients: &[Recipient],
+    fee: u64,
+    dust_threshold: u64,
+) -> Result<u64, TransactionError> {
+    if recipients.iter().any(|recipient| recipient.amount == 0) {
+        return Err(TransactionError::ZeroAmount);
+    }
+    if let Some(recipient) = recipients
+        .iter()
+        .find(|recipient| recipient.amount < dust_threshold)
+    {
+        return Err(TransactionError::BelowDustThreshold {
+            amount: recipient.amount,
+            threshold: dust_threshold,
+        });
+    }
+    recipients
+        .iter()
+        .try_fold(fee, |total, recipient| total.checked_add(recipient.amount))
+        .ok_or(TransactionError::AmountOverflow)
+}
+
+/// Decodes a raw [Transaction] from untrusted bytes, signed or not.
+pub fn decode_raw_transaction(bytes: &[u8]) -> Result<Transaction, DecodeError> {
+    Transaction::from_bytes(bytes)
+}
+
+fn sum_values(outputs: &[TXOutput]) -> Result<u64, ValueError> {
+    outputs.iter().try_fold(0_u64, |total, output| {
+        total
+            .checked_add(output.value)
+            .ok_or(ValueError::OutOfRange)
+    })
+}
+
+/// Looks up the key of `from` in `wallets`.
+fn local_wallet(wallets: &WalletStore, from: &str) -> Result<Wallet, TransactionError> {
+    if wallets.is_watch_only(from) {
+        return Err(TransactionError::WatchOnly(String::from(from)));
+    }
+    wallets
+        .get_wallet(from)
+        .ok_or_else(|| TransactionError::WalletNotFound(String::from(from)))
+}
+
+#[cfg(feature = "grpc")]
+impl From<&Transaction> for crate::grpc::pb::Transaction {
+    fn from(tx: &Transaction) -> Self {
+        Self {
+            id: tx.id.to_vec(),
+            vin: tx
+                .vin
+                .iter()
+                .map(|input| crate::grpc::pb::TxInput {
+                    txid: input.txid.to_vec(),
+                    vout: input.vout as u64,
+                    signature: input.signature.clone(),
+                    pub_key: input.pub_key.clone(),
+                })
+                .collect(),
+            vout: tx
+                .vout
+                .iter()
+                .map(|out| crate::grpc::pb::TxOutput {
+                    value: out.value,
+                    pub_key_hash: out.pub_key_hash.clone(),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(feature = "grpc")]
+impl From<crate::grpc::pb::Transaction> for Transaction {
+    /// An input index too large for this platform is kept as `usize::MAX`, which no
+    /// output has, so the [Transaction] is refused as spending an unknown output.
+    /// An id of the wrong length is read as all zeros.
+    fn from(tx: crate::grpc::pb::Transaction) -> Self {
+        Self {
+            id: Txid::from_slice(tx.id.as_slice()).unwrap_or_default(),
+            vin: tx
+                .vin
+                .into_iter()
+                .map(|input| TXInput {
+                    txid: Txid::from_slice(input.txid.as_slice()).unwrap_or_default(),
+                    vout: usize::try_from(input.vout).unwrap_or(usize::MAX),
+                    signature: input.signature,
+                    pub_key: input.pub_key,
+                })
+                .collect(),
+            vout: tx
+                .vout
+                .into_iter()
+                .map(|out| TXOutput {
+                    value: out.value,
+                    pub_key_hash: out.pub_key_hash,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::blockchain::BlockError;
+    use crate::clock::system_clock;
+    use crate::test_utils::{coinbase, mine, pay, temp_chain};
+    use crate::utxo_set::SpendError;
+
+    /// Hashes a copy of `tx` with a zero id and, unless it is a Coinbase, no signatures.
+    fn hash_of_unsigned_copy(tx: &Transaction) -> Txid {
+        let mut copy = tx.clone();
+        copy.id = Txid::default();
+        if !copy.is_coinbase() {
+            for input in &mut copy.vin {
+                input.signature.clear();
+            }
+        }
+        let digest = crate::sha256_digest(copy.serialize().as_slice());
+        Txid::from_slice(digest.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn ids_hash_the_transaction_without_its_signatures() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let tx = pay(&blockchain, &miner, &Wallet::new(), 3);
+        assert!(tx.get_vin().iter().all(|input| !input.signature.is_empty()));
+        for tx in [tx, coinbase(&miner, 1)] {
+            assert_eq!(tx.get_id(), hash_of_unsigned_copy(&tx));
+            assert_eq!(tx.hash(), tx.get_id());
+        }
+    }
+
+    /// An output paying `value` to a new address.
+    fn output(value: u64) -> TXOutput {
+        TXOutput::new(value, Wallet::new().get_address().as_str()).unwrap()
+    }
+
+    /// Replaces the outputs of `tx` with `vout`, then gives it the matching id and
+    /// signs it again with `from`.
+    fn with_outputs(
+        blockchain: &Blockchain,
+        tx: &Transaction,
+        vout: Vec<TXOutput>,
+        from: &Wallet,
+    ) -> Transaction {
+        let mut tx = tx.clone();
+        tx.vout = vout;
+        tx.id = tx.hash();
+        tx.sign(blockchain, from).unwrap();
+        tx
+    }
+
+    #[test]
+    fn fee_is_what_the_inputs_spend_beyond_the_outputs() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let tx = pay(&blockchain, &miner, &Wallet::new(), 3);
+        let outputs = tx.get_output_value().unwrap();
+        assert_eq!(tx.get_fee(&[output(outputs)]), Ok(0));
+        assert_eq!(tx.get_fee(&[output(outputs + 2)]), Ok(2));
+        assert_eq!(
+            tx.get_fee(&[output(outputs - 1)]),
+            Err(ValueError::OutputsExceedInputs {
+                inputs: outputs - 1,
+                outputs
+            })
+        );
+        assert_eq!(coinbase(&miner, 1).get_fee(&[]), Ok(0));
+    }
+
+    #[test]
+    fn values_past_u64_max_are_out_of_range() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let mut tx = pay(&blockchain, &miner, &Wallet::new(), 3);
+        assert_eq!(
+            tx.get_fee(&[output(u64::MAX), output(1)]),
+            Err(ValueError::OutOfRange)
+        );
+        tx.vout = vec![output(u64::MAX)];
+        assert_eq!(tx.get_output_value(), Ok(u64::MAX));
+        assert_eq!(tx.get_fee(&[output(u64::MAX)]), Ok(0));
+        tx.vout.push(output(1));
+        assert_eq!(tx.get_output_value(), Err(ValueError::OutOfRange));
+        assert_eq!(tx.get_fee(&[output(u64::MAX)]), Err(ValueError::OutOfRange));
+    }
+
+    #[test]
+    fn signed_transaction_creating_value_is_refused() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let utxo_set = UTXOSet::new(blockchain.clone());
+        let tx = pay(&blockchain, &miner, &Wallet::new(), 3);
+        let inputs = tx.get_output_value().unwrap() + tx.fee(&utxo_set).unwrap();
+
+        let exact = with_outputs(&blockchain, &tx, vec![output(inputs)], &miner);
+        assert_eq!(utxo_set.validate_tx(&exact), Ok(0));
+        let inflating = with_outputs(&blockchain, &tx, vec![output(inputs + 1)], &miner);
+        assert!(inflating.verify(&blockchain).unwrap());
+        assert_eq!(
+            utxo_set.validate_tx(&inflating),
+            Err(SpendError::Value(ValueError::OutputsExceedInputs {
+                inputs,
+                outputs: inputs + 1
+            }))
+        );
+        let overflowing = vec![output(u64::MAX), output(inputs)];
+        let overflowing = with_outputs(&blockchain, &tx, overflowing, &miner);
+        assert_eq!(
+            utxo_set.validate_tx(&overflowing),
+            Err(SpendError::Value(ValueError::OutOfRange))
+        );
+
+        let genesis = blockchain.get_block(blockchain.get_tip_hash()).unwrap();
+        let block = Block::new(
+            genesis.get_hash(),
+            vec![inflating.clone(), coinbase(&miner, 0)],
+            1,
+            blockchain.get_next_target_bits(&genesis),
+            &*system_clock(),
+        );
+        assert_eq!(
+            blockchain.validate_block(&block),
+            Err(BlockError::InvalidTransaction(inflating.get_id()))
+        );
+    }
+
+    /// Builds a [Transaction] from `miner` paying `recipients` and `fee`.
+    fn send(
+        blockchain: &Blockchain,
+        miner: &Wallet,
+        recipients: &[Recipient],
+        fee: u64,
+    ) -> Result<Transaction, TransactionError> {
+        let utxo_set = UTXOSet::new(blockchain.clone());
+        let from = miner.get_address();
+        Transaction::new_signed_transaction(from.as_str(), recipients, fee, &utxo_set, miner)
+    }
+
+    #[test]
+    fn zero_and_overflowing_amounts_are_refused() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let to = Wallet::new().get_address();
+        let to_recipient = |amount| Recipient {
+            address: to.clone(),
+            amount,
+        };
+        assert_eq!(
+            TXOutput::new(0, to.as_str()).map(|output| output.get_value()),
+            Err(TransactionError::ZeroAmount)
+        );
+        assert_eq!(
+            TXOutput::new(u64::MAX, to.as_str()).map(|output| output.get_value()),
+            Ok(u64::MAX)
+        );
+        assert_eq!(
+            send(&blockchain, &miner, &[to_recipient(0)], 1).map(|tx| tx.get_id()),
+            Err(TransactionError::ZeroAmount)
+        );
+        let overflowing = [to_recipient(u64::MAX), to_recipient(1)];
+        assert_eq!(
+            send(&blockchain, &miner, &overflowing, 0).map(|tx| tx.get_id()),
+            Err(TransactionError::AmountOverflow)
+        );
+        assert_eq!(
+            send(&blockchain, &miner, &[to_recipient(1)], u64::MAX).map(|tx| tx.get_id()),
+            Err(TransactionError::AmountOverflow)
+        );
+    }
+
+    #[test]
+    fn amounts_are_parsed_as_positive_whole_numbers() {
+        let to = Wallet::new().get_address();
+        let parse = |amount: &str| {
+            format!("{to}:{amount}")
+                .parse::<Recipient>()
+                .map(|recipient| recipient.amount)
+        };
+        assert_eq!(parse("1"), Ok(1));
+        assert_eq!(parse("18446744073709551615"), Ok(u64::MAX));
+        for refused in [
+            "0",
+            "18446744073709551616",
+            "1.5",
+            "1.",
+            "-5",
+            "+5",
+            "1e3",
+            "",
+        ] {
+            assert!(parse(refused).is_err(), "`{refused}` was accepted");
+        }
+    }
+
+    #[test]
+    fn raw_transaction_signed_from_hex_confirms_on_chain() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let genesis = blockchain.get_block_by_height(0).unwrap();
+        let spent = genesis.get_transactions()[0].clone();
+        let input = OutPoint {
+            txid: spent.get_id(),
+            vout: 0,
+        };
+        let recipient = Recipient {
+            address: Wallet::new().get_address(),
+            amount: 9,
+        };
+        let unsigned = create_raw_transaction(&[input], &[recipient]).unwrap();
+        let hex = HEXLOWER_PERMISSIVE.encode(unsigned.serialize().as_slice());
+
+        let mut decoded = Transaction::from_hex(hex.as_str()).unwrap();
+        assert_eq!(
+            blockchain
+                .mine_block(vec![decoded.clone(), coinbase(&miner, 1)])
+                .err(),
+            Some(BlockError::InvalidTransaction(decoded.get_id()))
+        );
+        decoded
+            .sign_with(miner.get_pksc8(), spent.get_vout())
+            .unwrap();
+        let hex = HEXLOWER_PERMISSIVE.encode(decoded.serialize().as_slice());
+        let bytes = HEXLOWER_PERMISSIVE.decode(hex.as_bytes()).unwrap();
+        let signed = decode_raw_transaction(bytes.as_slice()).unwrap();
+        assert_eq!(signed.get_vin()[0].get_txid(), spent.get_id());
+
+        mine(&blockchain, vec![signed.clone()], &miner);
+        let confirmed = blockchain.find_transaction(signed.get_id()).unwrap();
+        assert_eq!(confirmed.serialize(), signed.serialize());
+    }
+
+    #[test]
+    fn largest_amount_survives_serialization() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let mut tx = pay(&blockchain, &miner, &Wallet::new(), 3);
+        tx.vout = vec![output(u64::MAX)];
+        let read = Transaction::deserialize(tx.serialize().as_slice());
+        assert_eq!(read.get_output_value(), Ok(u64::MAX));
+        let json = serde_json::to_string(&tx).unwrap();
+        let read: Transaction = serde_json::from_str(json.as_str()).unwrap();
+        assert_eq!(read.get_output_value(), Ok(u64::MAX));
+    }
 }
@@ -0,0 +1,155 @@
+use std::fmt::{self, Debug, Display, Formatter};
+use std::str::FromStr;
+
+use data_encoding::{HEXLOWER, HEXLOWER_PERMISSIVE};
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize, Serializer};
+
+/// The length in bytes of a [Txid] or a [`BlockHash`], a SHA-256 digest.
+pub const HASH_LEN: usize = 32;
+
+/// The reasons bytes or a hex string can't be read as a [Txid] or a [`BlockHash`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashError {
+    InvalidHex,
+    /// The hash is this many bytes long instead of [`HASH_LEN`].
+    WrongLength(usize),
+}
+
+impl Display for HashError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidHex => write!(f, "the hash is not valid hex"),
+            Self::WrongLength(len) => {
+                write!(f, "the hash is {len} bytes long, expected {HASH_LEN}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for HashError {}
+
+/// Implements the accessors, the hex [Display] and [`FromStr`] and the conversions
+/// shared by the hash newtypes.
+macro_rules! hash_newtype {
+    ($name:ident) => {
+        impl $name {
+            /// Reads a hash from exactly [`HASH_LEN`] bytes.
+            pub fn from_slice(bytes: &[u8]) -> Result<Self, HashError> {
+                <[u8; HASH_LEN]>::try_from(bytes)
+                    .map(Self)
+                    .map_err(|_| HashError::WrongLength(bytes.len()))
+            }
+
+            pub const fn as_bytes(&self) -> &[u8; HASH_LEN] {
+                &self.0
+            }
+
+            pub fn to_vec(self) -> Vec<u8> {
+                self.0.to_vec()
+            }
+
+            /// Checks whether every byte is zero, as in a hash that was never set.
+            pub fn is_null(&self) -> bool {
+                self.0 == [0; HASH_LEN]
+            }
+        }
+
+        impl Display for $name {
+            fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                f.write_str(HEXLOWER.encode(&self.0).as_str())
+            }
+        }
+
+        impl Debug for $name {
+            fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+                write!(f, "{}({self})", stringify!($name))
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = HashError;
+
+            /// Parses lower or upper case hex.
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                let bytes = HEXLOWER_PERMISSIVE
+                    .decode(s.as_bytes())
+                    .map_err(|_| HashError::InvalidHex)?;
+                Self::from_slice(bytes.as_slice())
+            }
+        }
+
+        impl From<[u8; HASH_LEN]> for $name {
+            fn from(bytes: [u8; HASH_LEN]) -> Self {
+                Self(bytes)
+            }
+        }
+
+        impl AsRef<[u8]> for $name {
+            fn as_ref(&self) -> &[u8] {
+                &self.0
+            }
+        }
+
+        impl TryFrom<&[u8]> for $name {
+            type Error = HashError;
+
+            fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+                Self::from_slice(bytes)
+            }
+        }
+    };
+}
+
+/// The id of a [Transaction](crate::transactions::Transaction), the SHA-256 digest of
+/// its contents.
+///
+/// Serialized as a byte sequence, like the `Vec<u8>` it replaces.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct Txid([u8; HASH_LEN]);
+
+hash_newtype!(Txid);
+
+impl Serialize for Txid {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.0.as_slice().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Txid {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = Vec::<u8>::deserialize(deserializer)?;
+        Self::from_slice(bytes.as_slice()).map_err(de::Error::custom)
+    }
+}
+
+/// The hash of a [Block](crate::block::Block), which the proof of work brings below
+/// the target.
+///
+/// Serialized as lower case hex in human readable formats such as JSON, and as the
+/// raw 32 bytes otherwise, so that stored [Block](crate::block::Block)s stay compact.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Default)]
+pub struct BlockHash([u8; HASH_LEN]);
+
+hash_newtype!(BlockHash);
+
+impl Serialize for BlockHash {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(self)
+        } else {
+            self.0.serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for BlockHash {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let hex = String::deserialize(deserializer)?;
+            hex.parse().map_err(de::Error::custom)
+        } else {
+            <[u8; HASH_LEN]>::deserialize(deserializer).map(Self)
+        }
+    }
+}
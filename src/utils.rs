@@ -1,36 +1,153 @@
-use std::iter::repeat;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::fmt::{self, Debug, Display, Formatter};
+use std::hint;
+use std::io;
 
-use crypto::digest::Digest;
+use p256::ecdsa::{signature::Signer as _, SigningKey};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use p256::pkcs8::DecodePrivateKey;
 use ring::digest::{Context, SHA256};
-use ring::rand::SystemRandom;
-use ring::signature::{EcdsaKeyPair, ECDSA_P256_SHA256_FIXED, ECDSA_P256_SHA256_FIXED_SIGNING};
+use ring::rand::{SecureRandom, SystemRandom};
+use ring::signature::{
+    EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED, ECDSA_P256_SHA256_FIXED_SIGNING,
+};
+use ripemd::{Digest, Ripemd160};
+use serde::{Deserialize, Serialize};
+use zeroize::Zeroize;
+
+/// The length of a RIPEMD-160 digest, and so of a [`hash160`].
+pub const RIPEMD160_LEN: usize = 20;
 
 /// Retrieves the current timestamp as an integer representing milliseconds since the Unix epoch.
+///
+/// Reads the [`system_clock`](crate::clock::system_clock), so it doesn't go backwards
+/// when the system clock does.
 pub fn current_timestamp() -> i64 {
-    SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("time went backwards")
-        .as_millis()
-        .try_into()
-        .unwrap()
+    crate::clock::system_clock().now()
+}
+
+/// Formats `timestamp`, in milliseconds since the Unix epoch, as a UTC date and time
+/// such as `2024-03-01 12:00:00 UTC`.
+pub fn format_timestamp(timestamp: i64) -> String {
+    let secs = timestamp.div_euclid(1000);
+    let (days, secs) = (secs.div_euclid(86_400), secs.rem_euclid(86_400));
+    // Converts days since the epoch to a civil date, after Howard Hinnant's
+    // `civil_from_days`.
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02} UTC",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
 }
 
 /// Performs a SHA-256 hash operation on the input.
 pub fn sha256_digest(data: &[u8]) -> Vec<u8> {
-    let mut context = Context::new(&SHA256);
+    let mut context = Sha256::new();
+    context.update(data);
+    context.finish().to_vec()
+}
+
+/// Hashes the input with SHA-256 twice.
+pub fn sha256d(data: &[u8]) -> [u8; 32] {
+    let mut context = Sha256::new();
+    context.update(data);
+    let mut outer = Sha256::new();
+    outer.update(&context.finish());
+    outer.finish()
+}
+
+/// Hashes the input with SHA-256 prefixed by the SHA-256 hash of `tag` twice, as
+/// BIP 340 does, so that hashes made for one purpose never match those made for another.
+pub fn tagged_hash(tag: &str, data: &[u8]) -> [u8; 32] {
+    let mut tag_context = Sha256::new();
+    tag_context.update(tag.as_bytes());
+    let tag_hash = tag_context.finish();
+    let mut context = Sha256::new();
+    context.update(&tag_hash);
+    context.update(&tag_hash);
     context.update(data);
-    let digest = context.finish();
-    digest.as_ref().to_vec()
+    context.finish()
 }
 
 /// Calculates the RIPEMD-160 hash of the input.
 pub fn ripemd160_digest(data: &[u8]) -> Vec<u8> {
-    let mut ripemd160 = crypto::ripemd160::Ripemd160::new();
-    ripemd160.input(data);
-    let mut buf: Vec<u8> = repeat(0).take(ripemd160.output_bytes()).collect();
-    ripemd160.result(&mut buf);
-    buf
+    Ripemd160::digest(data).to_vec()
+}
+
+/// Calculates the RIPEMD-160 hash of the SHA-256 hash of the input, the hash
+/// addresses are made of.
+pub fn hash160(data: &[u8]) -> Vec<u8> {
+    let mut context = Hash160::new();
+    context.update(data);
+    context.finish().to_vec()
+}
+
+/// A SHA-256 computation over input given in any number of pieces, so that large
+/// inputs don't have to be buffered.
+#[derive(Clone)]
+pub struct Sha256(Context);
+
+impl Sha256 {
+    pub fn new() -> Self {
+        Self(Context::new(&SHA256))
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    pub fn finish(self) -> [u8; 32] {
+        self.0.finish().as_ref().try_into().unwrap()
+    }
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl io::Write for Sha256 {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A [`hash160`] computation over input given in any number of pieces.
+#[derive(Clone, Default)]
+pub struct Hash160(Sha256);
+
+impl Hash160 {
+    pub fn new() -> Self {
+        Self(Sha256::new())
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    pub fn finish(self) -> [u8; RIPEMD160_LEN] {
+        Ripemd160::digest(self.0.finish()).into()
+    }
 }
 
 /// Encodes a slice of bytes using the Base58 encoding scheme.
@@ -38,28 +155,387 @@ pub fn base58_encode(data: &[u8]) -> String {
     bs58::encode(data).into_string()
 }
 
+/// The reasons a string can't be decoded as Base58.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Base58Error {
+    /// The character at `index` is not in the Base58 alphabet, e.g. `0` or `l`.
+    InvalidCharacter {
+        character: char,
+        index: usize,
+    },
+    /// The character starting at byte `index` is not ASCII.
+    NonAsciiCharacter {
+        index: usize,
+    },
+    Malformed,
+}
+
+impl Display for Base58Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidCharacter { character, index } => write!(
+                f,
+                "`{character}` at position {index} is not a Base58 character"
+            ),
+            Self::NonAsciiCharacter { index } => {
+                write!(f, "the character at position {index} is not ASCII")
+            }
+            Self::Malformed => write!(f, "the string is not valid Base58"),
+        }
+    }
+}
+
+impl std::error::Error for Base58Error {}
+
 /// Decodes a Base58 encoded string back into it's original byte representation.
-pub fn base58_decode(data: &str) -> Vec<u8> {
-    bs58::decode(data).into_vec().unwrap()
+pub fn base58_decode(data: &str) -> Result<Vec<u8>, Base58Error> {
+    bs58::decode(data).into_vec().map_err(|e| match e {
+        bs58::decode::Error::InvalidCharacter { character, index } => {
+            Base58Error::InvalidCharacter { character, index }
+        }
+        bs58::decode::Error::NonAsciiCharacter { index } => {
+            Base58Error::NonAsciiCharacter { index }
+        }
+        _ => Base58Error::Malformed,
+    })
+}
+
+/// The characters of the Bech32 alphabet, in the order of the 5-bit values they encode.
+const BECH32_CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+const BECH32_GENERATOR: [u32; 5] = [
+    0x3b6a_57b2,
+    0x2650_8e6d,
+    0x1ea1_19fa,
+    0x3d42_33dd,
+    0x2a14_62b3,
+];
+const BECH32_SEPARATOR: char = '1';
+const BECH32_CHECKSUM_LEN: usize = 6;
+/// The longest Bech32 string BIP-173 allows.
+const BECH32_MAX_LEN: usize = 90;
+
+/// The reasons a string can't be decoded as Bech32.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bech32Error {
+    /// There is no `1` between the human-readable part and the data.
+    MissingSeparator,
+    EmptyHrp,
+    /// The character at `index` is outside the printable ASCII range in the
+    /// human-readable part, or not in the Bech32 alphabet in the data.
+    InvalidCharacter {
+        character: char,
+        index: usize,
+    },
+    /// Upper and lower case letters are mixed.
+    MixedCase,
+    /// The string is longer than 90 characters.
+    TooLong(usize),
+    /// The data is shorter than the 6 character checksum.
+    TooShort,
+    InvalidChecksum,
+    /// The data doesn't convert between 5 and 8-bit groups, because of leftover bits
+    /// or values wider than the group size.
+    InvalidPadding,
+}
+
+impl Display for Bech32Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingSeparator => write!(f, "the separator `1` is missing"),
+            Self::EmptyHrp => write!(f, "the human-readable part is empty"),
+            Self::InvalidCharacter { character, index } => write!(
+                f,
+                "`{}` at position {index} is not a Bech32 character",
+                character.escape_default()
+            ),
+            Self::MixedCase => write!(f, "upper and lower case letters are mixed"),
+            Self::TooLong(len) => write!(
+                f,
+                "{len} characters is longer than the {BECH32_MAX_LEN} Bech32 allows"
+            ),
+            Self::TooShort => write!(f, "the checksum is incomplete"),
+            Self::InvalidChecksum => write!(f, "the checksum doesn't match"),
+            Self::InvalidPadding => write!(f, "the data has leftover bits"),
+        }
+    }
+}
+
+impl std::error::Error for Bech32Error {}
+
+fn bech32_polymod(values: impl IntoIterator<Item = u8>) -> u32 {
+    let mut chk: u32 = 1;
+    for value in values {
+        let top = chk >> 25;
+        chk = ((chk & 0x01ff_ffff) << 5) ^ u32::from(value);
+        for (i, generator) in BECH32_GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                chk ^= generator;
+            }
+        }
+    }
+    chk
+}
+
+/// Expands the human-readable part for the checksum into the high bits of every
+/// character, a zero, then the low bits.
+fn bech32_hrp_expand(hrp: &[u8]) -> impl Iterator<Item = u8> + '_ {
+    let high = hrp.iter().map(|c| c >> 5);
+    let low = hrp.iter().map(|c| c & 31);
+    high.chain([0]).chain(low)
+}
+
+fn bech32_checksum(hrp: &[u8], data: &[u8]) -> [u8; BECH32_CHECKSUM_LEN] {
+    let values = bech32_hrp_expand(hrp)
+        .chain(data.iter().copied())
+        .chain([0; BECH32_CHECKSUM_LEN]);
+    let polymod = bech32_polymod(values) ^ 1;
+    let mut checksum = [0; BECH32_CHECKSUM_LEN];
+    for (i, value) in checksum.iter_mut().enumerate() {
+        // Masked to 5 bits, so the truncation is lossless.
+        #[allow(clippy::cast_possible_truncation)]
+        let bits = ((polymod >> (5 * (5 - i))) & 31) as u8;
+        *value = bits;
+    }
+    checksum
+}
+
+/// Regroups `data` from `from` to `to` bit values, e.g. bytes into the 5-bit values
+/// of [`bech32_encode`] and back, padding the last group with zeros when `pad`.
+pub fn convert_bits(data: &[u8], from: u32, to: u32, pad: bool) -> Result<Vec<u8>, Bech32Error> {
+    let max = (1u32 << to) - 1;
+    let mut acc: u32 = 0;
+    let mut bits = 0;
+    let mut converted = Vec::with_capacity(data.len() * from as usize / to as usize + 1);
+    for &value in data {
+        if u32::from(value) >> from != 0 {
+            return Err(Bech32Error::InvalidPadding);
+        }
+        acc = (acc << from) | u32::from(value);
+        bits += from;
+        while bits >= to {
+            bits -= to;
+            // Masked to at most 8 bits, so the truncation is lossless.
+            #[allow(clippy::cast_possible_truncation)]
+            converted.push(((acc >> bits) & max) as u8);
+        }
+    }
+    if pad {
+        if bits > 0 {
+            #[allow(clippy::cast_possible_truncation)]
+            converted.push(((acc << (to - bits)) & max) as u8);
+        }
+    } else if bits >= from || (acc << (to - bits)) & max != 0 {
+        return Err(Bech32Error::InvalidPadding);
+    }
+    Ok(converted)
+}
+
+/// Encodes the 5-bit values in `data` as lower case Bech32 under the human-readable
+/// part `hrp`, which is expected to be valid.
+///
+/// # Panics
+///
+/// Panics when a value of `data` doesn't fit in 5 bits.
+pub fn bech32_encode(hrp: &str, data: &[u8]) -> String {
+    let hrp = hrp.to_ascii_lowercase();
+    let checksum = bech32_checksum(hrp.as_bytes(), data);
+    let mut encoded = hrp;
+    encoded.push(BECH32_SEPARATOR);
+    for &value in data.iter().chain(checksum.iter()) {
+        assert!(value < 32, "Bech32 data must hold 5-bit values");
+        encoded.push(char::from(BECH32_CHARSET[usize::from(value)]));
+    }
+    encoded
+}
+
+/// Decodes a Bech32 string into its lower case human-readable part and its 5-bit
+/// values, without the checksum.
+pub fn bech32_decode(s: &str) -> Result<(String, Vec<u8>), Bech32Error> {
+    for (index, character) in s.char_indices() {
+        if !(character.is_ascii() && (33..=126).contains(&u32::from(character))) {
+            return Err(Bech32Error::InvalidCharacter { character, index });
+        }
+    }
+    if s.len() > BECH32_MAX_LEN {
+        return Err(Bech32Error::TooLong(s.len()));
+    }
+    if s.chars().any(|c| c.is_ascii_lowercase()) && s.chars().any(|c| c.is_ascii_uppercase()) {
+        return Err(Bech32Error::MixedCase);
+    }
+    let s = s.to_ascii_lowercase();
+    let separator = s
+        .rfind(BECH32_SEPARATOR)
+        .ok_or(Bech32Error::MissingSeparator)?;
+    if separator == 0 {
+        return Err(Bech32Error::EmptyHrp);
+    }
+    let (hrp, data) = (&s[..separator], &s[separator + 1..]);
+    if data.len() < BECH32_CHECKSUM_LEN {
+        return Err(Bech32Error::TooShort);
+    }
+    let mut values = Vec::with_capacity(data.len());
+    for (offset, character) in data.bytes().enumerate() {
+        let value = BECH32_CHARSET
+            .iter()
+            .position(|&c| c == character)
+            .ok_or_else(|| Bech32Error::InvalidCharacter {
+                character: char::from(character),
+                index: separator + 1 + offset,
+            })?;
+        // The alphabet has 32 characters, so the position fits in 5 bits.
+        #[allow(clippy::cast_possible_truncation)]
+        values.push(value as u8);
+    }
+    let polymod = bech32_polymod(bech32_hrp_expand(hrp.as_bytes()).chain(values.iter().copied()));
+    if polymod != 1 {
+        return Err(Bech32Error::InvalidChecksum);
+    }
+    values.truncate(values.len() - BECH32_CHECKSUM_LEN);
+    Ok((String::from(hrp), values))
+}
+
+/// Compares two byte strings in time depending only on their lengths, for checksums,
+/// signatures and secrets that an early exit would leak one byte at a time.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let difference = a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y));
+    hint::black_box(difference) == 0
+}
+
+/// Private key material, overwritten with zeros when dropped.
+///
+/// Serialized exactly like a `Vec<u8>`, and never printed by [Debug].
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct SecretBytes(Vec<u8>);
+
+impl SecretBytes {
+    pub const fn new(bytes: Vec<u8>) -> Self {
+        Self(bytes)
+    }
+
+    pub const fn expose(&self) -> &[u8] {
+        self.0.as_slice()
+    }
+}
+
+impl Zeroize for SecretBytes {
+    /// Overwrites the bytes with zeros, keeping their length.
+    fn zeroize(&mut self) {
+        self.0.as_mut_slice().zeroize();
+    }
+}
+
+impl Drop for SecretBytes {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+impl Debug for SecretBytes {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "SecretBytes([REDACTED; {}])", self.0.len())
+    }
+}
+
+/// Returns a random number from the system's secure random number generator.
+pub fn random_u64() -> u64 {
+    let mut bytes = [0; 8];
+    SystemRandom::new().fill(&mut bytes).unwrap();
+    u64::from_le_bytes(bytes)
 }
 
 /// Generates a new ECDSA key pair returning the private key as bytes.
-pub fn new_key_pair() -> Vec<u8> {
+pub fn new_key_pair() -> SecretBytes {
     let rng = SystemRandom::new();
     let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
-    pkcs8.as_ref().to_vec()
+    SecretBytes::new(pkcs8.as_ref().to_vec())
 }
 
-/// Signs the provided `message` using ECDSA P-256 SHA-256 algorithm.
-pub fn ecdsa_p256_sha256_sign_digest(pkcs8: &[u8], message: &[u8]) -> Vec<u8> {
+const PRIVATE_KEY_LEN: usize = 32;
+
+/// A PKCS #8 document that isn't a valid P-256 key pair.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyError(String);
+
+impl Display for KeyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid private key: {}", self.0)
+    }
+}
+
+impl std::error::Error for KeyError {}
+
+/// Derives the uncompressed public key of the P-256 key pair in `pkcs8`.
+pub fn public_key_from_pkcs8(pkcs8: &[u8]) -> Result<Vec<u8>, KeyError> {
     let key_pair = EcdsaKeyPair::from_pkcs8(
         &ECDSA_P256_SHA256_FIXED_SIGNING,
         pkcs8,
         &SystemRandom::new(),
     )
-    .unwrap();
+    .map_err(|e| KeyError(e.to_string()))?;
+    Ok(key_pair.public_key().as_ref().to_vec())
+}
+
+/// The DER of a P-256 PKCS #8 document up to the private key, as `ring` writes it.
+const PKCS8_PREFIX: [u8; 36] = [
+    0x30, 0x81, 0x87, 0x02, 0x01, 0x00, 0x30, 0x13, 0x06, 0x07, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02,
+    0x01, 0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07, 0x04, 0x6d, 0x30, 0x6b, 0x02,
+    0x01, 0x01, 0x04, 0x20,
+];
+/// The DER between the private and the public key of a P-256 PKCS #8 document.
+const PKCS8_PUBLIC_KEY_HEADER: [u8; 5] = [0xa1, 0x44, 0x03, 0x42, 0x00];
+
+/// Wraps the 32-byte P-256 `private_key` in a PKCS #8 document along with its public
+/// key, failing when it isn't a valid scalar.
+///
+/// `ring` can't derive a public key from a private one, so the `p256` crate does, in
+/// constant time.
+pub fn pkcs8_from_private_key(private_key: &[u8]) -> Result<SecretBytes, KeyError> {
+    if private_key.len() != PRIVATE_KEY_LEN {
+        return Err(KeyError(format!(
+            "expected {PRIVATE_KEY_LEN} bytes, got {}",
+            private_key.len()
+        )));
+    }
+    let secret_key = p256::SecretKey::from_slice(private_key)
+        .map_err(|_| KeyError(String::from("the scalar is out of range")))?;
+    let public_key = secret_key.public_key().to_encoded_point(false);
+    let mut pkcs8 = PKCS8_PREFIX.to_vec();
+    pkcs8.extend_from_slice(private_key);
+    pkcs8.extend_from_slice(&PKCS8_PUBLIC_KEY_HEADER);
+    pkcs8.extend_from_slice(public_key.as_bytes());
+    let pkcs8 = SecretBytes::new(pkcs8);
+    public_key_from_pkcs8(pkcs8.expose())?;
+    Ok(pkcs8)
+}
+
+/// Signs the provided `message` using ECDSA P-256 SHA-256 algorithm.
+pub fn ecdsa_p256_sha256_sign_digest(pkcs8: &[u8], message: &[u8]) -> Result<Vec<u8>, KeyError> {
     let rng = SystemRandom::new();
-    key_pair.sign(&rng, message).unwrap().as_ref().to_vec()
+    let key_pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8, &rng)
+        .map_err(|e| KeyError(e.to_string()))?;
+    let signature = key_pair
+        .sign(&rng, message)
+        .map_err(|_| KeyError(String::from("the key pair can't sign")))?;
+    Ok(signature.as_ref().to_vec())
+}
+
+/// Signs `message` like [`ecdsa_p256_sha256_sign_digest`], but deterministically.
+///
+/// The nonce is derived from the key and the message as RFC 6979 describes, so that
+/// the same message signed with the same key always gives the same signature.
+/// `ring` doesn't sign deterministically, so the `p256` crate does.
+pub fn ecdsa_p256_sha256_sign_deterministic(
+    pkcs8: &[u8],
+    message: &[u8],
+) -> Result<Vec<u8>, KeyError> {
+    public_key_from_pkcs8(pkcs8)?;
+    let secret_key = p256::SecretKey::from_pkcs8_der(pkcs8).map_err(|e| KeyError(e.to_string()))?;
+    let signature: p256::ecdsa::Signature = SigningKey::from(secret_key).sign(message);
+    Ok(signature.to_bytes().to_vec())
 }
 
 /// Verifies an ECDSA P-256 SHA-256 signature against a provided `message` using  the corresponding
@@ -70,3 +546,259 @@ pub fn ecdsa_p256_sha256_sign_verify(public_key: &[u8], signature: &[u8], messag
     let result = peer_public_key.verify(message, signature.as_ref());
     result.is_ok()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use data_encoding::{HEXLOWER, HEXUPPER};
+
+    /// The P-256 private key of RFC 6979, appendix A.2.5.
+    const RFC6979_PRIVATE_KEY: &str =
+        "C9AFA9D845BA75166B5C215767B1D6934E50C3DB36E89B127B8A622B120F6721";
+
+    #[test]
+    fn base58_decoding_reports_what_is_wrong_instead_of_panicking() {
+        let bytes = [0, 0, 1, 2, 254, 255];
+        assert_eq!(
+            base58_decode(base58_encode(&bytes).as_str()),
+            Ok(bytes.to_vec())
+        );
+        assert_eq!(base58_decode(""), Ok(vec![]));
+        assert_eq!(
+            base58_decode("abc0def"),
+            Err(Base58Error::InvalidCharacter {
+                character: '0',
+                index: 3
+            })
+        );
+        assert_eq!(
+            base58_decode("l").unwrap_err().to_string(),
+            "`l` at position 0 is not a Base58 character"
+        );
+        assert_eq!(
+            base58_decode("ab\u{e9}"),
+            Err(Base58Error::NonAsciiCharacter { index: 2 })
+        );
+    }
+
+    #[test]
+    fn deterministic_signatures_match_rfc6979() {
+        let private_key = HEXUPPER.decode(RFC6979_PRIVATE_KEY.as_bytes()).unwrap();
+        let pkcs8 = pkcs8_from_private_key(private_key.as_slice()).unwrap();
+        let vectors = [
+            (
+                "sample",
+                "EFD48B2AACB6A8FD1140DD9CD45E81D69D2C877B56AAF991C34D0EA84EAF3716\
+                 F7CB1C942D657C41D436C7A1B6E29F65F3E900DBB9AFF4064DC4AB2F843ACDA8",
+            ),
+            (
+                "test",
+                "F1ABB023518351CD71D881567B1EA663ED3EFCF6C5132B354F28D3B0B7D38367\
+                 019F4113742A2B14BD25926B49C649155F267E60D3814B4C0CC84250E46F0083",
+            ),
+        ];
+        for (message, signature) in vectors {
+            let signed =
+                ecdsa_p256_sha256_sign_deterministic(pkcs8.expose(), message.as_bytes()).unwrap();
+            assert_eq!(HEXUPPER.encode(signed.as_slice()), signature);
+        }
+    }
+
+    #[test]
+    fn deterministic_signatures_of_generated_keys_verify() {
+        let pkcs8 = new_key_pair();
+        let public_key = public_key_from_pkcs8(pkcs8.expose()).unwrap();
+        let signature = ecdsa_p256_sha256_sign_deterministic(pkcs8.expose(), b"message").unwrap();
+        assert!(ecdsa_p256_sha256_sign_verify(
+            public_key.as_slice(),
+            signature.as_slice(),
+            b"message"
+        ));
+    }
+
+    #[test]
+    fn signing_with_an_invalid_key_fails() {
+        let pkcs8 = new_key_pair();
+        let truncated = &pkcs8.expose()[..pkcs8.expose().len() - 1];
+        assert!(ecdsa_p256_sha256_sign_deterministic(truncated, b"message").is_err());
+        assert!(ecdsa_p256_sha256_sign_digest(truncated, b"message").is_err());
+        assert!(ecdsa_p256_sha256_sign_deterministic(&[0x30, 0x00], b"message").is_err());
+    }
+
+    #[test]
+    fn ripemd160_matches_the_reference_vectors() {
+        let million_a = "a".repeat(1_000_000);
+        let vectors = [
+            ("", "9c1185a5c5e9fc54612808977ee8f548b2258d31"),
+            ("abc", "8eb208f7e05d987a9b044a8e98c6b087f15a0bfc"),
+            ("message digest", "5d0689ef49d2fae572b881b123a85ffa21595f36"),
+            (
+                million_a.as_str(),
+                "52783243c1697bdbe16d37f97f68f08325dc1528",
+            ),
+        ];
+        for (message, digest) in vectors {
+            assert_eq!(
+                HEXLOWER.encode(&ripemd160_digest(message.as_bytes())),
+                digest
+            );
+        }
+    }
+
+    #[test]
+    fn hash160_of_the_generator_point_is_pinned() {
+        // The compressed secp256k1 generator point, whose hash160 BIP 173 encodes.
+        let public_key = HEXLOWER
+            .decode(b"0279be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798")
+            .unwrap();
+        assert_eq!(
+            HEXLOWER.encode(&hash160(public_key.as_slice())),
+            "751e76e8199196d454941c45d1b3a323f1433bd6"
+        );
+    }
+
+    #[test]
+    fn bech32_matches_the_bip173_vectors() {
+        let valid = [
+            "A12UEL5L",
+            "a12uel5l",
+            "an83characterlonghumanreadablepartthatcontainsthenumber1andtheexcludedcharactersbio1tt5tgs",
+            "abcdef1qpzry9x8gf2tvdw0s3jn54khce6mua7lmqqqxw",
+            "11qqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqqc8247j",
+            "split1checkupstagehandshakeupstreamerranterredcaperred2y9e3w",
+            "?1ezyfcl",
+        ];
+        for encoded in valid {
+            let (hrp, data) = bech32_decode(encoded).unwrap();
+            assert_eq!(bech32_encode(&hrp, &data), encoded.to_ascii_lowercase());
+        }
+
+        let too_long = "an84characterslonghumanreadablepartthatcontainsthenumber1andtheexcludedcharactersbio1569pvx";
+        let invalid_character =
+            |character, index| Bech32Error::InvalidCharacter { character, index };
+        let invalid = [
+            ("\x201nwldj5", invalid_character(' ', 0)),
+            ("\x7f1axkwrx", invalid_character('\x7f', 0)),
+            ("\u{80}1eym55h", invalid_character('\u{80}', 0)),
+            (too_long, Bech32Error::TooLong(91)),
+            ("pzry9x0s0muk", Bech32Error::MissingSeparator),
+            ("1pzry9x0s0muk", Bech32Error::EmptyHrp),
+            ("x1b4n0q5v", invalid_character('b', 2)),
+            ("li1dgmt3", Bech32Error::TooShort),
+            ("de1lg7wt\u{ff}", invalid_character('\u{ff}', 8)),
+            // The checksum was computed over the upper case human-readable part.
+            ("A1G7SGD8", Bech32Error::InvalidChecksum),
+            ("10a06t8", Bech32Error::EmptyHrp),
+            ("1qzzfhee", Bech32Error::EmptyHrp),
+            ("A12uEL5L", Bech32Error::MixedCase),
+            ("a12uel5m", Bech32Error::InvalidChecksum),
+        ];
+        for (encoded, error) in invalid {
+            assert_eq!(bech32_decode(encoded), Err(error), "{encoded:?}");
+        }
+    }
+
+    #[test]
+    fn hashes_match_the_pinned_vectors() {
+        let long = "abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+        let sha256 = [
+            (
+                "",
+                "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+            ),
+            (
+                "abc",
+                "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+            ),
+            (
+                long,
+                "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+            ),
+        ];
+        for (message, digest) in sha256 {
+            assert_eq!(HEXLOWER.encode(&sha256_digest(message.as_bytes())), digest);
+        }
+        let sha256d_vectors = [
+            (
+                "",
+                "5df6e0e2761359d30a8275058e299fcc0381534545f55cf43e41983f5d4c9456",
+            ),
+            (
+                "hello",
+                "9595c9df90075148eb06860365df33584b75bff782a510c6cd4883a419833d50",
+            ),
+        ];
+        for (message, digest) in sha256d_vectors {
+            assert_eq!(HEXLOWER.encode(&sha256d(message.as_bytes())), digest);
+        }
+        let tagged = [
+            (
+                "BIP0340/challenge",
+                "",
+                "c216d352f5818b7b4beacd4ae0a26fe888080823d2a598856661bcd54f1b3713",
+            ),
+            (
+                "BIP0340/challenge",
+                "abc",
+                "770a5b7e7c304bbcc3ea107343ff951dd404312ef418db0c3b94e2ebfbb50087",
+            ),
+            (
+                "TapLeaf",
+                "",
+                "5212c288a377d1f8164962a5a13429f9ba6a7b84e59776a52c6637df2106facb",
+            ),
+        ];
+        for (tag, message, digest) in tagged {
+            assert_eq!(
+                HEXLOWER.encode(&tagged_hash(tag, message.as_bytes())),
+                digest
+            );
+        }
+        assert_eq!(
+            HEXLOWER.encode(&hash160(&[])),
+            "b472a266d0bd89c13706a4132ccfb16f7c3b9fcb"
+        );
+    }
+
+    #[test]
+    fn streamed_hashes_match_the_one_shot_hashes() {
+        let message: Vec<u8> = (0..=255).cycle().take(1000).collect();
+        for piece_len in [1, 7, 64, 999, 1000] {
+            let mut sha256 = Sha256::new();
+            let mut written = Sha256::new();
+            let mut hash160_context = Hash160::new();
+            for piece in message.chunks(piece_len) {
+                sha256.update(piece);
+                io::Write::write_all(&mut written, piece).unwrap();
+                hash160_context.update(piece);
+            }
+            assert_eq!(sha256.finish().to_vec(), sha256_digest(&message));
+            assert_eq!(written.finish().to_vec(), sha256_digest(&message));
+            assert_eq!(hash160_context.finish().to_vec(), hash160(&message));
+        }
+    }
+
+    #[test]
+    fn secret_bytes_are_zeroed_and_never_printed() {
+        let secret = SecretBytes::new(vec![0xa5; 32]);
+        // Zeroed like on drop, on a copy that can still be read afterwards.
+        let mut kept = secret.clone();
+        kept.zeroize();
+        assert_eq!(kept.expose(), [0; 32]);
+        assert_eq!(secret.expose(), [0xa5; 32]);
+
+        let printed = format!("{secret:?}");
+        assert_eq!(printed, "SecretBytes([REDACTED; 32])");
+        assert!(!printed.contains("165"));
+    }
+
+    #[test]
+    fn constant_time_comparison_checks_every_byte() {
+        let a = [1, 2, 3, 4];
+        assert!(constant_time_eq(&a, &[1, 2, 3, 4]));
+        assert!(!constant_time_eq(&a, &[0, 2, 3, 4]));
+        assert!(!constant_time_eq(&a, &[1, 2, 3, 5]));
+        assert!(!constant_time_eq(&a, &[1, 2, 3]));
+        assert!(constant_time_eq(&[], &[]));
+    }
+}
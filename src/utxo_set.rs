@@ -1,53 +1,191 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
 
-use data_encoding::HEXLOWER;
+use log::info;
+use serde::{Deserialize, Serialize};
+use sled::transaction::{ConflictableTransactionError, TransactionResult, TransactionalTree};
+use sled::{Transactional, Tree};
 
-use crate::{block::Block, blockchain::Blockchain, transactions::TXOutput};
+use crate::block::Block;
+use crate::blockchain::Blockchain;
+use crate::transactions::{TXOutput, Transaction, ValueError};
+use crate::types::{BlockHash, Txid};
 
-const UTXO_TREE: &str = "chainstate";
+pub(crate) const UTXO_TREE: &str = "chainstate";
+/// Maps the hash of each [Block] applied by [`UTXOSet::update`] to the outputs it
+/// spent, so that [`UTXOSet::disconnect`] can restore them.
+pub(crate) const UNDO_TREE: &str = "undo";
+/// Holds the hash of the [Block] the stored set is up to date with.
+const BEST_BLOCK_KEY: &str = "best_block";
+/// Holds the version of the encoding of the UTXO tree. Sets without it store only
+/// the unspent outputs of each [Transaction], losing their indices, and are rebuilt
+/// when opened.
+const UTXO_FORMAT_KEY: &str = "utxo_format";
+const UTXO_FORMAT: u8 = 2;
+/// The number of [Block]s between two progress reports while rebuilding.
+pub const PROGRESS_INTERVAL: usize = 1000;
 
-/// Manages UTXOs (Unspent Transactional Outputs) in the [Blockchain]. Facilitates
-/// functionalities such as finding spendable outputs, reindexing outputs, updating
+/// Aggregate figures describing the [`UTXOSet`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct UtxoStats {
+    /// The [Transaction](crate::transactions::Transaction)s with unspent outputs.
+    pub transactions: usize,
+    pub outputs: usize,
+    pub total_value: u64,
+}
+
+/// The differences between the stored [`UTXOSet`] and one rebuilt from the
+/// [Blockchain], by transaction id.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct UtxoDiff {
+    /// Transactions with unspent outputs on the [Blockchain] that aren't stored.
+    pub missing: Vec<String>,
+    /// Stored transactions with no unspent outputs on the [Blockchain].
+    pub unexpected: Vec<String>,
+    /// Stored transactions whose unspent outputs differ from the [Blockchain].
+    pub mismatched: Vec<String>,
+}
+
+/// The reasons [`UTXOSet::validate_tx`] refuses a [Transaction].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SpendError {
+    /// The id isn't the hash of the [Transaction].
+    MismatchedId(Txid),
+    /// The set still holds outputs of a [Transaction] with the same id, which adding
+    /// this one would overwrite.
+    DuplicateTransaction(Txid),
+    /// An input spends an output that isn't in the set, because it doesn't exist or
+    /// is spent already.
+    MissingOutput {
+        txid: Txid,
+        vout: usize,
+    },
+    /// Two inputs spend the same output.
+    DuplicateInput {
+        txid: Txid,
+        vout: usize,
+    },
+    InvalidSignature,
+    Value(ValueError),
+}
+
+impl Display for SpendError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MismatchedId(txid) => write!(f, "the id {txid} is not the transaction hash"),
+            Self::DuplicateTransaction(txid) => {
+                write!(f, "the transaction {txid} already has unspent outputs")
+            }
+            Self::MissingOutput { txid, vout } => {
+                write!(f, "the output {txid}:{vout} is unknown or already spent")
+            }
+            Self::DuplicateInput { txid, vout } => {
+                write!(f, "the output {txid}:{vout} is spent more than once")
+            }
+            Self::InvalidSignature => write!(f, "the transaction signature is not valid"),
+            Self::Value(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for SpendError {}
+
+/// An output spent by an input of a [Block], kept to undo the [Block].
+#[derive(Serialize, Deserialize)]
+struct SpentOutput {
+    txid: Txid,
+    vout: usize,
+    output: TXOutput,
+}
+
+impl UtxoDiff {
+    pub const fn is_consistent(&self) -> bool {
+        self.missing.is_empty() && self.unexpected.is_empty() && self.mismatched.is_empty()
+    }
+}
+
+/// Manages UTXOs (Unspent Transactional Outputs) in the [Blockchain].
+///
+/// Facilitates functionalities such as finding spendable outputs, reindexing outputs, updating
 /// outputs after [Block] confirmation, and counting transactions within the blockchain.
 pub struct UTXOSet {
     blockchain: Blockchain,
+    utxo_tree: Tree,
+    undo_tree: Tree,
 }
 
 impl UTXOSet {
-    pub const fn new(blockchain: Blockchain) -> Self {
-        Self { blockchain }
+    pub fn new(blockchain: Blockchain) -> Self {
+        let utxo_tree = blockchain.get_db().open_tree(UTXO_TREE).unwrap();
+        let undo_tree = blockchain.get_db().open_tree(UNDO_TREE).unwrap();
+        let utxo_set = Self {
+            blockchain,
+            utxo_tree,
+            undo_tree,
+        };
+        utxo_set.migrate();
+        utxo_set
+    }
+
+    /// Rebuilds a set stored in an older format, whose undo data is dropped as well.
+    fn migrate(&self) {
+        let undo_tree = self.undo_tree();
+        if undo_tree.get(UTXO_FORMAT_KEY).unwrap().as_deref() == Some(&[UTXO_FORMAT]) {
+            return;
+        }
+        undo_tree.clear().unwrap();
+        if !self.utxo_tree().is_empty() {
+            info!("Rebuilding the UTXO set in the current format");
+            self.reindex();
+        }
+        undo_tree.insert(UTXO_FORMAT_KEY, &[UTXO_FORMAT]).unwrap();
     }
 
     pub const fn get_blockchain(&self) -> &Blockchain {
         &self.blockchain
     }
 
+    const fn utxo_tree(&self) -> &Tree {
+        &self.utxo_tree
+    }
+
+    const fn undo_tree(&self) -> &Tree {
+        &self.undo_tree
+    }
+
+    /// Returns the hash of the [Block] the stored set is up to date with, or `None`
+    /// when it was built before this was recorded.
+    pub fn get_best_block(&self) -> Option<BlockHash> {
+        self.undo_tree()
+            .get(BEST_BLOCK_KEY)
+            .unwrap()
+            .map(|bytes| BlockHash::from_slice(bytes.as_ref()).expect("the best block is valid"))
+    }
+
+    fn set_best_block(&self, block_hash: BlockHash) {
+        self.undo_tree()
+            .insert(BEST_BLOCK_KEY, block_hash.as_bytes())
+            .unwrap();
+    }
+
     /// Identifies spendable outputs for a given public key and required amount.
     pub fn find_spendable_outputs(
         &self,
         pub_key_hash: &[u8],
-        amount: i32,
-    ) -> (i32, HashMap<String, Vec<usize>>) {
-        let mut unspent_outputs: HashMap<String, Vec<usize>> = HashMap::new();
+        amount: u64,
+    ) -> (u64, HashMap<Txid, Vec<usize>>) {
+        let mut unspent_outputs: HashMap<Txid, Vec<usize>> = HashMap::new();
         let mut accumulated = 0;
-        let db = self.blockchain.get_db();
-        let utxo_tree = db.open_tree(UTXO_TREE).unwrap();
-        for item in &utxo_tree {
+        let utxo_tree = self.utxo_tree();
+        for item in utxo_tree {
             let (k, v) = item.unwrap();
-            let txid_hex = HEXLOWER.encode(k.to_vec().as_slice());
-            let outs: Vec<TXOutput> = bincode::deserialize(v.to_vec().as_slice())
+            let txid = Txid::from_slice(k.as_ref()).expect("UTXO keys are transaction ids");
+            let outs: Vec<Option<TXOutput>> = bincode::deserialize(v.to_vec().as_slice())
                 .expect("unable to deserialize TXOutput");
-            for (idx, out) in outs.iter().enumerate() {
+            for (idx, out) in unspent(&outs) {
                 if out.is_locked_with_key(pub_key_hash) && accumulated < amount {
-                    accumulated += out.get_value();
-                    if unspent_outputs.contains_key(txid_hex.as_str()) {
-                        unspent_outputs
-                            .get_mut(txid_hex.as_str())
-                            .unwrap()
-                            .push(idx);
-                    } else {
-                        unspent_outputs.insert(txid_hex.clone(), vec![idx]);
-                    }
+                    accumulated = accumulated.saturating_add(out.get_value());
+                    unspent_outputs.entry(txid).or_default().push(idx);
                 }
             }
         }
@@ -56,14 +194,13 @@ impl UTXOSet {
 
     /// Finds all UTXOs associated with a provided public hash.
     pub fn find_utxo(&self, pub_key_hash: &[u8]) -> Vec<TXOutput> {
-        let db = self.blockchain.get_db();
-        let utxo_tree = db.open_tree(UTXO_TREE).unwrap();
+        let utxo_tree = self.utxo_tree();
         let mut utxos = Vec::new();
-        for item in &utxo_tree {
+        for item in utxo_tree {
             let (_, v) = item.unwrap();
-            let outs: Vec<TXOutput> = bincode::deserialize(v.to_vec().as_slice())
+            let outs: Vec<Option<TXOutput>> = bincode::deserialize(v.to_vec().as_slice())
                 .expect("unable to deserialize TXOutput");
-            for out in &outs {
+            for (_, out) in unspent(&outs) {
                 if out.is_locked_with_key(pub_key_hash) {
                     utxos.push(out.clone());
                 }
@@ -72,59 +209,590 @@ impl UTXOSet {
         utxos
     }
 
+    /// Lists the unspent outputs locked to `pub_key_hash` as `(txid, vout, output)`.
+    pub fn find_unspent(&self, pub_key_hash: &[u8]) -> Vec<(Txid, usize, TXOutput)> {
+        let mut unspent: Vec<_> = self
+            .get_stored()
+            .into_iter()
+            .flat_map(|(txid, outs)| {
+                outs.into_iter()
+                    .enumerate()
+                    .filter_map(|(idx, out)| Some((idx, out?)))
+                    .filter(|(_, out)| out.is_locked_with_key(pub_key_hash))
+                    .map(move |(idx, out)| (txid, idx, out))
+            })
+            .collect();
+        unspent.sort_by(|a, b| a.0.cmp(&b.0).then(a.1.cmp(&b.1)));
+        unspent
+    }
+
+    /// Sums the unspent outputs locked to each of `pub_key_hashes` in a single pass
+    /// over the UTXO set, returning the balances in the same order.
+    pub fn get_balances(&self, pub_key_hashes: &[Vec<u8>]) -> Vec<u64> {
+        let utxo_tree = self.utxo_tree();
+        let mut balances = vec![0_u64; pub_key_hashes.len()];
+        for item in utxo_tree {
+            let (_, v) = item.unwrap();
+            let outs: Vec<Option<TXOutput>> = bincode::deserialize(v.to_vec().as_slice())
+                .expect("unable to deserialize TXOutput");
+            for (_, out) in unspent(&outs) {
+                if let Some(idx) = pub_key_hashes
+                    .iter()
+                    .position(|pub_key_hash| out.is_locked_with_key(pub_key_hash))
+                {
+                    balances[idx] = balances[idx].saturating_add(out.get_value());
+                }
+            }
+        }
+        balances
+    }
+
+    /// Counts the stored transactions, outputs and their total value.
+    pub fn get_stats(&self) -> UtxoStats {
+        let mut stats = UtxoStats::default();
+        for outs in self.get_stored().values() {
+            stats.transactions += 1;
+            stats.outputs += unspent(outs).count();
+            stats.total_value = unspent(outs)
+                .map(|(_, out)| out.get_value())
+                .fold(stats.total_value, u64::saturating_add);
+        }
+        stats
+    }
+
+    /// Compares the stored UTXO tree against one rebuilt from the [Blockchain]
+    /// without changing it.
+    pub fn verify(&self, progress: impl FnMut(usize)) -> UtxoDiff {
+        let expected = self.blockchain.find_utxo_with_progress(progress);
+        let stored = self.get_stored();
+        let txids: BTreeSet<&Txid> = expected.keys().chain(stored.keys()).collect();
+        let mut diff = UtxoDiff::default();
+        for txid in txids {
+            match (expected.get(txid), stored.get(txid)) {
+                (Some(_), None) => diff.missing.push(txid.to_string()),
+                (None, Some(_)) => diff.unexpected.push(txid.to_string()),
+                (Some(expected), Some(stored)) if !same_outputs(expected, stored) => {
+                    diff.mismatched.push(txid.to_string());
+                }
+                _ => {}
+            }
+        }
+        diff
+    }
+
+    /// Reads the whole UTXO tree keyed by transaction id.
+    fn get_stored(&self) -> HashMap<Txid, Vec<Option<TXOutput>>> {
+        let utxo_tree = self.utxo_tree();
+        utxo_tree
+            .iter()
+            .map(|item| {
+                let (k, v) = item.unwrap();
+                let outs: Vec<Option<TXOutput>> =
+                    bincode::deserialize(v.as_ref()).expect("unable to deserialize TXOutput");
+                let txid = Txid::from_slice(k.as_ref()).expect("UTXO keys are transaction ids");
+                (txid, outs)
+            })
+            .collect()
+    }
+
+    /// Returns output `vout` of `txid` when it is unspent.
+    pub fn get_output(&self, txid: Txid, vout: usize) -> Option<TXOutput> {
+        self.get_outputs(txid).get_mut(vout)?.take()
+    }
+
+    /// Checks whether the set holds unspent outputs of `txid`.
+    pub fn has_outputs(&self, txid: Txid) -> bool {
+        self.utxo_tree().contains_key(txid.as_bytes()).unwrap()
+    }
+
+    /// Returns output `vout` of `txid` when it is unspent, or of the `unconfirmed`
+    /// outputs when the set doesn't hold it.
+    pub fn get_output_or_unconfirmed(
+        &self,
+        txid: Txid,
+        vout: usize,
+        unconfirmed: &HashMap<Txid, Vec<TXOutput>>,
+    ) -> Option<TXOutput> {
+        self.get_output(txid, vout)
+            .or_else(|| unconfirmed.get(&txid)?.get(vout).cloned())
+    }
+
+    /// Checks that the id of `tx` is its hash, that no [Transaction] with the same id
+    /// has unspent outputs, and that its inputs spend distinct outputs of the set with
+    /// valid signatures, worth at least its outputs, returning the fee `tx` pays.
+    pub fn validate_tx(&self, tx: &Transaction) -> Result<u64, SpendError> {
+        self.validate_tx_spending(tx, &HashMap::new())
+    }
+
+    /// Like [`UTXOSet::validate_tx`], also letting the inputs spend the `unconfirmed`
+    /// outputs of [Transaction]s not yet in the set, by transaction id.
+    pub fn validate_tx_spending(
+        &self,
+        tx: &Transaction,
+        unconfirmed: &HashMap<Txid, Vec<TXOutput>>,
+    ) -> Result<u64, SpendError> {
+        self.check_tx_spending(tx, unconfirmed, true)
+    }
+
+    /// Like [`UTXOSet::validate_tx_spending`], leaving out the signatures unless
+    /// `verify_signatures` is set, for callers that verified them already.
+    pub fn check_tx_spending(
+        &self,
+        tx: &Transaction,
+        unconfirmed: &HashMap<Txid, Vec<TXOutput>>,
+        verify_signatures: bool,
+    ) -> Result<u64, SpendError> {
+        if !tx.has_valid_id() {
+            return Err(SpendError::MismatchedId(tx.get_id()));
+        }
+        if self.has_outputs(tx.get_id()) {
+            return Err(SpendError::DuplicateTransaction(tx.get_id()));
+        }
+        if tx.is_coinbase() {
+            return tx.get_fee(&[]).map_err(SpendError::Value);
+        }
+        let mut inputs = HashSet::new();
+        let mut spent = vec![];
+        for input in tx.get_vin() {
+            let (txid, vout) = (input.get_txid(), input.get_vout());
+            if !inputs.insert((txid, vout)) {
+                return Err(SpendError::DuplicateInput { txid, vout });
+            }
+            let output = self
+                .get_output_or_unconfirmed(txid, vout, unconfirmed)
+                .ok_or(SpendError::MissingOutput { txid, vout })?;
+            spent.push(output);
+        }
+        let fee = tx.get_fee(&spent).map_err(SpendError::Value)?;
+        if verify_signatures && !tx.verify_spending(spent.as_slice()) {
+            return Err(SpendError::InvalidSignature);
+        }
+        Ok(fee)
+    }
+
     pub fn count_transactions(&self) -> i32 {
-        let db = self.blockchain.get_db();
-        let utxo_tree = db.open_tree(UTXO_TREE).unwrap();
+        let utxo_tree = self.utxo_tree();
         utxo_tree.len().try_into().unwrap()
     }
 
     /// Reindexes the UTXO tree by clearing it and rebuilding it from the
     /// [Blockchain]'s [Transaction] outputs.
     pub fn reindex(&self) {
-        let db = self.blockchain.get_db();
-        let utxo_tree = db.open_tree(UTXO_TREE).unwrap();
+        self.reindex_with_progress(|_| {});
+    }
+
+    /// Like [`UTXOSet::reindex`], calling `progress` with the number of [Block]s
+    /// read so far after each one.
+    pub fn reindex_with_progress(&self, progress: impl FnMut(usize)) {
+        let tip_hash = self.blockchain.get_tip_hash();
+        let utxo_map = self.blockchain.find_utxo_with_progress(progress);
+        let utxo_tree = self.utxo_tree();
         utxo_tree.clear().unwrap();
-        let utxo_map = self.blockchain.find_utxo();
-        for (txid_hex, outs) in &utxo_map {
-            let txid = HEXLOWER.decode(txid_hex.as_bytes()).unwrap();
+        for (txid, outs) in &utxo_map {
             let value = bincode::serialize(outs).unwrap();
-            utxo_tree.insert(txid.as_slice(), value).unwrap();
+            utxo_tree.insert(txid.as_bytes(), value).unwrap();
+        }
+        self.set_best_block(tip_hash);
+    }
+
+    /// Replays every [Block] from `height` to the tip onto the stored set, which must
+    /// already be up to date below `height`. Returns the number of [Block]s replayed.
+    pub fn catch_up(
+        &self,
+        height: usize,
+        mut progress: impl FnMut(usize),
+    ) -> Result<usize, SpendError> {
+        let mut iterator = self.blockchain.forward_iterator(height);
+        let mut blocks = 0;
+        while let Some(block) = iterator.next() {
+            self.update(&block)?;
+            blocks += 1;
+            progress(blocks);
         }
+        Ok(blocks)
     }
 
-    /// Updates the UTXO set after a [Block] confirmation.
-    #[allow(clippy::similar_names)]
-    pub fn update(&self, block: &Block) {
-        let db = self.blockchain.get_db();
-        let utxo_tree = db.open_tree(UTXO_TREE).unwrap();
+    /// Updates the UTXO set after a [Block] confirmation, recording the outputs it
+    /// spends so that it can be disconnected again. The [Transaction]s are applied in
+    /// order, so that one may spend the outputs of another earlier in the [Block].
+    ///
+    /// The outputs, the undo record and the best [Block] are written in one database
+    /// transaction. Fails, changing nothing, when an input spends an output that isn't
+    /// in the set or that an earlier input of the [Block] spends.
+    pub fn update(&self, block: &Block) -> Result<(), SpendError> {
+        let mut changed: HashMap<Txid, Vec<Option<TXOutput>>> = HashMap::new();
+        let mut inputs = HashSet::new();
+        let mut undo = Vec::new();
         for tx in block.get_transactions() {
-            if !tx.is_coinbase() {
-                for vin in tx.get_vin() {
-                    let mut updated_outs = Vec::new();
-                    let outs_bytes = utxo_tree.get(vin.get_txid()).unwrap().unwrap();
-                    let outs: Vec<TXOutput> = bincode::deserialize(outs_bytes.as_ref())
-                        .expect("unable to deserialize TXOutput");
-                    for (idx, out) in outs.iter().enumerate() {
-                        if idx != vin.get_vout() {
-                            updated_outs.push(out.clone());
-                        }
-                    }
-                    if updated_outs.is_empty() {
-                        utxo_tree.remove(vin.get_txid()).unwrap();
-                    } else {
-                        let out_bytes = bincode::serialize(&updated_outs)
-                            .expect("unable to serialize TXOutput");
-                        utxo_tree.insert(vin.get_txid(), out_bytes).unwrap();
-                    }
+            let mut spent = Vec::new();
+            for input in tx.get_vin().iter().filter(|_| !tx.is_coinbase()) {
+                let (txid, vout) = (input.get_txid(), input.get_vout());
+                if !inputs.insert((txid, vout)) {
+                    return Err(SpendError::DuplicateInput { txid, vout });
                 }
+                let output = changed
+                    .entry(txid)
+                    .or_insert_with(|| self.get_outputs(txid))
+                    .get_mut(vout)
+                    .and_then(Option::take)
+                    .ok_or(SpendError::MissingOutput { txid, vout })?;
+                spent.push(SpentOutput { txid, vout, output });
             }
-            let mut new_outputs = Vec::new();
-            for out in tx.get_vout() {
-                new_outputs.push(out.clone());
+            undo.push(spent);
+            let outs = tx.get_vout().iter().cloned().map(Some).collect();
+            changed.insert(tx.get_id(), outs);
+        }
+        let undo_bytes = bincode::serialize(&undo).expect("unable to serialize undo data");
+        let block_hash = block.get_hash();
+        self.apply(&changed, |undo_tree| {
+            undo_tree.insert(block_hash.as_bytes(), undo_bytes.as_slice())?;
+            undo_tree.insert(BEST_BLOCK_KEY, block_hash.as_bytes())?;
+            Ok(())
+        });
+        Ok(())
+    }
+
+    /// Reverts [`UTXOSet::update`] for `block`, which must be the [Block] the set is up
+    /// to date with, removing its outputs and restoring those it spent.
+    ///
+    /// Returns `false`, changing nothing, when `block` isn't the best [Block] of the set
+    /// or it has no undo data, as for [Block]s only applied by a reindex.
+    #[must_use]
+    pub fn disconnect(&self, block: &Block) -> bool {
+        let block_hash = block.get_hash();
+        if self.get_best_block() != Some(block_hash) {
+            return false;
+        }
+        let Some(undo_bytes) = self.undo_tree().get(block_hash.as_bytes()).unwrap() else {
+            return false;
+        };
+        let undo: Vec<Vec<SpentOutput>> =
+            bincode::deserialize(undo_bytes.as_ref()).expect("unable to deserialize undo data");
+        let mut changed: HashMap<Txid, Vec<Option<TXOutput>>> = HashMap::new();
+        for (tx, spent) in block.get_transactions().iter().zip(undo).rev() {
+            changed.insert(tx.get_id(), vec![]);
+            for SpentOutput { txid, vout, output } in spent.into_iter().rev() {
+                let outs = changed
+                    .entry(txid)
+                    .or_insert_with(|| self.get_outputs(txid));
+                if outs.len() <= vout {
+                    outs.resize(vout + 1, None);
+                }
+                outs[vout] = Some(output);
             }
-            let outs_bytes =
-                bincode::serialize(&new_outputs).expect("unable to serialize TXOutput");
-            let _ = utxo_tree.insert(tx.get_id(), outs_bytes).unwrap();
         }
+        let pre_block_hash = block.get_pre_block_hash();
+        self.apply(&changed, |undo_tree| {
+            undo_tree.remove(block_hash.as_bytes())?;
+            undo_tree.insert(BEST_BLOCK_KEY, pre_block_hash.as_bytes())?;
+            Ok(())
+        });
+        true
+    }
+
+    /// Stores the `changed` outputs by transaction id, removing the entries of those
+    /// all spent, and makes the changes of `undo` to the undo tree, in one database
+    /// transaction.
+    fn apply(
+        &self,
+        changed: &HashMap<Txid, Vec<Option<TXOutput>>>,
+        undo: impl Fn(&TransactionalTree) -> Result<(), ConflictableTransactionError>,
+    ) {
+        let changed: Vec<(Txid, Option<Vec<u8>>)> = changed
+            .iter()
+            .map(|(txid, outs)| {
+                let bytes = outs
+                    .iter()
+                    .any(Option::is_some)
+                    .then(|| bincode::serialize(outs).expect("unable to serialize TXOutput"));
+                (*txid, bytes)
+            })
+            .collect();
+        let applied: TransactionResult<(), sled::Error> = (self.utxo_tree(), self.undo_tree())
+            .transaction(|(utxo_tree, undo_tree)| {
+                for (txid, bytes) in &changed {
+                    match bytes {
+                        Some(bytes) => utxo_tree.insert(txid.as_bytes(), bytes.as_slice())?,
+                        None => utxo_tree.remove(txid.as_bytes())?,
+                    };
+                }
+                undo(undo_tree)
+            });
+        applied.expect("the UTXO set is written");
+    }
+
+    /// Reads the outputs stored for `txid` by index, `None` where spent and empty when
+    /// all of them are.
+    fn get_outputs(&self, txid: Txid) -> Vec<Option<TXOutput>> {
+        self.utxo_tree()
+            .get(txid.as_bytes())
+            .unwrap()
+            .map(|bytes| {
+                bincode::deserialize(bytes.as_ref()).expect("unable to deserialize TXOutput")
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Iterates over the unspent outputs of a [Transaction] with their indices.
+fn unspent(outs: &[Option<TXOutput>]) -> impl Iterator<Item = (usize, &TXOutput)> {
+    outs.iter()
+        .enumerate()
+        .filter_map(|(idx, out)| Some((idx, out.as_ref()?)))
+}
+
+/// Checks that two [Transaction]s have the same unspent outputs at the same indices.
+fn same_outputs(a: &[Option<TXOutput>], b: &[Option<TXOutput>]) -> bool {
+    unspent(a).count() == unspent(b).count()
+        && unspent(a).zip(unspent(b)).all(|((a_idx, a), (b_idx, b))| {
+            a_idx == b_idx
+                && a.get_value() == b.get_value()
+                && a.get_pub_key_hash() == b.get_pub_key_hash()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use sled::IVec;
+
+    use super::*;
+    use crate::clock::system_clock;
+    use crate::test_utils::{coinbase, mine, pay, temp_chain};
+    use crate::transactions::{create_raw_transaction, OutPoint, Recipient};
+    use crate::wallet::{hash_pub_key, Wallet};
+
+    #[test]
+    fn update_fails_on_a_spent_input_and_changes_nothing() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let spent = pay(&blockchain, &miner, &Wallet::new(), 3);
+        let again = pay(&blockchain, &miner, &Wallet::new(), 4);
+        let tip = mine(&blockchain, vec![spent], &miner);
+        let block = Block::new(
+            tip.get_hash(),
+            vec![again.clone(), coinbase(&miner, 1)],
+            tip.get_height() + 1,
+            tip.get_target_bits(),
+            &*system_clock(),
+        );
+        let utxo_set = UTXOSet::new(blockchain);
+        let before = utxo_set.get_stats();
+        let input = &again.get_vin()[0];
+        assert_eq!(
+            utxo_set.update(&block),
+            Err(SpendError::MissingOutput {
+                txid: input.get_txid(),
+                vout: input.get_vout(),
+            })
+        );
+        assert_eq!(utxo_set.get_best_block(), Some(tip.get_hash()));
+        assert_eq!(utxo_set.get_stats(), before);
+    }
+
+    #[test]
+    fn connecting_and_disconnecting_blocks_matches_a_reindex() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let utxo_set = UTXOSet::new(blockchain.clone());
+        let first = mine(&blockchain, vec![], &miner);
+        let after_first = utxo_set.get_stats();
+        for amount in [3, 4] {
+            let payment = pay(&blockchain, &miner, &Wallet::new(), amount);
+            mine(&blockchain, vec![payment], &miner);
+        }
+        assert!(utxo_set.verify(|_| {}).is_consistent());
+
+        for _ in 0..2 {
+            assert!(blockchain.disconnect_tip().unwrap().is_some());
+        }
+        assert_eq!(blockchain.get_tip_hash(), first.get_hash());
+        assert_eq!(utxo_set.get_best_block(), Some(first.get_hash()));
+        assert!(utxo_set.verify(|_| {}).is_consistent());
+        assert_eq!(utxo_set.get_stats(), after_first);
+        utxo_set.reindex();
+        assert_eq!(utxo_set.get_stats(), after_first);
+    }
+
+    #[test]
+    fn outputs_created_earlier_in_a_block_can_be_spent() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let recipient = Wallet::new();
+        let tip = mine(&blockchain, vec![], &miner);
+        let payment = pay(&blockchain, &miner, &recipient, 3);
+        let unconfirmed = [(
+            OutPoint {
+                txid: payment.get_id(),
+                vout: 0,
+            },
+            payment.get_vout()[0].clone(),
+        )];
+        let recipients = [Recipient {
+            address: miner.get_address(),
+            amount: 1,
+        }];
+        let chained = Transaction::new_spending_transaction(
+            recipient.get_address().as_str(),
+            &recipients,
+            1,
+            &unconfirmed,
+            blockchain.get_params().dust_threshold,
+            &recipient,
+        )
+        .unwrap();
+        let block = Block::new(
+            tip.get_hash(),
+            vec![payment.clone(), chained.clone(), coinbase(&miner, 2)],
+            tip.get_height() + 1,
+            tip.get_target_bits(),
+            &*system_clock(),
+        );
+        let utxo_set = UTXOSet::new(blockchain);
+        let before = utxo_set.get_stats();
+
+        assert_eq!(utxo_set.update(&block), Ok(()));
+        assert!(utxo_set.get_output(payment.get_id(), 0).is_none());
+        assert!(utxo_set.get_output(payment.get_id(), 1).is_some());
+        assert!(utxo_set.get_output(chained.get_id(), 0).is_some());
+        assert_eq!(utxo_set.get_best_block(), Some(block.get_hash()));
+
+        assert!(utxo_set.disconnect(&block));
+        assert!(utxo_set.get_output(payment.get_id(), 1).is_none());
+        assert!(utxo_set.get_output(chained.get_id(), 0).is_none());
+        assert_eq!(utxo_set.get_best_block(), Some(tip.get_hash()));
+        assert_eq!(utxo_set.get_stats(), before);
+    }
+
+    #[test]
+    fn set_stored_in_an_older_format_is_rebuilt() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let tx = pay(&blockchain, &miner, &Wallet::new(), 3);
+        mine(&blockchain, vec![tx.clone()], &miner);
+        let utxo_set = UTXOSet::new(blockchain.clone());
+        utxo_set.undo_tree().remove(UTXO_FORMAT_KEY).unwrap();
+        utxo_set
+            .utxo_tree()
+            .insert(tx.get_id().as_bytes(), &b"an older encoding"[..])
+            .unwrap();
+
+        let migrated = UTXOSet::new(blockchain);
+        assert_eq!(
+            migrated
+                .undo_tree()
+                .get(UTXO_FORMAT_KEY)
+                .unwrap()
+                .as_deref(),
+            Some(&[UTXO_FORMAT][..])
+        );
+        assert_eq!(migrated.get_output(tx.get_id(), 0).unwrap().get_value(), 3);
+        assert!(migrated.verify(|_| {}).is_consistent());
+    }
+
+    /// The raw contents of the UTXO tree of `utxo_set`.
+    fn stored_bytes(utxo_set: &UTXOSet) -> Vec<(IVec, IVec)> {
+        utxo_set
+            .utxo_tree()
+            .iter()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn incremental_updates_store_the_same_set_as_a_reindex() {
+        let miner = Wallet::new();
+        let recipients = [Wallet::new(), Wallet::new(), Wallet::new()];
+        let blockchain = temp_chain(&miner);
+        let utxo_set = UTXOSet::new(blockchain.clone());
+        for (to, amount) in recipients.iter().cycle().zip((1..=5).cycle()).take(20) {
+            let payment = pay(&blockchain, &miner, to, amount);
+            mine(&blockchain, vec![payment], &miner);
+        }
+        let incremental = stored_bytes(&utxo_set);
+        utxo_set.reindex();
+        assert_eq!(stored_bytes(&utxo_set), incremental);
+
+        for _ in 0..5 {
+            assert!(blockchain.disconnect_tip().unwrap().is_some());
+        }
+        let disconnected = stored_bytes(&utxo_set);
+        utxo_set.reindex();
+        assert_eq!(stored_bytes(&utxo_set), disconnected);
+    }
+
+    #[test]
+    fn kept_trees_read_and_write_the_set_rebuilt_by_a_reindex() {
+        let miner = Wallet::new();
+        let recipient = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let utxo_set = UTXOSet::new(blockchain.clone());
+        let payment = pay(&blockchain, &miner, &recipient, 3);
+        mine(&blockchain, vec![payment], &miner);
+
+        UTXOSet::new(blockchain.clone()).reindex();
+        let pub_key_hashes = [hash_pub_key(recipient.get_public_key())];
+        assert_eq!(utxo_set.get_balances(&pub_key_hashes), vec![3]);
+        let payment = pay(&blockchain, &miner, &recipient, 4);
+        mine(&blockchain, vec![payment], &miner);
+        assert_eq!(utxo_set.get_balances(&pub_key_hashes), vec![7]);
+        assert!(utxo_set.verify(|_| {}).is_consistent());
+        assert_eq!(
+            stored_bytes(&utxo_set),
+            stored_bytes(&UTXOSet::new(blockchain))
+        );
+    }
+
+    #[test]
+    fn spend_of_a_missing_or_spent_output_is_refused() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let (first, second) = (
+            pay(&blockchain, &miner, &Wallet::new(), 3),
+            pay(&blockchain, &miner, &Wallet::new(), 4),
+        );
+        let utxo_set = UTXOSet::new(blockchain.clone());
+        assert_eq!(utxo_set.validate_tx(&second), Ok(1));
+
+        let genesis_coinbase = second.get_vin()[0].get_txid();
+        mine(&blockchain, vec![first], &miner);
+        assert_eq!(
+            utxo_set.validate_tx(&second),
+            Err(SpendError::MissingOutput {
+                txid: genesis_coinbase,
+                vout: 0
+            })
+        );
+
+        let other = UTXOSet::new(temp_chain(&miner));
+        assert_eq!(
+            other.validate_tx(&second),
+            Err(SpendError::MissingOutput {
+                txid: genesis_coinbase,
+                vout: 0
+            })
+        );
+    }
+
+    #[test]
+    fn spend_signed_by_another_key_than_the_owner_is_refused() {
+        let miner = Wallet::new();
+        let thief = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let genesis = blockchain.get_block(blockchain.get_tip_hash()).unwrap();
+        let stolen = OutPoint {
+            txid: genesis.get_transactions()[0].get_id(),
+            vout: 0,
+        };
+        let recipients = [Recipient {
+            address: thief.get_address(),
+            amount: 9,
+        }];
+        let mut tx = create_raw_transaction(&[stolen], &recipients).unwrap();
+        // Passed off as the thief's own output, which `sign_with` checks against.
+        let claimed = TXOutput::new(10, thief.get_address().as_str()).unwrap();
+        tx.sign_with(thief.get_pksc8(), &[claimed]).unwrap();
+
+        let utxo_set = UTXOSet::new(blockchain);
+        assert_eq!(utxo_set.validate_tx(&tx), Err(SpendError::InvalidSignature));
     }
 }
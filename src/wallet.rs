@@ -1,14 +1,66 @@
-use ring::rand::SystemRandom;
-use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+use std::fmt::{self, Display, Formatter};
+
+use ring::hmac;
+use ring::rand::{SecureRandom, SystemRandom};
 use serde::{Deserialize, Serialize};
 
-const VERSION: u8 = 0x00;
+use crate::config::{Network, GLOBAL_CONFIG};
+use crate::signer::SIGNATURE_LEN;
+use crate::{KeyError, SecretBytes};
+
 pub const ADDRESS_CHECK_SUM_LEN: usize = 4;
+/// The length of the public key hash an address holds, a RIPEMD-160 digest.
+const PUB_KEY_HASH_LEN: usize = crate::utils::RIPEMD160_LEN;
+/// Tags the hash of signed messages so that a message signature can never double as
+/// a [Transaction](crate::transactions::Transaction) signature.
+const SIGNED_MESSAGE_TAG: &str = "Himalia Signed Message";
+/// The entropy [`new_mnemonic`] draws, which makes a phrase of 12 words.
+const MNEMONIC_ENTROPY_LEN: usize = 16;
+
+/// A string that isn't a BIP-39 seed phrase.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MnemonicError(bip39::Error);
+
+impl Display for MnemonicError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "the seed phrase is not valid: {}", self.0)
+    }
+}
+
+impl std::error::Error for MnemonicError {}
+
+/// The reasons a string isn't a private key exported by [`Wallet::export_key`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExportedKeyError {
+    /// The key isn't Base58, or is too short to hold a checksum.
+    Malformed,
+    /// The checksum doesn't match, e.g. because a character was mistyped.
+    BadChecksum,
+    /// The key was exported on another network, or isn't a private key at all.
+    WrongVersion(u8),
+    InvalidKey(KeyError),
+}
+
+impl Display for ExportedKeyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Malformed => write!(f, "the private key is not valid Base58"),
+            Self::BadChecksum => write!(f, "the private key doesn't match its checksum"),
+            Self::WrongVersion(version) => write!(
+                f,
+                "the private key has version {version:#04x}, not the one of this network"
+            ),
+            Self::InvalidKey(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for ExportedKeyError {}
 
 /// Functionality for creating and managing wallet addresses in the blockchain system.
 #[derive(Clone, Serialize, Deserialize)]
 pub struct Wallet {
-    pkcs8: Vec<u8>,
+    pkcs8: SecretBytes,
     public_key: Vec<u8>,
 }
 
@@ -16,36 +68,97 @@ impl Wallet {
     /// Generates a new [Wallet] instance by creating a new cryptographic key pair,
     /// and extracting the public key.
     pub fn new() -> Self {
-        let pkcs8 = crate::new_key_pair();
-        let key_pair = EcdsaKeyPair::from_pkcs8(
-            &ECDSA_P256_SHA256_FIXED_SIGNING,
-            pkcs8.as_ref(),
-            &SystemRandom::new(),
-        )
-        .unwrap();
-        let public_key = key_pair.public_key().as_ref().to_vec();
-        Self { pkcs8, public_key }
+        Self::from_pkcs8(crate::new_key_pair()).expect("a generated key pair is valid")
+    }
+
+    /// Creates a [Wallet] holding an existing key pair, deriving its public key.
+    pub fn from_pkcs8(pkcs8: SecretBytes) -> Result<Self, KeyError> {
+        let public_key = crate::public_key_from_pkcs8(pkcs8.expose())?;
+        Ok(Self { pkcs8, public_key })
+    }
+
+    /// Derives the [Wallet] at `index` of `seed`, always the same one for the same
+    /// seed and index.
+    ///
+    /// The private key is the HMAC-SHA256 of the big-endian index and an attempt
+    /// counter keyed with the seed, taking the first attempt that is a valid scalar.
+    pub fn from_seed(seed: &[u8], index: u32) -> Self {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, seed);
+        (0..=u8::MAX)
+            .find_map(|attempt| {
+                let mut data = index.to_be_bytes().to_vec();
+                data.push(attempt);
+                let private_key = hmac::sign(&key, data.as_slice());
+                crate::pkcs8_from_private_key(private_key.as_ref()).ok()
+            })
+            .and_then(|pkcs8| Self::from_pkcs8(pkcs8).ok())
+            .expect("one of the attempts is a valid scalar")
     }
 
     /// Constructs an address from the [Wallet]'s public key in a Base58 format.
     pub fn get_address(&self) -> String {
         let pub_key_hash = hash_pub_key(self.public_key.as_slice());
-        let mut payload: Vec<u8> = Vec::new();
-        payload.push(VERSION);
-        payload.extend(pub_key_hash.as_slice());
-        let checksum = checksum(payload.as_slice());
-        payload.extend(checksum.as_slice());
-        crate::base58_encode(payload.as_slice())
+        convert_address(pub_key_hash.as_slice())
     }
 
     /// Retrieves the raw bytes representing the associated public key.
-    pub fn get_public_key(&self) -> &[u8] {
+    pub const fn get_public_key(&self) -> &[u8] {
         self.public_key.as_slice()
     }
 
+    /// Encodes the key pair of the [Wallet] in Base58 with a checksum, for
+    /// [`Wallet::from_exported_key`] to restore on another machine.
+    pub fn export_key(&self) -> String {
+        let version = GLOBAL_CONFIG.get_network().private_key_version();
+        let versioned = SecretBytes::new([&[version], self.pkcs8.expose()].concat());
+        let checksum = checksum(versioned.expose());
+        let payload = SecretBytes::new([versioned.expose(), checksum.as_slice()].concat());
+        crate::base58_encode(payload.expose())
+    }
+
+    /// Restores the [Wallet] of a key encoded by [`Wallet::export_key`] for the
+    /// selected [Network](crate::config::Network).
+    pub fn from_exported_key(key: &str) -> Result<Self, ExportedKeyError> {
+        let payload = SecretBytes::new(
+            crate::base58_decode(key.trim()).map_err(|_| ExportedKeyError::Malformed)?,
+        );
+        let Some(split) = payload
+            .expose()
+            .len()
+            .checked_sub(ADDRESS_CHECK_SUM_LEN + 1)
+        else {
+            return Err(ExportedKeyError::Malformed);
+        };
+        let (versioned, actual_checksum) = payload.expose().split_at(split + 1);
+        if !crate::constant_time_eq(checksum(versioned).as_slice(), actual_checksum) {
+            return Err(ExportedKeyError::BadChecksum);
+        }
+        if versioned[0] != GLOBAL_CONFIG.get_network().private_key_version() {
+            return Err(ExportedKeyError::WrongVersion(versioned[0]));
+        }
+        Self::from_pkcs8(SecretBytes::new(versioned[1..].to_vec()))
+            .map_err(ExportedKeyError::InvalidKey)
+    }
+
     /// Retrieves the raw bytes of the PKCS #8 representation of the public key.
-    pub fn get_pksc8(&self) -> &[u8] {
-        self.pkcs8.as_slice()
+    pub const fn get_pksc8(&self) -> &[u8] {
+        self.pkcs8.expose()
+    }
+
+    /// Signs an arbitrary `message` with the [Wallet]'s private key, to be checked with
+    /// [`verify_message`].
+    pub fn sign_message(&self, message: &[u8]) -> Result<Vec<u8>, KeyError> {
+        self.sign_bytes(&signed_message(message))
+    }
+
+    /// Signs `message` with the [Wallet]'s private key, deterministically when
+    /// `DETERMINISTIC_SIGNING` is set.
+    pub fn sign_bytes(&self, message: &[u8]) -> Result<Vec<u8>, KeyError> {
+        if GLOBAL_CONFIG.get_deterministic_signing() {
+            crate::ecdsa_p256_sha256_sign_deterministic(self.pkcs8.expose(), message)
+        } else {
+            crate::ecdsa_p256_sha256_sign_digest(self.pkcs8.expose(), message)
+        }
     }
 }
 
@@ -55,40 +168,279 @@ impl Default for Wallet {
     }
 }
 
+/// Generates a BIP-39 seed phrase of 12 words from the system's secure random
+/// number generator, for [`mnemonic_to_seed`].
+pub fn new_mnemonic() -> String {
+    let mut entropy = vec![0; MNEMONIC_ENTROPY_LEN];
+    SystemRandom::new().fill(&mut entropy).unwrap();
+    let entropy = SecretBytes::new(entropy);
+    bip39::Mnemonic::from_entropy(entropy.expose())
+        .expect("16 bytes are a valid amount of entropy")
+        .to_string()
+}
+
+/// Derives the 64-byte seed of a BIP-39 seed phrase without a passphrase, checking
+/// its words and its checksum.
+pub fn mnemonic_to_seed(mnemonic: &str) -> Result<SecretBytes, MnemonicError> {
+    let mnemonic = bip39::Mnemonic::parse_in(bip39::Language::English, mnemonic.trim())
+        .map_err(MnemonicError)?;
+    Ok(SecretBytes::new(mnemonic.to_seed("").to_vec()))
+}
+
+/// Checks that `signature` was made over `message` by the key behind `public_key`,
+/// and that `public_key` belongs to `address`.
+pub fn verify_message(address: &str, message: &[u8], signature: &[u8], public_key: &[u8]) -> bool {
+    convert_address(hash_pub_key(public_key).as_slice()) == address
+        && crate::ecdsa_p256_sha256_sign_verify(public_key, signature, &signed_message(message))
+}
+
+/// Packs the `public_key` of the signer and the `signature` of a message in a single
+/// Base58 string, so that [`verify_message_envelope`] needs nothing but the address.
+pub fn encode_message_envelope(public_key: &[u8], signature: &[u8]) -> String {
+    crate::base58_encode([public_key, signature].concat().as_slice())
+}
+
+/// Like [`verify_message`], given an envelope made by [`encode_message_envelope`]
+/// instead of the signature and the public key.
+pub fn verify_message_envelope(address: &str, message: &[u8], envelope: &str) -> bool {
+    let Ok(envelope) = crate::base58_decode(envelope) else {
+        return false;
+    };
+    let Some(split) = envelope.len().checked_sub(SIGNATURE_LEN) else {
+        return false;
+    };
+    let (public_key, signature) = envelope.split_at(split);
+    verify_message(address, message, signature, public_key)
+}
+
+fn signed_message(message: &[u8]) -> [u8; 32] {
+    crate::tagged_hash(SIGNED_MESSAGE_TAG, message)
+}
+
 /// Hashes the given public key using SHA-256 and then RIPEMD-160 hash functions.
 pub fn hash_pub_key(pub_key: &[u8]) -> Vec<u8> {
-    let pub_key_sha256 = crate::sha256_digest(pub_key);
-    crate::ripemd160_digest(pub_key_sha256.as_slice())
+    crate::hash160(pub_key)
 }
 
 /// Generates a checksum for a payload by applying a double SHA256 hash and
-/// extracting the first byte.
+/// extracting the first bytes.
 fn checksum(payload: &[u8]) -> Vec<u8> {
-    let first_sha = crate::sha256_digest(payload);
-    let second_sha = crate::sha256_digest(first_sha.as_slice());
-    second_sha[0..ADDRESS_CHECK_SUM_LEN].to_vec()
+    crate::sha256d(payload)[0..ADDRESS_CHECK_SUM_LEN].to_vec()
 }
 
 /// Validates the integrity of an address by decoding it, separating its components,
-/// and recomputing the checksum.
+/// and recomputing the checksum. Addresses of another [Network] are rejected.
 pub fn validate_address(address: &str) -> bool {
-    let payload = crate::base58_decode(address);
-    let actual_checksum = payload[payload.len() - ADDRESS_CHECK_SUM_LEN..].to_vec();
-    let version = payload[0];
-    let pub_key_hash = payload[1..payload.len() - ADDRESS_CHECK_SUM_LEN].to_vec();
-    let mut target_vec = Vec::new();
-    target_vec.push(version);
-    target_vec.extend(pub_key_hash);
-    let target_checksum = checksum(target_vec.as_slice());
-    actual_checksum.eq(target_checksum.as_slice())
-}
-
-/// Converts a public key hash into a Base58 encoded address.
+    address_pub_key_hash(address).is_some()
+}
+
+/// Extracts the public key hash from `address`, or `None` when it isn't a valid
+/// address of the selected [Network].
+pub fn address_pub_key_hash(address: &str) -> Option<Vec<u8>> {
+    address_pub_key_hash_for(address, GLOBAL_CONFIG.get_network())
+}
+
+/// Like [`address_pub_key_hash`] for addresses of `network` instead of the selected one.
+pub fn address_pub_key_hash_for(address: &str, network: Network) -> Option<Vec<u8>> {
+    let payload = crate::base58_decode(address).ok()?;
+    if payload.len() != 1 + PUB_KEY_HASH_LEN + ADDRESS_CHECK_SUM_LEN {
+        return None;
+    }
+    let (versioned, actual_checksum) = payload.split_at(payload.len() - ADDRESS_CHECK_SUM_LEN);
+    if versioned[0] != network.address_version() {
+        return None;
+    }
+    if !crate::constant_time_eq(checksum(versioned).as_slice(), actual_checksum) {
+        return None;
+    }
+    Some(versioned[1..].to_vec())
+}
+
+/// Converts a public key hash into a Base58 encoded address for the selected [Network].
 pub fn convert_address(pub_hash_key: &[u8]) -> String {
+    convert_address_for(pub_hash_key, GLOBAL_CONFIG.get_network())
+}
+
+/// Like [`convert_address`] for `network` instead of the selected [Network].
+pub fn convert_address_for(pub_hash_key: &[u8], network: Network) -> String {
     let mut payload: Vec<u8> = vec![];
-    payload.push(VERSION);
+    payload.push(network.address_version());
     payload.extend(pub_hash_key);
     let checksum = checksum(payload.as_slice());
     payload.extend(checksum.as_slice());
     crate::base58_encode(payload.as_slice())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::regtest;
+
+    /// The phrase of the all-zero entropy, the first vector of BIP-39.
+    const ZERO_MNEMONIC: &str = "abandon abandon abandon abandon abandon abandon abandon \
+                                 abandon abandon abandon abandon about";
+
+    #[test]
+    fn mnemonic_derives_the_bip39_seed() {
+        let seed = mnemonic_to_seed(ZERO_MNEMONIC).unwrap();
+        assert_eq!(
+            data_encoding::HEXLOWER.encode(seed.expose()),
+            "5eb00bbddcf069084889a8ab9155568165f5c453ccb85e70811aaed6f6da5fc1\
+             9a5ac40b389cd370d086206dec8aa6c43daea6690f20ad3d8d48b2d2ce9e38e4"
+        );
+    }
+
+    #[test]
+    fn mnemonics_with_a_bad_checksum_or_word_are_refused() {
+        let bad_checksum = ZERO_MNEMONIC.replace("about", "abandon");
+        assert!(mnemonic_to_seed(bad_checksum.as_str()).is_err());
+        let bad_word = ZERO_MNEMONIC.replace("about", "aboot");
+        assert!(mnemonic_to_seed(bad_word.as_str()).is_err());
+    }
+
+    #[test]
+    fn new_mnemonics_are_twelve_valid_words() {
+        let mnemonic = new_mnemonic();
+        assert_eq!(mnemonic.split_whitespace().count(), 12);
+        assert!(mnemonic_to_seed(mnemonic.as_str()).is_ok());
+        assert_ne!(mnemonic, new_mnemonic());
+    }
+
+    #[test]
+    fn seeds_derive_pinned_addresses() {
+        regtest();
+        let seed = mnemonic_to_seed(ZERO_MNEMONIC).unwrap();
+        let addresses: Vec<_> = (0..3)
+            .map(|index| Wallet::from_seed(seed.expose(), index).get_address())
+            .collect();
+        assert_eq!(
+            addresses,
+            [
+                "r9cDy7iP6zb2eJrpGt5yT9BrtNkcqvh6GY",
+                "rLizhdBu1hJLwYZZow1XL9XHYRcLiFpYxG",
+                "rTCqAR7o9He6qhK4mgS2Zwmoz2z5e9QGw3",
+            ]
+        );
+    }
+
+    /// Encodes `version` and `pub_key_hash` like [`convert_address`], with the checksum
+    /// passed through `tamper`.
+    fn address_with(version: u8, pub_key_hash: &[u8], tamper: impl Fn(&mut Vec<u8>)) -> String {
+        let mut payload = vec![version];
+        payload.extend(pub_key_hash);
+        let mut checksum = checksum(payload.as_slice());
+        tamper(&mut checksum);
+        payload.extend(checksum);
+        crate::base58_encode(payload.as_slice())
+    }
+
+    #[test]
+    fn only_well_formed_addresses_of_the_network_are_valid() {
+        regtest();
+        let wallet = Wallet::new();
+        let address = wallet.get_address();
+        let pub_key_hash = hash_pub_key(wallet.get_public_key());
+        let version = GLOBAL_CONFIG.get_network().address_version();
+        let cases = [
+            ("round-tripped", address.clone(), true),
+            ("empty", String::new(), false),
+            ("not base58", String::from("0OIl"), false),
+            ("too short", String::from("abc"), false),
+            (
+                "truncated",
+                String::from(&address[..address.len() - 1]),
+                false,
+            ),
+            (
+                "short hash",
+                address_with(version, &pub_key_hash[1..], |_| {}),
+                false,
+            ),
+            (
+                "long hash",
+                address_with(version, &[pub_key_hash.as_slice(), &[0]].concat(), |_| {}),
+                false,
+            ),
+            (
+                "wrong checksum",
+                address_with(version, &pub_key_hash, |checksum| checksum[0] ^= 1),
+                false,
+            ),
+            (
+                "wrong version",
+                address_with(version ^ 1, &pub_key_hash, |_| {}),
+                false,
+            ),
+        ];
+        for (name, address, valid) in cases {
+            assert_eq!(validate_address(address.as_str()), valid, "{name}");
+            let expected = valid.then(|| pub_key_hash.clone());
+            assert_eq!(address_pub_key_hash(address.as_str()), expected, "{name}");
+        }
+    }
+
+    #[test]
+    fn message_signature_verifies_only_unchanged_and_for_its_address() {
+        regtest();
+        let (signer, other) = (Wallet::new(), Wallet::new());
+        let (address, public_key) = (signer.get_address(), signer.get_public_key());
+        let signature = signer.sign_message(b"hello").unwrap();
+        assert!(verify_message(&address, b"hello", &signature, public_key));
+
+        assert!(!verify_message(&address, b"hello!", &signature, public_key));
+        assert!(!verify_message(
+            &other.get_address(),
+            b"hello",
+            &signature,
+            public_key
+        ));
+        assert!(!verify_message(
+            &address,
+            b"hello",
+            &signature,
+            other.get_public_key()
+        ));
+        let mut tampered = signature.clone();
+        tampered[0] ^= 1;
+        assert!(!verify_message(&address, b"hello", &tampered, public_key));
+
+        let envelope = encode_message_envelope(public_key, &signature);
+        assert!(verify_message_envelope(&address, b"hello", &envelope));
+        assert!(!verify_message_envelope(&address, b"hello!", &envelope));
+        let tampered = encode_message_envelope(public_key, &tampered);
+        assert!(!verify_message_envelope(&address, b"hello", &tampered));
+        assert!(!verify_message_envelope(&address, b"hello", "not base58"));
+    }
+
+    #[test]
+    fn random_strings_are_refused_without_panicking() {
+        regtest();
+        let alphabet: Vec<char> =
+            "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz0OIl+/ \u{e9}\u{1f600}"
+                .chars()
+                .collect();
+        let valid = Wallet::new().get_address();
+        for _ in 0..10_000 {
+            let len = usize::try_from(crate::random_u64() % 48).unwrap();
+            let typed: String = (0..len)
+                .map(|_| {
+                    let idx = crate::random_u64() % alphabet.len() as u64;
+                    alphabet[usize::try_from(idx).unwrap()]
+                })
+                .collect();
+            let bytes: Vec<u8> = (0..len)
+                .map(|_| crate::random_u64().to_le_bytes()[0])
+                .collect();
+            let encoded = crate::base58_encode(bytes.as_slice());
+            // Swaps one character of a valid address for another.
+            let mut typo: Vec<char> = valid.chars().collect();
+            let idx = crate::random_u64() % typo.len() as u64;
+            typo[usize::try_from(idx).unwrap()] = alphabet[len % alphabet.len()];
+            let typo: String = typo.into_iter().collect();
+
+            assert!(!validate_address(typed.as_str()), "{typed}");
+            assert!(!validate_address(encoded.as_str()), "{encoded}");
+            assert_eq!(validate_address(typo.as_str()), typo == valid, "{typo}");
+        }
+    }
+}
@@ -1,72 +1,548 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display, Formatter};
 use std::io::{BufWriter, Read, Write};
-use std::{collections::HashMap, env::current_dir};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use std::{error::Error, fs};
 
 use std::fs::{File, OpenOptions};
 
-use crate::wallet::Wallet;
+use log::info;
+use serde::{Deserialize, Serialize};
 
-pub const WALLET_FILE: &str = "wallet.dat";
+use crate::blockchain::Blockchain;
+use crate::config::{Config, GLOBAL_CONFIG};
+use crate::memory_pool::MempoolSnapshot;
+use crate::wallet::{address_pub_key_hash, hash_pub_key, mnemonic_to_seed, MnemonicError, Wallet};
+use crate::{current_timestamp, summary::AddressBalance, utxo_set::UTXOSet, SecretBytes};
+
+/// Marks a wallet file in the versioned [`WalletFile`] format. Files without it hold
+/// the bare address to [Wallet] map written by earlier versions.
+const WALLET_FILE_MAGIC: &[u8] = b"HMWALLET";
+/// The number of consecutive unused addresses after which [`Wallets::recover`] stops
+/// deriving more by default.
+pub const DEFAULT_GAP_LIMIT: u32 = 20;
+
+/// The reasons a hierarchical deterministic [Wallet] can't be derived.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WalletError {
+    InvalidMnemonic(MnemonicError),
+    /// The wallet file already derives its addresses from another seed.
+    SeedExists,
+    /// The wallet file has no seed to derive addresses from.
+    NoSeed,
+    /// An address to watch is not a valid address of the selected network.
+    InvalidAddress(String),
+    /// An address to watch already has its key in the wallet file.
+    HasKey(String),
+    /// The address is neither in the wallet file nor watched.
+    UnknownAddress(String),
+    /// Another address already has the label.
+    LabelTaken(String),
+    /// Several addresses share the label, in wallet files written before labels had
+    /// to be unique.
+    AmbiguousLabel {
+        label: String,
+        addresses: Vec<String>,
+    },
+}
+
+impl Display for WalletError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidMnemonic(e) => write!(f, "{e}"),
+            Self::SeedExists => write!(f, "the wallet already has a different seed"),
+            Self::NoSeed => write!(
+                f,
+                "the wallet has no seed, create one with `createwallet --hd` first"
+            ),
+            Self::InvalidAddress(address) => write!(f, "address `{address}` is not valid"),
+            Self::HasKey(address) => {
+                write!(f, "the wallet already holds the key of `{address}`")
+            }
+            Self::UnknownAddress(address) => {
+                write!(f, "address `{address}` is not in the wallet")
+            }
+            Self::LabelTaken(label) => {
+                write!(f, "the label `{label}` is already used by another address")
+            }
+            Self::AmbiguousLabel { label, addresses } => write!(
+                f,
+                "the label `{label}` is shared by {}, use an address instead",
+                addresses.join(", ")
+            ),
+        }
+    }
+}
+
+impl std::error::Error for WalletError {}
+
+impl From<MnemonicError> for WalletError {
+    fn from(e: MnemonicError) -> Self {
+        Self::InvalidMnemonic(e)
+    }
+}
+
+/// A [Wallet] with the metadata kept alongside it.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WalletEntry {
+    pub wallet: Wallet,
+    pub label: Option<String>,
+    /// Milliseconds since the Unix epoch, zero for wallets migrated from the old format.
+    pub created_at: i64,
+}
+
+/// An address whose key is kept elsewhere, tracked without being able to spend from
+/// it.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WatchOnlyEntry {
+    pub pub_key_hash: Vec<u8>,
+    pub label: Option<String>,
+    /// Milliseconds since the Unix epoch.
+    pub created_at: i64,
+}
+
+/// The seed hierarchical deterministic [Wallet]s are derived from, and the index of
+/// the next one.
+#[derive(Clone, Serialize, Deserialize)]
+struct HdChain {
+    seed: SecretBytes,
+    next_index: u32,
+}
+
+/// The contents of the wallet file after [`WALLET_FILE_MAGIC`].
+#[derive(Serialize, Deserialize)]
+enum WalletFile {
+    V1 {
+        entries: HashMap<String, WalletEntry>,
+    },
+    V2 {
+        entries: HashMap<String, WalletEntry>,
+        hd: Option<HdChain>,
+    },
+    V3 {
+        entries: HashMap<String, WalletEntry>,
+        hd: Option<HdChain>,
+        watch_only: HashMap<String, WatchOnlyEntry>,
+    },
+}
 
 /// Functionality to manage a collection of wallets within the blockchain.
-pub struct Wallets(HashMap<String, Wallet>);
+pub struct Wallets {
+    entries: HashMap<String, WalletEntry>,
+    hd: Option<HdChain>,
+    watch_only: HashMap<String, WatchOnlyEntry>,
+    path: PathBuf,
+    backup_dir: PathBuf,
+}
 
 impl Wallets {
-    /// Initializes a new [Wallets] instance by attempting to load wallets from a file.
+    /// Initializes a new [Wallets] instance from the wallet file in the global [Config].
     pub fn new() -> Self {
-        let mut wallets = Self(HashMap::new());
+        Self::from_config(&GLOBAL_CONFIG)
+    }
+
+    /// Initializes a new [Wallets] instance by attempting to load wallets from the
+    /// wallet file configured in `config`, migrating a legacy wallet file first.
+    pub fn from_config(config: &Config) -> Self {
+        let path = config.get_wallet_path();
+        if !config.has_wallet_override() {
+            migrate_legacy_file(config.get_legacy_wallet_path().as_path(), path.as_path());
+        }
+        let mut wallets = Self {
+            entries: HashMap::new(),
+            hd: None,
+            watch_only: HashMap::new(),
+            path,
+            backup_dir: config.get_wallet_backup_dir(),
+        };
         wallets.load_from_file();
         wallets
     }
 
     /// Generates a new [Wallet].
     pub fn create_wallet(&mut self) -> String {
-        let wallet = Wallet::new();
-        let address = wallet.get_address();
-        self.0.insert(address.clone(), wallet);
+        let address = self.add_wallets(&[None]).remove(0);
         self.save_to_file();
         address
     }
 
+    /// Generates `count` new [Wallet]s, saving the file once at the end.
+    ///
+    /// With more than one wallet, each `label` is suffixed with `-1`, `-2` and so on.
+    /// Nothing is generated when one of the labels is already in use.
+    pub fn create_wallets(
+        &mut self,
+        count: usize,
+        label: Option<&str>,
+    ) -> Result<Vec<String>, WalletError> {
+        let labels = new_labels(count, label);
+        self.check_labels_free(labels.as_slice())?;
+        let addresses = self.add_wallets(labels.as_slice());
+        self.save_to_file();
+        Ok(addresses)
+    }
+
+    /// Generates a [Wallet] for each of `labels`.
+    fn add_wallets(&mut self, labels: &[Option<String>]) -> Vec<String> {
+        let created_at = current_timestamp();
+        let mut addresses = Vec::with_capacity(labels.len());
+        for label in labels {
+            let wallet = Wallet::new();
+            let address = wallet.get_address();
+            let entry = WalletEntry {
+                wallet,
+                label: label.clone(),
+                created_at,
+            };
+            self.entries.insert(address.clone(), entry);
+            addresses.push(address);
+        }
+        addresses
+    }
+
+    /// Sets the seed of the hierarchical deterministic [Wallet]s to the one of
+    /// `mnemonic`, deriving from its first index. Setting the seed already in use
+    /// changes nothing.
+    pub fn create_hd(&mut self, mnemonic: &str) -> Result<(), WalletError> {
+        let seed = mnemonic_to_seed(mnemonic)?;
+        match &self.hd {
+            Some(hd) if hd.seed.expose() == seed.expose() => Ok(()),
+            Some(_) => Err(WalletError::SeedExists),
+            None => {
+                self.hd = Some(HdChain {
+                    seed,
+                    next_index: 0,
+                });
+                Ok(())
+            }
+        }
+    }
+
+    /// Whether the wallet file has a seed to derive [Wallet]s from.
+    pub const fn has_seed(&self) -> bool {
+        self.hd.is_some()
+    }
+
+    /// Derives the [Wallet] at the next index of the seed and adds it.
+    pub fn derive_next_address(&mut self, label: Option<&str>) -> Result<String, WalletError> {
+        self.check_labels_free(&[label.map(String::from)])?;
+        let hd = self.hd.as_mut().ok_or(WalletError::NoSeed)?;
+        let wallet = Wallet::from_seed(hd.seed.expose(), hd.next_index);
+        hd.next_index += 1;
+        let address = wallet.get_address();
+        let entry = WalletEntry {
+            wallet,
+            label: label.map(String::from),
+            created_at: current_timestamp(),
+        };
+        self.entries.insert(address.clone(), entry);
+        Ok(address)
+    }
+
+    /// Like [`Wallets::derive_next_address`] `count` times, with labels suffixed as
+    /// in [`Wallets::create_wallets`].
+    fn derive_wallets(
+        &mut self,
+        count: usize,
+        label: Option<&str>,
+    ) -> Result<Vec<String>, WalletError> {
+        let labels = new_labels(count, label);
+        self.check_labels_free(labels.as_slice())?;
+        labels
+            .iter()
+            .map(|label| self.derive_next_address(label.as_deref()))
+            .collect()
+    }
+
+    /// Restores the seed of `mnemonic` and adds every [Wallet] derived from it that
+    /// has received funds on `blockchain`, returning their addresses.
+    ///
+    /// Indices are derived in order until `gap_limit` consecutive ones have never
+    /// been paid, and the next index is set past the last one that was.
+    pub fn recover(
+        &mut self,
+        mnemonic: &str,
+        blockchain: &Blockchain,
+        gap_limit: u32,
+    ) -> Result<Vec<String>, WalletError> {
+        self.create_hd(mnemonic)?;
+        let mut used = HashSet::new();
+        let mut iterator = blockchain.iterator();
+        while let Some(block) = iterator.next() {
+            for tx in block.get_transactions() {
+                for output in tx.get_vout() {
+                    used.insert(output.get_pub_key_hash().to_vec());
+                }
+            }
+        }
+        let hd = self.hd.as_mut().ok_or(WalletError::NoSeed)?;
+        let mut recovered = vec![];
+        let mut index = 0;
+        let mut unused = 0;
+        while unused < gap_limit {
+            let wallet = Wallet::from_seed(hd.seed.expose(), index);
+            index += 1;
+            if !used.contains(&hash_pub_key(wallet.get_public_key())) {
+                unused += 1;
+                continue;
+            }
+            unused = 0;
+            hd.next_index = hd.next_index.max(index);
+            let address = wallet.get_address();
+            self.entries.entry(address.clone()).or_insert(WalletEntry {
+                wallet,
+                label: None,
+                created_at: current_timestamp(),
+            });
+            recovered.push(address);
+        }
+        Ok(recovered)
+    }
+
+    /// Adds the [Wallet] of an imported key, returning `false` without changing anything
+    /// when its address already has its key. A watched address stops being watch-only.
+    pub fn import_key(&mut self, wallet: Wallet, label: Option<&str>) -> Result<bool, WalletError> {
+        let address = wallet.get_address();
+        if self.entries.contains_key(address.as_str()) {
+            return Ok(false);
+        }
+        self.check_label_free(address.as_str(), label)?;
+        let watched = self.watch_only.remove(address.as_str());
+        let entry = WalletEntry {
+            wallet,
+            label: label
+                .map(String::from)
+                .or_else(|| watched.and_then(|entry| entry.label)),
+            created_at: current_timestamp(),
+        };
+        self.entries.insert(address, entry);
+        Ok(true)
+    }
+
+    /// Watches `address` without its key, or relabels it when it is watched already.
+    pub fn import_address(
+        &mut self,
+        address: &str,
+        label: Option<&str>,
+    ) -> Result<(), WalletError> {
+        let pub_key_hash = address_pub_key_hash(address)
+            .ok_or_else(|| WalletError::InvalidAddress(String::from(address)))?;
+        if self.entries.contains_key(address) {
+            return Err(WalletError::HasKey(String::from(address)));
+        }
+        self.check_label_free(address, label)?;
+        let entry = WatchOnlyEntry {
+            pub_key_hash,
+            label: label.map(String::from),
+            created_at: current_timestamp(),
+        };
+        self.watch_only.insert(String::from(address), entry);
+        Ok(())
+    }
+
     /// Retrieves all addresses associated with the [Wallet]s.
     pub fn get_addresses(&self) -> Vec<String> {
         let mut addresses = vec![];
-        for address in self.0.keys() {
+        for address in self.entries.keys() {
             addresses.push(address.clone());
         }
         addresses
     }
 
+    /// Retrieves the addresses watched without their key.
+    pub fn get_watch_only_addresses(&self) -> Vec<String> {
+        self.watch_only.keys().cloned().collect()
+    }
+
+    pub fn is_watch_only(&self, address: &str) -> bool {
+        self.watch_only.contains_key(address)
+    }
+
     /// Retrieves a reference to a [Wallet] by its address.
     pub fn get_wallet(&self, address: &str) -> Option<&Wallet> {
-        self.0.get(address)
+        self.entries.get(address).map(|entry| &entry.wallet)
+    }
+
+    /// Retrieves the [Wallet] at `address` along with its metadata.
+    pub fn get_entry(&self, address: &str) -> Option<&WalletEntry> {
+        self.entries.get(address)
+    }
+
+    pub fn get_label(&self, address: &str) -> Option<&str> {
+        match self.entries.get(address) {
+            Some(entry) => entry.label.as_deref(),
+            None => self.watch_only.get(address)?.label.as_deref(),
+        }
+    }
+
+    /// Labels `address`, with or without its key, or removes its label when `label`
+    /// is `None`.
+    pub fn set_label(&mut self, address: &str, label: Option<&str>) -> Result<(), WalletError> {
+        self.check_label_free(address, label)?;
+        let slot = match self.entries.get_mut(address) {
+            Some(entry) => &mut entry.label,
+            None => {
+                &mut self
+                    .watch_only
+                    .get_mut(address)
+                    .ok_or_else(|| WalletError::UnknownAddress(String::from(address)))?
+                    .label
+            }
+        };
+        *slot = label.map(String::from);
+        Ok(())
+    }
+
+    /// Finds the address labelled `label`, with or without its key.
+    pub fn get_by_label(&self, label: &str) -> Result<Option<String>, WalletError> {
+        let mut addresses: Vec<String> = self
+            .entries
+            .iter()
+            .filter_map(|(address, entry)| {
+                (entry.label.as_deref() == Some(label)).then_some(address)
+            })
+            .chain(self.watch_only.iter().filter_map(|(address, entry)| {
+                (entry.label.as_deref() == Some(label)).then_some(address)
+            }))
+            .cloned()
+            .collect();
+        match addresses.len() {
+            0 | 1 => Ok(addresses.pop()),
+            _ => {
+                addresses.sort();
+                Err(WalletError::AmbiguousLabel {
+                    label: String::from(label),
+                    addresses,
+                })
+            }
+        }
+    }
+
+    /// Fails when another address than `address` already has `label`.
+    fn check_label_free(&self, address: &str, label: Option<&str>) -> Result<(), WalletError> {
+        let Some(label) = label else {
+            return Ok(());
+        };
+        match self.get_by_label(label) {
+            Ok(None) => Ok(()),
+            Ok(Some(labelled)) if labelled == address => Ok(()),
+            _ => Err(WalletError::LabelTaken(String::from(label))),
+        }
+    }
+
+    /// Fails when any of `labels`, meant for new addresses, is already in use.
+    fn check_labels_free(&self, labels: &[Option<String>]) -> Result<(), WalletError> {
+        labels
+            .iter()
+            .flatten()
+            .find(|label| !matches!(self.get_by_label(label), Ok(None)))
+            .map_or(Ok(()), |label| Err(WalletError::LabelTaken(label.clone())))
+    }
+
+    /// Returns the [`AddressBalance`] of every address, watch-only ones included, sorted
+    /// by confirmed balance with the largest first, taking pending funds from `mempool`
+    /// when given.
+    pub fn get_balances(
+        &self,
+        utxo_set: &UTXOSet,
+        mempool: Option<&MempoolSnapshot>,
+    ) -> Vec<AddressBalance> {
+        let mut addresses = self.get_addresses();
+        addresses.extend(self.get_watch_only_addresses());
+        let pub_key_hashes: Vec<Vec<u8>> = addresses
+            .iter()
+            .map(|address| {
+                self.entries.get(address).map_or_else(
+                    || self.watch_only[address].pub_key_hash.clone(),
+                    |entry| hash_pub_key(entry.wallet.get_public_key()),
+                )
+            })
+            .collect();
+        let confirmed = utxo_set.get_balances(pub_key_hashes.as_slice());
+        let mut balances: Vec<AddressBalance> = addresses
+            .into_iter()
+            .zip(confirmed)
+            .map(|(address, confirmed)| {
+                let (pending_in, pending_out) =
+                    mempool.map_or((0, 0), |mempool| mempool.get_pending(address.as_str()));
+                AddressBalance {
+                    label: self.get_label(address.as_str()).map(String::from),
+                    watch_only: self.is_watch_only(address.as_str()),
+                    address,
+                    confirmed,
+                    pending_in,
+                    pending_out,
+                }
+            })
+            .collect();
+        balances.sort_by(|a, b| {
+            b.confirmed
+                .cmp(&a.confirmed)
+                .then_with(|| a.address.cmp(&b.address))
+        });
+        balances
+    }
+
+    /// Returns the location of the wallet file.
+    pub fn get_path(&self) -> &Path {
+        self.path.as_path()
     }
 
     /// Attempts to load [Wallets] data from a file.
     pub fn load_from_file(&mut self) {
-        let path = current_dir().unwrap().join(WALLET_FILE);
-        if !path.exists() {
+        if !self.path.exists() {
             return;
         }
-        let mut file = File::open(path).unwrap();
+        let mut file = File::open(&self.path).unwrap();
         let metadata = file.metadata().expect("unable to read metadata");
         let mut buf = vec![0; usize::try_from(metadata.len()).unwrap()];
-        let _ = file.read(&mut buf).expect("buffer overflow");
-        self.0 = bincode::deserialize(&buf[..]).expect("unable to deserialize file data");
+        file.read_exact(&mut buf)
+            .expect("unable to read the wallet file");
+        let buf = SecretBytes::new(buf);
+        (self.entries, self.hd, self.watch_only) = parse_wallet_file(buf.expose());
     }
 
-    /// Saves the contents of the [Wallets] map into a file.
+    /// Saves the contents of the [Wallets] map into a file, writing a temporary file
+    /// first so that an interrupted save never leaves a partial wallet behind.
     fn save_to_file(&self) {
-        let path = current_dir().unwrap().join(WALLET_FILE);
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).expect("unable to create the wallet directory");
+        }
+        let tmp_path = self.path.with_extension("dat.tmp");
         let file = OpenOptions::new()
             .create(true)
             .truncate(true)
             .write(true)
-            .open(&path)
-            .expect("unable to open wallet.dat");
+            .open(&tmp_path)
+            .expect("unable to open the wallet file");
         let mut writer = BufWriter::new(file);
-        let wallets_bytes = bincode::serialize(&self.0).expect("unable to serialize wallets");
-        writer.write_all(wallets_bytes.as_slice()).unwrap();
-        let _ = writer.flush();
+        let wallet_file = WalletFile::V3 {
+            entries: self.entries.clone(),
+            hd: self.hd.clone(),
+            watch_only: self.watch_only.clone(),
+        };
+        let wallets_bytes = SecretBytes::new(
+            bincode::serialize(&wallet_file).expect("unable to serialize wallets"),
+        );
+        writer.write_all(WALLET_FILE_MAGIC).unwrap();
+        writer.write_all(wallets_bytes.expose()).unwrap();
+        let file = writer
+            .into_inner()
+            .expect("unable to write the wallet file");
+        file.sync_all().expect("unable to write the wallet file");
+        fs::rename(tmp_path, &self.path).expect("unable to replace the wallet file");
+    }
+
+    /// Copies the wallet file into `dir`, or the configured backup directory when
+    /// `dir` is `None`, returning the path of the backup.
+    pub fn backup(&self, dir: Option<&Path>) -> Result<PathBuf, Box<dyn Error>> {
+        let dir = dir.unwrap_or(self.backup_dir.as_path());
+        fs::create_dir_all(dir)?;
+        let backup_path = dir.join(format!("wallet-{}.dat", current_timestamp()));
+        fs::copy(self.path.as_path(), backup_path.as_path())?;
+        Ok(backup_path)
     }
 }
 
@@ -75,3 +551,390 @@ impl Default for Wallets {
         Self::new()
     }
 }
+
+/// The modification time and length of the wallet file when it was last read or
+/// written, `None` while there is no file.
+type FileStamp = Option<(SystemTime, u64)>;
+
+fn file_stamp(path: &Path) -> FileStamp {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+struct StoreState {
+    wallets: Wallets,
+    stamp: FileStamp,
+}
+
+impl StoreState {
+    fn is_current(&self) -> bool {
+        file_stamp(self.wallets.get_path()) == self.stamp
+    }
+
+    /// Reads the wallet file again when another process changed it since.
+    fn refresh(&mut self) {
+        if !self.is_current() {
+            self.wallets.load_from_file();
+            self.stamp = file_stamp(self.wallets.get_path());
+        }
+    }
+}
+
+/// A handle to [Wallets] shared between threads, cloned cheaply.
+///
+/// Every change is a read, modify and save made while holding the lock, so that two
+/// of them can't interleave and lose a [Wallet]. The file is read again before use
+/// whenever another process modified it.
+#[derive(Clone)]
+pub struct WalletStore {
+    state: Arc<RwLock<StoreState>>,
+}
+
+impl WalletStore {
+    /// Opens the wallet file in the global [Config].
+    pub fn new() -> Self {
+        Self::from_config(&GLOBAL_CONFIG)
+    }
+
+    /// Opens the wallet file configured in `config`, see [`Wallets::from_config`].
+    pub fn from_config(config: &Config) -> Self {
+        let wallets = Wallets::from_config(config);
+        let stamp = file_stamp(wallets.get_path());
+        Self {
+            state: Arc::new(RwLock::new(StoreState { wallets, stamp })),
+        }
+    }
+
+    /// Calls `f` with the [Wallets] as they are on disk now.
+    pub fn read<R>(&self, f: impl FnOnce(&Wallets) -> R) -> R {
+        {
+            let state = self.state.read().unwrap();
+            if state.is_current() {
+                return f(&state.wallets);
+            }
+        }
+        let mut state = self.state.write().unwrap();
+        state.refresh();
+        f(&state.wallets)
+    }
+
+    /// Calls `f` with the [Wallets] as they are on disk now and saves its changes,
+    /// without any other change made through this handle in between.
+    pub fn update<R>(&self, f: impl FnOnce(&mut Wallets) -> R) -> R {
+        let mut state = self.state.write().unwrap();
+        state.refresh();
+        let result = f(&mut state.wallets);
+        state.wallets.save_to_file();
+        state.stamp = file_stamp(state.wallets.get_path());
+        result
+    }
+
+    /// Generates a new [Wallet] and saves it.
+    pub fn create_wallet(&self) -> String {
+        self.update(|wallets| wallets.add_wallets(&[None]))
+            .remove(0)
+    }
+
+    /// Generates `count` new [Wallet]s and saves them, see [`Wallets::create_wallets`].
+    pub fn create_wallets(
+        &self,
+        count: usize,
+        label: Option<&str>,
+    ) -> Result<Vec<String>, WalletError> {
+        self.update(|wallets| {
+            let labels = new_labels(count, label);
+            wallets.check_labels_free(labels.as_slice())?;
+            Ok(wallets.add_wallets(labels.as_slice()))
+        })
+    }
+
+    /// Derives `count` new [Wallet]s from the seed and saves them, creating the seed
+    /// of `mnemonic` first when given, see [`Wallets::create_hd`].
+    pub fn derive_wallets(
+        &self,
+        mnemonic: Option<&str>,
+        count: usize,
+        label: Option<&str>,
+    ) -> Result<Vec<String>, WalletError> {
+        self.update(|wallets| {
+            // Checked before the seed is stored, so that it's never kept without
+            // its seed phrase being shown.
+            wallets.check_labels_free(new_labels(count, label).as_slice())?;
+            if let Some(mnemonic) = mnemonic {
+                wallets.create_hd(mnemonic)?;
+            }
+            wallets.derive_wallets(count, label)
+        })
+    }
+
+    /// Restores the [Wallet]s of `mnemonic` and saves them, see [`Wallets::recover`].
+    pub fn recover(
+        &self,
+        mnemonic: &str,
+        blockchain: &Blockchain,
+        gap_limit: u32,
+    ) -> Result<Vec<String>, WalletError> {
+        self.update(|wallets| wallets.recover(mnemonic, blockchain, gap_limit))
+    }
+
+    pub fn has_seed(&self) -> bool {
+        self.read(Wallets::has_seed)
+    }
+
+    /// Returns a copy of the [Wallet] at `address`.
+    pub fn get_wallet(&self, address: &str) -> Option<Wallet> {
+        self.read(|wallets| wallets.get_wallet(address).cloned())
+    }
+
+    pub fn get_addresses(&self) -> Vec<String> {
+        self.read(Wallets::get_addresses)
+    }
+
+    pub fn get_label(&self, address: &str) -> Option<String> {
+        self.read(|wallets| wallets.get_label(address).map(String::from))
+    }
+
+    /// Adds the [Wallet] of an imported key and saves it, see [`Wallets::import_key`].
+    pub fn import_key(&self, wallet: Wallet, label: Option<&str>) -> Result<bool, WalletError> {
+        self.update(|wallets| wallets.import_key(wallet, label))
+    }
+
+    /// Watches `address` without its key and saves it, see [`Wallets::import_address`].
+    pub fn import_address(&self, address: &str, label: Option<&str>) -> Result<(), WalletError> {
+        self.update(|wallets| wallets.import_address(address, label))
+    }
+
+    pub fn is_watch_only(&self, address: &str) -> bool {
+        self.read(|wallets| wallets.is_watch_only(address))
+    }
+
+    /// Labels `address` and saves it, see [`Wallets::set_label`].
+    pub fn set_label(&self, address: &str, label: Option<&str>) -> Result<(), WalletError> {
+        self.update(|wallets| wallets.set_label(address, label))
+    }
+
+    pub fn get_by_label(&self, label: &str) -> Result<Option<String>, WalletError> {
+        self.read(|wallets| wallets.get_by_label(label))
+    }
+}
+
+impl Default for WalletStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The labels of `count` new [Wallet]s, `label` suffixed with `-1`, `-2` and so on
+/// when there is more than one.
+fn new_labels(count: usize, label: Option<&str>) -> Vec<Option<String>> {
+    (1..=count)
+        .map(|idx| {
+            label.map(|label| {
+                if count > 1 {
+                    format!("{label}-{idx}")
+                } else {
+                    String::from(label)
+                }
+            })
+        })
+        .collect()
+}
+
+/// Copies a wallet file left in the working directory by earlier versions to the
+/// configured location, unless a wallet already exists there.
+fn migrate_legacy_file(legacy_path: &Path, path: &Path) {
+    if !legacy_path.exists() || path.exists() || legacy_path == path {
+        return;
+    }
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).expect("unable to create the wallet directory");
+    }
+    fs::copy(legacy_path, path).expect("unable to migrate the legacy wallet file");
+    info!(
+        "Moved wallet file {} to {}, the old file can be removed",
+        legacy_path.display(),
+        path.display()
+    );
+}
+
+/// The entries, the seed and the watch-only entries of a wallet file.
+type WalletFileContents = (
+    HashMap<String, WalletEntry>,
+    Option<HdChain>,
+    HashMap<String, WatchOnlyEntry>,
+);
+
+/// Decodes a versioned [`WalletFile`], or a bare address to [Wallet] map written by
+/// earlier versions, returning the entries, the seed and the watch-only entries.
+fn parse_wallet_file(buf: &[u8]) -> WalletFileContents {
+    if let Some(bytes) = buf.strip_prefix(WALLET_FILE_MAGIC) {
+        return match bincode::deserialize(bytes).expect("unable to deserialize file data") {
+            WalletFile::V1 { entries } => (entries, None, HashMap::new()),
+            WalletFile::V2 { entries, hd } => (entries, hd, HashMap::new()),
+            WalletFile::V3 {
+                entries,
+                hd,
+                watch_only,
+            } => (entries, hd, watch_only),
+        };
+    }
+    let wallets: HashMap<String, Wallet> =
+        bincode::deserialize(buf).expect("unable to deserialize file data");
+    let entries = wallets
+        .into_iter()
+        .map(|(address, wallet)| {
+            let entry = WalletEntry {
+                wallet,
+                label: None,
+                created_at: 0,
+            };
+            (address, entry)
+        })
+        .collect();
+    (entries, None, HashMap::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::temp_chain;
+    use crate::transactions::Transaction;
+
+    /// A directory under the system temporary directory, removed when dropped.
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let name = format!("himalia-wallets-{}", crate::random_u64());
+            Self(std::env::temp_dir().join(name))
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(self.0.as_path());
+        }
+    }
+
+    /// A [Config] whose wallet file is `wallet.dat` in `dir`.
+    fn wallet_config(dir: &TempDir) -> Config {
+        let config = Config::new();
+        let path = dir.0.join("wallet.dat");
+        config.override_wallet_path(path.to_string_lossy().into_owned());
+        config
+    }
+
+    #[test]
+    fn labels_stay_unique_whichever_way_they_are_given() {
+        let dir = TempDir::new();
+        let mut wallets = Wallets::from_config(&wallet_config(&dir));
+        let spares = wallets.create_wallets(2, Some("spare")).unwrap();
+        assert_eq!(wallets.get_label(spares[0].as_str()), Some("spare-1"));
+        assert_eq!(wallets.get_by_label("spare-2"), Ok(Some(spares[1].clone())));
+
+        let taken = Err(WalletError::LabelTaken(String::from("spare-1")));
+        assert_eq!(
+            wallets.create_wallets(1, Some("spare-1")).map(|_| ()),
+            taken
+        );
+        assert_eq!(wallets.get_addresses().len(), 2);
+        assert_eq!(
+            wallets.set_label(spares[1].as_str(), Some("spare-1")),
+            taken
+        );
+        assert_eq!(
+            wallets.set_label(spares[0].as_str(), Some("spare-1")),
+            Ok(())
+        );
+        assert_eq!(wallets.set_label(spares[0].as_str(), None), Ok(()));
+        assert_eq!(
+            wallets.set_label(spares[1].as_str(), Some("spare-1")),
+            Ok(())
+        );
+        assert_eq!(wallets.get_by_label("spare-1"), Ok(Some(spares[1].clone())));
+    }
+
+    #[test]
+    fn legacy_wallet_file_loads_unlabelled_and_is_saved_in_the_current_format() {
+        let dir = TempDir::new();
+        let config = wallet_config(&dir);
+        let legacy: HashMap<String, Wallet> = (0..2)
+            .map(|_| Wallet::new())
+            .map(|wallet| (wallet.get_address(), wallet))
+            .collect();
+        fs::create_dir_all(dir.0.as_path()).unwrap();
+        fs::write(
+            config.get_wallet_path(),
+            bincode::serialize(&legacy).unwrap(),
+        )
+        .unwrap();
+
+        let mut wallets = Wallets::from_config(&config);
+        for address in legacy.keys() {
+            let entry = &wallets.entries[address];
+            assert_eq!(entry.label, None);
+            assert_eq!(entry.created_at, 0);
+            assert_eq!(
+                entry.wallet.get_public_key(),
+                legacy[address].get_public_key()
+            );
+        }
+        let new = wallets.create_wallets(1, Some("new")).unwrap().remove(0);
+
+        let saved = fs::read(config.get_wallet_path()).unwrap();
+        assert!(saved.starts_with(WALLET_FILE_MAGIC));
+        let reloaded = Wallets::from_config(&config);
+        let mut addresses = reloaded.get_addresses();
+        addresses.sort();
+        let mut expected: Vec<String> = legacy.into_keys().chain([new.clone()]).collect();
+        expected.sort();
+        assert_eq!(addresses, expected);
+        assert_eq!(reloaded.get_by_label("new"), Ok(Some(new)));
+    }
+
+    #[test]
+    fn concurrent_creates_and_spends_lose_no_wallet() {
+        let miner = Wallet::new();
+        let blockchain = temp_chain(&miner);
+        let utxo_set = UTXOSet::new(blockchain.clone());
+        let dir = TempDir::new();
+        let config = wallet_config(&dir);
+        let store = WalletStore::from_config(&config);
+        assert_eq!(store.import_key(miner.clone(), Some("miner")), Ok(true));
+
+        let from = miner.get_address();
+        let created: Vec<(String, Transaction)> = std::thread::scope(|scope| {
+            let mut handles = vec![];
+            for _ in 0..10 {
+                let (store, utxo_set, from) = (store.clone(), &utxo_set, &from);
+                handles.push(scope.spawn(move || {
+                    let address = store.create_wallet();
+                    let tx = Transaction::new_utxo_transaction(
+                        from.as_str(),
+                        address.as_str(),
+                        1,
+                        1,
+                        utxo_set,
+                        &store,
+                    )
+                    .unwrap();
+                    (address, tx)
+                }));
+            }
+            handles
+                .into_iter()
+                .map(|handle| handle.join().unwrap())
+                .collect()
+        });
+
+        let reopened = WalletStore::from_config(&config);
+        let addresses: HashSet<String> = reopened.get_addresses().into_iter().collect();
+        assert_eq!(addresses.len(), 11);
+        assert!(addresses.contains(&from));
+        for (address, tx) in &created {
+            assert!(addresses.contains(address));
+            assert!(reopened.get_wallet(address).is_some());
+            assert_eq!(tx.verify(&blockchain), Ok(true));
+        }
+    }
+}
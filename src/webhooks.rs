@@ -0,0 +1,405 @@
+use std::sync::mpsc;
+use std::{error::Error, thread, time::Duration};
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use sled::Tree;
+
+use crate::config::{Webhook, GLOBAL_CONFIG};
+use crate::events::{Event, TxEventStatus, SUBSCRIBER_CAPACITY};
+use crate::{blockchain::Blockchain, clock::Clock, server::GLOBAL_MEMORY_POOL};
+use crate::{transactions::Transaction, wallet::convert_address};
+
+/// Holds the payloads not delivered yet, so that they survive a restart.
+const OUTBOX_TREE: &str = "webhook_outbox";
+/// Holds the confirmed payments waiting to reach the depth their webhook asks for.
+const WATCH_TREE: &str = "webhook_watches";
+/// How often the outbox is checked for payloads due for delivery.
+const DELIVERY_POLL: u64 = 1000;
+const HTTP_TIMEOUT: u64 = 5000;
+/// The delay before the first retry, doubled after every failed attempt.
+const RETRY_DELAY: i64 = 1000;
+const MAX_RETRY_DELAY: i64 = 3_600_000;
+/// A payload still failing after this many attempts is dropped.
+const MAX_ATTEMPTS: u32 = 20;
+
+/// What is posted to a webhook about an output paying one of its addresses.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookPayload {
+    pub txid: String,
+    pub vout: usize,
+    pub address: String,
+    pub amount: u64,
+    pub block_hash: Option<String>,
+    pub block_height: Option<usize>,
+    pub confirmations: usize,
+}
+
+/// A payload waiting in the outbox.
+#[derive(Serialize, Deserialize)]
+struct Delivery {
+    url: String,
+    payload: WebhookPayload,
+    attempts: u32,
+    /// Milliseconds since the Unix epoch before which it isn't retried.
+    next_attempt: i64,
+}
+
+/// A confirmed payment waiting to be buried `depth` blocks deep.
+#[derive(Serialize, Deserialize)]
+struct Watch {
+    url: String,
+    payload: WebhookPayload,
+    depth: usize,
+}
+
+/// Posts payments to the addresses of the configured [Webhook]s as they reach the
+/// mempool and again once they are confirmed deep enough, on background threads.
+///
+/// A reorg is not reported, and payments seen while a subscription had fallen
+/// behind are missed.
+pub fn start(blockchain: &Blockchain) -> Result<(), sled::Error> {
+    let webhooks = GLOBAL_CONFIG.get_webhooks();
+    if webhooks.is_empty() {
+        return Ok(());
+    }
+    let outbox = blockchain.get_db().open_tree(OUTBOX_TREE)?;
+    info!("Posting payments to {} webhooks", webhooks.len());
+    let watcher = Watcher {
+        blockchain: blockchain.clone(),
+        webhooks,
+        outbox: outbox.clone(),
+        watches: blockchain.get_db().open_tree(WATCH_TREE)?,
+    };
+    let clock = blockchain.get_clock();
+    thread::spawn(move || watcher.run());
+    thread::spawn(move || deliver(&outbox, &*clock));
+    Ok(())
+}
+
+/// Turns [Event]s into payloads in the outbox.
+struct Watcher {
+    blockchain: Blockchain,
+    webhooks: Vec<Webhook>,
+    outbox: Tree,
+    watches: Tree,
+}
+
+impl Watcher {
+    fn run(&self) {
+        loop {
+            let (sender, receiver) = mpsc::sync_channel(SUBSCRIBER_CAPACITY);
+            self.blockchain.subscribe_with(sender.clone());
+            GLOBAL_MEMORY_POOL.subscribe_with(sender);
+            for event in receiver {
+                if let Err(e) = self.handle(&event) {
+                    error!("Unable to queue webhook payloads: {e}");
+                }
+            }
+            warn!("The webhook watcher fell behind, payments may have been missed");
+        }
+    }
+
+    fn handle(&self, event: &Event) -> Result<(), Box<dyn Error>> {
+        match event {
+            Event::Tx {
+                txid,
+                status: TxEventStatus::Pending,
+                ..
+            } => {
+                let Some(tx) = txid
+                    .parse()
+                    .ok()
+                    .and_then(|txid| GLOBAL_MEMORY_POOL.get(txid))
+                else {
+                    return Ok(());
+                };
+                for (webhook, payload) in self.payments(&tx) {
+                    self.enqueue(webhook.url.as_str(), payload)?;
+                }
+            }
+            Event::Block { hash, height, .. } => {
+                let block = hash
+                    .parse()
+                    .ok()
+                    .and_then(|hash| self.blockchain.get_block(hash));
+                if let Some(block) = block {
+                    for tx in block.get_transactions() {
+                        for (webhook, mut payload) in self.payments(tx) {
+                            if webhook.confirmations == 0 {
+                                continue;
+                            }
+                            payload.block_hash = Some(hash.clone());
+                            payload.block_height = Some(*height);
+                            let watch = Watch {
+                                url: webhook.url.clone(),
+                                payload,
+                                depth: webhook.confirmations,
+                            };
+                            self.watches
+                                .insert(watch_key(&watch), bincode::serialize(&watch)?)?;
+                        }
+                    }
+                }
+                self.confirm(*height)?;
+            }
+            Event::Tx { .. } => {}
+        }
+        Ok(())
+    }
+
+    fn enqueue(&self, url: &str, payload: WebhookPayload) -> Result<(), Box<dyn Error>> {
+        let delivery = Delivery {
+            url: String::from(url),
+            payload,
+            attempts: 0,
+            next_attempt: 0,
+        };
+        let id = self.blockchain.get_db().generate_id()?;
+        self.outbox
+            .insert(id.to_be_bytes(), bincode::serialize(&delivery)?)?;
+        Ok(())
+    }
+
+    /// Returns a payload per output of `tx` paying an address a [Webhook] watches.
+    fn payments(&self, tx: &Transaction) -> Vec<(&Webhook, WebhookPayload)> {
+        let mut payments = vec![];
+        for (vout, output) in tx.get_vout().iter().enumerate() {
+            let address = convert_address(output.get_pub_key_hash());
+            for webhook in &self.webhooks {
+                if webhook.addresses.contains(&address) {
+                    let payload = WebhookPayload {
+                        txid: tx.get_id().to_string(),
+                        vout,
+                        address: address.clone(),
+                        amount: output.get_value(),
+                        block_hash: None,
+                        block_height: None,
+                        confirmations: 0,
+                    };
+                    payments.push((webhook, payload));
+                }
+            }
+        }
+        payments
+    }
+
+    /// Moves the watched payments buried deep enough at `height` to the outbox.
+    fn confirm(&self, height: usize) -> Result<(), Box<dyn Error>> {
+        for entry in &self.watches {
+            let (key, value) = entry?;
+            let mut watch: Watch = bincode::deserialize(value.as_ref())?;
+            let confirmations = watch
+                .payload
+                .block_height
+                .map_or(0, |block_height| (height + 1).saturating_sub(block_height));
+            if confirmations >= watch.depth {
+                watch.payload.confirmations = confirmations;
+                self.enqueue(watch.url.as_str(), watch.payload)?;
+                self.watches.remove(key)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn watch_key(watch: &Watch) -> String {
+    format!(
+        "{}\n{}\n{}",
+        watch.url, watch.payload.txid, watch.payload.vout
+    )
+}
+
+/// Posts the payloads in the outbox that are due, oldest first, backing off
+/// exponentially after every failed attempt.
+fn deliver(outbox: &Tree, clock: &dyn Clock) {
+    loop {
+        for entry in outbox {
+            let Ok((key, value)) = entry else {
+                continue;
+            };
+            let Ok(mut delivery) = bincode::deserialize::<Delivery>(value.as_ref()) else {
+                let _ = outbox.remove(key);
+                continue;
+            };
+            let now = clock.now();
+            if delivery.next_attempt > now {
+                continue;
+            }
+            let result = match post(&delivery) {
+                Ok(()) => outbox.remove(key).map(|_| ()),
+                Err(e) if delivery.attempts + 1 >= MAX_ATTEMPTS => {
+                    error!(
+                        "Dropping the webhook payload for {} after {MAX_ATTEMPTS} attempts: {e}",
+                        delivery.url
+                    );
+                    outbox.remove(key).map(|_| ())
+                }
+                Err(e) => {
+                    let delay = RETRY_DELAY
+                        .saturating_mul(1 << delivery.attempts.min(32))
+                        .min(MAX_RETRY_DELAY);
+                    warn!(
+                        "Posting to {} failed, retrying in {delay} ms: {e}",
+                        delivery.url
+                    );
+                    delivery.attempts += 1;
+                    delivery.next_attempt = now + delay;
+                    match bincode::serialize(&delivery) {
+                        Ok(bytes) => outbox.insert(key, bytes).map(|_| ()),
+                        Err(_) => outbox.remove(key).map(|_| ()),
+                    }
+                }
+            };
+            if let Err(e) = result {
+                error!("Unable to update the webhook outbox: {e}");
+            }
+        }
+        thread::sleep(Duration::from_millis(DELIVERY_POLL));
+    }
+}
+
+fn post(delivery: &Delivery) -> Result<(), Box<dyn Error>> {
+    let response = attohttpc::post(delivery.url.as_str())
+        .timeout(Duration::from_millis(HTTP_TIMEOUT))
+        .json(&delivery.payload)?
+        .send()?;
+    if !response.is_success() {
+        return Err(format!("the server answered {}", response.status()).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::http::{self, Status};
+    use crate::test_utils::{mine, pay, temp_chain};
+    use crate::wallet::Wallet;
+    use serde_json::{json, Value};
+    use std::net::TcpListener;
+    use std::sync::Arc;
+
+    fn watcher(blockchain: &Blockchain, webhook: Webhook) -> Watcher {
+        let db = blockchain.get_db();
+        Watcher {
+            blockchain: blockchain.clone(),
+            webhooks: vec![webhook],
+            outbox: db.open_tree(OUTBOX_TREE).unwrap(),
+            watches: db.open_tree(WATCH_TREE).unwrap(),
+        }
+    }
+
+    fn queued(outbox: &Tree) -> Vec<Delivery> {
+        outbox
+            .iter()
+            .values()
+            .map(|value| bincode::deserialize(value.unwrap().as_ref()).unwrap())
+            .collect()
+    }
+
+    /// Answers the next post to `listener` with `status`, returning its JSON body.
+    fn answer(listener: &TcpListener, status: Status) -> Value {
+        let (mut stream, _) = listener.accept().unwrap();
+        let request = http::read_request(&stream).unwrap();
+        assert_eq!(
+            (request.method.as_str(), request.target.as_str()),
+            ("POST", "/payments")
+        );
+        http::write_response(&mut stream, status, &[], &json!({})).unwrap();
+        serde_json::from_slice(request.body.as_slice()).unwrap()
+    }
+
+    #[test]
+    fn payment_is_queued_from_the_mempool_and_again_once_deep_enough() {
+        let (miner, payee) = (Wallet::new(), Wallet::new());
+        let blockchain = temp_chain(&miner);
+        let url = String::from("http://127.0.0.1:1/payments");
+        let watcher = watcher(
+            &blockchain,
+            Webhook {
+                url: url.clone(),
+                addresses: vec![payee.get_address()],
+                confirmations: 2,
+            },
+        );
+        let tx = pay(&blockchain, &miner, &payee, 3);
+        GLOBAL_MEMORY_POOL.try_add(tx.clone(), &blockchain).unwrap();
+        watcher
+            .handle(&Event::tx(&tx, TxEventStatus::Pending))
+            .unwrap();
+        GLOBAL_MEMORY_POOL.remove(tx.get_id());
+        let pending = queued(&watcher.outbox);
+        assert_eq!(pending.len(), 1);
+        assert_eq!(pending[0].url, url);
+        assert_eq!(pending[0].payload.txid, tx.get_id().to_string());
+        assert_eq!(
+            (pending[0].payload.amount, pending[0].payload.confirmations),
+            (3, 0)
+        );
+        assert_eq!(pending[0].payload.block_hash, None);
+
+        let block = mine(&blockchain, vec![tx], &miner);
+        watcher.handle(&Event::block(&block)).unwrap();
+        assert_eq!(queued(&watcher.outbox).len(), 1);
+        assert_eq!(watcher.watches.len(), 1);
+
+        let next = mine(&blockchain, vec![], &miner);
+        watcher.handle(&Event::block(&next)).unwrap();
+        let deliveries = queued(&watcher.outbox);
+        assert_eq!(deliveries.len(), 2);
+        let confirmed = &deliveries[1].payload;
+        assert_eq!(confirmed.block_hash, Some(block.get_hash().to_string()));
+        assert_eq!(
+            (confirmed.block_height, confirmed.confirmations),
+            (Some(1), 2)
+        );
+        assert!(watcher.watches.is_empty());
+    }
+
+    #[test]
+    fn failed_post_is_retried_after_a_backoff_until_delivered() {
+        let blockchain = temp_chain(&Wallet::new());
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let watcher = watcher(
+            &blockchain,
+            Webhook {
+                url: format!("http://{}/payments", listener.local_addr().unwrap()),
+                addresses: vec![],
+                confirmations: 0,
+            },
+        );
+        let payload = WebhookPayload {
+            txid: String::from("ab"),
+            vout: 1,
+            address: String::from("address"),
+            amount: 5,
+            block_hash: None,
+            block_height: None,
+            confirmations: 0,
+        };
+        watcher
+            .enqueue(watcher.webhooks[0].url.as_str(), payload)
+            .unwrap();
+        let clock = Arc::new(MockClock::new(0));
+        let (outbox, delivery_clock) = (watcher.outbox.clone(), clock.clone());
+        thread::spawn(move || deliver(&outbox, &*delivery_clock));
+
+        assert_eq!(answer(&listener, http::BAD_REQUEST)["amount"], 5);
+        while queued(&watcher.outbox)[0].attempts == 0 {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(queued(&watcher.outbox)[0].next_attempt, RETRY_DELAY);
+
+        clock.advance(RETRY_DELAY);
+        let delivered = answer(&listener, http::OK);
+        assert_eq!(
+            (delivered["txid"].clone(), delivered["vout"].clone()),
+            (json!("ab"), json!(1))
+        );
+        while !watcher.outbox.is_empty() {
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
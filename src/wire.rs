@@ -0,0 +1,331 @@
+use std::fmt::{self, Display, Formatter};
+use std::io::{self, ErrorKind, Read, Write};
+
+use bincode::Options;
+
+use crate::config::GLOBAL_CONFIG;
+use crate::{server::Package, sha256_digest};
+
+/// The number of bytes in front of every payload: the network magic, the message
+/// type, the payload length and the checksum.
+pub const HEADER_LEN: usize = 13;
+/// The message type of the last kind of [Package].
+const MAX_MESSAGE_TYPE: u8 = 12;
+
+/// The reasons a frame read from a peer is refused.
+#[derive(Debug)]
+pub enum FrameError {
+    Io(io::Error),
+    /// The connection closed part way through a frame.
+    Truncated,
+    /// The frame is meant for another network, or isn't a frame at all.
+    WrongMagic([u8; 4]),
+    UnknownType(u8),
+    TooLarge {
+        length: usize,
+        max: usize,
+    },
+    /// The payload doesn't hash to the checksum of the header.
+    BadChecksum,
+    /// The payload doesn't decode into the [Package] its message type names.
+    Malformed,
+}
+
+impl FrameError {
+    /// Whether the peer sent something no well-behaved node sends, as opposed to the
+    /// connection failing.
+    pub const fn is_invalid(&self) -> bool {
+        !matches!(self, Self::Io(_) | Self::Truncated)
+    }
+}
+
+impl Display for FrameError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(e) => write!(f, "{e}"),
+            Self::Truncated => write!(f, "the connection closed part way through a frame"),
+            Self::WrongMagic(magic) => write!(
+                f,
+                "the frame starts with {magic:02x?}, not the magic of this network"
+            ),
+            Self::UnknownType(message_type) => {
+                write!(f, "the message type {message_type} is unknown")
+            }
+            Self::TooLarge { length, max } => write!(
+                f,
+                "the payload is {length} bytes, more than the {max} allowed"
+            ),
+            Self::BadChecksum => write!(f, "the payload doesn't match its checksum"),
+            Self::Malformed => write!(f, "the payload doesn't decode into its message type"),
+        }
+    }
+}
+
+impl std::error::Error for FrameError {}
+
+impl From<io::Error> for FrameError {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+/// Writes `pkg` to `writer` as a single frame: the magic of the configured network, the
+/// message type, the length of the payload and its checksum, all followed by the
+/// payload.
+pub fn write_package(writer: &mut impl Write, pkg: &Package) -> io::Result<()> {
+    let payload = options()
+        .serialize(pkg)
+        .map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+    let max = GLOBAL_CONFIG.get_size_limits().max_package_bytes;
+    let length = u32::try_from(payload.len())
+        .ok()
+        .filter(|_| payload.len() <= max)
+        .ok_or_else(|| io::Error::new(ErrorKind::InvalidInput, "the package is too large"))?;
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    frame.extend_from_slice(&GLOBAL_CONFIG.get_network().magic());
+    frame.push(message_type(pkg));
+    frame.extend_from_slice(&length.to_le_bytes());
+    frame.extend_from_slice(&checksum(payload.as_slice()));
+    frame.extend_from_slice(payload.as_slice());
+    writer.write_all(frame.as_slice())?;
+    writer.flush()
+}
+
+/// Reads the next frame written by [`write_package`] from `reader`, returning `None`
+/// once the connection closes between two frames.
+///
+/// The header is checked before the payload is read, so that a peer can't make this
+/// node buffer more than the configured `max_package_bytes`.
+pub fn read_package(reader: &mut impl Read) -> Result<Option<Package>, FrameError> {
+    let mut header = [0; HEADER_LEN];
+    match read_full(reader, &mut header)? {
+        0 => return Ok(None),
+        HEADER_LEN => {}
+        _ => return Err(FrameError::Truncated),
+    }
+    let magic = [header[0], header[1], header[2], header[3]];
+    if magic != GLOBAL_CONFIG.get_network().magic() {
+        return Err(FrameError::WrongMagic(magic));
+    }
+    let msg_type = header[4];
+    if msg_type > MAX_MESSAGE_TYPE {
+        return Err(FrameError::UnknownType(msg_type));
+    }
+    let length = u32::from_le_bytes([header[5], header[6], header[7], header[8]]) as usize;
+    let max = GLOBAL_CONFIG.get_size_limits().max_package_bytes;
+    if length > max {
+        return Err(FrameError::TooLarge { length, max });
+    }
+    let mut payload = vec![0; length];
+    if read_full(reader, payload.as_mut_slice())? < length {
+        return Err(FrameError::Truncated);
+    }
+    if checksum(payload.as_slice()) != header[9..HEADER_LEN] {
+        return Err(FrameError::BadChecksum);
+    }
+    let pkg: Package = options()
+        .with_limit(length as u64)
+        .deserialize(payload.as_slice())
+        .map_err(|_| FrameError::Malformed)?;
+    if message_type(&pkg) != msg_type {
+        return Err(FrameError::Malformed);
+    }
+    Ok(Some(pkg))
+}
+
+/// The bincode configuration of payloads, the one [`bincode::serialize`] uses.
+fn options() -> impl Options {
+    bincode::options().with_fixint_encoding()
+}
+
+/// The first four bytes of the double SHA-256 digest of `payload`.
+fn checksum(payload: &[u8]) -> [u8; 4] {
+    let digest = sha256_digest(sha256_digest(payload).as_slice());
+    [digest[0], digest[1], digest[2], digest[3]]
+}
+
+/// Numbers the kinds of [Package] in the frame header, up to [`MAX_MESSAGE_TYPE`].
+const fn message_type(pkg: &Package) -> u8 {
+    match pkg {
+        Package::Block { .. } => 0,
+        Package::GetBlocks { .. } => 1,
+        Package::GetData { .. } => 2,
+        Package::Inv { .. } => 3,
+        Package::Tx { .. } => 4,
+        Package::Version { .. } => 5,
+        Package::SetFilter { .. } => 6,
+        Package::ClearFilter { .. } => 7,
+        Package::GetAddr { .. } => 8,
+        Package::Addr { .. } => 9,
+        Package::FilteredBlock { .. } => 10,
+        Package::GetHeaders { .. } => 11,
+        Package::Headers { .. } => 12,
+    }
+}
+
+/// Fills `buf` from `reader` like [`Read::read_exact`], but returns how many bytes
+/// were read when the connection closes first.
+fn read_full(reader: &mut impl Read, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buf.len() {
+        match reader.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::merkle::MerkleTree;
+    use crate::server::OpType;
+    use crate::test_utils::temp_chain;
+    use crate::wallet::Wallet;
+
+    /// One [Package] of each kind, in the order of their message types.
+    fn one_of_each() -> Vec<Package> {
+        let blockchain = temp_chain(&Wallet::new());
+        let genesis = blockchain.get_block(blockchain.get_tip_hash()).unwrap();
+        let coinbase = &genesis.get_transactions()[0];
+        let addr_from = String::from("127.0.0.1:2001");
+        vec![
+            Package::Block {
+                addr_from: addr_from.clone(),
+                block: genesis.serialize(),
+            },
+            Package::GetBlocks {
+                addr_from: addr_from.clone(),
+            },
+            Package::GetData {
+                addr_from: addr_from.clone(),
+                op_type: OpType::Block,
+                id: genesis.get_hash().to_vec(),
+            },
+            Package::Inv {
+                addr_from: addr_from.clone(),
+                op_type: OpType::Tx,
+                items: vec![coinbase.get_id().to_vec()],
+            },
+            Package::Tx {
+                addr_from: addr_from.clone(),
+                transaction: coinbase.serialize(),
+            },
+            Package::Version {
+                addr_from: addr_from.clone(),
+                version: 1,
+                best_height: 0,
+                nonce: 7,
+                genesis_hash: genesis.get_hash(),
+            },
+            Package::SetFilter {
+                addr_from: addr_from.clone(),
+                filter_bytes: vec![0xff; 8],
+                hash_funcs: 3,
+                tweak: 5,
+            },
+            Package::ClearFilter {
+                addr_from: addr_from.clone(),
+            },
+            Package::GetAddr {
+                addr_from: addr_from.clone(),
+            },
+            Package::Addr {
+                addr_from: addr_from.clone(),
+                addrs: vec![String::from("127.0.0.1:2002")],
+            },
+            Package::FilteredBlock {
+                addr_from: addr_from.clone(),
+                header: genesis.get_header(),
+                transactions: vec![coinbase.serialize()],
+                proofs: vec![MerkleTree::new(&[coinbase.get_id()]).proof(0).unwrap()],
+            },
+            Package::GetHeaders {
+                addr_from: addr_from.clone(),
+                locator: vec![genesis.get_hash()],
+            },
+            Package::Headers {
+                addr_from,
+                headers: vec![genesis.get_header()],
+            },
+        ]
+    }
+
+    /// The frame [`write_package`] writes for `pkg`.
+    fn frame(pkg: &Package) -> Vec<u8> {
+        let mut frame = vec![];
+        write_package(&mut frame, pkg).unwrap();
+        frame
+    }
+
+    #[test]
+    fn every_package_round_trips() {
+        let packages = one_of_each();
+        assert_eq!(packages.len(), usize::from(MAX_MESSAGE_TYPE) + 1);
+        let stream: Vec<u8> = packages.iter().flat_map(frame).collect();
+        let mut reader = stream.as_slice();
+        for (msg_type, pkg) in (0..).zip(&packages) {
+            let read = read_package(&mut reader).unwrap().unwrap();
+            assert_eq!(message_type(&read), msg_type);
+            assert_eq!(
+                options().serialize(&read).unwrap(),
+                options().serialize(pkg).unwrap()
+            );
+        }
+        assert!(read_package(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn truncated_frame_is_refused() {
+        let frame = frame(&one_of_each().remove(0));
+        for len in [1, HEADER_LEN - 1, HEADER_LEN, frame.len() - 1] {
+            let e = read_package(&mut &frame[..len]).unwrap_err();
+            assert!(matches!(e, FrameError::Truncated), "{e}");
+            assert!(!e.is_invalid());
+        }
+    }
+
+    #[test]
+    fn oversized_frame_is_refused_before_its_payload_is_read() {
+        let max = GLOBAL_CONFIG.get_size_limits().max_package_bytes;
+        let mut frame = frame(&one_of_each().remove(1));
+        let length = u32::try_from(max + 1).unwrap();
+        frame[5..9].copy_from_slice(&length.to_le_bytes());
+        let e = read_package(&mut frame.as_slice()).unwrap_err();
+        assert!(
+            matches!(e, FrameError::TooLarge { length, max: m } if length == max + 1 && m == max),
+            "{e}"
+        );
+        assert!(e.is_invalid());
+
+        let oversized = Package::Block {
+            addr_from: String::new(),
+            block: vec![0; max],
+        };
+        let e = write_package(&mut vec![], &oversized).unwrap_err();
+        assert_eq!(e.kind(), ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn corrupted_frame_is_refused() {
+        let frame = frame(&one_of_each().remove(1));
+        let corrupt = |at: usize, value: u8| {
+            let mut corrupted = frame.clone();
+            corrupted[at] = value;
+            read_package(&mut corrupted.as_slice()).unwrap_err()
+        };
+        assert!(matches!(corrupt(0, 0), FrameError::WrongMagic(_)));
+        assert!(matches!(
+            corrupt(4, MAX_MESSAGE_TYPE + 1),
+            FrameError::UnknownType(13)
+        ));
+        assert!(matches!(corrupt(4, 0), FrameError::Malformed));
+        assert!(matches!(
+            corrupt(HEADER_LEN, frame[HEADER_LEN] ^ 1),
+            FrameError::BadChecksum
+        ));
+    }
+}
@@ -0,0 +1,1443 @@
+//! Runs the binary with bad input, checking the message and the exit code of each
+//! kind of failure, and checks the output and the effects of the commands scripts
+//! rely on.
+
+use std::fs;
+use std::io::Read;
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use assert_cmd::Command;
+use himalia::rpc;
+use serde_json::json;
+
+/// An address of the regtest network that no wallet of the tests holds.
+const FOREIGN_ADDRESS: &str = "rD1d5QeW2QREpdpRX6PwRJUsMq2ztqUomA";
+
+/// A data directory of its own for each test, removed when dropped.
+struct DataDir(PathBuf);
+
+impl DataDir {
+    fn new() -> Self {
+        let name = format!("himalia-cli-{}", himalia::random_u64());
+        Self(std::env::temp_dir().join(name))
+    }
+
+    fn command(&self) -> Command {
+        let mut command = Command::cargo_bin("himalia").expect("the binary is built");
+        command
+            .args(["--network", "regtest", "--data-dir"])
+            .arg(self.path());
+        command
+    }
+
+    fn path(&self) -> &Path {
+        self.0.as_path()
+    }
+
+    /// Creates a wallet, returning its address.
+    fn with_wallet(&self) -> String {
+        let output = self
+            .command()
+            .args(["createwallet", "--quiet"])
+            .output()
+            .expect("createwallet runs");
+        String::from_utf8(output.stdout)
+            .expect("the address is UTF-8")
+            .trim()
+            .to_string()
+    }
+
+    /// Creates a wallet and a chain whose genesis block pays it, returning its address.
+    fn with_funded_wallet(&self) -> String {
+        let address = self.with_wallet();
+        self.command()
+            .args(["createblockchain", address.as_str()])
+            .assert()
+            .success();
+        address
+    }
+
+    /// Runs `startnode` with `args` and the settings in `env` in the background.
+    fn start_node(&self, env: &[(&str, &str)], args: &[&str]) -> RunningNode {
+        let child = std::process::Command::new(env!("CARGO_BIN_EXE_himalia"))
+            .envs(env.iter().copied())
+            .args(["--network", "regtest", "--data-dir"])
+            .arg(self.path())
+            .arg("startnode")
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("startnode runs");
+        RunningNode(Some(child))
+    }
+}
+
+impl Drop for DataDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(self.path());
+    }
+}
+
+/// A node started by [`DataDir::start_node`], killed when dropped.
+struct RunningNode(Option<Child>);
+
+impl RunningNode {
+    /// Kills the node, returning what it wrote to stderr.
+    fn stop(mut self) -> String {
+        let mut child = self.0.take().expect("the node is running");
+        let _ = child.kill();
+        let mut stderr = String::new();
+        child
+            .stderr
+            .take()
+            .expect("stderr is piped")
+            .read_to_string(&mut stderr)
+            .expect("stderr is UTF-8");
+        let _ = child.wait();
+        stderr
+    }
+}
+
+impl RunningNode {
+    /// Waits up to ten seconds for the node to exit on its own, returning its exit code.
+    fn wait_for_exit(mut self) -> Option<i32> {
+        let child = self.0.as_mut().expect("the node is running");
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline {
+            if let Some(status) = child.try_wait().expect("the node can be waited on") {
+                return status.code();
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        panic!("the node is still running after ten seconds");
+    }
+}
+
+impl Drop for RunningNode {
+    fn drop(&mut self) {
+        if let Some(mut child) = self.0.take() {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// A local address nothing listens on.
+fn free_addr() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("a port is free");
+    listener
+        .local_addr()
+        .expect("the port is bound")
+        .to_string()
+}
+
+/// Waits up to ten seconds for something to listen on `addr`.
+fn wait_for_listener(addr: &str) {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while TcpStream::connect(addr).is_err() {
+        assert!(Instant::now() < deadline, "nothing listens on {addr}");
+        thread::sleep(Duration::from_millis(20));
+    }
+}
+
+/// Waits up to ten seconds for a connection to `listener`.
+fn accept_within(listener: &TcpListener) -> TcpStream {
+    listener
+        .set_nonblocking(true)
+        .expect("the listener is open");
+    let deadline = Instant::now() + Duration::from_secs(10);
+    loop {
+        match listener.accept() {
+            Ok((stream, _)) => return stream,
+            Err(_) if Instant::now() < deadline => thread::sleep(Duration::from_millis(20)),
+            Err(e) => panic!("no connection within ten seconds: {e}"),
+        }
+    }
+}
+
+/// Runs `command` with `--json`, returning the document it prints on stdout.
+fn json_output(command: &mut Command) -> serde_json::Value {
+    let output = command.arg("--json").output().expect("the command runs");
+    assert!(output.status.success());
+    serde_json::from_slice(&output.stdout).expect("stdout is a JSON document")
+}
+
+/// Runs `listaddresses` with `command`, returning what it prints.
+fn list_addresses(command: &mut Command) -> String {
+    let output = command
+        .arg("listaddresses")
+        .output()
+        .expect("listaddresses runs");
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).expect("the addresses are UTF-8")
+}
+
+#[test]
+fn invalid_address_is_a_usage_error() {
+    let dir = DataDir::new();
+    for address in ["nope", "0l", "x", "\u{e9}"] {
+        dir.command()
+            .args(["createblockchain", address])
+            .assert()
+            .code(1)
+            .stderr(format!("Error: address `{address}` is not valid\n"));
+    }
+}
+
+#[test]
+fn missing_blockchain_is_a_state_error() {
+    let dir = DataDir::new();
+    dir.command()
+        .args(["getbalance", FOREIGN_ADDRESS])
+        .assert()
+        .code(2)
+        .stderr("Error: no existing blockchain found, create one with `createblockchain` first\n");
+}
+
+#[test]
+fn missing_wallet_is_a_state_error() {
+    let dir = DataDir::new();
+    let address = dir.with_funded_wallet();
+    dir.command()
+        .args(["send", FOREIGN_ADDRESS, address.as_str(), "5", "1", "--yes"])
+        .assert()
+        .code(2)
+        .stderr(format!(
+            "Error: no wallet found for address `{FOREIGN_ADDRESS}`\n"
+        ));
+}
+
+#[test]
+fn insufficient_funds_is_a_state_error() {
+    let dir = DataDir::new();
+    let address = dir.with_funded_wallet();
+    dir.command()
+        .args([
+            "send",
+            address.as_str(),
+            FOREIGN_ADDRESS,
+            "500",
+            "1",
+            "--yes",
+        ])
+        .assert()
+        .code(2)
+        .stderr("Error: not enough funds, 10 available but 500 required\n");
+}
+
+#[test]
+fn sendmany_pays_every_recipient() {
+    let dir = DataDir::new();
+    let from = dir.with_funded_wallet();
+    let recipients: Vec<String> = (0..3).map(|_| dir.with_wallet()).collect();
+    let pairs = recipients
+        .iter()
+        .zip(1..)
+        .map(|(address, amount)| format!("{address}:{amount}"));
+    dir.command()
+        .args(["sendmany", from.as_str()])
+        .args(pairs)
+        .args(["--fee", "1", "--mine"])
+        .assert()
+        .success();
+    for (address, amount) in recipients.iter().zip(1..) {
+        dir.command()
+            .args(["getbalance", address.as_str()])
+            .assert()
+            .success()
+            .stdout(format!("Balance of {address}, {amount} (pending +0 -0)\n"));
+    }
+}
+
+#[test]
+fn sendmany_to_a_recipient_twice_is_a_usage_error() {
+    let dir = DataDir::new();
+    let from = dir.with_funded_wallet();
+    let twice = format!("{FOREIGN_ADDRESS}:1");
+    dir.command()
+        .args(["sendmany", from.as_str(), twice.as_str(), twice.as_str()])
+        .assert()
+        .code(1)
+        .stderr(format!(
+            "Error: recipient `{FOREIGN_ADDRESS}` is listed more than once, pass \
+             --allow-duplicates to allow it\n"
+        ));
+    dir.command()
+        .args(["sendmany", from.as_str(), twice.as_str(), twice.as_str()])
+        .args(["--allow-duplicates", "--mine"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn sendmany_with_a_malformed_recipient_is_a_usage_error() {
+    let dir = DataDir::new();
+    let from = dir.with_funded_wallet();
+    for recipient in [
+        format!("{FOREIGN_ADDRESS}:0"),
+        format!("{FOREIGN_ADDRESS}:1x"),
+        format!("{FOREIGN_ADDRESS}:-1"),
+        String::from(FOREIGN_ADDRESS),
+        String::from(":1"),
+        String::from("nope:1"),
+    ] {
+        dir.command()
+            .args(["sendmany", from.as_str(), recipient.as_str()])
+            .assert()
+            .code(1);
+    }
+}
+
+#[test]
+fn malformed_node_address_is_a_config_error() {
+    let dir = DataDir::new();
+    dir.command()
+        .env("NODE_ADDRESS", "localhost")
+        .args(["getbalance", FOREIGN_ADDRESS])
+        .assert()
+        .code(78)
+        .stderr(
+            "Error: invalid configuration\n  - node address `localhost` is not a valid \
+             host:port, expected e.g. 127.0.0.1:2001\n",
+        );
+}
+
+#[test]
+fn log_lines_land_in_the_configured_file() {
+    let dir = DataDir::new();
+    let address = dir.with_wallet();
+    dir.command()
+        .env("LOG_FILE", "node.log")
+        .env("LOG_LEVEL", "info")
+        .env("LOG_FORMAT", "json")
+        .args(["createblockchain", address.as_str()])
+        .assert()
+        .success()
+        .stderr("");
+    let log = fs::read_to_string(dir.path().join("regtest").join("node.log"))
+        .expect("the log file is written");
+    let messages: Vec<String> = log
+        .lines()
+        .map(|line| {
+            let record: serde_json::Value = serde_json::from_str(line).expect("a JSON record");
+            record["message"].as_str().unwrap_or_default().to_string()
+        })
+        .collect();
+    assert!(messages
+        .iter()
+        .any(|message| message.starts_with("Mining the block")));
+}
+
+#[test]
+fn legacy_wallet_file_is_migrated_once() {
+    let dir = DataDir::new();
+    let work_dir = dir.path().join("work");
+    fs::create_dir_all(work_dir.as_path()).expect("the working directory is created");
+    let legacy = work_dir.join("wallet-regtest.dat");
+    let output = dir
+        .command()
+        .arg("--wallet")
+        .arg(legacy.as_path())
+        .args(["createwallet", "--quiet"])
+        .output()
+        .expect("createwallet runs");
+    let address = String::from_utf8(output.stdout).expect("the address is UTF-8");
+
+    let listed = list_addresses(dir.command().current_dir(work_dir.as_path()));
+    assert!(listed.contains(address.trim()));
+    let migrated = dir.path().join("regtest").join("wallet.dat");
+    assert_eq!(
+        fs::read(migrated.as_path()).expect("the wallet file is migrated"),
+        fs::read(legacy.as_path()).expect("the legacy wallet file is kept")
+    );
+
+    fs::remove_file(legacy.as_path()).expect("the legacy wallet file is removed");
+    let listed = list_addresses(dir.command().current_dir(work_dir.as_path()));
+    assert!(listed.contains(address.trim()));
+}
+
+#[test]
+fn wallet_flag_takes_precedence_over_the_configured_wallet() {
+    let dir = DataDir::new();
+    let address = dir.with_wallet();
+    let listed = list_addresses(dir.command().args(["--wallet", "other.dat"]));
+    assert!(!listed.contains(address.as_str()));
+    let listed = list_addresses(dir.command().env("WALLET_PATH", "other.dat"));
+    assert!(!listed.contains(address.as_str()));
+    let listed = list_addresses(
+        dir.command()
+            .env("WALLET_PATH", "other.dat")
+            .args(["--wallet", "wallet.dat"]),
+    );
+    assert!(listed.contains(address.as_str()));
+}
+
+#[test]
+fn json_output_of_getbalance_and_send() {
+    let dir = DataDir::new();
+    let from = dir.with_funded_wallet();
+    let to = dir.with_wallet();
+    let balance = json_output(dir.command().args(["getbalance", from.as_str()]));
+    assert_eq!(balance["address"], from.as_str());
+    assert_eq!(balance["confirmed"], 10);
+    assert_eq!(balance["pending"], 0);
+
+    let sent = json_output(dir.command().args([
+        "send",
+        from.as_str(),
+        to.as_str(),
+        "3",
+        "1",
+        "--fee",
+        "2",
+        "--allow-high-fee",
+        "--yes",
+    ]));
+    assert_eq!(sent["fee"], 2);
+    assert_eq!(sent["txid"].as_str().map(str::len), Some(64));
+    assert_eq!(sent["mined_block"].as_str().map(str::len), Some(64));
+    let balance = json_output(dir.command().args(["getbalance", to.as_str()]));
+    assert_eq!(balance["confirmed"], 3);
+}
+
+#[test]
+fn json_errors_carry_the_exit_code() {
+    let dir = DataDir::new();
+    let output = dir
+        .command()
+        .args(["--json", "getbalance", "nope"])
+        .output()
+        .expect("getbalance runs");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let error: serde_json::Value =
+        serde_json::from_slice(&output.stderr).expect("stderr is a JSON document");
+    assert_eq!(error["error"]["code"], 1);
+    assert_eq!(error["error"]["message"], "address `nope` is not valid");
+}
+
+#[test]
+fn send_leaves_the_absolute_fee() {
+    let dir = DataDir::new();
+    let from = dir.with_funded_wallet();
+    let to = dir.with_wallet();
+    let sent = json_output(dir.command().args([
+        "send",
+        from.as_str(),
+        to.as_str(),
+        "9",
+        "1",
+        "--fee",
+        "1",
+        "--allow-high-fee",
+        "--yes",
+    ]));
+    assert_eq!(sent["fee"], 1);
+    let balance = json_output(dir.command().args(["getbalance", to.as_str()]));
+    assert_eq!(balance["confirmed"], 9);
+}
+
+#[test]
+fn send_derives_the_fee_from_the_fee_rate() {
+    let dir = DataDir::new();
+    let from = dir.with_funded_wallet();
+    let to = dir.with_wallet();
+    let built = json_output(dir.command().args([
+        "send",
+        from.as_str(),
+        to.as_str(),
+        "1",
+        "0",
+        "--fee-rate",
+        "0.01",
+        "--dry-run",
+    ]));
+    let size = built["size"].as_f64().expect("the size is a number");
+    let fee = (size * 0.01).ceil();
+    assert_eq!(built["transaction"]["fee"].as_f64(), Some(fee));
+    assert_eq!(
+        built["transaction"]["outputs"][1]["value"].as_f64(),
+        Some(10.0 - 1.0 - fee)
+    );
+}
+
+#[test]
+fn send_refuses_a_high_fee_unless_allowed() {
+    let dir = DataDir::new();
+    let from = dir.with_funded_wallet();
+    dir.command()
+        .args([
+            "send",
+            from.as_str(),
+            FOREIGN_ADDRESS,
+            "5",
+            "1",
+            "--fee",
+            "1",
+            "--yes",
+        ])
+        .assert()
+        .code(1)
+        .stderr(
+            "Error: fee 1 is more than 10% of the amount, pass --allow-high-fee to send anyway\n",
+        );
+    let balance = json_output(dir.command().args(["getbalance", from.as_str()]));
+    assert_eq!(balance["confirmed"], 10);
+}
+
+#[test]
+fn getblock_prints_a_block_by_hash_or_height() {
+    let dir = DataDir::new();
+    let from = dir.with_funded_wallet();
+    dir.command()
+        .args(["getblock", "00".repeat(32).as_str()])
+        .assert()
+        .code(1)
+        .stderr("Error: block not found\n");
+
+    let genesis = json_output(dir.command().args(["getblockbyheight", "0"]));
+    assert_eq!(genesis["height"], 0);
+    assert_eq!(genesis["transaction_count"], 1);
+    let sent = json_output(dir.command().args([
+        "send",
+        from.as_str(),
+        FOREIGN_ADDRESS,
+        "3",
+        "1",
+        "--yes",
+    ]));
+    let hash = sent["mined_block"].as_str().expect("send mines a block");
+    let block = json_output(dir.command().args(["getblock", hash]));
+    assert_eq!(block["hash"], hash);
+    assert_eq!(block["pre_block_hash"], genesis["hash"]);
+    assert_eq!(block["height"], 1);
+    assert_eq!(block["transaction_count"], 2);
+    let transactions = block["transactions"]
+        .as_array()
+        .expect("the block lists its transactions");
+    assert_eq!(transactions[0]["txid"], sent["txid"]);
+    assert_eq!(transactions[1]["is_coinbase"], true);
+    assert_eq!(
+        json_output(dir.command().args(["getblockbyheight", "1"])),
+        block
+    );
+}
+
+#[test]
+fn exported_chain_imports_into_a_fresh_data_dir() {
+    let source = DataDir::new();
+    let miner = source.with_funded_wallet();
+    source
+        .command()
+        .args(["generate", "3", miner.as_str()])
+        .assert()
+        .success();
+    let file = source.path().join("chain.bin");
+    source
+        .command()
+        .arg("exportchain")
+        .arg(file.as_path())
+        .assert()
+        .success();
+
+    let target = DataDir::new();
+    target
+        .command()
+        .arg("importchain")
+        .arg(file.as_path())
+        .assert()
+        .success();
+    let exported = json_output(source.command().arg("status"));
+    let imported = json_output(target.command().arg("status"));
+    assert_eq!(imported["chain"], exported["chain"]);
+    assert_eq!(imported["chain"]["height"], 3);
+    let balance = json_output(target.command().args(["getbalance", miner.as_str()]));
+    assert_eq!(balance["confirmed"], 40);
+
+    let output = target
+        .command()
+        .arg("importchain")
+        .arg(file.as_path())
+        .output()
+        .expect("importchain runs");
+    assert!(output.status.success());
+    let summary = String::from_utf8(output.stdout).expect("the summary is UTF-8");
+    assert!(summary.starts_with("Imported 0 blocks, skipped 4 known blocks"));
+}
+
+#[test]
+fn importing_a_truncated_chain_reports_how_far_it_got() {
+    let source = DataDir::new();
+    let miner = source.with_funded_wallet();
+    source
+        .command()
+        .args(["generate", "3", miner.as_str()])
+        .assert()
+        .success();
+    let file = source.path().join("chain.bin");
+    source
+        .command()
+        .arg("exportchain")
+        .arg(file.as_path())
+        .assert()
+        .success();
+    let bytes = fs::read(file.as_path()).expect("the chain is exported");
+    let truncated = source.path().join("truncated.bin");
+    fs::write(truncated.as_path(), &bytes[..bytes.len() - 10]).expect("the chain is truncated");
+
+    let target = DataDir::new();
+    let output = target
+        .command()
+        .arg("importchain")
+        .arg(truncated.as_path())
+        .output()
+        .expect("importchain runs");
+    assert!(!output.status.success());
+    let error = String::from_utf8(output.stderr).expect("the error is UTF-8");
+    assert!(error.starts_with("Error: the file is truncated after 3 blocks"));
+
+    target
+        .command()
+        .arg("importchain")
+        .arg(file.as_path())
+        .assert()
+        .success();
+    let exported = json_output(source.command().arg("status"));
+    let imported = json_output(target.command().arg("status"));
+    assert_eq!(imported["chain"], exported["chain"]);
+}
+
+#[test]
+fn signed_message_verifies_only_unchanged_and_for_its_address() {
+    let dir = DataDir::new();
+    let address = dir.with_wallet();
+    let other = dir.with_wallet();
+    let signed = json_output(
+        dir.command()
+            .args(["signmessage", address.as_str(), "hello"]),
+    );
+    let signature = signed["signature"].as_str().expect("a signature");
+    let public_key = signed["public_key"].as_str().expect("a public key");
+
+    dir.command()
+        .args([
+            "verifymessage",
+            address.as_str(),
+            "hello",
+            signature,
+            public_key,
+        ])
+        .assert()
+        .success()
+        .stdout(format!("The signature is valid for {address}\n"));
+    dir.command()
+        .args([
+            "verifymessage",
+            address.as_str(),
+            "hello!",
+            signature,
+            public_key,
+        ])
+        .assert()
+        .code(1)
+        .stderr(format!("Error: the signature is not valid for {address}\n"));
+    dir.command()
+        .args([
+            "verifymessage",
+            other.as_str(),
+            "hello",
+            signature,
+            public_key,
+        ])
+        .assert()
+        .code(1)
+        .stderr(format!("Error: the signature is not valid for {other}\n"));
+}
+
+#[test]
+fn signmessage_reads_the_message_from_stdin() {
+    let dir = DataDir::new();
+    let address = dir.with_wallet();
+    let signed = json_output(
+        dir.command()
+            .args(["signmessage", address.as_str()])
+            .write_stdin("from stdin"),
+    );
+    let signature = signed["signature"].as_str().expect("a signature");
+    let public_key = signed["public_key"].as_str().expect("a public key");
+    dir.command()
+        .args([
+            "verifymessage",
+            address.as_str(),
+            "from stdin",
+            signature,
+            public_key,
+        ])
+        .assert()
+        .success();
+}
+
+#[test]
+fn watch_only_address_counts_towards_the_balance_but_cannot_send() {
+    let dir = DataDir::new();
+    let address = dir.with_funded_wallet();
+    dir.command()
+        .args(["send", address.as_str(), FOREIGN_ADDRESS, "3", "1", "--yes"])
+        .assert()
+        .success();
+    dir.command()
+        .args(["importaddress", FOREIGN_ADDRESS, "--label", "cold"])
+        .assert()
+        .success()
+        .stdout(format!("Watching {FOREIGN_ADDRESS}\n"));
+
+    let listed = json_output(dir.command().arg("listaddresses"));
+    assert_eq!(
+        listed[1],
+        json!({ "address": FOREIGN_ADDRESS, "label": "cold", "watch_only": true })
+    );
+    let balance = json_output(dir.command().arg("getwalletbalance"));
+    assert_eq!(balance["watch_only_confirmed"], 3);
+    assert_eq!(balance["addresses"][1]["confirmed"], 3);
+    let history = json_output(dir.command().arg("listtransactions"));
+    let watched = history
+        .as_array()
+        .and_then(|listed| listed.iter().find(|entry| entry["watch_only"] == true))
+        .expect("the watched address is listed");
+    assert_eq!(watched["transactions"][0]["amount"], 3);
+
+    dir.command()
+        .args(["send", FOREIGN_ADDRESS, address.as_str(), "1", "1", "--yes"])
+        .assert()
+        .code(2)
+        .stderr(format!(
+            "Error: address `{FOREIGN_ADDRESS}` is watch-only, sign elsewhere with signrawtx\n"
+        ));
+    let balance = json_output(dir.command().arg("getwalletbalance"));
+    assert_eq!(balance["watch_only_confirmed"], 3);
+}
+
+#[test]
+fn signmessage_refuses_a_watch_only_address() {
+    let dir = DataDir::new();
+    dir.command()
+        .args(["importaddress", FOREIGN_ADDRESS])
+        .assert()
+        .success();
+    dir.command()
+        .args(["signmessage", FOREIGN_ADDRESS, "hello"])
+        .assert()
+        .code(2)
+        .stderr(format!(
+            "Error: address `{FOREIGN_ADDRESS}` is watch-only, sign elsewhere with signrawtx\n"
+        ));
+}
+
+#[test]
+fn startnode_dials_every_connect_peer() {
+    let dir = DataDir::new();
+    dir.with_funded_wallet();
+    let peers = [
+        TcpListener::bind("127.0.0.1:0").expect("a port is free"),
+        TcpListener::bind("127.0.0.1:0").expect("a port is free"),
+    ];
+    let addrs: Vec<String> = peers
+        .iter()
+        .map(|peer| peer.local_addr().expect("the port is bound").to_string())
+        .collect();
+    let _node = dir.start_node(
+        &[],
+        &[
+            "--no-listen",
+            "--connect",
+            addrs[0].as_str(),
+            "--connect",
+            addrs[1].as_str(),
+        ],
+    );
+    for peer in &peers {
+        accept_within(peer);
+    }
+}
+
+#[test]
+fn startnode_listens_on_the_bind_address() {
+    let dir = DataDir::new();
+    dir.with_funded_wallet();
+    let peer = TcpListener::bind("127.0.0.1:0").expect("a port is free");
+    let peer_addr = peer.local_addr().expect("the port is bound").to_string();
+    let bind = free_addr();
+    let _node = dir.start_node(
+        &[],
+        &["--bind", bind.as_str(), "--connect", peer_addr.as_str()],
+    );
+    accept_within(&peer);
+    assert!(TcpStream::connect(bind.as_str()).is_ok());
+}
+
+#[test]
+fn connections_beyond_the_workers_and_their_queue_are_closed() {
+    let dir = DataDir::new();
+    dir.with_funded_wallet();
+    let peer = TcpListener::bind("127.0.0.1:0").expect("a port is free");
+    let peer_addr = peer.local_addr().expect("the port is bound").to_string();
+    let bind = free_addr();
+    let _node = dir.start_node(
+        &[],
+        &["--bind", bind.as_str(), "--connect", peer_addr.as_str()],
+    );
+    wait_for_listener(bind.as_str());
+    // Lets the worker serving the probe above see it close.
+    thread::sleep(Duration::from_millis(200));
+
+    // Eight silent peers wait in the queue however quickly the eight workers take them
+    // from it, each of those until it times out, so none past the sixteenth is kept.
+    let mut streams: Vec<TcpStream> = (0..17)
+        .map(|_| TcpStream::connect(bind.as_str()).expect("the node accepts"))
+        .collect();
+    let refused = streams.last_mut().expect("there are connections");
+    refused
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .expect("the connection is open");
+    assert_eq!(refused.read(&mut [0; 1]).expect("the node closes it"), 0);
+    for stream in &mut streams[..8] {
+        stream
+            .set_nonblocking(true)
+            .expect("the connection is open");
+        let read = stream.read(&mut [0; 1]).map_err(|e| e.kind());
+        assert_eq!(read, Err(std::io::ErrorKind::WouldBlock));
+    }
+}
+
+/// Starts a data directory holding the chain of `source` as it is now.
+fn with_chain_of(source: &DataDir) -> DataDir {
+    let file = source
+        .path()
+        .join(format!("chain-{}.bin", himalia::random_u64()));
+    source
+        .command()
+        .arg("exportchain")
+        .arg(file.as_path())
+        .assert()
+        .success();
+    let dir = DataDir::new();
+    dir.command()
+        .arg("importchain")
+        .arg(file.as_path())
+        .assert()
+        .success();
+    dir
+}
+
+/// Waits up to twenty seconds for `done` to hold, asking it every tenth of a second.
+fn wait_until(what: &str, mut done: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(20);
+    while !done() {
+        assert!(Instant::now() < deadline, "{what} within twenty seconds");
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[test]
+fn node_behind_its_peer_downloads_the_missing_blocks() {
+    let ahead = DataDir::new();
+    let miner = ahead.with_funded_wallet();
+    let behind = with_chain_of(&ahead);
+    ahead
+        .command()
+        .args(["generate", "3", miner.as_str()])
+        .assert()
+        .success();
+    let expected = json_output(ahead.command().arg("status"));
+
+    let nobody = TcpListener::bind("127.0.0.1:0").expect("a port is free");
+    let nobody_addr = nobody.local_addr().expect("the port is bound").to_string();
+    let ahead_addr = free_addr();
+    let _ahead_node = ahead.start_node(
+        &[],
+        &[
+            "--bind",
+            ahead_addr.as_str(),
+            "--connect",
+            nobody_addr.as_str(),
+        ],
+    );
+    wait_for_listener(ahead_addr.as_str());
+    let behind_addr = free_addr();
+    let rpc_bind = free_addr();
+    let _behind_node = behind.start_node(
+        &[("RPC_BIND", rpc_bind.as_str()), ("RPC_AUTH", "secret")],
+        &[
+            "--bind",
+            behind_addr.as_str(),
+            "--connect",
+            ahead_addr.as_str(),
+        ],
+    );
+    wait_for_listener(rpc_bind.as_str());
+
+    // Version, GetBlocks, Inv, GetData and Block go back and forth until it's caught up.
+    wait_until("the node catches up", || {
+        let info = rpc::call(rpc_bind.as_str(), "secret", "getblockchaininfo", &json!([]))
+            .expect("getblockchaininfo answers");
+        info["chain"]["tip_hash"] == expected["chain"]["tip_hash"]
+    });
+}
+
+#[test]
+fn wallet_node_sees_a_payment_mined_by_its_peer() {
+    let mining = DataDir::new();
+    let payer = mining.with_funded_wallet();
+    let wallet = with_chain_of(&mining);
+    let recipient = wallet.with_wallet();
+
+    let nobody = TcpListener::bind("127.0.0.1:0").expect("a port is free");
+    let nobody_addr = nobody.local_addr().expect("the port is bound").to_string();
+    let (mining_addr, mining_rpc) = (free_addr(), free_addr());
+    let _mining_node = mining.start_node(
+        &[("RPC_BIND", mining_rpc.as_str()), ("RPC_AUTH", "secret")],
+        &[
+            "--bind",
+            mining_addr.as_str(),
+            "--connect",
+            nobody_addr.as_str(),
+            "--miner",
+            payer.as_str(),
+        ],
+    );
+    wait_for_listener(mining_addr.as_str());
+    let (wallet_addr, wallet_rpc) = (free_addr(), free_addr());
+    let _wallet_node = wallet.start_node(
+        &[("RPC_BIND", wallet_rpc.as_str()), ("RPC_AUTH", "secret")],
+        &[
+            "--bind",
+            wallet_addr.as_str(),
+            "--connect",
+            mining_addr.as_str(),
+        ],
+    );
+    wait_for_listener(mining_rpc.as_str());
+    wait_for_listener(wallet_rpc.as_str());
+    let call = |rpc_bind: &str, method: &str, params: serde_json::Value| {
+        rpc::call(rpc_bind, "secret", method, &params).expect("the node answers")
+    };
+    wait_until("the miner hears of the wallet node", || {
+        let peers = call(mining_rpc.as_str(), "getpeerinfo", json!([]));
+        peers.as_array().is_some_and(|peers| {
+            peers
+                .iter()
+                .any(|peer| peer["address"] == wallet_addr.as_str())
+        })
+    });
+
+    call(
+        mining_rpc.as_str(),
+        "sendtoaddress",
+        json!([payer, recipient, 4, 1]),
+    );
+    wait_until("the payment is confirmed on the wallet node", || {
+        let balance = call(wallet_rpc.as_str(), "getbalance", json!([recipient]));
+        balance["confirmed"] == 4
+    });
+    let info = call(wallet_rpc.as_str(), "getblockchaininfo", json!([]));
+    assert_eq!(info["chain"]["height"], 1);
+    let mempool = call(wallet_rpc.as_str(), "getmempoolinfo", json!([]));
+    assert_eq!(mempool["count"], 0);
+}
+
+#[test]
+fn miner_node_mines_a_lone_pending_transaction_once_the_interval_passes() {
+    let dir = DataDir::new();
+    let payer = dir.with_funded_wallet();
+    let nobody = TcpListener::bind("127.0.0.1:0").expect("a port is free");
+    let nobody_addr = nobody.local_addr().expect("the port is bound").to_string();
+    let rpc_bind = free_addr();
+    let _node = dir.start_node(
+        &[("RPC_BIND", rpc_bind.as_str()), ("RPC_AUTH", "secret")],
+        &[
+            "--no-listen",
+            "--connect",
+            nobody_addr.as_str(),
+            "--miner",
+            payer.as_str(),
+            "--threshold",
+            "5",
+            "--mining-interval",
+            "2",
+        ],
+    );
+    wait_for_listener(rpc_bind.as_str());
+    let call = |method: &str, params: serde_json::Value| {
+        rpc::call(rpc_bind.as_str(), "secret", method, &params).expect("the node answers")
+    };
+
+    call("sendtoaddress", json!([payer, FOREIGN_ADDRESS, 4, 1]));
+    let sent = Instant::now();
+    assert_eq!(call("getmempoolinfo", json!([]))["count"], 1);
+    wait_until("the pending transaction is mined", || {
+        call("getblockchaininfo", json!([]))["chain"]["height"] == 1
+    });
+    // The interval, and the second the timer sleeps between checks, with some slack.
+    assert!(
+        sent.elapsed() < Duration::from_secs(4),
+        "{:?}",
+        sent.elapsed()
+    );
+    assert_eq!(call("getmempoolinfo", json!([]))["count"], 0);
+}
+
+#[test]
+fn startnode_accepts_the_deprecated_positional_miner() {
+    let dir = DataDir::new();
+    let miner = dir.with_funded_wallet();
+    let peer = TcpListener::bind("127.0.0.1:0").expect("a port is free");
+    let peer_addr = peer.local_addr().expect("the port is bound").to_string();
+    let node = dir.start_node(
+        &[],
+        &[
+            miner.as_str(),
+            "--no-listen",
+            "--connect",
+            peer_addr.as_str(),
+        ],
+    );
+    accept_within(&peer);
+    assert!(node
+        .stop()
+        .starts_with("warning: the positional mining address is deprecated, use --miner\n"));
+    dir.command()
+        .args(["startnode", "--miner", miner.as_str(), miner.as_str()])
+        .assert()
+        .code(1);
+}
+
+#[test]
+fn mine_extends_the_chain_and_pays_the_miner() {
+    let dir = DataDir::new();
+    let address = dir.with_funded_wallet();
+    let mined = json_output(
+        dir.command()
+            .args(["mine", address.as_str(), "--count", "3"]),
+    );
+    let blocks = mined["blocks"].as_array().expect("mine lists its blocks");
+    let heights: Vec<&serde_json::Value> = blocks.iter().map(|block| &block["height"]).collect();
+    assert_eq!(heights, [1, 2, 3]);
+    let tip = json_output(dir.command().args(["getblockbyheight", "3"]));
+    assert_eq!(tip["hash"], blocks[2]["hash"]);
+    dir.command()
+        .args(["getblockbyheight", "4"])
+        .assert()
+        .failure();
+    let balance = json_output(dir.command().args(["getbalance", address.as_str()]));
+    assert_eq!(balance["confirmed"], 40);
+
+    let output = dir
+        .command()
+        .args(["mine", address.as_str()])
+        .output()
+        .expect("mine runs");
+    assert!(output.status.success());
+    let printed = String::from_utf8(output.stdout).expect("the block is printed in UTF-8");
+    let tip = json_output(dir.command().args(["getblockbyheight", "4"]));
+    let hash = tip["hash"].as_str().expect("the block has a hash");
+    assert_eq!(printed, format!("4 {hash}\n"));
+}
+
+#[test]
+fn imported_key_signs_the_same_transactions_as_the_original() {
+    let original = DataDir::new();
+    let address = original.with_funded_wallet();
+    let output = original
+        .command()
+        .args(["dumpprivkey", address.as_str()])
+        .output()
+        .expect("dumpprivkey runs");
+    assert!(output.status.success());
+    let key = String::from_utf8(output.stdout).expect("the key is printed in UTF-8");
+    let copy = with_chain_of(&original);
+    for imported_before in [false, true] {
+        let output = copy
+            .command()
+            .args(["importprivkey", key.trim()])
+            .output()
+            .expect("importprivkey runs");
+        assert!(output.status.success());
+        assert_eq!(output.stdout, format!("{address}\n").as_bytes());
+        let stderr = String::from_utf8(output.stderr).expect("the warning is UTF-8");
+        assert_eq!(stderr.contains("nothing was imported"), imported_before);
+    }
+
+    let signed = |dir: &DataDir| {
+        let output = dir
+            .command()
+            .env("DETERMINISTIC_SIGNING", "true")
+            .args(["send", address.as_str(), FOREIGN_ADDRESS, "3", "1"])
+            .args(["--yes", "--dry-run", "--raw"])
+            .output()
+            .expect("send runs");
+        assert!(output.status.success());
+        output.stdout
+    };
+    assert_eq!(signed(&copy), signed(&original));
+}
+
+#[test]
+fn dry_run_changes_no_state() {
+    let dir = DataDir::new();
+    let from = dir.with_funded_wallet();
+    let regtest = dir.path().join("regtest");
+    let wallet = fs::read(regtest.join("wallet.dat")).expect("the wallet is saved");
+    let status = json_output(dir.command().arg("status"));
+
+    let sent = json_output(dir.command().args([
+        "send",
+        from.as_str(),
+        FOREIGN_ADDRESS,
+        "5",
+        "1",
+        "--fee",
+        "1",
+        "--dry-run",
+        "--raw",
+    ]));
+    assert_eq!(sent["dry_run"], true);
+    assert_eq!(sent["signed"], true);
+    assert!(sent["hex"].as_str().is_some_and(|hex| !hex.is_empty()));
+    let sent = json_output(dir.command().args([
+        "sendmany",
+        from.as_str(),
+        format!("{FOREIGN_ADDRESS}:2").as_str(),
+        "--fee",
+        "1",
+        "--dry-run",
+        "--no-sign",
+    ]));
+    assert_eq!(sent["signed"], false);
+
+    assert_eq!(fs::read(regtest.join("wallet.dat")).ok(), Some(wallet));
+    assert_eq!(json_output(dir.command().arg("status")), status);
+    assert!(!regtest.join("mempool.json").exists());
+    let balance = json_output(dir.command().args(["getbalance", from.as_str()]));
+    assert_eq!(balance["confirmed"], 10);
+    assert_eq!(balance["pending"], 0);
+}
+
+#[test]
+fn reindexutxo_verify_rebuilds_an_inconsistent_set_with_a_state_error() {
+    let dir = DataDir::new();
+    dir.with_funded_wallet();
+    dir.command()
+        .args(["reindexutxo", "--verify"])
+        .assert()
+        .success();
+    {
+        let db = sled::open(dir.path().join("regtest")).expect("the chain opens");
+        let chainstate = db.open_tree("chainstate").expect("the UTXO set opens");
+        let (txid, _) = chainstate
+            .iter()
+            .filter_map(Result::ok)
+            .find(|(key, _)| key.len() == 32)
+            .expect("the genesis coinbase is unspent");
+        chainstate.remove(txid).expect("the output is removed");
+        db.flush().expect("the removal is written");
+    }
+
+    let output = dir
+        .command()
+        .args(["--json", "reindexutxo", "--verify"])
+        .output()
+        .expect("reindexutxo runs");
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8(output.stderr).expect("the error is UTF-8"),
+        "{\"error\":{\"code\":2,\"message\":\"the UTXO set was inconsistent and has been rebuilt\"}}\n"
+    );
+    let report: serde_json::Value =
+        serde_json::from_slice(&output.stdout).expect("stdout is a JSON document");
+    assert_eq!(report["rebuilt"], true);
+    assert_eq!(report["after"]["transactions"], 1);
+    dir.command()
+        .args(["reindexutxo", "--verify"])
+        .assert()
+        .success();
+}
+
+#[test]
+fn resetchain_keeps_the_wallet_and_allows_a_new_chain() {
+    let dir = DataDir::new();
+    let address = dir.with_funded_wallet();
+    let wallet_path = dir.path().join("regtest").join("wallet.dat");
+    let wallet = fs::read(wallet_path.as_path()).expect("the wallet is saved");
+
+    dir.command()
+        .arg("resetchain")
+        .write_stdin("n\n")
+        .assert()
+        .code(1);
+    json_output(dir.command().args(["getbalance", address.as_str()]));
+
+    dir.command()
+        .args(["resetchain", "--yes"])
+        .assert()
+        .success();
+    assert_eq!(fs::read(wallet_path.as_path()).ok(), Some(wallet));
+    dir.command()
+        .args(["getbalance", address.as_str()])
+        .assert()
+        .code(2);
+    dir.command()
+        .args(["createblockchain", address.as_str()])
+        .assert()
+        .success();
+    let balance = json_output(dir.command().args(["getbalance", address.as_str()]));
+    assert_eq!(balance["confirmed"], 10);
+}
+
+#[test]
+fn resetchain_refuses_while_a_node_runs() {
+    let dir = DataDir::new();
+    dir.with_funded_wallet();
+    let peer = TcpListener::bind("127.0.0.1:0").expect("a port is free");
+    let peer_addr = peer.local_addr().expect("the port is bound").to_string();
+    let _node = dir.start_node(&[], &["--no-listen", "--connect", peer_addr.as_str()]);
+    accept_within(&peer);
+    dir.command()
+        .args(["resetchain", "--yes"])
+        .assert()
+        .code(2)
+        .stderr(format!(
+            "Error: the blockchain in {} is in use, stop the running node first\n",
+            dir.path().join("regtest").display()
+        ));
+}
+
+#[test]
+fn running_node_answers_json_rpc_and_stops_on_request() {
+    let dir = DataDir::new();
+    let address = dir.with_funded_wallet();
+    let peer = TcpListener::bind("127.0.0.1:0").expect("a port is free");
+    let peer_addr = peer.local_addr().expect("the port is bound").to_string();
+    let rpc_bind = free_addr();
+    let node = dir.start_node(
+        &[("RPC_BIND", rpc_bind.as_str()), ("RPC_AUTH", "secret")],
+        &["--no-listen", "--connect", peer_addr.as_str()],
+    );
+    wait_for_listener(rpc_bind.as_str());
+    let call = |method: &str, params: serde_json::Value| {
+        rpc::call(rpc_bind.as_str(), "secret", method, &params)
+    };
+
+    let info = call("getblockchaininfo", json!([])).expect("getblockchaininfo answers");
+    assert_eq!(info["network"], "regtest");
+    assert_eq!(info["chain"]["height"], 0);
+    let balance = call("getbalance", json!([address])).expect("getbalance answers");
+    assert_eq!(balance["confirmed"], 10);
+    let error = call("nosuchmethod", json!([])).expect_err("the method is unknown");
+    assert_eq!(
+        error.to_string(),
+        "method `nosuchmethod` not found (rpc error -32601)"
+    );
+    let refused = rpc::call(rpc_bind.as_str(), "wrong", "getblockchaininfo", &json!([]));
+    assert!(refused.is_err());
+
+    let balance = json_output(
+        dir.command()
+            .envs([("RPC_BIND", rpc_bind.as_str()), ("RPC_AUTH", "secret")])
+            .args(["getbalance", address.as_str()]),
+    );
+    assert_eq!(balance["confirmed"], 10);
+
+    assert_eq!(call("stop", json!([])).ok(), Some(json!("stopping")));
+    assert_eq!(node.wait_for_exit(), Some(0));
+}
+
+#[test]
+fn pending_balance_counts_a_payment_to_yourself_until_it_is_mined() {
+    let dir = DataDir::new();
+    let address = dir.with_funded_wallet();
+    let peer = TcpListener::bind("127.0.0.1:0").expect("a port is free");
+    let peer_addr = peer.local_addr().expect("the port is bound").to_string();
+    let rpc_bind = free_addr();
+    let env = [("RPC_BIND", rpc_bind.as_str()), ("RPC_AUTH", "secret")];
+    let node = dir.start_node(&env, &["--no-listen", "--connect", peer_addr.as_str()]);
+    wait_for_listener(rpc_bind.as_str());
+    let balance = |dir: &DataDir, env: &[(&str, &str)]| {
+        json_output(dir.command().envs(env.iter().copied()).args([
+            "getbalance",
+            address.as_str(),
+            "--include-mempool",
+        ]))
+    };
+
+    json_output(dir.command().envs(env).args([
+        "send",
+        address.as_str(),
+        address.as_str(),
+        "4",
+        "0",
+        "--fee",
+        "1",
+        "--allow-high-fee",
+        "--yes",
+    ]));
+    // The whole coinbase is spent, and the payment comes back with the change.
+    let pending = balance(&dir, &env);
+    assert_eq!(pending["confirmed"], 10);
+    assert_eq!(pending["pending_in"], 9);
+    assert_eq!(pending["pending_out"], 10);
+    assert_eq!(pending["pending_balance"], 9);
+
+    assert_eq!(
+        rpc::call(rpc_bind.as_str(), "secret", "stop", &json!([])).ok(),
+        Some(json!("stopping"))
+    );
+    assert_eq!(node.wait_for_exit(), Some(0));
+    let mined = json_output(dir.command().args(["mine", FOREIGN_ADDRESS]));
+    assert_eq!(mined["blocks"][0]["transactions"], 2);
+    let confirmed = balance(&dir, &[]);
+    assert_eq!(confirmed["confirmed"], 9);
+    assert_eq!(confirmed["pending_in"], 0);
+    assert_eq!(confirmed["pending_out"], 0);
+    assert_eq!(confirmed["pending_balance"], 9);
+}
+
+#[test]
+fn transaction_signed_offline_is_accepted_once_by_a_running_node() {
+    let dir = DataDir::new();
+    let address = dir.with_funded_wallet();
+    let genesis = json_output(dir.command().args(["getblockbyheight", "0"]));
+    let coinbase = genesis["transactions"][0]["txid"]
+        .as_str()
+        .expect("the coinbase has a txid")
+        .to_owned();
+    let unsigned = json_output(dir.command().args([
+        "createrawtx",
+        "--input",
+        format!("{coinbase}:0").as_str(),
+        "--output",
+        format!("{FOREIGN_ADDRESS}:9").as_str(),
+    ]));
+    let signed = json_output(dir.command().args([
+        "signrawtx",
+        unsigned["hex"].as_str().expect("createrawtx prints hex"),
+        address.as_str(),
+        "--prevout",
+        format!("{address}:10").as_str(),
+    ]));
+    let hex = signed["hex"].as_str().expect("signrawtx prints hex");
+
+    let peer = TcpListener::bind("127.0.0.1:0").expect("a port is free");
+    let peer_addr = peer.local_addr().expect("the port is bound").to_string();
+    let rpc_bind = free_addr();
+    let env = [("RPC_BIND", rpc_bind.as_str()), ("RPC_AUTH", "secret")];
+    let node = dir.start_node(&env, &["--no-listen", "--connect", peer_addr.as_str()]);
+    wait_for_listener(rpc_bind.as_str());
+
+    let sent = json_output(dir.command().envs(env).args(["sendrawtransaction", hex]));
+    let txid = sent["txid"]
+        .as_str()
+        .expect("sendrawtransaction prints the txid");
+    let mempool = rpc::call(rpc_bind.as_str(), "secret", "getmempoolinfo", &json!([]))
+        .expect("getmempoolinfo answers");
+    assert_eq!(mempool["count"], 1);
+    assert_eq!(mempool["total_fees"], 1);
+    let tx = rpc::call(
+        rpc_bind.as_str(),
+        "secret",
+        "gettransaction",
+        &json!([txid]),
+    );
+    assert!(tx.is_ok(), "the node knows {txid}: {tx:?}");
+
+    let again = dir
+        .command()
+        .envs(env)
+        .args(["sendrawtransaction", hex])
+        .output()
+        .expect("the binary runs");
+    assert_eq!(again.status.code(), Some(2));
+    assert_eq!(
+        String::from_utf8_lossy(&again.stderr),
+        "Error: the transaction is already in the mempool (rpc error -32002)\n"
+    );
+
+    let garbage = dir
+        .command()
+        .envs(env)
+        .args(["sendrawtransaction", "zz"])
+        .output()
+        .expect("the binary runs");
+    assert_eq!(garbage.status.code(), Some(1));
+
+    node.stop();
+}
+
+/// Writes an executable shell script running `body` into `dir`, returning its path.
+#[cfg(unix)]
+fn script(dir: &Path, name: &str, body: &str) -> PathBuf {
+    use std::os::unix::fs::PermissionsExt;
+
+    let path = dir.join(name);
+    fs::write(&path, format!("#!/bin/sh\n{body}\n")).expect("the script is written");
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).expect("the script runs");
+    path
+}
+
+#[cfg(unix)]
+#[test]
+fn send_signs_with_an_external_program_and_aborts_when_it_refuses() {
+    let dir = DataDir::new();
+    let address = dir.with_funded_wallet();
+    let exported = dir
+        .command()
+        .args(["dumpprivkey", address.as_str()])
+        .output()
+        .expect("the binary runs");
+    let exported = String::from_utf8(exported.stdout).expect("the key is printed");
+    // The version byte and the PKCS #8 header come before the private key.
+    let payload = himalia::base58_decode(exported.trim()).expect("the key is Base58");
+    let key = data_encoding::HEXLOWER.encode(&payload[37..69]);
+    let fake_signer = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/fake_signer.py");
+    let signer = script(
+        dir.path(),
+        "signer",
+        format!("exec python3 {fake_signer} {key} \"$@\"").as_str(),
+    );
+    let refusing = script(
+        dir.path(),
+        "refusing",
+        format!(
+            "if [ \"$1\" = sign ]; then echo 'the key is locked' >&2; exit 3; fi\nexec {} \"$@\"",
+            signer.display()
+        )
+        .as_str(),
+    );
+    let send = |signer: &Path| {
+        dir.command()
+            .env("SIGNER_COMMAND", signer)
+            .args(["send", address.as_str(), FOREIGN_ADDRESS, "5", "1"])
+            .args(["--signer", "external", "--yes"])
+            .output()
+            .expect("the binary runs")
+    };
+
+    let refused = send(refusing.as_path());
+    assert_eq!(refused.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&refused.stderr);
+    assert!(
+        stderr.starts_with("Error: the signer refused to sign: ")
+            && stderr.ends_with(": the key is locked\n"),
+        "{stderr}"
+    );
+    let status = json_output(dir.command().arg("status"));
+    assert_eq!(status["chain"]["height"], 0);
+
+    assert!(send(signer.as_path()).status.success());
+    let balance = json_output(dir.command().args(["getbalance", FOREIGN_ADDRESS]));
+    assert_eq!(balance["confirmed"], 5);
+}
@@ -0,0 +1,63 @@
+#!/usr/bin/env python3
+"""A stand-in for an external signer, holding the P-256 private key given in hex as
+the first argument.
+
+`fake_signer.py <key> public-key` prints the uncompressed public key, and
+`fake_signer.py <key> sign` reads a hex digest from stdin and prints the fixed-size
+signature of its SHA-256, as himalia's `CommandSigner` expects.
+"""
+
+import hashlib
+import secrets
+import sys
+
+P = 0xFFFFFFFF00000001000000000000000000000000FFFFFFFFFFFFFFFFFFFFFFFF
+N = 0xFFFFFFFF00000000FFFFFFFFFFFFFFFFBCE6FAADA7179E84F3B9CAC2FC632551
+G = (
+    0x6B17D1F2E12C4247F8BCE6E563A440F277037D812DEB33A0F4A13945D898C296,
+    0x4FE342E2FE1A7F9B8EE7EB4A7C0F9E162BCE33576B315ECECBB6406837BF51F5,
+)
+
+
+def add(a, b):
+    if a is None:
+        return b
+    if b is None:
+        return a
+    if a[0] == b[0] and (a[1] + b[1]) % P == 0:
+        return None
+    if a == b:
+        slope = (3 * a[0] * a[0] - 3) * pow(2 * a[1], -1, P)
+    else:
+        slope = (b[1] - a[1]) * pow(b[0] - a[0], -1, P)
+    x = (slope * slope - a[0] - b[0]) % P
+    return (x, (slope * (a[0] - x) - a[1]) % P)
+
+
+def multiply(k, point):
+    result = None
+    while k:
+        if k & 1:
+            result = add(result, point)
+        point = add(point, point)
+        k >>= 1
+    return result
+
+
+def main():
+    key = int(sys.argv[1], 16)
+    if sys.argv[2] == "public-key":
+        x, y = multiply(key, G)
+        print("04" + x.to_bytes(32, "big").hex() + y.to_bytes(32, "big").hex())
+    elif sys.argv[2] == "sign":
+        digest = bytes.fromhex(sys.stdin.read().strip())
+        e = int.from_bytes(hashlib.sha256(digest).digest(), "big")
+        k = secrets.randbelow(N - 1) + 1
+        r = multiply(k, G)[0] % N
+        s = pow(k, -1, N) * (e + r * key) % N
+        print(r.to_bytes(32, "big").hex() + s.to_bytes(32, "big").hex())
+    else:
+        sys.exit(f"unknown command {sys.argv[2]}")
+
+
+main()